@@ -259,6 +259,15 @@ where
 		DARecoveryProfile::Other(profile) => profile,
 	};
 
+	// No parachain blocks are exchanged until the relay chain has synced, don't let the
+	// parachain sync mistake it for a stall and evict its peers.
+	sync_service.set_waiting("waiting for the relay chain to sync".into());
+	task_manager.spawn_handle().spawn(
+		"cumulus-wait-for-relay-chain",
+		None,
+		wait_for_relay_chain_sync(relay_chain_interface.clone(), sync_service.clone()),
+	);
+
 	let pov_recovery = PoVRecovery::new(
 		recovery_handle,
 		da_recovery_profile,
@@ -277,6 +286,28 @@ where
 	Ok(())
 }
 
+/// Signal `sync_service` ready once the relay chain has imported a block and is no longer major
+/// syncing.
+async fn wait_for_relay_chain_sync<Block: BlockT>(
+	relay_chain_interface: impl RelayChainInterface + 'static,
+	sync_service: Arc<SyncingService<Block>>,
+) {
+	match relay_chain_interface.import_notification_stream().await {
+		Ok(mut imported) =>
+			while imported.next().await.is_some() {
+				if !relay_chain_interface.is_major_syncing().await.unwrap_or(false) {
+					break
+				}
+			},
+		Err(error) => log::warn!(
+			target: LOG_TARGET_SYNC,
+			"Failed to follow the relay chain imports: {error}",
+		),
+	}
+
+	sync_service.set_ready();
+}
+
 /// Start a full node for a parachain.
 ///
 /// A full node will only sync the given parachain and will follow the
@@ -503,7 +503,7 @@ pub fn new_full_base(
 					_ => None,
 				}
 			});
-		let (authority_discovery_worker, _service) =
+		let (authority_discovery_worker, authority_discovery_service) =
 			sc_authority_discovery::new_worker_and_service_with_config(
 				sc_authority_discovery::WorkerConfig {
 					publish_non_global_ips: auth_disc_publish_non_global_ips,
@@ -521,6 +521,17 @@ pub fn new_full_base(
 			Some("networking"),
 			authority_discovery_worker.run(),
 		);
+
+		// Announce new blocks to the known authorities first.
+		let sync_service = sync_service.clone();
+		task_manager.spawn_handle().spawn(
+			"authority-discovery-priority-peers",
+			Some("networking"),
+			authority_discovery_service
+				.refresh_authority_peer_ids(std::time::Duration::from_secs(60), move |peer_ids| {
+					sync_service.set_priority_peers(peer_ids)
+				}),
+		);
 	}
 
 	// if the node isn't actively participating in consensus then it doesn't
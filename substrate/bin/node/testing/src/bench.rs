@@ -384,6 +384,8 @@ impl BenchDb {
 			state_pruning: Some(PruningMode::ArchiveAll),
 			source: database_type.into_settings(dir.into()),
 			blocks_pruning: sc_client_db::BlocksPruning::KeepAll,
+			metrics_registry: None,
+			state_value_cache_size: None,
 		};
 		let task_executor = TaskExecutor::new();
 
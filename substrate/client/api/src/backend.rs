@@ -42,6 +42,9 @@ pub use sp_state_machine::{Backend as StateBackend, BackendTransaction, KeyValue
 /// Extracts the state backend type for the given backend.
 pub type StateBackendFor<B, Block> = <B as Backend<Block>>::State;
 
+/// Storage keys changed by a block, by prefixed child storage key, empty for the top trie.
+pub type ChangedStorageKeys = Vec<(Vec<u8>, Vec<Vec<u8>>)>;
+
 /// Describes which block import notification stream should be notified.
 #[derive(Debug, Clone, Copy)]
 pub enum ImportNotificationAction {
@@ -586,6 +589,15 @@ pub trait Backend<Block: BlockT>: AuxStore + Send + Sync {
 	/// Returns state backend with post-state of given block.
 	fn state_at(&self, hash: Block::Hash) -> sp_blockchain::Result<Self::State>;
 
+	/// Returns the storage keys the given block changed on top of the state of its parent, if
+	/// they are still recorded. Recent blocks imported with their state changes are recorded.
+	fn changed_storage_keys(
+		&self,
+		_hash: Block::Hash,
+	) -> sp_blockchain::Result<Option<ChangedStorageKeys>> {
+		Ok(None)
+	}
+
 	/// Attempts to revert the chain by `n` blocks. If `revert_finalized` is set it will attempt to
 	/// revert past any finalized block, this is unsafe and can potentially leave the node in an
 	/// inconsistent state. All blocks higher than the best block are also reverted and not counting
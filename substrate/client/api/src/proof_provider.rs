@@ -22,6 +22,18 @@ use sp_runtime::traits::Block as BlockT;
 use sp_state_machine::{KeyValueStates, KeyValueStorageLevel};
 use sp_storage::ChildInfo;
 
+/// Storage entries of a trie level that differ between two blocks.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageDeltaLevel {
+	/// Prefixed storage key of the level, for
+	/// top trie it is as an empty byte array.
+	pub storage_key: Vec<u8>,
+	/// Keys whose value was added or changed, with their new value.
+	pub key_values: Vec<(Vec<u8>, Vec<u8>)>,
+	/// Keys removed since the older block.
+	pub removed: Vec<Vec<u8>>,
+}
+
 /// Interface for providing block proving utilities.
 pub trait ProofProvider<Block: BlockT> {
 	/// Reads storage value at a given block + key, returning read proof.
@@ -81,6 +93,22 @@ pub trait ProofProvider<Block: BlockT> {
 		size_limit: usize,
 	) -> sp_blockchain::Result<Vec<(KeyValueStorageLevel, bool)>>;
 
+	/// Given two `Hash`es iterate over the storage keys starting at `start_key`, collecting
+	/// the entries that differ between the state of `base` and the state of `hash`.
+	/// Uses the same cursor and size limit semantic as [`Self::storage_collection`],
+	/// with the size limit applying to the collected differences.
+	/// A child trie is only traversed when its root differs between the two states.
+	/// For each collected level a boolean indicates if the iteration reached its end.
+	/// The number of keys compared is bounded, when the bound is reached before any difference
+	/// is found the last compared entry is collected so that the iteration resumes after it.
+	fn storage_delta_collection(
+		&self,
+		base: Block::Hash,
+		hash: Block::Hash,
+		start_key: &[Vec<u8>],
+		size_limit: usize,
+	) -> sp_blockchain::Result<Vec<(StorageDeltaLevel, bool)>>;
+
 	/// Verify read storage proof for a set of keys.
 	/// Returns collected key-value pairs and a the nested state
 	/// depth of current iteration or 0 if completed.
@@ -167,4 +167,6 @@ pub(crate) enum ServicetoWorkerMsg {
 	GetAddressesByAuthorityId(AuthorityId, oneshot::Sender<Option<HashSet<Multiaddr>>>),
 	/// See [`Service::get_authority_ids_by_peer_id`].
 	GetAuthorityIdsByPeerId(PeerId, oneshot::Sender<Option<HashSet<AuthorityId>>>),
+	/// See [`Service::get_authority_peer_ids`].
+	GetAuthorityPeerIds(oneshot::Sender<HashSet<PeerId>>),
 }
@@ -16,7 +16,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashSet, fmt::Debug};
+use std::{collections::HashSet, fmt::Debug, time::Duration};
 
 use crate::ServicetoWorkerMsg;
 
@@ -24,6 +24,7 @@ use futures::{
 	channel::{mpsc, oneshot},
 	SinkExt,
 };
+use futures_timer::Delay;
 
 use libp2p::{Multiaddr, PeerId};
 use sp_authority_discovery::AuthorityId;
@@ -91,4 +92,30 @@ impl Service {
 
 		rx.await.ok().flatten()
 	}
+
+	/// Get the [`PeerId`]s of all authorities in the local address cache.
+	///
+	/// Returns `None` if connection to the [`crate::Worker`] failed.
+	pub async fn get_authority_peer_ids(&mut self) -> Option<HashSet<PeerId>> {
+		let (tx, rx) = oneshot::channel();
+
+		self.to_worker.send(ServicetoWorkerMsg::GetAuthorityPeerIds(tx)).await.ok()?;
+
+		rx.await.ok()
+	}
+
+	/// Pass the [`PeerId`]s of all authorities in the local address cache to `on_refresh` every
+	/// `interval`, e.g. to propagate blocks to them first.
+	///
+	/// Resolves once connection to the [`crate::Worker`] fails.
+	pub async fn refresh_authority_peer_ids(
+		mut self,
+		interval: Duration,
+		mut on_refresh: impl FnMut(HashSet<PeerId>),
+	) {
+		while let Some(peer_ids) = self.get_authority_peer_ids().await {
+			on_refresh(peer_ids);
+			Delay::new(interval).await;
+		}
+	}
 }
@@ -300,6 +300,9 @@ where
 				let _ = sender
 					.send(self.addr_cache.get_authority_ids_by_peer_id(&peer_id).map(Clone::clone));
 			},
+			ServicetoWorkerMsg::GetAuthorityPeerIds(sender) => {
+				let _ = sender.send(self.addr_cache.peer_ids());
+			},
 		}
 	}
 
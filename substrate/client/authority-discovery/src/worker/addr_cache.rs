@@ -130,6 +130,11 @@ impl AddrCache {
 		self.peer_id_to_authority_ids.get(peer_id)
 	}
 
+	/// Returns the [`PeerId`]s of all authorities in the cache.
+	pub fn peer_ids(&self) -> HashSet<PeerId> {
+		self.peer_id_to_authority_ids.keys().cloned().collect()
+	}
+
 	/// Removes all [`PeerId`]s and [`Multiaddr`]s from the cache that are not related to the given
 	/// [`AuthorityId`]s.
 	pub fn retain_ids(&mut self, authority_ids: &[AuthorityId]) {
@@ -275,6 +280,10 @@ mod tests {
 				cache.get_authority_ids_by_peer_id(&peer_id_from_multiaddr(&third.1).unwrap()),
 				"Expect `get_authority_id_by_peer_id` to return `None` for third authority.",
 			);
+			assert!(
+				!cache.peer_ids().contains(&peer_id_from_multiaddr(&third.1).unwrap()),
+				"Expect `peer_ids` to not contain the peer id of third authority.",
+			);
 
 			TestResult::passed()
 		}
@@ -221,6 +221,59 @@ pub enum OffchainWorkerEnabled {
 	WhenAuthority,
 }
 
+/// What to do with block announcements received while all validation slots are occupied.
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+#[value(rename_all = "kebab-case")]
+pub enum BlockAnnounceValidationOverflow {
+	/// Ignore the new announcement.
+	DropNewest,
+	/// Cancel the oldest pending validation of the peer occupying the most slots and penalize it.
+	DropOldest,
+	/// Stop receiving notifications until a validation finishes.
+	Backpressure,
+}
+
+impl Into<sc_network::config::BlockAnnounceValidationOverflow> for BlockAnnounceValidationOverflow {
+	fn into(self) -> sc_network::config::BlockAnnounceValidationOverflow {
+		match self {
+			BlockAnnounceValidationOverflow::DropNewest =>
+				sc_network::config::BlockAnnounceValidationOverflow::DropNewest,
+			BlockAnnounceValidationOverflow::DropOldest =>
+				sc_network::config::BlockAnnounceValidationOverflow::DropOldest,
+			BlockAnnounceValidationOverflow::Backpressure =>
+				sc_network::config::BlockAnnounceValidationOverflow::Backpressure,
+		}
+	}
+}
+
+/// Which block announcements are sent to light peers.
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+#[value(rename_all = "kebab-case")]
+pub enum LightPeerAnnouncements {
+	/// Every announced block.
+	All,
+	/// Only the blocks whose number is a multiple of `--light-peer-announcement-interval`.
+	EveryNth,
+	/// Only new best blocks.
+	BestOnly,
+	/// No block.
+	Never,
+}
+
+impl LightPeerAnnouncements {
+	/// Convert to the network configuration, `EveryNth` announcing every `interval` blocks.
+	pub fn into_config(self, interval: u32) -> sc_network::config::LightPeerAnnouncements {
+		match self {
+			LightPeerAnnouncements::All => sc_network::config::LightPeerAnnouncements::All,
+			LightPeerAnnouncements::EveryNth =>
+				sc_network::config::LightPeerAnnouncements::EveryNth(interval),
+			LightPeerAnnouncements::BestOnly =>
+				sc_network::config::LightPeerAnnouncements::BestOnly,
+			LightPeerAnnouncements::Never => sc_network::config::LightPeerAnnouncements::Never,
+		}
+	}
+}
+
 /// Syncing mode.
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
 #[value(rename_all = "kebab-case")]
@@ -16,11 +16,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{arg_enums::SyncMode, params::node_key_params::NodeKeyParams};
+use crate::{
+	arg_enums::{BlockAnnounceValidationOverflow, LightPeerAnnouncements, SyncMode},
+	params::node_key_params::NodeKeyParams,
+};
 use clap::Args;
 use sc_network::{
 	config::{
-		NetworkConfiguration, NodeKeyConfig, NonReservedPeerMode, SetConfig, TransportConfig,
+		NetworkConfiguration, NodeKeyConfig, NonReservedPeerMode, RequestRetryPolicy, SetConfig,
+		TransportConfig, DEFAULT_BLOCK_ANNOUNCE_DATA_CACHE_MAX_BYTES,
+		DEFAULT_BLOCK_REQUEST_TIMEOUT, DEFAULT_MAX_BLOCK_ANNOUNCE_DATA_SIZE,
+		DEFAULT_MAX_STATE_RESPONSE_SIZE, DEFAULT_STATE_REQUEST_TIMEOUT,
+		DEFAULT_WARP_PROOF_REQUEST_TIMEOUT,
 	},
 	multiaddr::Protocol,
 };
@@ -28,7 +35,7 @@ use sc_service::{
 	config::{Multiaddr, MultiaddrWithPeerId},
 	ChainSpec, ChainType,
 };
-use std::{borrow::Cow, num::NonZeroUsize, path::PathBuf};
+use std::{borrow::Cow, num::NonZeroUsize, path::PathBuf, time::Duration};
 
 /// Parameters used to create the network configuration.
 #[derive(Debug, Clone, Args)]
@@ -100,10 +107,24 @@ pub struct NetworkParams {
 
 	/// Maximum number of peers from which to ask for the same blocks in parallel.
 	/// This allows downloading announced blocks from multiple peers. Decrease to save
-	/// traffic and risk increased latency.
+	/// traffic and risk increased latency. Only applies with `--racing-downloads`.
 	#[arg(long, value_name = "COUNT", default_value_t = 5)]
 	pub max_parallel_downloads: u32,
 
+	/// Request the same blocks from several peers at once, importing the first response.
+	///
+	/// Lowers latency at the cost of downloading blocks several times. By default a range of
+	/// blocks is only requested from one peer at a time.
+	#[arg(long)]
+	pub racing_downloads: bool,
+
+	/// Request the last blocks of major sync from two peers at once when fewer than this many are
+	/// left, so that a single slow peer can't hold up the end of the sync.
+	///
+	/// Disabled by default.
+	#[arg(long, value_name = "COUNT", default_value_t = 0)]
+	pub tail_race_blocks: u32,
+
 	#[allow(missing_docs)]
 	#[clap(flatten)]
 	pub node_key_params: NodeKeyParams,
@@ -149,6 +170,199 @@ pub struct NetworkParams {
 	/// and observe block requests timing out.
 	#[arg(long, value_name = "COUNT", default_value_t = 64)]
 	pub max_blocks_per_request: u32,
+
+	/// Maximum size in bytes of the state responses served to other nodes.
+	///
+	/// Nodes may request smaller responses. Lower it on a node with little memory, raise it to let
+	/// other nodes download the state faster.
+	#[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_STATE_RESPONSE_SIZE)]
+	pub max_state_response_size: usize,
+
+	/// Don't serve state proofs to other nodes, only the raw state entries.
+	///
+	/// Building proofs takes more memory and time. Nodes requiring proofs download the state
+	/// from other nodes.
+	#[arg(long)]
+	pub no_state_proofs: bool,
+
+	/// Time in seconds after which a block request without response fails.
+	#[arg(
+		long,
+		value_name = "SECONDS",
+		default_value_t = DEFAULT_BLOCK_REQUEST_TIMEOUT.as_secs()
+	)]
+	pub block_request_timeout: u64,
+
+	/// Time in seconds after which a state request without response fails.
+	///
+	/// Raise it along with `--max-state-response-size` if state requests time out.
+	#[arg(
+		long,
+		value_name = "SECONDS",
+		default_value_t = DEFAULT_STATE_REQUEST_TIMEOUT.as_secs()
+	)]
+	pub state_request_timeout: u64,
+
+	/// Time in seconds after which a warp proof request without response fails.
+	#[arg(
+		long,
+		value_name = "SECONDS",
+		default_value_t = DEFAULT_WARP_PROOF_REQUEST_TIMEOUT.as_secs()
+	)]
+	pub warp_proof_request_timeout: u64,
+
+	/// Number of consecutive timed out sync requests after which a peer is disconnected.
+	///
+	/// The requests that timed out before are sent again after an exponential backoff.
+	#[arg(long, value_name = "COUNT", default_value_t = 3)]
+	pub max_request_timeouts: u32,
+
+	/// Maximum number of cached block announcement data entries.
+	///
+	/// Defaults to the number of inbound and outbound peer slots.
+	#[arg(long, value_name = "COUNT")]
+	pub block_announce_data_cache_size: Option<u32>,
+
+	/// Time in seconds after which cached block announcement data expires.
+	#[arg(long, value_name = "SECONDS", default_value_t = 600)]
+	pub block_announce_data_cache_ttl: u64,
+
+	/// Maximum size in bytes of the data attached to a received block announcement.
+	///
+	/// Announcements with larger data are dropped and the peer sending them is reported.
+	#[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_BLOCK_ANNOUNCE_DATA_SIZE)]
+	pub max_block_announce_data_size: usize,
+
+	/// Maximum number of block announcements validated concurrently.
+	#[arg(long, value_name = "COUNT", default_value_t = 256)]
+	pub max_concurrent_block_announce_validations: usize,
+
+	/// Maximum number of block announcements of a single peer validated concurrently.
+	#[arg(long, value_name = "COUNT", default_value_t = 4)]
+	pub max_concurrent_block_announce_validations_per_peer: usize,
+
+	/// What to do with block announcements received while all validation slots are occupied.
+	#[arg(
+		long,
+		value_enum,
+		value_name = "MODE",
+		default_value_t = BlockAnnounceValidationOverflow::DropNewest,
+		ignore_case = true,
+	)]
+	pub block_announce_validation_overflow: BlockAnnounceValidationOverflow,
+
+	/// Maximum number of block announcements sent to a single peer per second.
+	///
+	/// Announcements over the limit are coalesced into the most recent one. Unlimited by default.
+	#[arg(long, value_name = "COUNT")]
+	pub block_announce_rate_limit: Option<u32>,
+
+	/// Which block announcements are sent to light peers.
+	///
+	/// Announcing every block to many light clients costs a lot of bandwidth.
+	#[arg(
+		long,
+		value_enum,
+		value_name = "POLICY",
+		default_value_t = LightPeerAnnouncements::All,
+		ignore_case = true,
+	)]
+	pub light_peer_announcements: LightPeerAnnouncements,
+
+	/// Interval in blocks between the announcements sent to light peers with
+	/// `--light-peer-announcements every-nth`.
+	#[arg(long, value_name = "COUNT", default_value_t = 16)]
+	pub light_peer_announcement_interval: u32,
+
+	/// Minimum number of block hashes remembered per peer to avoid announcing blocks it knows of.
+	///
+	/// Raised automatically when more blocks are produced over ten minutes. Consider increasing it
+	/// on chains with short block times.
+	#[arg(long, value_name = "COUNT", default_value_t = 1024)]
+	pub max_known_blocks: usize,
+
+	/// Include the last finalized block in the block announces handshake.
+	///
+	/// Peers running older versions refuse to connect, only enable once they have upgraded.
+	#[arg(long)]
+	pub announce_finalized_in_handshake: bool,
+
+	/// Exchange finalized blocks with the peers to request justifications from those that hold
+	/// them.
+	#[arg(long)]
+	pub finality_notifications: bool,
+
+	/// Sync without ever announcing blocks to other nodes, for monitoring nodes.
+	///
+	/// Peers running older versions see a full node.
+	#[arg(long)]
+	pub observer: bool,
+
+	/// Disconnect reserved nodes too when syncing has stalled and all peers are evicted.
+	///
+	/// By default reserved nodes stay connected.
+	#[arg(long)]
+	pub evict_reserved_peers_on_stall: bool,
+
+	/// Only download blocks and state from reserved nodes.
+	///
+	/// Block announcements of other peers are still processed.
+	#[arg(long)]
+	pub sync_from_reserved_peers_only: bool,
+
+	/// Specify a list of nodes to request justifications from, without taking peer slots.
+	///
+	/// No blocks or state are downloaded from them.
+	#[arg(long, value_name = "ADDR", num_args = 1..)]
+	pub justification_peers: Vec<MultiaddrWithPeerId>,
+
+	/// Number of the full peer slots kept for the peers known to be authorities.
+	///
+	/// Lets validators stay connected to each other when the other slots are full. No more slots
+	/// are kept than there are known authorities.
+	#[arg(long, value_name = "COUNT", default_value_t = 0)]
+	pub authority_peer_slots: u32,
+
+	/// Only download finalized blocks, never following the unfinalized tips of the chain.
+	#[arg(long)]
+	pub sync_finalized_only: bool,
+
+	/// Number of blocks below the finalized block whose state is downloaded by fast and warp sync.
+	///
+	/// Increase if peers prune the state of the target block before it is downloaded.
+	#[arg(long, value_name = "COUNT", default_value_t = 0)]
+	pub sync_target_offset: u32,
+
+	/// Download the block history skipped by warp sync in the background.
+	///
+	/// Progress is kept in the database, so a node that warp synced without this flag
+	/// downloads its history once restarted with it.
+	#[arg(long)]
+	pub download_history: bool,
+
+	/// Maximum number of block history requests sent per second.
+	///
+	/// Unlimited by default. Only applies with `--download-history`.
+	#[arg(long, value_name = "COUNT")]
+	pub history_download_rate: Option<u32>,
+
+	/// Number of blocks behind the peers past which the node is considered major syncing.
+	#[arg(long, value_name = "COUNT", default_value_t = 5)]
+	pub major_sync_threshold: u32,
+
+	/// Number of blocks below `--major-sync-threshold` the node must catch up to before major
+	/// syncing ends.
+	#[arg(long, value_name = "COUNT", default_value_t = 0)]
+	pub major_sync_hysteresis: u32,
+
+	/// Number of blocks queued for import past which no new blocks are requested.
+	#[arg(long, value_name = "COUNT", default_value_t = 2048)]
+	pub import_queue_high_water_mark: usize,
+
+	/// Number of blocks queued for import below which blocks are requested again after reaching
+	/// `--import-queue-high-water-mark`.
+	#[arg(long, value_name = "COUNT", default_value_t = 1024)]
+	pub import_queue_low_water_mark: usize,
 }
 
 impl NetworkParams {
@@ -236,7 +450,26 @@ impl NetworkParams {
 				allow_private_ip,
 			},
 			max_parallel_downloads: self.max_parallel_downloads,
+			racing_downloads: self.racing_downloads,
+			tail_race_blocks: self.tail_race_blocks,
 			max_blocks_per_request: self.max_blocks_per_request,
+			max_state_response_size: self.max_state_response_size,
+			serve_state_proofs: !self.no_state_proofs,
+			block_request_timeout: Duration::from_secs(self.block_request_timeout),
+			state_request_timeout: Duration::from_secs(self.state_request_timeout),
+			warp_proof_request_timeout: Duration::from_secs(self.warp_proof_request_timeout),
+			block_request_retry: RequestRetryPolicy {
+				max_timeouts: self.max_request_timeouts,
+				..Default::default()
+			},
+			state_request_retry: RequestRetryPolicy {
+				max_timeouts: self.max_request_timeouts,
+				..Default::default()
+			},
+			warp_proof_request_retry: RequestRetryPolicy {
+				max_timeouts: self.max_request_timeouts,
+				..Default::default()
+			},
 			enable_dht_random_walk: !self.reserved_only,
 			allow_non_globals_in_dht,
 			kademlia_disjoint_query_paths: self.kademlia_disjoint_query_paths,
@@ -244,6 +477,36 @@ impl NetworkParams {
 			yamux_window_size: None,
 			ipfs_server: self.ipfs_server,
 			sync_mode: self.sync.into(),
+			block_announce_data_cache_size: self.block_announce_data_cache_size,
+			block_announce_data_cache_max_bytes: DEFAULT_BLOCK_ANNOUNCE_DATA_CACHE_MAX_BYTES,
+			block_announce_data_cache_ttl: Duration::from_secs(self.block_announce_data_cache_ttl),
+			max_block_announce_data_size: self.max_block_announce_data_size,
+			max_concurrent_block_announce_validations: self
+				.max_concurrent_block_announce_validations,
+			max_concurrent_block_announce_validations_per_peer: self
+				.max_concurrent_block_announce_validations_per_peer,
+			block_announce_validation_overflow: self.block_announce_validation_overflow.into(),
+			block_announce_rate_limit: self.block_announce_rate_limit,
+			light_peer_announcements: self
+				.light_peer_announcements
+				.into_config(self.light_peer_announcement_interval),
+			max_known_blocks: self.max_known_blocks,
+			announce_finalized_in_handshake: self.announce_finalized_in_handshake,
+			finality_notifications: self.finality_notifications,
+			observer: self.observer,
+			sync_instance_name: None,
+			evict_reserved_peers_on_stall: self.evict_reserved_peers_on_stall,
+			sync_from_reserved_peers_only: self.sync_from_reserved_peers_only,
+			justification_peers: self.justification_peers.clone(),
+			authority_peer_slots: self.authority_peer_slots,
+			sync_finalized_only: self.sync_finalized_only,
+			sync_target_offset: self.sync_target_offset,
+			download_history: self.download_history,
+			history_download_rate: self.history_download_rate,
+			major_sync_threshold: self.major_sync_threshold,
+			major_sync_hysteresis: self.major_sync_hysteresis,
+			import_queue_high_water_mark: self.import_queue_high_water_mark,
+			import_queue_low_water_mark: self.import_queue_low_water_mark,
 		}
 	}
 }
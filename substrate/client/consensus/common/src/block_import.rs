@@ -131,8 +131,19 @@ pub enum StorageChanges<Block: BlockT> {
 pub struct ImportedState<B: BlockT> {
 	/// Target block hash.
 	pub block: B::Hash,
-	/// State keys and values.
+	/// State keys and values, empty if `delta` is set.
 	pub state: sp_state_machine::KeyValueStates,
+	/// Storage changes on top of the state of an already imported block, if only those were
+	/// downloaded.
+	pub delta: Option<StateDelta<B>>,
+}
+
+/// Storage changes on top of the state of a base block.
+pub struct StateDelta<B: BlockT> {
+	/// Block whose state the changes apply to.
+	pub base: B::Hash,
+	/// Changes by prefixed child storage key, empty for the top trie. `None` values are removals.
+	pub changes: Vec<(Vec<u8>, Vec<(Vec<u8>, Option<Vec<u8>>)>)>,
 }
 
 impl<B: BlockT> std::fmt::Debug for ImportedState<B> {
@@ -22,14 +22,18 @@ use std::{
 	sync::Arc,
 };
 
-use finality_grandpa::{voter_set::VoterSet, Error as GrandpaError};
+use finality_grandpa::{voter_set::VoterSet, Error as GrandpaError, SignedPrecommit};
 use parity_scale_codec::{Decode, DecodeAll, Encode};
 use sp_blockchain::{Error as ClientError, HeaderBackend};
-use sp_consensus_grandpa::AuthorityId;
+use sp_consensus_grandpa::{AuthorityId, AuthoritySignature};
 use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor};
 
 use crate::{AuthorityList, Commit, Error};
 
+/// Number of precommits from which the signatures of a justification are checked on several
+/// threads.
+const PARALLEL_SIGNATURE_CHECK_THRESHOLD: usize = 64;
+
 /// A GRANDPA justification for block finality, it includes a commit message and
 /// an ancestry proof including all headers routing all precommit target blocks
 /// to the commit target block. Due to the current voting strategy the precommit
@@ -202,22 +206,18 @@ impl<Block: BlockT> GrandpaJustification<Block> {
 				 qed.",
 			);
 
-		let mut buf = Vec::new();
+		if !check_precommit_signatures(
+			&self.justification.commit.precommits,
+			self.justification.round,
+			set_id,
+		) {
+			return Err(ClientError::BadJustification(
+				"invalid signature for precommit in grandpa justification".to_string(),
+			))
+		}
+
 		let mut visited_hashes = HashSet::new();
 		for signed in self.justification.commit.precommits.iter() {
-			if !sp_consensus_grandpa::check_message_signature_with_buffer(
-				&finality_grandpa::Message::Precommit(signed.precommit.clone()),
-				&signed.id,
-				&signed.signature,
-				self.justification.round,
-				set_id,
-				&mut buf,
-			) {
-				return Err(ClientError::BadJustification(
-					"invalid signature for precommit in grandpa justification".to_string(),
-				))
-			}
-
 			if base_hash == signed.precommit.target_hash {
 				continue
 			}
@@ -261,6 +261,48 @@ impl<Block: BlockT> GrandpaJustification<Block> {
 	}
 }
 
+/// Check the signatures of the `precommits` of `round` in the authority set `set_id`.
+///
+/// Justifications of large authority sets hold hundreds of precommits, whose signatures are
+/// checked on as many threads as available in that case.
+fn check_precommit_signatures<H, N>(
+	precommits: &[SignedPrecommit<H, N, AuthoritySignature, AuthorityId>],
+	round: u64,
+	set_id: u64,
+) -> bool
+where
+	H: Clone + Encode + Sync,
+	N: Clone + Encode + Sync,
+{
+	let check = |precommits: &[SignedPrecommit<H, N, AuthoritySignature, AuthorityId>]| {
+		let mut buf = Vec::new();
+		precommits.iter().all(|signed| {
+			sp_consensus_grandpa::check_message_signature_with_buffer(
+				&finality_grandpa::Message::Precommit(signed.precommit.clone()),
+				&signed.id,
+				&signed.signature,
+				round,
+				set_id,
+				&mut buf,
+			)
+		})
+	};
+
+	let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+	if threads == 1 || precommits.len() < PARALLEL_SIGNATURE_CHECK_THRESHOLD {
+		return check(precommits)
+	}
+
+	let chunk_size = (precommits.len() + threads - 1) / threads;
+	std::thread::scope(|scope| {
+		let checks = precommits
+			.chunks(chunk_size)
+			.map(|chunk| scope.spawn(move || check(chunk)))
+			.collect::<Vec<_>>();
+		checks.into_iter().all(|check| check.join().unwrap_or(false))
+	})
+}
+
 /// A utility trait implementing `finality_grandpa::Chain` using a given set of headers.
 /// This is useful when validating commits, using the given set of headers to
 /// verify a valid ancestry route to the target commit block.
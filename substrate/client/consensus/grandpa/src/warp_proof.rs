@@ -314,6 +314,14 @@ where
 	fn current_authorities(&self) -> AuthorityList {
 		self.authority_set.inner().current_authorities.clone()
 	}
+
+	fn next_proof_begin(&self, proof: &EncodedProof) -> Option<Block::Hash> {
+		let proof = WarpSyncProof::<Block>::decode_all(&mut proof.0.as_slice()).ok()?;
+		if proof.is_finished {
+			return None
+		}
+		proof.proofs.last().map(|fragment| fragment.header.hash())
+	}
 }
 
 #[cfg(test)]
@@ -122,6 +122,8 @@ fn create_backend(config: BenchmarkConfig, temp_dir: &TempDir) -> Backend<Block>
 		state_pruning: Some(PruningMode::ArchiveAll),
 		source: DatabaseSource::ParityDb { path },
 		blocks_pruning: BlocksPruning::KeepAll,
+		metrics_registry: None,
+		state_value_cache_size: None,
 	};
 
 	Backend::new(settings, 100).expect("Creates backend")
@@ -44,6 +44,7 @@ mod utils;
 use linked_hash_map::LinkedHashMap;
 use log::{debug, trace, warn};
 use parking_lot::{Mutex, RwLock};
+use prometheus_endpoint::Registry;
 use std::{
 	collections::{HashMap, HashSet},
 	io,
@@ -60,7 +61,7 @@ use crate::{
 use codec::{Decode, Encode};
 use hash_db::Prefix;
 use sc_client_api::{
-	backend::NewBlockState,
+	backend::{ChangedStorageKeys, NewBlockState},
 	leaves::{FinalizationOutcome, LeafSet},
 	utils::is_descendent_of,
 	IoInfo, MemoryInfo, MemorySize, UsageInfo,
@@ -100,6 +101,10 @@ pub use bench::BenchmarkingState;
 
 const CACHE_HEADERS: usize = 8;
 
+/// Number of finalized blocks for which the changed storage keys are kept, see
+/// [`Backend::changed_storage_keys`](sc_client_api::Backend::changed_storage_keys).
+const STATE_CHANGES_WINDOW: u32 = 256;
+
 /// DB-backed patricia trie state, transaction type is an overlay of changes to commit.
 pub type DbState<B> =
 	sp_state_machine::TrieBackend<Arc<dyn sp_state_machine::Storage<HashingFor<B>>>, HashingFor<B>>;
@@ -307,6 +312,13 @@ pub struct DatabaseSettings {
 	///
 	/// NOTE: only finalized blocks are subject for removal!
 	pub blocks_pruning: BlocksPruning,
+	/// Prometheus registry the state database metrics are registered with.
+	pub metrics_registry: Option<Registry>,
+	/// The number of state trie nodes read from the database to keep in the state database
+	/// value cache.
+	///
+	/// If `None` is given, the cache is disabled.
+	pub state_value_cache_size: Option<u32>,
 }
 
 /// Block pruning settings.
@@ -841,6 +853,8 @@ pub struct BlockImportOperation<Block: BlockT> {
 	finalized_blocks: Vec<(Block::Hash, Option<Justification>)>,
 	set_head: Option<Block::Hash>,
 	commit_state: bool,
+	/// Block of the state the storage changes apply to, if set by `begin_state_operation`.
+	state_parent: Option<Block::Hash>,
 	index_ops: Vec<IndexOperation>,
 }
 
@@ -1001,6 +1015,44 @@ struct StorageDb<Block: BlockT> {
 	pub db: Arc<dyn Database<DbHash>>,
 	pub state_db: StateDb<Block::Hash, Vec<u8>, StateMetaDb>,
 	prefix_keys: bool,
+	/// Keys of the state column changed by the state database commits added to a transaction,
+	/// whose cached values are dropped once it is written. `None` if the value cache is
+	/// disabled.
+	written_state_keys: Option<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl<Block: BlockT> StorageDb<Block> {
+	/// Add a state database commit to `transaction`.
+	fn apply_state_commit(
+		&self,
+		transaction: &mut Transaction<DbHash>,
+		commit: sc_state_db::CommitSet<Vec<u8>>,
+	) {
+		if let Some(written) = &self.written_state_keys {
+			written.lock().extend(
+				commit
+					.data
+					.inserted
+					.iter()
+					.map(|(key, _)| key.clone())
+					.chain(commit.data.deleted.iter().cloned()),
+			);
+		}
+		apply_state_commit(transaction, commit);
+	}
+
+	/// Write `transaction` to the database, and drop the cached values of the state keys it
+	/// changed.
+	fn commit(&self, transaction: Transaction<DbHash>) -> sp_database::error::Result<()> {
+		let result = self.db.commit(transaction);
+		if let Some(written) = &self.written_state_keys {
+			// The keys are dropped from the cache even if the commit failed, as part of it may
+			// have been written.
+			let keys = std::mem::take(&mut *written.lock());
+			self.state_db.note_written_keys(keys.iter());
+		}
+		result
+	}
 }
 
 impl<Block: BlockT> sp_state_machine::Storage<HashingFor<Block>> for StorageDb<Block> {
@@ -1173,6 +1225,8 @@ impl<Block: BlockT> Backend<Block> {
 			state_pruning: Some(state_pruning),
 			source: DatabaseSource::Custom { db, require_create_flag: true },
 			blocks_pruning,
+			metrics_registry: None,
+			state_value_cache_size: None,
 		};
 
 		Self::new(db_setting, canonicalization_delay).expect("failed to create test-db")
@@ -1214,6 +1268,12 @@ impl<Block: BlockT> Backend<Block> {
 			should_init,
 		)
 		.map_err(map_e)?;
+		if let Err(e) = state_db.register_metrics(config.metrics_registry.as_ref()) {
+			warn!("Failed to register state database metrics: {}", e);
+		}
+		if let Some(capacity) = config.state_value_cache_size {
+			state_db.set_value_cache_size(capacity);
+		}
 
 		apply_state_commit(&mut db_init_transaction, state_db_init_commit_set);
 
@@ -1221,8 +1281,15 @@ impl<Block: BlockT> Backend<Block> {
 		let is_archive_pruning = state_pruning_used.is_archive();
 		let blockchain = BlockchainDb::new(db.clone())?;
 
-		let storage_db =
-			StorageDb { db: db.clone(), state_db, prefix_keys: !db.supports_ref_counting() };
+		let storage_db = StorageDb {
+			db: db.clone(),
+			state_db,
+			prefix_keys: !db.supports_ref_counting(),
+			written_state_keys: config
+				.state_value_cache_size
+				.filter(|capacity| *capacity > 0)
+				.map(|_| Default::default()),
+		};
 
 		let offchain_storage = offchain::LocalStorage::new(db.clone());
 
@@ -1434,7 +1501,7 @@ impl<Block: BlockT> Backend<Block> {
 					sc_state_db::Error<sp_database::error::DatabaseError>,
 				>,
 			)?;
-			apply_state_commit(transaction, commit);
+			self.storage.apply_state_commit(transaction, commit);
 		}
 
 		Ok(())
@@ -1537,8 +1604,23 @@ impl<Block: BlockT> Backend<Block> {
 				let mut bytes: u64 = 0;
 				let mut removal: u64 = 0;
 				let mut bytes_removal: u64 = 0;
+				// Nodes of the child tries are tagged, so that their pruning can be configured.
+				let updated_child_tries = operation
+					.child_storage_updates
+					.iter()
+					.map(|(storage_key, _)| ChildInfo::new_default(storage_key))
+					.collect::<Vec<_>>();
+				let mut child_tries: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
 				for (mut key, (val, rc)) in operation.db_updates.drain() {
+					let child_trie =
+						if rc != 0 { child_trie_of_node(&updated_child_tries, &key) } else { None };
 					self.storage.db.sanitize_key(&mut key);
+					if let Some(child_info) = child_trie {
+						child_tries
+							.entry(child_info.prefixed_storage_key().into_inner())
+							.or_default()
+							.push(key.clone());
+					}
 					if rc > 0 {
 						ops += 1;
 						bytes += key.len() as u64 + val.len() as u64;
@@ -1562,6 +1644,7 @@ impl<Block: BlockT> Backend<Block> {
 						}
 					}
 				}
+				changeset.child_tries = child_tries.into_iter().collect();
 				self.state_usage.tally_writes_nodes(ops, bytes);
 				self.state_usage.tally_removed_nodes(removal, bytes_removal);
 
@@ -1580,14 +1663,29 @@ impl<Block: BlockT> Backend<Block> {
 				}
 				self.state_usage.tally_writes(ops, bytes);
 				let number_u64 = number.saturated_into::<u64>();
+				// The state of a block imported from a state delta derives from the delta base.
+				let state_parent = operation.state_parent.unwrap_or(parent_hash);
 				let commit = self
 					.storage
 					.state_db
-					.insert_block(&hash, number_u64, pending_block.header.parent_hash(), changeset)
+					.insert_block(&hash, number_u64, &state_parent, changeset)
 					.map_err(|e: sc_state_db::Error<sp_database::error::DatabaseError>| {
 						sp_blockchain::Error::from_state_db(e)
 					})?;
-				apply_state_commit(&mut transaction, commit);
+				self.storage.apply_state_commit(&mut transaction, commit);
+				if !number.is_zero() && operation.state_parent == Some(parent_hash) {
+					// Only changes on top of the parent state are recorded, so that the records
+					// of consecutive blocks add up to the changes between their states.
+					let changes = changed_storage_keys(
+						&operation.storage_updates,
+						&operation.child_storage_updates,
+					);
+					transaction.set_from_vec(
+						columns::STATE_META,
+						&state_changes_key(number, hash)?,
+						changes.encode(),
+					);
+				}
 				if number <= last_finalized_num {
 					// Canonicalize in the db when re-importing existing blocks with state.
 					let commit = self.storage.state_db.canonicalize_block(&hash).map_err(
@@ -1595,7 +1693,7 @@ impl<Block: BlockT> Backend<Block> {
 							sc_state_db::Error<sp_database::error::DatabaseError>,
 						>,
 					)?;
-					apply_state_commit(&mut transaction, commit);
+					self.storage.apply_state_commit(&mut transaction, commit);
 					meta_updates.push(MetaUpdate {
 						hash,
 						number,
@@ -1746,7 +1844,7 @@ impl<Block: BlockT> Backend<Block> {
 			}
 		}
 
-		self.storage.db.commit(transaction)?;
+		self.storage.commit(transaction)?;
 
 		// Apply all in-memory state changes.
 		// Code beyond this point can't fail.
@@ -1797,7 +1895,7 @@ impl<Block: BlockT> Backend<Block> {
 					sc_state_db::Error<sp_database::error::DatabaseError>,
 				>,
 			)?;
-			apply_state_commit(transaction, commit);
+			self.storage.apply_state_commit(transaction, commit);
 		}
 
 		let new_displaced = self.blockchain.leaves.write().finalize_height(f_num);
@@ -1808,6 +1906,7 @@ impl<Block: BlockT> Backend<Block> {
 			&new_displaced,
 			current_transaction_justifications,
 		)?;
+		self.prune_state_changes(transaction, f_num, f_hash, &new_displaced)?;
 
 		Ok(())
 	}
@@ -1854,6 +1953,35 @@ impl<Block: BlockT> Backend<Block> {
 		Ok(())
 	}
 
+	/// Remove the changed storage keys recorded for the block [`STATE_CHANGES_WINDOW`] blocks
+	/// behind the finalized one and for the blocks of displaced branches.
+	fn prune_state_changes(
+		&self,
+		transaction: &mut Transaction<DbHash>,
+		finalized_number: NumberFor<Block>,
+		finalized_hash: Block::Hash,
+		displaced: &FinalizationOutcome<Block::Hash, NumberFor<Block>>,
+	) -> ClientResult<()> {
+		if finalized_number >= STATE_CHANGES_WINDOW.into() {
+			let number = finalized_number.saturating_sub(STATE_CHANGES_WINDOW.into());
+			if let Some(hash) = self.blockchain.hash(number)? {
+				transaction.remove(columns::STATE_META, &state_changes_key(number, hash)?);
+			}
+		}
+		for h in displaced.leaves() {
+			match sp_blockchain::tree_route(&self.blockchain, *h, finalized_hash) {
+				Ok(tree_route) =>
+					for r in tree_route.retracted() {
+						transaction
+							.remove(columns::STATE_META, &state_changes_key(r.number, r.hash)?);
+					},
+				Err(sp_blockchain::Error::UnknownBlock(_)) => {},
+				Err(e) => Err(e)?,
+			}
+		}
+		Ok(())
+	}
+
 	fn prune_displaced_branches(
 		&self,
 		transaction: &mut Transaction<DbHash>,
@@ -1934,6 +2062,19 @@ impl<Block: BlockT> Backend<Block> {
 	}
 }
 
+/// The child trie among `child_tries` that the trie node stored under `key` belongs to.
+///
+/// The nodes of a child trie are stored under keys starting with the keyspace of the child trie.
+fn child_trie_of_node<'a>(child_tries: &'a [ChildInfo], key: &[u8]) -> Option<&'a ChildInfo> {
+	child_tries
+		.iter()
+		.filter(|child_info| {
+			let keyspace = child_info.keyspace();
+			key.len() >= keyspace.len() + DB_HASH_LEN && key.starts_with(keyspace)
+		})
+		.max_by_key(|child_info| child_info.keyspace().len())
+}
+
 fn apply_state_commit(
 	transaction: &mut Transaction<DbHash>,
 	commit: sc_state_db::CommitSet<Vec<u8>>,
@@ -2011,6 +2152,29 @@ fn apply_index_ops<Block: BlockT>(
 	extrinsic_index.encode()
 }
 
+/// Key of the storage keys changed by a block in the `STATE_META` column.
+fn state_changes_key<N: TryInto<u32>, H: AsRef<[u8]>>(number: N, hash: H) -> ClientResult<Vec<u8>> {
+	let mut key = meta_keys::STATE_CHANGES_PREFIX.to_vec();
+	key.extend(utils::number_and_hash_to_lookup_key(number, hash)?);
+	Ok(key)
+}
+
+/// Storage keys changed by the given storage updates. Changed child tries also change their
+/// root in the top trie.
+fn changed_storage_keys(
+	storage_updates: &StorageCollection,
+	child_storage_updates: &ChildStorageCollection,
+) -> ChangedStorageKeys {
+	let mut top = storage_updates.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+	let mut children = Vec::with_capacity(child_storage_updates.len());
+	for (storage_key, updates) in child_storage_updates {
+		let storage_key = ChildInfo::new_default(storage_key).prefixed_storage_key().into_inner();
+		top.push(storage_key.clone());
+		children.push((storage_key, updates.iter().map(|(key, _)| key.clone()).collect()));
+	}
+	std::iter::once((Vec::new(), top)).chain(children).collect()
+}
+
 fn apply_indexed_body<Block: BlockT>(transaction: &mut Transaction<DbHash>, body: Vec<Vec<u8>>) {
 	for extrinsic in body {
 		let hash = sp_runtime::traits::BlakeTwo256::hash(&extrinsic);
@@ -2040,7 +2204,7 @@ where
 		for k in delete {
 			transaction.remove(columns::AUX, k);
 		}
-		self.storage.db.commit(transaction)?;
+		self.storage.commit(transaction)?;
 		Ok(())
 	}
 
@@ -2067,6 +2231,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 			finalized_blocks: Vec::new(),
 			set_head: None,
 			commit_state: false,
+			state_parent: None,
 			index_ops: Default::default(),
 		})
 	}
@@ -2083,6 +2248,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 		}
 
 		operation.commit_state = true;
+		operation.state_parent = Some(block);
 		Ok(())
 	}
 
@@ -2122,7 +2288,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 			&mut current_transaction_justifications,
 		)?;
 
-		self.storage.db.commit(transaction)?;
+		self.storage.commit(transaction)?;
 		self.blockchain.update_meta(m);
 		Ok(())
 	}
@@ -2164,7 +2330,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 			justifications.encode(),
 		);
 
-		self.storage.db.commit(transaction)?;
+		self.storage.commit(transaction)?;
 
 		Ok(())
 	}
@@ -2257,7 +2423,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 
 				match self.storage.state_db.revert_one() {
 					Some(commit) => {
-						apply_state_commit(&mut transaction, commit);
+						self.storage.apply_state_commit(&mut transaction, commit);
 
 						number_to_revert = prev_number;
 						hash_to_revert = prev_hash;
@@ -2307,7 +2473,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 							meta_keys::CHILDREN_PREFIX,
 							hash_to_revert,
 						);
-						self.storage.db.commit(transaction)?;
+						self.storage.commit(transaction)?;
 
 						let is_best = number_to_revert < best_number;
 
@@ -2334,7 +2500,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 
 			leaves.revert(hash_to_revert, number_to_revert);
 			leaves.prepare_transaction(&mut transaction, columns::META, meta_keys::LEAF_PREFIX);
-			self.storage.db.commit(transaction)?;
+			self.storage.commit(transaction)?;
 
 			Ok(())
 		};
@@ -2369,7 +2535,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 
 		let mut transaction = Transaction::new();
 		if let Some(commit) = self.storage.state_db.remove(&hash) {
-			apply_state_commit(&mut transaction, commit);
+			self.storage.apply_state_commit(&mut transaction, commit);
 		}
 		transaction.remove(columns::KEY_LOOKUP, hash.as_ref());
 
@@ -2400,7 +2566,7 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 
 		let remove_outcome = leaves.remove(hash, hdr.number, parent_leaf);
 		leaves.prepare_transaction(&mut transaction, columns::META, meta_keys::LEAF_PREFIX);
-		if let Err(e) = self.storage.db.commit(transaction) {
+		if let Err(e) = self.storage.commit(transaction) {
 			if let Some(outcome) = remove_outcome {
 				leaves.undo().undo_remove(outcome);
 			}
@@ -2457,6 +2623,16 @@ impl<Block: BlockT> sc_client_api::backend::Backend<Block> for Backend<Block> {
 		}
 	}
 
+	fn changed_storage_keys(&self, hash: Block::Hash) -> ClientResult<Option<ChangedStorageKeys>> {
+		let Some(number) = self.blockchain.number(hash)? else { return Ok(None) };
+		match self.storage.db.get(columns::STATE_META, &state_changes_key(number, hash)?) {
+			Some(changes) => ChangedStorageKeys::decode(&mut &changes[..]).map(Some).map_err(|e| {
+				sp_blockchain::Error::Backend(format!("Error decoding changed storage keys: {}", e))
+			}),
+			None => Ok(None),
+		}
+	}
+
 	fn have_state_at(&self, hash: Block::Hash, number: NumberFor<Block>) -> bool {
 		if self.is_archive {
 			match self.blockchain.header_metadata(hash) {
@@ -2691,6 +2867,8 @@ pub(crate) mod tests {
 				state_pruning: Some(PruningMode::blocks_pruning(1)),
 				source: DatabaseSource::Custom { db: backing, require_create_flag: false },
 				blocks_pruning: BlocksPruning::KeepFinalized,
+				metrics_registry: None,
+				state_value_cache_size: None,
 			},
 			0,
 		)
@@ -2785,10 +2963,32 @@ pub(crate) mod tests {
 
 	#[test]
 	fn delete_only_when_negative_rc() {
+		delete_only_when_negative_rc_inner(None);
+		delete_only_when_negative_rc_inner(Some(16));
+	}
+
+	fn delete_only_when_negative_rc_inner(state_value_cache_size: Option<u32>) {
 		sp_tracing::try_init_simple();
 		let state_version = StateVersion::default();
 		let key;
-		let backend = Backend::<Block>::new_test(1, 0);
+		let db = sp_database::as_database(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		let backend = Backend::<Block>::new(
+			DatabaseSettings {
+				trie_cache_maximum_size: Some(16 * 1024 * 1024),
+				state_pruning: Some(PruningMode::blocks_pruning(1)),
+				source: DatabaseSource::Custom { db, require_create_flag: true },
+				blocks_pruning: BlocksPruning::Some(1),
+				metrics_registry: None,
+				state_value_cache_size,
+			},
+			0,
+		)
+		.unwrap();
+		// Reads go through the state database and its value cache, if enabled.
+		let read = |backend: &Backend<Block>, key: &<Block as BlockT>::Hash| {
+			sp_state_machine::Storage::<BlakeTwo256>::get(&*backend.storage, key, EMPTY_PREFIX)
+				.unwrap()
+		};
 
 		let hash = {
 			let mut op = backend.begin_operation().unwrap();
@@ -2824,6 +3024,7 @@ pub(crate) mod tests {
 					.unwrap(),
 				&b"hello"[..]
 			);
+			assert_eq!(read(&backend, &key), Some(b"hello".to_vec()));
 			hash
 		};
 
@@ -2861,6 +3062,7 @@ pub(crate) mod tests {
 					.unwrap(),
 				&b"hello"[..]
 			);
+			assert_eq!(read(&backend, &key), Some(b"hello".to_vec()));
 			hash
 		};
 
@@ -2895,6 +3097,7 @@ pub(crate) mod tests {
 				.db
 				.get(columns::STATE, &sp_trie::prefixed_key::<BlakeTwo256>(&key, EMPTY_PREFIX))
 				.is_some());
+			assert_eq!(read(&backend, &key), Some(b"hello".to_vec()));
 			hash
 		};
 
@@ -2954,6 +3157,7 @@ pub(crate) mod tests {
 				.db
 				.get(columns::STATE, &sp_trie::prefixed_key::<BlakeTwo256>(&key, EMPTY_PREFIX))
 				.is_none());
+			assert_eq!(read(&backend, &key), None);
 			hash
 		};
 
@@ -2966,6 +3170,7 @@ pub(crate) mod tests {
 			.db
 			.get(columns::STATE, &sp_trie::prefixed_key::<BlakeTwo256>(&key, EMPTY_PREFIX))
 			.is_none());
+		assert_eq!(read(&backend, &key), None);
 	}
 
 	#[test]
@@ -4422,4 +4627,83 @@ pub(crate) mod tests {
 		backend.unpin_block(fork_hash_3);
 		assert!(bc.body(fork_hash_3).unwrap().is_none());
 	}
+
+	#[test]
+	fn child_trie_nodes_are_found_by_keyspace() {
+		let child_tries = [ChildInfo::new_default(b"child"), ChildInfo::new_default(b"child2")];
+		let node = |prefix: &[u8]| [prefix, &[1u8; DB_HASH_LEN][..]].concat();
+		let find = |key: &[u8]| child_trie_of_node(&child_tries, key).map(|c| c.storage_key());
+
+		assert_eq!(find(&node(b"child")), Some(&b"child"[..]));
+		assert_eq!(find(&node(b"child\x10")), Some(&b"child"[..]));
+		assert_eq!(find(&node(b"child2")), Some(&b"child2"[..]));
+		assert_eq!(find(&node(b"")), None);
+		assert_eq!(find(b"child2"), None);
+	}
+
+	#[test]
+	fn changed_storage_keys_are_recorded_and_pruned() {
+		let backend = Backend::<Block>::new_test(1000, 0);
+		let child_info = ChildInfo::new_default(b"child");
+		let child_key = child_info.prefixed_storage_key().into_inner();
+		let mut hashes = vec![insert_header(&backend, 0, Default::default(), None, H256::random())];
+		for number in 1..=STATE_CHANGES_WINDOW as u64 + 1 {
+			let parent_hash = *hashes.last().unwrap();
+			let key = number.encode();
+			let mut op = backend.begin_operation().unwrap();
+			backend.begin_state_operation(&mut op, parent_hash).unwrap();
+			let (root, overlay) = op.old_state.full_storage_root(
+				std::iter::once((&key[..], Some(&key[..]))),
+				std::iter::once((&child_info, std::iter::once((&key[..], Some(&key[..]))))),
+				StateVersion::V1,
+			);
+			op.update_db_storage(overlay).unwrap();
+			op.update_storage(
+				vec![(key.clone(), Some(key.clone()))],
+				vec![(b"child".to_vec(), vec![(key.clone(), Some(key.clone()))])],
+			)
+			.unwrap();
+			let header = Header {
+				number,
+				parent_hash,
+				state_root: root,
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			op.set_block_data(header.clone(), None, None, None, NewBlockState::Final)
+				.unwrap();
+			backend.commit_operation(op).unwrap();
+			hashes.push(header.hash());
+		}
+
+		// Genesis doesn't change the state of a parent.
+		assert_eq!(backend.changed_storage_keys(hashes[0]).unwrap(), None);
+		// Out of the window of the last finalized block.
+		assert_eq!(backend.changed_storage_keys(hashes[1]).unwrap(), None);
+		for number in 2..hashes.len() {
+			let key = (number as u64).encode();
+			assert_eq!(
+				backend.changed_storage_keys(hashes[number]).unwrap(),
+				Some(vec![
+					(Vec::new(), vec![key.clone(), child_key.clone()]),
+					(child_key.clone(), vec![key]),
+				]),
+			);
+		}
+
+		// Blocks imported with their whole state, as by state sync, aren't recorded.
+		let mut op = backend.begin_operation().unwrap();
+		let root = op.reset_storage(Storage::default(), StateVersion::V1).unwrap();
+		let header = Header {
+			number: hashes.len() as u64,
+			parent_hash: hashes[hashes.len() - 1],
+			state_root: root,
+			digest: Default::default(),
+			extrinsics_root: Default::default(),
+		};
+		op.set_block_data(header.clone(), None, None, None, NewBlockState::Best)
+			.unwrap();
+		backend.commit_operation(op).unwrap();
+		assert_eq!(backend.changed_storage_keys(header.hash()).unwrap(), None);
+	}
 }
@@ -56,6 +56,8 @@ pub mod meta_keys {
 	pub const LEAF_PREFIX: &[u8; 4] = b"leaf";
 	/// Children prefix list key.
 	pub const CHILDREN_PREFIX: &[u8; 8] = b"children";
+	/// Prefix of the storage keys changed by a block, stored next to the state-db journal.
+	pub const STATE_CHANGES_PREFIX: &[u8; 13] = b"state_changes";
 }
 
 /// Database metadata.
@@ -26,14 +26,17 @@ use sc_network_common::sync::{
 	SyncState, SyncStatus,
 };
 use sp_runtime::traits::{Block as BlockT, CheckedDiv, NumberFor, Saturating, Zero};
-use std::{fmt, time::Instant};
+use std::{
+	fmt,
+	time::{Duration, Instant},
+};
 
 /// State of the informant display system.
 ///
 /// This is the system that handles the line that gets regularly printed and that looks something
 /// like:
 ///
-/// > Syncing  5.4 bps, target=#531028 (4 peers), best: #90683 (0x4ca8…51b8),
+/// > Syncing  5.4 bps, target=#531028, eta 1h 02m (4 peers), best: #90683 (0x4ca8…51b8),
 /// > finalized #360 (0x6f24…a38b), ⬇ 5.5kiB/s ⬆ 0.9kiB/s
 ///
 /// # Usage
@@ -96,6 +99,13 @@ impl<B: BlockT> InformantDisplay<B> {
 			(diff_bytes_inbound, diff_bytes_outbound)
 		};
 
+		let eta = sync_status
+			.throughput
+			.as_ref()
+			.and_then(|throughput| throughput.eta)
+			.map(|eta| format!(", eta {}", EtaFormat(eta)))
+			.unwrap_or_default();
+
 		let (level, status, target) =
 			match (sync_status.state, sync_status.state_sync, sync_status.warp_sync) {
 				// Do not set status to "Block history" when we are doing a major sync.
@@ -139,7 +149,7 @@ impl<B: BlockT> InformantDisplay<B> {
 				),
 				(SyncState::Idle, _, _) => ("💤", "Idle".into(), "".into()),
 				(SyncState::Downloading { target }, _, _) =>
-					("⚙️ ", format!("Syncing{}", speed), format!(", target=#{target}")),
+					("⚙️ ", format!("Syncing{}", speed), format!(", target=#{target}{eta}")),
 				(SyncState::Importing { target }, _, _) =>
 					("⚙️ ", format!("Preparing{}", speed), format!(", target=#{target}")),
 			};
@@ -246,3 +256,22 @@ impl fmt::Display for TransferRateFormat {
 		write!(f, "{:.1}MiB/s", self.0 as f64 / (1024.0 * 1024.0))
 	}
 }
+
+/// Contains an estimated remaining duration. Implements `fmt::Display` and shows it rounded to
+/// seconds, minutes or hours.
+struct EtaFormat(Duration);
+impl fmt::Display for EtaFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let secs = self.0.as_secs();
+
+		if secs < 60 {
+			return write!(f, "{}s", secs)
+		}
+
+		if secs < 60 * 60 {
+			return write!(f, "{}m {:02}s", secs / 60, secs % 60)
+		}
+
+		write!(f, "{}h {:02}m", secs / (60 * 60), secs / 60 % 60)
+	}
+}
@@ -77,6 +77,11 @@ bitflags::bitflags! {
 		const LIGHT = 0b00000010;
 		/// Act as an authority
 		const AUTHORITY = 0b00000100;
+		/// Observer, syncing without announcing blocks to other nodes.
+		///
+		/// Advertised as the light bit along the full one, which other nodes never set together,
+		/// so that nodes not knowing observers decode it as a full node.
+		const OBSERVER = Self::FULL.bits | Self::LIGHT.bits;
 	}
 }
 
@@ -95,6 +100,11 @@ impl Roles {
 	pub fn is_light(&self) -> bool {
 		!self.is_full()
 	}
+
+	/// Does this role represents a client that never announces blocks?
+	pub fn is_observer(&self) -> bool {
+		self.contains(Self::OBSERVER)
+	}
 }
 
 impl<'a> From<&'a Role> for Roles {
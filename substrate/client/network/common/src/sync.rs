@@ -36,7 +36,7 @@ use sp_runtime::{
 };
 use warp::WarpSyncProgress;
 
-use std::{any::Any, fmt, fmt::Formatter, pin::Pin, sync::Arc};
+use std::{any::Any, fmt, fmt::Formatter, pin::Pin, sync::Arc, time::Duration};
 
 /// The sync status of a peer we are trying to sync with
 #[derive(Debug)]
@@ -56,6 +56,49 @@ pub struct ExtendedPeerInfo<B: BlockT> {
 	pub best_hash: B::Hash,
 	/// Peer best block number
 	pub best_number: NumberFor<B>,
+	/// Version of the block announces protocol negotiated with the peer, `N` in
+	/// `/block-announces/N`.
+	pub block_announces_version: u8,
+	/// Names of the request-response protocols negotiated with the peer, which accepted a request
+	/// over them, whether it answered it or not. Those the peer wasn't sent a request over yet
+	/// aren't listed.
+	pub request_protocols: Vec<String>,
+	/// Whether the peer opened the connection.
+	pub inbound: bool,
+}
+
+/// Sync statistics of a connected peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerStatistics {
+	/// Number of blocks received in block responses.
+	pub blocks_received: u64,
+	/// Number of bytes received in responses.
+	pub bytes_received: u64,
+	/// Number of successful responses.
+	pub responses_received: u64,
+	/// Total time spent waiting for successful responses.
+	pub total_response_time: Duration,
+	/// Number of requests that failed for reasons other than a timeout.
+	pub failed_requests: u64,
+	/// Number of requests that timed out.
+	pub timed_out_requests: u64,
+	/// Number of block announcements received.
+	pub announcements_received: u64,
+	/// Maximum number of blocks currently requested from the peer at once.
+	pub max_blocks_per_request: u32,
+	/// Number of block hashes dropped from those the peer is known to have, to stay within the
+	/// limit. Blocks dropped may be announced to the peer again.
+	pub known_blocks_evicted: u64,
+}
+
+impl PeerStatistics {
+	/// Average time spent waiting for a successful response, `None` if there was no response yet.
+	pub fn average_response_time(&self) -> Option<Duration> {
+		u32::try_from(self.responses_received)
+			.ok()
+			.filter(|responses| *responses > 0)
+			.map(|responses| self.total_response_time / responses)
+	}
 }
 
 /// Reported sync state.
@@ -85,6 +128,57 @@ pub struct StateDownloadProgress {
 	pub size: u64,
 }
 
+/// Sync strategy driving the download of the chain.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ActiveSyncStrategy {
+	/// Downloading and importing all blocks.
+	Full,
+	/// Downloading blocks and the state of a recent block.
+	State,
+	/// Downloading warp proofs up to a recent block and its state.
+	Warp,
+}
+
+/// Reported gap sync progress.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct GapSyncProgress<BlockNumber> {
+	/// First block of the gap.
+	pub start: BlockNumber,
+	/// Last block of the gap.
+	pub end: BlockNumber,
+	/// Highest block of the gap queued for import.
+	pub best_queued: BlockNumber,
+}
+
+/// Download throughput measured over a recent time window.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncThroughput {
+	/// Blocks downloaded per second.
+	pub blocks_per_second: f64,
+	/// Bytes received in responses per second.
+	pub bytes_per_second: f64,
+	/// Estimated time until the best seen block is downloaded, `None` if there is no block to
+	/// download or no blocks were downloaded recently.
+	pub eta: Option<Duration>,
+}
+
+/// Estimate of the best block of the network from the best blocks reported by the peers.
+///
+/// The peers whose best block changed recently weigh more than those whose best block is stale.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkBestEstimate<N> {
+	/// Weighted median of the best blocks of the peers.
+	pub median: N,
+	/// Best block at or below which a quarter of the peer weight is.
+	pub lower_quartile: N,
+	/// Best block at or above which a quarter of the peer weight is.
+	pub upper_quartile: N,
+	/// Number of peers the estimate is based on, the more the more reliable it is.
+	pub num_peers: u32,
+	/// Number of those peers whose best block changed recently.
+	pub num_fresh_peers: u32,
+}
+
 /// Syncing status and statistics.
 #[derive(Debug, Clone)]
 pub struct SyncStatus<Block: BlockT> {
@@ -102,6 +196,14 @@ pub struct SyncStatus<Block: BlockT> {
 	pub state_sync: Option<StateDownloadProgress>,
 	/// Warp sync in progress, if any.
 	pub warp_sync: Option<WarpSyncProgress<Block>>,
+	/// Sync strategy currently active.
+	pub strategy: ActiveSyncStrategy,
+	/// Gap sync in progress, if any.
+	pub gap_sync: Option<GapSyncProgress<NumberFor<Block>>>,
+	/// Number of fork targets being downloaded.
+	pub num_fork_targets: u32,
+	/// Recent download throughput, if measured.
+	pub throughput: Option<SyncThroughput>,
 }
 
 /// A peer did not behave as expected and should be reported.
@@ -204,7 +306,7 @@ pub enum PeerRequest<B: BlockT> {
 	WarpProof,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PeerRequestType {
 	Block,
 	State,
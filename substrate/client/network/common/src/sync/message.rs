@@ -221,8 +221,17 @@ pub mod generic {
 	}
 }
 
+/// Version of [`BlockAnnouncesHandshake`] carrying the last finalized block.
+const BLOCK_ANNOUNCES_HANDSHAKE_V2: u8 = 2;
+
 /// Handshake sent when we open a block announces substream.
-#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+///
+/// Version 2 of the handshake appends a version byte and the last finalized block. Nodes that only
+/// know version 1 reject it, so it must only be sent once peers have upgraded.
+///
+/// Nodes that send a version 2 handshake also understand empty notifications on the block
+/// announces substream, sent as keep-alives when there is nothing to announce.
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BlockAnnouncesHandshake<B: BlockT> {
 	/// Roles of the node.
 	pub roles: Roles,
@@ -232,6 +241,8 @@ pub struct BlockAnnouncesHandshake<B: BlockT> {
 	pub best_hash: B::Hash,
 	/// Genesis block hash.
 	pub genesis_hash: B::Hash,
+	/// Number and hash of the last finalized block, sent from version 2 of the handshake on.
+	pub finalized: Option<(NumberFor<B>, B::Hash)>,
 }
 
 impl<B: BlockT> BlockAnnouncesHandshake<B> {
@@ -241,6 +252,43 @@ impl<B: BlockT> BlockAnnouncesHandshake<B> {
 		best_hash: B::Hash,
 		genesis_hash: B::Hash,
 	) -> Self {
-		Self { genesis_hash, roles, best_number, best_hash }
+		Self { genesis_hash, roles, best_number, best_hash, finalized: None }
+	}
+
+	/// Include the last finalized block, making this a version 2 handshake.
+	pub fn with_finalized(mut self, number: NumberFor<B>, hash: B::Hash) -> Self {
+		self.finalized = Some((number, hash));
+		self
+	}
+}
+
+// Custom Encode/Decode impl so that version 1 handshakes, without the finalized block, are still
+// understood.
+impl<B: BlockT> Encode for BlockAnnouncesHandshake<B> {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.roles.encode_to(dest);
+		self.best_number.encode_to(dest);
+		self.best_hash.encode_to(dest);
+		self.genesis_hash.encode_to(dest);
+		if let Some(finalized) = &self.finalized {
+			BLOCK_ANNOUNCES_HANDSHAKE_V2.encode_to(dest);
+			finalized.encode_to(dest);
+		}
+	}
+}
+
+impl<B: BlockT> Decode for BlockAnnouncesHandshake<B> {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let roles = Roles::decode(input)?;
+		let best_number = NumberFor::<B>::decode(input)?;
+		let best_hash = B::Hash::decode(input)?;
+		let genesis_hash = B::Hash::decode(input)?;
+		// Version 1 handshakes end here.
+		let finalized = match u8::decode(input) {
+			Ok(BLOCK_ANNOUNCES_HANDSHAKE_V2) => Some(Decode::decode(input)?),
+			Ok(_) => return Err("Unknown block announces handshake version".into()),
+			Err(_) => None,
+		};
+		Ok(Self { roles, best_number, best_hash, genesis_hash, finalized })
 	}
 }
@@ -14,10 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::sync::message::BlockData;
 use codec::{Decode, Encode};
 pub use sp_consensus_grandpa::{AuthorityList, SetId};
 use sp_runtime::traits::{Block as BlockT, NumberFor};
-use std::fmt;
+use std::{error::Error, fmt, sync::Arc};
 
 /// Scale-encoded warp sync proof response.
 pub struct EncodedProof(pub Vec<u8>);
@@ -55,6 +56,88 @@ pub trait WarpSyncProvider<Block: BlockT>: Send + Sync {
 	/// Get current list of authorities. This is supposed to be genesis authorities when starting
 	/// sync.
 	fn current_authorities(&self) -> AuthorityList;
+	/// Block the proof following `proof` starts at, read without verifying `proof`, so that it
+	/// can be downloaded while `proof` is verified.
+	///
+	/// Returns `None` if `proof` completes the warp sync or can't be read, in which case the next
+	/// proof is requested once `proof` is verified.
+	fn next_proof_begin(&self, _proof: &EncodedProof) -> Option<Block::Hash> {
+		None
+	}
+}
+
+/// State of the finality mechanism warp proofs are verified against, e.g. the GRANDPA authority
+/// set. Opaque to warp sync.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct VerifierState(pub Vec<u8>);
+
+/// Proof verification result of a [`WarpSyncBackend`].
+pub enum ProofVerification<Block: BlockT> {
+	/// Proof is valid, but the target was not reached.
+	Partial(VerifierState, Block::Hash),
+	/// Target finality is proved.
+	Complete(VerifierState, Block::Header),
+}
+
+/// Warp sync backend of a finality mechanism. Generates and verifies the proofs leading warp sync
+/// to its target block.
+///
+/// Implemented for GRANDPA by any `Arc` of a [`WarpSyncProvider`]. Chains with other finality
+/// mechanisms, e.g. parachains verifying proofs anchored in the relay chain, implement it
+/// directly.
+pub trait WarpSyncBackend<Block: BlockT>: Send + Sync {
+	/// Generate proof starting at given block hash.
+	fn generate(&self, start: Block::Hash) -> Result<EncodedProof, Box<dyn Error + Send + Sync>>;
+	/// State the first proof is verified against, e.g. the genesis authorities.
+	fn initial_state(&self) -> VerifierState;
+	/// Verify warp proof against `state`, the state reached by verifying the previous proofs.
+	fn verify(
+		&self,
+		proof: &EncodedProof,
+		state: &VerifierState,
+	) -> Result<ProofVerification<Block>, Box<dyn Error + Send + Sync>>;
+	/// Block the proof following `proof` starts at, see
+	/// [`WarpSyncProvider::next_proof_begin`].
+	fn next_proof_begin(&self, _proof: &EncodedProof) -> Option<Block::Hash> {
+		None
+	}
+	/// Check the target block proven by the last proof, before its state is downloaded. The
+	/// header is already known to match.
+	fn verify_target_block(
+		&self,
+		_block: &BlockData<Block>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		Ok(())
+	}
+}
+
+impl<Block: BlockT, P: WarpSyncProvider<Block> + ?Sized> WarpSyncBackend<Block> for Arc<P> {
+	fn generate(&self, start: Block::Hash) -> Result<EncodedProof, Box<dyn Error + Send + Sync>> {
+		WarpSyncProvider::generate(&**self, start)
+	}
+
+	fn initial_state(&self) -> VerifierState {
+		VerifierState((0 as SetId, self.current_authorities()).encode())
+	}
+
+	fn verify(
+		&self,
+		proof: &EncodedProof,
+		state: &VerifierState,
+	) -> Result<ProofVerification<Block>, Box<dyn Error + Send + Sync>> {
+		let (set_id, authorities) = <(SetId, AuthorityList)>::decode(&mut &state.0[..])
+			.map_err(|e| format!("Invalid authority set: {:?}", e))?;
+		Ok(match WarpSyncProvider::verify(&**self, proof, set_id, authorities)? {
+			VerificationResult::Partial(set_id, authorities, last_hash) =>
+				ProofVerification::Partial(VerifierState((set_id, authorities).encode()), last_hash),
+			VerificationResult::Complete(set_id, authorities, header) =>
+				ProofVerification::Complete(VerifierState((set_id, authorities).encode()), header),
+		})
+	}
+
+	fn next_proof_begin(&self, proof: &EncodedProof) -> Option<Block::Hash> {
+		WarpSyncProvider::next_proof_begin(&**self, proof)
+	}
 }
 
 /// Reported warp sync phase.
@@ -56,6 +56,7 @@ use std::{
 	path::{Path, PathBuf},
 	pin::Pin,
 	str::{self, FromStr},
+	time::Duration,
 };
 
 pub use libp2p::{
@@ -283,6 +284,66 @@ impl NonReservedPeerMode {
 	}
 }
 
+/// What to do with a block announcement received while all its validation slots are occupied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockAnnounceValidationOverflow {
+	/// Ignore the new announcement. This is the default.
+	DropNewest,
+	/// Cancel the oldest pending validation of the peer occupying the most slots and penalize
+	/// that peer.
+	///
+	/// If only the slots of the sending peer are occupied, the oldest validation of that peer is
+	/// cancelled.
+	DropOldest,
+	/// Stop receiving notifications until a validation finishes.
+	///
+	/// Announcements of a peer whose own slots are occupied are still ignored.
+	Backpressure,
+}
+
+/// Which block announcements are sent to light peers.
+///
+/// Light peers don't report their progress, so unlike full peers they are otherwise sent every
+/// announcement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightPeerAnnouncements {
+	/// Every announced block. This is the default.
+	All,
+	/// Only the blocks whose number is a multiple of the given interval.
+	EveryNth(u32),
+	/// Only the blocks that are our new best block.
+	BestOnly,
+	/// None, light peers learn about new blocks by other means.
+	Never,
+}
+
+/// How the failed requests of a protocol used by sync are retried.
+///
+/// A peer whose request failed isn't sent requests of the same protocol during a backoff, and is
+/// disconnected after too many consecutive failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestRetryPolicy {
+	/// Number of consecutive timed out requests after which the peer is disconnected.
+	pub max_timeouts: u32,
+	/// Number of consecutive malformed responses after which the peer is disconnected.
+	pub max_malformed_responses: u32,
+	/// Backoff after the first failed request, doubling with every consecutive failure.
+	pub initial_backoff: Duration,
+	/// Upper bound of the backoff.
+	pub max_backoff: Duration,
+}
+
+impl Default for RequestRetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_timeouts: 3,
+			max_malformed_responses: 1,
+			initial_backoff: Duration::from_secs(1),
+			max_backoff: Duration::from_secs(30),
+		}
+	}
+}
+
 /// The configuration of a node's secret key, describing the type of key
 /// and how it is obtained. A node's identity keypair is the result of
 /// the evaluation of the node key configuration.
@@ -522,6 +583,30 @@ impl NonDefaultSetConfig {
 	}
 }
 
+/// Default maximum total size of the cached block announcement data.
+pub const DEFAULT_BLOCK_ANNOUNCE_DATA_CACHE_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default maximum size of the data attached to a received block announcement.
+pub const DEFAULT_MAX_BLOCK_ANNOUNCE_DATA_SIZE: usize = 128 * 1024;
+
+/// Default maximum size of the served state responses.
+pub const DEFAULT_MAX_STATE_RESPONSE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Default timeout of the block requests.
+pub const DEFAULT_BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Default timeout of the state requests.
+pub const DEFAULT_STATE_REQUEST_TIMEOUT: Duration = Duration::from_secs(40);
+
+/// Default timeout of the warp proof requests.
+pub const DEFAULT_WARP_PROOF_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default maximum number of concurrent block announce validations.
+pub const DEFAULT_MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS: usize = 256;
+
+/// Default maximum number of concurrent block announce validations per peer.
+pub const DEFAULT_MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER: usize = 4;
+
 /// Network service configuration.
 #[derive(Clone, Debug)]
 pub struct NetworkConfiguration {
@@ -558,15 +643,175 @@ pub struct NetworkConfiguration {
 	/// Configuration for the transport layer.
 	pub transport: TransportConfig,
 
-	/// Maximum number of peers to ask the same blocks in parallel.
+	/// Maximum number of peers to ask the same blocks in parallel, if `racing_downloads`.
 	pub max_parallel_downloads: u32,
 
+	/// Request the same blocks from several peers at once, importing the first response.
+	///
+	/// Otherwise a range of blocks is only requested from one peer at a time, and again from
+	/// another peer once the request failed.
+	pub racing_downloads: bool,
+
+	/// Number of blocks below the target of major sync from which the remaining blocks are
+	/// requested from two peers at once, the slower response being cancelled. Disabled if zero.
+	pub tail_race_blocks: u32,
+
 	/// Maximum number of blocks per request.
 	pub max_blocks_per_request: u32,
 
+	/// Maximum size of the state responses served to other peers, which may request smaller
+	/// ones.
+	pub max_state_response_size: usize,
+
+	/// Whether state proofs are served to other peers, raw state entries and state deltas are
+	/// always served.
+	pub serve_state_proofs: bool,
+
+	/// Time after which a block request without response fails.
+	pub block_request_timeout: Duration,
+
+	/// Time after which a state request without response fails.
+	///
+	/// Large state responses may need longer than the block responses to be served.
+	pub state_request_timeout: Duration,
+
+	/// Time after which a warp proof request without response fails.
+	pub warp_proof_request_timeout: Duration,
+
+	/// Retry policy of the failed block requests, justification requests included.
+	pub block_request_retry: RequestRetryPolicy,
+
+	/// Retry policy of the failed state requests.
+	pub state_request_retry: RequestRetryPolicy,
+
+	/// Retry policy of the failed warp proof requests.
+	pub warp_proof_request_retry: RequestRetryPolicy,
+
+	/// Maximum number of cached block announcement data entries, used when re-announcing blocks.
+	///
+	/// Defaults to the number of inbound and outbound peer slots of the default peer set.
+	pub block_announce_data_cache_size: Option<u32>,
+
+	/// Maximum total size in bytes of the cached block announcement data.
+	pub block_announce_data_cache_max_bytes: usize,
+
+	/// Time after which cached block announcement data expires.
+	pub block_announce_data_cache_ttl: Duration,
+
+	/// Maximum size in bytes of the data attached to a received block announcement.
+	///
+	/// Announcements with larger data are dropped and the peer sending them is reported.
+	pub max_block_announce_data_size: usize,
+
+	/// Maximum number of block announcements validated concurrently.
+	pub max_concurrent_block_announce_validations: usize,
+
+	/// Maximum number of block announcements of a single peer validated concurrently.
+	pub max_concurrent_block_announce_validations_per_peer: usize,
+
+	/// What to do with block announcements received while all validation slots are occupied.
+	pub block_announce_validation_overflow: BlockAnnounceValidationOverflow,
+
+	/// Maximum number of block announcements sent to a single peer per second, unlimited if
+	/// `None`.
+	///
+	/// Announcements over the limit are coalesced, only the most recent one is sent once the
+	/// limit allows it.
+	pub block_announce_rate_limit: Option<u32>,
+
+	/// Which block announcements are sent to light peers.
+	pub light_peer_announcements: LightPeerAnnouncements,
+
+	/// Minimum number of block hashes remembered per peer so that blocks it knows of aren't
+	/// announced to it again, raised to the number of blocks announced over the last ten minutes
+	/// when more are. Each entry takes about 32 bytes per peer.
+	pub max_known_blocks: usize,
+
+	/// Include the last finalized block in the block announces handshake.
+	///
+	/// Nodes that don't know this version of the handshake refuse to connect, so it should only be
+	/// enabled once the peers have upgraded. Handshakes of either version are always accepted.
+	pub announce_finalized_in_handshake: bool,
+
+	/// Exchange the finalized blocks with the peers over the finality notification protocol, so
+	/// that justifications are requested from the peers that hold them.
+	///
+	/// Only peers that enabled it too use the protocol.
+	pub finality_notifications: bool,
+
+	/// Sync normally but never announce blocks to peers, e.g. for monitoring nodes that must not
+	/// influence block propagation. The observer role is advertised in the handshake, nodes that
+	/// don't know it see a full node. An authority configured so isn't advertised as one.
+	pub observer: bool,
+
+	/// Name telling this sync instance apart from the others running in the same process, e.g.
+	/// the embedded relay chain node of a parachain.
+	///
+	/// When set, the sync metrics are labeled with the name so that instances can share a
+	/// registry, and the sync log lines and protocol names are prefixed with it. Peers only sync
+	/// with the instances of the same name.
+	pub sync_instance_name: Option<String>,
+
 	/// Initial syncing mode.
 	pub sync_mode: SyncMode,
 
+	/// Disconnect reserved peers too when syncing has stalled and all peers are evicted.
+	///
+	/// By default reserved peers of the default peer set and of the notification protocols are
+	/// kept connected.
+	pub evict_reserved_peers_on_stall: bool,
+
+	/// Only send block, state and warp proof requests to reserved peers of the default peer set.
+	///
+	/// Block announcements of all peers are still processed.
+	pub sync_from_reserved_peers_only: bool,
+
+	/// Peers connected on the sync protocols only to request justifications from, e.g. archive
+	/// nodes holding old justifications. They are connected like reserved peers, without taking
+	/// peer slots, and are sent no other requests.
+	pub justification_peers: Vec<MultiaddrWithPeerId>,
+
+	/// Number of the full peer slots kept for the peers known to be authorities, e.g. through
+	/// authority discovery, so that authorities stay connected to each other when the other slots
+	/// are full. The other peers can't take these slots even when they are unused, unless fewer
+	/// authorities are known, as when the known authorities aren't set by the node.
+	pub authority_peer_slots: u32,
+
+	/// Only download blocks up to the finalized block known from the peers and from finality
+	/// proofs, ignoring the unfinalized tips of the chain.
+	pub sync_finalized_only: bool,
+
+	/// Number of blocks below the finalized block whose state is downloaded by state and warp
+	/// sync.
+	///
+	/// A target further behind the tip of the chain is less likely to be pruned by the peers while
+	/// its state is downloaded, at the cost of more blocks to import afterwards.
+	pub sync_target_offset: u32,
+
+	/// Download the block history left out by warp sync in the background.
+	///
+	/// Otherwise the block gap is kept in the database, and its download starts once enabled on a
+	/// later run.
+	pub download_history: bool,
+
+	/// Maximum number of block history requests sent per second, unlimited if `None`.
+	pub history_download_rate: Option<u32>,
+
+	/// Number of blocks behind the best block of the peers past which the node is considered
+	/// major syncing.
+	pub major_sync_threshold: u32,
+
+	/// Number of blocks below `major_sync_threshold` the node must catch up to before it is no
+	/// longer considered major syncing, so the state doesn't flap around the threshold.
+	pub major_sync_hysteresis: u32,
+
+	/// Number of blocks queued for import past which no new blocks are requested from peers.
+	pub import_queue_high_water_mark: usize,
+
+	/// Number of blocks queued for import below which blocks are requested again after the queue
+	/// went past `import_queue_high_water_mark`.
+	pub import_queue_low_water_mark: usize,
+
 	/// True if Kademlia random discovery should be enabled.
 	///
 	/// If true, the node will automatically randomly walk the DHT in order to find new peers.
@@ -631,8 +876,46 @@ impl NetworkConfiguration {
 			node_name: node_name.into(),
 			transport: TransportConfig::Normal { enable_mdns: false, allow_private_ip: true },
 			max_parallel_downloads: 5,
+			racing_downloads: false,
+			tail_race_blocks: 0,
 			max_blocks_per_request: 64,
+			max_state_response_size: DEFAULT_MAX_STATE_RESPONSE_SIZE,
+			serve_state_proofs: true,
+			block_request_timeout: DEFAULT_BLOCK_REQUEST_TIMEOUT,
+			state_request_timeout: DEFAULT_STATE_REQUEST_TIMEOUT,
+			warp_proof_request_timeout: DEFAULT_WARP_PROOF_REQUEST_TIMEOUT,
+			block_request_retry: Default::default(),
+			state_request_retry: Default::default(),
+			warp_proof_request_retry: Default::default(),
+			block_announce_data_cache_size: None,
+			block_announce_data_cache_max_bytes: DEFAULT_BLOCK_ANNOUNCE_DATA_CACHE_MAX_BYTES,
+			block_announce_data_cache_ttl: Duration::from_secs(10 * 60),
+			max_block_announce_data_size: DEFAULT_MAX_BLOCK_ANNOUNCE_DATA_SIZE,
+			max_concurrent_block_announce_validations:
+				DEFAULT_MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS,
+			max_concurrent_block_announce_validations_per_peer:
+				DEFAULT_MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER,
+			block_announce_validation_overflow: BlockAnnounceValidationOverflow::DropNewest,
+			block_announce_rate_limit: None,
+			light_peer_announcements: LightPeerAnnouncements::All,
+			max_known_blocks: 1024,
+			announce_finalized_in_handshake: false,
+			finality_notifications: false,
+			observer: false,
+			sync_instance_name: None,
 			sync_mode: SyncMode::Full,
+			evict_reserved_peers_on_stall: false,
+			sync_from_reserved_peers_only: false,
+			justification_peers: Vec::new(),
+			authority_peer_slots: 0,
+			sync_finalized_only: false,
+			sync_target_offset: 0,
+			download_history: false,
+			history_download_rate: None,
+			major_sync_threshold: 5,
+			major_sync_hysteresis: 0,
+			import_queue_high_water_mark: 2048,
+			import_queue_low_water_mark: 1024,
 			enable_dht_random_walk: true,
 			allow_non_globals_in_dht: false,
 			kademlia_disjoint_query_paths: false,
@@ -23,7 +23,7 @@ use crate::{types::ProtocolName, NotificationsSink};
 
 use bytes::Bytes;
 use futures::channel::oneshot;
-use libp2p::{kad::record::Key, PeerId};
+use libp2p::{kad::record::Key, Multiaddr, PeerId};
 
 use sc_network_common::{role::ObservedRole, sync::message::BlockAnnouncesHandshake};
 use sp_runtime::traits::Block as BlockT;
@@ -102,8 +102,13 @@ pub enum SyncEvent<B: BlockT> {
 	NotificationStreamOpened {
 		/// Node we opened the substream with.
 		remote: PeerId,
+		/// Address we dialed to reach the node, `None` if we never dialed it.
+		remote_address: Option<Multiaddr>,
 		/// Received handshake.
 		received_handshake: BlockAnnouncesHandshake<B>,
+		/// If the negotiated name is not the "main" protocol name but a fallback, contains the
+		/// name of the negotiated fallback.
+		negotiated_fallback: Option<ProtocolName>,
 		/// Notification sink.
 		sink: NotificationsSink,
 		/// Is the connection inbound.
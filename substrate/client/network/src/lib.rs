@@ -139,6 +139,9 @@
 //! block announces are pushed to other nodes. The handshake is empty on both sides. The message
 //! format is a SCALE-encoded tuple containing a block header followed with an opaque list of
 //! bytes containing some data associated with this block announcement, e.g. a candidate message.
+//! - **`/<genesis-hash>/block-announces/2`** is the same protocol, negotiated in preference to
+//! version 1. Nodes speaking it also understand the handshake carrying the last finalized block
+//! and empty keep-alive notifications.
 //! - Notifications protocols that are registered using
 //! `NetworkConfiguration::notifications_protocols`. For example: `/paritytech/grandpa/1`. See
 //! below for more information.
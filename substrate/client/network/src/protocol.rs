@@ -29,8 +29,9 @@ use futures::{channel::oneshot, stream::FuturesUnordered, StreamExt};
 use libp2p::{
 	core::Endpoint,
 	swarm::{
-		behaviour::FromSwarm, ConnectionDenied, ConnectionId, NetworkBehaviour, PollParameters,
-		THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+		behaviour::{ConnectionClosed, FromSwarm},
+		ConnectionDenied, ConnectionId, NetworkBehaviour, PollParameters, THandler,
+		THandlerInEvent, THandlerOutEvent, ToSwarm,
 	},
 	Multiaddr, PeerId,
 };
@@ -90,6 +91,8 @@ pub struct Protocol<B: BlockT> {
 	bad_handshake_substreams: HashSet<(PeerId, SetId)>,
 	/// Connected peers on sync protocol.
 	peers: HashMap<PeerId, Roles>,
+	/// Address used to dial each peer we have an outbound connection with.
+	dialed_addresses: HashMap<PeerId, Multiaddr>,
 	sync_substream_validations: FuturesUnordered<PendingSyncSubstreamValidation>,
 	tx: TracingUnboundedSender<crate::event::SyncEvent<B>>,
 	_marker: std::marker::PhantomData<B>,
@@ -136,6 +139,7 @@ impl<B: BlockT> Protocol<B> {
 				.collect(),
 			bad_handshake_substreams: Default::default(),
 			peers: HashMap::new(),
+			dialed_addresses: HashMap::new(),
 			sync_substream_validations: FuturesUnordered::new(),
 			tx,
 			// TODO: remove when `BlockAnnouncesHandshake` is moved away from `Protocol`
@@ -236,6 +240,7 @@ impl<B: BlockT> NetworkBehaviour for Protocol<B> {
 		addr: &Multiaddr,
 		role_override: Endpoint,
 	) -> Result<THandler<Self>, ConnectionDenied> {
+		self.dialed_addresses.insert(peer, addr.clone());
 		self.behaviour.handle_established_outbound_connection(
 			connection_id,
 			peer,
@@ -257,6 +262,14 @@ impl<B: BlockT> NetworkBehaviour for Protocol<B> {
 	}
 
 	fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+		if let FromSwarm::ConnectionClosed(ConnectionClosed {
+			peer_id,
+			remaining_established: 0,
+			..
+		}) = &event
+		{
+			self.dialed_addresses.remove(peer_id);
+		}
 		self.behaviour.on_swarm_event(event);
 	}
 
@@ -325,6 +338,7 @@ impl<B: BlockT> NetworkBehaviour for Protocol<B> {
 								best_number: handshake.best_number,
 								best_hash: handshake.best_hash,
 								genesis_hash: handshake.genesis_hash,
+								finalized: None,
 							};
 
 							let (tx, rx) = oneshot::channel();
@@ -332,7 +346,9 @@ impl<B: BlockT> NetworkBehaviour for Protocol<B> {
 								crate::SyncEvent::NotificationStreamOpened {
 									inbound,
 									remote: peer_id,
+									remote_address: self.dialed_addresses.get(&peer_id).cloned(),
 									received_handshake: handshake,
+									negotiated_fallback,
 									sink: notifications_sink,
 									tx,
 								},
@@ -373,7 +389,12 @@ impl<B: BlockT> NetworkBehaviour for Protocol<B> {
 										crate::SyncEvent::NotificationStreamOpened {
 											inbound,
 											remote: peer_id,
+											remote_address: self
+												.dialed_addresses
+												.get(&peer_id)
+												.cloned(),
 											received_handshake: handshake,
+											negotiated_fallback,
 											sink: notifications_sink,
 											tx,
 										},
@@ -166,10 +166,13 @@ where
 			.into_iter()
 			.filter(|boot_node| boot_node.peer_id != local_peer_id)
 			.collect();
+		// Justification peers are connected on the sync protocols without taking peer slots.
+		let justification_peers = network_config.justification_peers.clone();
 		network_config.default_peers_set.reserved_nodes = network_config
 			.default_peers_set
 			.reserved_nodes
 			.into_iter()
+			.chain(justification_peers)
 			.filter(|reserved_node| {
 				if reserved_node.peer_id == local_peer_id {
 					warn!(
@@ -36,12 +36,13 @@ pub fn interval(duration: Duration) -> impl Stream<Item = ()> + Unpin {
 pub struct LruHashSet<T: Hash + Eq> {
 	set: LinkedHashSet<T>,
 	limit: NonZeroUsize,
+	evictions: u64,
 }
 
 impl<T: Hash + Eq> LruHashSet<T> {
 	/// Create a new `LruHashSet` with the given (exclusive) limit.
 	pub fn new(limit: NonZeroUsize) -> Self {
-		Self { set: LinkedHashSet::new(), limit }
+		Self { set: LinkedHashSet::new(), limit, evictions: 0 }
 	}
 
 	/// Insert element into the set.
@@ -53,11 +54,26 @@ impl<T: Hash + Eq> LruHashSet<T> {
 		if self.set.insert(e) {
 			if self.set.len() == usize::from(self.limit) {
 				self.set.pop_front(); // remove oldest entry
+				self.evictions += 1;
 			}
 			return true
 		}
 		false
 	}
+
+	/// Change the (exclusive) limit of the set, removing the oldest entries over it.
+	pub fn set_limit(&mut self, limit: NonZeroUsize) {
+		self.limit = limit;
+		while self.set.len() >= usize::from(limit) {
+			self.set.pop_front();
+			self.evictions += 1;
+		}
+	}
+
+	/// Number of entries removed so far to maintain the limit.
+	pub fn evictions(&self) -> u64 {
+		self.evictions
+	}
 }
 
 #[cfg(test)]
@@ -84,5 +100,26 @@ mod tests {
 		// We reached the limit. The next element forces the oldest one out.
 		assert!(set.insert(3));
 		assert_eq!(vec![&1, &3], set.set.iter().collect::<Vec<_>>());
+		assert_eq!(set.evictions(), 1);
+	}
+
+	#[test]
+	fn lowering_the_limit_evicts_the_oldest_entries() {
+		let mut set = LruHashSet::<u8>::new(NonZeroUsize::new(4).unwrap());
+		for e in 1..=3 {
+			assert!(set.insert(e));
+		}
+		assert_eq!(set.evictions(), 0);
+
+		set.set_limit(NonZeroUsize::new(2).unwrap());
+		assert_eq!(vec![&3], set.set.iter().collect::<Vec<_>>());
+		assert_eq!(set.evictions(), 2);
+
+		// Raising the limit keeps everything.
+		set.set_limit(NonZeroUsize::new(4).unwrap());
+		assert!(set.insert(4));
+		assert!(set.insert(5));
+		assert_eq!(vec![&3, &4, &5], set.set.iter().collect::<Vec<_>>());
+		assert_eq!(set.evictions(), 2);
 	}
 }
@@ -16,7 +16,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-const PROTOS: &[&str] = &["src/schema/api.v1.proto"];
+const PROTOS: &[&str] = &["src/schema/api.v1.proto", "src/schema/api.v2.proto"];
 
 fn main() {
 	prost_build::compile_protos(PROTOS, &["src/schema"]).unwrap();
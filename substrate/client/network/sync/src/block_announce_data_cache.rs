@@ -0,0 +1,172 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`BlockAnnounceDataCache`] keeps the data associated with received block announcements, so it
+//! can be attached when the block is re-announced.
+
+use schnellru::{Limiter, LruMap};
+use std::{
+	hash::Hash,
+	time::{Duration, Instant},
+};
+
+/// Cached announcement data and the time it was inserted.
+type Entry = (Instant, Vec<u8>);
+
+/// A limiter for a map which is limited by the number of entries and the total size of the
+/// cached data.
+struct ByLengthAndSize {
+	max_entries: usize,
+	max_bytes: usize,
+	bytes: usize,
+}
+
+impl<K> Limiter<K, Entry> for ByLengthAndSize {
+	type KeyToInsert<'a> = K;
+	type LinkType = u32;
+
+	fn is_over_the_limit(&self, length: usize) -> bool {
+		length > self.max_entries || self.bytes > self.max_bytes
+	}
+
+	fn on_insert(&mut self, _length: usize, key: K, value: Entry) -> Option<(K, Entry)> {
+		if self.max_entries == 0 || value.1.len() > self.max_bytes {
+			return None
+		}
+		self.bytes += value.1.len();
+		Some((key, value))
+	}
+
+	fn on_replace(
+		&mut self,
+		_length: usize,
+		_old_key: &mut K,
+		_new_key: K,
+		old_value: &mut Entry,
+		new_value: &mut Entry,
+	) -> bool {
+		if new_value.1.len() > self.max_bytes {
+			return false
+		}
+		self.bytes = self.bytes - old_value.1.len() + new_value.1.len();
+		true
+	}
+
+	fn on_removed(&mut self, _key: &mut K, value: &mut Entry) {
+		self.bytes -= value.1.len();
+	}
+
+	fn on_cleared(&mut self) {
+		self.bytes = 0;
+	}
+
+	fn on_grow(&mut self, _new_memory_usage: usize) -> bool {
+		true
+	}
+}
+
+/// LRU cache of block announcement data, limited by the number of entries and their total size.
+/// Entries expire after a configured time to live.
+pub(crate) struct BlockAnnounceDataCache<H: Hash + PartialEq> {
+	entries: LruMap<H, Entry, ByLengthAndSize>,
+	ttl: Duration,
+}
+
+impl<H: Hash + PartialEq> BlockAnnounceDataCache<H> {
+	/// Create a new cache holding at most `max_entries` entries of `max_bytes` in total, each for
+	/// at most `ttl`.
+	pub fn new(max_entries: u32, max_bytes: usize, ttl: Duration) -> Self {
+		Self {
+			entries: LruMap::new(ByLengthAndSize {
+				max_entries: max_entries as usize,
+				max_bytes,
+				bytes: 0,
+			}),
+			ttl,
+		}
+	}
+
+	/// Cache `data` announced with block `hash`. Data larger than the whole cache is dropped.
+	pub fn insert(&mut self, hash: H, data: Vec<u8>) {
+		self.entries.insert(hash, (Instant::now(), data));
+	}
+
+	/// Data announced with block `hash`, if it is cached and has not expired.
+	pub fn get(&mut self, hash: &H) -> Option<Vec<u8>> {
+		let (inserted, _) = self.entries.peek(hash)?;
+		if inserted.elapsed() > self.ttl {
+			self.entries.remove(hash);
+			return None
+		}
+		self.entries.get(hash).map(|(_, data)| data.clone())
+	}
+
+	/// Total size of the cached data.
+	pub fn bytes(&self) -> usize {
+		self.entries.limiter().bytes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_least_recently_used_entries_by_count() {
+		let mut cache = BlockAnnounceDataCache::new(2, 1024, Duration::from_secs(60));
+		cache.insert(1u32, vec![1]);
+		cache.insert(2, vec![2]);
+		assert_eq!(cache.get(&1), Some(vec![1]));
+
+		cache.insert(3, vec![3]);
+		assert_eq!(cache.get(&2), None);
+		assert_eq!(cache.get(&1), Some(vec![1]));
+		assert_eq!(cache.get(&3), Some(vec![3]));
+	}
+
+	#[test]
+	fn evicts_entries_by_size() {
+		let mut cache = BlockAnnounceDataCache::new(10, 10, Duration::from_secs(60));
+		cache.insert(1u32, vec![0; 4]);
+		cache.insert(2, vec![0; 4]);
+		assert_eq!(cache.bytes(), 8);
+
+		cache.insert(3, vec![0; 4]);
+		assert_eq!(cache.get(&1), None);
+		assert_eq!(cache.bytes(), 8);
+
+		// Data larger than the cache is not cached and doesn't evict anything.
+		cache.insert(4, vec![0; 11]);
+		assert_eq!(cache.get(&4), None);
+		assert_eq!(cache.bytes(), 8);
+
+		// Replacing an entry accounts for the new size.
+		cache.insert(3, vec![0; 7]);
+		assert_eq!(cache.get(&2), None);
+		assert_eq!(cache.bytes(), 7);
+	}
+
+	#[test]
+	fn expired_entries_are_removed() {
+		let mut cache = BlockAnnounceDataCache::new(10, 1024, Duration::ZERO);
+		cache.insert(1u32, vec![1]);
+		std::thread::sleep(Duration::from_millis(1));
+		assert_eq!(cache.get(&1), None);
+		assert_eq!(cache.bytes(), 0);
+	}
+}
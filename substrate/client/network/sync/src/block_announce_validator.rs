@@ -19,14 +19,18 @@
 //! `BlockAnnounceValidator` is responsible for async validation of block announcements.
 
 use crate::futures_stream::FuturesStream;
-use futures::{Future, FutureExt, Stream, StreamExt};
+use futures::{
+	future::{AbortHandle, Abortable, BoxFuture},
+	Future, FutureExt, Stream, StreamExt,
+};
 use libp2p::PeerId;
 use log::{debug, error, trace, warn};
+use sc_network::config::BlockAnnounceValidationOverflow;
 use sc_network_common::sync::message::BlockAnnounce;
 use sp_consensus::block_validation::Validation;
 use sp_runtime::traits::{Block as BlockT, Header, Zero};
 use std::{
-	collections::{hash_map::Entry, HashMap},
+	collections::{hash_map::Entry, HashMap, VecDeque},
 	default::Default,
 	pin::Pin,
 	task::{Context, Poll},
@@ -35,17 +39,6 @@ use std::{
 /// Log target for this file.
 const LOG_TARGET: &str = "sync";
 
-/// Maximum number of concurrent block announce validations.
-///
-/// If the queue reaches the maximum, we drop any new block
-/// announcements.
-const MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS: usize = 256;
-
-/// Maximum number of concurrent block announce validations per peer.
-///
-/// See [`MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS`] for more information.
-const MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER: usize = 4;
-
 /// Item that yields [`Stream`] implementation of [`BlockAnnounceValidator`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum BlockAnnounceValidationResult<H> {
@@ -94,36 +87,84 @@ enum AllocateSlotForBlockAnnounceValidation {
 	MaximumPeerSlotsReached,
 }
 
+/// A block announce validation that has not finished yet.
+struct PendingValidation {
+	/// Identifier of the validation future.
+	id: u64,
+	/// The peer that sent the announcement.
+	peer_id: PeerId,
+	/// Handle to cancel the validation.
+	abort_handle: AbortHandle,
+}
+
+/// Limits of concurrent block announce validations.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ValidationLimits {
+	/// Maximum number of concurrent validations.
+	pub max_validations: usize,
+	/// Maximum number of concurrent validations per peer.
+	pub max_validations_per_peer: usize,
+	/// What to do when the limits are reached.
+	pub overflow: BlockAnnounceValidationOverflow,
+}
+
 pub(crate) struct BlockAnnounceValidator<B: BlockT> {
 	/// A type to check incoming block announcements.
 	validator: Box<dyn sp_consensus::block_validation::BlockAnnounceValidator<B> + Send>,
-	/// All block announcements that are currently being validated.
+	/// All block announcements that are currently being validated, yielding the validation id
+	/// and the result, or `None` if the validation was cancelled.
 	validations: FuturesStream<
-		Pin<Box<dyn Future<Output = BlockAnnounceValidationResult<B::Header>> + Send>>,
+		Pin<
+			Box<
+				dyn Future<Output = (u64, Option<BlockAnnounceValidationResult<B::Header>>)> + Send,
+			>,
+		>,
 	>,
+	/// Validations that have not finished yet, oldest first.
+	pending: VecDeque<PendingValidation>,
+	/// Identifier of the next validation.
+	next_id: u64,
 	/// Number of concurrent block announce validations per peer.
 	validations_per_peer: HashMap<PeerId, usize>,
+	/// Limits of concurrent validations.
+	limits: ValidationLimits,
 }
 
 impl<B: BlockT> BlockAnnounceValidator<B> {
 	pub(crate) fn new(
 		validator: Box<dyn sp_consensus::block_validation::BlockAnnounceValidator<B> + Send>,
+		limits: ValidationLimits,
 	) -> Self {
 		Self {
 			validator,
 			validations: Default::default(),
+			pending: VecDeque::new(),
+			next_id: 0,
 			validations_per_peer: Default::default(),
+			limits,
 		}
 	}
 
+	/// Whether no more announcements should be received until a validation finishes.
+	///
+	/// Only the case with [`BlockAnnounceValidationOverflow::Backpressure`] when all validation
+	/// slots are occupied.
+	pub(crate) fn is_backpressured(&self) -> bool {
+		self.limits.overflow == BlockAnnounceValidationOverflow::Backpressure &&
+			self.pending.len() >= self.limits.max_validations
+	}
+
 	/// Push a block announce validation.
+	///
+	/// Returns the peer flooding the validation queue whose oldest announcement validation was
+	/// cancelled to make room for this one, with [`BlockAnnounceValidationOverflow::DropOldest`].
 	pub(crate) fn push_block_announce_validation(
 		&mut self,
 		peer_id: PeerId,
 		hash: B::Hash,
 		announce: BlockAnnounce<B::Header>,
 		is_best: bool,
-	) {
+	) -> Option<PeerId> {
 		let header = &announce.header;
 		let number = *header.number();
 		debug!(
@@ -141,11 +182,31 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 				peer_id,
 				hash,
 			);
-			return
+			return None
+		}
+
+		// Try to allocate a slot for this block announce validation, cancelling the oldest
+		// validation of the peer occupying the most slots if configured to do so.
+		let mut dropped = None;
+		let mut allocation = self.allocate_slot_for_block_announce_validation(&peer_id);
+		if self.limits.overflow == BlockAnnounceValidationOverflow::DropOldest {
+			let oldest = match allocation {
+				AllocateSlotForBlockAnnounceValidation::Allocated => None,
+				AllocateSlotForBlockAnnounceValidation::TotalMaximumSlotsReached => self
+					.flooding_peer()
+					.and_then(|flooding| {
+						self.pending.iter().position(|pending| pending.peer_id == flooding)
+					}),
+				AllocateSlotForBlockAnnounceValidation::MaximumPeerSlotsReached =>
+					self.pending.iter().position(|pending| pending.peer_id == peer_id),
+			};
+			if let Some(index) = oldest {
+				dropped = self.cancel_validation(index);
+				allocation = self.allocate_slot_for_block_announce_validation(&peer_id);
+			}
 		}
 
-		// Try to allocate a slot for this block announce validation.
-		match self.allocate_slot_for_block_announce_validation(&peer_id) {
+		match allocation {
 			AllocateSlotForBlockAnnounceValidation::Allocated => {},
 			AllocateSlotForBlockAnnounceValidation::TotalMaximumSlotsReached => {
 				warn!(
@@ -155,7 +216,7 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 					hash,
 					peer_id,
 				);
-				return
+				return dropped
 			},
 			AllocateSlotForBlockAnnounceValidation::MaximumPeerSlotsReached => {
 				warn!(
@@ -165,7 +226,7 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 					hash,
 					peer_id,
 				);
-				return
+				return dropped
 			},
 		}
 
@@ -173,7 +234,8 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 		let assoc_data = announce.data.as_ref().map_or(&[][..], |v| v.as_slice());
 		let future = self.validator.validate(header, assoc_data);
 
-		self.validations.push(
+		self.push_validation(
+			peer_id,
 			async move {
 				match future.await {
 					Ok(Validation::Success { is_new_best }) => {
@@ -215,6 +277,50 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 			}
 			.boxed(),
 		);
+
+		dropped
+	}
+
+	/// Start a validation of an announcement from `peer_id`, that can be cancelled until it
+	/// finishes.
+	fn push_validation(
+		&mut self,
+		peer_id: PeerId,
+		validation: BoxFuture<'static, BlockAnnounceValidationResult<B::Header>>,
+	) {
+		let id = self.next_id;
+		self.next_id += 1;
+		let (abort_handle, abort_registration) = AbortHandle::new_pair();
+		self.pending.push_back(PendingValidation { id, peer_id, abort_handle });
+		self.validations.push(
+			Abortable::new(validation, abort_registration)
+				.map(move |result| (id, result.ok()))
+				.boxed(),
+		);
+	}
+
+	/// The peer with the most pending validations, the one with the oldest pending validation
+	/// among those with as many.
+	fn flooding_peer(&self) -> Option<PeerId> {
+		let most = self.validations_per_peer.values().copied().max()?;
+		self.pending
+			.iter()
+			.find(|pending| self.validations_per_peer.get(&pending.peer_id) == Some(&most))
+			.map(|pending| pending.peer_id)
+	}
+
+	/// Cancel the pending validation at `index`, returning the peer that sent the announcement.
+	fn cancel_validation(&mut self, index: usize) -> Option<PeerId> {
+		let pending = self.pending.remove(index)?;
+		pending.abort_handle.abort();
+		self.deallocate_slot_for_block_announce_validation(&pending.peer_id);
+		debug!(
+			target: LOG_TARGET,
+			"Cancelled the oldest block announce validation of {} to make room for a new one.",
+			pending.peer_id,
+		);
+
+		Some(pending.peer_id)
 	}
 
 	/// Checks if there is a slot for a block announce validation.
@@ -232,7 +338,7 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 		&mut self,
 		peer_id: &PeerId,
 	) -> AllocateSlotForBlockAnnounceValidation {
-		if self.validations.len() >= MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS {
+		if self.pending.len() >= self.limits.max_validations {
 			return AllocateSlotForBlockAnnounceValidation::TotalMaximumSlotsReached
 		}
 
@@ -241,14 +347,13 @@ impl<B: BlockT> BlockAnnounceValidator<B> {
 				entry.insert(1);
 				AllocateSlotForBlockAnnounceValidation::Allocated
 			},
-			Entry::Occupied(mut entry) => {
-				if *entry.get() < MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER {
+			Entry::Occupied(mut entry) =>
+				if *entry.get() < self.limits.max_validations_per_peer {
 					*entry.get_mut() += 1;
 					AllocateSlotForBlockAnnounceValidation::Allocated
 				} else {
 					AllocateSlotForBlockAnnounceValidation::MaximumPeerSlotsReached
-				}
-			},
+				},
 		}
 	}
 
@@ -292,11 +397,17 @@ impl<B: BlockT> Stream for BlockAnnounceValidator<B> {
 
 	/// Poll for finished block announce validations. The stream never terminates.
 	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-		let validation = futures::ready!(self.validations.poll_next_unpin(cx))
-			.expect("`FuturesStream` never terminates; qed");
-		self.deallocate_slot_for_block_announce_validation(validation.peer_id());
+		loop {
+			let (id, validation) = futures::ready!(self.validations.poll_next_unpin(cx))
+				.expect("`FuturesStream` never terminates; qed");
+			// Cancelled validations were already removed from the pending ones.
+			let Some(validation) = validation else { continue };
+
+			self.pending.retain(|pending| pending.id != id);
+			self.deallocate_slot_for_block_announce_validation(validation.peer_id());
 
-		Poll::Ready(Some(validation))
+			return Poll::Ready(Some(validation))
+		}
 	}
 }
 
@@ -306,12 +417,34 @@ mod tests {
 	use crate::block_announce_validator::AllocateSlotForBlockAnnounceValidation;
 	use libp2p::PeerId;
 	use sp_consensus::block_validation::DefaultBlockAnnounceValidator;
-	use substrate_test_runtime_client::runtime::Block;
+	use substrate_test_runtime_client::runtime::{Block, Header as TestHeader};
+
+	const LIMITS: ValidationLimits = ValidationLimits {
+		max_validations: 256,
+		max_validations_per_peer: 4,
+		overflow: BlockAnnounceValidationOverflow::DropNewest,
+	};
+
+	fn make_announce(number: u64) -> BlockAnnounce<TestHeader> {
+		BlockAnnounce {
+			header: TestHeader::new(
+				number,
+				Default::default(),
+				Default::default(),
+				Default::default(),
+				Default::default(),
+			),
+			state: None,
+			data: None,
+		}
+	}
 
 	#[test]
 	fn allocate_one_validation_slot() {
-		let mut validator =
-			BlockAnnounceValidator::<Block>::new(Box::new(DefaultBlockAnnounceValidator {}));
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			LIMITS,
+		);
 		let peer_id = PeerId::random();
 
 		assert!(matches!(
@@ -322,8 +455,10 @@ mod tests {
 
 	#[test]
 	fn allocate_validation_slots_for_two_peers() {
-		let mut validator =
-			BlockAnnounceValidator::<Block>::new(Box::new(DefaultBlockAnnounceValidator {}));
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			LIMITS,
+		);
 		let peer_id_1 = PeerId::random();
 		let peer_id_2 = PeerId::random();
 
@@ -339,11 +474,13 @@ mod tests {
 
 	#[test]
 	fn maximum_validation_slots_per_peer() {
-		let mut validator =
-			BlockAnnounceValidator::<Block>::new(Box::new(DefaultBlockAnnounceValidator {}));
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			LIMITS,
+		);
 		let peer_id = PeerId::random();
 
-		for _ in 0..MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER {
+		for _ in 0..LIMITS.max_validations_per_peer {
 			assert!(matches!(
 				validator.allocate_slot_for_block_announce_validation(&peer_id),
 				AllocateSlotForBlockAnnounceValidation::Allocated,
@@ -358,11 +495,13 @@ mod tests {
 
 	#[test]
 	fn validation_slots_per_peer_deallocated() {
-		let mut validator =
-			BlockAnnounceValidator::<Block>::new(Box::new(DefaultBlockAnnounceValidator {}));
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			LIMITS,
+		);
 		let peer_id = PeerId::random();
 
-		for _ in 0..MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS_PER_PEER {
+		for _ in 0..LIMITS.max_validations_per_peer {
 			assert!(matches!(
 				validator.allocate_slot_for_block_announce_validation(&peer_id),
 				AllocateSlotForBlockAnnounceValidation::Allocated,
@@ -384,15 +523,16 @@ mod tests {
 
 	#[test]
 	fn maximum_validation_slots_for_all_peers() {
-		let mut validator =
-			BlockAnnounceValidator::<Block>::new(Box::new(DefaultBlockAnnounceValidator {}));
-
-		for _ in 0..MAX_CONCURRENT_BLOCK_ANNOUNCE_VALIDATIONS {
-			validator.validations.push(
-				futures::future::ready(BlockAnnounceValidationResult::Skip {
-					peer_id: PeerId::random(),
-				})
-				.boxed(),
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			LIMITS,
+		);
+
+		for _ in 0..LIMITS.max_validations {
+			let peer_id = PeerId::random();
+			validator.push_validation(
+				peer_id,
+				futures::future::ready(BlockAnnounceValidationResult::Skip { peer_id }).boxed(),
 			);
 		}
 
@@ -402,4 +542,142 @@ mod tests {
 			AllocateSlotForBlockAnnounceValidation::TotalMaximumSlotsReached,
 		));
 	}
+
+	#[test]
+	fn drop_oldest_cancels_oldest_validation_of_peer() {
+		let limits = ValidationLimits {
+			max_validations_per_peer: 2,
+			overflow: BlockAnnounceValidationOverflow::DropOldest,
+			..LIMITS
+		};
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			limits,
+		);
+		let peer_id = PeerId::random();
+		let other_peer_id = PeerId::random();
+
+		for number in 1..=2 {
+			let announce = make_announce(number);
+			assert_eq!(
+				validator.push_block_announce_validation(
+					peer_id,
+					announce.header.hash(),
+					announce,
+					false
+				),
+				None,
+			);
+		}
+		let announce = make_announce(1);
+		assert_eq!(
+			validator.push_block_announce_validation(
+				other_peer_id,
+				announce.header.hash(),
+				announce,
+				false
+			),
+			None,
+		);
+		let announce = make_announce(3);
+		assert_eq!(
+			validator.push_block_announce_validation(
+				peer_id,
+				announce.header.hash(),
+				announce,
+				false
+			),
+			Some(peer_id),
+		);
+
+		let mut validated = (0..3)
+			.map(|_| match futures::executor::block_on(validator.next()) {
+				Some(BlockAnnounceValidationResult::Process { peer_id, announce, .. }) =>
+					(peer_id == other_peer_id, *announce.header.number()),
+				result => panic!("Unexpected validation result {result:?}"),
+			})
+			.collect::<Vec<_>>();
+		validated.sort();
+		assert_eq!(validated, vec![(false, 2), (false, 3), (true, 1)]);
+		assert!(validator.pending.is_empty());
+		assert!(validator.validations_per_peer.is_empty());
+	}
+
+	#[test]
+	fn backpressure_when_all_slots_are_occupied() {
+		let limits = ValidationLimits {
+			max_validations: 2,
+			overflow: BlockAnnounceValidationOverflow::Backpressure,
+			..LIMITS
+		};
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			limits,
+		);
+
+		for number in 1..=2 {
+			assert!(!validator.is_backpressured());
+			let announce = make_announce(number);
+			validator.push_block_announce_validation(
+				PeerId::random(),
+				announce.header.hash(),
+				announce,
+				false,
+			);
+		}
+		assert!(validator.is_backpressured());
+
+		futures::executor::block_on(validator.next());
+		assert!(!validator.is_backpressured());
+	}
+
+	#[test]
+	fn drop_oldest_cancels_validation_of_flooding_peer() {
+		let limits = ValidationLimits {
+			max_validations: 3,
+			overflow: BlockAnnounceValidationOverflow::DropOldest,
+			..LIMITS
+		};
+		let mut validator = BlockAnnounceValidator::<Block>::new(
+			Box::new(DefaultBlockAnnounceValidator {}),
+			limits,
+		);
+		let honest_peer_id = PeerId::random();
+		let flooding_peer_id = PeerId::random();
+
+		for (peer_id, number) in
+			[(honest_peer_id, 1), (flooding_peer_id, 1), (flooding_peer_id, 2)]
+		{
+			let announce = make_announce(number);
+			assert_eq!(
+				validator.push_block_announce_validation(
+					peer_id,
+					announce.header.hash(),
+					announce,
+					false
+				),
+				None,
+			);
+		}
+		let announce = make_announce(2);
+		assert_eq!(
+			validator.push_block_announce_validation(
+				honest_peer_id,
+				announce.header.hash(),
+				announce,
+				false
+			),
+			Some(flooding_peer_id),
+		);
+
+		let mut validated = (0..3)
+			.map(|_| match futures::executor::block_on(validator.next()) {
+				Some(BlockAnnounceValidationResult::Process { peer_id, announce, .. }) =>
+					(peer_id == flooding_peer_id, *announce.header.number()),
+				result => panic!("Unexpected validation result {result:?}"),
+			})
+			.collect::<Vec<_>>();
+		validated.sort();
+		assert_eq!(validated, vec![(false, 1), (false, 2), (true, 2)]);
+	}
 }
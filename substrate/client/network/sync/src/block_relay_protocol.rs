@@ -18,7 +18,10 @@
 
 use futures::channel::oneshot;
 use libp2p::PeerId;
-use sc_network::request_responses::{ProtocolConfig, RequestFailure};
+use sc_network::{
+	request_responses::{ProtocolConfig, RequestFailure},
+	types::ProtocolName,
+};
 use sc_network_common::sync::message::{BlockData, BlockRequest};
 use sp_runtime::traits::Block as BlockT;
 use std::sync::Arc;
@@ -45,6 +48,20 @@ pub trait BlockDownloader<Block: BlockT>: Send + Sync {
 		request: BlockRequest<Block>,
 	) -> Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled>;
 
+	/// Performs [`BlockDownloader::download_blocks`] over the request-response `protocol`, one of
+	/// the block request protocol names the syncing engine negotiates with the peers.
+	///
+	/// Downloaders speaking a single protocol ignore `protocol`.
+	async fn download_blocks_over(
+		&self,
+		who: PeerId,
+		request: BlockRequest<Block>,
+		protocol: ProtocolName,
+	) -> Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled> {
+		let _ = protocol;
+		self.download_blocks(who, request).await
+	}
+
 	/// Parses the protocol specific response to retrieve the block data.
 	fn block_response_into_blocks(
 		&self,
@@ -69,4 +86,7 @@ pub struct BlockRelayParams<Block: BlockT> {
 	pub server: Box<dyn BlockServer<Block>>,
 	pub downloader: Arc<dyn BlockDownloader<Block>>,
 	pub request_response_config: ProtocolConfig,
+	/// Further block request protocols, e.g. of an earlier naming scheme, tried in order with the
+	/// peers that don't support `request_response_config`.
+	pub fallback_protocols: Vec<ProtocolConfig>,
 }
@@ -34,7 +34,7 @@ use log::debug;
 use prost::Message;
 use sc_client_api::BlockBackend;
 use sc_network::{
-	config::ProtocolId,
+	config::{ProtocolId, DEFAULT_BLOCK_REQUEST_TIMEOUT},
 	request_responses::{
 		IfDisconnected, IncomingRequest, OutgoingResponse, ProtocolConfig, RequestFailure,
 	},
@@ -51,7 +51,6 @@ use std::{
 	cmp::min,
 	hash::{Hash, Hasher},
 	sync::Arc,
-	time::Duration,
 };
 
 const LOG_TARGET: &str = "sync";
@@ -81,7 +80,7 @@ pub fn generate_protocol_config<Hash: AsRef<[u8]>>(
 			.collect(),
 		max_request_size: 1024 * 1024,
 		max_response_size: 16 * 1024 * 1024,
-		request_timeout: Duration::from_secs(20),
+		request_timeout: DEFAULT_BLOCK_REQUEST_TIMEOUT,
 		inbound_queue: None,
 	}
 }
@@ -182,6 +181,7 @@ where
 			server: Box::new(Self { client, request_receiver, seen_requests }),
 			downloader: Arc::new(FullBlockDownloader::new(protocol_config.name.clone(), network)),
 			request_response_config: protocol_config,
+			fallback_protocols: Vec::new(),
 		}
 	}
 
@@ -568,6 +568,15 @@ impl<B: BlockT> BlockDownloader<B> for FullBlockDownloader {
 		&self,
 		who: PeerId,
 		request: BlockRequest<B>,
+	) -> Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled> {
+		self.download_blocks_over(who, request, self.protocol_name.clone()).await
+	}
+
+	async fn download_blocks_over(
+		&self,
+		who: PeerId,
+		request: BlockRequest<B>,
+		protocol: ProtocolName,
 	) -> Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled> {
 		// Build the request protobuf.
 		let bytes = BlockRequestSchema {
@@ -583,13 +592,7 @@ impl<B: BlockT> BlockDownloader<B> for FullBlockDownloader {
 		.encode_to_vec();
 
 		let (tx, rx) = oneshot::channel();
-		self.network.start_request(
-			who,
-			self.protocol_name.clone(),
-			bytes,
-			tx,
-			IfDisconnected::ImmediateError,
-		);
+		self.network.start_request(who, protocol, bytes, tx, IfDisconnected::ImmediateError);
 		rx.await
 	}
 
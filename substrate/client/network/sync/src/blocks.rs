@@ -93,6 +93,10 @@ impl<B: BlockT> BlockCollection<B> {
 				trace!(target: "sync", "Ignored block data already downloaded: {}", start);
 				return
 			},
+			Some(&BlockRangeState::Queued { .. }) => {
+				trace!(target: "sync", "Ignored block data already queued for import: {}", start);
+				return
+			},
 			_ => (),
 		}
 
@@ -178,6 +182,14 @@ impl<B: BlockT> BlockCollection<B> {
 		Some(range)
 	}
 
+	/// Number of ranges being downloaded.
+	pub fn downloading_ranges(&self) -> usize {
+		self.blocks
+			.values()
+			.filter(|state| matches!(state, BlockRangeState::Downloading { .. }))
+			.count()
+	}
+
 	/// Get a valid chain of blocks ordered in descending order and ready for importing into
 	/// the blockchain.
 	/// `from` is the maximum block number for the start of the range that we are interested in.
@@ -431,4 +443,30 @@ mod test {
 		assert!(bc.blocks.is_empty());
 		assert!(bc.queued_blocks.is_empty());
 	}
+
+	#[test]
+	fn late_duplicate_response_is_ignored() {
+		let mut bc: BlockCollection<Block> = BlockCollection::new();
+		let peer1 = PeerId::random();
+		let peer2 = PeerId::random();
+
+		let blocks = generate_blocks(5);
+
+		// Without racing the second peer gets the next range.
+		assert_eq!(bc.needed_blocks(peer1, 5, 50, 39, 1, 200), Some(40..45));
+		assert_eq!(bc.needed_blocks(peer2, 5, 50, 39, 1, 200), Some(45..50));
+		bc.clear_peer_download(&peer2);
+
+		// Racing for the same range.
+		assert_eq!(bc.needed_blocks(peer2, 5, 50, 39, 2, 200), Some(40..45));
+
+		bc.clear_peer_download(&peer1);
+		bc.insert(40, blocks.clone(), peer1);
+		assert_eq!(bc.ready_blocks(40).len(), 5);
+
+		// The response of the slower peer doesn't make the blocks ready again.
+		bc.clear_peer_download(&peer2);
+		bc.insert(40, blocks, peer2);
+		assert!(bc.ready_blocks(40).is_empty());
+	}
 }
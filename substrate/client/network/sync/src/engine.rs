@@ -20,15 +20,33 @@
 //! to tip and keep the blockchain up to date with network updates.
 
 use crate::{
+	block_announce_data_cache::BlockAnnounceDataCache,
 	block_announce_validator::{
 		BlockAnnounceValidationResult, BlockAnnounceValidator as BlockAnnounceValidatorStream,
+		ValidationLimits,
 	},
 	block_relay_protocol::{BlockDownloader, BlockResponseError},
+	engine_state::SyncEngineState,
+	futures_stream::FuturesStream,
+	instance,
 	pending_responses::{PendingResponses, ResponseEvent},
-	schema::v1::{StateRequest, StateResponse},
-	service::{self, chain_sync::ToServiceCommand},
-	warp::WarpSyncParams,
-	BlockRequestEvent, ChainSync, ClientError, SyncingService,
+	persisted_peers::{self, PersistedPeer},
+	rate_limit::RateLimiter,
+	request_retry::RequestFailureKind,
+	schema::{
+		v1::{StateRequest, StateResponse},
+		v2,
+	},
+	service::{
+		self,
+		chain_sync::{RequestBlockError, ToServiceCommand},
+		command_channel::{command_channel, CommandReceiver},
+		network::{PeerAuditAction, PeerAuditEvent},
+	},
+	sync_snapshot,
+	throughput::ThroughputTracker,
+	warp::{WarpSyncParams, WarpSyncTargetError},
+	warp_progress, BlockRequestEvent, ChainSync, ClientError, SyncingService,
 };
 
 use codec::{Decode, Encode};
@@ -38,20 +56,20 @@ use futures::{
 	FutureExt, StreamExt,
 };
 use futures_timer::Delay;
-use libp2p::{request_response::OutboundFailure, PeerId};
+use libp2p::{multiaddr::Protocol, request_response::OutboundFailure, Multiaddr, PeerId};
 use log::{debug, trace};
 use prometheus_endpoint::{
-	register, Gauge, GaugeVec, MetricSource, Opts, PrometheusError, Registry, SourcedGauge, U64,
+	exponential_buckets, register, Counter, CounterVec, Gauge, GaugeVec, HistogramOpts,
+	HistogramVec, MetricSource, Opts, PrometheusError, Registry, SourcedGauge, U64,
 };
 use prost::Message;
-use schnellru::{ByLength, LruMap};
 
 use sc_client_api::{BlockBackend, HeaderBackend, ProofProvider};
 use sc_consensus::import_queue::ImportQueueService;
 use sc_network::{
 	config::{
-		FullNetworkConfiguration, NonDefaultSetConfig, NonReservedPeerMode, NotificationHandshake,
-		ProtocolId, SetConfig,
+		FullNetworkConfiguration, LightPeerAnnouncements, NonDefaultSetConfig, NonReservedPeerMode,
+		NotificationHandshake, ProtocolId, SetConfig,
 	},
 	request_responses::{IfDisconnected, RequestFailure},
 	utils::LruHashSet,
@@ -60,58 +78,85 @@ use sc_network::{
 use sc_network_common::{
 	role::Roles,
 	sync::{
-		message::{BlockAnnounce, BlockAnnouncesHandshake, BlockRequest, BlockState},
+		message::{
+			BlockAnnounce, BlockAnnouncesHandshake, BlockAttributes, BlockData, BlockRequest,
+			BlockState, Direction, FromBlock,
+		},
 		warp::{EncodedProof, WarpProofRequest},
 		BadPeer, ChainSync as ChainSyncT, ExtendedPeerInfo, OpaqueStateRequest,
-		OpaqueStateResponse, PeerRequest, SyncEvent,
+		OpaqueStateResponse, PeerRequest, PeerRequestType, PeerStatistics, SyncEvent,
+		SyncStatus, SyncThroughput,
 	},
 };
 use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedReceiver, TracingUnboundedSender};
 use sp_blockchain::HeaderMetadata;
-use sp_consensus::block_validation::BlockAnnounceValidator;
-use sp_runtime::traits::{Block as BlockT, Header, NumberFor, Zero};
+use sp_consensus::block_validation::{BlockAnnounceValidator, ChainedBlockAnnounceValidator};
+use sp_runtime::traits::{
+	AtLeast32BitUnsigned, Block as BlockT, Header, NumberFor, SaturatedConversion, Saturating,
+	Zero,
+};
+use tracing::Instrument;
 
 use std::{
 	collections::{HashMap, HashSet},
-	iter,
+	mem,
 	num::NonZeroUsize,
+	path::PathBuf,
 	sync::{
 		atomic::{AtomicBool, AtomicUsize, Ordering},
 		Arc,
 	},
-	task::Poll,
 	time::{Duration, Instant},
 };
 
 /// Log target for this file.
 const LOG_TARGET: &'static str = "sync";
 
+/// Target of the spans of the sections of the [`SyncingEngine`] event loop.
+const POLL_LOG_TARGET: &'static str = "sync::poll";
+
 /// Interval at which we perform time based maintenance
 const TICK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1100);
 
-/// Maximum number of known block hashes to keep for a peer.
-const MAX_KNOWN_BLOCKS: usize = 1024; // ~32kb per peer + LruHashSet overhead
+/// Full peers whose best block is more than this many blocks below a block aren't sent its
+/// announcement, they catch up through block requests anyway.
+const MAX_ANNOUNCE_DISTANCE: u32 = 256;
+
+/// Peers that understand keep-alive messages are sent one when nothing else was sent to them for
+/// this long, so that they don't mistake a quiet chain for a stall.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Peers remember at least the hashes of the blocks announced over this long, so that they aren't
+/// announced the blocks again on chains producing them quickly.
+const KNOWN_BLOCKS_WINDOW: Duration = Duration::from_secs(10 * 60);
 
 /// If the block announces stream to peer has been inactive for 30 seconds meaning local node
 /// has not sent or received block announcements to/from the peer, report the node for inactivity,
 /// disconnect it and attempt to establish connection to some other peer.
 const INACTIVITY_EVICT_THRESHOLD: Duration = Duration::from_secs(30);
 
-/// When `SyncingEngine` is started, wait two minutes before actually staring to count peers as
-/// evicted.
-///
-/// Parachain collator may incorrectly get evicted because it's waiting to receive a number of
-/// relaychain blocks before it can start creating parachain blocks. During this wait,
-/// `SyncingEngine` still counts it as active and as the peer is not sending blocks, it may get
-/// evicted if a block is not received within the first 30 secons since the peer connected.
-///
-/// To prevent this from happening, define a threshold for how long `SyncingEngine` should wait
-/// before it starts evicting peers.
-const INITIAL_EVICTION_WAIT_PERIOD: Duration = Duration::from_secs(2 * 60);
+/// Time after which a peer whose best block didn't change is deprioritized as a sync source.
+const STALE_PEER_BEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Number of blocks the best block advances by after which status subscribers are sent an update
+/// even if nothing else changed.
+const STATUS_PROGRESS_BLOCKS: u32 = 128;
+
+/// Peers are disconnected after sending this many block announcements that fail to decode.
+const MAX_UNDECODABLE_ANNOUNCEMENTS: u32 = 8;
 
 /// Maximum allowed size for a block announce.
 const MAX_BLOCK_ANNOUNCE_SIZE: u64 = 1024 * 1024;
 
+/// Maximum number of useful sync peers persisted across restarts.
+const MAX_PERSISTED_PEERS: usize = 32;
+
+/// How long the peers restored from an earlier session stay reserved to be dialed.
+const RESTORED_PEERS_RESERVATION: Duration = Duration::from_secs(60);
+
+/// Time window over which the download throughput is measured.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(30);
+
 mod rep {
 	use sc_network::ReputationChange as Rep;
 	/// Peer has different genesis.
@@ -128,6 +173,67 @@ mod rep {
 	pub const REFUSED: Rep = Rep::new(-(1 << 10), "Request refused");
 	/// Reputation change when a peer doesn't respond in time to our messages.
 	pub const TIMEOUT: Rep = Rep::new(-(1 << 10), "Request timeout");
+	/// Peer occupies the most block announce validation slots when they are all occupied.
+	pub const ANNOUNCEMENT_DROPPED: Rep =
+		Rep::new(-(1 << 8), "Block announcement dropped from full validation queue");
+	/// Peer sent a block announcement with data over the size limit.
+	pub const OVERSIZED_ANNOUNCEMENT_DATA: Rep =
+		Rep::new(-(1 << 12), "Block announcement data over the size limit");
+	/// Peer sent a block announcement that failed to decode.
+	pub const UNDECODABLE_ANNOUNCEMENT: Rep =
+		Rep::new(-(1 << 10), "Undecodable block announcement");
+	/// Peer was banned through the syncing service.
+	pub const BANNED: Rep = Rep::new(-(1 << 12), "Banned through the syncing service");
+}
+
+/// Names of the peer statistics reported to Prometheus, in the order of
+/// [`peer_statistics_values`].
+const PEER_STATISTICS: [&str; 8] = [
+	"blocks_received",
+	"bytes_received",
+	"average_response_time_ms",
+	"failed_requests",
+	"timed_out_requests",
+	"announcements_received",
+	"max_blocks_per_request",
+	"known_blocks_evicted",
+];
+
+fn peer_statistics_values(statistics: &PeerStatistics) -> [u64; 8] {
+	let average_response_time = statistics
+		.average_response_time()
+		.map_or(0, |time| u64::try_from(time.as_millis()).unwrap_or(u64::MAX));
+	[
+		statistics.blocks_received,
+		statistics.bytes_received,
+		average_response_time,
+		statistics.failed_requests,
+		statistics.timed_out_requests,
+		statistics.announcements_received,
+		statistics.max_blocks_per_request.into(),
+		statistics.known_blocks_evicted,
+	]
+}
+
+/// Roles of peers the statistics are reported for.
+const PEER_ROLES: [&str; 3] = ["authority", "full", "light"];
+
+/// Statistics of a group of peers. The counters are summed, `max_blocks_per_request` is the
+/// highest of the peers.
+fn sum_peer_statistics<'a>(peers: impl Iterator<Item = &'a PeerStatistics>) -> PeerStatistics {
+	peers.fold(PeerStatistics::default(), |mut total, statistics| {
+		total.blocks_received += statistics.blocks_received;
+		total.bytes_received += statistics.bytes_received;
+		total.responses_received += statistics.responses_received;
+		total.total_response_time += statistics.total_response_time;
+		total.failed_requests += statistics.failed_requests;
+		total.timed_out_requests += statistics.timed_out_requests;
+		total.announcements_received += statistics.announcements_received;
+		total.max_blocks_per_request =
+			total.max_blocks_per_request.max(statistics.max_blocks_per_request);
+		total.known_blocks_evicted += statistics.known_blocks_evicted;
+		total
+	})
 }
 
 struct Metrics {
@@ -135,6 +241,20 @@ struct Metrics {
 	queued_blocks: Gauge<U64>,
 	fork_targets: Gauge<U64>,
 	justifications: GaugeVec<U64>,
+	peer_statistics: GaugeVec<U64>,
+	peer_slots: GaugeVec<U64>,
+	block_announce_data_cache_bytes: Gauge<U64>,
+	block_announce_data_cache_lookups: CounterVec<U64>,
+	bandwidth: CounterVec<U64>,
+	request_timeouts: CounterVec<U64>,
+	request_duration: HistogramVec,
+	block_announces: CounterVec<U64>,
+	oversized_block_announce_data: Counter<U64>,
+	undecodable_block_announces: Counter<U64>,
+	known_blocks_evictions: Counter<U64>,
+	poll_duration: CounterVec<U64>,
+	peer_reports: CounterVec<U64>,
+	peer_disconnects: CounterVec<U64>,
 }
 
 impl Metrics {
@@ -164,8 +284,172 @@ impl Metrics {
 				)?;
 				register(g, r)?
 			},
+			peer_statistics: {
+				let g = GaugeVec::new(
+					Opts::new(
+						"substrate_sync_peer_statistics",
+						"Sync statistics of connected peers, summed by role",
+					),
+					&["role", "statistic"],
+				)?;
+				register(g, r)?
+			},
+			peer_slots: {
+				let g = GaugeVec::new(
+					Opts::new(
+						"substrate_sync_peer_slots",
+						"Number of occupied peer slots and their limit, by kind of slot",
+					),
+					&["slot", "status"],
+				)?;
+				register(g, r)?
+			},
+			block_announce_data_cache_bytes: {
+				let g = Gauge::new(
+					"substrate_sync_block_announce_data_cache_bytes",
+					"Total size of the cached block announcement data",
+				)?;
+				register(g, r)?
+			},
+			block_announce_data_cache_lookups: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_block_announce_data_cache_lookups",
+						"Number of block announcement data cache lookups",
+					),
+					&["result"],
+				)?;
+				register(c, r)?
+			},
+			bandwidth: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_bandwidth_bytes",
+						"Bytes exchanged with peers on the sync protocols, by role of the peer",
+					),
+					&["protocol", "direction", "role"],
+				)?;
+				register(c, r)?
+			},
+			request_timeouts: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_request_timeouts",
+						"Number of sync requests that timed out, by protocol",
+					),
+					&["protocol"],
+				)?;
+				register(c, r)?
+			},
+			request_duration: {
+				let h = HistogramVec::new(
+					HistogramOpts {
+						common_opts: Opts::new(
+							"substrate_sync_request_duration_seconds",
+							"Time between sending a sync request and its outcome, by protocol and \
+							outcome",
+						),
+						buckets: exponential_buckets(0.01, 2.0, 14)
+							.expect("parameters are always valid values; qed"),
+					},
+					&["protocol", "outcome"],
+				)?;
+				register(h, r)?
+			},
+			block_announces: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_block_announces_sent",
+						"Number of block announcements to peers, by whether they were sent, \
+						delayed by the rate limit, coalesced into a newer announcement or skipped \
+						as the peer doesn't need them",
+					),
+					&["result"],
+				)?;
+				register(c, r)?
+			},
+			oversized_block_announce_data: {
+				let c = Counter::new(
+					"substrate_sync_oversized_block_announce_data",
+					"Number of received block announcements dropped as their data is over the size \
+					limit",
+				)?;
+				register(c, r)?
+			},
+			undecodable_block_announces: {
+				let c = Counter::new(
+					"substrate_sync_undecodable_block_announces",
+					"Number of received block announcements that failed to decode",
+				)?;
+				register(c, r)?
+			},
+			known_blocks_evictions: {
+				let c = Counter::new(
+					"substrate_sync_known_blocks_evictions",
+					"Number of block hashes dropped from those peers are known to have",
+				)?;
+				register(c, r)?
+			},
+			peer_reports: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_peer_reports",
+						"Number of reputation changes of peers by syncing, by reason",
+					),
+					&["reason"],
+				)?;
+				register(c, r)?
+			},
+			peer_disconnects: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_peer_disconnects",
+						"Number of peers disconnected by syncing, by protocol",
+					),
+					&["protocol"],
+				)?;
+				register(c, r)?
+			},
+			poll_duration: {
+				let c = CounterVec::new(
+					Opts::new(
+						"substrate_sync_poll_duration_microseconds",
+						"Time spent in each section of the sync engine poll while poll profiling \
+						is enabled",
+					),
+					&["section"],
+				)?;
+				register(c, r)?
+			},
 		})
 	}
+
+	/// Account `bytes` sent or received on `protocol` to or from a peer with `roles`.
+	fn report_bandwidth(&self, protocol: &str, direction: &str, roles: Roles, bytes: usize) {
+		self.bandwidth
+			.with_label_values(&[protocol, direction, Self::role_label(roles)])
+			.inc_by(bytes as u64);
+	}
+
+	/// Label of a peer with `roles` in the metrics, one of [`PEER_ROLES`].
+	fn role_label(roles: Roles) -> &'static str {
+		if roles.is_authority() {
+			"authority"
+		} else if roles.is_full() {
+			"full"
+		} else {
+			"light"
+		}
+	}
+
+	/// Label of the protocol of `request` in the metrics.
+	fn protocol_label(request: PeerRequestType) -> &'static str {
+		match request {
+			PeerRequestType::Block => "block-request",
+			PeerRequestType::State => "state-request",
+			PeerRequestType::WarpProof => "warp-proof-request",
+		}
+	}
 }
 
 /// The "major syncing" metric.
@@ -199,6 +483,41 @@ impl MetricSource for MajorSyncingGauge {
 	}
 }
 
+/// Version of the block announces protocol negotiated with a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockAnnouncesVersion {
+	/// `/block-announces/1`.
+	V1 = 1,
+	/// `/block-announces/2`. Nodes speaking it also understand version 2 handshakes and empty
+	/// keep-alive notifications.
+	V2 = 2,
+}
+
+impl BlockAnnouncesVersion {
+	/// Most recent version, negotiated in preference to the older ones.
+	const LATEST: Self = Self::V2;
+
+	/// Name of this version of the block announces protocol of the chain with `genesis_hash`.
+	fn protocol_name(self, genesis_hash: &[u8], fork_id: &Option<String>) -> ProtocolName {
+		let genesis_hash = array_bytes::bytes2hex("", genesis_hash);
+		let version = self as u8;
+		if let Some(fork_id) = fork_id {
+			format!("/{genesis_hash}/{fork_id}/block-announces/{version}").into()
+		} else {
+			format!("/{genesis_hash}/block-announces/{version}").into()
+		}
+	}
+
+	/// Version negotiated when the substream was opened with `negotiated_fallback`.
+	fn negotiated(negotiated_fallback: Option<&ProtocolName>) -> Self {
+		match negotiated_fallback {
+			None => Self::LATEST,
+			Some(name) if name.ends_with("/block-announces/2") => Self::V2,
+			Some(_) => Self::V1,
+		}
+	}
+}
+
 /// Peer information
 #[derive(Debug)]
 pub struct Peer<B: BlockT> {
@@ -209,6 +528,113 @@ pub struct Peer<B: BlockT> {
 	sink: NotificationsSink,
 	/// Is the peer inbound.
 	inbound: bool,
+	/// Address we dialed to reach the peer, if any.
+	address: Option<Multiaddr>,
+	/// Sync statistics of the peer.
+	statistics: PeerStatistics,
+	/// Limits the block announcements sent to the peer, if configured.
+	announce_limiter: Option<RateLimiter>,
+	/// Most recent encoded block announcement held back by `announce_limiter`.
+	pending_announcement: Option<Vec<u8>>,
+	/// Version of the block announces protocol negotiated with the peer.
+	version: BlockAnnouncesVersion,
+	/// The peer understands empty keep-alive notifications.
+	keep_alive: bool,
+	/// Instant when the last notification was sent to the peer.
+	last_notification_sent: Instant,
+	/// Instant when the best block of the peer last changed.
+	best_updated: Instant,
+	/// Number of block announcements from the peer that failed to decode.
+	undecodable_announcements: u32,
+	/// Index of the block request protocol used with the peer in
+	/// [`SyncingEngine::block_request_protocol_names`].
+	block_request_protocol: usize,
+	/// Whether state proofs are requested from the peer over the v2 state request protocol, until
+	/// it turns out not to support it.
+	state_request_v2: bool,
+	/// Whether the pending state request to the peer was sent over the v2 state request protocol.
+	pending_state_frame: bool,
+}
+
+impl<B: BlockT> Peer<B> {
+	/// Send the encoded block announcement `message` if the rate limit allows it, otherwise hold
+	/// it back in place of any announcement held back before.
+	fn announce(&mut self, message: Vec<u8>, metrics: Option<&Metrics>, now: Instant) {
+		if self.pending_announcement.take().is_some() {
+			if let Some(metrics) = metrics {
+				metrics.block_announces.with_label_values(&["coalesced"]).inc();
+			}
+		}
+
+		if self.announce_limiter.as_mut().map_or(true, |limiter| limiter.try_acquire(now)) {
+			self.send_announcement(message, metrics, now);
+		} else {
+			if let Some(metrics) = metrics {
+				metrics.block_announces.with_label_values(&["delayed"]).inc();
+			}
+			self.pending_announcement = Some(message);
+		}
+	}
+
+	/// Send the held back block announcement, if any and the rate limit allows it.
+	///
+	/// Returns `true` if an announcement was sent.
+	fn flush_announcement(&mut self, metrics: Option<&Metrics>, now: Instant) -> bool {
+		if self.pending_announcement.is_none() ||
+			!self.announce_limiter.as_mut().map_or(true, |limiter| limiter.try_acquire(now))
+		{
+			return false
+		}
+		if let Some(message) = self.pending_announcement.take() {
+			self.send_announcement(message, metrics, now);
+		}
+		true
+	}
+
+	/// Remember that the peer knows of the block `hash`.
+	///
+	/// Returns `true` if the peer wasn't known to know of it yet.
+	fn know_block(&mut self, hash: B::Hash, metrics: Option<&Metrics>) -> bool {
+		let evictions = self.known_blocks.evictions();
+		let inserted = self.known_blocks.insert(hash);
+		self.record_known_blocks_evictions(evictions, metrics);
+		inserted
+	}
+
+	/// Change the number of block hashes remembered for the peer.
+	fn set_known_blocks_limit(&mut self, limit: NonZeroUsize, metrics: Option<&Metrics>) {
+		let evictions = self.known_blocks.evictions();
+		self.known_blocks.set_limit(limit);
+		self.record_known_blocks_evictions(evictions, metrics);
+	}
+
+	fn record_known_blocks_evictions(&mut self, before: u64, metrics: Option<&Metrics>) {
+		let evictions = self.known_blocks.evictions();
+		if let Some(metrics) = metrics {
+			metrics.known_blocks_evictions.inc_by(evictions - before);
+		}
+		self.statistics.known_blocks_evicted = evictions;
+	}
+
+	/// Send an empty keep-alive notification if the peer understands it and nothing was sent to
+	/// it for [`KEEP_ALIVE_INTERVAL`].
+	fn keep_alive(&mut self, now: Instant) {
+		if self.keep_alive &&
+			now.saturating_duration_since(self.last_notification_sent) >= KEEP_ALIVE_INTERVAL
+		{
+			self.last_notification_sent = now;
+			self.sink.send_sync_notification(Vec::new());
+		}
+	}
+
+	fn send_announcement(&mut self, message: Vec<u8>, metrics: Option<&Metrics>, now: Instant) {
+		if let Some(metrics) = metrics {
+			metrics.block_announces.with_label_values(&["sent"]).inc();
+			metrics.report_bandwidth("block-announces", "sent", self.info.roles, message.len());
+		}
+		self.last_notification_sent = now;
+		self.sink.send_sync_notification(message);
+	}
 }
 
 pub struct SyncingEngine<B: BlockT, Client> {
@@ -229,7 +655,7 @@ pub struct SyncingEngine<B: BlockT, Client> {
 	network_service: service::network::NetworkServiceHandle,
 
 	/// Channel for receiving service commands
-	service_rx: TracingUnboundedReceiver<ToServiceCommand<B>>,
+	service_rx: CommandReceiver<B>,
 
 	/// Channel for receiving inbound connections from `Protocol`.
 	rx: sc_utils::mpsc::TracingUnboundedReceiver<sc_network::SyncEvent<B>>,
@@ -243,9 +669,24 @@ pub struct SyncingEngine<B: BlockT, Client> {
 	/// Set of channels for other protocols that have subscribed to syncing events.
 	event_streams: Vec<TracingUnboundedSender<SyncEvent>>,
 
+	/// Set of channels subscribed to changes of the sync status.
+	status_streams: Vec<TracingUnboundedSender<SyncStatus<B>>>,
+
+	/// Peers reported or disconnected through `network_service` and `ChainSync`.
+	peer_audit_rx: TracingUnboundedReceiver<PeerAuditEvent>,
+
+	/// Set of channels subscribed to the peers reported or disconnected.
+	peer_audit_streams: Vec<TracingUnboundedSender<PeerAuditEvent>>,
+
+	/// Status last sent to `status_streams` and the best block number at the time.
+	last_status: Option<(SyncStatus<B>, NumberFor<B>)>,
+
 	/// Interval at which we call `tick`.
 	tick_timeout: Delay,
 
+	/// Account the time spent in each section of `poll` in the metrics.
+	profile_poll: bool,
+
 	/// All connected peers. Contains both full and light node peers.
 	peers: HashMap<PeerId, Peer<B>>,
 
@@ -259,6 +700,20 @@ pub struct SyncingEngine<B: BlockT, Client> {
 	/// List of nodes that should never occupy peer slots.
 	default_peers_set_no_slot_peers: HashSet<PeerId>,
 
+	/// Peers connected without peer slots only to request justifications from.
+	justification_peers: HashSet<PeerId>,
+
+	/// Reserved nodes of the notification protocols, which are always important peers.
+	notification_reserved_peers: HashSet<PeerId>,
+
+	/// Whether block, state and warp proof requests are only sent to the reserved nodes of the
+	/// default peer set.
+	sync_from_reserved_peers_only: bool,
+
+	/// Whether important and no-slot peers are evicted along with the others when syncing has
+	/// stalled.
+	evict_reserved_peers_on_stall: bool,
+
 	/// Value that was passed as part of the configuration. Used to cap the number of full
 	/// nodes.
 	default_peers_set_num_full: usize,
@@ -276,7 +731,7 @@ pub struct SyncingEngine<B: BlockT, Client> {
 	block_announce_validator: BlockAnnounceValidatorStream<B>,
 
 	/// A cache for the data that was associated to a block announcement.
-	block_announce_data_cache: LruMap<B::Hash, Vec<u8>>,
+	block_announce_data_cache: BlockAnnounceDataCache<B::Hash>,
 
 	/// The `PeerId`'s of all boot nodes.
 	boot_node_ids: HashSet<PeerId>,
@@ -291,24 +746,103 @@ pub struct SyncingEngine<B: BlockT, Client> {
 	/// Prometheus metrics.
 	metrics: Option<Metrics>,
 
-	/// When the syncing was started.
+	/// Why the node is intentionally idle, e.g. a parachain collator waiting for relay chain
+	/// blocks, if it is.
 	///
-	/// Stored as an `Option<Instant>` so once the initial wait has passed, `SyncingEngine`
-	/// can reset the peer timers and continue with the normal eviction process.
-	syncing_started: Option<Instant>,
+	/// No blocks are exchanged while waiting, so peers aren't evicted for inactivity until
+	/// [`SyncingService::set_ready`] is called.
+	waiting: Option<String>,
 
 	/// Instant when the last notification was sent or received.
 	last_notification_io: Instant,
 
+	/// Syncing is paused: no new requests are sent and received responses are not processed.
+	paused: bool,
+
+	/// Block requests `ChainSync` issued while syncing is paused, sent once it is resumed.
+	paused_block_requests: HashMap<PeerId, BlockRequest<B>>,
+
+	/// Peers banned through [`SyncingService`](service::chain_sync::SyncingService), with the
+	/// time of the ban and its duration.
+	banned_peers: HashMap<PeerId, (Instant, Duration)>,
+
+	/// Download throughput of recent responses.
+	throughput: ThroughputTracker,
+
+	/// Maximum number of block announcements sent to a peer per second, unlimited if `None`.
+	block_announce_rate_limit: Option<u32>,
+
+	/// Which block announcements are sent to light peers.
+	light_peer_announcements: LightPeerAnnouncements,
+
+	/// Configured minimum number of block hashes remembered per peer.
+	max_known_blocks: NonZeroUsize,
+
+	/// Number of block hashes currently remembered per peer, scaled with the block production
+	/// rate.
+	known_blocks_limit: NonZeroUsize,
+
+	/// Number of blocks announced since `known_blocks_window_start`.
+	blocks_announced: usize,
+
+	/// Start of the current [`KNOWN_BLOCKS_WINDOW`].
+	known_blocks_window_start: Instant,
+
+	/// Maximum size of the data attached to a received block announcement.
+	max_block_announce_data_size: usize,
+
+	/// Peers, like the known validators, that blocks are announced to before other peers.
+	priority_peers: HashSet<PeerId>,
+
+	/// Number of the full peer slots kept for `priority_peers`.
+	authority_peer_slots: usize,
+
+	/// Include the last finalized block in the block announces handshake.
+	announce_finalized_in_handshake: bool,
+
+	/// Where useful sync peers are persisted on shutdown, `None` if they are not persisted.
+	persisted_peers_path: Option<PathBuf>,
+
+	/// Peers that served blocks in this or an earlier session, persisted on shutdown.
+	persisted_peers: HashMap<PeerId, PersistedPeer<B>>,
+
+	/// Peers restored from an earlier session that are reserved until
+	/// `restored_peers_unreserved_at`, so they are dialed right away.
+	reserved_restored_peers: Vec<PeerId>,
+
+	/// When the restored peers stop being reserved.
+	restored_peers_unreserved_at: Instant,
+
+	/// Where the sync progress is saved when the engine is stopped, `None` if it is not saved.
+	sync_snapshot_path: Option<PathBuf>,
+
+	/// Sender to notify once the engine has stopped, if it was requested to stop.
+	stop_requested: Option<oneshot::Sender<()>>,
+
+	/// Name of this sync instance, if several run in the process.
+	instance_name: Option<String>,
+
 	/// Pending responses
 	pending_responses: PendingResponses<B>,
 
 	/// Block downloader
 	block_downloader: Arc<dyn BlockDownloader<B>>,
 
+	/// Block request protocol names by priority, peers not supporting one are sent the block
+	/// requests over the next. The downloader's own protocol is used if empty.
+	block_request_protocol_names: Vec<ProtocolName>,
+
+	/// Block requests made through [`SyncingService::request_block_from`], answered outside of
+	/// `ChainSync`.
+	one_off_requests: FuturesStream<BoxFuture<'static, ()>>,
+
 	/// Protocol name used to send out state requests
 	state_request_protocol_name: ProtocolName,
 
+	/// Protocol name of the v2 state request protocol, used to download state proofs in bounded
+	/// frames from the peers supporting it.
+	state_request_v2_protocol_name: Option<ProtocolName>,
+
 	/// Protocol name used to send out warp sync requests
 	warp_sync_protocol_name: Option<ProtocolName>,
 }
@@ -331,17 +865,38 @@ where
 		net_config: &FullNetworkConfiguration,
 		protocol_id: ProtocolId,
 		fork_id: &Option<String>,
-		block_announce_validator: Box<dyn BlockAnnounceValidator<B> + Send>,
+		mut block_announce_validators: Vec<Box<dyn BlockAnnounceValidator<B> + Send>>,
 		warp_sync_params: Option<WarpSyncParams<B>>,
 		network_service: service::network::NetworkServiceHandle,
 		import_queue: Box<dyn ImportQueueService<B>>,
 		block_downloader: Arc<dyn BlockDownloader<B>>,
+		block_request_protocol_names: Vec<ProtocolName>,
 		state_request_protocol_name: ProtocolName,
+		state_request_v2_protocol_name: Option<ProtocolName>,
 		warp_sync_protocol_name: Option<ProtocolName>,
 		rx: sc_utils::mpsc::TracingUnboundedReceiver<sc_network::SyncEvent<B>>,
 	) -> Result<(Self, SyncingService<B>, NonDefaultSetConfig), ClientError> {
 		let mode = net_config.network_config.sync_mode;
+		let instance_name = net_config.network_config.sync_instance_name.clone();
+		let _span = instance_name.as_deref().map(instance::log_span).map(tracing::Span::entered);
+		let instance_registry = match (&instance_name, metrics_registry) {
+			(Some(name), Some(registry)) => Some(
+				instance::instance_registry(registry, name)
+					.map_err(|err| ClientError::Application(Box::new(err)))?,
+			),
+			_ => None,
+		};
+		let metrics_registry = instance_registry.as_ref().or(metrics_registry);
+		let block_announce_validator = if block_announce_validators.len() == 1 {
+			block_announce_validators.remove(0)
+		} else {
+			Box::new(ChainedBlockAnnounceValidator::new(block_announce_validators))
+		};
 		let max_parallel_downloads = net_config.network_config.max_parallel_downloads;
+		// The limit of `LruHashSet` is exclusive, keep room for at least one block.
+		let max_known_blocks =
+			NonZeroUsize::new(net_config.network_config.max_known_blocks.max(2))
+				.expect("At least 2; qed");
 		let max_blocks_per_request = if net_config.network_config.max_blocks_per_request >
 			crate::MAX_BLOCKS_IN_RESPONSE as u32
 		{
@@ -354,23 +909,22 @@ where
 		} else {
 			net_config.network_config.max_blocks_per_request
 		};
-		let cache_capacity = (net_config.network_config.default_peers_set.in_peers +
-			net_config.network_config.default_peers_set.out_peers)
-			.max(1);
+		let cache_capacity =
+			net_config.network_config.block_announce_data_cache_size.unwrap_or_else(|| {
+				(net_config.network_config.default_peers_set.in_peers +
+					net_config.network_config.default_peers_set.out_peers)
+					.max(1)
+			});
+		let notification_reserved_peers = net_config
+			.notification_protocols()
+			.iter()
+			.flat_map(|config| config.set_config.reserved_nodes.iter().map(|info| info.peer_id))
+			.collect::<HashSet<_>>();
 		let important_peers = {
-			let mut imp_p = HashSet::new();
+			let mut imp_p = notification_reserved_peers.clone();
 			for reserved in &net_config.network_config.default_peers_set.reserved_nodes {
 				imp_p.insert(reserved.peer_id);
 			}
-			for config in net_config.notification_protocols() {
-				let peer_ids = config
-					.set_config
-					.reserved_nodes
-					.iter()
-					.map(|info| info.peer_id)
-					.collect::<Vec<PeerId>>();
-				imp_p.extend(peer_ids);
-			}
 
 			imp_p.shrink_to_fit();
 			imp_p
@@ -383,6 +937,12 @@ where
 			list.shrink_to_fit();
 			list
 		};
+		let justification_peers = net_config
+			.network_config
+			.justification_peers
+			.iter()
+			.map(|peer| peer.peer_id)
+			.collect::<HashSet<_>>();
 		let default_peers_set_no_slot_peers = {
 			let mut no_slot_p: HashSet<PeerId> = net_config
 				.network_config
@@ -390,6 +950,7 @@ where
 				.reserved_nodes
 				.iter()
 				.map(|reserved| reserved.peer_id)
+				.chain(justification_peers.iter().copied())
 				.collect();
 			no_slot_p.shrink_to_fit();
 			no_slot_p
@@ -415,21 +976,34 @@ where
 		let warp_sync_target_block_header_rx = warp_sync_target_block_header_rx
 			.map_or(futures::future::pending().boxed().fuse(), |rx| rx.boxed().fuse());
 
+		let roles =
+			if net_config.network_config.observer { roles | Roles::OBSERVER } else { roles };
+		let (peer_audit_tx, peer_audit_rx) = tracing_unbounded("mpsc_sync_peer_audit", 100_000);
+		let network_service = network_service.with_peer_audit(peer_audit_tx);
+		let announce_finalized_in_handshake =
+			net_config.network_config.announce_finalized_in_handshake;
 		let block_announce_config = Self::get_block_announce_proto_config(
 			protocol_id,
 			fork_id,
-			roles,
-			client.info().best_number,
-			client.info().best_hash,
-			client
-				.block_hash(Zero::zero())
-				.ok()
-				.flatten()
-				.expect("Genesis block exists; qed"),
+			instance_name.as_deref(),
+			{
+				let info = client.info();
+				let handshake = BlockAnnouncesHandshake::<B>::build(
+					roles,
+					info.best_number,
+					info.best_hash,
+					info.genesis_hash,
+				);
+				if announce_finalized_in_handshake {
+					handshake.with_finalized(info.finalized_number, info.finalized_hash)
+				} else {
+					handshake
+				}
+			},
 		);
 		let block_announce_protocol_name = block_announce_config.notifications_protocol.clone();
 
-		let chain_sync = ChainSync::new(
+		let mut chain_sync = ChainSync::new(
 			mode,
 			client.clone(),
 			block_announce_protocol_name.clone(),
@@ -440,8 +1014,92 @@ where
 			network_service.clone(),
 			import_queue,
 		)?;
+		if net_config.network_config.sync_from_reserved_peers_only {
+			chain_sync.set_request_peers(Some(default_peers_set_no_slot_peers.clone()));
+		}
+		chain_sync.set_justification_peers(justification_peers.clone());
+		chain_sync.set_racing_downloads(net_config.network_config.racing_downloads);
+		chain_sync.set_tail_race_blocks(net_config.network_config.tail_race_blocks);
+		chain_sync.set_finalized_only(net_config.network_config.sync_finalized_only);
+		chain_sync.set_sync_target_offset(net_config.network_config.sync_target_offset);
+		chain_sync.set_request_retry_policies(
+			net_config.network_config.block_request_retry,
+			net_config.network_config.state_request_retry,
+			net_config.network_config.warp_proof_request_retry,
+		);
+		chain_sync.set_download_history(
+			net_config.network_config.download_history,
+			net_config.network_config.history_download_rate,
+		);
+		chain_sync.set_major_sync_thresholds(
+			net_config.network_config.major_sync_threshold,
+			net_config.network_config.major_sync_hysteresis,
+		);
+		chain_sync.set_import_queue_water_marks(
+			net_config.network_config.import_queue_high_water_mark,
+			net_config.network_config.import_queue_low_water_mark,
+		);
+
+		if let Some(path) = &net_config.network_config.net_config_path {
+			chain_sync.set_warp_progress_path(warp_progress::path(path));
+		}
+
+		let sync_snapshot_path =
+			net_config.network_config.net_config_path.as_deref().map(sync_snapshot::path);
+		match sync_snapshot_path.as_deref().map(sync_snapshot::take::<B>) {
+			Some(Ok(Some(snapshot))) => chain_sync.restore_snapshot(snapshot),
+			Some(Err(error)) =>
+				log::warn!(target: LOG_TARGET, "Failed to load sync snapshot: {error}"),
+			Some(Ok(None)) | None => {},
+		}
+
+		let persisted_peers_path =
+			net_config.network_config.net_config_path.as_deref().map(persisted_peers::path);
+		let persisted_peers = match persisted_peers_path.as_deref().map(persisted_peers::load::<B>)
+		{
+			Some(Ok(peers)) => peers,
+			Some(Err(error)) => {
+				log::warn!(target: LOG_TARGET, "Failed to load persisted sync peers: {error}");
+				Vec::new()
+			},
+			None => Vec::new(),
+		};
+		if !persisted_peers.is_empty() {
+			log::debug!(target: LOG_TARGET, "Restored {} persisted sync peers", persisted_peers.len());
+		}
+		for peer in &persisted_peers {
+			for address in &peer.addresses {
+				network_service.add_known_address(peer.peer_id, address.clone());
+			}
+		}
+		chain_sync.restore_peer_bests(
+			persisted_peers.iter().map(|peer| (peer.peer_id, peer.best_hash, peer.best_number)),
+		);
+
+		// Reserve the restored peers for a while so they are dialed right away, unless only
+		// reserved peers are accepted or they are reserved already.
+		let accepts_non_reserved = net_config.network_config.default_peers_set.non_reserved_mode ==
+			NonReservedPeerMode::Accept;
+		let reserved_restored_peers = persisted_peers
+			.iter()
+			.filter(|peer| {
+				accepts_non_reserved &&
+					!peer.addresses.is_empty() &&
+					!important_peers.contains(&peer.peer_id)
+			})
+			.map(|peer| peer.peer_id)
+			.collect::<Vec<_>>();
+		if !reserved_restored_peers.is_empty() {
+			network_service.add_peers_to_reserved_set(
+				block_announce_protocol_name.clone(),
+				reserved_restored_peers
+					.iter()
+					.map(|peer_id| Multiaddr::empty().with(Protocol::P2p((*peer_id).into())))
+					.collect(),
+			);
+		}
 
-		let (tx, service_rx) = tracing_unbounded("mpsc_chain_sync", 100_000);
+		let (tx, service_rx) = command_channel();
 		let num_connected = Arc::new(AtomicUsize::new(0));
 		let is_major_syncing = Arc::new(AtomicBool::new(false));
 		let genesis_hash = client
@@ -463,10 +1121,23 @@ where
 				chain_sync,
 				network_service,
 				peers: HashMap::new(),
-				block_announce_data_cache: LruMap::new(ByLength::new(cache_capacity)),
+				block_announce_data_cache: BlockAnnounceDataCache::new(
+					cache_capacity,
+					net_config.network_config.block_announce_data_cache_max_bytes,
+					net_config.network_config.block_announce_data_cache_ttl,
+				),
 				block_announce_protocol_name,
 				block_announce_validator: BlockAnnounceValidatorStream::new(
 					block_announce_validator,
+					ValidationLimits {
+						max_validations: net_config
+							.network_config
+							.max_concurrent_block_announce_validations,
+						max_validations_per_peer: net_config
+							.network_config
+							.max_concurrent_block_announce_validations_per_peer,
+						overflow: net_config.network_config.block_announce_validation_overflow,
+					},
 				),
 				num_connected: num_connected.clone(),
 				is_major_syncing: is_major_syncing.clone(),
@@ -478,14 +1149,56 @@ where
 				warp_sync_target_block_header_rx,
 				boot_node_ids,
 				default_peers_set_no_slot_peers,
+				justification_peers,
+				notification_reserved_peers,
+				sync_from_reserved_peers_only: net_config
+					.network_config
+					.sync_from_reserved_peers_only,
+				evict_reserved_peers_on_stall: net_config
+					.network_config
+					.evict_reserved_peers_on_stall,
 				default_peers_set_num_full,
 				default_peers_set_num_light,
 				num_in_peers: 0usize,
 				max_in_peers,
 				event_streams: Vec::new(),
+				status_streams: Vec::new(),
+				peer_audit_rx,
+				peer_audit_streams: Vec::new(),
+				last_status: None,
 				tick_timeout: Delay::new(TICK_TIMEOUT),
-				syncing_started: None,
+				profile_poll: false,
+				waiting: None,
 				last_notification_io: Instant::now(),
+				paused: false,
+				paused_block_requests: HashMap::new(),
+				banned_peers: HashMap::new(),
+				throughput: ThroughputTracker::new(THROUGHPUT_WINDOW, Instant::now()),
+				block_announce_rate_limit: net_config
+					.network_config
+					.block_announce_rate_limit
+					.map(|limit| limit.max(1)),
+				light_peer_announcements: net_config.network_config.light_peer_announcements,
+				max_known_blocks,
+				known_blocks_limit: max_known_blocks,
+				blocks_announced: 0,
+				known_blocks_window_start: Instant::now(),
+				max_block_announce_data_size: net_config
+					.network_config
+					.max_block_announce_data_size,
+				priority_peers: HashSet::new(),
+				authority_peer_slots: net_config.network_config.authority_peer_slots as usize,
+				announce_finalized_in_handshake,
+				persisted_peers_path,
+				persisted_peers: persisted_peers
+					.into_iter()
+					.map(|peer| (peer.peer_id, peer))
+					.collect(),
+				reserved_restored_peers,
+				restored_peers_unreserved_at: Instant::now() + RESTORED_PEERS_RESERVATION,
+				sync_snapshot_path,
+				stop_requested: None,
+				instance_name,
 				metrics: if let Some(r) = metrics_registry {
 					match Metrics::register(r, is_major_syncing.clone()) {
 						Ok(metrics) => Some(metrics),
@@ -498,8 +1211,11 @@ where
 					None
 				},
 				pending_responses: PendingResponses::new(),
+				one_off_requests: FuturesStream::default(),
 				block_downloader,
+				block_request_protocol_names,
 				state_request_protocol_name,
+				state_request_v2_protocol_name,
 				warp_sync_protocol_name,
 			},
 			SyncingService::new(tx, num_connected, is_major_syncing),
@@ -516,6 +1232,9 @@ where
 			let m = self.chain_sync.metrics();
 
 			metrics.fork_targets.set(m.fork_targets.into());
+			metrics
+				.block_announce_data_cache_bytes
+				.set(self.block_announce_data_cache.bytes() as u64);
 			metrics.queued_blocks.set(m.queued_blocks.into());
 
 			metrics
@@ -534,12 +1253,48 @@ where
 				.justifications
 				.with_label_values(&["importing"])
 				.set(m.justifications.importing_requests.into());
+
+			let num_full_peers = self.chain_sync.num_peers();
+			let num_no_slot_peers = self.default_peers_set_no_slot_connected_peers.len();
+			for (slot, occupied, limit) in [
+				("inbound", self.num_in_peers, Some(self.max_in_peers)),
+				("full", num_full_peers, Some(self.default_peers_set_num_full + num_no_slot_peers)),
+				(
+					"light",
+					self.peers.len() - num_full_peers,
+					Some(self.default_peers_set_num_light),
+				),
+				("no_slot", num_no_slot_peers, None),
+				("authority", self.num_authority_peers(), Some(self.authority_peer_slots)),
+			] {
+				metrics.peer_slots.with_label_values(&[slot, "occupied"]).set(occupied as u64);
+				if let Some(limit) = limit {
+					metrics.peer_slots.with_label_values(&[slot, "limit"]).set(limit as u64);
+				}
+			}
+
+			for role in PEER_ROLES {
+				let statistics = sum_peer_statistics(
+					self.peers
+						.values()
+						.filter(|peer| Metrics::role_label(peer.info.roles) == role)
+						.map(|peer| &peer.statistics),
+				);
+				let values = peer_statistics_values(&statistics);
+				for (statistic, value) in PEER_STATISTICS.into_iter().zip(values) {
+					metrics.peer_statistics.with_label_values(&[role, statistic]).set(value);
+				}
+			}
 		}
 	}
 
 	fn update_peer_info(&mut self, peer_id: &PeerId) {
 		if let Some(info) = self.chain_sync.peer_info(peer_id) {
 			if let Some(ref mut peer) = self.peers.get_mut(peer_id) {
+				if peer.info.best_hash != info.best_hash {
+					peer.best_updated = Instant::now();
+					self.chain_sync.set_peer_best_stale(peer_id, false);
+				}
 				peer.info.best_hash = info.best_hash;
 				peer.info.best_number = info.best_number;
 			}
@@ -583,6 +1338,32 @@ where
 	) {
 		let hash = announce.header.hash();
 
+		if self.chain_sync.is_banned_block(&hash, announce.header.parent_hash()) {
+			log::debug!(
+				target: LOG_TARGET,
+				"Disconnecting {peer_id} announcing banned block {hash} or its child",
+			);
+			self.network_service.report_peer(peer_id, rep::BAD_BLOCK_ANNOUNCEMENT);
+			self.network_service
+				.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+			return
+		}
+
+		let data_size = announce.data.as_ref().map_or(0, |data| data.len());
+		if data_size > self.max_block_announce_data_size {
+			log::debug!(
+				target: LOG_TARGET,
+				"Dropping announcement of {hash} from {peer_id}: data of {data_size} bytes is over \
+				the limit of {} bytes",
+				self.max_block_announce_data_size,
+			);
+			self.network_service.report_peer(peer_id, rep::OVERSIZED_ANNOUNCEMENT_DATA);
+			if let Some(metrics) = &self.metrics {
+				metrics.oversized_block_announce_data.inc();
+			}
+			return
+		}
+
 		let peer = match self.peers.get_mut(&peer_id) {
 			Some(p) => p,
 			None => {
@@ -594,7 +1375,8 @@ where
 				return
 			},
 		};
-		peer.known_blocks.insert(hash);
+		peer.know_block(hash, self.metrics.as_ref());
+		peer.statistics.announcements_received += 1;
 
 		if peer.info.roles.is_full() {
 			let is_best = match announce.state.unwrap_or(BlockState::Best) {
@@ -602,8 +1384,12 @@ where
 				BlockState::Normal => false,
 			};
 
-			self.block_announce_validator
-				.push_block_announce_validation(peer_id, hash, announce, is_best);
+			if let Some(flooding) = self
+				.block_announce_validator
+				.push_block_announce_validation(peer_id, hash, announce, is_best)
+			{
+				self.network_service.report_peer(flooding, rep::ANNOUNCEMENT_DROPPED);
+			}
 		}
 	}
 
@@ -612,6 +1398,11 @@ where
 	/// In chain-based consensus, we often need to make sure non-best forks are
 	/// at least temporarily synced.
 	pub fn announce_block(&mut self, hash: B::Hash, data: Option<Vec<u8>>) {
+		if self.roles.is_observer() {
+			log::trace!(target: LOG_TARGET, "Not announcing block {hash:?} as an observer");
+			return
+		}
+
 		let header = match self.client.header(hash) {
 			Ok(Some(header)) => header,
 			Ok(None) => {
@@ -628,16 +1419,56 @@ where
 		if header.number().is_zero() {
 			return
 		}
+		self.blocks_announced += 1;
 
 		let is_best = self.client.info().best_hash == hash;
 		log::debug!(target: LOG_TARGET, "Reannouncing block {hash:?} is_best: {is_best}");
 
 		let data = data
-			.or_else(|| self.block_announce_data_cache.get(&hash).cloned())
+			.or_else(|| {
+				let cached = self.block_announce_data_cache.get(&hash);
+				if let Some(metrics) = &self.metrics {
+					let result = if cached.is_some() { "hit" } else { "miss" };
+					metrics.block_announce_data_cache_lookups.with_label_values(&[result]).inc();
+				}
+				cached
+			})
 			.unwrap_or_default();
 
-		for (peer_id, ref mut peer) in self.peers.iter_mut() {
-			let inserted = peer.known_blocks.insert(hash);
+		// Authorities are announced to first, as they are likely to author the next block.
+		let priority_peers = &self.priority_peers;
+		let mut peers = self.peers.iter_mut().collect::<Vec<_>>();
+		peers.sort_by_key(|(peer_id, peer)| {
+			!(peer.info.roles.is_authority() || priority_peers.contains(peer_id))
+		});
+
+		let announce_to_light_peers =
+			announce_to_light_peers(self.light_peer_announcements, *header.number(), is_best);
+		for (peer_id, peer) in peers {
+			// Full peers far behind don't need the announcement, and peers whose best block it is
+			// already have it even if it was evicted from `known_blocks`. Their best block is taken
+			// from `ChainSync`, which follows their announcements and responses. Light peers don't
+			// report their progress, so they are sent what the configured policy allows.
+			let skip = if peer.info.roles.is_full() {
+				!full_peer_needs_announcement::<B>(
+					self.chain_sync
+						.peer_info(peer_id)
+						.map(|info| (info.best_hash, info.best_number)),
+					hash,
+					*header.number(),
+				)
+			} else {
+				!announce_to_light_peers
+			};
+			if skip {
+				log::trace!(target: LOG_TARGET, "Not announcing block {hash:?} to {peer_id}");
+				if let Some(metrics) = &self.metrics {
+					metrics.block_announces.with_label_values(&["skipped"]).inc();
+				}
+				continue
+			}
+
+			let inserted = peer.know_block(hash, self.metrics.as_ref());
 			if inserted {
 				log::trace!(target: LOG_TARGET, "Announcing block {hash:?} to {peer_id}");
 				let message = BlockAnnounce {
@@ -647,251 +1478,599 @@ where
 				};
 
 				self.last_notification_io = Instant::now();
-				peer.sink.send_sync_notification(message.encode());
+				peer.announce(message.encode(), self.metrics.as_ref(), self.last_notification_io);
+			}
+		}
+	}
+
+	/// Send the block announcements held back by the rate limit, as far as it allows.
+	fn flush_block_announcements(&mut self) {
+		let now = Instant::now();
+		for peer in self.peers.values_mut() {
+			if peer.flush_announcement(self.metrics.as_ref(), now) {
+				self.last_notification_io = now;
 			}
 		}
 	}
 
+	/// Scale the number of block hashes remembered per peer with the number of blocks announced
+	/// over the last [`KNOWN_BLOCKS_WINDOW`], never going below the configured minimum.
+	fn scale_known_blocks(&mut self) {
+		if self.known_blocks_window_start.elapsed() < KNOWN_BLOCKS_WINDOW {
+			return
+		}
+
+		let limit = NonZeroUsize::new(self.blocks_announced)
+			.map_or(self.max_known_blocks, |announced| announced.max(self.max_known_blocks));
+		self.blocks_announced = 0;
+		self.known_blocks_window_start = Instant::now();
+		if limit == self.known_blocks_limit {
+			return
+		}
+
+		log::debug!(target: LOG_TARGET, "Remembering {limit} known blocks per peer");
+		self.known_blocks_limit = limit;
+		for peer in self.peers.values_mut() {
+			peer.set_known_blocks_limit(limit, self.metrics.as_ref());
+		}
+	}
+
 	/// Inform sync about new best imported block.
 	pub fn new_best_block_imported(&mut self, hash: B::Hash, number: NumberFor<B>) {
 		log::debug!(target: LOG_TARGET, "New best block imported {hash:?}/#{number}");
 
 		self.chain_sync.update_chain_info(&hash, number);
+		self.update_handshake(hash, number);
+	}
+
+	/// Update the block announces handshake sent to new peers with the best block `hash` and
+	/// `number`, and the finalized block if enabled.
+	fn update_handshake(&mut self, hash: B::Hash, number: NumberFor<B>) {
+		let mut handshake =
+			BlockAnnouncesHandshake::<B>::build(self.roles, number, hash, self.genesis_hash);
+		if self.announce_finalized_in_handshake {
+			let info = self.client.info();
+			handshake = handshake.with_finalized(info.finalized_number, info.finalized_hash);
+		}
 		self.network_service.set_notification_handshake(
 			self.block_announce_protocol_name.clone(),
-			BlockAnnouncesHandshake::<B>::build(self.roles, number, hash, self.genesis_hash)
-				.encode(),
+			handshake.encode(),
 		)
 	}
 
 	pub async fn run(mut self) {
-		self.syncing_started = Some(Instant::now());
-
-		loop {
-			futures::future::poll_fn(|cx| self.poll(cx)).await;
+		let span = self
+			.instance_name
+			.as_deref()
+			.map_or_else(tracing::Span::none, instance::log_span);
+		async {
+			self.event_loop().await;
+			self.shutdown();
 		}
+		.instrument(span)
+		.await
 	}
 
-	pub fn poll(&mut self, cx: &mut std::task::Context) -> Poll<()> {
-		self.num_connected.store(self.peers.len(), Ordering::Relaxed);
-		self.is_major_syncing
-			.store(self.chain_sync.status().state.is_major_syncing(), Ordering::Relaxed);
-
-		while let Poll::Ready(()) = self.tick_timeout.poll_unpin(cx) {
-			self.report_metrics();
-			self.tick_timeout.reset(TICK_TIMEOUT);
+	/// Drop in-flight requests and save the sync progress and the useful peers, so the next start
+	/// resumes from them.
+	fn shutdown(&mut self) {
+		log::debug!(target: LOG_TARGET, "Stopping syncing engine");
 
-			// if `SyncingEngine` has just started, don't evict seemingly inactive peers right away
-			// as they may not have produced blocks not because they've disconnected but because
-			// they're still waiting to receive enough relaychain blocks to start producing blocks.
-			if let Some(started) = self.syncing_started {
-				if started.elapsed() < INITIAL_EVICTION_WAIT_PERIOD {
-					continue
-				}
+		self.pending_responses = PendingResponses::new();
+		if let Some(path) = &self.sync_snapshot_path {
+			if let Err(error) = sync_snapshot::save(path, &self.chain_sync.snapshot()) {
+				log::warn!(target: LOG_TARGET, "Failed to save sync snapshot: {error}");
+			}
+		}
+		if let Some(path) = &self.persisted_peers_path {
+			let mut peers = self.persisted_peers.values().cloned().collect::<Vec<_>>();
+			peers.sort_by_key(|peer| std::cmp::Reverse(peer.throughput));
 
-				self.syncing_started = None;
-				self.last_notification_io = Instant::now();
+			if let Err(error) = persisted_peers::save(path, &peers) {
+				log::warn!(target: LOG_TARGET, "Failed to persist sync peers: {error}");
 			}
+		}
+	}
 
-			// if syncing hasn't sent or received any blocks within `INACTIVITY_EVICT_THRESHOLD`,
-			// it means the local node has stalled and is connected to peers who either don't
-			// consider it connected or are also all stalled. In order to unstall the node,
-			// disconnect all peers and allow `ProtocolController` to establish new connections.
-			if self.last_notification_io.elapsed() > INACTIVITY_EVICT_THRESHOLD {
-				log::debug!(
-					target: LOG_TARGET,
-					"syncing has halted due to inactivity, evicting all peers",
-				);
+	/// Drive the engine until it is requested to stop.
+	///
+	/// Each iteration waits for the next event of any source and handles it, the sources that
+	/// can't be handled right now, like network events while block announcements can't be
+	/// validated or `ChainSync` while syncing is paused, aren't polled. The ready sources are
+	/// polled in a random order, so that a busy source doesn't starve the others.
+	async fn event_loop(&mut self) {
+		while self.stop_requested.is_none() {
+			let backpressured = self.block_announce_validator.is_backpressured();
+			tokio::select! {
+				// Network events aren't received while block announcements can't be validated,
+				// if configured so.
+				Some(event) = self.rx.next(), if !backpressured =>
+					self.in_poll_section("network_events", |this| this.process_sync_event(event)),
+				_ = &mut self.tick_timeout => {
+					self.tick_timeout.reset(TICK_TIMEOUT);
+					self.in_poll_section("tick", Self::perform_periodic_actions);
+				},
+				Some(command) = self.service_rx.next() =>
+					self.in_poll_section("service_commands", |this| {
+						this.process_service_command(command)
+					}),
+				target = &mut self.warp_sync_target_block_header_rx =>
+					self.in_poll_section("chain_sync", |this| this.on_warp_sync_target(target)),
+				// While syncing is paused, responses to the requests in flight are kept in
+				// `pending_responses` and are imported once syncing is resumed.
+				obsolete = futures::future::poll_fn(|cx| self.chain_sync.poll_verified_proofs(cx)),
+					if !self.paused =>
+					self.in_poll_section("chain_sync", |this| {
+						for peer_id in obsolete {
+							this.pending_responses.remove(&peer_id);
+						}
+					}),
+				Some(event) = self.pending_responses.next(), if !self.paused =>
+					self.in_poll_section("chain_sync", |this| this.process_response_event(event)),
+				Some(()) = self.one_off_requests.next() => {},
+				Some(event) = self.peer_audit_rx.next() =>
+					self.in_poll_section("peer_audit", |this| this.on_peer_audit_event(event)),
+				Some(result) = self.block_announce_validator.next() =>
+					self.in_poll_section("block_announce_validation", |this| {
+						this.process_block_announce_validation_result(result)
+					}),
+			}
 
-				for peer in self.peers.keys() {
-					self.network_service.report_peer(*peer, rep::INACTIVE_SUBSTREAM);
-					self.network_service
-						.disconnect_peer(*peer, self.block_announce_protocol_name.clone());
-				}
+			self.num_connected.store(self.peers.len(), Ordering::Relaxed);
+			self.is_major_syncing
+				.store(self.chain_sync.update_major_sync(), Ordering::Relaxed);
 
-				// after all the peers have been evicted, start timer again to prevent evicting
-				// new peers that join after the old peer have been evicted
-				self.last_notification_io = Instant::now();
+			// Send outbound requests on `ChainSync`'s behalf.
+			if !self.paused {
+				self.in_poll_section("chain_sync", Self::send_chain_sync_requests);
 			}
 		}
+	}
+
+	/// Report `peer_id` for a block announcement that failed to decode, disconnecting it after
+	/// [`MAX_UNDECODABLE_ANNOUNCEMENTS`] of them.
+	fn on_undecodable_announcement(&mut self, peer_id: PeerId, error: codec::Error) {
+		log::debug!(target: LOG_TARGET, "Failed to decode block announce from {peer_id}: {error}");
+		self.network_service.report_peer(peer_id, rep::UNDECODABLE_ANNOUNCEMENT);
+		if let Some(metrics) = &self.metrics {
+			metrics.undecodable_block_announces.inc();
+		}
+
+		let Some(peer) = self.peers.get_mut(&peer_id) else { return };
+		peer.undecodable_announcements += 1;
+		if peer.undecodable_announcements == MAX_UNDECODABLE_ANNOUNCEMENTS {
+			log::debug!(
+				target: LOG_TARGET,
+				"Disconnecting {peer_id} after {MAX_UNDECODABLE_ANNOUNCEMENTS} undecodable block \
+				announcements",
+			);
+			self.network_service
+				.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+		}
+	}
+
+	/// Account a peer reported or disconnected and publish it to the subscribers.
+	fn on_peer_audit_event(&mut self, event: PeerAuditEvent) {
+		if let Some(metrics) = &self.metrics {
+			match &event.action {
+				PeerAuditAction::Reported(change) =>
+					metrics.peer_reports.with_label_values(&[change.reason]).inc(),
+				PeerAuditAction::Disconnected(protocol) =>
+					metrics.peer_disconnects.with_label_values(&[&**protocol]).inc(),
+			}
+		}
+		self.peer_audit_streams.retain(|stream| stream.unbounded_send(event.clone()).is_ok());
+	}
+
+	/// Time based maintenance, performed every [`TICK_TIMEOUT`].
+	fn perform_periodic_actions(&mut self) {
+		self.report_metrics();
+		self.banned_peers
+			.retain(|_, (banned_at, duration)| banned_at.elapsed() < *duration);
+		self.chain_sync.detect_block_gap();
+		self.chain_sync.expire_request_backoffs();
+		self.flush_block_announcements();
+		self.scale_known_blocks();
+		if !self.reserved_restored_peers.is_empty() &&
+			Instant::now() >= self.restored_peers_unreserved_at
+		{
+			self.network_service.remove_peers_from_reserved_set(
+				self.block_announce_protocol_name.clone(),
+				mem::take(&mut self.reserved_restored_peers),
+			);
+		}
+		// Keep-alives don't count as activity here, only those received from peers do.
+		let now = Instant::now();
+		for (peer_id, peer) in self.peers.iter_mut() {
+			peer.keep_alive(now);
+			let stale = now.duration_since(peer.best_updated) > STALE_PEER_BEST_TIMEOUT;
+			self.chain_sync.set_peer_best_stale(peer_id, stale);
+		}
+		self.notify_status_streams();
+
+		// no blocks are exchanged while syncing is paused or the node is waiting, e.g. for
+		// enough relay chain blocks to start producing blocks, don't mistake it for a stall.
+		if self.paused || self.waiting.is_some() {
+			return
+		}
 
-		while let Poll::Ready(Some(event)) = self.service_rx.poll_next_unpin(cx) {
-			match event {
-				ToServiceCommand::SetSyncForkRequest(peers, hash, number) => {
-					self.chain_sync.set_sync_fork_request(peers, &hash, number);
+		// if syncing hasn't sent or received any blocks within `INACTIVITY_EVICT_THRESHOLD`,
+		// it means the local node has stalled and is connected to peers who either don't
+		// consider it connected or are also all stalled. In order to unstall the node,
+		// disconnect all peers and allow `ProtocolController` to establish new connections.
+		// Reserved peers are kept unless configured otherwise, as `ProtocolController` would
+		// reconnect them anyway.
+		if self.last_notification_io.elapsed() > INACTIVITY_EVICT_THRESHOLD {
+			log::debug!(
+				target: LOG_TARGET,
+				"syncing has halted due to inactivity, evicting all peers",
+			);
+
+			for peer in self.peers.keys().filter(|peer| {
+				evicted_on_stall(
+					peer,
+					self.evict_reserved_peers_on_stall,
+					&self.important_peers,
+					&self.default_peers_set_no_slot_peers,
+				)
+			}) {
+				self.network_service.report_peer(*peer, rep::INACTIVE_SUBSTREAM);
+				self.network_service
+					.disconnect_peer(*peer, self.block_announce_protocol_name.clone());
+			}
+
+			// after all the peers have been evicted, start timer again to prevent evicting
+			// new peers that join after the old peer have been evicted
+			self.last_notification_io = Instant::now();
+		}
+	}
+
+	fn process_service_command(&mut self, command: ToServiceCommand<B>) {
+		match command {
+			ToServiceCommand::SetSyncForkRequest(peers, hash, number) => {
+				self.chain_sync.set_sync_fork_request(peers, &hash, number);
+			},
+			ToServiceCommand::EventStream(tx) => self.event_streams.push(tx),
+			ToServiceCommand::SetPollProfiling(enabled) => self.profile_poll = enabled,
+			ToServiceCommand::PeerFinalized(peer_id, hash, number, has_justification) => self
+				.chain_sync
+				.on_peer_finalized(&peer_id, hash, number, has_justification),
+			ToServiceCommand::SubscribePeerAudit(tx) => self.peer_audit_streams.push(tx),
+			ToServiceCommand::SubscribeStatus(tx) =>
+				if tx.unbounded_send(self.status()).is_ok() {
+					self.status_streams.push(tx);
 				},
-				ToServiceCommand::EventStream(tx) => self.event_streams.push(tx),
-				ToServiceCommand::RequestJustification(hash, number) =>
-					self.chain_sync.request_justification(&hash, number),
-				ToServiceCommand::ClearJustificationRequests =>
-					self.chain_sync.clear_justification_requests(),
-				ToServiceCommand::BlocksProcessed(imported, count, results) => {
-					for result in self.chain_sync.on_blocks_processed(imported, count, results) {
-						match result {
-							Ok(event) => match event {
-								BlockRequestEvent::SendRequest { peer_id, request } => {
-									// drop obsolete pending response first
-									self.pending_responses.remove(&peer_id);
-									self.send_block_request(peer_id, request);
-								},
-								BlockRequestEvent::RemoveStale { peer_id } => {
-									self.pending_responses.remove(&peer_id);
-								},
+			ToServiceCommand::RequestJustification(hash, number) =>
+				self.chain_sync.request_justification(&hash, number),
+			ToServiceCommand::ClearJustificationRequests =>
+				self.chain_sync.clear_justification_requests(),
+			ToServiceCommand::BlocksProcessed(imported, count, results) => {
+				for result in self.chain_sync.on_blocks_processed(imported, count, results) {
+					match result {
+						Ok(event) => match event {
+							BlockRequestEvent::SendRequest { peer_id, request } => {
+								// drop obsolete pending response first
+								self.pending_responses.remove(&peer_id);
+								self.send_block_request(peer_id, request);
 							},
-							Err(BadPeer(peer_id, repu)) => {
+							BlockRequestEvent::RemoveStale { peer_id } => {
 								self.pending_responses.remove(&peer_id);
-								self.network_service.disconnect_peer(
-									peer_id,
-									self.block_announce_protocol_name.clone(),
-								);
-								self.network_service.report_peer(peer_id, repu)
+								self.paused_block_requests.remove(&peer_id);
 							},
-						}
+						},
+						Err(BadPeer(peer_id, repu)) => {
+							self.pending_responses.remove(&peer_id);
+							self.paused_block_requests.remove(&peer_id);
+							self.network_service.disconnect_peer(
+								peer_id,
+								self.block_announce_protocol_name.clone(),
+							);
+							self.network_service.report_peer(peer_id, repu)
+						},
 					}
+				}
+			},
+			ToServiceCommand::JustificationImported(peer_id, hash, number, success) => {
+				self.chain_sync.on_justification_import(hash, number, success);
+				if !success {
+					log::info!(
+						target: LOG_TARGET,
+						"💔 Invalid justification provided by {peer_id} for #{hash}",
+					);
+					self.network_service
+						.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+					self.network_service.report_peer(
+						peer_id,
+						ReputationChange::new_fatal("Invalid justification"),
+					);
+				}
+			},
+			ToServiceCommand::AnnounceBlock(hash, data) => self.announce_block(hash, data),
+			ToServiceCommand::SetWarpSyncTarget(header, tx) => {
+				let _ = tx.send(self.set_warp_sync_target(header));
+			},
+			ToServiceCommand::NewBestBlockImported(hash, number) =>
+				self.new_best_block_imported(hash, number),
+			ToServiceCommand::Status(tx) => {
+				let _ = tx.send(self.status());
+			},
+			ToServiceCommand::NumActivePeers(tx) => {
+				let _ = tx.send(self.num_active_peers());
+			},
+			ToServiceCommand::SyncState(tx) => {
+				let _ = tx.send(self.chain_sync.status());
+			},
+			ToServiceCommand::BestSeenBlock(tx) => {
+				let _ = tx.send(self.chain_sync.status().best_seen_block);
+			},
+			ToServiceCommand::NumSyncPeers(tx) => {
+				let _ = tx.send(self.chain_sync.status().num_peers);
+			},
+			ToServiceCommand::NumQueuedBlocks(tx) => {
+				let _ = tx.send(self.chain_sync.status().queued_blocks);
+			},
+			ToServiceCommand::NumDownloadedBlocks(tx) => {
+				let _ = tx.send(self.chain_sync.num_downloaded_blocks());
+			},
+			ToServiceCommand::BlocksBehind(tx) => {
+				let _ = tx.send(self.chain_sync.blocks_behind());
+			},
+			ToServiceCommand::NetworkBestEstimate(tx) => {
+				let _ = tx.send(self.chain_sync.network_best_estimate());
+			},
+			ToServiceCommand::NumSyncRequests(tx) => {
+				let _ = tx.send(self.chain_sync.num_sync_requests());
+			},
+			ToServiceCommand::PeersInfo(tx) => {
+				let peers_info = self
+					.peers
+					.iter()
+					.map(|(peer_id, peer)| (*peer_id, peer.info.clone()))
+					.collect();
+				let _ = tx.send(peers_info);
+			},
+			ToServiceCommand::PeerStatistics(tx) => {
+				let peer_statistics = self
+					.peers
+					.iter()
+					.map(|(peer_id, peer)| (*peer_id, peer.statistics.clone()))
+					.collect();
+				let _ = tx.send(peer_statistics);
+			},
+			ToServiceCommand::Throughput(tx) => {
+				let _ = tx.send(self.throughput());
+			},
+			ToServiceCommand::OnBlockFinalized(hash, header) => {
+				self.chain_sync.on_block_finalized(&hash, *header.number());
+				// Requests for forks below the finalized block are no longer needed.
+				for peer_id in self.chain_sync.take_cancelled_requests() {
+					self.pending_responses.remove(&peer_id);
+				}
+				if self.announce_finalized_in_handshake {
+					let info = self.client.info();
+					self.update_handshake(info.best_hash, info.best_number);
+				}
+			},
+			ToServiceCommand::SetSyncTarget(target) => self.chain_sync.set_sync_target(target),
+			ToServiceCommand::StartGapSync(range) => self.chain_sync.start_gap_sync(range),
+			ToServiceCommand::SetMaxGapRequests(max_gap_requests) =>
+				self.chain_sync.set_max_gap_requests(max_gap_requests),
+			ToServiceCommand::SetPriorityPeers(peers) => self.priority_peers = peers,
+			ToServiceCommand::SetReservedPeers(peers) => self.set_reserved_peers(peers),
+			ToServiceCommand::SetForkChoice(fork_choice) =>
+				self.chain_sync.set_fork_choice(fork_choice),
+			ToServiceCommand::SetPeerSelection(peer_selection) =>
+				self.chain_sync.set_peer_selection(peer_selection),
+			ToServiceCommand::ImportBlocks(blocks, tx) => {
+				let _ = tx.send(self.chain_sync.import_offline_blocks(blocks));
+			},
+			ToServiceCommand::SetWaiting(reason) => {
+				log::debug!(target: LOG_TARGET, "Waiting, not evicting inactive peers: {reason}");
+				self.waiting = Some(reason);
+			},
+			ToServiceCommand::SetReady =>
+				if self.waiting.take().is_some() {
+					log::debug!(target: LOG_TARGET, "Ready, evicting inactive peers again");
+					self.last_notification_io = Instant::now();
 				},
-				ToServiceCommand::JustificationImported(peer_id, hash, number, success) => {
-					self.chain_sync.on_justification_import(hash, number, success);
-					if !success {
-						log::info!(
-							target: LOG_TARGET,
-							"💔 Invalid justification provided by {peer_id} for #{hash}",
-						);
-						self.network_service
-							.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
-						self.network_service.report_peer(
-							peer_id,
-							ReputationChange::new_fatal("Invalid justification"),
-						);
+			ToServiceCommand::RequestBlockFrom(peer_id, hash, tx) =>
+				self.request_block_from(peer_id, hash, tx),
+			ToServiceCommand::DumpState(tx) => {
+				let _ = tx.send(self.dump_state());
+			},
+			ToServiceCommand::PauseSync => {
+				log::info!(target: LOG_TARGET, "⏸️  Syncing paused");
+				self.paused = true;
+			},
+			ToServiceCommand::DisconnectPeer(peer_id, reason) => {
+				log::debug!(target: LOG_TARGET, "Disconnecting {peer_id}: {reason}");
+				// Stop syncing from the peer right away rather than when the substream closes.
+				let _ = self.on_sync_peer_disconnected(peer_id);
+				self.network_service
+					.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+			},
+			ToServiceCommand::BanPeer(peer_id, duration) => {
+				log::debug!(target: LOG_TARGET, "Banning {peer_id} for {duration:?}");
+				self.banned_peers.insert(peer_id, (Instant::now(), duration));
+				let _ = self.on_sync_peer_disconnected(peer_id);
+				self.network_service.report_peer(peer_id, rep::BANNED);
+				self.network_service
+					.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+			},
+			ToServiceCommand::BanBlock(hash) => {
+				self.chain_sync.ban_block(hash);
+				for peer_id in self.chain_sync.take_cancelled_requests() {
+					self.pending_responses.remove(&peer_id);
+					self.paused_block_requests.remove(&peer_id);
+				}
+			},
+			ToServiceCommand::ResumeSync =>
+				if self.paused {
+					log::info!(target: LOG_TARGET, "▶️  Syncing resumed");
+					self.paused = false;
+					self.last_notification_io = Instant::now();
+					for (peer_id, request) in mem::take(&mut self.paused_block_requests) {
+						self.send_block_request(peer_id, request);
 					}
 				},
-				ToServiceCommand::AnnounceBlock(hash, data) => self.announce_block(hash, data),
-				ToServiceCommand::NewBestBlockImported(hash, number) =>
-					self.new_best_block_imported(hash, number),
-				ToServiceCommand::Status(tx) => {
-					let mut status = self.chain_sync.status();
-					status.num_connected_peers = self.peers.len() as u32;
-					let _ = tx.send(status);
-				},
-				ToServiceCommand::NumActivePeers(tx) => {
-					let _ = tx.send(self.num_active_peers());
-				},
-				ToServiceCommand::SyncState(tx) => {
-					let _ = tx.send(self.chain_sync.status());
-				},
-				ToServiceCommand::BestSeenBlock(tx) => {
-					let _ = tx.send(self.chain_sync.status().best_seen_block);
-				},
-				ToServiceCommand::NumSyncPeers(tx) => {
-					let _ = tx.send(self.chain_sync.status().num_peers);
-				},
-				ToServiceCommand::NumQueuedBlocks(tx) => {
-					let _ = tx.send(self.chain_sync.status().queued_blocks);
-				},
-				ToServiceCommand::NumDownloadedBlocks(tx) => {
-					let _ = tx.send(self.chain_sync.num_downloaded_blocks());
-				},
-				ToServiceCommand::NumSyncRequests(tx) => {
-					let _ = tx.send(self.chain_sync.num_sync_requests());
-				},
-				ToServiceCommand::PeersInfo(tx) => {
-					let peers_info = self
-						.peers
-						.iter()
-						.map(|(peer_id, peer)| (*peer_id, peer.info.clone()))
-						.collect();
-					let _ = tx.send(peers_info);
-				},
-				ToServiceCommand::OnBlockFinalized(hash, header) =>
-					self.chain_sync.on_block_finalized(&hash, *header.number()),
-			}
+			ToServiceCommand::Stop(tx) => self.stop_requested = Some(tx),
 		}
+	}
 
-		while let Poll::Ready(Some(event)) = self.rx.poll_next_unpin(cx) {
-			match event {
-				sc_network::SyncEvent::NotificationStreamOpened {
-					remote,
-					received_handshake,
-					sink,
-					inbound,
-					tx,
-				} => match self.on_sync_peer_connected(remote, &received_handshake, sink, inbound) {
-					Ok(()) => {
-						let _ = tx.send(true);
-					},
-					Err(()) => {
-						log::debug!(
-							target: LOG_TARGET,
-							"Failed to register peer {remote:?}: {received_handshake:?}",
-						);
-						let _ = tx.send(false);
-					},
+	fn process_sync_event(&mut self, event: sc_network::SyncEvent<B>) {
+		match event {
+			sc_network::SyncEvent::NotificationStreamOpened {
+				remote,
+				remote_address,
+				received_handshake,
+				negotiated_fallback,
+				sink,
+				inbound,
+				tx,
+			} => match self.on_sync_peer_connected(
+				remote,
+				remote_address,
+				&received_handshake,
+				BlockAnnouncesVersion::negotiated(negotiated_fallback.as_ref()),
+				sink,
+				inbound,
+			) {
+				Ok(()) => {
+					let _ = tx.send(true);
 				},
-				sc_network::SyncEvent::NotificationStreamClosed { remote } => {
-					if self.on_sync_peer_disconnected(remote).is_err() {
-						log::trace!(
-							target: LOG_TARGET,
-							"Disconnected peer which had earlier been refused by on_sync_peer_connected {}",
-							remote
-						);
-					}
+				Err(()) => {
+					log::debug!(
+						target: LOG_TARGET,
+						"Failed to register peer {remote:?}: {received_handshake:?}",
+					);
+					let _ = tx.send(false);
 				},
-				sc_network::SyncEvent::NotificationsReceived { remote, messages } => {
-					for message in messages {
-						if self.peers.contains_key(&remote) {
-							if let Ok(announce) = BlockAnnounce::decode(&mut message.as_ref()) {
-								self.last_notification_io = Instant::now();
-								self.push_block_announce_validation(remote, announce);
-							} else {
-								log::warn!(target: "sub-libp2p", "Failed to decode block announce");
-							}
+			},
+			sc_network::SyncEvent::NotificationStreamClosed { remote } => {
+				if self.on_sync_peer_disconnected(remote).is_err() {
+					log::trace!(
+						target: LOG_TARGET,
+						"Disconnected peer which had earlier been refused by on_sync_peer_connected {}",
+						remote
+					);
+				}
+			},
+			sc_network::SyncEvent::NotificationsReceived { remote, messages } => {
+				for message in messages {
+					if self.peers.contains_key(&remote) {
+						self.report_bandwidth(
+							&remote,
+							"block-announces",
+							"received",
+							message.len(),
+						);
+						if message.is_empty() {
+							// keep-alive, the peer is healthy but has nothing to announce
+							self.last_notification_io = Instant::now();
 						} else {
-							log::trace!(
-								target: LOG_TARGET,
-								"Received sync for peer earlier refused by sync layer: {remote}",
-							);
+							match BlockAnnounce::decode(&mut message.as_ref()) {
+								Ok(announce) => {
+									self.last_notification_io = Instant::now();
+									self.push_block_announce_validation(remote, announce);
+								},
+								Err(error) => self.on_undecodable_announcement(remote, error),
+							}
 						}
+					} else {
+						log::trace!(
+							target: LOG_TARGET,
+							"Received sync for peer earlier refused by sync layer: {remote}",
+						);
 					}
-				},
-				sc_network::SyncEvent::NotificationSinkReplaced { remote, sink } => {
-					if let Some(peer) = self.peers.get_mut(&remote) {
-						peer.sink = sink;
-					}
-				},
-			}
+				}
+			},
+			sc_network::SyncEvent::NotificationSinkReplaced { remote, sink } => {
+				if let Some(peer) = self.peers.get_mut(&remote) {
+					peer.sink = sink;
+				}
+			},
 		}
+	}
 
-		// Retreive warp sync target block header just before polling `ChainSync`
-		// to make progress as soon as we receive it.
-		match self.warp_sync_target_block_header_rx.poll_unpin(cx) {
-			Poll::Ready(Ok(target)) => {
-				self.chain_sync.set_warp_sync_target_block(target);
-			},
-			Poll::Ready(Err(err)) => {
+	fn on_warp_sync_target(&mut self, target: Result<B::Header, oneshot::Canceled>) {
+		match target {
+			Ok(target) =>
+				if let Err(error) = self.set_warp_sync_target(target) {
+					log::error!(
+						target: LOG_TARGET,
+						"Failed to set target block for warp sync. Error: {error}",
+					);
+				},
+			Err(err) => {
 				log::error!(
 					target: LOG_TARGET,
 					"Failed to get target block for warp sync. Error: {err:?}",
 				);
 			},
-			Poll::Pending => {},
 		}
+	}
 
-		// Send outbound requests on `ChanSync`'s behalf.
-		self.send_chain_sync_requests();
-
-		// Poll & process pending responses.
-		while let Poll::Ready(Some(event)) = self.pending_responses.poll_next_unpin(cx) {
-			self.process_response_event(event);
+	/// Run `f` in the span of the event loop section `name`, accounting the time spent in it if
+	/// poll profiling is enabled.
+	fn in_poll_section<R>(&mut self, name: &'static str, f: impl FnOnce(&mut Self) -> R) -> R {
+		let _span = tracing::debug_span!(target: POLL_LOG_TARGET, "sync_poll", section = name)
+			.entered();
+		let started = self.profile_poll.then(Instant::now);
+		let result = f(self);
+		if let (Some(started), Some(metrics)) = (started, &self.metrics) {
+			metrics
+				.poll_duration
+				.with_label_values(&[name])
+				.inc_by(started.elapsed().as_micros() as u64);
 		}
+		result
+	}
+
+	/// Number of connected full peers known to be authorities that take peer slots.
+	fn num_authority_peers(&self) -> usize {
+		self.peers
+			.iter()
+			.filter(|(peer_id, peer)| {
+				peer.info.roles.is_full() &&
+					self.priority_peers.contains(peer_id) &&
+					!self.default_peers_set_no_slot_connected_peers.contains(peer_id)
+			})
+			.count()
+	}
 
-		// Poll block announce validations last, because if a block announcement was received
-		// through the event stream between `SyncingEngine` and `Protocol` and the validation
-		// finished right after it is queued, the resulting block request (if any) can be sent
-		// right away.
-		while let Poll::Ready(Some(result)) = self.block_announce_validator.poll_next_unpin(cx) {
-			self.process_block_announce_validation_result(result);
+	/// Replace the reserved nodes of the default peer set with `reserved`, moving the connected
+	/// peers in or out of the peer slots accordingly.
+	fn set_reserved_peers(&mut self, reserved: HashSet<PeerId>) {
+		log::debug!(target: LOG_TARGET, "Setting reserved peers to {reserved:?}");
+		// Justification peers never take peer slots.
+		let no_slot = reserved.union(&self.justification_peers).copied().collect::<HashSet<_>>();
+
+		for (peer_id, peer) in &self.peers {
+			let counted_inbound = peer.inbound && peer.info.roles.is_full();
+			let was_reserved = self.default_peers_set_no_slot_connected_peers.contains(peer_id);
+			match (was_reserved, no_slot.contains(peer_id)) {
+				(false, true) => {
+					self.default_peers_set_no_slot_connected_peers.insert(*peer_id);
+					if counted_inbound {
+						self.num_in_peers = self.num_in_peers.saturating_sub(1);
+					}
+				},
+				(true, false) => {
+					self.default_peers_set_no_slot_connected_peers.remove(peer_id);
+					if counted_inbound {
+						// May exceed `max_in_peers` until inbound peers disconnect.
+						self.num_in_peers += 1;
+					}
+				},
+				_ => {},
+			}
 		}
 
-		Poll::Pending
+		self.important_peers = self.notification_reserved_peers.union(&reserved).copied().collect();
+		if self.sync_from_reserved_peers_only {
+			self.chain_sync.set_request_peers(Some(reserved));
+		}
+		self.default_peers_set_no_slot_peers = no_slot;
 	}
 
 	/// Called by peer when it is disconnecting.
@@ -924,6 +2103,7 @@ where
 
 			self.chain_sync.peer_disconnected(&peer_id);
 			self.pending_responses.remove(&peer_id);
+			self.paused_block_requests.remove(&peer_id);
 			self.event_streams.retain(|stream| {
 				stream.unbounded_send(SyncEvent::PeerDisconnected(peer_id)).is_ok()
 			});
@@ -941,11 +2121,13 @@ where
 	pub fn on_sync_peer_connected(
 		&mut self,
 		peer_id: PeerId,
+		address: Option<Multiaddr>,
 		status: &BlockAnnouncesHandshake<B>,
+		version: BlockAnnouncesVersion,
 		sink: NotificationsSink,
 		inbound: bool,
 	) -> Result<(), ()> {
-		log::trace!(target: LOG_TARGET, "New peer {peer_id} {status:?}");
+		log::trace!(target: LOG_TARGET, "New peer {peer_id} ({version:?}) {status:?}");
 
 		if self.peers.contains_key(&peer_id) {
 			log::error!(
@@ -956,6 +2138,14 @@ where
 			return Err(())
 		}
 
+		if let Some((banned_at, duration)) = self.banned_peers.get(&peer_id) {
+			if banned_at.elapsed() < *duration {
+				log::debug!(target: LOG_TARGET, "Rejecting banned peer {peer_id}");
+				return Err(())
+			}
+			self.banned_peers.remove(&peer_id);
+		}
+
 		if status.genesis_hash != self.genesis_hash {
 			self.network_service.report_peer(peer_id, rep::GENESIS_MISMATCH);
 
@@ -1001,8 +2191,18 @@ where
 			return Err(())
 		}
 
+		// Peers other than authorities can't take the slots kept for them.
+		let authority_slots_kept = if no_slot_peer || self.priority_peers.contains(&peer_id) {
+			0
+		} else {
+			authority_slots_kept(
+				self.authority_peer_slots,
+				self.priority_peers.len(),
+				self.num_authority_peers(),
+			)
+		};
 		if status.roles.is_full() &&
-			self.chain_sync.num_peers() >=
+			self.chain_sync.num_peers() + authority_slots_kept >=
 				self.default_peers_set_num_full +
 					self.default_peers_set_no_slot_connected_peers.len() +
 					this_peer_reserved_slot
@@ -1019,22 +2219,52 @@ where
 			return Err(())
 		}
 
-		let peer = Peer {
+		let mut peer = Peer {
 			info: ExtendedPeerInfo {
 				roles: status.roles,
 				best_hash: status.best_hash,
 				best_number: status.best_number,
+				block_announces_version: version as u8,
+				request_protocols: Vec::new(),
+				inbound,
 			},
-			known_blocks: LruHashSet::new(
-				NonZeroUsize::new(MAX_KNOWN_BLOCKS).expect("Constant is nonzero"),
-			),
+			known_blocks: LruHashSet::new(self.known_blocks_limit),
 			sink,
 			inbound,
+			address,
+			statistics: PeerStatistics::default(),
+			announce_limiter: self
+				.block_announce_rate_limit
+				.map(|limit| RateLimiter::new(limit, Instant::now())),
+			pending_announcement: None,
+			version,
+			// Nodes that know the version 2 handshake understand keep-alives.
+			keep_alive: version >= BlockAnnouncesVersion::V2 || status.finalized.is_some(),
+			last_notification_sent: Instant::now(),
+			best_updated: Instant::now(),
+			undecodable_announcements: 0,
+			block_request_protocol: 0,
+			state_request_v2: true,
+			pending_state_frame: false,
 		};
 
 		let req = if peer.info.roles.is_full() {
-			match self.chain_sync.new_peer(peer_id, peer.info.best_hash, peer.info.best_number) {
-				Ok(req) => req,
+			match self.chain_sync.new_peer_with_finalized(
+				peer_id,
+				peer.info.best_hash,
+				peer.info.best_number,
+				status.finalized,
+			) {
+				Ok(req) => {
+					peer.statistics.max_blocks_per_request =
+						self.chain_sync.peer_max_blocks_per_request(&peer_id).unwrap_or_default();
+					if let Some(throughput) =
+						self.persisted_peers.get(&peer_id).and_then(|peer| peer.throughput)
+					{
+						self.chain_sync.restore_peer_throughput(&peer_id, throughput);
+					}
+					req
+				},
 				Err(BadPeer(id, repu)) => {
 					self.network_service.report_peer(id, repu);
 					return Err(())
@@ -1089,12 +2319,128 @@ where
 			return
 		}
 
+		// `ChainSync` already considers the request in flight, keep it until syncing is resumed.
+		if self.paused {
+			self.paused_block_requests.insert(peer_id, request);
+			return
+		}
+
+		let response = self.download_blocks(peer_id, request.clone());
+		self.pending_responses.insert(peer_id, PeerRequest::Block(request), response);
+	}
+
+	/// Download blocks from `peer_id` over the block request protocol negotiated with it.
+	fn download_blocks(
+		&self,
+		peer_id: PeerId,
+		request: BlockRequest<B>,
+	) -> BoxFuture<'static, Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled>> {
 		let downloader = self.block_downloader.clone();
+		let protocol = self
+			.block_request_protocol_names
+			.get(self.peers.get(&peer_id).map_or(0, |peer| peer.block_request_protocol))
+			.cloned();
+
+		async move {
+			match protocol {
+				Some(protocol) => downloader.download_blocks_over(peer_id, request, protocol).await,
+				None => downloader.download_blocks(peer_id, request).await,
+			}
+		}
+		.boxed()
+	}
 
-		self.pending_responses.insert(
-			peer_id,
-			PeerRequest::Block(request.clone()),
-			async move { downloader.download_blocks(peer_id, request).await }.boxed(),
+	/// Switch `peer_id` to the next block request protocol after it didn't support the current
+	/// one.
+	///
+	/// Returns `false` if there is no other protocol to try.
+	fn next_block_request_protocol(&mut self, peer_id: &PeerId) -> bool {
+		let Some(peer) = self.peers.get_mut(peer_id) else { return false };
+		if peer.block_request_protocol + 1 >= self.block_request_protocol_names.len() {
+			return false
+		}
+
+		peer.block_request_protocol += 1;
+		debug!(
+			target: LOG_TARGET,
+			"Peer {peer_id} doesn't support the block request protocol, trying {}",
+			self.block_request_protocol_names[peer.block_request_protocol],
+		);
+		true
+	}
+
+	/// Name of the request-response protocol `request` was sent to `peer` over, `None` if the
+	/// block downloader picked it.
+	fn request_protocol_name(
+		&self,
+		peer: &Peer<B>,
+		request: &PeerRequest<B>,
+	) -> Option<ProtocolName> {
+		match request {
+			PeerRequest::Block(_) =>
+				self.block_request_protocol_names.get(peer.block_request_protocol).cloned(),
+			PeerRequest::State => match &self.state_request_v2_protocol_name {
+				Some(protocol) if peer.pending_state_frame => Some(protocol.clone()),
+				_ => Some(self.state_request_protocol_name.clone()),
+			},
+			PeerRequest::WarpProof => self.warp_sync_protocol_name.clone(),
+		}
+	}
+
+	/// Diagnostic snapshot of the engine state.
+	fn dump_state(&self) -> SyncEngineState {
+		let mut state = self.chain_sync.engine_state();
+		state.paused = self.paused;
+		state.waiting = self.waiting.clone();
+		for (peer_id, peer) in &self.peers {
+			let peer_state = state.peers.entry(peer_id.to_base58()).or_default();
+			peer_state.roles = format!("{:?}", peer.info.roles);
+			peer_state.inbound = peer.inbound;
+			peer_state.protocol_version = peer.version as u8;
+			peer_state.no_slot = self.default_peers_set_no_slot_connected_peers.contains(peer_id);
+			peer_state.request_in_flight = self.pending_responses.contains(peer_id);
+		}
+		state
+	}
+
+	/// Request the block `hash` from `peer_id` outside of the `ChainSync` scheduling, sending the
+	/// result to `tx`.
+	fn request_block_from(
+		&mut self,
+		peer_id: PeerId,
+		hash: B::Hash,
+		tx: oneshot::Sender<Result<Option<BlockData<B>>, RequestBlockError>>,
+	) {
+		if !self.peers.contains_key(&peer_id) {
+			let _ = tx.send(Err(RequestBlockError::UnknownPeer));
+			return
+		}
+
+		let request = BlockRequest::<B> {
+			id: 0,
+			fields: BlockAttributes::HEADER |
+				BlockAttributes::BODY |
+				BlockAttributes::JUSTIFICATION,
+			from: FromBlock::Hash(hash),
+			direction: Direction::Descending,
+			max: Some(1),
+		};
+		let downloader = self.block_downloader.clone();
+		let response = self.download_blocks(peer_id, request.clone());
+
+		self.one_off_requests.push(
+			async move {
+				let result = match response.await {
+					Ok(Ok(response)) => downloader
+						.block_response_into_blocks(&request, response)
+						.map(|blocks| blocks.into_iter().next())
+						.map_err(RequestBlockError::InvalidResponse),
+					Ok(Err(error)) => Err(RequestBlockError::Request(error)),
+					Err(oneshot::Canceled) => Err(RequestBlockError::Canceled),
+				};
+				let _ = tx.send(result);
+			}
+			.boxed(),
 		);
 	}
 
@@ -1109,11 +2455,21 @@ where
 
 		self.pending_responses.insert(peer_id, PeerRequest::State, rx.boxed());
 
-		match Self::encode_state_request(&request) {
-			Ok(data) => {
+		let v2 = self.state_request_v2_protocol_name.is_some() &&
+			self.peers.get(&peer_id).map_or(false, |peer| peer.state_request_v2);
+		match Self::encode_state_request(&request, v2) {
+			Ok((data, frame)) => {
+				self.report_bandwidth(&peer_id, "state-request", "sent", data.len());
+				if let Some(peer) = self.peers.get_mut(&peer_id) {
+					peer.pending_state_frame = frame;
+				}
+				let protocol = match &self.state_request_v2_protocol_name {
+					Some(protocol) if frame => protocol.clone(),
+					_ => self.state_request_protocol_name.clone(),
+				};
 				self.network_service.start_request(
 					peer_id,
-					self.state_request_protocol_name.clone(),
+					protocol,
 					data,
 					tx,
 					IfDisconnected::ImmediateError,
@@ -1128,6 +2484,14 @@ where
 		}
 	}
 
+	/// Set the warp sync target block, dropping the pending warp requests it made obsolete.
+	fn set_warp_sync_target(&mut self, header: B::Header) -> Result<(), WarpSyncTargetError> {
+		for peer_id in self.chain_sync.set_warp_sync_target_block(header)? {
+			self.pending_responses.remove(&peer_id);
+		}
+		Ok(())
+	}
+
 	fn send_warp_sync_request(&mut self, peer_id: PeerId, request: WarpProofRequest<B>) {
 		if !self.chain_sync.is_peer_known(&peer_id) {
 			trace!(target: LOG_TARGET, "Cannot send warp proof request to unknown peer {peer_id}");
@@ -1139,14 +2503,18 @@ where
 
 		self.pending_responses.insert(peer_id, PeerRequest::WarpProof, rx.boxed());
 
-		match &self.warp_sync_protocol_name {
-			Some(name) => self.network_service.start_request(
-				peer_id,
-				name.clone(),
-				request.encode(),
-				tx,
-				IfDisconnected::ImmediateError,
-			),
+		match self.warp_sync_protocol_name.clone() {
+			Some(name) => {
+				let request = request.encode();
+				self.report_bandwidth(&peer_id, "warp-proof-request", "sent", request.len());
+				self.network_service.start_request(
+					peer_id,
+					name,
+					request,
+					tx,
+					IfDisconnected::ImmediateError,
+				)
+			},
 			None => {
 				log::warn!(
 					target: LOG_TARGET,
@@ -1156,36 +2524,230 @@ where
 		}
 	}
 
-	fn encode_state_request(request: &OpaqueStateRequest) -> Result<Vec<u8>, String> {
+	/// Account `bytes` sent to or received from `peer_id` on `protocol`.
+	///
+	/// Block requests are encoded by the [`BlockDownloader`], so only their responses are
+	/// accounted.
+	fn report_bandwidth(&self, peer_id: &PeerId, protocol: &str, direction: &str, bytes: usize) {
+		if let (Some(metrics), Some(peer)) = (&self.metrics, self.peers.get(peer_id)) {
+			metrics.report_bandwidth(protocol, direction, peer.info.roles, bytes);
+		}
+	}
+
+	/// Encode `request`, as a request for a frame of the v2 state request protocol if `v2` and it
+	/// is for a state proof.
+	///
+	/// Returns whether it was encoded for the v2 protocol.
+	fn encode_state_request(
+		request: &OpaqueStateRequest,
+		v2: bool,
+	) -> Result<(Vec<u8>, bool), String> {
 		let request: &StateRequest = request.0.downcast_ref().ok_or_else(|| {
 			"Failed to downcast opaque state response during encoding, this is an \
 				implementation bug."
 				.to_string()
 		})?;
 
-		Ok(request.encode_to_vec())
+		if v2 && !request.no_proof && request.base_block.is_empty() {
+			let request = v2::StateRequest {
+				block: request.block.clone(),
+				start: request.start.clone(),
+				max_frame_size: request.max_response_size,
+			};
+			return Ok((request.encode_to_vec(), true))
+		}
+		Ok((request.encode_to_vec(), false))
 	}
 
-	fn decode_state_response(response: &[u8]) -> Result<OpaqueStateResponse, String> {
-		let response = StateResponse::decode(response)
-			.map_err(|error| format!("Failed to decode state response: {error}"))?;
+	/// Decode a state response, a frame of the v2 state request protocol if `frame`.
+	///
+	/// Frames are imported as the proof of a v1 response.
+	fn decode_state_response(response: &[u8], frame: bool) -> Result<StateResponse, String> {
+		if frame {
+			return v2::StateResponse::decode(response)
+				.map(|frame| StateResponse {
+					proof: frame.proof,
+					modes: frame.modes,
+					..Default::default()
+				})
+				.map_err(|error| format!("Failed to decode state frame: {error}"))
+		}
+		StateResponse::decode(response)
+			.map_err(|error| format!("Failed to decode state response: {error}"))
+	}
 
-		Ok(OpaqueStateResponse(Box::new(response)))
+	/// Request state proofs from `peer_id` over the v1 state request protocol after it didn't
+	/// support the v2 one.
+	///
+	/// Returns `false` if the pending state request wasn't sent over the v2 protocol.
+	fn fall_back_to_state_request_v1(&mut self, peer_id: &PeerId) -> bool {
+		let Some(peer) = self.peers.get_mut(peer_id) else { return false };
+		if !std::mem::take(&mut peer.pending_state_frame) {
+			return false
+		}
+
+		peer.state_request_v2 = false;
+		debug!(
+			target: LOG_TARGET,
+			"Peer {peer_id} doesn't support the v2 state request protocol, falling back to v1",
+		);
+		true
+	}
+
+	/// Record `peer_id` as a useful sync peer to persist, evicting the slowest persisted peer if
+	/// there are too many.
+	fn remember_peer(&mut self, peer_id: PeerId) {
+		let Some(peer) = self.peers.get(&peer_id) else { return };
+		let mut addresses = self
+			.persisted_peers
+			.remove(&peer_id)
+			.map_or_else(Vec::new, |peer| peer.addresses);
+		if let Some(address) = &peer.address {
+			if !addresses.contains(address) {
+				addresses.insert(0, address.clone());
+			}
+		}
+
+		if self.persisted_peers.len() >= MAX_PERSISTED_PEERS {
+			if let Some(slowest) = self
+				.persisted_peers
+				.values()
+				.min_by_key(|peer| peer.throughput.unwrap_or_default())
+				.map(|peer| peer.peer_id)
+			{
+				self.persisted_peers.remove(&slowest);
+			}
+		}
+		self.persisted_peers.insert(
+			peer_id,
+			PersistedPeer {
+				peer_id,
+				addresses,
+				best_hash: peer.info.best_hash,
+				best_number: peer.info.best_number,
+				throughput: self.chain_sync.peer_throughput(&peer_id),
+			},
+		);
+	}
+
+	/// High-level view of the syncing status.
+	fn status(&mut self) -> SyncStatus<B> {
+		let mut status = self.chain_sync.status();
+		status.num_connected_peers = self.peers.len() as u32;
+		status.throughput = Some(self.throughput());
+		status
+	}
+
+	/// Send the current status to the status subscribers if it changed materially since the last
+	/// one sent to them.
+	fn notify_status_streams(&mut self) {
+		if self.status_streams.is_empty() {
+			self.last_status = None;
+			return
+		}
+
+		let status = self.status();
+		let best_number = self.client.info().best_number;
+		if let Some((last_status, last_best_number)) = &self.last_status {
+			if !is_material_status_change(last_status, *last_best_number, &status, best_number) {
+				return
+			}
+		}
+
+		self.status_streams.retain(|stream| stream.unbounded_send(status.clone()).is_ok());
+		self.last_status = Some((status, best_number));
+	}
+
+	/// Recent download throughput and the estimated time to download the best seen block.
+	fn throughput(&mut self) -> SyncThroughput {
+		let best_number = self.client.info().best_number;
+		let remaining_blocks = self
+			.chain_sync
+			.status()
+			.best_seen_block
+			.map(|best_seen| best_seen.saturating_sub(best_number).saturated_into::<u64>());
+		self.throughput.throughput(Instant::now(), remaining_blocks)
 	}
 
 	fn process_response_event(&mut self, response_event: ResponseEvent<B>) {
-		let ResponseEvent { peer_id, request, response } = response_event;
+		let ResponseEvent { peer_id, request, response, elapsed } = response_event;
+
+		let max_blocks_per_request = match (&request, &response) {
+			(PeerRequest::Block(_), Ok(Ok(_))) =>
+				self.chain_sync.on_block_response_time(&peer_id, Some(elapsed)),
+			(PeerRequest::Block(_), _) => self.chain_sync.on_block_response_time(&peer_id, None),
+			_ => None,
+		};
+
+		let outcome = match &response {
+			Ok(Ok(_)) => "success",
+			Ok(Err(RequestFailure::Network(OutboundFailure::Timeout))) => "timeout",
+			Ok(Err(RequestFailure::Refused)) => "refused",
+			Ok(Err(_)) => "failure",
+			Err(_) => "canceled",
+		};
+		if let Some(metrics) = &self.metrics {
+			metrics
+				.request_duration
+				.with_label_values(&[Metrics::protocol_label(request.get_type()), outcome])
+				.observe(elapsed.as_secs_f64());
+		}
+		if matches!(outcome, "success" | "timeout") {
+			self.chain_sync.on_response_time(&peer_id, request.get_type(), elapsed);
+		}
+
+		let protocol = self
+			.peers
+			.get(&peer_id)
+			.and_then(|peer| self.request_protocol_name(peer, &request));
+		if let Some(peer) = self.peers.get_mut(&peer_id) {
+			if let Some(protocol) = &protocol {
+				note_request_protocol(&mut peer.info.request_protocols, protocol, &response);
+			}
+			let statistics = &mut peer.statistics;
+			if let Some(max_blocks_per_request) = max_blocks_per_request {
+				statistics.max_blocks_per_request = max_blocks_per_request;
+			}
+			match &response {
+				Ok(Ok(resp)) => {
+					if let Some(metrics) = &self.metrics {
+						let protocol = Metrics::protocol_label(request.get_type());
+						metrics.report_bandwidth(protocol, "received", peer.info.roles, resp.len());
+					}
+					self.throughput.record(Instant::now(), 0, resp.len() as u64);
+					statistics.responses_received += 1;
+					statistics.bytes_received += resp.len() as u64;
+					statistics.total_response_time += elapsed;
+				},
+				Ok(Err(RequestFailure::Network(OutboundFailure::Timeout))) =>
+					statistics.timed_out_requests += 1,
+				_ => statistics.failed_requests += 1,
+			}
+		}
 
 		match response {
 			Ok(Ok(resp)) => match request {
 				PeerRequest::Block(req) => {
 					match self.block_downloader.block_response_into_blocks(&req, resp) {
 						Ok(blocks) => {
+							self.chain_sync.on_request_success(&peer_id, PeerRequestType::Block);
+							if let Some(peer) = self.peers.get_mut(&peer_id) {
+								peer.statistics.blocks_received += blocks.len() as u64;
+							}
+							self.throughput.record(Instant::now(), blocks.len() as u64, 0);
+							self.chain_sync.on_block_throughput(&peer_id, blocks.len(), elapsed);
+							if !blocks.is_empty() {
+								self.remember_peer(peer_id);
+							}
 							if let Some((peer_id, new_req)) =
 								self.chain_sync.on_block_response(peer_id, req, blocks)
 							{
 								self.send_block_request(peer_id, new_req);
 							}
+							// Justification requests raced by other peers are no longer needed.
+							for peer_id in self.chain_sync.take_cancelled_requests() {
+								self.pending_responses.remove(&peer_id);
+							}
 						},
 						Err(BlockResponseError::DecodeFailed(e)) => {
 							debug!(
@@ -1195,10 +2757,16 @@ where
 								e
 							);
 							self.network_service.report_peer(peer_id, rep::BAD_MESSAGE);
-							self.network_service.disconnect_peer(
-								peer_id,
-								self.block_announce_protocol_name.clone(),
-							);
+							if self.chain_sync.on_request_failure(
+								&peer_id,
+								PeerRequestType::Block,
+								RequestFailureKind::MalformedResponse,
+							) {
+								self.network_service.disconnect_peer(
+									peer_id,
+									self.block_announce_protocol_name.clone(),
+								);
+							}
 							return
 						},
 						Err(BlockResponseError::ExtractionFailed(e)) => {
@@ -1214,7 +2782,11 @@ where
 					}
 				},
 				PeerRequest::State => {
-					let response = match Self::decode_state_response(&resp[..]) {
+					let frame = self
+						.peers
+						.get_mut(&peer_id)
+						.map_or(false, |peer| std::mem::take(&mut peer.pending_state_frame));
+					let response = match Self::decode_state_response(&resp[..], frame) {
 						Ok(proto) => proto,
 						Err(e) => {
 							debug!(
@@ -1222,18 +2794,37 @@ where
 								"Failed to decode state response from peer {peer_id:?}: {e:?}.",
 							);
 							self.network_service.report_peer(peer_id, rep::BAD_MESSAGE);
-							self.network_service.disconnect_peer(
-								peer_id,
-								self.block_announce_protocol_name.clone(),
-							);
+							if self.chain_sync.on_request_failure(
+								&peer_id,
+								PeerRequestType::State,
+								RequestFailureKind::MalformedResponse,
+							) {
+								self.network_service.disconnect_peer(
+									peer_id,
+									self.block_announce_protocol_name.clone(),
+								);
+							}
 							return
 						},
 					};
-
-					self.chain_sync.on_state_response(peer_id, response);
+					self.chain_sync.on_request_success(&peer_id, PeerRequestType::State);
+
+					self.chain_sync.on_state_response_time(
+						&peer_id,
+						elapsed,
+						response.max_response_size,
+					);
+					self.chain_sync.on_state_response_modes(&peer_id, response.modes);
+					self.chain_sync
+						.on_state_response(peer_id, OpaqueStateResponse(Box::new(response)));
 				},
 				PeerRequest::WarpProof => {
+					self.chain_sync.on_request_success(&peer_id, PeerRequestType::WarpProof);
 					self.chain_sync.on_warp_sync_response(peer_id, EncodedProof(resp));
+					// Download the next proof while this one is verified.
+					if let Some((peer_id, request)) = self.chain_sync.warp_sync_request() {
+						self.send_warp_sync_request(peer_id, request);
+					}
 				},
 			},
 			Ok(Err(e)) => {
@@ -1241,11 +2832,45 @@ where
 
 				match e {
 					RequestFailure::Network(OutboundFailure::Timeout) => {
+						if let Some(metrics) = &self.metrics {
+							metrics
+								.request_timeouts
+								.with_label_values(&[Metrics::protocol_label(request.get_type())])
+								.inc();
+						}
 						self.network_service.report_peer(peer_id, rep::TIMEOUT);
-						self.network_service
-							.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+						// Retried after a backoff, unless the peer timed out too many times.
+						if self.chain_sync.on_request_failure(
+							&peer_id,
+							request.get_type(),
+							RequestFailureKind::Timeout,
+						) {
+							self.network_service.disconnect_peer(
+								peer_id,
+								self.block_announce_protocol_name.clone(),
+							);
+						}
 					},
 					RequestFailure::Network(OutboundFailure::UnsupportedProtocols) => {
+						match request {
+							PeerRequest::Block(req) =>
+								if self.next_block_request_protocol(&peer_id) {
+									self.send_block_request(peer_id, req);
+									return
+								},
+							PeerRequest::State =>
+								if self.fall_back_to_state_request_v1(&peer_id) {
+									self.chain_sync.on_state_request_dropped(&peer_id);
+									// Send the request again, over the v1 protocol.
+									if let Some((peer_id, request)) =
+										self.chain_sync.state_request()
+									{
+										self.send_state_request(peer_id, request);
+									}
+									return
+								},
+							PeerRequest::WarpProof => {},
+						}
 						self.network_service.report_peer(peer_id, rep::BAD_PROTOCOL);
 						self.network_service
 							.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
@@ -1292,41 +2917,30 @@ where
 		self.pending_responses.len()
 	}
 
-	/// Get config for the block announcement protocol
+	/// Get config for the block announcement protocol, namespaced for the sync instance
+	/// `instance_name` if any.
 	fn get_block_announce_proto_config(
 		protocol_id: ProtocolId,
 		fork_id: &Option<String>,
-		roles: Roles,
-		best_number: NumberFor<B>,
-		best_hash: B::Hash,
-		genesis_hash: B::Hash,
+		instance_name: Option<&str>,
+		handshake: BlockAnnouncesHandshake<B>,
 	) -> NonDefaultSetConfig {
-		let block_announces_protocol = {
-			let genesis_hash = genesis_hash.as_ref();
-			if let Some(ref fork_id) = fork_id {
-				format!(
-					"/{}/{}/block-announces/1",
-					array_bytes::bytes2hex("", genesis_hash),
-					fork_id
-				)
-			} else {
-				format!("/{}/block-announces/1", array_bytes::bytes2hex("", genesis_hash))
-			}
-		};
+		let genesis_hash = handshake.genesis_hash.as_ref();
 
 		NonDefaultSetConfig {
-			notifications_protocol: block_announces_protocol.into(),
-			fallback_names: iter::once(
+			notifications_protocol: instance::instance_protocol_name(
+				instance_name,
+				BlockAnnouncesVersion::LATEST.protocol_name(genesis_hash, fork_id),
+			),
+			fallback_names: vec![
+				BlockAnnouncesVersion::V1.protocol_name(genesis_hash, fork_id),
 				format!("/{}/block-announces/1", protocol_id.as_ref()).into(),
-			)
+			]
+			.into_iter()
+			.map(|name| instance::instance_protocol_name(instance_name, name))
 			.collect(),
 			max_notification_size: MAX_BLOCK_ANNOUNCE_SIZE,
-			handshake: Some(NotificationHandshake::new(BlockAnnouncesHandshake::<B>::build(
-				roles,
-				best_number,
-				best_hash,
-				genesis_hash,
-			))),
+			handshake: Some(NotificationHandshake::new(handshake)),
 			// NOTE: `set_config` will be ignored by `protocol.rs` as the block announcement
 			// protocol is still hardcoded into the peerset.
 			set_config: SetConfig {
@@ -1338,3 +2952,267 @@ where
 		}
 	}
 }
+
+impl<B: BlockT, Client> Drop for SyncingEngine<B, Client> {
+	fn drop(&mut self) {
+		if let Some(tx) = self.stop_requested.take() {
+			let _ = tx.send(());
+		}
+	}
+}
+
+/// Whether the sync status changed enough since `old` to be sent to the status subscribers.
+///
+/// That's the case on transitions between sync states, strategies and phases, when peers connect
+/// or disconnect, and every [`STATUS_PROGRESS_BLOCKS`] blocks of progress.
+fn is_material_status_change<B: BlockT>(
+	old: &SyncStatus<B>,
+	old_best_number: NumberFor<B>,
+	new: &SyncStatus<B>,
+	new_best_number: NumberFor<B>,
+) -> bool {
+	mem::discriminant(&old.state) != mem::discriminant(&new.state) ||
+		old.strategy != new.strategy ||
+		old.num_peers != new.num_peers ||
+		old.num_connected_peers != new.num_connected_peers ||
+		old.warp_sync.as_ref().map(|progress| &progress.phase) !=
+			new.warp_sync.as_ref().map(|progress| &progress.phase) ||
+		old.state_sync.is_some() != new.state_sync.is_some() ||
+		old.gap_sync.is_some() != new.gap_sync.is_some() ||
+		new_best_number.saturating_sub(old_best_number) >= STATUS_PROGRESS_BLOCKS.into()
+}
+
+/// Whether light peers are sent the announcement of the block `number` under `policy`.
+fn announce_to_light_peers<N: AtLeast32BitUnsigned>(
+	policy: LightPeerAnnouncements,
+	number: N,
+	is_best: bool,
+) -> bool {
+	match policy {
+		LightPeerAnnouncements::All => true,
+		LightPeerAnnouncements::EveryNth(interval) => (number % interval.max(1).into()).is_zero(),
+		LightPeerAnnouncements::BestOnly => is_best,
+		LightPeerAnnouncements::Never => false,
+	}
+}
+
+/// Whether a full peer with the best block `peer_best`, if known, needs the announcement of the
+/// block `hash` with `number`. It doesn't if it is far behind or if the block is its best block.
+fn full_peer_needs_announcement<B: BlockT>(
+	peer_best: Option<(B::Hash, NumberFor<B>)>,
+	hash: B::Hash,
+	number: NumberFor<B>,
+) -> bool {
+	peer_best.map_or(true, |(best_hash, best_number)| {
+		best_hash != hash && best_number.saturating_add(MAX_ANNOUNCE_DISTANCE.into()) >= number
+	})
+}
+
+/// Number of the `slots` full peer slots kept for the `known` authorities, `connected` of which
+/// already take one. Only as many slots as there are known authorities are kept, so that the slots
+/// go to other peers while no authority is known.
+fn authority_slots_kept(slots: usize, known: usize, connected: usize) -> usize {
+	slots.min(known).saturating_sub(connected)
+}
+
+/// Whether `peer` is disconnected when all peers are evicted because syncing has stalled.
+fn evicted_on_stall(
+	peer: &PeerId,
+	evict_reserved_peers: bool,
+	important_peers: &HashSet<PeerId>,
+	no_slot_peers: &HashSet<PeerId>,
+) -> bool {
+	evict_reserved_peers || !(important_peers.contains(peer) || no_slot_peers.contains(peer))
+}
+
+/// Update the names of the request-response protocols negotiated with a peer, `protocols`, with
+/// the outcome of a request sent over `protocol`.
+fn note_request_protocol(
+	protocols: &mut Vec<String>,
+	protocol: &ProtocolName,
+	response: &Result<Result<Vec<u8>, RequestFailure>, oneshot::Canceled>,
+) {
+	match response {
+		// The peer accepted the protocol, whether it answered the request or not.
+		Ok(Ok(_)) | Ok(Err(RequestFailure::Refused)) =>
+			if !protocols.iter().any(|name| name == &**protocol) {
+				protocols.push(protocol.to_string());
+			},
+		Ok(Err(RequestFailure::Network(OutboundFailure::UnsupportedProtocols))) =>
+			protocols.retain(|name| name != &**protocol),
+		_ => {},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reserved_peers_are_kept_on_stall() {
+		let (important, no_slot, other) = (PeerId::random(), PeerId::random(), PeerId::random());
+		let important_peers = HashSet::from([important]);
+		let no_slot_peers = HashSet::from([no_slot]);
+
+		assert!(!evicted_on_stall(&important, false, &important_peers, &no_slot_peers));
+		assert!(!evicted_on_stall(&no_slot, false, &important_peers, &no_slot_peers));
+		assert!(evicted_on_stall(&other, false, &important_peers, &no_slot_peers));
+
+		for peer in [important, no_slot, other] {
+			assert!(evicted_on_stall(&peer, true, &important_peers, &no_slot_peers));
+		}
+	}
+
+	#[test]
+	fn peer_statistics_are_summed() {
+		let first = PeerStatistics {
+			blocks_received: 10,
+			responses_received: 1,
+			total_response_time: Duration::from_millis(100),
+			max_blocks_per_request: 64,
+			..Default::default()
+		};
+		let second = PeerStatistics {
+			blocks_received: 5,
+			responses_received: 3,
+			total_response_time: Duration::from_millis(300),
+			max_blocks_per_request: 32,
+			..Default::default()
+		};
+
+		let total = sum_peer_statistics([&first, &second].into_iter());
+		assert_eq!(total.blocks_received, 15);
+		assert_eq!(total.max_blocks_per_request, 64);
+		assert_eq!(total.average_response_time(), Some(Duration::from_millis(100)));
+		assert_eq!(sum_peer_statistics(std::iter::empty()), PeerStatistics::default());
+	}
+
+	#[test]
+	fn announcements_follow_best_block_of_peer() {
+		use sp_core::H256;
+		use substrate_test_runtime_client::runtime::Block;
+
+		let (hash, other_hash) = (H256::repeat_byte(1), H256::repeat_byte(2));
+		let distance = MAX_ANNOUNCE_DISTANCE as u64;
+
+		assert!(full_peer_needs_announcement::<Block>(None, hash, 1000));
+		assert!(full_peer_needs_announcement::<Block>(Some((other_hash, 999)), hash, 1000));
+		assert!(!full_peer_needs_announcement::<Block>(Some((hash, 1000)), hash, 1000));
+		assert!(full_peer_needs_announcement::<Block>(
+			Some((other_hash, 1000 - distance)),
+			hash,
+			1000,
+		));
+		assert!(!full_peer_needs_announcement::<Block>(
+			Some((other_hash, 999 - distance)),
+			hash,
+			1000,
+		));
+	}
+
+	#[test]
+	fn state_proofs_are_requested_in_frames() {
+		use substrate_test_runtime_client::{runtime::Block, TestClient};
+		type Engine = SyncingEngine<Block, TestClient>;
+
+		let request = |no_proof| {
+			OpaqueStateRequest(Box::new(StateRequest {
+				block: vec![1; 32],
+				start: vec![vec![2]],
+				no_proof,
+				max_response_size: 1024,
+				..Default::default()
+			}))
+		};
+
+		let (data, frame) = Engine::encode_state_request(&request(false), true).unwrap();
+		assert!(frame);
+		assert_eq!(
+			v2::StateRequest::decode(&data[..]).unwrap(),
+			v2::StateRequest { block: vec![1; 32], start: vec![vec![2]], max_frame_size: 1024 },
+		);
+		// Raw entries are only served over the v1 protocol.
+		assert!(!Engine::encode_state_request(&request(true), true).unwrap().1);
+		assert!(!Engine::encode_state_request(&request(false), false).unwrap().1);
+
+		let frame = v2::StateResponse { proof: vec![3; 8], modes: 1 }.encode_to_vec();
+		let response = Engine::decode_state_response(&frame, true).unwrap();
+		assert_eq!(response.proof, vec![3; 8]);
+		assert_eq!(response.modes, 1);
+	}
+
+	#[test]
+	fn request_protocols_are_recorded_as_negotiated() {
+		let v1 = ProtocolName::from("/sync/2");
+		let v2 = ProtocolName::from("/state/3");
+		let mut protocols = Vec::new();
+
+		// Failures before the protocol is negotiated don't tell whether the peer supports it.
+		note_request_protocol(
+			&mut protocols,
+			&v1,
+			&Ok(Err(RequestFailure::Network(OutboundFailure::Timeout))),
+		);
+		note_request_protocol(&mut protocols, &v1, &Err(oneshot::Canceled));
+		assert!(protocols.is_empty());
+
+		note_request_protocol(&mut protocols, &v1, &Ok(Err(RequestFailure::Refused)));
+		note_request_protocol(&mut protocols, &v2, &Ok(Ok(vec![1])));
+		note_request_protocol(&mut protocols, &v1, &Ok(Ok(vec![1])));
+		assert_eq!(protocols, vec!["/sync/2".to_string(), "/state/3".to_string()]);
+
+		note_request_protocol(
+			&mut protocols,
+			&v2,
+			&Ok(Err(RequestFailure::Network(OutboundFailure::UnsupportedProtocols))),
+		);
+		assert_eq!(protocols, vec!["/sync/2".to_string()]);
+	}
+
+	#[test]
+	fn observer_handshake_is_decoded_by_nodes_not_knowing_observers() {
+		use codec::DecodeAll;
+		use substrate_test_runtime_client::runtime::{Block, Hash};
+
+		/// Block announces handshake as decoded by the nodes predating observers.
+		#[derive(Decode)]
+		struct LegacyHandshake {
+			roles: u8,
+			_best_number: u64,
+			_best_hash: Hash,
+			_genesis_hash: Hash,
+		}
+		/// Role bits known to the nodes predating observers, which refuse any other.
+		const LEGACY_ROLES: u8 = 0b111;
+
+		let handshake = BlockAnnouncesHandshake::<Block>::build(
+			Roles::FULL | Roles::OBSERVER,
+			10,
+			Hash::repeat_byte(1),
+			Hash::repeat_byte(2),
+		)
+		.encode();
+
+		let legacy = LegacyHandshake::decode_all(&mut &handshake[..]).unwrap();
+		assert_eq!(legacy.roles & !LEGACY_ROLES, 0);
+		let roles = Roles::from_bits(legacy.roles).unwrap();
+		assert!(roles.is_full() && !roles.is_authority());
+
+		let decoded = BlockAnnouncesHandshake::<Block>::decode_all(&mut &handshake[..]).unwrap();
+		assert!(decoded.roles.is_observer());
+		assert!(!Roles::FULL.is_observer());
+		assert!(!Roles::AUTHORITY.is_observer());
+		assert!(!Roles::LIGHT.is_observer());
+	}
+
+	#[test]
+	fn authority_slots_are_only_kept_for_known_authorities() {
+		// No authority is known, e.g. without authority discovery.
+		assert_eq!(authority_slots_kept(4, 0, 0), 0);
+		assert_eq!(authority_slots_kept(4, 2, 0), 2);
+		assert_eq!(authority_slots_kept(4, 2, 1), 1);
+		assert_eq!(authority_slots_kept(4, 10, 1), 3);
+		assert_eq!(authority_slots_kept(4, 10, 5), 0);
+		assert_eq!(authority_slots_kept(0, 10, 0), 0);
+	}
+}
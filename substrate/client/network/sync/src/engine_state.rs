@@ -0,0 +1,108 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Internal state of `SyncingEngine`, for diagnostic purposes.
+//!
+//! **Warning**: These APIs are not stable.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Snapshot of the internal state of `SyncingEngine`.
+///
+/// Meant for general diagnostic purposes.
+///
+/// **Warning**: This API is not stable.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncEngineState {
+	/// Active sync strategy.
+	pub strategy: String,
+	/// Global sync state, e.g. idle or downloading up to a target.
+	pub state: String,
+	/// Phase of the warp sync in progress, if any.
+	pub warp_sync: Option<String>,
+	/// Progress of the state sync in progress, if any.
+	pub state_sync: Option<String>,
+	/// Whether syncing is paused.
+	pub paused: bool,
+	/// Why the node is intentionally idle, if it is.
+	pub waiting: Option<String>,
+	/// Number of the best block queued for import.
+	pub best_queued_number: u64,
+	/// Hash of the best block queued for import.
+	pub best_queued_hash: String,
+	/// Number of blocks queued for import.
+	pub queued_blocks: u32,
+	/// Sync peers, by peer id.
+	pub peers: HashMap<String, SyncPeerState>,
+	/// Forks being downloaded.
+	pub fork_targets: Vec<ForkTargetState>,
+	/// Range of the block history being downloaded, if any.
+	pub gap_sync: Option<GapSyncState>,
+}
+
+/// Part of the `SyncEngineState` struct. Unstable.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncPeerState {
+	/// Roles the peer advertised.
+	pub roles: String,
+	/// Whether the peer connected to us.
+	pub inbound: bool,
+	/// Version of the block announces protocol negotiated with the peer.
+	pub protocol_version: u8,
+	/// Whether the peer doesn't occupy one of the limited peer slots, e.g. a reserved peer.
+	pub no_slot: bool,
+	/// Best block number of the peer.
+	pub best_number: u64,
+	/// Best block hash of the peer.
+	pub best_hash: String,
+	/// Number of the best block we know we have in common with the peer.
+	pub common_number: u64,
+	/// What we are downloading from the peer, if anything.
+	pub state: String,
+	/// Maximum number of blocks requested from the peer at once.
+	pub max_blocks_per_request: u32,
+	/// Whether a request to the peer is in flight.
+	pub request_in_flight: bool,
+}
+
+/// Part of the `SyncEngineState` struct. Unstable.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForkTargetState {
+	/// Hash of the fork head.
+	pub hash: String,
+	/// Number of the fork head.
+	pub number: u64,
+	/// Peers the fork can be downloaded from.
+	pub peers: Vec<String>,
+}
+
+/// Part of the `SyncEngineState` struct. Unstable.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GapSyncState {
+	/// First block of the gap.
+	pub start: u64,
+	/// Last block of the gap.
+	pub end: u64,
+	/// Best block of the gap queued for import.
+	pub best_queued: u64,
+}
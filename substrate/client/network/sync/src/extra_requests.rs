@@ -49,6 +49,10 @@ pub(crate) struct ExtraRequests<B: BlockT> {
 	pending_requests: VecDeque<ExtraRequest<B>>,
 	/// requests which are currently underway to some peer
 	active_requests: HashMap<PeerId, ExtraRequest<B>>,
+	/// maximum number of peers the same request is sent to at once
+	max_racing_peers: usize,
+	/// peers whose requests were cancelled because another peer answered first
+	cancelled_requests: Vec<PeerId>,
 	/// previous requests without response
 	failed_requests: HashMap<ExtraRequest<B>, Vec<(PeerId, Instant)>>,
 	/// successful requests
@@ -64,17 +68,26 @@ impl<B: BlockT> ExtraRequests<B> {
 			best_seen_finalized_number: Zero::zero(),
 			pending_requests: VecDeque::new(),
 			active_requests: HashMap::new(),
+			max_racing_peers: 1,
+			cancelled_requests: Vec::new(),
 			failed_requests: HashMap::new(),
 			importing_requests: HashSet::new(),
 			request_type_name,
 		}
 	}
 
+	/// Send each request to up to `max_racing_peers` peers at once, accepting the first response.
+	pub(crate) fn with_max_racing_peers(mut self, max_racing_peers: usize) -> Self {
+		self.max_racing_peers = max_racing_peers.max(1);
+		self
+	}
+
 	/// Reset all state as if returned from `new`.
 	pub(crate) fn reset(&mut self) {
 		self.tree = ForkTree::new();
 		self.pending_requests.clear();
 		self.active_requests.clear();
+		self.cancelled_requests.clear();
 		self.failed_requests.clear();
 	}
 
@@ -109,10 +122,23 @@ impl<B: BlockT> ExtraRequests<B> {
 	/// Retry any pending request if a peer disconnected.
 	pub(crate) fn peer_disconnected(&mut self, who: &PeerId) {
 		if let Some(request) = self.active_requests.remove(who) {
+			self.reschedule(request);
+		}
+	}
+
+	/// Put `request` back in front of the queue, unless it is already queued to race it.
+	fn reschedule(&mut self, request: ExtraRequest<B>) {
+		if !self.pending_requests.contains(&request) {
 			self.pending_requests.push_front(request);
 		}
 	}
 
+	/// Peers whose requests were cancelled because another peer answered the same request first,
+	/// since the last call.
+	pub(crate) fn take_cancelled_requests(&mut self) -> Vec<PeerId> {
+		std::mem::take(&mut self.cancelled_requests)
+	}
+
 	/// Processes the response for the request previously sent to the given peer.
 	pub(crate) fn on_response<R>(
 		&mut self,
@@ -130,6 +156,17 @@ impl<B: BlockT> ExtraRequests<B> {
 				);
 
 				self.importing_requests.insert(request);
+				// The first response wins the race, the other peers needn't answer.
+				self.pending_requests.retain(|pending| *pending != request);
+				let cancelled_requests = &mut self.cancelled_requests;
+				self.active_requests.retain(|peer, active| {
+					if *active == request {
+						cancelled_requests.push(*peer);
+						false
+					} else {
+						true
+					}
+				});
 				return Some((who, request.0, request.1, r))
 			} else {
 				trace!(target: "sync",
@@ -138,7 +175,7 @@ impl<B: BlockT> ExtraRequests<B> {
 				);
 			}
 			self.failed_requests.entry(request).or_default().push((who, Instant::now()));
-			self.pending_requests.push_front(request);
+			self.reschedule(request);
 		} else {
 			trace!(target: "sync",
 				"No active {} request to {:?}",
@@ -281,10 +318,11 @@ impl<'a, B: BlockT> Matcher<'a, B> {
 	/// # Note
 	///
 	/// The returned `PeerId` (if any) is guaranteed to come from the given `peers`
-	/// argument.
+	/// argument and to be accepted by `allowed`.
 	pub(crate) fn next(
 		&mut self,
 		peers: &HashMap<PeerId, PeerSync<B>>,
+		allowed: impl Fn(&PeerId) -> bool,
 	) -> Option<(PeerId, ExtraRequest<B>)> {
 		if self.remaining == 0 {
 			return None
@@ -296,9 +334,19 @@ impl<'a, B: BlockT> Matcher<'a, B> {
 		}
 
 		while let Some(request) = self.extras.pending_requests.pop_front() {
-			for (peer, sync) in
-				peers.iter().filter(|(_, sync)| sync.state == PeerSyncState::Available)
-			{
+			let mut candidates = peers
+				.iter()
+				.filter(|(peer, sync)| sync.state == PeerSyncState::Available && allowed(peer))
+				.collect::<Vec<_>>();
+			// peers that announced holding the justification are asked first, then the peers that
+			// reported having finalized the block as they are the most likely to have it
+			candidates.sort_by_key(|(_, sync)| {
+				(
+					!sync.justification_hints.contains(&request.0),
+					!sync.finalized_number.map_or(false, |number| number >= request.1),
+				)
+			});
+			for (peer, sync) in candidates {
 				// only ask peers that have synced at least up to the block number that we're asking
 				// the extra for
 				if sync.best_number < request.1 {
@@ -325,6 +373,17 @@ impl<'a, B: BlockT> Matcher<'a, B> {
 					self.extras.request_type_name, peer, request,
 				);
 
+				// keep the request queued until enough peers race for it
+				let racing_peers = self
+					.extras
+					.active_requests
+					.values()
+					.filter(|active| **active == request)
+					.count();
+				if racing_peers < self.extras.max_racing_peers {
+					self.extras.pending_requests.push_front(request);
+				}
+
 				return Some((*peer, request))
 			}
 
@@ -343,7 +402,7 @@ impl<'a, B: BlockT> Matcher<'a, B> {
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::PeerSync;
+	use crate::{state_request_handler::STATE_MODES_LEGACY, PeerSync};
 	use quickcheck::{Arbitrary, Gen, QuickCheck};
 	use sp_blockchain::Error as ClientError;
 	use sp_test_primitives::{Block, BlockNumber, Hash};
@@ -365,7 +424,7 @@ mod tests {
 			let mut m = requests.matcher();
 
 			for p in &pending {
-				let (peer, r) = m.next(&peers.0).unwrap();
+				let (peer, r) = m.next(&peers.0, |_| true).unwrap();
 				assert_eq!(p, &r);
 				peers.0.get_mut(&peer).unwrap().state =
 					PeerSyncState::DownloadingJustification(r.0);
@@ -405,7 +464,7 @@ mod tests {
 			}
 
 			let mut m = requests.matcher();
-			while let Some((peer, r)) = m.next(&peers.0) {
+			while let Some((peer, r)) = m.next(&peers.0, |_| true) {
 				peers.0.get_mut(&peer).unwrap().state =
 					PeerSyncState::DownloadingJustification(r.0);
 			}
@@ -441,7 +500,7 @@ mod tests {
 			}
 
 			let mut m = requests.matcher();
-			while let Some((peer, r)) = m.next(&peers.0) {
+			while let Some((peer, r)) = m.next(&peers.0, |_| true) {
 				peers.0.get_mut(&peer).unwrap().state =
 					PeerSyncState::DownloadingJustification(r.0);
 			}
@@ -532,6 +591,155 @@ mod tests {
 		assert_eq!(finality_proofs.tree.roots().count(), 0);
 	}
 
+	#[test]
+	fn racing_request_is_cancelled_on_first_response() {
+		let mut requests = ExtraRequests::<Block>::new("test").with_max_racing_peers(2);
+		let mut peers = (0..3)
+			.map(|_| {
+				let peer_id = PeerId::random();
+				let sync = PeerSync {
+					peer_id,
+					common_number: 0,
+					best_hash: Hash::random(),
+					best_number: 10,
+					state: PeerSyncState::Available,
+					max_blocks_per_request: 64,
+					throughput: None,
+					latency: None,
+					finalized_number: None,
+					max_state_response_size: None,
+					state_modes: STATE_MODES_LEGACY,
+					best_stale: false,
+					request_run: None,
+					justification_hints: Vec::new(),
+				};
+				(peer_id, sync)
+			})
+			.collect::<HashMap<_, _>>();
+
+		let request = (Hash::random(), 5);
+		requests.schedule(request, |a, b| Ok(a[0] >= b[0]));
+
+		let mut m = requests.matcher();
+		let mut racing = Vec::new();
+		while let Some((peer, r)) = m.next(&peers, |_| true) {
+			assert_eq!(r, request);
+			peers.get_mut(&peer).unwrap().state = PeerSyncState::DownloadingJustification(r.0);
+			racing.push(peer);
+		}
+		assert_eq!(racing.len(), 2);
+		assert!(requests.pending_requests.is_empty());
+
+		// An empty response doesn't cancel the race.
+		assert!(requests.on_response::<()>(racing[0], None).is_none());
+		assert!(requests.take_cancelled_requests().is_empty());
+		assert_eq!(requests.pending_requests.iter().collect::<Vec<_>>(), vec![&request]);
+
+		let (peer, ..) = requests.on_response(racing[1], Some(())).unwrap();
+		assert_eq!(peer, racing[1]);
+		assert!(requests.pending_requests.is_empty());
+		assert!(requests.active_requests.is_empty());
+		assert!(requests.take_cancelled_requests().is_empty());
+
+		// Start a new race and answer it, the other peer is cancelled.
+		let request = (Hash::random(), 6);
+		requests.tree = ForkTree::new();
+		requests.schedule(request, |a, b| Ok(a[0] >= b[0]));
+		for peer in peers.values_mut() {
+			peer.state = PeerSyncState::Available;
+		}
+		let mut m = requests.matcher();
+		let first = m.next(&peers, |_| true).unwrap().0;
+		let second = m.next(&peers, |_| true).unwrap().0;
+		assert!(requests.on_response(first, Some(())).is_some());
+		assert_eq!(requests.take_cancelled_requests(), vec![second]);
+		assert!(requests.on_response(second, Some(())).is_none());
+	}
+
+	#[test]
+	fn requests_prefer_peers_that_finalized_the_block() {
+		let mut requests = ExtraRequests::<Block>::new("test");
+		let mut peers = (0..8)
+			.map(|i| {
+				let peer_id = PeerId::random();
+				let sync = PeerSync {
+					peer_id,
+					common_number: 0,
+					best_hash: Hash::random(),
+					best_number: 10,
+					state: PeerSyncState::Available,
+					max_blocks_per_request: 64,
+					throughput: None,
+					latency: None,
+					finalized_number: if i == 0 { Some(4) } else { None },
+					max_state_response_size: None,
+					state_modes: STATE_MODES_LEGACY,
+					best_stale: false,
+					request_run: None,
+					justification_hints: Vec::new(),
+				};
+				(peer_id, sync)
+			})
+			.collect::<HashMap<_, _>>();
+		let finalized_peer = PeerId::random();
+		peers.insert(
+			finalized_peer,
+			PeerSync {
+				peer_id: finalized_peer,
+				common_number: 0,
+				best_hash: Hash::random(),
+				best_number: 10,
+				state: PeerSyncState::Available,
+				max_blocks_per_request: 64,
+				throughput: None,
+				latency: None,
+				finalized_number: Some(5),
+				max_state_response_size: None,
+				state_modes: STATE_MODES_LEGACY,
+				best_stale: false,
+				request_run: None,
+				justification_hints: Vec::new(),
+			},
+		);
+
+		requests.schedule((Hash::random(), 5), |a, b| Ok(a[0] >= b[0]));
+		let (peer, _) = requests.matcher().next(&peers, |_| true).unwrap();
+		assert_eq!(peer, finalized_peer);
+	}
+
+	#[test]
+	fn requests_prefer_peers_holding_the_justification() {
+		let mut requests = ExtraRequests::<Block>::new("test");
+		let request = (Hash::random(), 5);
+		let peer = |finalized_number, justification_hints| {
+			let peer_id = PeerId::random();
+			let sync = PeerSync {
+				peer_id,
+				common_number: 0,
+				best_hash: Hash::random(),
+				best_number: 10,
+				state: PeerSyncState::Available,
+				max_blocks_per_request: 64,
+				throughput: None,
+				latency: None,
+				finalized_number,
+				max_state_response_size: None,
+				state_modes: STATE_MODES_LEGACY,
+				best_stale: false,
+				request_run: None,
+				justification_hints,
+			};
+			(peer_id, sync)
+		};
+		let mut peers = (0..8).map(|_| peer(Some(5), Vec::new())).collect::<HashMap<_, _>>();
+		let (holding_peer, sync) = peer(None, vec![request.0]);
+		peers.insert(holding_peer, sync);
+
+		requests.schedule(request, |a, b| Ok(a[0] >= b[0]));
+		let (peer, _) = requests.matcher().next(&peers, |_| true).unwrap();
+		assert_eq!(peer, holding_peer);
+	}
+
 	// Some Arbitrary instances to allow easy construction of random peer sets:
 
 	#[derive(Debug, Clone)]
@@ -561,6 +769,15 @@ mod tests {
 				best_hash: Hash::random(),
 				best_number: u64::arbitrary(g),
 				state: ArbitraryPeerSyncState::arbitrary(g).0,
+				max_blocks_per_request: 64,
+				throughput: None,
+				latency: None,
+				finalized_number: None,
+				max_state_response_size: None,
+				state_modes: STATE_MODES_LEGACY,
+				best_stale: false,
+				request_run: None,
+				justification_hints: Vec::new(),
 			};
 			ArbitraryPeerSync(ps)
 		}
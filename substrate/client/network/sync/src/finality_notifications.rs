@@ -0,0 +1,305 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Optional notification protocol over which peers tell each other the blocks they finalize and
+//! whether they hold their justification, so that justifications are requested from the peers
+//! that have them instead of probing.
+//!
+//! The protocol is disabled unless
+//! [`NetworkConfiguration::finality_notifications`](sc_network::config::NetworkConfiguration)
+//! is set. Peers are connected on it once they are connected for syncing.
+
+use crate::service::chain_sync::SyncingService;
+
+use codec::{Decode, Encode};
+use futures::{prelude::*, stream};
+use libp2p::{multiaddr, PeerId};
+use log::{debug, trace};
+
+use sc_client_api::{BlockBackend, BlockchainEvents, FinalityNotifications, HeaderBackend};
+use sc_network::{
+	config::{NonDefaultSetConfig, NonReservedPeerMode, SetConfig},
+	event::Event,
+	types::ProtocolName,
+	NetworkEventStream, NetworkNotification, NetworkPeers,
+};
+use sc_network_common::sync::{SyncEvent, SyncEventStream};
+use sp_runtime::traits::{Block as BlockT, Header, NumberFor};
+
+use std::{collections::HashSet, iter, pin::Pin, sync::Arc};
+
+const LOG_TARGET: &str = "sync";
+
+/// Maximum size of a finality notification, a block hash and number and a flag.
+const MAX_FINALITY_NOTIFICATION_SIZE: u64 = 1024;
+
+mod rep {
+	use sc_network::ReputationChange as Rep;
+	/// Peer sent a finality notification that failed to decode.
+	pub const BAD_NOTIFICATION: Rep = Rep::new(-(1 << 10), "Bad finality notification");
+}
+
+/// Notification that the sender finalized a block.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct BlockFinalized<H, N> {
+	/// Hash of the finalized block.
+	pub hash: H,
+	/// Number of the finalized block.
+	pub number: N,
+	/// Whether the sender holds a justification of the block and can serve it.
+	pub has_justification: bool,
+}
+
+/// Generate the finality notification protocol name from the genesis hash and fork id.
+fn generate_protocol_name<Hash: AsRef<[u8]>>(genesis_hash: Hash, fork_id: Option<&str>) -> String {
+	let genesis_hash = genesis_hash.as_ref();
+	if let Some(fork_id) = fork_id {
+		format!("/{}/{}/finality/1", array_bytes::bytes2hex("", genesis_hash), fork_id)
+	} else {
+		format!("/{}/finality/1", array_bytes::bytes2hex("", genesis_hash))
+	}
+}
+
+/// Prototype of a [`FinalityNotificationsHandler`], registering the protocol before the network
+/// is started.
+pub struct FinalityNotificationsPrototype {
+	protocol_name: ProtocolName,
+}
+
+impl FinalityNotificationsPrototype {
+	/// Create a new instance.
+	pub fn new<Hash: AsRef<[u8]>>(genesis_hash: Hash, fork_id: Option<&str>) -> Self {
+		Self { protocol_name: generate_protocol_name(genesis_hash, fork_id).into() }
+	}
+
+	/// Returns the configuration of the set to put in the network configuration.
+	pub fn set_config(&self) -> NonDefaultSetConfig {
+		NonDefaultSetConfig {
+			notifications_protocol: self.protocol_name.clone(),
+			fallback_names: Vec::new(),
+			max_notification_size: MAX_FINALITY_NOTIFICATION_SIZE,
+			handshake: None,
+			set_config: SetConfig {
+				in_peers: 0,
+				out_peers: 0,
+				reserved_nodes: Vec::new(),
+				non_reserved_mode: NonReservedPeerMode::Deny,
+			},
+		}
+	}
+
+	/// Turns the prototype into the actual handler.
+	pub fn build<B, N, Client>(
+		self,
+		network: N,
+		sync: Arc<SyncingService<B>>,
+		client: Arc<Client>,
+	) -> FinalityNotificationsHandler<B, N, Client>
+	where
+		B: BlockT + 'static,
+		N: NetworkPeers + NetworkEventStream + NetworkNotification,
+		Client: BlockchainEvents<B> + BlockBackend<B> + HeaderBackend<B>,
+	{
+		let net_event_stream = network.event_stream("finality-notifications-net");
+		let sync_event_stream = sync.event_stream("finality-notifications-sync");
+		let finality_notifications = client.finality_notification_stream();
+
+		FinalityNotificationsHandler {
+			protocol_name: self.protocol_name,
+			network,
+			sync,
+			client,
+			net_event_stream: net_event_stream.fuse(),
+			sync_event_stream: sync_event_stream.fuse(),
+			finality_notifications: finality_notifications.fuse(),
+			peers: HashSet::new(),
+		}
+	}
+}
+
+/// Handler of the finality notification protocol. Call [`FinalityNotificationsHandler::run`] to
+/// start the processing.
+pub struct FinalityNotificationsHandler<B: BlockT, N, Client> {
+	protocol_name: ProtocolName,
+	/// Network service to use to send messages and manage peers.
+	network: N,
+	/// Syncing service, told about the blocks finalized by peers.
+	sync: Arc<SyncingService<B>>,
+	client: Arc<Client>,
+	/// Stream of networking events.
+	net_event_stream: stream::Fuse<Pin<Box<dyn Stream<Item = Event> + Send>>>,
+	/// Receiver for syncing-related events.
+	sync_event_stream: stream::Fuse<Pin<Box<dyn Stream<Item = SyncEvent> + Send>>>,
+	/// Blocks finalized locally.
+	finality_notifications: stream::Fuse<FinalityNotifications<B>>,
+	/// Peers with an open substream.
+	peers: HashSet<PeerId>,
+}
+
+impl<B, N, Client> FinalityNotificationsHandler<B, N, Client>
+where
+	B: BlockT + 'static,
+	N: NetworkPeers + NetworkEventStream + NetworkNotification,
+	Client: BlockchainEvents<B> + BlockBackend<B> + HeaderBackend<B>,
+{
+	/// Turns the [`FinalityNotificationsHandler`] into a future that should run forever and not
+	/// be interrupted.
+	pub async fn run(mut self) {
+		loop {
+			futures::select! {
+				notification = self.finality_notifications.next() => {
+					if let Some(notification) = notification {
+						let number = *notification.header.number();
+						let message = self.block_finalized(notification.hash, number).encode();
+						for peer in &self.peers {
+							self.network.write_notification(
+								*peer,
+								self.protocol_name.clone(),
+								message.clone(),
+							);
+						}
+					} else {
+						// The client has seemingly closed. Closing as well.
+						return
+					}
+				},
+				network_event = self.net_event_stream.next() => {
+					if let Some(network_event) = network_event {
+						self.handle_network_event(network_event);
+					} else {
+						// Networking has seemingly closed. Closing as well.
+						return
+					}
+				},
+				sync_event = self.sync_event_stream.next() => {
+					if let Some(sync_event) = sync_event {
+						self.handle_sync_event(sync_event);
+					} else {
+						// Syncing has seemingly closed. Closing as well.
+						return
+					}
+				},
+			}
+		}
+	}
+
+	/// Notification that the block `hash` was finalized locally.
+	fn block_finalized(
+		&self,
+		hash: B::Hash,
+		number: NumberFor<B>,
+	) -> BlockFinalized<B::Hash, NumberFor<B>> {
+		let has_justification = self.client.justifications(hash).ok().flatten().is_some();
+		BlockFinalized { hash, number, has_justification }
+	}
+
+	fn handle_sync_event(&mut self, event: SyncEvent) {
+		match event {
+			SyncEvent::PeerConnected(remote) => {
+				let addr = iter::once(multiaddr::Protocol::P2p(remote.into()))
+					.collect::<multiaddr::Multiaddr>();
+				let result = self.network.add_peers_to_reserved_set(
+					self.protocol_name.clone(),
+					iter::once(addr).collect(),
+				);
+				if let Err(err) = result {
+					log::error!(target: LOG_TARGET, "Add reserved peer failed: {}", err);
+				}
+			},
+			SyncEvent::PeerDisconnected(remote) => {
+				let result = self.network.remove_peers_from_reserved_set(
+					self.protocol_name.clone(),
+					iter::once(remote).collect(),
+				);
+				if let Err(err) = result {
+					log::error!(target: LOG_TARGET, "Remove reserved peer failed: {}", err);
+				}
+			},
+		}
+	}
+
+	fn handle_network_event(&mut self, event: Event) {
+		match event {
+			Event::NotificationStreamOpened { remote, protocol, .. }
+				if protocol == self.protocol_name =>
+			{
+				self.peers.insert(remote);
+				// Tell the new peer about the last block finalized so far.
+				let info = self.client.info();
+				let message = self.block_finalized(info.finalized_hash, info.finalized_number);
+				self.network
+					.write_notification(remote, self.protocol_name.clone(), message.encode());
+			},
+			Event::NotificationStreamClosed { remote, protocol }
+				if protocol == self.protocol_name =>
+			{
+				self.peers.remove(&remote);
+			},
+			Event::NotificationsReceived { remote, messages } => {
+				for (protocol, message) in messages {
+					if protocol != self.protocol_name {
+						continue
+					}
+
+					match BlockFinalized::<B::Hash, NumberFor<B>>::decode(&mut message.as_ref()) {
+						Ok(BlockFinalized { hash, number, has_justification }) => {
+							trace!(
+								target: LOG_TARGET,
+								"{remote} finalized #{number} ({hash}), justification: \
+								{has_justification}",
+							);
+							self.sync.on_peer_finalized(remote, hash, number, has_justification);
+						},
+						Err(error) => {
+							debug!(
+								target: LOG_TARGET,
+								"Failed to decode finality notification from {remote}: {error}",
+							);
+							self.network.report_peer(remote, rep::BAD_NOTIFICATION);
+						},
+					}
+				}
+			},
+			// Not our concern.
+			Event::Dht(_) |
+			Event::NotificationStreamOpened { .. } |
+			Event::NotificationStreamClosed { .. } => {},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn protocol_name_is_versioned() {
+		let genesis_hash = [0xab; 4];
+		assert_eq!(generate_protocol_name(genesis_hash, None), "/abababab/finality/1");
+		assert_eq!(generate_protocol_name(genesis_hash, Some("fork")), "/abababab/fork/finality/1");
+	}
+
+	#[test]
+	fn block_finalized_fits_the_notification_size() {
+		let message =
+			BlockFinalized { hash: [0xff; 32], number: u64::MAX, has_justification: true };
+		let encoded = message.encode();
+		assert!(encoded.len() as u64 <= MAX_FINALITY_NOTIFICATION_SIZE);
+		assert_eq!(BlockFinalized::decode(&mut encoded.as_ref()).unwrap(), message);
+	}
+}
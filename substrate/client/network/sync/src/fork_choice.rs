@@ -0,0 +1,41 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`ForkChoice`] decides which announced fork `ChainSync` downloads first.
+
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use std::cmp::Ordering;
+
+/// Fork choice rule consulted by `ChainSync` when picking the announced fork to download.
+///
+/// Chains whose fork choice isn't the longest chain, e.g. weight-based ones, can register their
+/// own rule with [`SyncingService::set_fork_choice`](crate::SyncingService::set_fork_choice).
+pub trait ForkChoice<B: BlockT>: Send {
+	/// Compare the announced heads `a` and `b`. [`Ordering::Greater`] means `a` is preferred.
+	fn compare(&self, a: &B::Header, b: &B::Header) -> Ordering;
+}
+
+/// Prefers the fork with the highest block number. This is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LongestChain;
+
+impl<B: BlockT> ForkChoice<B> for LongestChain {
+	fn compare(&self, a: &B::Header, b: &B::Header) -> Ordering {
+		a.number().cmp(b.number())
+	}
+}
@@ -0,0 +1,147 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Support for running several `SyncingEngine`s in one process, e.g. the ones of a parachain and
+//! of its embedded relay chain node.
+//!
+//! Engines given an instance name with
+//! [`NetworkConfiguration::sync_instance_name`](sc_network::config::NetworkConfiguration) register
+//! their metrics with a `sync_instance` label, so that they can share a registry, and log in a span
+//! prefixing their log lines with the name. Their protocol names are prefixed with the name, see
+//! [`instance_protocol_name`]. Their services can be controlled together through
+//! [`CombinedSyncingService`](crate::service::combined::CombinedSyncingService).
+
+use prometheus_endpoint::{
+	prometheus::{
+		core::{Collector, Desc},
+		proto::MetricFamily,
+	},
+	PrometheusError, Registry,
+};
+use sc_network::ProtocolName;
+use std::{collections::HashMap, iter};
+
+/// Label distinguishing the metrics of the sync instances sharing a registry.
+const INSTANCE_LABEL: &str = "sync_instance";
+
+/// Name of the span whose `name` field prefixes log lines, see
+/// `sc_tracing::logging::PREFIX_LOG_SPAN`.
+const PREFIX_LOG_SPAN: &str = "substrate-log-prefix";
+
+/// Registry for the metrics of the sync instance `name`, forwarding them to `registry` with the
+/// instance label added.
+///
+/// Fails if another instance of the same name is registered with `registry`.
+pub(crate) fn instance_registry(
+	registry: &Registry,
+	name: &str,
+) -> Result<Registry, PrometheusError> {
+	let labels = iter::once((INSTANCE_LABEL.to_string(), name.to_string())).collect();
+	let instance = Registry::new_custom(None, Some(labels))?;
+	registry.register(Box::new(InstanceMetrics {
+		desc: Desc::new(
+			"substrate_sync_instance".into(),
+			"Sync instance sharing the registry".into(),
+			Vec::new(),
+			iter::once((INSTANCE_LABEL.to_string(), name.to_string())).collect::<HashMap<_, _>>(),
+		)?,
+		registry: instance.clone(),
+	}))?;
+
+	Ok(instance)
+}
+
+/// Protocol `name` of the sync instance `instance`, prefixed with the instance name so that the
+/// instances sharing a network don't receive each other's requests and notifications.
+///
+/// The block announce, block, state and warp sync protocols of an instance must all be named with
+/// it, the names are left as they are without an instance.
+pub fn instance_protocol_name(instance: Option<&str>, name: ProtocolName) -> ProtocolName {
+	match instance {
+		Some(instance) => format!("/{instance}{name}").into(),
+		None => name,
+	}
+}
+
+/// Span prefixing the log lines of the sync instance `name`.
+pub(crate) fn log_span(name: &str) -> tracing::Span {
+	tracing::info_span!(PREFIX_LOG_SPAN, name = name)
+}
+
+/// Collects the metrics of one sync instance.
+#[derive(Clone)]
+struct InstanceMetrics {
+	/// Identifies the instance, the collected metrics are described by the instance registry.
+	desc: Desc,
+	registry: Registry,
+}
+
+impl Collector for InstanceMetrics {
+	fn desc(&self) -> Vec<&Desc> {
+		vec![&self.desc]
+	}
+
+	fn collect(&self) -> Vec<MetricFamily> {
+		self.registry.gather()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prometheus_endpoint::{register, Counter, U64};
+
+	#[test]
+	fn instances_share_a_registry() {
+		let registry = Registry::new();
+		for (name, count) in [("relay", 1), ("para", 2)] {
+			let instance = instance_registry(&registry, name).unwrap();
+			let counter = register(
+				Counter::<U64>::new("substrate_sync_test", "Test counter").unwrap(),
+				&instance,
+			)
+			.unwrap();
+			counter.inc_by(count);
+		}
+		assert!(instance_registry(&registry, "relay").is_err());
+
+		let families = registry.gather();
+		let family = families.iter().find(|family| family.get_name() == "substrate_sync_test");
+		let mut counts = family
+			.unwrap()
+			.get_metric()
+			.iter()
+			.map(|metric| {
+				(metric.get_label()[0].get_value().to_string(), metric.get_counter().get_value())
+			})
+			.collect::<Vec<_>>();
+		counts.sort_by(|a, b| a.0.cmp(&b.0));
+
+		assert_eq!(counts, vec![("para".to_string(), 2.0), ("relay".to_string(), 1.0)]);
+	}
+
+	#[test]
+	fn protocol_names_are_namespaced() {
+		let name = ProtocolName::from("/abcd/sync/2");
+		assert_eq!(instance_protocol_name(None, name.clone()), name);
+		assert_eq!(
+			instance_protocol_name(Some("para"), name),
+			ProtocolName::from("/para/abcd/sync/2"),
+		);
+	}
+}
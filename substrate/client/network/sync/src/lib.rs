@@ -30,9 +30,20 @@
 
 use crate::{
 	blocks::BlockCollection,
-	schema::v1::StateResponse,
-	state::StateSync,
-	warp::{WarpProofImportResult, WarpSync, WarpSyncConfig},
+	engine_state::{ForkTargetState, GapSyncState, SyncEngineState, SyncPeerState},
+	fork_choice::{ForkChoice, LongestChain},
+	peer_selection::{PeerSelection, Random},
+	rate_limit::RateLimiter,
+	request_retry::{RequestFailureKind, RequestRetries},
+	response_times::ResponseTimes,
+	schema::v1::{StateRequest, StateResponse},
+	state::{PreparedImport, StateSync, VerifiedState},
+	state_request_handler::{
+		STATE_MODES_LEGACY, STATE_MODE_DELTA, STATE_MODE_ENTRIES, STATE_MODE_PROOF,
+	},
+	sync_snapshot::SyncSnapshot,
+	verification_pool::VerificationPool,
+	warp::{WarpProofImportResult, WarpSync, WarpSyncConfig, WarpSyncTargetError},
 };
 
 use codec::Encode;
@@ -45,20 +56,23 @@ use sc_client_api::{BlockBackend, ProofProvider};
 use sc_consensus::{
 	import_queue::ImportQueueService, BlockImportError, BlockImportStatus, IncomingBlock,
 };
-use sc_network::types::ProtocolName;
+use sc_network::{config::RequestRetryPolicy, types::ProtocolName};
+use schnellru::{ByLength, LruMap};
 use sc_network_common::sync::{
 	message::{
 		BlockAnnounce, BlockAttributes, BlockData, BlockRequest, BlockResponse, Direction,
 		FromBlock,
 	},
 	warp::{EncodedProof, WarpProofRequest, WarpSyncPhase, WarpSyncProgress},
-	BadPeer, ChainSync as ChainSyncT, Metrics, OnBlockData, OnBlockJustification, OnStateData,
-	OpaqueStateRequest, OpaqueStateResponse, PeerInfo, SyncMode, SyncState, SyncStatus,
+	ActiveSyncStrategy, BadPeer, ChainSync as ChainSyncT, GapSyncProgress, Metrics,
+	NetworkBestEstimate, OnBlockData, OnBlockJustification, OnStateData, OpaqueStateRequest,
+	OpaqueStateResponse, PeerInfo, PeerRequestType, SyncMode, SyncState, SyncStatus,
 };
 use sp_arithmetic::traits::Saturating;
 use sp_blockchain::{Error as ClientError, HeaderBackend, HeaderMetadata};
 use sp_consensus::{BlockOrigin, BlockStatus};
 use sp_runtime::{
+	generic::{BlockId, SignedBlock},
 	traits::{
 		Block as BlockT, CheckedSub, Hash, HashingFor, Header as HeaderT, NumberFor, One,
 		SaturatedConversion, Zero,
@@ -68,38 +82,67 @@ use sp_runtime::{
 
 use std::{
 	collections::{HashMap, HashSet},
-	ops::Range,
+	ops::{Range, RangeInclusive},
+	path::PathBuf,
 	sync::Arc,
+	task::Poll,
+	time::{Duration, Instant},
 };
 
+pub use instance::instance_protocol_name;
 pub use service::chain_sync::SyncingService;
 
+mod block_announce_data_cache;
 mod block_announce_validator;
 mod extra_requests;
 mod futures_stream;
+mod instance;
 mod pending_responses;
+mod persisted_peers;
+mod rate_limit;
+mod request_retry;
+mod response_times;
 mod schema;
+mod sync_snapshot;
+mod throughput;
+mod verification_pool;
+mod warp_progress;
 
 pub mod block_relay_protocol;
 pub mod block_request_handler;
 pub mod blocks;
 pub mod engine;
+pub mod engine_state;
+pub mod finality_notifications;
+pub mod fork_choice;
 pub mod mock;
+pub mod peer_selection;
 pub mod service;
 pub mod state;
 pub mod state_request_handler;
 pub mod warp;
 pub mod warp_request_handler;
+pub mod warp_snapshot;
 
 /// Log target for this file.
 const LOG_TARGET: &'static str = "sync";
 
-/// Maximum blocks to store in the import queue.
+/// Number of blocks in the import queue past which no new blocks are requested, by default.
 const MAX_IMPORTING_BLOCKS: usize = 2048;
 
+/// Number of blocks in the import queue below which blocks are requested again after reaching
+/// [`MAX_IMPORTING_BLOCKS`], by default.
+const RESUME_IMPORTING_BLOCKS: usize = MAX_IMPORTING_BLOCKS / 2;
+
 /// Maximum blocks to download ahead of any gap.
 const MAX_DOWNLOAD_AHEAD: u32 = 2048;
 
+/// Number of block ranges of `max_blocks_per_request` blocks that fit in [`MAX_DOWNLOAD_AHEAD`]
+/// blocks, the most downloaded at once during major sync.
+fn max_parallel_ranges(max_blocks_per_request: u32) -> u32 {
+	(MAX_DOWNLOAD_AHEAD / max_blocks_per_request.max(1)).max(1)
+}
+
 /// Maximum blocks to look backwards. The gap is the difference between the highest block and the
 /// common block of a node.
 const MAX_BLOCKS_TO_LOOK_BACKWARDS: u32 = MAX_DOWNLOAD_AHEAD / 2;
@@ -107,6 +150,10 @@ const MAX_BLOCKS_TO_LOOK_BACKWARDS: u32 = MAX_DOWNLOAD_AHEAD / 2;
 /// Pick the state to sync as the latest finalized number minus this.
 const STATE_SYNC_FINALITY_THRESHOLD: u32 = 8;
 
+/// Download the state delta to a newer finalized block instead of importing blocks when the
+/// median of peer best blocks is more than this ahead of the downloaded state.
+const STATE_DELTA_SYNC_THRESHOLD: u32 = 64;
+
 /// We use a heuristic that with a high likelihood, by the time
 /// `MAJOR_SYNC_BLOCKS` have been imported we'll be on the same
 /// chain as (or at least closer to) the peer so we want to delay
@@ -117,9 +164,59 @@ const MAJOR_SYNC_BLOCKS: u8 = 5;
 /// Number of peers that need to be connected before warp sync is started.
 const MIN_PEERS_TO_START_WARP_SYNC: usize = 3;
 
+/// Number of peers a justification request is sent to at once, the first response is imported.
+const JUSTIFICATION_RACING_PEERS: usize = 3;
+
 /// Maximum blocks per response.
 pub(crate) const MAX_BLOCKS_IN_RESPONSE: usize = 128;
 
+/// Block responses received within this time let the number of blocks requested from the peer
+/// grow, slower or failed responses halve it.
+const BLOCK_RESPONSE_TARGET_TIME: Duration = Duration::from_secs(5);
+
+/// Number of blocks by which the blocks requested from a peer grow after a fast response.
+const BLOCKS_PER_REQUEST_INCREASE: u32 = 4;
+
+/// Peers serving fewer blocks per second on average are not assigned new block ranges while
+/// faster peers are available.
+const SLOW_PEER_BLOCKS_PER_SECOND: u64 = 16;
+
+/// Minimum number of blocks in a response for it to count towards the peer throughput. Smaller
+/// responses are dominated by latency.
+const MIN_THROUGHPUT_SAMPLE_BLOCKS: usize = 16;
+
+/// State responses served slower than this make us request smaller ones from the peer.
+const STATE_RESPONSE_TARGET_TIME: Duration = Duration::from_secs(10);
+
+/// Minimum size of the state responses requested from a peer.
+const MIN_STATE_RESPONSE_SIZE: u32 = 64 * 1024;
+
+/// Number of threads verifying state response proofs.
+const STATE_VERIFICATION_WORKERS: usize = 2;
+
+/// Number of state responses waiting for a verification thread, the responses received while the
+/// queue is full are verified right away.
+const STATE_VERIFICATION_QUEUE: usize = 2;
+
+/// Number of consecutive scheduling rounds a kind of request may go without a peer while it has
+/// outstanding work, before it is served ahead of the higher priorities.
+const MAX_STARVED_ROUNDS: u32 = 8;
+
+/// Number of requests of one kind in a row after which a peer is offered the other kinds of
+/// requests first.
+const MAX_CONSECUTIVE_REQUESTS: u32 = 4;
+
+/// Maximum number of blocks remembered per peer as having their justification held by the peer.
+const MAX_JUSTIFICATION_HINTS: usize = 32;
+
+/// Number of forks whose common ancestor with our chain is remembered for the peers connecting
+/// later on the same fork.
+const ANCESTRY_CACHE_SIZE: u32 = 1024;
+
+/// Weight of the peers whose best block changed recently in [`ChainSync::network_best_estimate`],
+/// relative to the peers whose best block is stale.
+const FRESH_PEER_WEIGHT: u64 = 2;
+
 mod rep {
 	use sc_network::ReputationChange as Rep;
 	/// Reputation change when a peer sent us a message that led to a
@@ -153,6 +250,13 @@ mod rep {
 
 	/// Peer response data does not have requested bits.
 	pub const BAD_RESPONSE: Rep = Rep::new(-(1 << 12), "Incomplete response");
+
+	/// Reputation change when a peer serves blocks too slowly.
+	pub const SLOW_PEER: Rep = Rep::new(-(1 << 8), "Slow block responses");
+
+	/// Reputation change when a peer serves another block than the best block it announced.
+	pub const ANNOUNCE_MISMATCH: Rep =
+		Rep::new(-(1 << 12), "Block response mismatches announcement");
 }
 
 enum AllowedRequests {
@@ -200,6 +304,37 @@ impl Default for AllowedRequests {
 	}
 }
 
+/// Kinds of outstanding work assigned to the available peers, highest priority first.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum RequestPriority {
+	/// Block ranges of the best chain, including the ancestry searches preceding them.
+	BestChain,
+	/// Justifications of already imported blocks.
+	Justification,
+	/// Fork targets announced by peers.
+	Fork,
+	/// Block history below the warp or state sync target.
+	Gap,
+}
+
+impl RequestPriority {
+	const ALL: [Self; 4] = [Self::BestChain, Self::Justification, Self::Fork, Self::Gap];
+}
+
+/// Whether a peer that was last sent `run.1` requests of the kind `run.0` in a row is skipped
+/// for `kind` while other kinds of requests in `with_work` may use it.
+///
+/// Peers are only skipped on a first pass over the kinds of requests, so they are still sent
+/// requests of `kind` if no other kind has a request for them.
+fn defers_request(
+	run: Option<(RequestPriority, u32)>,
+	kind: RequestPriority,
+	with_work: &[RequestPriority],
+) -> bool {
+	matches!(run, Some((last, count)) if last == kind && count >= MAX_CONSECUTIVE_REQUESTS) &&
+		with_work.iter().any(|other| *other != kind)
+}
+
 struct SyncingMetrics {
 	pub import_queue_blocks_submitted: Counter<U64>,
 	pub import_queue_justifications_submitted: Counter<U64>,
@@ -228,8 +363,13 @@ impl SyncingMetrics {
 
 struct GapSync<B: BlockT> {
 	blocks: BlockCollection<B>,
+	start: NumberFor<B>,
 	best_queued_number: NumberFor<B>,
 	target: NumberFor<B>,
+	/// Hashes of gap blocks known from the parent hash of their child, which is either the block
+	/// above the gap or a downloaded gap block. Ranges ending at such a block are requested by
+	/// hash and checked to link to the child.
+	anchors: HashMap<NumberFor<B>, B::Hash>,
 }
 
 /// An event used to notify [`engine::SyncingEngine`] if we want to perform a block request
@@ -256,34 +396,108 @@ pub struct ChainSync<B: BlockT, Client> {
 	mode: SyncMode,
 	/// Any extra justification requests.
 	extra_justifications: ExtraRequests<B>,
+	/// Peers whose justification requests were cancelled and whose responses can be dropped.
+	cancelled_requests: Vec<PeerId>,
 	/// A set of hashes of blocks that are being downloaded or have been
 	/// downloaded and are queued for import.
 	queue_blocks: HashSet<B::Hash>,
 	/// Fork sync targets.
 	fork_targets: HashMap<B::Hash, ForkTarget<B>>,
+	/// Fork targets restored from an earlier session, waiting for a peer to download them from.
+	/// Only the peers in their `peers` are used, or any peer if empty.
+	restored_fork_targets: HashMap<B::Hash, ForkTarget<B>>,
+	/// Number and hash of the common ancestor found by ancestry searches, by the best block of
+	/// the peer searched with.
+	ancestry_cache: LruMap<B::Hash, (NumberFor<B>, B::Hash)>,
+	/// Decides which fork target is downloaded first.
+	fork_choice: Box<dyn ForkChoice<B>>,
+	/// Decides which peers are assigned block ranges first.
+	peer_selection: Box<dyn PeerSelection<B>>,
 	/// A set of peers for which there might be potential block requests
 	allowed_requests: AllowedRequests,
-	/// Maximum number of peers to ask the same blocks in parallel.
+	/// Number of consecutive scheduling rounds each kind of request had outstanding work but no
+	/// peer left to serve it.
+	starved_requests: HashMap<RequestPriority, u32>,
+	/// Consecutive failed requests of the peers, which back off before being sent requests again.
+	request_retries: RequestRetries,
+	/// Recent round-trip times of the requests sent to the peers.
+	response_times: ResponseTimes,
+	/// Maximum number of peers to ask the same blocks in parallel, if `racing_downloads`.
 	max_parallel_downloads: u32,
+	/// Number of distinct block ranges downloaded at once during major sync, between one and the
+	/// number of full responses that fit in `MAX_DOWNLOAD_AHEAD` blocks. Grows while the import
+	/// queue is starved and shrinks when block requests fail.
+	parallel_downloads: u32,
+	/// Whether the same blocks may be requested from several peers at once, the first response
+	/// winning. Otherwise a range of blocks is only requested again once its request has failed.
+	racing_downloads: bool,
+	/// Number of blocks below the target of major sync from which the remaining blocks are
+	/// requested from two peers at once, even without `racing_downloads`. Disabled if zero.
+	tail_race_blocks: u32,
 	/// Maximum blocks per request.
 	max_blocks_per_request: u32,
 	/// Total number of downloaded blocks.
 	downloaded_blocks: usize,
 	/// State sync in progress, if any.
 	state_sync: Option<StateSync<B, Client>>,
+	/// Block whose state was downloaded, if the state delta to a newer block is to be
+	/// downloaded next.
+	state_delta_base: Option<B::Hash>,
 	/// Warp sync in progress, if any.
 	warp_sync: Option<WarpSync<B, Client>>,
+	/// Verifies the proofs of state responses off the sync thread, by peer and target block.
+	///
+	/// Spawned on the first proof and dropped once the state is downloaded.
+	state_verification: Option<VerificationPool<(PeerId, B::Hash, Option<VerifiedState>)>>,
 	/// Warp sync configuration.
 	///
 	/// Will be `None` after `self.warp_sync` is `Some(_)`.
 	warp_sync_config: Option<WarpSyncConfig<B>>,
 	/// A temporary storage for warp sync target block until warp sync is initialized.
 	warp_sync_target_block_header: Option<B::Header>,
+	/// Where verified warp proofs are saved, so a restarted warp sync resumes from them.
+	warp_progress_path: Option<PathBuf>,
 	/// Enable importing existing blocks. This is used used after the state download to
 	/// catch up to the latest state while re-importing blocks.
 	import_existing: bool,
 	/// Gap download process.
 	gap_sync: Option<GapSync<B>>,
+	/// Maximum number of gap block requests in flight at once, unlimited if `None`.
+	max_gap_requests: Option<usize>,
+	/// Number of blocks below the finalized block whose state is downloaded by state and warp
+	/// sync.
+	sync_target_offset: u32,
+	/// Whether the block gap reported by the client is downloaded.
+	download_history: bool,
+	/// Limits the rate of gap block requests, unlimited if `None`.
+	gap_request_limiter: Option<RateLimiter>,
+	/// Number of blocks behind the peers past which we are major syncing.
+	major_sync_threshold: u32,
+	/// Number of blocks below `major_sync_threshold` we must catch up to before major sync ends.
+	major_sync_hysteresis: u32,
+	/// Whether we were major syncing when last checked by [`ChainSync::update_major_sync`].
+	major_syncing: bool,
+	/// Number of blocks in the import queue past which no new blocks are requested.
+	import_queue_high_water_mark: usize,
+	/// Number of blocks in the import queue below which blocks are requested again.
+	import_queue_low_water_mark: usize,
+	/// Whether block requests are paused until the import queue drains below
+	/// `import_queue_low_water_mark`.
+	import_queue_full: bool,
+	/// Block past which no new blocks are downloaded.
+	sync_target: Option<BlockId<B>>,
+	/// Whether no blocks above the finalized block known from the peers and the explicit sync
+	/// requests are downloaded.
+	finalized_only: bool,
+	/// Highest block explicitly requested with [`ChainSyncT::set_sync_fork_request`], e.g. by the
+	/// finality gadget after receiving a finality proof for it.
+	requested_finalized_number: NumberFor<B>,
+	/// Peers that requests may be sent to, all peers if `None`.
+	request_peers: Option<HashSet<PeerId>>,
+	/// Peers connected only to request justifications from, sent no other requests.
+	justification_peers: HashSet<PeerId>,
+	/// Blocks that are never requested or imported, nor are their descendants.
+	banned_blocks: HashSet<B::Hash>,
 	/// Handle for communicating with `NetworkService`
 	network_service: service::network::NetworkServiceHandle,
 	/// Protocol name used for block announcements
@@ -309,9 +523,69 @@ pub struct PeerSync<B: BlockT> {
 	/// The state of syncing this peer is in for us, generally categories
 	/// into `Available` or "busy" with something as defined by `PeerSyncState`.
 	pub state: PeerSyncState<B>,
+	/// Maximum number of blocks requested from this peer at once, adapted to how fast the peer
+	/// serves block requests.
+	pub max_blocks_per_request: u32,
+	/// Moving average of the blocks per second served by this peer, `None` until a large enough
+	/// block response was received.
+	pub throughput: Option<u64>,
+	/// Moving average of the time this peer took to serve block requests, `None` until it
+	/// served one.
+	pub latency: Option<Duration>,
+	/// Number of the last finalized block the peer reported when connecting, if it did.
+	pub finalized_number: Option<NumberFor<B>>,
+	/// Size of the state responses requested from this peer, adapted to how fast the peer serves
+	/// them and capped by the maximum it advertises. `None` lets the peer pick its maximum.
+	pub max_state_response_size: Option<u32>,
+	/// Request modes the peer advertised serving in its last state response, see
+	/// [`STATE_MODE_DELTA`]. State requests are only sent in modes the peer serves.
+	pub state_modes: u32,
+	/// Whether the best block of the peer didn't change for a while, so it is likely to be out of
+	/// date. Such peers are kept off the critical path like slow peers.
+	pub best_stale: bool,
+	/// Kind of the last requests sent to this peer and how many were sent in a row.
+	request_run: Option<(RequestPriority, u32)>,
+	/// Blocks whose justification the peer announced holding, most recent last, at most
+	/// [`MAX_JUSTIFICATION_HINTS`].
+	pub justification_hints: Vec<B::Hash>,
 }
 
 impl<B: BlockT> PeerSync<B> {
+	/// Whether the peer serves blocks slower than [`SLOW_PEER_BLOCKS_PER_SECOND`] on average.
+	fn is_slow(&self) -> bool {
+		self.throughput
+			.map_or(false, |throughput| throughput < SLOW_PEER_BLOCKS_PER_SECOND)
+	}
+
+	/// Adapt `request` to the request modes and the response size negotiated with the peer,
+	/// requesting a proof if the peer doesn't serve raw entries.
+	///
+	/// Returns `false` if the peer doesn't serve the request.
+	fn adapt_state_request(&self, request: &mut StateRequest) -> bool {
+		let serves = |mode| self.state_modes & mode != 0;
+		let served = if !request.base_block.is_empty() {
+			serves(STATE_MODE_DELTA)
+		} else if request.no_proof && serves(STATE_MODE_ENTRIES) {
+			true
+		} else {
+			// Proven entries are imported as well.
+			request.no_proof = false;
+			serves(STATE_MODE_PROOF)
+		};
+		if served {
+			request.max_response_size = self.max_state_response_size.unwrap_or(0);
+		}
+		served
+	}
+
+	/// Record that a request of the given `kind` was sent to the peer.
+	fn note_request(&mut self, kind: RequestPriority) {
+		self.request_run = match self.request_run {
+			Some((last, count)) if last == kind => Some((kind, count.saturating_add(1))),
+			_ => Some((kind, 1)),
+		};
+	}
+
 	/// Update the `common_number` iff `new_common > common_number`.
 	fn update_common_number(&mut self, new_common: NumberFor<B>) {
 		if self.common_number < new_common {
@@ -330,6 +604,7 @@ impl<B: BlockT> PeerSync<B> {
 struct ForkTarget<B: BlockT> {
 	number: NumberFor<B>,
 	parent_hash: Option<B::Hash>,
+	header: Option<B::Header>,
 	peers: HashSet<PeerId>,
 }
 
@@ -391,10 +666,16 @@ where
 			median_seen.and_then(|median| (median > self.best_queued_number).then_some(median));
 		let sync_state = if let Some(target) = median_seen {
 			// A chain is classified as downloading if the provided best block is
-			// more than `MAJOR_SYNC_BLOCKS` behind the best block or as importing
-			// if the same can be said about queued blocks.
+			// more than `major_sync_threshold` behind the best block or as importing
+			// if the same can be said about queued blocks. Once major syncing, it stays
+			// so until we are within the threshold minus the hysteresis.
 			let best_block = self.client.info().best_number;
-			if target > best_block && target - best_block > MAJOR_SYNC_BLOCKS.into() {
+			let threshold = if self.major_syncing {
+				self.major_sync_threshold.saturating_sub(self.major_sync_hysteresis)
+			} else {
+				self.major_sync_threshold
+			};
+			if target > best_block && target - best_block > threshold.into() {
 				// If target is not queued, we're downloading, otherwise importing.
 				if target > self.best_queued_number {
 					SyncState::Downloading { target }
@@ -431,6 +712,18 @@ where
 			queued_blocks: self.queue_blocks.len() as u32,
 			state_sync: self.state_sync.as_ref().map(|s| s.progress()),
 			warp_sync: warp_sync_progress,
+			strategy: match self.mode {
+				SyncMode::Full => ActiveSyncStrategy::Full,
+				SyncMode::LightState { .. } => ActiveSyncStrategy::State,
+				SyncMode::Warp => ActiveSyncStrategy::Warp,
+			},
+			gap_sync: self.gap_sync.as_ref().map(|gap_sync| GapSyncProgress {
+				start: gap_sync.start,
+				end: gap_sync.target,
+				best_queued: gap_sync.best_queued_number,
+			}),
+			num_fork_targets: self.fork_targets.len() as u32,
+			throughput: None,
 		}
 	}
 
@@ -455,122 +748,7 @@ where
 		best_hash: B::Hash,
 		best_number: NumberFor<B>,
 	) -> Result<Option<BlockRequest<B>>, BadPeer> {
-		// There is nothing sync can get from the node that has no blockchain data.
-		match self.block_status(&best_hash) {
-			Err(e) => {
-				debug!(target:LOG_TARGET, "Error reading blockchain: {e}");
-				Err(BadPeer(who, rep::BLOCKCHAIN_READ_ERROR))
-			},
-			Ok(BlockStatus::KnownBad) => {
-				info!("💔 New peer with known bad best block {} ({}).", best_hash, best_number);
-				Err(BadPeer(who, rep::BAD_BLOCK))
-			},
-			Ok(BlockStatus::Unknown) => {
-				if best_number.is_zero() {
-					info!("💔 New peer with unknown genesis hash {} ({}).", best_hash, best_number);
-					return Err(BadPeer(who, rep::GENESIS_MISMATCH))
-				}
-
-				// If there are more than `MAJOR_SYNC_BLOCKS` in the import queue then we have
-				// enough to do in the import queue that it's not worth kicking off
-				// an ancestor search, which is what we do in the next match case below.
-				if self.queue_blocks.len() > MAJOR_SYNC_BLOCKS.into() {
-					debug!(
-						target:LOG_TARGET,
-						"New peer with unknown best hash {} ({}), assuming common block.",
-						self.best_queued_hash,
-						self.best_queued_number
-					);
-					self.peers.insert(
-						who,
-						PeerSync {
-							peer_id: who,
-							common_number: self.best_queued_number,
-							best_hash,
-							best_number,
-							state: PeerSyncState::Available,
-						},
-					);
-					return Ok(None)
-				}
-
-				// If we are at genesis, just start downloading.
-				let (state, req) = if self.best_queued_number.is_zero() {
-					debug!(
-						target:LOG_TARGET,
-						"New peer with best hash {best_hash} ({best_number}).",
-					);
-
-					(PeerSyncState::Available, None)
-				} else {
-					let common_best = std::cmp::min(self.best_queued_number, best_number);
-
-					debug!(
-						target:LOG_TARGET,
-						"New peer with unknown best hash {} ({}), searching for common ancestor.",
-						best_hash,
-						best_number
-					);
-
-					(
-						PeerSyncState::AncestorSearch {
-							current: common_best,
-							start: self.best_queued_number,
-							state: AncestorSearchState::ExponentialBackoff(One::one()),
-						},
-						Some(ancestry_request::<B>(common_best)),
-					)
-				};
-
-				self.allowed_requests.add(&who);
-				self.peers.insert(
-					who,
-					PeerSync {
-						peer_id: who,
-						common_number: Zero::zero(),
-						best_hash,
-						best_number,
-						state,
-					},
-				);
-
-				if let SyncMode::Warp = self.mode {
-					if self.peers.len() >= MIN_PEERS_TO_START_WARP_SYNC && self.warp_sync.is_none()
-					{
-						log::debug!(target: LOG_TARGET, "Starting warp state sync.");
-
-						if let Some(config) = self.warp_sync_config.take() {
-							let mut warp_sync = WarpSync::new(self.client.clone(), config);
-							if let Some(header) = self.warp_sync_target_block_header.take() {
-								warp_sync.set_target_block(header);
-							}
-							self.warp_sync = Some(warp_sync);
-						}
-					}
-				}
-				Ok(req)
-			},
-			Ok(BlockStatus::Queued) |
-			Ok(BlockStatus::InChainWithState) |
-			Ok(BlockStatus::InChainPruned) => {
-				debug!(
-					target: LOG_TARGET,
-					"New peer with known best hash {best_hash} ({best_number}).",
-				);
-				self.peers.insert(
-					who,
-					PeerSync {
-						peer_id: who,
-						common_number: std::cmp::min(self.best_queued_number, best_number),
-						best_hash,
-						best_number,
-						state: PeerSyncState::Available,
-					},
-				);
-				self.allowed_requests.add(&who);
-				Ok(None)
-			},
-		}
+		self.new_peer_with_finalized(who, best_hash, best_number, None)
 	}
 
 	fn update_chain_info(&mut self, best_hash: &B::Hash, best_number: NumberFor<B>) {
@@ -620,6 +798,12 @@ where
 			return
 		}
 
+		if self.banned_blocks.contains(hash) {
+			debug!(target: LOG_TARGET, "Refusing to sync banned hash {hash:?}");
+			return
+		}
+
+		self.requested_finalized_number = self.requested_finalized_number.max(number);
 		trace!(target: LOG_TARGET, "Downloading requested old fork {hash:?}");
 		for peer_id in &peers {
 			if let Some(peer) = self.peers.get_mut(peer_id) {
@@ -637,7 +821,12 @@ where
 
 		self.fork_targets
 			.entry(*hash)
-			.or_insert_with(|| ForkTarget { number, peers: Default::default(), parent_hash: None })
+			.or_insert_with(|| ForkTarget {
+				number,
+				parent_hash: None,
+				header: None,
+				peers: Default::default(),
+			})
 			.peers
 			.extend(peers);
 	}
@@ -650,6 +839,19 @@ where
 	) -> Result<OnBlockData<B>, BadPeer> {
 		self.downloaded_blocks += response.blocks.len();
 		let mut gap = false;
+		// Another peer may have served the same blocks first, e.g. after peer churn or when racing.
+		let already_known = !response.blocks.is_empty() &&
+			response.blocks.iter().all(|block| self.is_known(&block.hash));
+		if let Some(block) =
+			response.blocks.iter().find(|block| is_banned_block(&self.banned_blocks, block))
+		{
+			debug!(
+				target: LOG_TARGET,
+				"Peer {who} served banned block {} or its child",
+				block.hash,
+			);
+			return Err(BadPeer(*who, rep::BAD_BLOCK))
+		}
 		let new_blocks: Vec<IncomingBlock<B>> = if let Some(peer) = self.peers.get_mut(who) {
 			let mut blocks = response.blocks;
 			if request.as_ref().map_or(false, |r| r.direction == Direction::Descending) {
@@ -665,7 +867,36 @@ where
 						if let Some(start_block) =
 							validate_blocks::<B>(&blocks, who, Some(request))?
 						{
-							self.blocks.insert(start_block, blocks, *who);
+							// The block at the height of the peer's best block must be the one it
+							// announced. It may have switched forks since, so the peer is only
+							// penalized and its response dropped.
+							if let Some(block) = blocks.iter().find(|block| {
+								block.hash != peer.best_hash &&
+									block.header.as_ref().map_or(false, |header| {
+										*header.number() == peer.best_number
+									})
+							}) {
+								debug!(
+									target: LOG_TARGET,
+									"Block #{} {} from {} mismatches its announced best block {}",
+									peer.best_number,
+									block.hash,
+									who,
+									peer.best_hash,
+								);
+								self.network_service.report_peer(*who, rep::ANNOUNCE_MISMATCH);
+								return Ok(OnBlockData::Continue)
+							}
+							if already_known {
+								trace!(
+									target: LOG_TARGET,
+									"Discarding late response from {who}, blocks from #{start_block} \
+									are already queued or imported",
+								);
+							} else {
+								self.blocks.insert(start_block, blocks, *who);
+								self.cancel_raced_downloads(who, start_block);
+							}
 						}
 						self.ready_blocks()
 					},
@@ -676,6 +907,7 @@ where
 							if let Some(start_block) =
 								validate_blocks::<B>(&blocks, who, Some(request))?
 							{
+								link_gap_blocks(&mut gap_sync.anchors, start_block, &blocks, who)?;
 								gap_sync.blocks.insert(start_block, blocks, *who);
 							}
 							gap = true;
@@ -790,9 +1022,12 @@ where
 							);
 							return Err(BadPeer(*who, rep::GENESIS_MISMATCH))
 						}
-						if let Some((next_state, next_num)) =
-							handle_ancestor_search_state(state, *current, matching_hash.is_some())
-						{
+						if let Some((next_state, next_num)) = handle_ancestor_search_state(
+							state,
+							*current,
+							matching_hash.is_some(),
+							peer.common_number,
+						) {
 							peer.state = PeerSyncState::AncestorSearch {
 								current: next_num,
 								start: *start,
@@ -812,6 +1047,10 @@ where
 								matching_hash,
 								peer.common_number,
 							);
+							if let Ok(Some(hash)) = self.client.hash(peer.common_number) {
+								self.ancestry_cache
+									.insert(peer.best_hash, (peer.common_number, hash));
+							}
 							if peer.common_number < peer.best_number &&
 								peer.best_number < self.best_queued_number
 							{
@@ -826,6 +1065,7 @@ where
 									.or_insert_with(|| ForkTarget {
 										number: peer.best_number,
 										parent_hash: None,
+										header: None,
 										peers: Default::default(),
 									})
 									.peers
@@ -838,27 +1078,16 @@ where
 					PeerSyncState::DownloadingWarpTargetBlock => {
 						peer.state = PeerSyncState::Available;
 						if let Some(warp_sync) = &mut self.warp_sync {
-							if blocks.len() == 1 {
-								validate_blocks::<B>(&blocks, who, Some(request))?;
-								match warp_sync.import_target_block(
-									blocks.pop().expect("`blocks` len checked above."),
-								) {
-									warp::TargetBlockImportResult::Success =>
-										return Ok(OnBlockData::Continue),
-									warp::TargetBlockImportResult::BadResponse =>
-										return Err(BadPeer(*who, rep::VERIFICATION_FAIL)),
-								}
-							} else if blocks.is_empty() {
+							if blocks.is_empty() {
 								debug!(target: LOG_TARGET, "Empty block response from {who}");
 								return Err(BadPeer(*who, rep::NO_BLOCK))
-							} else {
-								debug!(
-									target: LOG_TARGET,
-									"Too many blocks ({}) in warp target block response from {}",
-									blocks.len(),
-									who,
-								);
-								return Err(BadPeer(*who, rep::NOT_REQUESTED))
+							}
+							validate_blocks::<B>(&blocks, who, Some(request))?;
+							match warp_sync.import_target_block(blocks) {
+								warp::TargetBlockImportResult::Success =>
+									return Ok(OnBlockData::Continue),
+								warp::TargetBlockImportResult::BadResponse =>
+									return Err(BadPeer(*who, rep::VERIFICATION_FAIL)),
 							}
 						} else {
 							debug!(
@@ -955,6 +1184,14 @@ where
 			if let Some((peer, hash, number, j)) =
 				self.extra_justifications.on_response(who, justification)
 			{
+				// Free the peers that were asked for the same justification.
+				for peer_id in self.extra_justifications.take_cancelled_requests() {
+					if let Some(peer) = self.peers.get_mut(&peer_id) {
+						peer.state = PeerSyncState::Available;
+					}
+					self.cancelled_requests.push(peer_id);
+				}
+				self.allowed_requests.set_all();
 				return Ok(OnBlockJustification::Import { peer, hash, number, justifications: j })
 			}
 		}
@@ -975,6 +1212,34 @@ where
 			is_descendent_of(&**client, base, block)
 		});
 
+		let mut pruned = HashSet::new();
+		self.fork_targets.retain(|hash, target| {
+			if target.number <= number {
+				trace!(
+					target: LOG_TARGET,
+					"Removed finalized fork target {:?} (#{})",
+					hash,
+					target.number,
+				);
+				pruned.insert(*hash);
+				return false
+			}
+			true
+		});
+		self.restored_fork_targets.retain(|_, target| target.number > number);
+		if !pruned.is_empty() {
+			// The forks can no longer be imported, free the peers downloading them.
+			for (peer_id, peer) in self.peers.iter_mut() {
+				if let PeerSyncState::DownloadingStale(hash) = peer.state {
+					if pruned.contains(&hash) {
+						peer.state = PeerSyncState::Available;
+						self.cancelled_requests.push(*peer_id);
+					}
+				}
+			}
+			self.allowed_requests.set_all();
+		}
+
 		if let SyncMode::LightState { skip_proofs, .. } = &self.mode {
 			if self.state_sync.is_none() && !self.peers.is_empty() && self.queue_blocks.is_empty() {
 				// Finalized a recent block.
@@ -982,18 +1247,32 @@ where
 				heads.sort();
 				let median = heads[heads.len() / 2];
 				if number + STATE_SYNC_FINALITY_THRESHOLD.saturated_into() >= median {
-					if let Ok(Some(header)) = self.client.header(*hash) {
-						log::debug!(
-							target: LOG_TARGET,
-							"Starting state sync for #{number} ({hash})",
-						);
-						self.state_sync = Some(StateSync::new(
-							self.client.clone(),
-							header,
-							None,
-							None,
-							*skip_proofs,
-						));
+					// The state of genesis is known already.
+					let offset = number
+						.saturating_sub(One::one())
+						.min(self.sync_target_offset.saturated_into());
+					let target = if offset.is_zero() {
+						Some(*hash)
+					} else {
+						self.client.hash(number - offset).ok().flatten()
+					};
+					if let Some(header) =
+						target.and_then(|hash| self.client.header(hash).ok().flatten())
+					{
+						let (number, hash) = (*header.number(), header.hash());
+						self.state_sync = Some(if let Some(base) = self.state_delta_base {
+							log::debug!(
+								target: LOG_TARGET,
+								"Starting state delta sync from {base} to #{number} ({hash})",
+							);
+							StateSync::new_delta(self.client.clone(), base, header, None, None)
+						} else {
+							log::debug!(
+								target: LOG_TARGET,
+								"Starting state sync for #{number} ({hash})",
+							);
+							StateSync::new(self.client.clone(), header, None, None, *skip_proofs)
+						});
 						self.allowed_requests.set_all();
 					}
 				}
@@ -1016,6 +1295,10 @@ where
 	) {
 		let number = *announce.header.number();
 		let hash = announce.header.hash();
+		if self.is_banned_block(&hash, announce.header.parent_hash()) {
+			debug!(target: LOG_TARGET, "Ignoring announcement of banned block {hash:?} from {who}");
+			return
+		}
 		let parent_status =
 			self.block_status(announce.header.parent_hash()).unwrap_or(BlockStatus::Unknown);
 		let known_parent = parent_status != BlockStatus::Unknown;
@@ -1086,6 +1369,7 @@ where
 				.or_insert_with(|| ForkTarget {
 					number,
 					parent_hash: Some(*announce.header.parent_hash()),
+					header: Some(announce.header.clone()),
 					peers: Default::default(),
 				})
 				.peers
@@ -1100,6 +1384,8 @@ where
 		}
 		self.peers.remove(who);
 		self.extra_justifications.peer_disconnected(who);
+		self.request_retries.peer_disconnected(who);
+		self.response_times.peer_disconnected(who);
 		self.allowed_requests.set_all();
 		self.fork_targets.retain(|_, target| {
 			target.peers.remove(who);
@@ -1153,21 +1439,52 @@ where
 			blocks: BlockCollection::new(),
 			best_queued_hash: Default::default(),
 			best_queued_number: Zero::zero(),
-			extra_justifications: ExtraRequests::new("justification"),
+			extra_justifications: ExtraRequests::new("justification")
+				.with_max_racing_peers(JUSTIFICATION_RACING_PEERS),
+			cancelled_requests: Vec::new(),
 			mode,
 			queue_blocks: Default::default(),
 			fork_targets: Default::default(),
+			restored_fork_targets: Default::default(),
+			ancestry_cache: LruMap::new(ByLength::new(ANCESTRY_CACHE_SIZE)),
+			fork_choice: Box::new(LongestChain),
+			peer_selection: Box::new(Random),
 			allowed_requests: Default::default(),
+			starved_requests: Default::default(),
+			request_retries: Default::default(),
+			response_times: Default::default(),
 			max_parallel_downloads,
+			parallel_downloads: max_parallel_ranges(max_blocks_per_request),
+			racing_downloads: false,
+			tail_race_blocks: 0,
 			max_blocks_per_request,
 			downloaded_blocks: 0,
 			state_sync: None,
+			state_delta_base: None,
+			state_verification: None,
 			warp_sync: None,
 			import_existing: false,
 			gap_sync: None,
+			max_gap_requests: None,
+			sync_target_offset: 0,
+			download_history: false,
+			gap_request_limiter: None,
+			major_sync_threshold: MAJOR_SYNC_BLOCKS.into(),
+			major_sync_hysteresis: 0,
+			major_syncing: false,
+			import_queue_high_water_mark: MAX_IMPORTING_BLOCKS,
+			import_queue_low_water_mark: RESUME_IMPORTING_BLOCKS,
+			import_queue_full: false,
+			sync_target: None,
+			finalized_only: false,
+			requested_finalized_number: Zero::zero(),
+			request_peers: None,
+			justification_peers: HashSet::new(),
+			banned_blocks: HashSet::new(),
 			network_service,
 			warp_sync_config,
 			warp_sync_target_block_header: None,
+			warp_progress_path: None,
 			block_announce_protocol_name,
 			import_queue,
 			metrics: if let Some(r) = &metrics_registry {
@@ -1190,7 +1507,7 @@ where
 		Ok(sync)
 	}
 
-	/// Returns the median seen block number.
+	/// Returns the median seen block number, capped by the sync target.
 	fn median_seen(&self) -> Option<NumberFor<B>> {
 		let mut best_seens = self.peers.values().map(|p| p.best_number).collect::<Vec<_>>();
 
@@ -1200,1263 +1517,3956 @@ where
 			let middle = best_seens.len() / 2;
 
 			// Not the "perfect median" when we have an even number of peers.
-			Some(*best_seens.select_nth_unstable(middle).1)
+			let median = *best_seens.select_nth_unstable(middle).1;
+			Some(self.sync_target_number().map_or(median, |target| median.min(target)))
 		}
 	}
 
-	fn required_block_attributes(&self) -> BlockAttributes {
-		match self.mode {
-			SyncMode::Full =>
-				BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION | BlockAttributes::BODY,
-			SyncMode::LightState { storage_chain_mode: false, .. } | SyncMode::Warp =>
-				BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION | BlockAttributes::BODY,
-			SyncMode::LightState { storage_chain_mode: true, .. } =>
-				BlockAttributes::HEADER |
-					BlockAttributes::JUSTIFICATION |
-					BlockAttributes::INDEXED_BODY,
-		}
+	/// Estimate the best block of the network from the best blocks of the peers, `None` without
+	/// peers.
+	///
+	/// Unlike the best block of any single peer, a minority of peers reporting far off best
+	/// blocks doesn't move the median.
+	pub fn network_best_estimate(&self) -> Option<NetworkBestEstimate<NumberFor<B>>> {
+		let mut weighted = self
+			.peers
+			.values()
+			.map(|peer| (peer.best_number, if peer.best_stale { 1 } else { FRESH_PEER_WEIGHT }))
+			.collect::<Vec<_>>();
+		weighted.sort_unstable_by_key(|(number, _)| *number);
+		let (highest, _) = *weighted.last()?;
+		let total_weight = weighted.iter().map(|(_, weight)| weight).sum::<u64>();
+
+		// Lowest best block with at least `numerator / denominator` of the weight at or below it.
+		let quantile = |numerator: u64, denominator: u64| {
+			let mut weight_below = 0;
+			weighted
+				.iter()
+				.find(|(_, weight)| {
+					weight_below += weight;
+					weight_below * denominator >= total_weight * numerator
+				})
+				.map_or(highest, |(number, _)| *number)
+		};
+
+		Some(NetworkBestEstimate {
+			median: quantile(1, 2),
+			lower_quartile: quantile(1, 4),
+			upper_quartile: quantile(3, 4),
+			num_peers: self.peers.len() as u32,
+			num_fresh_peers: self.peers.values().filter(|peer| !peer.best_stale).count() as u32,
+		})
 	}
 
-	fn skip_execution(&self) -> bool {
-		match self.mode {
-			SyncMode::Full => false,
-			SyncMode::LightState { .. } => true,
-			SyncMode::Warp => true,
-		}
+	/// Number of blocks our best block is behind the median best block of the peers, `None`
+	/// without peers.
+	pub fn blocks_behind(&self) -> Option<NumberFor<B>> {
+		self.median_seen()
+			.map(|median| median.saturating_sub(self.client.info().best_number))
 	}
 
-	fn validate_and_queue_blocks(
-		&mut self,
-		mut new_blocks: Vec<IncomingBlock<B>>,
-		gap: bool,
-	) -> OnBlockData<B> {
-		let orig_len = new_blocks.len();
-		new_blocks.retain(|b| !self.queue_blocks.contains(&b.hash));
-		if new_blocks.len() != orig_len {
-			debug!(
-				target: LOG_TARGET,
-				"Ignoring {} blocks that are already queued",
-				orig_len - new_blocks.len(),
-			);
-		}
+	/// Consider sync major once we are more than `threshold` blocks behind the peers, and until
+	/// we are within `threshold - hysteresis` blocks of them.
+	pub fn set_major_sync_thresholds(&mut self, threshold: u32, hysteresis: u32) {
+		self.major_sync_threshold = threshold;
+		self.major_sync_hysteresis = hysteresis;
+	}
 
-		let origin = if !gap && !self.status().state.is_major_syncing() {
-			BlockOrigin::NetworkBroadcast
-		} else {
-			BlockOrigin::NetworkInitialSync
+	/// Stop requesting blocks once more than `high` blocks are queued for import, until fewer than
+	/// `low` are.
+	pub fn set_import_queue_water_marks(&mut self, high: usize, low: usize) {
+		self.import_queue_high_water_mark = high;
+		self.import_queue_low_water_mark = low.min(high);
+	}
+
+	/// Save verified warp proofs at `path` and resume warp sync from the ones saved there.
+	pub fn set_warp_progress_path(&mut self, path: PathBuf) {
+		self.warp_progress_path = Some(path);
+	}
+
+	/// Download the state of the block `offset` blocks below the finalized block picked as the
+	/// state or warp sync target, trading recency for a lower risk of the peers pruning the state
+	/// while it is downloaded.
+	///
+	/// Applies to the state and warp syncs started after the call. Zero by default.
+	pub fn set_sync_target_offset(&mut self, offset: u32) {
+		self.sync_target_offset = offset;
+	}
+
+	/// Only download the blocks up to the finalized block, as known from the finalized blocks the
+	/// peers report and the blocks explicitly requested by the finality gadget. The unfinalized
+	/// tips of the chain are ignored. Disabled by default.
+	pub fn set_finalized_only(&mut self, finalized_only: bool) {
+		self.finalized_only = finalized_only;
+	}
+
+	/// Request the remaining blocks from two peers at once when fewer than `blocks` blocks are left
+	/// to reach the target of major sync, so that a single slow peer can't hold up its end. The
+	/// slower response is cancelled. Disabled if zero, the default.
+	pub fn set_tail_race_blocks(&mut self, blocks: u32) {
+		self.tail_race_blocks = blocks;
+	}
+
+	/// Allow requesting the same blocks from up to `max_parallel_downloads` peers at once, trading
+	/// bandwidth for latency. Disabled by default.
+	pub fn set_racing_downloads(&mut self, racing_downloads: bool) {
+		self.racing_downloads = racing_downloads;
+	}
+
+	/// Record whether we are currently major syncing, which decides the threshold applied by
+	/// [`ChainSync::status`] until the next call.
+	pub fn update_major_sync(&mut self) -> bool {
+		self.major_syncing = self.status().state.is_major_syncing();
+		self.major_syncing
+	}
+
+	/// Stop downloading new blocks past `target`. Sync is reported as idle once it is reached.
+	///
+	/// A target given by hash only limits the download once its number is known, that is once
+	/// the block is imported or announced by a peer.
+	pub fn set_sync_target(&mut self, target: BlockId<B>) {
+		debug!(target: LOG_TARGET, "Setting sync target to {target:?}");
+		// The number of a target given by hash is looked up in the client only once, it is
+		// noted when the block is queued for import otherwise.
+		let target = match target {
+			BlockId::Hash(hash) => match self.client.number(hash) {
+				Ok(Some(number)) => BlockId::Number(number),
+				_ => target,
+			},
+			target => target,
 		};
+		self.sync_target = Some(target);
+		self.allowed_requests.set_all();
+	}
 
-		if let Some((h, n)) = new_blocks
-			.last()
-			.and_then(|b| b.header.as_ref().map(|h| (&b.hash, *h.number())))
-		{
-			trace!(
-				target:LOG_TARGET,
-				"Accepted {} blocks ({:?}) with origin {:?}",
-				new_blocks.len(),
-				h,
-				origin,
-			);
-			self.on_block_queued(h, n)
-		}
-		self.queue_blocks.extend(new_blocks.iter().map(|b| b.hash));
-		OnBlockData::Import(origin, new_blocks)
+	/// Maximum number of blocks currently requested from `peer_id` at once.
+	pub fn peer_max_blocks_per_request(&self, peer_id: &PeerId) -> Option<u32> {
+		self.peers.get(peer_id).map(|peer| peer.max_blocks_per_request)
 	}
 
-	fn update_peer_common_number(&mut self, peer_id: &PeerId, new_common: NumberFor<B>) {
-		if let Some(peer) = self.peers.get_mut(peer_id) {
-			peer.update_common_number(new_common);
-		}
+	/// Number of distinct block ranges currently allowed to be downloaded at once during major
+	/// sync.
+	pub fn parallel_downloads(&self) -> u32 {
+		self.parallel_downloads
 	}
 
-	/// Called when a block has been queued for import.
+	/// Adapt the number of blocks requested from `peer_id` to the time it took to serve a block
+	/// request, `None` if the request failed. Fast responses grow the number additively up to
+	/// the configured maximum, slow or failed ones halve it.
 	///
-	/// Updates our internal state for best queued block and then goes
-	/// through all peers to update our view of their state as well.
-	fn on_block_queued(&mut self, hash: &B::Hash, number: NumberFor<B>) {
-		if self.fork_targets.remove(hash).is_some() {
-			trace!(target: LOG_TARGET, "Completed fork sync {hash:?}");
-		}
-		if let Some(gap_sync) = &mut self.gap_sync {
-			if number > gap_sync.best_queued_number && number <= gap_sync.target {
-				gap_sync.best_queued_number = number;
-			}
+	/// The number of ranges downloaded in parallel grows by one on fast responses while the import
+	/// queue holds less than a full response, and shrinks by one on failed requests.
+	///
+	/// Returns the new number of blocks requested from the peer.
+	pub fn on_block_response_time(
+		&mut self,
+		peer_id: &PeerId,
+		elapsed: Option<Duration>,
+	) -> Option<u32> {
+		let fast = elapsed.map_or(false, |elapsed| elapsed <= BLOCK_RESPONSE_TARGET_TIME);
+		let starved = self.queue_blocks.len() < self.max_blocks_per_request as usize;
+		if elapsed.is_none() {
+			self.parallel_downloads = self.parallel_downloads.saturating_sub(1).max(1);
+		} else if fast && starved {
+			self.parallel_downloads = (self.parallel_downloads + 1)
+				.min(max_parallel_ranges(self.max_blocks_per_request));
 		}
-		if number > self.best_queued_number {
-			self.best_queued_number = number;
-			self.best_queued_hash = *hash;
-			// Update common blocks
-			for (n, peer) in self.peers.iter_mut() {
-				if let PeerSyncState::AncestorSearch { .. } = peer.state {
-					// Wait for ancestry search to complete first.
-					continue
-				}
-				let new_common_number =
-					if peer.best_number >= number { number } else { peer.best_number };
-				trace!(
-					target: LOG_TARGET,
-					"Updating peer {} info, ours={}, common={}->{}, their best={}",
-					n,
-					number,
-					peer.common_number,
-					new_common_number,
-					peer.best_number,
-				);
-				peer.common_number = new_common_number;
-			}
+
+		let max_blocks_per_request = self.max_blocks_per_request;
+		let peer = self.peers.get_mut(peer_id)?;
+		if let Some(elapsed) = elapsed {
+			peer.latency =
+				Some(peer.latency.map_or(elapsed, |average| (average * 7 + elapsed) / 8));
 		}
-		self.allowed_requests.set_all();
+		peer.max_blocks_per_request = if fast {
+			peer.max_blocks_per_request
+				.saturating_add(BLOCKS_PER_REQUEST_INCREASE)
+				.min(max_blocks_per_request)
+		} else {
+			(peer.max_blocks_per_request / 2).max(1)
+		};
+		Some(peer.max_blocks_per_request)
 	}
 
-	/// Restart the sync process. This will reset all pending block requests and return an iterator
-	/// of new block requests to make to peers. Peers that were downloading finality data (i.e.
-	/// their state was `DownloadingJustification`) are unaffected and will stay in the same state.
-	fn restart(&mut self) -> impl Iterator<Item = Result<BlockRequestEvent<B>, BadPeer>> + '_ {
-		self.blocks.clear();
-		if let Err(e) = self.reset_sync_start_point() {
-			warn!(target: LOG_TARGET, "💔  Unable to restart sync: {e}");
+	/// Adapt the size of the state responses requested from `peer_id` to the time it took to serve
+	/// the last one, given the maximum size `max_response_size` it advertised in it, zero if it
+	/// didn't.
+	///
+	/// Returns the size of the next requests, `None` if the peer picks it.
+	pub fn on_state_response_time(
+		&mut self,
+		peer_id: &PeerId,
+		elapsed: Duration,
+		max_response_size: u32,
+	) -> Option<u32> {
+		let peer = self.peers.get_mut(peer_id)?;
+		if max_response_size == 0 {
+			// The peer doesn't take the requested size into account.
+			return None
 		}
-		self.allowed_requests.set_all();
-		debug!(
-			target: LOG_TARGET,
-			"Restarted with {} ({})",
-			self.best_queued_number,
-			self.best_queued_hash,
-		);
-		let old_peers = std::mem::take(&mut self.peers);
 
-		old_peers.into_iter().filter_map(move |(peer_id, mut p)| {
-			// peers that were downloading justifications
-			// should be kept in that state.
-			if let PeerSyncState::DownloadingJustification(_) = p.state {
-				// We make sure our commmon number is at least something we have.
-				p.common_number = self.best_queued_number;
-				self.peers.insert(peer_id, p);
-				return None
-			}
-
-			// handle peers that were in other states.
-			match self.new_peer(peer_id, p.best_hash, p.best_number) {
-				// since the request is not a justification, remove it from pending responses
-				Ok(None) => Some(Ok(BlockRequestEvent::RemoveStale { peer_id })),
-				// update the request if the new one is available
-				Ok(Some(request)) => Some(Ok(BlockRequestEvent::SendRequest { peer_id, request })),
-				// this implies that we need to drop pending response from the peer
-				Err(e) => Some(Err(e)),
-			}
-		})
+		let size = peer.max_state_response_size.unwrap_or(max_response_size);
+		let size = if elapsed <= STATE_RESPONSE_TARGET_TIME {
+			size.saturating_mul(2)
+		} else {
+			size / 2
+		};
+		peer.max_state_response_size =
+			Some(size.min(max_response_size).max(MIN_STATE_RESPONSE_SIZE.min(max_response_size)));
+		peer.max_state_response_size
 	}
 
-	/// Find a block to start sync from. If we sync with state, that's the latest block we have
-	/// state for.
-	fn reset_sync_start_point(&mut self) -> Result<(), ClientError> {
-		let info = self.client.info();
-		if matches!(self.mode, SyncMode::LightState { .. }) && info.finalized_state.is_some() {
-			warn!(
-				target: LOG_TARGET,
-				"Can't use fast sync mode with a partially synced database. Reverting to full sync mode."
-			);
-			self.mode = SyncMode::Full;
-		}
-		if matches!(self.mode, SyncMode::Warp) && info.finalized_state.is_some() {
-			warn!(
-				target: LOG_TARGET,
-				"Can't use warp sync mode with a partially synced database. Reverting to full sync mode."
-			);
-			self.mode = SyncMode::Full;
+	/// Record the request modes `peer_id` advertised serving in a state response, see
+	/// [`STATE_MODE_DELTA`], zero if it didn't.
+	pub fn on_state_response_modes(&mut self, peer_id: &PeerId, modes: u32) {
+		if let Some(peer) = self.peers.get_mut(peer_id) {
+			peer.state_modes = if modes == 0 { STATE_MODES_LEGACY } else { modes };
 		}
-		self.import_existing = false;
-		self.best_queued_hash = info.best_hash;
-		self.best_queued_number = info.best_number;
+	}
 
-		if self.mode == SyncMode::Full &&
-			self.client.block_status(info.best_hash)? != BlockStatus::InChainWithState
-		{
-			self.import_existing = true;
-			// Latest state is missing, start with the last finalized state or genesis instead.
-			if let Some((hash, number)) = info.finalized_state {
-				debug!(target: LOG_TARGET, "Starting from finalized state #{number}");
-				self.best_queued_hash = hash;
-				self.best_queued_number = number;
-			} else {
-				debug!(target: LOG_TARGET, "Restarting from genesis");
-				self.best_queued_hash = Default::default();
-				self.best_queued_number = Zero::zero();
-			}
+	/// Update the moving average of blocks per second served by `peer_id` with a response of
+	/// `blocks` received `elapsed` after the request was sent.
+	///
+	/// Peers that become slow are reported, and are only assigned new block ranges when no
+	/// faster peer is connected. They are still used for fork and justification requests.
+	pub fn on_block_throughput(&mut self, peer_id: &PeerId, blocks: usize, elapsed: Duration) {
+		if blocks < MIN_THROUGHPUT_SAMPLE_BLOCKS {
+			return
 		}
+		let peer = match self.peers.get_mut(peer_id) {
+			Some(peer) => peer,
+			None => return,
+		};
 
-		if let Some((start, end)) = info.block_gap {
-			debug!(target: LOG_TARGET, "Starting gap sync #{start} - #{end}");
-			self.gap_sync = Some(GapSync {
-				best_queued_number: start - One::one(),
-				target: end,
-				blocks: BlockCollection::new(),
-			});
+		let millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX).max(1);
+		let sample = (blocks as u64).saturating_mul(1000) / millis;
+		let was_slow = peer.is_slow();
+		peer.throughput =
+			Some(peer.throughput.map_or(sample, |average| (average * 7 + sample) / 8));
+
+		if peer.is_slow() && !was_slow {
+			debug!(
+				target: LOG_TARGET,
+				"Peer {peer_id} is slow ({:?} blocks/s), deprioritizing it.",
+				peer.throughput,
+			);
+			self.network_service.report_peer(*peer_id, rep::SLOW_PEER);
 		}
-		trace!(
-			target: LOG_TARGET,
-			"Restarted sync at #{} ({:?})",
-			self.best_queued_number,
-			self.best_queued_hash,
-		);
-		Ok(())
 	}
 
-	/// What is the status of the block corresponding to the given hash?
-	fn block_status(&self, hash: &B::Hash) -> Result<BlockStatus, ClientError> {
-		if self.queue_blocks.contains(hash) {
-			return Ok(BlockStatus::Queued)
-		}
-		self.client.block_status(*hash)
+	/// Moving average of the blocks per second served by `peer_id`, if measured.
+	pub fn peer_throughput(&self, peer_id: &PeerId) -> Option<u64> {
+		self.peers.get(peer_id).and_then(|peer| peer.throughput)
 	}
 
-	/// Is the block corresponding to the given hash known?
-	fn is_known(&self, hash: &B::Hash) -> bool {
-		self.block_status(hash).ok().map_or(false, |s| s != BlockStatus::Unknown)
+	/// Use `throughput` measured in an earlier session for `peer_id` until it is measured again.
+	pub fn restore_peer_throughput(&mut self, peer_id: &PeerId, throughput: u64) {
+		if let Some(peer) = self.peers.get_mut(peer_id) {
+			peer.throughput.get_or_insert(throughput);
+		}
 	}
 
-	/// Is any peer downloading the given hash?
-	fn is_already_downloading(&self, hash: &B::Hash) -> bool {
-		self.peers
-			.iter()
-			.any(|(_, p)| p.state == PeerSyncState::DownloadingStale(*hash))
+	/// Use `fork_choice` to decide which announced fork is downloaded first.
+	pub fn set_fork_choice(&mut self, fork_choice: Box<dyn ForkChoice<B>>) {
+		self.fork_choice = fork_choice;
 	}
 
-	/// Is the peer know to the sync state machine?
-	pub fn is_peer_known(&self, peer_id: &PeerId) -> bool {
-		self.peers.contains_key(peer_id)
+	/// Use `peer_selection` to decide which peers are assigned block ranges first.
+	pub fn set_peer_selection(&mut self, peer_selection: Box<dyn PeerSelection<B>>) {
+		self.peer_selection = peer_selection;
 	}
 
-	/// Get the set of downloaded blocks that are ready to be queued for import.
-	fn ready_blocks(&mut self) -> Vec<IncomingBlock<B>> {
-		self.blocks
-			.ready_blocks(self.best_queued_number + One::one())
+	/// Queue `blocks` read from a trusted local source, e.g. a block archive, for import as if they
+	/// were downloaded.
+	///
+	/// Blocks already queued are skipped. Returns the number of queued blocks.
+	pub fn import_offline_blocks(&mut self, blocks: Vec<SignedBlock<B>>) -> usize {
+		let blocks = blocks
 			.into_iter()
-			.map(|block_data| {
-				let justifications = block_data
-					.block
-					.justifications
-					.or_else(|| legacy_justification_mapping(block_data.block.justification));
-				IncomingBlock {
-					hash: block_data.block.hash,
-					header: block_data.block.header,
-					body: block_data.block.body,
-					indexed_body: block_data.block.indexed_body,
+			.filter_map(|SignedBlock { block, justifications }| {
+				let hash = block.hash();
+				if self.queue_blocks.contains(&hash) || self.banned_blocks.contains(&hash) {
+					return None
+				}
+				let (header, body) = block.deconstruct();
+				Some(IncomingBlock {
+					hash,
+					header: Some(header),
+					body: Some(body),
+					indexed_body: None,
 					justifications,
-					origin: block_data.origin,
+					origin: None,
 					allow_missing_state: true,
 					import_existing: self.import_existing,
 					skip_execution: self.skip_execution(),
 					state: None,
-				}
+				})
 			})
-			.collect()
-	}
+			.collect::<Vec<IncomingBlock<B>>>();
 
-	/// Set warp sync target block externally in case we skip warp proof downloading.
-	pub fn set_warp_sync_target_block(&mut self, header: B::Header) {
-		if let Some(ref mut warp_sync) = self.warp_sync {
-			warp_sync.set_target_block(header);
-		} else {
-			self.warp_sync_target_block_header = Some(header);
+		if let Some((hash, number)) =
+			blocks.last().and_then(|b| b.header.as_ref().map(|h| (b.hash, *h.number())))
+		{
+			debug!(target: LOG_TARGET, "Importing {} offline blocks up to #{number}", blocks.len());
+			self.on_block_queued(&hash, number);
 		}
-	}
-
-	/// Generate block request for downloading of the target block body during warp sync.
-	fn warp_target_block_request(&mut self) -> Option<(PeerId, BlockRequest<B>)> {
-		let sync = &self.warp_sync.as_ref()?;
+		self.queue_blocks.extend(blocks.iter().map(|b| b.hash));
 
-		if self.allowed_requests.is_empty() ||
-			sync.is_complete() ||
-			self.peers
-				.iter()
-				.any(|(_, peer)| peer.state == PeerSyncState::DownloadingWarpTargetBlock)
-		{
-			// Only one pending warp target block request is allowed.
-			return None
+		let queued = blocks.len();
+		if queued > 0 {
+			self.import_blocks(BlockOrigin::File, blocks);
 		}
+		queued
+	}
 
-		if let Some((target_number, request)) = sync.next_target_block_request() {
-			// Find a random peer that has a block with the target number.
-			for (id, peer) in self.peers.iter_mut() {
-				if peer.state.is_available() && peer.best_number >= target_number {
-					trace!(target: LOG_TARGET, "New warp target block request for {id}");
-					peer.state = PeerSyncState::DownloadingWarpTargetBlock;
-					self.allowed_requests.clear();
-					return Some((*id, request))
-				}
+	/// Handle a new connected peer, like [`ChainSyncT::new_peer`], given the last block it
+	/// reported as finalized in its handshake, if any.
+	///
+	/// The finalized block bounds the search for the common ancestor with the peer when we have
+	/// it too.
+	pub fn new_peer_with_finalized(
+		&mut self,
+		who: PeerId,
+		best_hash: B::Hash,
+		best_number: NumberFor<B>,
+		finalized: Option<(NumberFor<B>, B::Hash)>,
+	) -> Result<Option<BlockRequest<B>>, BadPeer> {
+		let req = self.add_peer(who, best_hash, best_number, finalized)?;
+
+		// Restored fork targets are downloaded from the first accepted peer that can serve them.
+		let restored = self
+			.restored_fork_targets
+			.iter()
+			.filter(|(_, target)| {
+				target.number <= best_number &&
+					(target.peers.is_empty() || target.peers.contains(&who))
+			})
+			.map(|(hash, _)| *hash)
+			.collect::<Vec<_>>();
+		for hash in restored {
+			if let Some(target) = self.restored_fork_targets.remove(&hash) {
+				self.fork_targets
+					.entry(hash)
+					.or_insert(ForkTarget { peers: HashSet::new(), ..target })
+					.peers
+					.insert(who);
 			}
 		}
 
-		None
+		Ok(req)
 	}
 
-	pub(crate) fn on_block_response(
+	/// Add a new connected peer to the sync state, see [`Self::new_peer_with_finalized`].
+	fn add_peer(
 		&mut self,
-		peer_id: PeerId,
-		request: BlockRequest<B>,
-		blocks: Vec<BlockData<B>>,
-	) -> Option<(PeerId, BlockRequest<B>)> {
-		let block_response = BlockResponse::<B> { id: request.id, blocks };
+		who: PeerId,
+		best_hash: B::Hash,
+		best_number: NumberFor<B>,
+		finalized: Option<(NumberFor<B>, B::Hash)>,
+	) -> Result<Option<BlockRequest<B>>, BadPeer> {
+		// There is nothing sync can get from the node that has no blockchain data.
+		match self.block_status(&best_hash) {
+			Err(e) => {
+				debug!(target:LOG_TARGET, "Error reading blockchain: {e}");
+				Err(BadPeer(who, rep::BLOCKCHAIN_READ_ERROR))
+			},
+			Ok(BlockStatus::KnownBad) => {
+				info!("💔 New peer with known bad best block {} ({}).", best_hash, best_number);
+				Err(BadPeer(who, rep::BAD_BLOCK))
+			},
+			Ok(BlockStatus::Unknown) => {
+				if best_number.is_zero() {
+					info!("💔 New peer with unknown genesis hash {} ({}).", best_hash, best_number);
+					return Err(BadPeer(who, rep::GENESIS_MISMATCH))
+				}
 
-		let blocks_range = || match (
-			block_response
-				.blocks
-				.first()
-				.and_then(|b| b.header.as_ref().map(|h| h.number())),
-			block_response.blocks.last().and_then(|b| b.header.as_ref().map(|h| h.number())),
-		) {
-			(Some(first), Some(last)) if first != last => format!(" ({}..{})", first, last),
-			(Some(first), Some(_)) => format!(" ({})", first),
-			_ => Default::default(),
-		};
-		trace!(
-			target: LOG_TARGET,
-			"BlockResponse {} from {} with {} blocks {}",
-			block_response.id,
-			peer_id,
-			block_response.blocks.len(),
-			blocks_range(),
-		);
+				// If there are more than `MAJOR_SYNC_BLOCKS` in the import queue then we have
+				// enough to do in the import queue that it's not worth kicking off
+				// an ancestor search, which is what we do in the next match case below.
+				if self.queue_blocks.len() > MAJOR_SYNC_BLOCKS.into() {
+					debug!(
+						target:LOG_TARGET,
+						"New peer with unknown best hash {} ({}), assuming common block.",
+						self.best_queued_hash,
+						self.best_queued_number
+					);
+					self.peers.insert(
+						who,
+						PeerSync {
+							peer_id: who,
+							common_number: self.best_queued_number,
+							best_hash,
+							best_number,
+							state: PeerSyncState::Available,
+							max_blocks_per_request: self.max_blocks_per_request,
+							throughput: None,
+							latency: None,
+							finalized_number: finalized.map(|(number, _)| number),
+							max_state_response_size: None,
+							state_modes: STATE_MODES_LEGACY,
+							best_stale: false,
+							request_run: None,
+							justification_hints: Vec::new(),
+						},
+					);
+					return Ok(None)
+				}
 
-		if request.fields == BlockAttributes::JUSTIFICATION {
-			match self.on_block_justification(peer_id, block_response) {
-				Ok(OnBlockJustification::Nothing) => None,
-				Ok(OnBlockJustification::Import { peer, hash, number, justifications }) => {
-					self.import_justifications(peer, hash, number, justifications);
-					None
-				},
-				Err(BadPeer(id, repu)) => {
-					self.network_service
-						.disconnect_peer(id, self.block_announce_protocol_name.clone());
-					self.network_service.report_peer(id, repu);
-					None
-				},
-			}
-		} else {
-			match self.on_block_data(&peer_id, Some(request), block_response) {
-				Ok(OnBlockData::Import(origin, blocks)) => {
-					self.import_blocks(origin, blocks);
-					None
-				},
-				Ok(OnBlockData::Request(peer, req)) => Some((peer, req)),
-				Ok(OnBlockData::Continue) => None,
-				Err(BadPeer(id, repu)) => {
-					self.network_service
-						.disconnect_peer(id, self.block_announce_protocol_name.clone());
-					self.network_service.report_peer(id, repu);
-					None
-				},
-			}
-		}
-	}
+				let common_best = std::cmp::min(self.best_queued_number, best_number);
+				// The blocks finalized by the peer that we have too are common to both chains.
+				let floor = finalized
+					.filter(|(number, hash)| {
+						*number <= common_best &&
+							self.client.hash(*number).ok().flatten() == Some(*hash)
+					})
+					.map_or_else(Zero::zero, |(number, _)| number);
+				// Peers on the same fork share the common ancestor, as long as it is in our chain.
+				let cached = self
+					.ancestry_cache
+					.get(&best_hash)
+					.copied()
+					.filter(|(number, hash)| self.client.hash(*number).ok().flatten() == Some(*hash))
+					.map(|(number, _)| number);
+
+				// If we are at genesis, just start downloading. There is no need for a common
+				// ancestor with peers we don't download from.
+				let (state, req, common_number) = if self.best_queued_number.is_zero() ||
+					!may_request(&self.request_peers, &self.justification_peers, &who)
+				{
+					debug!(
+						target:LOG_TARGET,
+						"New peer with best hash {best_hash} ({best_number}).",
+					);
 
-	pub fn on_state_response(&mut self, peer_id: PeerId, response: OpaqueStateResponse) {
-		match self.on_state_data(&peer_id, response) {
-			Ok(OnStateData::Import(origin, block)) => self.import_blocks(origin, vec![block]),
-			Ok(OnStateData::Continue) => {},
-			Err(BadPeer(id, repu)) => {
-				self.network_service
-					.disconnect_peer(id, self.block_announce_protocol_name.clone());
-				self.network_service.report_peer(id, repu);
+					(PeerSyncState::Available, None, Zero::zero())
+				} else if let Some(common_number) =
+					cached.or_else(|| (floor == common_best).then_some(floor))
+				{
+					debug!(
+						target:LOG_TARGET,
+						"New peer with unknown best hash {} ({}), common ancestor {} already known.",
+						best_hash,
+						best_number,
+						common_number,
+					);
+
+					(PeerSyncState::Available, None, common_number)
+				} else {
+					debug!(
+						target:LOG_TARGET,
+						"New peer with unknown best hash {} ({}), searching for common ancestor above {}.",
+						best_hash,
+						best_number,
+						floor,
+					);
+
+					(
+						PeerSyncState::AncestorSearch {
+							current: common_best,
+							start: self.best_queued_number,
+							state: AncestorSearchState::ExponentialBackoff(One::one()),
+						},
+						Some(ancestry_request::<B>(common_best)),
+						floor,
+					)
+				};
+
+				// Without a search, check right away whether the peer is on a stale fork.
+				if req.is_none() &&
+					!common_number.is_zero() &&
+					common_number < best_number &&
+					best_number < self.best_queued_number
+				{
+					trace!(target: LOG_TARGET, "Added fork target {best_hash} for {who}");
+					self.fork_targets
+						.entry(best_hash)
+						.or_insert_with(|| ForkTarget {
+							number: best_number,
+							parent_hash: None,
+							header: None,
+							peers: Default::default(),
+						})
+						.peers
+						.insert(who);
+				}
+
+				self.allowed_requests.add(&who);
+				self.peers.insert(
+					who,
+					PeerSync {
+						peer_id: who,
+						common_number,
+						best_hash,
+						best_number,
+						state,
+						max_blocks_per_request: self.max_blocks_per_request,
+						throughput: None,
+						latency: None,
+						finalized_number: finalized.map(|(number, _)| number),
+						max_state_response_size: None,
+						state_modes: STATE_MODES_LEGACY,
+						best_stale: false,
+						request_run: None,
+						justification_hints: Vec::new(),
+					},
+				);
+
+				if let SyncMode::Warp = self.mode {
+					if self.peers.len() >= MIN_PEERS_TO_START_WARP_SYNC && self.warp_sync.is_none()
+					{
+						log::debug!(target: LOG_TARGET, "Starting warp state sync.");
+
+						if let Some(config) = self.warp_sync_config.take() {
+							let mut warp_sync = WarpSync::new(self.client.clone(), config);
+							warp_sync.set_target_offset(self.sync_target_offset);
+							if let Some(path) = &self.warp_progress_path {
+								warp_sync.resume_from(path.clone());
+							}
+							if let Some(header) = self.warp_sync_target_block_header.take() {
+								if let Err(error) = warp_sync.set_target_block(header) {
+									warn!(
+										target: LOG_TARGET,
+										"Failed to set warp sync target block: {error}",
+									);
+								}
+							}
+							self.warp_sync = Some(warp_sync);
+						}
+					}
+				}
+				Ok(req)
+			},
+			Ok(BlockStatus::Queued) |
+			Ok(BlockStatus::InChainWithState) |
+			Ok(BlockStatus::InChainPruned) => {
+				debug!(
+					target: LOG_TARGET,
+					"New peer with known best hash {best_hash} ({best_number}).",
+				);
+				self.peers.insert(
+					who,
+					PeerSync {
+						peer_id: who,
+						common_number: std::cmp::min(self.best_queued_number, best_number),
+						best_hash,
+						best_number,
+						state: PeerSyncState::Available,
+						max_blocks_per_request: self.max_blocks_per_request,
+						throughput: None,
+						latency: None,
+						finalized_number: finalized.map(|(number, _)| number),
+						max_state_response_size: None,
+						state_modes: STATE_MODES_LEGACY,
+						best_stale: false,
+						request_run: None,
+						justification_hints: Vec::new(),
+					},
+				);
+				self.allowed_requests.add(&who);
+				Ok(None)
 			},
 		}
 	}
 
-	pub fn on_warp_sync_response(&mut self, peer_id: PeerId, response: EncodedProof) {
-		if let Err(BadPeer(id, repu)) = self.on_warp_sync_data(&peer_id, response) {
-			self.network_service
-				.disconnect_peer(id, self.block_announce_protocol_name.clone());
-			self.network_service.report_peer(id, repu);
+	/// Record whether the best block of `peer_id` didn't change for a while, keeping it off the
+	/// critical path of major sync while other peers are available.
+	pub fn set_peer_best_stale(&mut self, peer_id: &PeerId, stale: bool) {
+		let Some(peer) = self.peers.get_mut(peer_id) else { return };
+		if peer.best_stale && !stale {
+			self.allowed_requests.add(peer_id);
 		}
+		peer.best_stale = stale;
 	}
 
-	fn justification_requests(&mut self) -> Vec<(PeerId, BlockRequest<B>)> {
-		let peers = &mut self.peers;
-		let mut matcher = self.extra_justifications.matcher();
-		std::iter::from_fn(move || {
-			if let Some((peer, request)) = matcher.next(peers) {
-				peers
-					.get_mut(&peer)
-					.expect(
-						"`Matcher::next` guarantees the `PeerId` comes from the given peers; qed",
-					)
-					.state = PeerSyncState::DownloadingJustification(request.0);
-				let req = BlockRequest::<B> {
-					id: 0,
-					fields: BlockAttributes::JUSTIFICATION,
-					from: FromBlock::Hash(request.0),
-					direction: Direction::Ascending,
-					max: Some(1),
-				};
-				Some((peer, req))
-			} else {
-				None
+	/// Record that `peer_id` finalized the block `hash` and whether it holds its justification,
+	/// as announced over the finality notification protocol.
+	///
+	/// Pending justification requests for the block are sent to the peers that hold it first.
+	pub fn on_peer_finalized(
+		&mut self,
+		peer_id: &PeerId,
+		hash: B::Hash,
+		number: NumberFor<B>,
+		has_justification: bool,
+	) {
+		let Some(peer) = self.peers.get_mut(peer_id) else { return };
+		peer.finalized_number = peer.finalized_number.max(Some(number));
+		if has_justification && !peer.justification_hints.contains(&hash) {
+			if peer.justification_hints.len() == MAX_JUSTIFICATION_HINTS {
+				peer.justification_hints.remove(0);
 			}
-		})
-		.collect()
+			peer.justification_hints.push(hash);
+			self.allowed_requests.add(peer_id);
+		}
 	}
 
-	fn block_requests(&mut self) -> Vec<(PeerId, BlockRequest<B>)> {
-		if self.mode == SyncMode::Warp {
-			return self
-				.warp_target_block_request()
-				.map_or_else(|| Vec::new(), |req| Vec::from([req]))
+	/// Record the last finalized block number `peer_id` reported when connecting.
+	pub fn set_peer_finalized(&mut self, peer_id: &PeerId, number: NumberFor<B>) {
+		if let Some(peer) = self.peers.get_mut(peer_id) {
+			peer.finalized_number = Some(number);
 		}
+	}
 
-		if self.allowed_requests.is_empty() || self.state_sync.is_some() {
-			return Vec::new()
+	/// Sync progress to resume from after a restart.
+	pub(crate) fn snapshot(&self) -> SyncSnapshot<B> {
+		SyncSnapshot {
+			best_queued_hash: self.best_queued_hash,
+			best_queued_number: self.best_queued_number,
+			gap_sync: self.gap_sync.as_ref().map(|gap_sync| GapSyncProgress {
+				start: gap_sync.start,
+				end: gap_sync.target,
+				best_queued: gap_sync.best_queued_number,
+			}),
+			fork_targets: self
+				.fork_targets
+				.iter()
+				.chain(&self.restored_fork_targets)
+				.map(|(hash, target)| (*hash, target.number, target.parent_hash))
+				.collect(),
 		}
+	}
 
-		if self.queue_blocks.len() > MAX_IMPORTING_BLOCKS {
-			trace!(target: LOG_TARGET, "Too many blocks in the queue.");
-			return Vec::new()
+	/// Diagnostic snapshot of the sync state. Peer details only known to `SyncingEngine` are left
+	/// to it to fill in.
+	pub(crate) fn engine_state(&self) -> SyncEngineState {
+		let status = self.status();
+		SyncEngineState {
+			strategy: format!("{:?}", status.strategy),
+			state: format!("{:?}", status.state),
+			warp_sync: status.warp_sync.map(|progress| progress.phase.to_string()),
+			state_sync: status.state_sync.map(|progress| format!("{progress:?}")),
+			best_queued_number: self.best_queued_number.saturated_into(),
+			best_queued_hash: format!("{:?}", self.best_queued_hash),
+			queued_blocks: status.queued_blocks,
+			peers: self
+				.peers
+				.iter()
+				.map(|(peer_id, peer)| {
+					let state = SyncPeerState {
+						best_number: peer.best_number.saturated_into(),
+						best_hash: format!("{:?}", peer.best_hash),
+						common_number: peer.common_number.saturated_into(),
+						state: format!("{:?}", peer.state),
+						max_blocks_per_request: peer.max_blocks_per_request,
+						..Default::default()
+					};
+					(peer_id.to_base58(), state)
+				})
+				.collect(),
+			fork_targets: self
+				.fork_targets
+				.iter()
+				.map(|(hash, target)| ForkTargetState {
+					hash: format!("{hash:?}"),
+					number: target.number.saturated_into(),
+					peers: target.peers.iter().map(|peer_id| peer_id.to_base58()).collect(),
+				})
+				.collect(),
+			gap_sync: self.gap_sync.as_ref().map(|gap_sync| GapSyncState {
+				start: gap_sync.start.saturated_into(),
+				end: gap_sync.target.saturated_into(),
+				best_queued: gap_sync.best_queued_number.saturated_into(),
+			}),
+			..Default::default()
 		}
-		let is_major_syncing = self.status().state.is_major_syncing();
-		let attrs = self.required_block_attributes();
-		let blocks = &mut self.blocks;
-		let fork_targets = &mut self.fork_targets;
-		let last_finalized =
-			std::cmp::min(self.best_queued_number, self.client.info().finalized_number);
-		let best_queued = self.best_queued_number;
-		let client = &self.client;
-		let queue = &self.queue_blocks;
-		let allowed_requests = self.allowed_requests.take();
-		let max_parallel = if is_major_syncing { 1 } else { self.max_parallel_downloads };
-		let max_blocks_per_request = self.max_blocks_per_request;
-		let gap_sync = &mut self.gap_sync;
-		self.peers
-			.iter_mut()
-			.filter_map(move |(&id, peer)| {
-				if !peer.state.is_available() || !allowed_requests.contains(&id) {
-					return None
-				}
+	}
 
-				// If our best queued is more than `MAX_BLOCKS_TO_LOOK_BACKWARDS` blocks away from
-				// the common number, the peer best number is higher than our best queued and the
-				// common number is smaller than the last finalized block number, we should do an
-				// ancestor search to find a better common block. If the queue is full we wait till
-				// all blocks are imported though.
-				if best_queued.saturating_sub(peer.common_number) >
-					MAX_BLOCKS_TO_LOOK_BACKWARDS.into() &&
-					best_queued < peer.best_number &&
-					peer.common_number < last_finalized &&
-					queue.len() <= MAJOR_SYNC_BLOCKS.into()
-				{
-					trace!(
-						target: LOG_TARGET,
-						"Peer {:?} common block {} too far behind of our best {}. Starting ancestry search.",
-						id,
-						peer.common_number,
-						best_queued,
-					);
-					let current = std::cmp::min(peer.best_number, best_queued);
-					peer.state = PeerSyncState::AncestorSearch {
-						current,
-						start: best_queued,
-						state: AncestorSearchState::ExponentialBackoff(One::one()),
-					};
-					Some((id, ancestry_request::<B>(current)))
-				} else if let Some((range, req)) = peer_block_request(
-					&id,
-					peer,
-					blocks,
-					attrs,
-					max_parallel,
-					max_blocks_per_request,
-					last_finalized,
-					best_queued,
-				) {
-					peer.state = PeerSyncState::DownloadingNew(range.start);
-					trace!(
-						target: LOG_TARGET,
-						"New block request for {}, (best:{}, common:{}) {:?}",
-						id,
-						peer.best_number,
-						peer.common_number,
-						req,
-					);
-					Some((id, req))
-				} else if let Some((hash, req)) = fork_sync_request(
-					&id,
-					fork_targets,
-					best_queued,
-					last_finalized,
-					attrs,
-					|hash| {
-						if queue.contains(hash) {
-							BlockStatus::Queued
-						} else {
-							client.block_status(*hash).unwrap_or(BlockStatus::Unknown)
-						}
-					},
-					max_blocks_per_request,
-				) {
-					trace!(target: LOG_TARGET, "Downloading fork {hash:?} from {id}");
-					peer.state = PeerSyncState::DownloadingStale(hash);
-					Some((id, req))
-				} else if let Some((range, req)) = gap_sync.as_mut().and_then(|sync| {
-					peer_gap_block_request(
-						&id,
-						peer,
-						&mut sync.blocks,
-						attrs,
-						sync.target,
-						sync.best_queued_number,
-						max_blocks_per_request,
-					)
-				}) {
-					peer.state = PeerSyncState::DownloadingGap(range.start);
-					trace!(
-						target: LOG_TARGET,
-						"New gap block request for {}, (best:{}, common:{}) {:?}",
-						id,
-						peer.best_number,
-						peer.common_number,
-						req,
-					);
-					Some((id, req))
-				} else {
-					None
-				}
-			})
-			.collect()
-	}
+	/// Resume from the sync progress saved before a restart.
+	///
+	/// Imported blocks are already known to the client, so only the fork targets above the
+	/// finalized block and the gap sync are restored. The fork targets are downloaded from the
+	/// first peer whose best block is high enough. A block gap reported by the client is resumed
+	/// by [`Self::detect_block_gap`] instead, with the progress tracked by the client.
+	pub(crate) fn restore_snapshot(&mut self, snapshot: SyncSnapshot<B>) {
+		debug!(
+			target: LOG_TARGET,
+			"Resuming sync stopped at #{} ({:?}), gap sync {:?}, {} fork targets",
+			snapshot.best_queued_number,
+			snapshot.best_queued_hash,
+			snapshot.gap_sync,
+			snapshot.fork_targets.len(),
+		);
 
-	fn state_request(&mut self) -> Option<(PeerId, OpaqueStateRequest)> {
-		if self.allowed_requests.is_empty() {
-			return None
-		}
-		if (self.state_sync.is_some() || self.warp_sync.is_some()) &&
-			self.peers.iter().any(|(_, peer)| peer.state == PeerSyncState::DownloadingState)
-		{
-			// Only one pending state request is allowed.
-			return None
-		}
-		if let Some(sync) = &self.state_sync {
-			if sync.is_complete() {
-				return None
+		let finalized_number = self.client.info().finalized_number;
+		for (hash, number, parent_hash) in snapshot.fork_targets {
+			if number <= finalized_number ||
+				self.block_status(&hash).map_or(true, |status| status != BlockStatus::Unknown)
+			{
+				continue
 			}
-
-			for (id, peer) in self.peers.iter_mut() {
-				if peer.state.is_available() && peer.common_number >= sync.target_block_num() {
-					peer.state = PeerSyncState::DownloadingState;
-					let request = sync.next_request();
-					trace!(target: LOG_TARGET, "New StateRequest for {}: {:?}", id, request);
-					self.allowed_requests.clear();
-					return Some((*id, OpaqueStateRequest(Box::new(request))))
-				}
+			let peers = self
+				.peers
+				.iter()
+				.filter(|(_, peer)| peer.best_number >= number)
+				.map(|(peer_id, _)| *peer_id)
+				.collect::<HashSet<_>>();
+			let target = ForkTarget { number, parent_hash, header: None, peers };
+			if target.peers.is_empty() {
+				self.restored_fork_targets.insert(hash, target);
+			} else {
+				self.fork_targets.entry(hash).or_insert(target);
 			}
 		}
-		if let Some(sync) = &self.warp_sync {
-			if sync.is_complete() {
-				return None
-			}
-			if let (Some(request), Some(target)) =
-				(sync.next_state_request(), sync.target_block_number())
-			{
-				for (id, peer) in self.peers.iter_mut() {
-					if peer.state.is_available() && peer.best_number >= target {
-						trace!(target: LOG_TARGET, "New StateRequest for {id}: {request:?}");
-						peer.state = PeerSyncState::DownloadingState;
-						self.allowed_requests.clear();
-						return Some((*id, OpaqueStateRequest(Box::new(request))))
-					}
-				}
+
+		if let Some(gap) = snapshot.gap_sync {
+			if self.client.info().block_gap.is_none() && gap.best_queued < gap.end {
+				self.start_gap_sync(gap.best_queued + One::one()..=gap.end);
 			}
 		}
-		None
 	}
 
-	fn warp_sync_request(&mut self) -> Option<(PeerId, WarpProofRequest<B>)> {
-		if let Some(sync) = &self.warp_sync {
-			if self.allowed_requests.is_empty() ||
-				sync.is_complete() ||
-				self.peers
-					.iter()
-					.any(|(_, peer)| peer.state == PeerSyncState::DownloadingWarpProof)
+	/// Seed the best blocks `(peer_id, hash, number)` that peers reported before a restart.
+	///
+	/// The unknown ones above the finalized block become fork targets, downloaded from the peer
+	/// that reported them once it connects with a high enough best block.
+	pub(crate) fn restore_peer_bests(
+		&mut self,
+		bests: impl IntoIterator<Item = (PeerId, B::Hash, NumberFor<B>)>,
+	) {
+		let finalized_number = self.client.info().finalized_number;
+		for (peer_id, hash, number) in bests {
+			if number <= finalized_number ||
+				self.block_status(&hash).map_or(true, |status| status != BlockStatus::Unknown)
 			{
-				// Only one pending state request is allowed.
-				return None
-			}
-			if let Some(request) = sync.next_warp_proof_request() {
-				let mut targets: Vec<_> = self.peers.values().map(|p| p.best_number).collect();
-				if !targets.is_empty() {
-					targets.sort();
-					let median = targets[targets.len() / 2];
-					// Find a random peer that is synced as much as peer majority.
-					for (id, peer) in self.peers.iter_mut() {
-						if peer.state.is_available() && peer.best_number >= median {
-							trace!(target: LOG_TARGET, "New WarpProofRequest for {id}");
-							peer.state = PeerSyncState::DownloadingWarpProof;
-							self.allowed_requests.clear();
-							return Some((*id, request))
-						}
-					}
-				}
+				continue
 			}
+			self.restored_fork_targets
+				.entry(hash)
+				.or_insert_with(|| ForkTarget {
+					number,
+					parent_hash: None,
+					header: None,
+					peers: Default::default(),
+				})
+				.peers
+				.insert(peer_id);
 		}
-		None
 	}
 
-	fn on_state_data(
-		&mut self,
-		who: &PeerId,
-		response: OpaqueStateResponse,
-	) -> Result<OnStateData<B>, BadPeer> {
-		let response: Box<StateResponse> = response.0.downcast().map_err(|_error| {
-			error!(
-				target: LOG_TARGET,
-				"Failed to downcast opaque state response, this is an implementation bug."
-			);
-
-			BadPeer(*who, rep::BAD_RESPONSE)
-		})?;
+	/// Download the blocks in `range` in the background, below the best queued block.
+	///
+	/// Blocks are imported as gap blocks, so the client must accept them, as is the case for a
+	/// block gap it reports. A gap sync already in progress is replaced, and empty ranges or
+	/// ranges starting at genesis are ignored.
+	pub fn start_gap_sync(&mut self, range: RangeInclusive<NumberFor<B>>) {
+		let (start, end) = range.into_inner();
+		if start.is_zero() || start > end {
+			debug!(target: LOG_TARGET, "Ignoring invalid gap sync range #{start} - #{end}");
+			return
+		}
 
-		if let Some(peer) = self.peers.get_mut(who) {
-			if let PeerSyncState::DownloadingState = peer.state {
-				peer.state = PeerSyncState::Available;
-				self.allowed_requests.set_all();
+		debug!(target: LOG_TARGET, "Starting gap sync #{start} - #{end}");
+		if let Some(gap_sync) = self.gap_sync.take() {
+			// Downloads of the replaced gap are dropped, let the peers download again.
+			for peer in self.peers.values_mut() {
+				if let PeerSyncState::DownloadingGap(_) = peer.state {
+					peer.state = PeerSyncState::Available;
+				}
 			}
-		}
-		let import_result = if let Some(sync) = &mut self.state_sync {
-			debug!(
-				target: LOG_TARGET,
-				"Importing state data from {} with {} keys, {} proof nodes.",
-				who,
-				response.entries.len(),
-				response.proof.len(),
-			);
-			sync.import(*response)
-		} else if let Some(sync) = &mut self.warp_sync {
 			debug!(
 				target: LOG_TARGET,
-				"Importing state data from {} with {} keys, {} proof nodes.",
-				who,
-				response.entries.len(),
-				response.proof.len(),
+				"Replacing gap sync #{} - #{}",
+				gap_sync.start,
+				gap_sync.target,
 			);
-			sync.import_state(*response)
-		} else {
-			debug!(target: LOG_TARGET, "Ignored obsolete state response from {who}");
-			return Err(BadPeer(*who, rep::NOT_REQUESTED))
-		};
-
-		match import_result {
-			state::ImportResult::Import(hash, header, state, body, justifications) => {
-				let origin = BlockOrigin::NetworkInitialSync;
-				let block = IncomingBlock {
-					hash,
-					header: Some(header),
-					body,
-					indexed_body: None,
-					justifications,
-					origin: None,
-					allow_missing_state: true,
-					import_existing: true,
-					skip_execution: self.skip_execution(),
-					state: Some(state),
-				};
-				debug!(target: LOG_TARGET, "State download is complete. Import is queued");
-				Ok(OnStateData::Import(origin, block))
-			},
-			state::ImportResult::Continue => Ok(OnStateData::Continue),
-			state::ImportResult::BadResponse => {
-				debug!(target: LOG_TARGET, "Bad state data received from {who}");
-				Err(BadPeer(*who, rep::BAD_BLOCK))
-			},
 		}
+		// The block above the gap is known, so the gap is downloaded from its parent downwards.
+		let anchors = self
+			.client
+			.hash(end + One::one())
+			.ok()
+			.flatten()
+			.and_then(|hash| self.client.header(hash).ok().flatten())
+			.map(|header| (end, *header.parent_hash()))
+			.into_iter()
+			.collect();
+		self.gap_sync = Some(GapSync {
+			start,
+			best_queued_number: start - One::one(),
+			target: end,
+			blocks: BlockCollection::new(),
+			anchors,
+		});
+		self.allowed_requests.set_all();
 	}
 
-	fn on_warp_sync_data(&mut self, who: &PeerId, response: EncodedProof) -> Result<(), BadPeer> {
-		if let Some(peer) = self.peers.get_mut(who) {
-			if let PeerSyncState::DownloadingWarpProof = peer.state {
-				peer.state = PeerSyncState::Available;
-				self.allowed_requests.set_all();
-			}
+	/// Start a gap sync for the block gap reported by the client, if downloading the history is
+	/// enabled and no gap sync is in progress. Called periodically.
+	///
+	/// Gaps appear after warp sync, or when blocks are imported without their history.
+	pub fn detect_block_gap(&mut self) {
+		if self.gap_sync.is_some() && self.gap_request_limiter.is_some() {
+			// Retry the gap block requests held back by the rate limit.
+			self.allowed_requests.set_all();
 		}
-		let import_result = if let Some(sync) = &mut self.warp_sync {
-			debug!(
-				target: LOG_TARGET,
-				"Importing warp proof data from {}, {} bytes.",
-				who,
-				response.0.len(),
-			);
-			sync.import_warp_proof(response)
-		} else {
-			debug!(target: LOG_TARGET, "Ignored obsolete warp sync response from {who}");
-			return Err(BadPeer(*who, rep::NOT_REQUESTED))
-		};
-
-		match import_result {
-			WarpProofImportResult::Success => Ok(()),
-			WarpProofImportResult::BadResponse => {
-				debug!(target: LOG_TARGET, "Bad proof data received from {who}");
-				Err(BadPeer(*who, rep::BAD_BLOCK))
-			},
+		if !self.download_history ||
+			self.gap_sync.is_some() ||
+			self.warp_sync.is_some() ||
+			self.state_sync.is_some()
+		{
+			return
+		}
+		if let Some((start, end)) = self.client.info().block_gap {
+			self.start_gap_sync(start..=end);
 		}
 	}
 
-	fn import_blocks(&mut self, origin: BlockOrigin, blocks: Vec<IncomingBlock<B>>) {
-		if let Some(metrics) = &self.metrics {
-			metrics.import_queue_blocks_submitted.inc();
-		}
+	/// Limit the number of gap block requests in flight at once, so that filling the block
+	/// history doesn't compete with keeping up with the chain. Unlimited if `None`.
+	pub fn set_max_gap_requests(&mut self, max_gap_requests: Option<usize>) {
+		self.max_gap_requests = max_gap_requests;
+		self.allowed_requests.set_all();
+	}
 
-		self.import_queue.import_blocks(origin, blocks);
+	/// Download the block gap reported by the client in the background, sending at most `rate`
+	/// gap block requests per second if given. Disabled by default.
+	///
+	/// The download progress is tracked by the client, which shrinks the gap as blocks are
+	/// imported, so it is resumed on restart.
+	pub fn set_download_history(&mut self, download_history: bool, rate: Option<u32>) {
+		self.download_history = download_history;
+		self.gap_request_limiter = rate.map(|rate| RateLimiter::new(rate, Instant::now()));
+		if download_history {
+			self.detect_block_gap();
+		}
 	}
 
-	fn import_justifications(
+	/// Set how the failed block, state and warp proof requests are retried.
+	pub fn set_request_retry_policies(
 		&mut self,
-		peer: PeerId,
-		hash: B::Hash,
-		number: NumberFor<B>,
-		justifications: Justifications,
+		block: RequestRetryPolicy,
+		state: RequestRetryPolicy,
+		warp_proof: RequestRetryPolicy,
 	) {
-		if let Some(metrics) = &self.metrics {
-			metrics.import_queue_justifications_submitted.inc();
+		self.request_retries.set_policies(block, state, warp_proof);
+	}
+
+	/// Handle the failure of a `request` to `peer_id`, returning whether the peer failed too many
+	/// times in a row and should be disconnected.
+	///
+	/// Otherwise the peer is made available again, its request is handed to the next available
+	/// peer and the peer itself is not sent requests of the same kind until its backoff elapsed.
+	pub(crate) fn on_request_failure(
+		&mut self,
+		peer_id: &PeerId,
+		request: PeerRequestType,
+		failure: RequestFailureKind,
+	) -> bool {
+		if self.request_retries.on_failure(peer_id, request, failure, Instant::now()) {
+			return true
 		}
 
-		self.import_queue.import_justifications(peer, hash, number, justifications);
+		let Some(peer) = self.peers.get_mut(peer_id) else { return false };
+		debug!(
+			target: LOG_TARGET,
+			"{request:?} request to {peer_id} failed ({failure:?}) in state {:?}, backing off",
+			peer.state,
+		);
+		match peer.state {
+			PeerSyncState::DownloadingNew(_) => self.blocks.clear_peer_download(peer_id),
+			PeerSyncState::DownloadingGap(_) =>
+				if let Some(gap_sync) = &mut self.gap_sync {
+					gap_sync.blocks.clear_peer_download(peer_id)
+				},
+			PeerSyncState::DownloadingJustification(_) =>
+				self.extra_justifications.peer_disconnected(peer_id),
+			_ => {},
+		}
+		peer.state = PeerSyncState::Available;
+		self.allowed_requests.set_all();
+		false
 	}
 
-	/// A batch of blocks have been processed, with or without errors.
-	///
-	/// Call this when a batch of blocks have been processed by the import
-	/// queue, with or without errors. If an error is returned, the pending response
-	/// from the peer must be dropped.
-	fn on_blocks_processed(
+	/// Record that `peer_id` answered a `request`, forgetting its previous failures.
+	pub(crate) fn on_request_success(&mut self, peer_id: &PeerId, request: PeerRequestType) {
+		self.request_retries.on_success(peer_id, request);
+	}
+
+	/// Record that a `request` to `peer_id` was answered or timed out `elapsed` after it was sent.
+	pub fn on_response_time(
 		&mut self,
-		imported: usize,
-		count: usize,
-		results: Vec<(Result<BlockImportStatus<NumberFor<B>>, BlockImportError>, B::Hash)>,
-	) -> Box<dyn Iterator<Item = Result<BlockRequestEvent<B>, BadPeer>>> {
-		trace!(target: LOG_TARGET, "Imported {imported} of {count}");
+		peer_id: &PeerId,
+		request: PeerRequestType,
+		elapsed: Duration,
+	) {
+		self.response_times.record(peer_id, request, elapsed);
+	}
 
-		let mut output = Vec::new();
+	/// The 95th percentile of the recent round-trip times of `request`s to `peer_id`, `None` if
+	/// none was answered or timed out yet.
+	pub fn response_time_p95(
+		&self,
+		peer_id: &PeerId,
+		request: PeerRequestType,
+	) -> Option<Duration> {
+		self.response_times.p95(peer_id, request)
+	}
 
-		let mut has_error = false;
-		for (_, hash) in &results {
-			self.queue_blocks.remove(hash);
-			self.blocks.clear_queued(hash);
-			if let Some(gap_sync) = &mut self.gap_sync {
-				gap_sync.blocks.clear_queued(hash);
-			}
+	/// Make the peers whose backoff elapsed available for requests again. Called periodically.
+	pub fn expire_request_backoffs(&mut self) {
+		if self.request_retries.expire_backoffs(Instant::now()) {
+			self.allowed_requests.set_all();
 		}
-		for (result, hash) in results {
-			if has_error {
-				break
-			}
+	}
 
-			has_error |= result.is_err();
+	/// Peers whose in-flight requests were cancelled since the last call, because another peer
+	/// answered the same request first or the blocks they were downloading are no longer needed.
+	/// Their responses should be dropped.
+	pub fn take_cancelled_requests(&mut self) -> Vec<PeerId> {
+		std::mem::take(&mut self.cancelled_requests)
+	}
 
-			match result {
-				Ok(BlockImportStatus::ImportedKnown(number, who)) =>
-					if let Some(peer) = who {
-						self.update_peer_common_number(&peer, number);
-					},
-				Ok(BlockImportStatus::ImportedUnknown(number, aux, who)) => {
-					if aux.clear_justification_requests {
-						trace!(
-							target: LOG_TARGET,
-							"Block imported clears all pending justification requests {number}: {hash:?}",
-						);
-						self.clear_justification_requests();
-					}
+	/// Never request, accept or import the block `hash` and its descendants. Peers serving them
+	/// are disconnected.
+	pub fn ban_block(&mut self, hash: B::Hash) {
+		debug!(target: LOG_TARGET, "Banning block {hash:?}");
+		self.banned_blocks.insert(hash);
+		self.restored_fork_targets.remove(&hash);
+		if self.fork_targets.remove(&hash).is_some() {
+			for (peer_id, peer) in self.peers.iter_mut() {
+				if peer.state == PeerSyncState::DownloadingStale(hash) {
+					peer.state = PeerSyncState::Available;
+					self.cancelled_requests.push(*peer_id);
+				}
+			}
+			self.allowed_requests.set_all();
+		}
+	}
 
-					if aux.needs_justification {
-						trace!(
-							target: LOG_TARGET,
-							"Block imported but requires justification {number}: {hash:?}",
-						);
-						self.request_justification(&hash, number);
-					}
+	/// Whether the block `hash` or its parent `parent_hash` was banned with
+	/// [`ChainSync::ban_block`].
+	pub fn is_banned_block(&self, hash: &B::Hash, parent_hash: &B::Hash) -> bool {
+		self.banned_blocks.contains(hash) || self.banned_blocks.contains(parent_hash)
+	}
 
-					if aux.bad_justification {
-						if let Some(ref peer) = who {
-							warn!("💔 Sent block with bad justification to import");
-							output.push(Err(BadPeer(*peer, rep::BAD_JUSTIFICATION)));
-						}
-					}
-
-					if let Some(peer) = who {
-						self.update_peer_common_number(&peer, number);
-					}
-					let state_sync_complete =
-						self.state_sync.as_ref().map_or(false, |s| s.target() == hash);
-					if state_sync_complete {
-						info!(
-							target: LOG_TARGET,
-							"State sync is complete ({} MiB), restarting block sync.",
-							self.state_sync.as_ref().map_or(0, |s| s.progress().size / (1024 * 1024)),
-						);
-						self.state_sync = None;
-						self.mode = SyncMode::Full;
-						output.extend(self.restart());
-					}
-					let warp_sync_complete = self
-						.warp_sync
-						.as_ref()
-						.map_or(false, |s| s.target_block_hash() == Some(hash));
-					if warp_sync_complete {
-						info!(
-							target: LOG_TARGET,
-							"Warp sync is complete ({} MiB), restarting block sync.",
-							self.warp_sync.as_ref().map_or(0, |s| s.progress().total_bytes / (1024 * 1024)),
-						);
-						self.warp_sync = None;
-						self.mode = SyncMode::Full;
-						output.extend(self.restart());
-					}
-					let gap_sync_complete =
-						self.gap_sync.as_ref().map_or(false, |s| s.target == number);
-					if gap_sync_complete {
-						info!(
-							target: LOG_TARGET,
-							"Block history download is complete."
-						);
-						self.gap_sync = None;
-					}
-				},
-				Err(BlockImportError::IncompleteHeader(who)) =>
-					if let Some(peer) = who {
-						warn!(
-							target: LOG_TARGET,
-							"💔 Peer sent block with incomplete header to import",
-						);
-						output.push(Err(BadPeer(peer, rep::INCOMPLETE_HEADER)));
-						output.extend(self.restart());
-					},
-				Err(BlockImportError::VerificationFailed(who, e)) => {
-					let extra_message =
-						who.map_or_else(|| "".into(), |peer| format!(" received from ({peer})"));
-
-					warn!(
-						target: LOG_TARGET,
-						"💔 Verification failed for block {hash:?}{extra_message}: {e:?}",
-					);
-
-					if let Some(peer) = who {
-						output.push(Err(BadPeer(peer, rep::VERIFICATION_FAIL)));
-					}
-
-					output.extend(self.restart());
-				},
-				Err(BlockImportError::BadBlock(who)) =>
-					if let Some(peer) = who {
-						warn!(
-							target: LOG_TARGET,
-							"💔 Block {hash:?} received from peer {peer} has been blacklisted",
-						);
-						output.push(Err(BadPeer(peer, rep::BAD_BLOCK)));
-					},
-				Err(BlockImportError::MissingState) => {
-					// This may happen if the chain we were requesting upon has been discarded
-					// in the meantime because other chain has been finalized.
-					// Don't mark it as bad as it still may be synced if explicitly requested.
-					trace!(target: LOG_TARGET, "Obsolete block {hash:?}");
-				},
-				e @ Err(BlockImportError::UnknownParent) | e @ Err(BlockImportError::Other(_)) => {
-					warn!(target: LOG_TARGET, "💔 Error importing block {hash:?}: {}", e.unwrap_err());
-					self.state_sync = None;
-					self.warp_sync = None;
-					output.extend(self.restart());
-				},
-				Err(BlockImportError::Cancelled) => {},
-			};
-		}
+	/// Only send block, state and warp proof requests to `peers`, or to all peers if `None`.
+	/// Block announcements of the other peers are still processed.
+	pub fn set_request_peers(&mut self, peers: Option<HashSet<PeerId>>) {
+		self.request_peers = peers;
+		self.allowed_requests.set_all();
+	}
 
+	/// Only send justification requests to `peers`, which may be sent them even if they aren't
+	/// request peers.
+	pub fn set_justification_peers(&mut self, peers: HashSet<PeerId>) {
+		self.justification_peers = peers;
 		self.allowed_requests.set_all();
-		Box::new(output.into_iter())
 	}
-}
 
-// This is purely during a backwards compatible transitionary period and should be removed
-// once we can assume all nodes can send and receive multiple Justifications
-// The ID tag is hardcoded here to avoid depending on the GRANDPA crate.
-// See: https://github.com/paritytech/substrate/issues/8172
-fn legacy_justification_mapping(
-	justification: Option<EncodedJustification>,
-) -> Option<Justifications> {
-	justification.map(|just| (*b"FRNK", just).into())
-}
+	/// Number of the sync target block, if there is a target and its number is known.
+	fn sync_target_number(&self) -> Option<NumberFor<B>> {
+		let target = self.explicit_sync_target_number();
+		if !self.finalized_only {
+			return target
+		}
+		let finalized = self.known_finalized_number();
+		Some(target.map_or(finalized, |target| target.min(finalized)))
+	}
 
-/// Request the ancestry for a block. Sends a request for header and justification for the given
-/// block number. Used during ancestry search.
-fn ancestry_request<B: BlockT>(block: NumberFor<B>) -> BlockRequest<B> {
-	BlockRequest::<B> {
-		id: 0,
-		fields: BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION,
-		from: FromBlock::Number(block),
-		direction: Direction::Ascending,
-		max: Some(1),
+	/// Highest finalized block we know of, ours, the highest block explicitly requested or the
+	/// median of the finalized blocks the peers reported, so that a minority of peers can't lure
+	/// us onto unfinalized blocks.
+	fn known_finalized_number(&self) -> NumberFor<B> {
+		let mut reported =
+			self.peers.values().filter_map(|peer| peer.finalized_number).collect::<Vec<_>>();
+		let median = if reported.is_empty() {
+			Zero::zero()
+		} else {
+			let middle = reported.len() / 2;
+			*reported.select_nth_unstable(middle).1
+		};
+		median
+			.max(self.requested_finalized_number)
+			.max(self.client.info().finalized_number)
 	}
-}
 
-/// The ancestor search state expresses which algorithm, and its stateful parameters, we are using
-/// to try to find an ancestor block
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub enum AncestorSearchState<B: BlockT> {
-	/// Use exponential backoff to find an ancestor, then switch to binary search.
-	/// We keep track of the exponent.
-	ExponentialBackoff(NumberFor<B>),
-	/// Using binary search to find the best ancestor.
-	/// We keep track of left and right bounds.
-	BinarySearch(NumberFor<B>, NumberFor<B>),
-}
+	fn explicit_sync_target_number(&self) -> Option<NumberFor<B>> {
+		match self.sync_target.as_ref()? {
+			BlockId::Number(number) => Some(*number),
+			BlockId::Hash(hash) => self
+				.peers
+				.values()
+				.find(|p| p.best_hash == *hash)
+				.map(|p| p.best_number)
+				.or_else(|| self.fork_targets.get(hash).map(|target| target.number)),
+		}
+	}
 
-/// This function handles the ancestor search strategy used. The goal is to find a common point
-/// that both our chains agree on that is as close to the tip as possible.
-/// The way this works is we first have an exponential backoff strategy, where we try to step
-/// forward until we find a block hash mismatch. The size of the step doubles each step we take.
-///
-/// When we've found a block hash mismatch we then fall back to a binary search between the two
-/// last known points to find the common block closest to the tip.
-fn handle_ancestor_search_state<B: BlockT>(
-	state: &AncestorSearchState<B>,
-	curr_block_num: NumberFor<B>,
-	block_hash_match: bool,
-) -> Option<(AncestorSearchState<B>, NumberFor<B>)> {
-	let two = <NumberFor<B>>::one() + <NumberFor<B>>::one();
-	match state {
-		AncestorSearchState::ExponentialBackoff(next_distance_to_tip) => {
-			let next_distance_to_tip = *next_distance_to_tip;
-			if block_hash_match && next_distance_to_tip == One::one() {
-				// We found the ancestor in the first step so there is no need to execute binary
-				// search.
-				return None
-			}
-			if block_hash_match {
-				let left = curr_block_num;
-				let right = left + next_distance_to_tip / two;
-				let middle = left + (right - left) / two;
-				Some((AncestorSearchState::BinarySearch(left, right), middle))
-			} else {
-				let next_block_num =
-					curr_block_num.checked_sub(&next_distance_to_tip).unwrap_or_else(Zero::zero);
-				let next_distance_to_tip = next_distance_to_tip * two;
-				Some((
-					AncestorSearchState::ExponentialBackoff(next_distance_to_tip),
-					next_block_num,
-				))
-			}
-		},
-		AncestorSearchState::BinarySearch(mut left, mut right) => {
-			if left >= curr_block_num {
-				return None
-			}
-			if block_hash_match {
-				left = curr_block_num;
-			} else {
-				right = curr_block_num;
-			}
-			assert!(right >= left);
-			let middle = left + (right - left) / two;
-			if middle == curr_block_num {
-				None
-			} else {
-				Some((AncestorSearchState::BinarySearch(left, right), middle))
-			}
-		},
+	fn required_block_attributes(&self) -> BlockAttributes {
+		match self.mode {
+			SyncMode::Full =>
+				BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION | BlockAttributes::BODY,
+			SyncMode::LightState { storage_chain_mode: false, .. } | SyncMode::Warp =>
+				BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION | BlockAttributes::BODY,
+			SyncMode::LightState { storage_chain_mode: true, .. } =>
+				BlockAttributes::HEADER |
+					BlockAttributes::JUSTIFICATION |
+					BlockAttributes::INDEXED_BODY,
+		}
 	}
-}
 
-/// Get a new block request for the peer if any.
-fn peer_block_request<B: BlockT>(
-	id: &PeerId,
-	peer: &PeerSync<B>,
-	blocks: &mut BlockCollection<B>,
-	attrs: BlockAttributes,
-	max_parallel_downloads: u32,
-	max_blocks_per_request: u32,
-	finalized: NumberFor<B>,
-	best_num: NumberFor<B>,
-) -> Option<(Range<NumberFor<B>>, BlockRequest<B>)> {
-	if best_num >= peer.best_number {
-		// Will be downloaded as alternative fork instead.
-		return None
-	} else if peer.common_number < finalized {
-		trace!(
-			target: LOG_TARGET,
-			"Requesting pre-finalized chain from {:?}, common={}, finalized={}, peer best={}, our best={}",
-			id, peer.common_number, finalized, peer.best_number, best_num,
-		);
+	fn skip_execution(&self) -> bool {
+		match self.mode {
+			SyncMode::Full => false,
+			SyncMode::LightState { .. } => true,
+			SyncMode::Warp => true,
+		}
 	}
-	let range = blocks.needed_blocks(
-		*id,
-		max_blocks_per_request,
-		peer.best_number,
-		peer.common_number,
-		max_parallel_downloads,
-		MAX_DOWNLOAD_AHEAD,
-	)?;
 
-	// The end is not part of the range.
-	let last = range.end.saturating_sub(One::one());
+	fn validate_and_queue_blocks(
+		&mut self,
+		mut new_blocks: Vec<IncomingBlock<B>>,
+		gap: bool,
+	) -> OnBlockData<B> {
+		let orig_len = new_blocks.len();
+		new_blocks.retain(|b| !self.queue_blocks.contains(&b.hash));
+		if new_blocks.len() != orig_len {
+			debug!(
+				target: LOG_TARGET,
+				"Ignoring {} blocks that are already queued",
+				orig_len - new_blocks.len(),
+			);
+		}
 
-	let from = if peer.best_number == last {
-		FromBlock::Hash(peer.best_hash)
-	} else {
-		FromBlock::Number(last)
-	};
+		let origin = if !gap && !self.status().state.is_major_syncing() {
+			BlockOrigin::NetworkBroadcast
+		} else {
+			BlockOrigin::NetworkInitialSync
+		};
 
-	let request = BlockRequest::<B> {
-		id: 0,
-		fields: attrs,
-		from,
-		direction: Direction::Descending,
-		max: Some((range.end - range.start).saturated_into::<u32>()),
-	};
+		if let Some((h, n)) = new_blocks
+			.last()
+			.and_then(|b| b.header.as_ref().map(|h| (&b.hash, *h.number())))
+		{
+			trace!(
+				target:LOG_TARGET,
+				"Accepted {} blocks ({:?}) with origin {:?}",
+				new_blocks.len(),
+				h,
+				origin,
+			);
+			self.on_block_queued(h, n)
+		}
+		self.queue_blocks.extend(new_blocks.iter().map(|b| b.hash));
+		OnBlockData::Import(origin, new_blocks)
+	}
 
-	Some((range, request))
-}
+	fn update_peer_common_number(&mut self, peer_id: &PeerId, new_common: NumberFor<B>) {
+		if let Some(peer) = self.peers.get_mut(peer_id) {
+			peer.update_common_number(new_common);
+		}
+	}
 
-/// Get a new block request for the peer if any.
-fn peer_gap_block_request<B: BlockT>(
-	id: &PeerId,
-	peer: &PeerSync<B>,
-	blocks: &mut BlockCollection<B>,
-	attrs: BlockAttributes,
-	target: NumberFor<B>,
-	common_number: NumberFor<B>,
-	max_blocks_per_request: u32,
-) -> Option<(Range<NumberFor<B>>, BlockRequest<B>)> {
-	let range = blocks.needed_blocks(
-		*id,
-		max_blocks_per_request,
-		std::cmp::min(peer.best_number, target),
-		common_number,
-		1,
-		MAX_DOWNLOAD_AHEAD,
-	)?;
+	/// Called when a block has been queued for import.
+	///
+	/// Updates our internal state for best queued block and then goes
+	/// through all peers to update our view of their state as well.
+	fn on_block_queued(&mut self, hash: &B::Hash, number: NumberFor<B>) {
+		self.restored_fork_targets.remove(hash);
+		if self.fork_targets.remove(hash).is_some() {
+			trace!(target: LOG_TARGET, "Completed fork sync {hash:?}");
+		}
+		if matches!(self.sync_target, Some(BlockId::Hash(target)) if target == *hash) {
+			self.sync_target = Some(BlockId::Number(number));
+		}
+		if let Some(gap_sync) = &mut self.gap_sync {
+			if number > gap_sync.best_queued_number && number <= gap_sync.target {
+				gap_sync.best_queued_number = number;
+			}
+		}
+		if number > self.best_queued_number {
+			self.best_queued_number = number;
+			self.best_queued_hash = *hash;
+			// Update common blocks
+			for (n, peer) in self.peers.iter_mut() {
+				if let PeerSyncState::AncestorSearch { .. } = peer.state {
+					// Wait for ancestry search to complete first.
+					continue
+				}
+				let new_common_number =
+					if peer.best_number >= number { number } else { peer.best_number };
+				trace!(
+					target: LOG_TARGET,
+					"Updating peer {} info, ours={}, common={}->{}, their best={}",
+					n,
+					number,
+					peer.common_number,
+					new_common_number,
+					peer.best_number,
+				);
+				peer.common_number = new_common_number;
+			}
+		}
+		self.allowed_requests.set_all();
+	}
 
-	// The end is not part of the range.
-	let last = range.end.saturating_sub(One::one());
-	let from = FromBlock::Number(last);
+	/// Restart the sync process. This will reset all pending block requests and return an iterator
+	/// of new block requests to make to peers. Peers that were downloading finality data (i.e.
+	/// their state was `DownloadingJustification`) are unaffected and will stay in the same state.
+	fn restart(&mut self) -> impl Iterator<Item = Result<BlockRequestEvent<B>, BadPeer>> + '_ {
+		self.blocks.clear();
+		if let Err(e) = self.reset_sync_start_point() {
+			warn!(target: LOG_TARGET, "💔  Unable to restart sync: {e}");
+		}
+		self.allowed_requests.set_all();
+		debug!(
+			target: LOG_TARGET,
+			"Restarted with {} ({})",
+			self.best_queued_number,
+			self.best_queued_hash,
+		);
+		let old_peers = std::mem::take(&mut self.peers);
 
-	let request = BlockRequest::<B> {
-		id: 0,
-		fields: attrs,
-		from,
-		direction: Direction::Descending,
-		max: Some((range.end - range.start).saturated_into::<u32>()),
-	};
-	Some((range, request))
-}
+		old_peers.into_iter().filter_map(move |(peer_id, mut p)| {
+			// peers that were downloading justifications
+			// should be kept in that state.
+			if let PeerSyncState::DownloadingJustification(_) = p.state {
+				// We make sure our commmon number is at least something we have.
+				p.common_number = self.best_queued_number;
+				self.peers.insert(peer_id, p);
+				return None
+			}
 
-/// Get pending fork sync targets for a peer.
-fn fork_sync_request<B: BlockT>(
-	id: &PeerId,
-	targets: &mut HashMap<B::Hash, ForkTarget<B>>,
-	best_num: NumberFor<B>,
-	finalized: NumberFor<B>,
-	attributes: BlockAttributes,
-	check_block: impl Fn(&B::Hash) -> BlockStatus,
-	max_blocks_per_request: u32,
-) -> Option<(B::Hash, BlockRequest<B>)> {
-	targets.retain(|hash, r| {
-		if r.number <= finalized {
-			trace!(
+			// handle peers that were in other states.
+			match self.new_peer(peer_id, p.best_hash, p.best_number) {
+				// since the request is not a justification, remove it from pending responses
+				Ok(None) => Some(Ok(BlockRequestEvent::RemoveStale { peer_id })),
+				// update the request if the new one is available
+				Ok(Some(request)) => Some(Ok(BlockRequestEvent::SendRequest { peer_id, request })),
+				// this implies that we need to drop pending response from the peer
+				Err(e) => Some(Err(e)),
+			}
+		})
+	}
+
+	/// Find a block to start sync from. If we sync with state, that's the latest block we have
+	/// state for.
+	fn reset_sync_start_point(&mut self) -> Result<(), ClientError> {
+		let info = self.client.info();
+		if matches!(self.mode, SyncMode::LightState { .. }) &&
+			info.finalized_state.is_some() &&
+			self.state_delta_base.is_none()
+		{
+			warn!(
 				target: LOG_TARGET,
-				"Removed expired fork sync request {:?} (#{})",
-				hash,
-				r.number,
+				"Can't use fast sync mode with a partially synced database. Reverting to full sync mode."
 			);
-			return false
+			self.mode = SyncMode::Full;
 		}
-		if check_block(hash) != BlockStatus::Unknown {
-			trace!(
+		if matches!(self.mode, SyncMode::Warp) && info.finalized_state.is_some() {
+			warn!(
 				target: LOG_TARGET,
-				"Removed obsolete fork sync request {:?} (#{})",
-				hash,
-				r.number,
+				"Can't use warp sync mode with a partially synced database. Reverting to full sync mode."
 			);
-			return false
+			self.mode = SyncMode::Full;
 		}
-		true
-	});
-	for (hash, r) in targets {
-		if !r.peers.contains(&id) {
-			continue
-		}
-		// Download the fork only if it is behind or not too far ahead our tip of the chain
-		// Otherwise it should be downloaded in full sync mode.
-		if r.number <= best_num ||
-			(r.number - best_num).saturated_into::<u32>() < max_blocks_per_request as u32
+		self.import_existing = false;
+		self.best_queued_hash = info.best_hash;
+		self.best_queued_number = info.best_number;
+
+		if self.mode == SyncMode::Full &&
+			self.client.block_status(info.best_hash)? != BlockStatus::InChainWithState
 		{
-			let parent_status = r.parent_hash.as_ref().map_or(BlockStatus::Unknown, check_block);
-			let count = if parent_status == BlockStatus::Unknown {
-				(r.number - finalized).saturated_into::<u32>() // up to the last finalized block
+			self.import_existing = true;
+			// Latest state is missing, start with the last finalized state or genesis instead.
+			if let Some((hash, number)) = info.finalized_state {
+				debug!(target: LOG_TARGET, "Starting from finalized state #{number}");
+				self.best_queued_hash = hash;
+				self.best_queued_number = number;
 			} else {
-				// request only single block
-				1
-			};
-			trace!(
-				target: LOG_TARGET,
-				"Downloading requested fork {hash:?} from {id}, {count} blocks",
-			);
-			return Some((
-				*hash,
-				BlockRequest::<B> {
-					id: 0,
-					fields: attributes,
-					from: FromBlock::Hash(*hash),
-					direction: Direction::Descending,
-					max: Some(count),
-				},
-			))
-		} else {
-			trace!(target: LOG_TARGET, "Fork too far in the future: {:?} (#{})", hash, r.number);
+				debug!(target: LOG_TARGET, "Restarting from genesis");
+				self.best_queued_hash = Default::default();
+				self.best_queued_number = Zero::zero();
+			}
+		}
+
+		if let Some((start, end)) = info.block_gap.filter(|_| self.download_history) {
+			self.start_gap_sync(start..=end);
+		}
+		trace!(
+			target: LOG_TARGET,
+			"Restarted sync at #{} ({:?})",
+			self.best_queued_number,
+			self.best_queued_hash,
+		);
+		Ok(())
+	}
+
+	/// What is the status of the block corresponding to the given hash?
+	fn block_status(&self, hash: &B::Hash) -> Result<BlockStatus, ClientError> {
+		if self.queue_blocks.contains(hash) {
+			return Ok(BlockStatus::Queued)
 		}
+		self.client.block_status(*hash)
 	}
-	None
-}
 
-/// Returns `true` if the given `block` is a descendent of `base`.
-fn is_descendent_of<Block, T>(
-	client: &T,
-	base: &Block::Hash,
-	block: &Block::Hash,
-) -> sp_blockchain::Result<bool>
-where
-	Block: BlockT,
-	T: HeaderMetadata<Block, Error = sp_blockchain::Error> + ?Sized,
-{
-	if base == block {
-		return Ok(false)
+	/// Is the block corresponding to the given hash known?
+	fn is_known(&self, hash: &B::Hash) -> bool {
+		self.block_status(hash).ok().map_or(false, |s| s != BlockStatus::Unknown)
+	}
+
+	/// Is any peer downloading the given hash?
+	fn is_already_downloading(&self, hash: &B::Hash) -> bool {
+		self.peers
+			.iter()
+			.any(|(_, p)| p.state == PeerSyncState::DownloadingStale(*hash))
+	}
+
+	/// Is the peer know to the sync state machine?
+	pub fn is_peer_known(&self, peer_id: &PeerId) -> bool {
+		self.peers.contains_key(peer_id)
+	}
+
+	/// Get the set of downloaded blocks that are ready to be queued for import.
+	/// Cancel the requests of the peers other than `winner` downloading the blocks from `start`,
+	/// which `winner` just served.
+	fn cancel_raced_downloads(&mut self, winner: &PeerId, start: NumberFor<B>) {
+		for (peer_id, peer) in self.peers.iter_mut() {
+			if peer_id != winner && peer.state == PeerSyncState::DownloadingNew(start) {
+				trace!(target: LOG_TARGET, "Cancelling download from #{start} raced by {peer_id}");
+				self.blocks.clear_peer_download(peer_id);
+				peer.state = PeerSyncState::Available;
+				self.allowed_requests.add(peer_id);
+				self.cancelled_requests.push(*peer_id);
+			}
+		}
+	}
+
+	fn ready_blocks(&mut self) -> Vec<IncomingBlock<B>> {
+		self.blocks
+			.ready_blocks(self.best_queued_number + One::one())
+			.into_iter()
+			.map(|block_data| {
+				let justifications = block_data
+					.block
+					.justifications
+					.or_else(|| legacy_justification_mapping(block_data.block.justification));
+				IncomingBlock {
+					hash: block_data.block.hash,
+					header: block_data.block.header,
+					body: block_data.block.body,
+					indexed_body: block_data.block.indexed_body,
+					justifications,
+					origin: block_data.origin,
+					allow_missing_state: true,
+					import_existing: self.import_existing,
+					skip_execution: self.skip_execution(),
+					state: None,
+				}
+			})
+			.collect()
+	}
+
+	/// Set warp sync target block externally, skipping the warp proof download.
+	///
+	/// The target must be above the finalized block. It replaces the current target, unless the
+	/// state of that one is being downloaded already.
+	///
+	/// Returns the peers whose pending warp requests were made obsolete by the new target, their
+	/// responses should be dropped.
+	pub fn set_warp_sync_target_block(
+		&mut self,
+		header: B::Header,
+	) -> Result<Vec<PeerId>, WarpSyncTargetError> {
+		if *header.number() <= self.client.info().finalized_number {
+			return Err(WarpSyncTargetError::NotAboveFinalized)
+		}
+
+		if let Some(ref mut warp_sync) = self.warp_sync {
+			warp_sync.set_target_block(header)?;
+		} else if self.warp_sync_config.is_some() {
+			self.warp_sync_target_block_header = Some(header);
+			return Ok(Vec::new())
+		} else {
+			return Err(WarpSyncTargetError::NotWarpSyncing)
+		}
+
+		let mut obsolete = Vec::new();
+		for (id, peer) in self.peers.iter_mut() {
+			if let PeerSyncState::DownloadingWarpProof | PeerSyncState::DownloadingWarpTargetBlock =
+				peer.state
+			{
+				peer.state = PeerSyncState::Available;
+				obsolete.push(*id);
+			}
+		}
+		self.allowed_requests.set_all();
+
+		Ok(obsolete)
+	}
+
+	/// Generate block request for downloading of the target block body during warp sync.
+	fn warp_target_block_request(&mut self) -> Option<(PeerId, BlockRequest<B>)> {
+		let sync = &self.warp_sync.as_ref()?;
+
+		if self.allowed_requests.is_empty() ||
+			sync.is_complete() ||
+			self.peers
+				.iter()
+				.any(|(_, peer)| peer.state == PeerSyncState::DownloadingWarpTargetBlock)
+		{
+			// Only one pending warp target block request is allowed.
+			return None
+		}
+
+		if let Some((target_number, request)) = sync.next_target_block_request() {
+			let now = Instant::now();
+			// Find a random peer that has a block with the target number.
+			for (id, peer) in self.peers.iter_mut() {
+				if peer.state.is_available() &&
+					peer.best_number >= target_number &&
+					may_request(&self.request_peers, &self.justification_peers, id) &&
+					!self.request_retries.is_backing_off(id, PeerRequestType::Block, now)
+				{
+					trace!(target: LOG_TARGET, "New warp target block request for {id}");
+					peer.state = PeerSyncState::DownloadingWarpTargetBlock;
+					self.allowed_requests.clear();
+					return Some((*id, request))
+				}
+			}
+		}
+
+		None
+	}
+
+	pub(crate) fn on_block_response(
+		&mut self,
+		peer_id: PeerId,
+		request: BlockRequest<B>,
+		blocks: Vec<BlockData<B>>,
+	) -> Option<(PeerId, BlockRequest<B>)> {
+		let block_response = BlockResponse::<B> { id: request.id, blocks };
+
+		let blocks_range = || match (
+			block_response
+				.blocks
+				.first()
+				.and_then(|b| b.header.as_ref().map(|h| h.number())),
+			block_response.blocks.last().and_then(|b| b.header.as_ref().map(|h| h.number())),
+		) {
+			(Some(first), Some(last)) if first != last => format!(" ({}..{})", first, last),
+			(Some(first), Some(_)) => format!(" ({})", first),
+			_ => Default::default(),
+		};
+		trace!(
+			target: LOG_TARGET,
+			"BlockResponse {} from {} with {} blocks {}",
+			block_response.id,
+			peer_id,
+			block_response.blocks.len(),
+			blocks_range(),
+		);
+
+		if request.fields == BlockAttributes::JUSTIFICATION {
+			match self.on_block_justification(peer_id, block_response) {
+				Ok(OnBlockJustification::Nothing) => None,
+				Ok(OnBlockJustification::Import { peer, hash, number, justifications }) => {
+					self.import_justifications(peer, hash, number, justifications);
+					None
+				},
+				Err(BadPeer(id, repu)) => {
+					self.network_service
+						.disconnect_peer(id, self.block_announce_protocol_name.clone());
+					self.network_service.report_peer(id, repu);
+					None
+				},
+			}
+		} else {
+			match self.on_block_data(&peer_id, Some(request), block_response) {
+				Ok(OnBlockData::Import(origin, blocks)) => {
+					self.import_blocks(origin, blocks);
+					None
+				},
+				Ok(OnBlockData::Request(peer, req)) => Some((peer, req)),
+				Ok(OnBlockData::Continue) => None,
+				Err(BadPeer(id, repu)) => {
+					self.network_service
+						.disconnect_peer(id, self.block_announce_protocol_name.clone());
+					self.network_service.report_peer(id, repu);
+					None
+				},
+			}
+		}
+	}
+
+	/// Make `peer_id` available again after its pending state request was dropped without an
+	/// answer, so that the request is made again.
+	pub(crate) fn on_state_request_dropped(&mut self, peer_id: &PeerId) {
+		let Some(peer) = self.peers.get_mut(peer_id) else { return };
+		if let PeerSyncState::DownloadingState = peer.state {
+			peer.state = PeerSyncState::Available;
+			self.allowed_requests.set_all();
+		}
+	}
+
+	pub fn on_state_response(&mut self, peer_id: PeerId, response: OpaqueStateResponse) {
+		match self.on_state_data(&peer_id, response) {
+			Ok(OnStateData::Import(origin, block)) => self.import_blocks(origin, vec![block]),
+			Ok(OnStateData::Continue) => {},
+			Err(BadPeer(id, repu)) => {
+				self.network_service
+					.disconnect_peer(id, self.block_announce_protocol_name.clone());
+				self.network_service.report_peer(id, repu);
+			},
+		}
+	}
+
+	pub fn on_warp_sync_response(&mut self, peer_id: PeerId, response: EncodedProof) {
+		if let Err(BadPeer(id, repu)) = self.on_warp_sync_data(&peer_id, response) {
+			self.network_service
+				.disconnect_peer(id, self.block_announce_protocol_name.clone());
+			self.network_service.report_peer(id, repu);
+		}
+	}
+
+	/// Process the warp proofs and the state chunks verified in the background since the last
+	/// call, pending until any was.
+	///
+	/// Returns the peers whose warp proof requests were made obsolete by an invalid proof, their
+	/// responses should be dropped.
+	pub fn poll_verified_proofs(&mut self, cx: &mut std::task::Context) -> Poll<Vec<PeerId>> {
+		let warp_proofs = self.poll_warp_proofs(cx);
+		let state = self.poll_state_verification(cx);
+		match (warp_proofs, state) {
+			(Poll::Ready(obsolete), _) => Poll::Ready(obsolete),
+			(Poll::Pending, Poll::Ready(())) => Poll::Ready(Vec::new()),
+			(Poll::Pending, Poll::Pending) => Poll::Pending,
+		}
+	}
+
+	/// Process the warp proofs verified since the last call, reporting the peers that sent
+	/// invalid ones.
+	fn poll_warp_proofs(&mut self, cx: &mut std::task::Context) -> Poll<Vec<PeerId>> {
+		let Some(sync) = &mut self.warp_sync else { return Poll::Pending };
+
+		let results = sync.poll_verified_proofs(cx);
+		if results.is_empty() {
+			return Poll::Pending
+		}
+
+		let mut obsolete = Vec::new();
+		for (peer_id, result) in results {
+			self.allowed_requests.set_all();
+			if let WarpProofImportResult::BadResponse = result {
+				debug!(target: LOG_TARGET, "Bad proof data received from {peer_id}");
+				self.network_service
+					.disconnect_peer(peer_id, self.block_announce_protocol_name.clone());
+				self.network_service.report_peer(peer_id, rep::BAD_BLOCK);
+
+				// The pending request starts at a block proven by the invalid proof.
+				for (id, peer) in self.peers.iter_mut() {
+					if let PeerSyncState::DownloadingWarpProof = peer.state {
+						peer.state = PeerSyncState::Available;
+						obsolete.push(*id);
+					}
+				}
+			}
+		}
+
+		Poll::Ready(obsolete)
+	}
+
+	fn justification_requests(&mut self) -> Vec<(PeerId, BlockRequest<B>)> {
+		let peers = &mut self.peers;
+		let request_peers = &self.request_peers;
+		let justification_peers = &self.justification_peers;
+		let request_retries = &self.request_retries;
+		let now = Instant::now();
+		let mut matcher = self.extra_justifications.matcher();
+		std::iter::from_fn(move || {
+			if let Some((peer, request)) = matcher.next(peers, |peer_id| {
+				(justification_peers.contains(peer_id) ||
+					may_request(request_peers, justification_peers, peer_id)) &&
+					!request_retries.is_backing_off(peer_id, PeerRequestType::Block, now)
+			}) {
+				peers
+					.get_mut(&peer)
+					.expect(
+						"`Matcher::next` guarantees the `PeerId` comes from the given peers; qed",
+					)
+					.state = PeerSyncState::DownloadingJustification(request.0);
+				Some((peer, justification_request::<B>(request.0)))
+			} else {
+				None
+			}
+		})
+		.collect()
+	}
+
+	fn block_requests(&mut self) -> Vec<(PeerId, BlockRequest<B>)> {
+		if self.mode == SyncMode::Warp {
+			return self
+				.warp_target_block_request()
+				.map_or_else(|| Vec::new(), |req| Vec::from([req]))
+		}
+
+		if self.allowed_requests.is_empty() || self.state_sync.is_some() {
+			return Vec::new()
+		}
+
+		let queued = self.queue_blocks.len();
+		if self.import_queue_full && queued < self.import_queue_low_water_mark {
+			debug!(target: LOG_TARGET, "Import queue drained to {queued} blocks, resuming");
+			self.import_queue_full = false;
+		} else if !self.import_queue_full && queued > self.import_queue_high_water_mark {
+			debug!(target: LOG_TARGET, "{queued} blocks in the import queue, pausing requests");
+			self.import_queue_full = true;
+		}
+		if self.import_queue_full {
+			trace!(target: LOG_TARGET, "Too many blocks in the queue.");
+			return Vec::new()
+		}
+		let is_major_syncing = self.status().state.is_major_syncing();
+		let attrs = self.required_block_attributes();
+		let sync_target = self.sync_target_number();
+		let last_finalized =
+			std::cmp::min(self.best_queued_number, self.client.info().finalized_number);
+		let best_queued = self.best_queued_number;
+		let allowed_requests = self.allowed_requests.take();
+		let max_parallel = if self.racing_downloads { self.max_parallel_downloads } else { 1 };
+		let max_ranges = if is_major_syncing { Some(self.parallel_downloads) } else { None };
+		let near_target = self.median_seen().map_or(false, |target| {
+			target > best_queued &&
+				(target - best_queued).saturated_into::<u32>() < self.tail_race_blocks
+		});
+		let max_parallel = if near_target { max_parallel.max(2) } else { max_parallel };
+		let now = Instant::now();
+		let mut gap_requests = self
+			.peers
+			.values()
+			.filter(|peer| matches!(peer.state, PeerSyncState::DownloadingGap(_)))
+			.count();
+		let has_preferred_peers =
+			self.peers.values().any(|peer| !peer.is_slow() && !peer.best_stale);
+		let mut selected = self.peers.values().collect::<Vec<_>>();
+		self.peer_selection.order(&mut selected);
+		let request_peers = &self.request_peers;
+		let justification_peers = &self.justification_peers;
+		let request_retries = &self.request_retries;
+		let mut available = selected
+			.into_iter()
+			.filter(|peer| {
+				peer.state.is_available() &&
+					allowed_requests.contains(&peer.peer_id) &&
+					may_request(request_peers, justification_peers, &peer.peer_id) &&
+					!request_retries.is_backing_off(&peer.peer_id, PeerRequestType::Block, now)
+			})
+			.map(|peer| peer.peer_id)
+			.collect::<Vec<_>>();
+		let had_available = !available.is_empty();
+
+		let order = self.request_order();
+		let with_work = order
+			.iter()
+			.copied()
+			.filter(|priority| match priority {
+				RequestPriority::BestChain => true,
+				RequestPriority::Justification =>
+					self.extra_justifications.pending_requests().next().is_some(),
+				RequestPriority::Fork => !self.fork_targets.is_empty(),
+				RequestPriority::Gap => self.gap_sync.is_some(),
+			})
+			.collect::<Vec<_>>();
+
+		// A peer sent many requests of one kind in a row is first offered the other kinds, and
+		// only offered that kind again on the second pass if none had a request for it.
+		let mut requests = Vec::new();
+		let mut deferred = HashSet::new();
+		let passes = [true, false]
+			.into_iter()
+			.flat_map(|first_pass| order.iter().map(move |priority| (*priority, first_pass)));
+		for (priority, first_pass) in passes {
+			let has_work = with_work.contains(&priority);
+			if first_pass {
+				if has_work && had_available && available.is_empty() {
+					*self.starved_requests.entry(priority).or_default() += 1;
+				} else {
+					self.starved_requests.remove(&priority);
+				}
+			}
+			if !has_work || available.is_empty() {
+				continue
+			}
+			let skip = available
+				.iter()
+				.filter(|id| {
+					if first_pass {
+						self.peers.get(*id).map_or(false, |peer| {
+							defers_request(peer.request_run, priority, &with_work)
+						})
+					} else {
+						!deferred.contains(*id)
+					}
+				})
+				.copied()
+				.collect::<HashSet<_>>();
+			if first_pass {
+				deferred.extend(skip.iter().copied());
+			} else if skip.len() == available.len() {
+				continue
+			}
+
+			let peers = &mut self.peers;
+			match priority {
+				RequestPriority::BestChain => {
+					let blocks = &mut self.blocks;
+					let queue = &self.queue_blocks;
+					available.retain(|id| {
+						if skip.contains(id) {
+							return true
+						}
+						let Some(peer) = peers.get_mut(id) else { return false };
+						// Slow peers and peers whose best block is out of date are kept off the
+						// critical path while other peers can download it.
+						let critical_path =
+							!(peer.is_slow() || peer.best_stale) || !has_preferred_peers;
+						let ranges_left = max_ranges
+							.map_or(true, |max| blocks.downloading_ranges() < max as usize);
+
+						// If our best queued is more than `MAX_BLOCKS_TO_LOOK_BACKWARDS` blocks away
+						// from the common number, the peer best number is higher than our best
+						// queued and the common number is smaller than the last finalized block
+						// number, we should do an ancestor search to find a better common block. If
+						// the queue is full we wait till all blocks are imported though.
+						if best_queued.saturating_sub(peer.common_number) >
+							MAX_BLOCKS_TO_LOOK_BACKWARDS.into() &&
+							best_queued < peer.best_number &&
+							sync_target.map_or(true, |target| best_queued < target) &&
+							peer.common_number < last_finalized &&
+							queue.len() <= MAJOR_SYNC_BLOCKS.into()
+						{
+							trace!(
+								target: LOG_TARGET,
+								"Peer {:?} common block {} too far behind of our best {}. Starting ancestry search.",
+								id,
+								peer.common_number,
+								best_queued,
+							);
+							let current = std::cmp::min(peer.best_number, best_queued);
+							peer.state = PeerSyncState::AncestorSearch {
+								current,
+								start: best_queued,
+								state: AncestorSearchState::ExponentialBackoff(One::one()),
+							};
+							peer.note_request(priority);
+							requests.push((*id, ancestry_request::<B>(current)));
+							false
+						} else if let Some((range, req)) = (critical_path && ranges_left)
+							.then(|| {
+								peer_block_request(
+									id,
+									peer,
+									blocks,
+									attrs,
+									max_parallel,
+									peer.max_blocks_per_request,
+									last_finalized,
+									best_queued,
+									sync_target,
+								)
+							})
+							.flatten()
+						{
+							peer.state = PeerSyncState::DownloadingNew(range.start);
+							peer.note_request(priority);
+							trace!(
+								target: LOG_TARGET,
+								"New block request for {}, (best:{}, common:{}) {:?}",
+								id,
+								peer.best_number,
+								peer.common_number,
+								req,
+							);
+							requests.push((*id, req));
+							false
+						} else {
+							true
+						}
+					});
+				},
+				RequestPriority::Justification => {
+					let mut matcher = self.extra_justifications.matcher();
+					while let Some((id, request)) = matcher.next(peers, |peer_id| {
+						available.contains(peer_id) && !skip.contains(peer_id)
+					}) {
+						let peer = peers.get_mut(&id).expect(
+							"`Matcher::next` guarantees the `PeerId` comes from the given peers; qed",
+						);
+						peer.state = PeerSyncState::DownloadingJustification(request.0);
+						peer.note_request(priority);
+						available.retain(|peer_id| *peer_id != id);
+						requests.push((id, justification_request::<B>(request.0)));
+					}
+				},
+				RequestPriority::Fork => {
+					let fork_targets = &mut self.fork_targets;
+					let fork_choice = &*self.fork_choice;
+					let client = &self.client;
+					let queue = &self.queue_blocks;
+					available.retain(|id| {
+						if skip.contains(id) {
+							return true
+						}
+						let Some(peer) = peers.get_mut(id) else { return false };
+						let Some((hash, req)) = fork_sync_request(
+							id,
+							fork_targets,
+							best_queued,
+							last_finalized,
+							attrs,
+							|hash| {
+								if queue.contains(hash) {
+									BlockStatus::Queued
+								} else {
+									client.block_status(*hash).unwrap_or(BlockStatus::Unknown)
+								}
+							},
+							peer.max_blocks_per_request,
+							sync_target,
+							fork_choice,
+						) else {
+							return true
+						};
+						trace!(target: LOG_TARGET, "Downloading fork {hash:?} from {id}");
+						peer.state = PeerSyncState::DownloadingStale(hash);
+						peer.note_request(priority);
+						requests.push((*id, req));
+						false
+					});
+				},
+				RequestPriority::Gap => {
+					let gap_sync = &mut self.gap_sync;
+					let max_gap_requests = self.max_gap_requests;
+					let gap_request_limiter = &mut self.gap_request_limiter;
+					available.retain(|id| {
+						if skip.contains(id) {
+							return true
+						}
+						let Some(peer) = peers.get_mut(id) else { return false };
+						let Some((range, req)) = gap_sync
+							.as_mut()
+							.filter(|_| max_gap_requests.map_or(true, |max| gap_requests < max))
+							.filter(|_| {
+								gap_request_limiter
+									.as_mut()
+									.map_or(true, |limiter| limiter.has_token(now))
+							})
+							.and_then(|sync| {
+								peer_gap_block_request(
+									id,
+									peer,
+									&mut sync.blocks,
+									&sync.anchors,
+									attrs,
+									sync.target,
+									sync.best_queued_number,
+									peer.max_blocks_per_request,
+								)
+							})
+						else {
+							return true
+						};
+						peer.state = PeerSyncState::DownloadingGap(range.start);
+						peer.note_request(priority);
+						gap_requests += 1;
+						if let Some(limiter) = gap_request_limiter.as_mut() {
+							limiter.try_acquire(now);
+						}
+						trace!(
+							target: LOG_TARGET,
+							"New gap block request for {}, (best:{}, common:{}) {:?}",
+							id,
+							peer.best_number,
+							peer.common_number,
+							req,
+						);
+						requests.push((*id, req));
+						false
+					});
+				},
+			}
+		}
+
+		requests
+	}
+
+	/// The order in which the kinds of requests are assigned peers in this round: by priority,
+	/// except for the kinds starved for `MAX_STARVED_ROUNDS` rounds, which go first.
+	fn request_order(&self) -> Vec<RequestPriority> {
+		let mut order = RequestPriority::ALL.to_vec();
+		order.sort_by_key(|priority| {
+			let starved = self
+				.starved_requests
+				.get(priority)
+				.map_or(false, |rounds| *rounds >= MAX_STARVED_ROUNDS);
+			(!starved, *priority)
+		});
+		order
+	}
+
+	fn state_request(&mut self) -> Option<(PeerId, OpaqueStateRequest)> {
+		if self.allowed_requests.is_empty() {
+			return None
+		}
+		if (self.state_sync.is_some() || self.warp_sync.is_some()) &&
+			self.peers.iter().any(|(_, peer)| peer.state == PeerSyncState::DownloadingState)
+		{
+			// Only one pending state request is allowed.
+			return None
+		}
+		let now = Instant::now();
+		if self.state_sync.as_ref().map_or(false, |sync| sync.base().is_some()) &&
+			!self.peers.values().any(|peer| peer.state_modes & STATE_MODE_DELTA != 0)
+		{
+			let skip_proofs = match self.mode {
+				SyncMode::LightState { skip_proofs, .. } => skip_proofs,
+				_ => false,
+			};
+			if let Some(sync) = self.state_sync.take() {
+				debug!(
+					target: LOG_TARGET,
+					"No peer serves state deltas, downloading the whole state instead.",
+				);
+				self.state_sync = Some(sync.without_base(skip_proofs));
+			}
+		}
+		if let Some(sync) = &self.state_sync {
+			if sync.is_complete() || sync.is_verifying() {
+				return None
+			}
+
+			for (id, peer) in self.peers.iter_mut() {
+				let mut request = sync.next_request();
+				if peer.state.is_available() &&
+					peer.common_number >= sync.target_block_num() &&
+					peer.adapt_state_request(&mut request) &&
+					may_request(&self.request_peers, &self.justification_peers, id) &&
+					!self.request_retries.is_backing_off(id, PeerRequestType::State, now)
+				{
+					peer.state = PeerSyncState::DownloadingState;
+					trace!(target: LOG_TARGET, "New StateRequest for {}: {:?}", id, request);
+					self.allowed_requests.clear();
+					return Some((*id, OpaqueStateRequest(Box::new(request))))
+				}
+			}
+		}
+		if let Some(sync) = &self.warp_sync {
+			if sync.is_complete() {
+				return None
+			}
+			if let (Some(request), Some(target)) =
+				(sync.next_state_request(), sync.target_block_number())
+			{
+				for (id, peer) in self.peers.iter_mut() {
+					let mut request = request.clone();
+					if peer.state.is_available() &&
+						peer.best_number >= target &&
+						peer.adapt_state_request(&mut request) &&
+						may_request(&self.request_peers, &self.justification_peers, id) &&
+						!self.request_retries.is_backing_off(id, PeerRequestType::State, now)
+					{
+						trace!(target: LOG_TARGET, "New StateRequest for {id}: {request:?}");
+						peer.state = PeerSyncState::DownloadingState;
+						self.allowed_requests.clear();
+						return Some((*id, OpaqueStateRequest(Box::new(request))))
+					}
+				}
+			}
+		}
+		None
+	}
+
+	fn warp_sync_request(&mut self) -> Option<(PeerId, WarpProofRequest<B>)> {
+		if let Some(sync) = &self.warp_sync {
+			if self.allowed_requests.is_empty() ||
+				sync.is_complete() ||
+				self.peers
+					.iter()
+					.any(|(_, peer)| peer.state == PeerSyncState::DownloadingWarpProof)
+			{
+				// Only one pending state request is allowed.
+				return None
+			}
+			if let Some(request) = sync.next_warp_proof_request() {
+				let mut targets: Vec<_> = self.peers.values().map(|p| p.best_number).collect();
+				if !targets.is_empty() {
+					targets.sort();
+					let median = targets[targets.len() / 2];
+					// Find a peer that is synced as much as peer majority, preferring the peer that
+					// reported the highest finalized block, as warp proofs lead up to it.
+					let request_peers = &self.request_peers;
+					let justification_peers = &self.justification_peers;
+					let request_retries = &self.request_retries;
+					let now = Instant::now();
+					if let Some((id, peer)) =
+						self.peers
+							.iter_mut()
+							.filter(|(id, peer)| {
+								peer.state.is_available() &&
+									peer.best_number >= median &&
+									may_request(request_peers, justification_peers, id) &&
+									!request_retries.is_backing_off(
+										id,
+										PeerRequestType::WarpProof,
+										now,
+									)
+							})
+							.max_by_key(|(_, peer)| peer.finalized_number)
+					{
+						trace!(target: LOG_TARGET, "New WarpProofRequest for {id}");
+						peer.state = PeerSyncState::DownloadingWarpProof;
+						self.allowed_requests.clear();
+						return Some((*id, request))
+					}
+				}
+			}
+		}
+		None
+	}
+
+	fn on_state_data(
+		&mut self,
+		who: &PeerId,
+		response: OpaqueStateResponse,
+	) -> Result<OnStateData<B>, BadPeer> {
+		let response: Box<StateResponse> = response.0.downcast().map_err(|_error| {
+			error!(
+				target: LOG_TARGET,
+				"Failed to downcast opaque state response, this is an implementation bug."
+			);
+
+			BadPeer(*who, rep::BAD_RESPONSE)
+		})?;
+
+		if let Some(peer) = self.peers.get_mut(who) {
+			if let PeerSyncState::DownloadingState = peer.state {
+				peer.state = PeerSyncState::Available;
+				self.allowed_requests.set_all();
+			}
+		}
+		if response.entries.is_empty() && response.proof.is_empty() && response.modes != 0 {
+			let request = match (&self.state_sync, &self.warp_sync) {
+				(Some(sync), _) => Some(sync.next_request()),
+				(None, Some(sync)) => sync.next_state_request(),
+				(None, None) => None,
+			};
+			if let (Some(mut request), Some(peer)) = (request, self.peers.get(who)) {
+				if !peer.adapt_state_request(&mut request) {
+					// Not sent such requests anymore, as recorded from the advertised modes.
+					debug!(target: LOG_TARGET, "{who} doesn't serve the requested state mode");
+					return Ok(OnStateData::Continue)
+				}
+			}
+		}
+		let (target, prepared) = if let Some(sync) = &mut self.state_sync {
+			debug!(
+				target: LOG_TARGET,
+				"Importing state data from {} with {} keys, {} proof nodes.",
+				who,
+				response.entries.len(),
+				response.proof.len(),
+			);
+			(sync.target(), sync.prepare_import(*response))
+		} else if let Some(sync) = &mut self.warp_sync {
+			debug!(
+				target: LOG_TARGET,
+				"Importing state data from {} with {} keys, {} proof nodes.",
+				who,
+				response.entries.len(),
+				response.proof.len(),
+			);
+			let target = sync.target_block_hash().unwrap_or_default();
+			(target, sync.prepare_state_import(*response))
+		} else {
+			debug!(target: LOG_TARGET, "Ignored obsolete state response from {who}");
+			return Err(BadPeer(*who, rep::NOT_REQUESTED))
+		};
+
+		let import_result = match prepared {
+			PreparedImport::Imported(import_result) => import_result,
+			PreparedImport::Verify(verification) => {
+				let pool = self.state_verification.get_or_insert_with(|| {
+					VerificationPool::new(
+						"state-verification",
+						STATE_VERIFICATION_WORKERS,
+						STATE_VERIFICATION_QUEUE,
+					)
+				});
+				let peer_id = *who;
+				match pool.push(Box::new(move || (peer_id, target, verification.run()))) {
+					Ok(()) => return Ok(OnStateData::Continue),
+					Err(job) => {
+						let (_, _, verified) = job();
+						self.import_verified_state(target, verified)
+							.expect("The state proof being verified is of the current target; qed")
+					},
+				}
+			},
+		};
+
+		self.on_state_imported(who, import_result)
+	}
+
+	/// Import a state chunk verified by the [`VerificationPool`], proving the state of `target`.
+	///
+	/// Returns `None` if the state of `target` isn't downloaded anymore.
+	fn import_verified_state(
+		&mut self,
+		target: B::Hash,
+		verified: Option<VerifiedState>,
+	) -> Option<state::ImportResult<B>> {
+		if let Some(sync) = &mut self.state_sync {
+			(sync.target() == target && sync.is_verifying()).then(|| sync.import_verified(verified))
+		} else if let Some(sync) = &mut self.warp_sync {
+			if sync.target_block_hash() != Some(target) {
+				return None
+			}
+			sync.import_verified_state(verified)
+		} else {
+			None
+		}
+	}
+
+	/// Import the state chunks verified since the last call, reporting the peers that sent invalid
+	/// ones.
+	fn poll_state_verification(&mut self, cx: &mut std::task::Context) -> Poll<()> {
+		let Some(pool) = &mut self.state_verification else { return Poll::Pending };
+
+		let results = pool.poll_results(cx);
+		let any_verified = !results.is_empty();
+		for (peer_id, target, verified) in results {
+			self.allowed_requests.set_all();
+			let Some(import_result) = self.import_verified_state(target, verified) else {
+				debug!(target: LOG_TARGET, "Ignored obsolete state data from {peer_id}");
+				continue
+			};
+			match self.on_state_imported(&peer_id, import_result) {
+				Ok(OnStateData::Import(origin, block)) => self.import_blocks(origin, vec![block]),
+				Ok(OnStateData::Continue) => {},
+				Err(BadPeer(id, repu)) => {
+					self.network_service
+						.disconnect_peer(id, self.block_announce_protocol_name.clone());
+					self.network_service.report_peer(id, repu);
+				},
+			}
+		}
+
+		if self.state_verification.as_ref().map_or(false, |pool| pool.pending() == 0) &&
+			self.state_sync.is_none() &&
+			self.warp_sync.is_none()
+		{
+			self.state_verification = None;
+		}
+
+		if any_verified {
+			Poll::Ready(())
+		} else {
+			Poll::Pending
+		}
+	}
+
+	fn on_state_imported(
+		&mut self,
+		who: &PeerId,
+		import_result: state::ImportResult<B>,
+	) -> Result<OnStateData<B>, BadPeer> {
+		match import_result {
+			state::ImportResult::Import(hash, header, state, body, justifications) => {
+				let origin = BlockOrigin::NetworkInitialSync;
+				let block = IncomingBlock {
+					hash,
+					header: Some(header),
+					body,
+					indexed_body: None,
+					justifications,
+					origin: None,
+					allow_missing_state: true,
+					import_existing: true,
+					skip_execution: self.skip_execution(),
+					state: Some(state),
+				};
+				debug!(target: LOG_TARGET, "State download is complete. Import is queued");
+				Ok(OnStateData::Import(origin, block))
+			},
+			state::ImportResult::Continue => Ok(OnStateData::Continue),
+			state::ImportResult::BadResponse => {
+				debug!(target: LOG_TARGET, "Bad state data received from {who}");
+				Err(BadPeer(*who, rep::BAD_BLOCK))
+			},
+		}
+	}
+
+	fn on_warp_sync_data(&mut self, who: &PeerId, response: EncodedProof) -> Result<(), BadPeer> {
+		if let Some(peer) = self.peers.get_mut(who) {
+			if let PeerSyncState::DownloadingWarpProof = peer.state {
+				peer.state = PeerSyncState::Available;
+				self.allowed_requests.set_all();
+			}
+		}
+		let import_result = if let Some(sync) = &mut self.warp_sync {
+			debug!(
+				target: LOG_TARGET,
+				"Importing warp proof data from {}, {} bytes.",
+				who,
+				response.0.len(),
+			);
+			sync.import_warp_proof(*who, response)
+		} else {
+			debug!(target: LOG_TARGET, "Ignored obsolete warp sync response from {who}");
+			return Err(BadPeer(*who, rep::NOT_REQUESTED))
+		};
+
+		match import_result {
+			WarpProofImportResult::Success => Ok(()),
+			WarpProofImportResult::BadResponse => {
+				debug!(target: LOG_TARGET, "Bad proof data received from {who}");
+				Err(BadPeer(*who, rep::BAD_BLOCK))
+			},
+		}
+	}
+
+	fn import_blocks(&mut self, origin: BlockOrigin, blocks: Vec<IncomingBlock<B>>) {
+		if let Some(metrics) = &self.metrics {
+			metrics.import_queue_blocks_submitted.inc();
+		}
+
+		self.import_queue.import_blocks(origin, blocks);
+	}
+
+	fn import_justifications(
+		&mut self,
+		peer: PeerId,
+		hash: B::Hash,
+		number: NumberFor<B>,
+		justifications: Justifications,
+	) {
+		if let Some(metrics) = &self.metrics {
+			metrics.import_queue_justifications_submitted.inc();
+		}
+
+		self.import_queue.import_justifications(peer, hash, number, justifications);
+	}
+
+	/// A batch of blocks have been processed, with or without errors.
+	///
+	/// Call this when a batch of blocks have been processed by the import
+	/// queue, with or without errors. If an error is returned, the pending response
+	/// from the peer must be dropped.
+	fn on_blocks_processed(
+		&mut self,
+		imported: usize,
+		count: usize,
+		results: Vec<(Result<BlockImportStatus<NumberFor<B>>, BlockImportError>, B::Hash)>,
+	) -> Box<dyn Iterator<Item = Result<BlockRequestEvent<B>, BadPeer>>> {
+		trace!(target: LOG_TARGET, "Imported {imported} of {count}");
+
+		let mut output = Vec::new();
+
+		let mut has_error = false;
+		for (_, hash) in &results {
+			self.queue_blocks.remove(hash);
+			self.blocks.clear_queued(hash);
+			if let Some(gap_sync) = &mut self.gap_sync {
+				gap_sync.blocks.clear_queued(hash);
+			}
+		}
+		for (result, hash) in results {
+			if has_error {
+				break
+			}
+
+			has_error |= result.is_err();
+
+			match result {
+				Ok(BlockImportStatus::ImportedKnown(number, who)) =>
+					if let Some(peer) = who {
+						self.update_peer_common_number(&peer, number);
+					},
+				Ok(BlockImportStatus::ImportedUnknown(number, aux, who)) => {
+					if aux.clear_justification_requests {
+						trace!(
+							target: LOG_TARGET,
+							"Block imported clears all pending justification requests {number}: {hash:?}",
+						);
+						self.clear_justification_requests();
+					}
+
+					if aux.needs_justification {
+						trace!(
+							target: LOG_TARGET,
+							"Block imported but requires justification {number}: {hash:?}",
+						);
+						self.request_justification(&hash, number);
+					}
+
+					if aux.bad_justification {
+						if let Some(ref peer) = who {
+							warn!("💔 Sent block with bad justification to import");
+							output.push(Err(BadPeer(*peer, rep::BAD_JUSTIFICATION)));
+						}
+					}
+
+					if let Some(peer) = who {
+						self.update_peer_common_number(&peer, number);
+					}
+					let state_sync_complete =
+						self.state_sync.as_ref().map_or(false, |s| s.target() == hash);
+					if state_sync_complete {
+						let size = self
+							.state_sync
+							.take()
+							.map_or(0, |s| s.progress().size / (1024 * 1024));
+						// Catch up with the state delta if the chain moved on while downloading.
+						if self.median_seen().map_or(false, |median| {
+							median > number + STATE_DELTA_SYNC_THRESHOLD.saturated_into()
+						}) {
+							info!(
+								target: LOG_TARGET,
+								"State sync is complete ({size} MiB), syncing the state delta to a newer block.",
+							);
+							self.state_delta_base = Some(hash);
+						} else {
+							info!(
+								target: LOG_TARGET,
+								"State sync is complete ({size} MiB), restarting block sync.",
+							);
+							self.state_delta_base = None;
+							self.mode = SyncMode::Full;
+						}
+						output.extend(self.restart());
+					}
+					let warp_sync_complete = self
+						.warp_sync
+						.as_ref()
+						.map_or(false, |s| s.target_block_hash() == Some(hash));
+					if warp_sync_complete {
+						info!(
+							target: LOG_TARGET,
+							"Warp sync is complete ({} MiB), restarting block sync.",
+							self.warp_sync.as_ref().map_or(0, |s| s.progress().total_bytes / (1024 * 1024)),
+						);
+						if let Some(warp_sync) = self.warp_sync.take() {
+							warp_sync.clear_progress();
+						}
+						self.mode = SyncMode::Full;
+						output.extend(self.restart());
+					}
+					let gap_sync_complete =
+						self.gap_sync.as_ref().map_or(false, |s| s.target == number);
+					if gap_sync_complete {
+						info!(
+							target: LOG_TARGET,
+							"Block history download is complete."
+						);
+						self.gap_sync = None;
+					}
+				},
+				Err(BlockImportError::IncompleteHeader(who)) =>
+					if let Some(peer) = who {
+						warn!(
+							target: LOG_TARGET,
+							"💔 Peer sent block with incomplete header to import",
+						);
+						output.push(Err(BadPeer(peer, rep::INCOMPLETE_HEADER)));
+						output.extend(self.restart());
+					},
+				Err(BlockImportError::VerificationFailed(who, e)) => {
+					let extra_message =
+						who.map_or_else(|| "".into(), |peer| format!(" received from ({peer})"));
+
+					warn!(
+						target: LOG_TARGET,
+						"💔 Verification failed for block {hash:?}{extra_message}: {e:?}",
+					);
+
+					if let Some(peer) = who {
+						output.push(Err(BadPeer(peer, rep::VERIFICATION_FAIL)));
+					}
+
+					output.extend(self.restart());
+				},
+				Err(BlockImportError::BadBlock(who)) =>
+					if let Some(peer) = who {
+						warn!(
+							target: LOG_TARGET,
+							"💔 Block {hash:?} received from peer {peer} has been blacklisted",
+						);
+						output.push(Err(BadPeer(peer, rep::BAD_BLOCK)));
+					},
+				Err(BlockImportError::MissingState) => {
+					// This may happen if the chain we were requesting upon has been discarded
+					// in the meantime because other chain has been finalized.
+					// Don't mark it as bad as it still may be synced if explicitly requested.
+					trace!(target: LOG_TARGET, "Obsolete block {hash:?}");
+				},
+				e @ Err(BlockImportError::UnknownParent) | e @ Err(BlockImportError::Other(_)) => {
+					warn!(target: LOG_TARGET, "💔 Error importing block {hash:?}: {}", e.unwrap_err());
+					self.state_sync = None;
+					self.state_delta_base = None;
+					self.warp_sync = None;
+					output.extend(self.restart());
+				},
+				Err(BlockImportError::Cancelled) => {},
+			};
+		}
+
+		self.allowed_requests.set_all();
+		Box::new(output.into_iter())
+	}
+}
+
+// This is purely during a backwards compatible transitionary period and should be removed
+// once we can assume all nodes can send and receive multiple Justifications
+// The ID tag is hardcoded here to avoid depending on the GRANDPA crate.
+// See: https://github.com/paritytech/substrate/issues/8172
+fn legacy_justification_mapping(
+	justification: Option<EncodedJustification>,
+) -> Option<Justifications> {
+	justification.map(|just| (*b"FRNK", just).into())
+}
+
+/// Request the ancestry for a block. Sends a request for header and justification for the given
+/// block number. Used during ancestry search.
+fn ancestry_request<B: BlockT>(block: NumberFor<B>) -> BlockRequest<B> {
+	BlockRequest::<B> {
+		id: 0,
+		fields: BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION,
+		from: FromBlock::Number(block),
+		direction: Direction::Ascending,
+		max: Some(1),
+	}
+}
+
+/// The ancestor search state expresses which algorithm, and its stateful parameters, we are using
+/// to try to find an ancestor block
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AncestorSearchState<B: BlockT> {
+	/// Use exponential backoff to find an ancestor, then switch to binary search.
+	/// We keep track of the exponent.
+	ExponentialBackoff(NumberFor<B>),
+	/// Using binary search to find the best ancestor.
+	/// We keep track of left and right bounds.
+	BinarySearch(NumberFor<B>, NumberFor<B>),
+}
+
+/// This function handles the ancestor search strategy used. The goal is to find a common point
+/// that both our chains agree on that is as close to the tip as possible.
+/// The way this works is we first have an exponential backoff strategy, where we try to step
+/// forward until we find a block hash mismatch. The size of the step doubles each step we take.
+///
+/// When we've found a block hash mismatch we then fall back to a binary search between the two
+/// last known points to find the common block closest to the tip.
+fn handle_ancestor_search_state<B: BlockT>(
+	state: &AncestorSearchState<B>,
+	curr_block_num: NumberFor<B>,
+	block_hash_match: bool,
+	known_common: NumberFor<B>,
+) -> Option<(AncestorSearchState<B>, NumberFor<B>)> {
+	let two = <NumberFor<B>>::one() + <NumberFor<B>>::one();
+	match state {
+		AncestorSearchState::ExponentialBackoff(next_distance_to_tip) => {
+			let next_distance_to_tip = *next_distance_to_tip;
+			if block_hash_match && next_distance_to_tip == One::one() {
+				// We found the ancestor in the first step so there is no need to execute binary
+				// search.
+				return None
+			}
+			if block_hash_match {
+				let left = curr_block_num;
+				let right = left + next_distance_to_tip / two;
+				let middle = left + (right - left) / two;
+				Some((AncestorSearchState::BinarySearch(left, right), middle))
+			} else {
+				let next_block_num =
+					curr_block_num.checked_sub(&next_distance_to_tip).unwrap_or_else(Zero::zero);
+				if !known_common.is_zero() && next_block_num <= known_common {
+					// Don't probe below a block known to be common, binary search above it.
+					let left = known_common;
+					let middle = left + (curr_block_num - left) / two;
+					if middle == left {
+						return None
+					}
+					return Some((AncestorSearchState::BinarySearch(left, curr_block_num), middle))
+				}
+				let next_distance_to_tip = next_distance_to_tip * two;
+				Some((
+					AncestorSearchState::ExponentialBackoff(next_distance_to_tip),
+					next_block_num,
+				))
+			}
+		},
+		AncestorSearchState::BinarySearch(mut left, mut right) => {
+			if left >= curr_block_num {
+				return None
+			}
+			if block_hash_match {
+				left = curr_block_num;
+			} else {
+				right = curr_block_num;
+			}
+			assert!(right >= left);
+			let middle = left + (right - left) / two;
+			if middle == curr_block_num {
+				None
+			} else {
+				Some((AncestorSearchState::BinarySearch(left, right), middle))
+			}
+		},
+	}
+}
+
+/// Get a new block request for the peer if any.
+fn peer_block_request<B: BlockT>(
+	id: &PeerId,
+	peer: &PeerSync<B>,
+	blocks: &mut BlockCollection<B>,
+	attrs: BlockAttributes,
+	max_parallel_downloads: u32,
+	max_blocks_per_request: u32,
+	finalized: NumberFor<B>,
+	best_num: NumberFor<B>,
+	max_number: Option<NumberFor<B>>,
+) -> Option<(Range<NumberFor<B>>, BlockRequest<B>)> {
+	let peer_best = max_number.map_or(peer.best_number, |max| max.min(peer.best_number));
+	if best_num >= peer_best {
+		// Will be downloaded as alternative fork instead.
+		return None
+	} else if peer.common_number < finalized {
+		trace!(
+			target: LOG_TARGET,
+			"Requesting pre-finalized chain from {:?}, common={}, finalized={}, peer best={}, our best={}",
+			id, peer.common_number, finalized, peer.best_number, best_num,
+		);
+	}
+	let range = blocks.needed_blocks(
+		*id,
+		max_blocks_per_request,
+		peer_best,
+		peer.common_number,
+		max_parallel_downloads,
+		MAX_DOWNLOAD_AHEAD,
+	)?;
+
+	// The end is not part of the range.
+	let last = range.end.saturating_sub(One::one());
+
+	let from = if peer.best_number == last {
+		FromBlock::Hash(peer.best_hash)
+	} else {
+		FromBlock::Number(last)
+	};
+
+	let request = BlockRequest::<B> {
+		id: 0,
+		fields: range_attributes(peer, &range, attrs),
+		from,
+		direction: Direction::Descending,
+		max: Some((range.end - range.start).saturated_into::<u32>()),
+	};
+
+	Some((range, request))
+}
+
+/// The attributes to request the blocks in `range` from `peer` with.
+///
+/// The justifications of the blocks the peer has finalized are downloaded along with their
+/// bodies, sparing separate justification requests once they are imported. The peer has no
+/// justifications for the blocks above its finalized block yet, so they are not asked for.
+fn range_attributes<B: BlockT>(
+	peer: &PeerSync<B>,
+	range: &Range<NumberFor<B>>,
+	mut attrs: BlockAttributes,
+) -> BlockAttributes {
+	if peer.finalized_number.map_or(false, |finalized| range.start > finalized) {
+		attrs.remove(BlockAttributes::JUSTIFICATION);
+	}
+	attrs
+}
+
+/// Whether requests other than justification requests may be sent to `peer_id`.
+fn may_request(
+	request_peers: &Option<HashSet<PeerId>>,
+	justification_peers: &HashSet<PeerId>,
+	peer_id: &PeerId,
+) -> bool {
+	!justification_peers.contains(peer_id) &&
+		request_peers.as_ref().map_or(true, |peers| peers.contains(peer_id))
+}
+
+/// Request the justification of the block `hash`.
+fn justification_request<B: BlockT>(hash: B::Hash) -> BlockRequest<B> {
+	BlockRequest::<B> {
+		id: 0,
+		fields: BlockAttributes::JUSTIFICATION,
+		from: FromBlock::Hash(hash),
+		direction: Direction::Ascending,
+		max: Some(1),
+	}
+}
+
+/// Get a new block request for the peer if any.
+fn peer_gap_block_request<B: BlockT>(
+	id: &PeerId,
+	peer: &PeerSync<B>,
+	blocks: &mut BlockCollection<B>,
+	anchors: &HashMap<NumberFor<B>, B::Hash>,
+	attrs: BlockAttributes,
+	target: NumberFor<B>,
+	common_number: NumberFor<B>,
+	max_blocks_per_request: u32,
+) -> Option<(Range<NumberFor<B>>, BlockRequest<B>)> {
+	let range = blocks.needed_blocks(
+		*id,
+		max_blocks_per_request,
+		std::cmp::min(peer.best_number, target),
+		common_number,
+		1,
+		MAX_DOWNLOAD_AHEAD,
+	)?;
+
+	// The end is not part of the range.
+	let last = range.end.saturating_sub(One::one());
+	let from = anchors.get(&last).map_or(FromBlock::Number(last), |hash| FromBlock::Hash(*hash));
+
+	let request = BlockRequest::<B> {
+		id: 0,
+		fields: attrs,
+		from,
+		direction: Direction::Descending,
+		max: Some((range.end - range.start).saturated_into::<u32>()),
+	};
+	Some((range, request))
+}
+
+/// Check that the gap `blocks` starting at `start` link to the child of the highest one if its
+/// hash is known, and remember the hash of the block below them.
+fn link_gap_blocks<B: BlockT>(
+	anchors: &mut HashMap<NumberFor<B>, B::Hash>,
+	start: NumberFor<B>,
+	blocks: &[BlockData<B>],
+	who: &PeerId,
+) -> Result<(), BadPeer> {
+	let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else { return Ok(()) };
+	let last_number = start + (blocks.len() as u32 - 1).into();
+	if let Some(expected) = anchors.get(&last_number) {
+		if *expected != last.hash {
+			debug!(
+				target: LOG_TARGET,
+				"Gap block #{last_number} from {who} is {:?}, expected {expected:?}",
+				last.hash,
+			);
+			return Err(BadPeer(*who, rep::BAD_BLOCK))
+		}
+		anchors.remove(&last_number);
+	}
+	if let Some(header) = &first.header {
+		if !start.is_zero() {
+			anchors.insert(start - One::one(), *header.parent_hash());
+		}
+	}
+	Ok(())
+}
+
+/// Whether `block` or its parent is in `banned_blocks`.
+fn is_banned_block<B: BlockT>(banned_blocks: &HashSet<B::Hash>, block: &BlockData<B>) -> bool {
+	banned_blocks.contains(&block.hash) ||
+		block
+			.header
+			.as_ref()
+			.map_or(false, |header| banned_blocks.contains(header.parent_hash()))
+}
+
+/// Get pending fork sync targets for a peer.
+///
+/// If several targets can be downloaded from the peer, the one preferred by `fork_choice` is.
+fn fork_sync_request<B: BlockT>(
+	id: &PeerId,
+	targets: &mut HashMap<B::Hash, ForkTarget<B>>,
+	best_num: NumberFor<B>,
+	finalized: NumberFor<B>,
+	attributes: BlockAttributes,
+	check_block: impl Fn(&B::Hash) -> BlockStatus,
+	max_blocks_per_request: u32,
+	max_number: Option<NumberFor<B>>,
+	fork_choice: &dyn ForkChoice<B>,
+) -> Option<(B::Hash, BlockRequest<B>)> {
+	targets.retain(|hash, r| {
+		if r.number <= finalized {
+			trace!(
+				target: LOG_TARGET,
+				"Removed expired fork sync request {:?} (#{})",
+				hash,
+				r.number,
+			);
+			return false
+		}
+		if check_block(hash) != BlockStatus::Unknown {
+			trace!(
+				target: LOG_TARGET,
+				"Removed obsolete fork sync request {:?} (#{})",
+				hash,
+				r.number,
+			);
+			return false
+		}
+		true
+	});
+	let (hash, r) = targets
+		.iter()
+		.filter(|(hash, r)| {
+			if !r.peers.contains(id) || max_number.map_or(false, |max| r.number > max) {
+				return false
+			}
+			// Download the fork only if it is behind or not too far ahead our tip of the chain
+			// Otherwise it should be downloaded in full sync mode.
+			let in_range = r.number <= best_num ||
+				(r.number - best_num).saturated_into::<u32>() < max_blocks_per_request as u32;
+			if !in_range {
+				trace!(target: LOG_TARGET, "Fork too far in the future: {:?} (#{})", hash, r.number);
+			}
+			in_range
+		})
+		// Targets added without an announcement have no header to compare.
+		.max_by(|(_, a), (_, b)| match (&a.header, &b.header) {
+			(Some(a), Some(b)) => fork_choice.compare(a, b),
+			_ => a.number.cmp(&b.number),
+		})?;
+
+	let parent_status = r.parent_hash.as_ref().map_or(BlockStatus::Unknown, check_block);
+	let count = if parent_status == BlockStatus::Unknown {
+		(r.number - finalized).saturated_into::<u32>() // up to the last finalized block
+	} else {
+		// request only single block
+		1
+	};
+	trace!(target: LOG_TARGET, "Downloading requested fork {hash:?} from {id}, {count} blocks");
+	Some((
+		*hash,
+		BlockRequest::<B> {
+			id: 0,
+			fields: attributes,
+			from: FromBlock::Hash(*hash),
+			direction: Direction::Descending,
+			max: Some(count),
+		},
+	))
+}
+
+/// Returns `true` if the given `block` is a descendent of `base`.
+fn is_descendent_of<Block, T>(
+	client: &T,
+	base: &Block::Hash,
+	block: &Block::Hash,
+) -> sp_blockchain::Result<bool>
+where
+	Block: BlockT,
+	T: HeaderMetadata<Block, Error = sp_blockchain::Error> + ?Sized,
+{
+	if base == block {
+		return Ok(false)
+	}
+
+	let ancestor = sp_blockchain::lowest_common_ancestor(client, *block, *base)?;
+
+	Ok(ancestor.hash == *base)
+}
+
+/// Validate that the given `blocks` are correct.
+/// Returns the number of the first block in the sequence.
+///
+/// It is expected that `blocks` are in ascending order.
+fn validate_blocks<Block: BlockT>(
+	blocks: &Vec<BlockData<Block>>,
+	who: &PeerId,
+	request: Option<BlockRequest<Block>>,
+) -> Result<Option<NumberFor<Block>>, BadPeer> {
+	if let Some(request) = request {
+		if Some(blocks.len() as _) > request.max {
+			debug!(
+				target: LOG_TARGET,
+				"Received more blocks than requested from {}. Expected in maximum {:?}, got {}.",
+				who,
+				request.max,
+				blocks.len(),
+			);
+
+			return Err(BadPeer(*who, rep::NOT_REQUESTED))
+		}
+
+		let block_header =
+			if request.direction == Direction::Descending { blocks.last() } else { blocks.first() }
+				.and_then(|b| b.header.as_ref());
+
+		let expected_block = block_header.as_ref().map_or(false, |h| match request.from {
+			FromBlock::Hash(hash) => h.hash() == hash,
+			FromBlock::Number(n) => h.number() == &n,
+		});
+
+		if !expected_block {
+			debug!(
+				target: LOG_TARGET,
+				"Received block that was not requested. Requested {:?}, got {:?}.",
+				request.from,
+				block_header,
+			);
+
+			return Err(BadPeer(*who, rep::NOT_REQUESTED))
+		}
+
+		if request.fields.contains(BlockAttributes::HEADER) &&
+			blocks.iter().any(|b| b.header.is_none())
+		{
+			trace!(
+				target: LOG_TARGET,
+				"Missing requested header for a block in response from {who}.",
+			);
+
+			return Err(BadPeer(*who, rep::BAD_RESPONSE))
+		}
+
+		if request.fields.contains(BlockAttributes::BODY) && blocks.iter().any(|b| b.body.is_none())
+		{
+			trace!(
+				target: LOG_TARGET,
+				"Missing requested body for a block in response from {who}.",
+			);
+
+			return Err(BadPeer(*who, rep::BAD_RESPONSE))
+		}
+
+		// The blocks are in ascending order here, each must be the parent of the next one.
+		let unlinked = blocks.windows(2).any(|pair| match (&pair[0].header, &pair[1].header) {
+			(Some(_), Some(child)) => *child.parent_hash() != pair[0].hash,
+			_ => false,
+		});
+		if unlinked {
+			debug!(
+				target: LOG_TARGET,
+				"Received blocks that don't form a chain from {who}.",
+			);
+
+			return Err(BadPeer(*who, rep::BAD_BLOCK))
+		}
+	}
+
+	for b in blocks {
+		if let Some(header) = &b.header {
+			let hash = header.hash();
+			if hash != b.hash {
+				debug!(
+					target:LOG_TARGET,
+					"Bad header received from {}. Expected hash {:?}, got {:?}",
+					who,
+					b.hash,
+					hash,
+				);
+				return Err(BadPeer(*who, rep::BAD_BLOCK))
+			}
+		}
+		if let (Some(header), Some(body)) = (&b.header, &b.body) {
+			let expected = *header.extrinsics_root();
+			let got = HashingFor::<Block>::ordered_trie_root(
+				body.iter().map(Encode::encode).collect(),
+				sp_runtime::StateVersion::V0,
+			);
+			if expected != got {
+				debug!(
+					target:LOG_TARGET,
+					"Bad extrinsic root for a block {} received from {}. Expected {:?}, got {:?}",
+					b.hash,
+					who,
+					expected,
+					got,
+				);
+				return Err(BadPeer(*who, rep::BAD_BLOCK))
+			}
+		}
+	}
+
+	Ok(blocks.first().and_then(|b| b.header.as_ref()).map(|h| *h.number()))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::service::network::NetworkServiceProvider;
+	use futures::executor::block_on;
+	use sc_block_builder::BlockBuilderProvider;
+	use sc_network_common::sync::message::{BlockAnnounce, BlockData, BlockState, FromBlock};
+	use sp_blockchain::HeaderBackend;
+	use substrate_test_runtime_client::{
+		runtime::{Block, Hash, Header},
+		BlockBuilderExt, ClientBlockImportExt, ClientExt, DefaultTestClientBuilderExt, TestClient,
+		TestClientBuilder, TestClientBuilderExt,
+	};
+
+	/// `ChainSync` in full mode over `client`, without warp sync or metrics.
+	fn new_chain_sync(
+		client: Arc<TestClient>,
+		max_parallel_downloads: u32,
+		max_blocks_per_request: u32,
+	) -> ChainSync<Block, TestClient> {
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			max_parallel_downloads,
+			max_blocks_per_request,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn processes_empty_response_on_justification_request_for_unknown_block() {
+		// if we ask for a justification for a given block to a peer that doesn't know that block
+		// (different from not having a justification), the peer will reply with an empty response.
+		// internally we should process the response as the justification not being available.
+
+		let client = Arc::new(TestClientBuilder::new().build());
+		let peer_id = PeerId::random();
+
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let (a1_hash, a1_number) = {
+			let a1 = client.new_block(Default::default()).unwrap().build().unwrap().block;
+			(a1.hash(), *a1.header.number())
+		};
+
+		// add a new peer with the same best block
+		sync.new_peer(peer_id, a1_hash, a1_number).unwrap();
+
+		// and request a justification for the block
+		sync.request_justification(&a1_hash, a1_number);
+
+		// the justification request should be scheduled to that peer
+		assert!(sync
+			.justification_requests()
+			.iter()
+			.any(|(who, request)| { *who == peer_id && request.from == FromBlock::Hash(a1_hash) }));
+
+		// there are no extra pending requests
+		assert_eq!(sync.extra_justifications.pending_requests().count(), 0);
+
+		// there's one in-flight extra request to the expected peer
+		assert!(sync.extra_justifications.active_requests().any(|(who, (hash, number))| {
+			*who == peer_id && *hash == a1_hash && *number == a1_number
+		}));
+
+		// if the peer replies with an empty response (i.e. it doesn't know the block),
+		// the active request should be cleared.
+		assert_eq!(
+			sync.on_block_justification(peer_id, BlockResponse::<Block> { id: 0, blocks: vec![] }),
+			Ok(OnBlockJustification::Nothing),
+		);
+
+		// there should be no in-flight requests
+		assert_eq!(sync.extra_justifications.active_requests().count(), 0);
+
+		// and the request should now be pending again, waiting for reschedule
+		assert!(sync
+			.extra_justifications
+			.pending_requests()
+			.any(|(hash, number)| { *hash == a1_hash && *number == a1_number }));
+	}
+
+	#[test]
+	fn restart_doesnt_affect_peers_downloading_finality_data() {
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let peer_id1 = PeerId::random();
+		let peer_id2 = PeerId::random();
+		let peer_id3 = PeerId::random();
+
+		let mut new_blocks = |n| {
+			for _ in 0..n {
+				let block = client.new_block(Default::default()).unwrap().build().unwrap().block;
+				block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
+			}
+
+			let info = client.info();
+			(info.best_hash, info.best_number)
+		};
+
+		let (b1_hash, b1_number) = new_blocks(50);
+
+		// add 2 peers at blocks that we don't have locally
+		sync.new_peer(peer_id1, Hash::random(), 42).unwrap();
+		sync.new_peer(peer_id2, Hash::random(), 10).unwrap();
+
+		// we wil send block requests to these peers
+		// for these blocks we don't know about
+		assert!(sync
+			.block_requests()
+			.into_iter()
+			.all(|(p, _)| { p == peer_id1 || p == peer_id2 }));
+
+		// add a new peer at a known block
+		sync.new_peer(peer_id3, b1_hash, b1_number).unwrap();
+
+		// we request a justification for a block we have locally
+		sync.request_justification(&b1_hash, b1_number);
+
+		// the justification request should be scheduled to the
+		// new peer which is at the given block
+		assert!(sync.justification_requests().iter().any(|(p, r)| {
+			*p == peer_id3 &&
+				r.fields == BlockAttributes::JUSTIFICATION &&
+				r.from == FromBlock::Hash(b1_hash)
+		}));
+
+		assert_eq!(
+			sync.peers.get(&peer_id3).unwrap().state,
+			PeerSyncState::DownloadingJustification(b1_hash),
+		);
+
+		// we restart the sync state
+		let block_requests = sync.restart();
+
+		// which should make us send out block requests to the first two peers
+		assert!(block_requests.map(|r| r.unwrap()).all(|event| match event {
+			BlockRequestEvent::SendRequest { peer_id, .. } =>
+				peer_id == peer_id1 || peer_id == peer_id2,
+			BlockRequestEvent::RemoveStale { .. } => false,
+		}));
+
+		// peer 3 should be unaffected it was downloading finality data
+		assert_eq!(
+			sync.peers.get(&peer_id3).unwrap().state,
+			PeerSyncState::DownloadingJustification(b1_hash),
+		);
+
+		// Set common block to something that we don't have (e.g. failed import)
+		sync.peers.get_mut(&peer_id3).unwrap().common_number = 100;
+		let _ = sync.restart().count();
+		assert_eq!(sync.peers.get(&peer_id3).unwrap().common_number, 50);
+	}
+
+	/// Send a block annoucnement for the given `header`.
+	fn send_block_announce(
+		header: Header,
+		peer_id: PeerId,
+		sync: &mut ChainSync<Block, TestClient>,
+	) {
+		let announce = BlockAnnounce {
+			header: header.clone(),
+			state: Some(BlockState::Best),
+			data: Some(Vec::new()),
+		};
+
+		sync.on_validated_block_announce(true, peer_id, &announce);
+	}
+
+	/// Create a block response from the given `blocks`.
+	fn create_block_response(blocks: Vec<Block>) -> BlockResponse<Block> {
+		BlockResponse::<Block> {
+			id: 0,
+			blocks: blocks
+				.into_iter()
+				.map(|b| BlockData::<Block> {
+					hash: b.hash(),
+					header: Some(b.header().clone()),
+					body: Some(b.deconstruct().1),
+					indexed_body: None,
+					receipt: None,
+					message_queue: None,
+					justification: None,
+					justifications: None,
+				})
+				.collect(),
+		}
+	}
+
+	/// Get a block request from `sync` and check that is matches the expected request.
+	fn get_block_request(
+		sync: &mut ChainSync<Block, TestClient>,
+		from: FromBlock<Hash, u64>,
+		max: u32,
+		peer: &PeerId,
+	) -> BlockRequest<Block> {
+		let requests = sync.block_requests();
+
+		log::trace!(target: LOG_TARGET, "Requests: {requests:?}");
+
+		assert_eq!(1, requests.len());
+		assert_eq!(*peer, requests[0].0);
+
+		let request = requests[0].1.clone();
+
+		assert_eq!(from, request.from);
+		assert_eq!(Some(max), request.max);
+		request
+	}
+
+	/// Build and import a new best block.
+	fn build_block(client: &mut Arc<TestClient>, at: Option<Hash>, fork: bool) -> Block {
+		let at = at.unwrap_or_else(|| client.info().best_hash);
+
+		let mut block_builder = client.new_block_at(at, Default::default(), false).unwrap();
+
+		if fork {
+			block_builder.push_storage_change(vec![1, 2, 3], Some(vec![4, 5, 6])).unwrap();
+		}
+
+		let block = block_builder.build().unwrap().block;
+
+		block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
+		block
+	}
+
+	/// This test is a regression test as observed on a real network.
+	///
+	/// The node is connected to multiple peers. Both of these peers are having a best block (1)
+	/// that is below our best block (3). Now peer 2 announces a fork of block 3 that we will
+	/// request from peer 2. After importing the fork, peer 2 and then peer 1 will announce block 4.
+	/// But as peer 1 in our view is still at block 1, we will request block 2 (which we already
+	/// have) from it. In the meanwhile peer 2 sends us block 4 and 3 and we send another request
+	/// for block 2 to peer 2. Peer 1 answers with block 2 and then peer 2. This will need to
+	/// succeed, as we have requested block 2 from both peers.
+	#[test]
+	fn do_not_report_peer_on_block_response_for_block_request() {
+		sp_tracing::try_init_simple();
+
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			5,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let peer_id1 = PeerId::random();
+		let peer_id2 = PeerId::random();
+
+		let mut client2 = client.clone();
+		let mut build_block_at = |at, import| {
+			let mut block_builder = client2.new_block_at(at, Default::default(), false).unwrap();
+			// Make sure we generate a different block as fork
+			block_builder.push_storage_change(vec![1, 2, 3], Some(vec![4, 5, 6])).unwrap();
+
+			let block = block_builder.build().unwrap().block;
+
+			if import {
+				block_on(client2.import(BlockOrigin::Own, block.clone())).unwrap();
+			}
+
+			block
+		};
+
+		let block1 = build_block(&mut client, None, false);
+		let block2 = build_block(&mut client, None, false);
+		let block3 = build_block(&mut client, None, false);
+		let block3_fork = build_block_at(block2.hash(), false);
+
+		// Add two peers which are on block 1.
+		sync.new_peer(peer_id1, block1.hash(), 1).unwrap();
+		sync.new_peer(peer_id2, block1.hash(), 1).unwrap();
+
+		// Tell sync that our best block is 3.
+		sync.update_chain_info(&block3.hash(), 3);
+
+		// There should be no requests.
+		assert!(sync.block_requests().is_empty());
+
+		// Let peer2 announce a fork of block 3
+		send_block_announce(block3_fork.header().clone(), peer_id2, &mut sync);
+
+		// Import and tell sync that we now have the fork.
+		block_on(client.import(BlockOrigin::Own, block3_fork.clone())).unwrap();
+		sync.update_chain_info(&block3_fork.hash(), 3);
+
+		let block4 = build_block_at(block3_fork.hash(), false);
+
+		// Let peer2 announce block 4 and check that sync wants to get the block.
+		send_block_announce(block4.header().clone(), peer_id2, &mut sync);
+
+		let request = get_block_request(&mut sync, FromBlock::Hash(block4.hash()), 2, &peer_id2);
+
+		// Peer1 announces the same block, but as the common block is still `1`, sync will request
+		// block 2 again.
+		send_block_announce(block4.header().clone(), peer_id1, &mut sync);
+
+		let request2 = get_block_request(&mut sync, FromBlock::Number(2), 1, &peer_id1);
+
+		let response = create_block_response(vec![block4.clone(), block3_fork.clone()]);
+		let res = sync.on_block_data(&peer_id2, Some(request), response).unwrap();
+
+		// We should not yet import the blocks, because there is still an open request for fetching
+		// block `2` which blocks the import.
+		assert!(matches!(res, OnBlockData::Import(_, blocks) if blocks.is_empty()));
+
+		let request3 = get_block_request(&mut sync, FromBlock::Number(2), 1, &peer_id2);
+
+		let response = create_block_response(vec![block2.clone()]);
+		let res = sync.on_block_data(&peer_id1, Some(request2), response).unwrap();
+		assert!(matches!(
+			res,
+			OnBlockData::Import(_, blocks)
+				if blocks.iter().all(|b| [2, 3, 4].contains(b.header.as_ref().unwrap().number()))
+		));
+
+		let response = create_block_response(vec![block2.clone()]);
+		let res = sync.on_block_data(&peer_id2, Some(request3), response).unwrap();
+		// Nothing to import
+		assert!(matches!(res, OnBlockData::Import(_, blocks) if blocks.is_empty()));
+	}
+
+	fn unwrap_from_block_number(from: FromBlock<Hash, u64>) -> u64 {
+		if let FromBlock::Number(from) = from {
+			from
+		} else {
+			panic!("Expected a number!");
+		}
+	}
+
+	/// A regression test for a behavior we have seen on a live network.
+	///
+	/// The scenario is that the node is doing a full resync and is connected to some node that is
+	/// doing a major sync as well. This other node that is doing a major sync will finish before
+	/// our node and send a block announcement message, but we don't have seen any block
+	/// announcement from this node in its sync process. Meaning our common number didn't change. It
+	/// is now expected that we start an ancestor search to find the common number.
+	#[test]
+	fn do_ancestor_search_when_common_block_to_best_qeued_gap_is_to_big() {
+		sp_tracing::try_init_simple();
+
+		let blocks = {
+			let mut client = Arc::new(TestClientBuilder::new().build());
+			(0..MAX_DOWNLOAD_AHEAD * 2)
+				.map(|_| build_block(&mut client, None, false))
+				.collect::<Vec<_>>()
+		};
+
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let info = client.info();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			5,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let peer_id1 = PeerId::random();
+		let peer_id2 = PeerId::random();
+
+		let best_block = blocks.last().unwrap().clone();
+		let max_blocks_to_request = sync.max_blocks_per_request;
+		// Connect the node we will sync from
+		sync.new_peer(peer_id1, best_block.hash(), *best_block.header().number())
+			.unwrap();
+		sync.new_peer(peer_id2, info.best_hash, 0).unwrap();
+
+		let mut best_block_num = 0;
+		while best_block_num < MAX_DOWNLOAD_AHEAD {
+			let request = get_block_request(
+				&mut sync,
+				FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
+				max_blocks_to_request as u32,
+				&peer_id1,
+			);
+
+			let from = unwrap_from_block_number(request.from.clone());
+
+			let mut resp_blocks = blocks[best_block_num as usize..from as usize].to_vec();
+			resp_blocks.reverse();
+
+			let response = create_block_response(resp_blocks.clone());
+
+			let res = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
+			assert!(matches!(
+				res,
+				OnBlockData::Import(_, blocks) if blocks.len() == max_blocks_to_request as usize
+			),);
+
+			best_block_num += max_blocks_to_request as u32;
+
+			let _ = sync.on_blocks_processed(
+				max_blocks_to_request as usize,
+				max_blocks_to_request as usize,
+				resp_blocks
+					.iter()
+					.rev()
+					.map(|b| {
+						(
+							Ok(BlockImportStatus::ImportedUnknown(
+								*b.header().number(),
+								Default::default(),
+								Some(peer_id1),
+							)),
+							b.hash(),
+						)
+					})
+					.collect(),
+			);
+
+			resp_blocks
+				.into_iter()
+				.rev()
+				.for_each(|b| block_on(client.import_as_final(BlockOrigin::Own, b)).unwrap());
+		}
+
+		// "Wait" for the queue to clear
+		sync.queue_blocks.clear();
+
+		// Let peer2 announce that it finished syncing
+		send_block_announce(best_block.header().clone(), peer_id2, &mut sync);
+
+		let (peer1_req, peer2_req) =
+			sync.block_requests().into_iter().fold((None, None), |res, req| {
+				if req.0 == peer_id1 {
+					(Some(req.1), res.1)
+				} else if req.0 == peer_id2 {
+					(res.0, Some(req.1))
+				} else {
+					panic!("Unexpected req: {:?}", req)
+				}
+			});
+
+		// We should now do an ancestor search to find the correct common block.
+		let peer2_req = peer2_req.unwrap();
+		assert_eq!(Some(1), peer2_req.max);
+		assert_eq!(FromBlock::Number(best_block_num as u64), peer2_req.from);
+
+		let response = create_block_response(vec![blocks[(best_block_num - 1) as usize].clone()]);
+		let res = sync.on_block_data(&peer_id2, Some(peer2_req), response).unwrap();
+		assert!(matches!(
+			res,
+			OnBlockData::Import(_, blocks) if blocks.is_empty()
+		),);
+
+		let peer1_from = unwrap_from_block_number(peer1_req.unwrap().from);
+
+		// As we are on the same chain, we should directly continue with requesting blocks from
+		// peer 2 as well.
+		get_block_request(
+			&mut sync,
+			FromBlock::Number(peer1_from + max_blocks_to_request as u64),
+			max_blocks_to_request as u32,
+			&peer_id2,
+		);
+	}
+
+	/// A test that ensures that we can sync a huge fork.
+	///
+	/// The following scenario:
+	/// A peer connects to us and we both have the common block 512. The last finalized is 2048.
+	/// Our best block is 4096. The peer send us a block announcement with 4097 from a fork.
+	///
+	/// We will first do an ancestor search to find the common block. After that we start to sync
+	/// the fork and finish it ;)
+	#[test]
+	fn can_sync_huge_fork() {
+		sp_tracing::try_init_simple();
+
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 4)
+			.map(|_| build_block(&mut client, None, false))
+			.collect::<Vec<_>>();
+
+		let fork_blocks = {
+			let mut client = Arc::new(TestClientBuilder::new().build());
+			let fork_blocks = blocks[..MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2]
+				.into_iter()
+				.inspect(|b| block_on(client.import(BlockOrigin::Own, (*b).clone())).unwrap())
+				.cloned()
+				.collect::<Vec<_>>();
+
+			fork_blocks
+				.into_iter()
+				.chain(
+					(0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 2 + 1)
+						.map(|_| build_block(&mut client, None, true)),
+				)
+				.collect::<Vec<_>>()
+		};
+
+		let info = client.info();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			5,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let finalized_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2 - 1].clone();
+		let just = (*b"TEST", Vec::new());
+		client.finalize_block(finalized_block.hash(), Some(just)).unwrap();
+		sync.update_chain_info(&info.best_hash, info.best_number);
+
+		let peer_id1 = PeerId::random();
+
+		let common_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize / 2].clone();
+		// Connect the node we will sync from
+		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
+			.unwrap();
+
+		send_block_announce(fork_blocks.last().unwrap().header().clone(), peer_id1, &mut sync);
+
+		let mut request =
+			get_block_request(&mut sync, FromBlock::Number(info.best_number), 1, &peer_id1);
+
+		// Do the ancestor search
+		loop {
+			let block = &fork_blocks[unwrap_from_block_number(request.from.clone()) as usize - 1];
+			let response = create_block_response(vec![block.clone()]);
+
+			let on_block_data = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
+			request = if let OnBlockData::Request(_peer, request) = on_block_data {
+				request
+			} else {
+				// We found the ancenstor
+				break
+			};
+
+			log::trace!(target: LOG_TARGET, "Request: {request:?}");
+		}
+
+		// Now request and import the fork.
+		let mut best_block_num = *finalized_block.header().number() as u32;
+		let max_blocks_to_request = sync.max_blocks_per_request;
+		while best_block_num < *fork_blocks.last().unwrap().header().number() as u32 - 1 {
+			let request = get_block_request(
+				&mut sync,
+				FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
+				max_blocks_to_request as u32,
+				&peer_id1,
+			);
+
+			let from = unwrap_from_block_number(request.from.clone());
+
+			let mut resp_blocks = fork_blocks[best_block_num as usize..from as usize].to_vec();
+			resp_blocks.reverse();
+
+			let response = create_block_response(resp_blocks.clone());
+
+			let res = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
+			assert!(matches!(
+				res,
+				OnBlockData::Import(_, blocks) if blocks.len() == sync.max_blocks_per_request as usize
+			),);
+
+			best_block_num += sync.max_blocks_per_request as u32;
+
+			let _ = sync.on_blocks_processed(
+				max_blocks_to_request as usize,
+				max_blocks_to_request as usize,
+				resp_blocks
+					.iter()
+					.rev()
+					.map(|b| {
+						(
+							Ok(BlockImportStatus::ImportedUnknown(
+								*b.header().number(),
+								Default::default(),
+								Some(peer_id1),
+							)),
+							b.hash(),
+						)
+					})
+					.collect(),
+			);
+
+			resp_blocks
+				.into_iter()
+				.rev()
+				.for_each(|b| block_on(client.import(BlockOrigin::Own, b)).unwrap());
+		}
+
+		// Request the tip
+		get_block_request(
+			&mut sync,
+			FromBlock::Hash(fork_blocks.last().unwrap().hash()),
+			1,
+			&peer_id1,
+		);
+	}
+
+	#[test]
+	fn syncs_fork_without_duplicate_requests() {
+		sp_tracing::try_init_simple();
+
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 4)
+			.map(|_| build_block(&mut client, None, false))
+			.collect::<Vec<_>>();
+
+		let fork_blocks = {
+			let mut client = Arc::new(TestClientBuilder::new().build());
+			let fork_blocks = blocks[..MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2]
+				.into_iter()
+				.inspect(|b| block_on(client.import(BlockOrigin::Own, (*b).clone())).unwrap())
+				.cloned()
+				.collect::<Vec<_>>();
+
+			fork_blocks
+				.into_iter()
+				.chain(
+					(0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 2 + 1)
+						.map(|_| build_block(&mut client, None, true)),
+				)
+				.collect::<Vec<_>>()
+		};
+
+		let info = client.info();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			5,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let finalized_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2 - 1].clone();
+		let just = (*b"TEST", Vec::new());
+		client.finalize_block(finalized_block.hash(), Some(just)).unwrap();
+		sync.update_chain_info(&info.best_hash, info.best_number);
+
+		let peer_id1 = PeerId::random();
+
+		let common_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize / 2].clone();
+		// Connect the node we will sync from
+		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
+			.unwrap();
+
+		send_block_announce(fork_blocks.last().unwrap().header().clone(), peer_id1, &mut sync);
+
+		let mut request =
+			get_block_request(&mut sync, FromBlock::Number(info.best_number), 1, &peer_id1);
+
+		// Do the ancestor search
+		loop {
+			let block = &fork_blocks[unwrap_from_block_number(request.from.clone()) as usize - 1];
+			let response = create_block_response(vec![block.clone()]);
+
+			let on_block_data = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
+			request = if let OnBlockData::Request(_peer, request) = on_block_data {
+				request
+			} else {
+				// We found the ancenstor
+				break
+			};
+
+			log::trace!(target: LOG_TARGET, "Request: {request:?}");
+		}
+
+		// Now request and import the fork.
+		let mut best_block_num = *finalized_block.header().number() as u32;
+		let max_blocks_to_request = sync.max_blocks_per_request;
+
+		let mut request = get_block_request(
+			&mut sync,
+			FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
+			max_blocks_to_request as u32,
+			&peer_id1,
+		);
+		let last_block_num = *fork_blocks.last().unwrap().header().number() as u32 - 1;
+		while best_block_num < last_block_num {
+			let from = unwrap_from_block_number(request.from.clone());
+
+			let mut resp_blocks = fork_blocks[best_block_num as usize..from as usize].to_vec();
+			resp_blocks.reverse();
+
+			let response = create_block_response(resp_blocks.clone());
+
+			let res = sync.on_block_data(&peer_id1, Some(request.clone()), response).unwrap();
+			assert!(matches!(
+				res,
+				OnBlockData::Import(_, blocks) if blocks.len() == max_blocks_to_request as usize
+			),);
+
+			best_block_num += max_blocks_to_request as u32;
+
+			if best_block_num < last_block_num {
+				// make sure we're not getting a duplicate request in the time before the blocks are
+				// processed
+				request = get_block_request(
+					&mut sync,
+					FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
+					max_blocks_to_request as u32,
+					&peer_id1,
+				);
+			}
+
+			let mut notify_imported: Vec<_> = resp_blocks
+				.iter()
+				.rev()
+				.map(|b| {
+					(
+						Ok(BlockImportStatus::ImportedUnknown(
+							*b.header().number(),
+							Default::default(),
+							Some(peer_id1),
+						)),
+						b.hash(),
+					)
+				})
+				.collect();
+
+			// The import queue may send notifications in batches of varying size. So we simulate
+			// this here by splitting the batch into 2 notifications.
+			let max_blocks_to_request = sync.max_blocks_per_request;
+			let second_batch = notify_imported.split_off(notify_imported.len() / 2);
+			let _ = sync.on_blocks_processed(
+				max_blocks_to_request as usize,
+				max_blocks_to_request as usize,
+				notify_imported,
+			);
+
+			let _ = sync.on_blocks_processed(
+				max_blocks_to_request as usize,
+				max_blocks_to_request as usize,
+				second_batch,
+			);
+
+			resp_blocks
+				.into_iter()
+				.rev()
+				.for_each(|b| block_on(client.import(BlockOrigin::Own, b)).unwrap());
+		}
+
+		// Request the tip
+		get_block_request(
+			&mut sync,
+			FromBlock::Hash(fork_blocks.last().unwrap().hash()),
+			1,
+			&peer_id1,
+		);
+	}
+
+	#[test]
+	fn removes_target_fork_on_disconnect() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let peer_id1 = PeerId::random();
+		let common_block = blocks[1].clone();
+		// Connect the node we will sync from
+		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
+			.unwrap();
+
+		// Create a "new" header and announce it
+		let mut header = blocks[0].header().clone();
+		header.number = 4;
+		send_block_announce(header, peer_id1, &mut sync);
+		assert!(sync.fork_targets.len() == 1);
+
+		sync.peer_disconnected(&peer_id1);
+		assert!(sync.fork_targets.len() == 0);
+	}
+
+	#[test]
+	fn status_reports_strategy_and_fork_targets() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let status = sync.status();
+		assert_eq!(status.strategy, ActiveSyncStrategy::Full);
+		assert_eq!(status.gap_sync, None);
+		assert_eq!(status.num_fork_targets, 0);
+
+		let peer_id1 = PeerId::random();
+		let common_block = blocks[1].clone();
+		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
+			.unwrap();
+
+		let mut header = blocks[0].header().clone();
+		header.number = 4;
+		send_block_announce(header, peer_id1, &mut sync);
+		assert_eq!(sync.status().num_fork_targets, 1);
+	}
+
+	#[test]
+	fn gap_sync_requests_are_limited() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			1,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		let best_block = blocks.last().unwrap();
+		let peer_id1 = PeerId::random();
+		let peer_id2 = PeerId::random();
+		sync.new_peer(peer_id1, best_block.hash(), *best_block.header().number())
+			.unwrap();
+		sync.new_peer(peer_id2, best_block.hash(), *best_block.header().number())
+			.unwrap();
+		assert!(sync.block_requests().is_empty());
+
+		// Invalid ranges are ignored.
+		sync.start_gap_sync(0..=2);
+		assert_eq!(sync.status().gap_sync, None);
+
+		sync.set_max_gap_requests(Some(1));
+		sync.start_gap_sync(1..=2);
+		assert_eq!(
+			sync.status().gap_sync,
+			Some(GapSyncProgress { start: 1, end: 2, best_queued: 0 }),
+		);
+		assert_eq!(sync.block_requests().len(), 1);
+		assert!(sync.block_requests().is_empty());
+
+		sync.set_max_gap_requests(None);
+		assert_eq!(sync.block_requests().len(), 1);
 	}
 
-	let ancestor = sp_blockchain::lowest_common_ancestor(client, *block, *base)?;
+	#[test]
+	fn offline_blocks_are_queued_once() {
+		sp_tracing::try_init_simple();
+		let mut import_queue = sc_consensus::import_queue::mock::MockImportQueueHandle::new();
+		import_queue
+			.expect_import_blocks()
+			.withf(|origin, blocks| *origin == BlockOrigin::File && blocks.len() == 3)
+			.times(1)
+			.return_const(());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3)
+			.map(|_| SignedBlock {
+				block: build_block(&mut client, None, false),
+				justifications: None,
+			})
+			.collect::<Vec<_>>();
 
-	Ok(ancestor.hash == *base)
-}
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			Box::new(import_queue),
+		)
+		.unwrap();
 
-/// Validate that the given `blocks` are correct.
-/// Returns the number of the first block in the sequence.
-///
-/// It is expected that `blocks` are in ascending order.
-fn validate_blocks<Block: BlockT>(
-	blocks: &Vec<BlockData<Block>>,
-	who: &PeerId,
-	request: Option<BlockRequest<Block>>,
-) -> Result<Option<NumberFor<Block>>, BadPeer> {
-	if let Some(request) = request {
-		if Some(blocks.len() as _) > request.max {
-			debug!(
-				target: LOG_TARGET,
-				"Received more blocks than requested from {}. Expected in maximum {:?}, got {}.",
-				who,
-				request.max,
-				blocks.len(),
-			);
+		assert_eq!(sync.import_offline_blocks(blocks.clone()), 3);
+		assert_eq!(sync.status().queued_blocks, 3);
+		assert_eq!(sync.status().best_seen_block, None);
 
-			return Err(BadPeer(*who, rep::NOT_REQUESTED))
+		// Blocks already queued are not imported again.
+		assert_eq!(sync.import_offline_blocks(blocks), 0);
+	}
+
+	#[test]
+	fn fork_choice_picks_the_fork_to_download() {
+		struct ShortestChain;
+		impl ForkChoice<Block> for ShortestChain {
+			fn compare(&self, a: &Header, b: &Header) -> std::cmp::Ordering {
+				b.number.cmp(&a.number)
+			}
 		}
 
-		let block_header =
-			if request.direction == Direction::Descending { blocks.last() } else { blocks.first() }
-				.and_then(|b| b.header.as_ref());
+		let peer_id = PeerId::random();
+		let target = |number| {
+			let header = Header::new(
+				number,
+				Default::default(),
+				Default::default(),
+				Hash::random(),
+				Default::default(),
+			);
+			(
+				header.hash(),
+				ForkTarget {
+					number,
+					parent_hash: Some(header.parent_hash),
+					header: Some(header),
+					peers: HashSet::from([peer_id]),
+				},
+			)
+		};
+		let mut targets: HashMap<_, _> = [target(5), target(7)].into_iter().collect();
+		let request = |targets: &mut HashMap<Hash, ForkTarget<Block>>,
+		               fork_choice: &dyn ForkChoice<Block>| {
+			fork_sync_request::<Block>(
+				&peer_id,
+				targets,
+				10,
+				0,
+				BlockAttributes::HEADER,
+				|_| BlockStatus::Unknown,
+				64,
+				None,
+				fork_choice,
+			)
+			.map(|(hash, _)| targets[&hash].number)
+		};
 
-		let expected_block = block_header.as_ref().map_or(false, |h| match request.from {
-			FromBlock::Hash(hash) => h.hash() == hash,
-			FromBlock::Number(n) => h.number() == &n,
-		});
+		assert_eq!(request(&mut targets, &LongestChain), Some(7));
+		assert_eq!(request(&mut targets, &ShortestChain), Some(5));
+	}
 
-		if !expected_block {
-			debug!(
-				target: LOG_TARGET,
-				"Received block that was not requested. Requested {:?}, got {:?}.",
-				request.from,
-				block_header,
-			);
+	#[test]
+	fn fork_targets_are_restored_from_snapshot() {
+		sp_tracing::try_init_simple();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+		let new_sync = |client: Arc<TestClient>| {
+			let import_queue =
+				Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+			let (_chain_sync_network_provider, chain_sync_network_handle) =
+				NetworkServiceProvider::new();
+			ChainSync::new(
+				SyncMode::Full,
+				client,
+				ProtocolName::from("test-block-announce-protocol"),
+				1,
+				64,
+				None,
+				None,
+				chain_sync_network_handle,
+				import_queue,
+			)
+			.unwrap()
+		};
 
-			return Err(BadPeer(*who, rep::NOT_REQUESTED))
-		}
+		let mut sync = new_sync(client.clone());
+		let peer_id1 = PeerId::random();
+		let common_block = blocks[1].clone();
+		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
+			.unwrap();
 
-		if request.fields.contains(BlockAttributes::HEADER) &&
-			blocks.iter().any(|b| b.header.is_none())
-		{
-			trace!(
-				target: LOG_TARGET,
-				"Missing requested header for a block in response from {who}.",
-			);
+		let mut header = blocks[0].header().clone();
+		header.number = 4;
+		let hash = header.hash();
+		send_block_announce(header, peer_id1, &mut sync);
+		let snapshot = sync.snapshot();
+		assert_eq!(snapshot.fork_targets.len(), 1);
 
-			return Err(BadPeer(*who, rep::BAD_RESPONSE))
-		}
+		// Restored targets wait until a peer with a high enough best block connects.
+		let mut sync = new_sync(client.clone());
+		sync.restore_snapshot(snapshot);
+		assert!(sync.fork_targets.is_empty());
+		assert!(sync.restored_fork_targets.contains_key(&hash));
 
-		if request.fields.contains(BlockAttributes::BODY) && blocks.iter().any(|b| b.body.is_none())
-		{
-			trace!(
-				target: LOG_TARGET,
-				"Missing requested body for a block in response from {who}.",
-			);
+		let peer_id2 = PeerId::random();
+		sync.new_peer(peer_id2, common_block.hash(), *common_block.header().number())
+			.unwrap();
+		sync.peer_disconnected(&peer_id2);
+		assert!(sync.restored_fork_targets.contains_key(&hash));
 
-			return Err(BadPeer(*who, rep::BAD_RESPONSE))
-		}
-	}
+		let peer_id3 = PeerId::random();
+		sync.new_peer(peer_id3, hash, 4).unwrap();
+		assert!(sync.restored_fork_targets.is_empty());
+		assert_eq!(sync.fork_targets[&hash].peers, HashSet::from([peer_id3]));
 
-	for b in blocks {
-		if let Some(header) = &b.header {
-			let hash = header.hash();
-			if hash != b.hash {
-				debug!(
-					target:LOG_TARGET,
-					"Bad header received from {}. Expected hash {:?}, got {:?}",
-					who,
-					b.hash,
-					hash,
-				);
-				return Err(BadPeer(*who, rep::BAD_BLOCK))
-			}
-		}
-		if let (Some(header), Some(body)) = (&b.header, &b.body) {
-			let expected = *header.extrinsics_root();
-			let got = HashingFor::<Block>::ordered_trie_root(
-				body.iter().map(Encode::encode).collect(),
-				sp_runtime::StateVersion::V0,
-			);
-			if expected != got {
-				debug!(
-					target:LOG_TARGET,
-					"Bad extrinsic root for a block {} received from {}. Expected {:?}, got {:?}",
-					b.hash,
-					who,
-					expected,
-					got,
-				);
-				return Err(BadPeer(*who, rep::BAD_BLOCK))
-			}
-		}
+		// Like any other fork target, it is dropped once no peer can serve it.
+		sync.peer_disconnected(&peer_id3);
+		assert!(sync.fork_targets.is_empty());
 	}
 
-	Ok(blocks.first().and_then(|b| b.header.as_ref()).map(|h| *h.number()))
-}
+	#[test]
+	fn gap_sync_is_restored_from_snapshot() {
+		sp_tracing::try_init_simple();
+		let client = Arc::new(TestClientBuilder::new().build());
+		let mut sync = new_chain_sync(client.clone(), 1, 64);
+		let snapshot = SyncSnapshot {
+			best_queued_hash: client.info().best_hash,
+			best_queued_number: 0,
+			gap_sync: Some(GapSyncProgress { start: 1, end: 10, best_queued: 4 }),
+			fork_targets: Vec::new(),
+		};
 
-#[cfg(test)]
-mod test {
-	use super::*;
-	use crate::service::network::NetworkServiceProvider;
-	use futures::executor::block_on;
-	use sc_block_builder::BlockBuilderProvider;
-	use sc_network_common::sync::message::{BlockAnnounce, BlockData, BlockState, FromBlock};
-	use sp_blockchain::HeaderBackend;
-	use substrate_test_runtime_client::{
-		runtime::{Block, Hash, Header},
-		BlockBuilderExt, ClientBlockImportExt, ClientExt, DefaultTestClientBuilderExt, TestClient,
-		TestClientBuilder, TestClientBuilderExt,
-	};
+		sync.restore_snapshot(snapshot);
+		let gap_sync = sync.gap_sync.as_ref().unwrap();
+		assert_eq!((gap_sync.start, gap_sync.target), (5, 10));
+	}
 
 	#[test]
-	fn processes_empty_response_on_justification_request_for_unknown_block() {
-		// if we ask for a justification for a given block to a peer that doesn't know that block
-		// (different from not having a justification), the peer will reply with an empty response.
-		// internally we should process the response as the justification not being available.
+	fn peer_bests_are_restored_as_fork_targets() {
+		sp_tracing::try_init_simple();
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+		let mut sync = new_chain_sync(client.clone(), 1, 64);
+
+		let mut header = blocks[0].header().clone();
+		header.number = 4;
+		let hash = header.hash();
+		let peer_id1 = PeerId::random();
+		let peer_id2 = PeerId::random();
+		sync.restore_peer_bests([(peer_id1, blocks[2].hash(), 3), (peer_id2, hash, 4)]);
+
+		// Only the unknown best block is downloaded, from the peer that reported it.
+		assert_eq!(sync.restored_fork_targets.len(), 1);
+		assert_eq!(sync.restored_fork_targets[&hash].number, 4);
+		assert_eq!(sync.restored_fork_targets[&hash].peers, HashSet::from([peer_id2]));
 
+		let peer_id3 = PeerId::random();
+		sync.new_peer(peer_id3, hash, 4).unwrap();
+		assert!(sync.fork_targets.is_empty());
+
+		sync.new_peer(peer_id2, hash, 4).unwrap();
+		assert!(sync.restored_fork_targets.is_empty());
+		assert_eq!(sync.fork_targets[&hash].peers, HashSet::from([peer_id2]));
+	}
+
+	#[test]
+	fn does_not_request_blocks_past_sync_target() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
 		let client = Arc::new(TestClientBuilder::new().build());
+
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+		sync.set_sync_target(BlockId::Number(10));
+
 		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
+
+		// Only the blocks up to the target are requested.
+		get_block_request(&mut sync, FromBlock::Number(10), 10, &peer_id);
+		assert_eq!(sync.status().best_seen_block, Some(10));
 
+		// A target given by the hash of a known block is resolved to its number right away.
+		sync.set_sync_target(BlockId::Hash(client.info().genesis_hash));
+		assert_eq!(sync.sync_target, Some(BlockId::Number(0)));
+	}
+
+	#[test]
+	fn major_sync_ends_below_threshold_minus_hysteresis() {
+		sp_tracing::try_init_simple();
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
+		let client = Arc::new(TestClientBuilder::new().build());
+
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
 			client.clone(),
@@ -2469,55 +5479,67 @@ mod test {
 			import_queue,
 		)
 		.unwrap();
+		sync.set_major_sync_thresholds(5, 3);
+		assert_eq!(sync.blocks_behind(), None);
 
-		let (a1_hash, a1_number) = {
-			let a1 = client.new_block(Default::default()).unwrap().build().unwrap().block;
-			(a1.hash(), *a1.header.number())
-		};
+		sync.new_peer(PeerId::random(), Hash::random(), 100).unwrap();
 
-		// add a new peer with the same best block
-		sync.new_peer(peer_id, a1_hash, a1_number).unwrap();
+		// The sync target caps how far behind the peers we are.
+		sync.set_sync_target(BlockId::Number(4));
+		assert_eq!(sync.blocks_behind(), Some(4));
+		assert!(!sync.update_major_sync());
 
-		// and request a justification for the block
-		sync.request_justification(&a1_hash, a1_number);
+		sync.set_sync_target(BlockId::Number(10));
+		assert!(sync.update_major_sync());
 
-		// the justification request should be scheduled to that peer
-		assert!(sync
-			.justification_requests()
-			.iter()
-			.any(|(who, request)| { *who == peer_id && request.from == FromBlock::Hash(a1_hash) }));
+		sync.set_sync_target(BlockId::Number(4));
+		assert!(sync.update_major_sync());
 
-		// there are no extra pending requests
-		assert_eq!(sync.extra_justifications.pending_requests().count(), 0);
+		sync.set_sync_target(BlockId::Number(2));
+		assert!(!sync.update_major_sync());
+	}
 
-		// there's one in-flight extra request to the expected peer
-		assert!(sync.extra_justifications.active_requests().any(|(who, (hash, number))| {
-			*who == peer_id && *hash == a1_hash && *number == a1_number
-		}));
+	#[test]
+	fn requests_are_only_sent_to_request_peers() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let client = Arc::new(TestClientBuilder::new().build());
 
-		// if the peer replies with an empty response (i.e. it doesn't know the block),
-		// the active request should be cleared.
-		assert_eq!(
-			sync.on_block_justification(peer_id, BlockResponse::<Block> { id: 0, blocks: vec![] }),
-			Ok(OnBlockJustification::Nothing),
-		);
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-		// there should be no in-flight requests
-		assert_eq!(sync.extra_justifications.active_requests().count(), 0);
+		let reserved = PeerId::random();
+		let other = PeerId::random();
+		sync.set_request_peers(Some(HashSet::from([reserved])));
 
-		// and the request should now be pending again, waiting for reschedule
-		assert!(sync
-			.extra_justifications
-			.pending_requests()
-			.any(|(hash, number)| { *hash == a1_hash && *number == a1_number }));
+		sync.new_peer(other, Hash::random(), 10).unwrap();
+		assert!(sync.block_requests().is_empty());
+
+		sync.new_peer(reserved, Hash::random(), 10).unwrap();
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].0, reserved);
 	}
 
-	#[test]
-	fn restart_doesnt_affect_peers_downloading_finality_data() {
-		let mut client = Arc::new(TestClientBuilder::new().build());
+	#[test]
+	fn justification_peers_are_only_sent_justification_requests() {
+		sp_tracing::try_init_simple();
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
+		let client = Arc::new(TestClientBuilder::new().build());
 
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
@@ -2532,169 +5554,207 @@ mod test {
 		)
 		.unwrap();
 
-		let peer_id1 = PeerId::random();
-		let peer_id2 = PeerId::random();
-		let peer_id3 = PeerId::random();
-
-		let mut new_blocks = |n| {
-			for _ in 0..n {
-				let block = client.new_block(Default::default()).unwrap().build().unwrap().block;
-				block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
-			}
-
-			let info = client.info();
-			(info.best_hash, info.best_number)
-		};
+		let justification_peer = PeerId::random();
+		sync.set_request_peers(Some(HashSet::new()));
+		sync.set_justification_peers(HashSet::from([justification_peer]));
 
-		let (b1_hash, b1_number) = new_blocks(50);
+		let a1 = client.new_block(Default::default()).unwrap().build().unwrap().block;
+		let (a1_hash, a1_number) = (a1.hash(), *a1.header.number());
 
-		// add 2 peers at blocks that we don't have locally
-		sync.new_peer(peer_id1, Hash::random(), 42).unwrap();
-		sync.new_peer(peer_id2, Hash::random(), 10).unwrap();
+		// The peer is ahead, but isn't sent block requests.
+		sync.new_peer(justification_peer, a1_hash, a1_number).unwrap();
+		assert!(sync.block_requests().is_empty());
 
-		// we wil send block requests to these peers
-		// for these blocks we don't know about
-		assert!(sync
-			.block_requests()
-			.into_iter()
-			.all(|(p, _)| { p == peer_id1 || p == peer_id2 }));
+		// It is sent justification requests even though it isn't a request peer.
+		sync.request_justification(&a1_hash, a1_number);
+		assert!(sync.justification_requests().iter().any(|(who, request)| {
+			*who == justification_peer && request.from == FromBlock::Hash(a1_hash)
+		}));
+	}
 
-		// add a new peer at a known block
-		sync.new_peer(peer_id3, b1_hash, b1_number).unwrap();
+	#[test]
+	fn blocks_per_request_adapts_to_response_time() {
+		sp_tracing::try_init_simple();
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let client = Arc::new(TestClientBuilder::new().build());
 
-		// we request a justification for a block we have locally
-		sync.request_justification(&b1_hash, b1_number);
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-		// the justification request should be scheduled to the
-		// new peer which is at the given block
-		assert!(sync.justification_requests().iter().any(|(p, r)| {
-			*p == peer_id3 &&
-				r.fields == BlockAttributes::JUSTIFICATION &&
-				r.from == FromBlock::Hash(b1_hash)
-		}));
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
+		assert_eq!(sync.peer_max_blocks_per_request(&peer_id), Some(64));
 
+		let slow = BLOCK_RESPONSE_TARGET_TIME * 2;
+		assert_eq!(sync.on_block_response_time(&peer_id, Some(slow)), Some(32));
+		assert_eq!(sync.on_block_response_time(&peer_id, None), Some(16));
 		assert_eq!(
-			sync.peers.get(&peer_id3).unwrap().state,
-			PeerSyncState::DownloadingJustification(b1_hash),
+			sync.on_block_response_time(&peer_id, Some(Duration::from_millis(100))),
+			Some(16 + BLOCKS_PER_REQUEST_INCREASE),
 		);
+		for _ in 0..20 {
+			sync.on_block_response_time(&peer_id, Some(Duration::from_millis(100)));
+		}
+		assert_eq!(sync.peer_max_blocks_per_request(&peer_id), Some(64));
+		for _ in 0..10 {
+			sync.on_block_response_time(&peer_id, None);
+		}
+		assert_eq!(sync.peer_max_blocks_per_request(&peer_id), Some(1));
+		assert_eq!(sync.on_block_response_time(&PeerId::random(), None), None);
+	}
 
-		// we restart the sync state
-		let block_requests = sync.restart();
-
-		// which should make us send out block requests to the first two peers
-		assert!(block_requests.map(|r| r.unwrap()).all(|event| match event {
-			BlockRequestEvent::SendRequest { peer_id, .. } =>
-				peer_id == peer_id1 || peer_id == peer_id2,
-			BlockRequestEvent::RemoveStale { .. } => false,
-		}));
-
-		// peer 3 should be unaffected it was downloading finality data
+	#[test]
+	fn state_response_size_adapts_to_response_time() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let mut sync = new_chain_sync(client, 1, 64);
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
+
+		let fast = Duration::from_millis(100);
+		let slow = STATE_RESPONSE_TARGET_TIME * 2;
+		let max = 4 * MIN_STATE_RESPONSE_SIZE;
+		// Peers not advertising a maximum pick the size of their responses.
+		assert_eq!(sync.on_state_response_time(&peer_id, fast, 0), None);
+		assert_eq!(sync.peers[&peer_id].max_state_response_size, None);
+
+		assert_eq!(sync.on_state_response_time(&peer_id, fast, max), Some(max));
+		assert_eq!(sync.on_state_response_time(&peer_id, slow, max), Some(max / 2));
+		assert_eq!(sync.on_state_response_time(&peer_id, slow, max), Some(max / 4));
+		assert_eq!(sync.on_state_response_time(&peer_id, slow, max), Some(MIN_STATE_RESPONSE_SIZE));
 		assert_eq!(
-			sync.peers.get(&peer_id3).unwrap().state,
-			PeerSyncState::DownloadingJustification(b1_hash),
+			sync.on_state_response_time(&peer_id, fast, max),
+			Some(2 * MIN_STATE_RESPONSE_SIZE),
 		);
-
-		// Set common block to something that we don't have (e.g. failed import)
-		sync.peers.get_mut(&peer_id3).unwrap().common_number = 100;
-		let _ = sync.restart().count();
-		assert_eq!(sync.peers.get(&peer_id3).unwrap().common_number, 50);
+		// A lower advertised maximum caps the size, below the minimum too.
+		let max = MIN_STATE_RESPONSE_SIZE / 2;
+		assert_eq!(sync.on_state_response_time(&peer_id, fast, max), Some(max));
+		assert_eq!(sync.on_state_response_time(&peer_id, slow, max), Some(max));
+
+		// The adapted size is requested from the peer.
+		let mut request = StateRequest::default();
+		assert!(sync.peers[&peer_id].adapt_state_request(&mut request));
+		assert_eq!(request.max_response_size, max);
+		assert_eq!(sync.on_state_response_time(&PeerId::random(), fast, max), None);
 	}
 
-	/// Send a block annoucnement for the given `header`.
-	fn send_block_announce(
-		header: Header,
-		peer_id: PeerId,
-		sync: &mut ChainSync<Block, TestClient>,
-	) {
-		let announce = BlockAnnounce {
-			header: header.clone(),
-			state: Some(BlockState::Best),
-			data: Some(Vec::new()),
-		};
-
-		sync.on_validated_block_announce(true, peer_id, &announce);
-	}
+	#[test]
+	fn state_requests_follow_modes_advertised_by_peer() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let mut sync = new_chain_sync(client, 1, 64);
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
 
-	/// Create a block response from the given `blocks`.
-	fn create_block_response(blocks: Vec<Block>) -> BlockResponse<Block> {
-		BlockResponse::<Block> {
-			id: 0,
-			blocks: blocks
-				.into_iter()
-				.map(|b| BlockData::<Block> {
-					hash: b.hash(),
-					header: Some(b.header().clone()),
-					body: Some(b.deconstruct().1),
-					indexed_body: None,
-					receipt: None,
-					message_queue: None,
-					justification: None,
-					justifications: None,
-				})
-				.collect(),
-		}
+		let adapt = |sync: &ChainSync<Block, TestClient>, mut request: StateRequest| {
+			sync.peers[&peer_id].adapt_state_request(&mut request).then_some(request)
+		};
+		let proof = StateRequest { block: vec![1; 32], ..Default::default() };
+		let entries = StateRequest { no_proof: true, ..proof.clone() };
+		let delta = StateRequest { base_block: vec![2; 32], ..entries.clone() };
+
+		// Peers serve proofs and raw entries until they advertise otherwise.
+		assert_eq!(adapt(&sync, proof.clone()), Some(proof.clone()));
+		assert_eq!(adapt(&sync, entries.clone()), Some(entries.clone()));
+		assert_eq!(adapt(&sync, delta.clone()), None);
+
+		sync.on_state_response_modes(&peer_id, STATE_MODE_DELTA | STATE_MODE_PROOF);
+		assert_eq!(adapt(&sync, delta.clone()), Some(delta.clone()));
+		// The proof is requested from peers not serving raw entries.
+		assert_eq!(adapt(&sync, entries.clone()), Some(proof.clone()));
+
+		sync.on_state_response_modes(&peer_id, STATE_MODE_DELTA | STATE_MODE_ENTRIES);
+		assert_eq!(adapt(&sync, proof.clone()), None);
+		assert_eq!(adapt(&sync, entries.clone()), Some(entries));
+
+		// Responders not advertising any mode are legacy ones.
+		sync.on_state_response_modes(&peer_id, 0);
+		assert_eq!(adapt(&sync, proof.clone()), Some(proof));
+		assert_eq!(adapt(&sync, delta), None);
 	}
 
-	/// Get a block request from `sync` and check that is matches the expected request.
-	fn get_block_request(
-		sync: &mut ChainSync<Block, TestClient>,
-		from: FromBlock<Hash, u64>,
-		max: u32,
-		peer: &PeerId,
-	) -> BlockRequest<Block> {
-		let requests = sync.block_requests();
+	#[test]
+	fn parallel_downloads_adapt_to_import_queue_and_failures() {
+		sp_tracing::try_init_simple();
+		let client = Arc::new(TestClientBuilder::new().build());
 
-		log::trace!(target: LOG_TARGET, "Requests: {requests:?}");
+		// Up to four ranges of 512 blocks are downloaded at once.
+		let mut sync = new_chain_sync(client.clone(), 1, 512);
 
-		assert_eq!(1, requests.len());
-		assert_eq!(*peer, requests[0].0);
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
+		assert_eq!(sync.parallel_downloads(), 4);
+
+		// Failures reduce the number of ranges down to one, slow responses keep it.
+		sync.on_block_response_time(&peer_id, None);
+		assert_eq!(sync.parallel_downloads(), 3);
+		for _ in 0..3 {
+			sync.on_block_response_time(&peer_id, None);
+		}
+		assert_eq!(sync.parallel_downloads(), 1);
+		sync.on_block_response_time(&peer_id, Some(BLOCK_RESPONSE_TARGET_TIME * 2));
+		assert_eq!(sync.parallel_downloads(), 1);
 
-		let request = requests[0].1.clone();
+		// No ranges are added while the import queue is busy.
+		let fast = Some(Duration::from_millis(100));
+		sync.queue_blocks.extend((0..512).map(|_| Hash::random()));
+		sync.on_block_response_time(&peer_id, fast);
+		assert_eq!(sync.parallel_downloads(), 1);
 
-		assert_eq!(from, request.from);
-		assert_eq!(Some(max), request.max);
-		request
+		// Fast responses add ranges up to the maximum once the import queue is starved.
+		sync.queue_blocks.clear();
+		sync.on_block_response_time(&peer_id, fast);
+		assert_eq!(sync.parallel_downloads(), 2);
+		for _ in 0..3 {
+			sync.on_block_response_time(&peer_id, fast);
+		}
+		assert_eq!(sync.parallel_downloads(), 4);
 	}
 
-	/// Build and import a new best block.
-	fn build_block(client: &mut Arc<TestClient>, at: Option<Hash>, fork: bool) -> Block {
-		let at = at.unwrap_or_else(|| client.info().best_hash);
-
-		let mut block_builder = client.new_block_at(at, Default::default(), false).unwrap();
+	#[test]
+	fn parallel_downloads_limit_ranges_in_flight() {
+		sp_tracing::try_init_simple();
+		let client = Arc::new(TestClientBuilder::new().build());
+		let mut sync = new_chain_sync(client.clone(), 1, 64);
 
-		if fork {
-			block_builder.push_storage_change(vec![1, 2, 3], Some(vec![4, 5, 6])).unwrap();
+		let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+		for peer_id in &peers {
+			sync.new_peer(*peer_id, Hash::random(), 1000).unwrap();
+		}
+		while sync.parallel_downloads() > 2 {
+			sync.on_block_response_time(&peers[0], None);
 		}
 
-		let block = block_builder.build().unwrap().block;
-
-		block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
-		block
+		// Each range is requested from one peer, and only two ranges are downloaded at once.
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 2);
+		assert_ne!(requests[0].1.from, requests[1].1.from);
+		assert_eq!(sync.blocks.downloading_ranges(), 2);
 	}
 
-	/// This test is a regression test as observed on a real network.
-	///
-	/// The node is connected to multiple peers. Both of these peers are having a best block (1)
-	/// that is below our best block (3). Now peer 2 announces a fork of block 3 that we will
-	/// request from peer 2. After importing the fork, peer 2 and then peer 1 will announce block 4.
-	/// But as peer 1 in our view is still at block 1, we will request block 2 (which we already
-	/// have) from it. In the meanwhile peer 2 sends us block 4 and 3 and we send another request
-	/// for block 2 to peer 2. Peer 1 answers with block 2 and then peer 2. This will need to
-	/// succeed, as we have requested block 2 from both peers.
 	#[test]
-	fn do_not_report_peer_on_block_response_for_block_request() {
+	fn slow_peers_are_kept_off_the_critical_path() {
 		sp_tracing::try_init_simple();
-
-		let mut client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
+		let client = Arc::new(TestClientBuilder::new().build());
 
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
 			client.clone(),
 			ProtocolName::from("test-block-announce-protocol"),
-			5,
+			1,
 			64,
 			None,
 			None,
@@ -2703,119 +5763,46 @@ mod test {
 		)
 		.unwrap();
 
-		let peer_id1 = PeerId::random();
-		let peer_id2 = PeerId::random();
-
-		let mut client2 = client.clone();
-		let mut build_block_at = |at, import| {
-			let mut block_builder = client2.new_block_at(at, Default::default(), false).unwrap();
-			// Make sure we generate a different block as fork
-			block_builder.push_storage_change(vec![1, 2, 3], Some(vec![4, 5, 6])).unwrap();
-
-			let block = block_builder.build().unwrap().block;
-
-			if import {
-				block_on(client2.import(BlockOrigin::Own, block.clone())).unwrap();
-			}
-
-			block
-		};
-
-		let block1 = build_block(&mut client, None, false);
-		let block2 = build_block(&mut client, None, false);
-		let block3 = build_block(&mut client, None, false);
-		let block3_fork = build_block_at(block2.hash(), false);
-
-		// Add two peers which are on block 1.
-		sync.new_peer(peer_id1, block1.hash(), 1).unwrap();
-		sync.new_peer(peer_id2, block1.hash(), 1).unwrap();
-
-		// Tell sync that our best block is 3.
-		sync.update_chain_info(&block3.hash(), 3);
-
-		// There should be no requests.
-		assert!(sync.block_requests().is_empty());
-
-		// Let peer2 announce a fork of block 3
-		send_block_announce(block3_fork.header().clone(), peer_id2, &mut sync);
-
-		// Import and tell sync that we now have the fork.
-		block_on(client.import(BlockOrigin::Own, block3_fork.clone())).unwrap();
-		sync.update_chain_info(&block3_fork.hash(), 3);
-
-		let block4 = build_block_at(block3_fork.hash(), false);
-
-		// Let peer2 announce block 4 and check that sync wants to get the block.
-		send_block_announce(block4.header().clone(), peer_id2, &mut sync);
-
-		let request = get_block_request(&mut sync, FromBlock::Hash(block4.hash()), 2, &peer_id2);
-
-		// Peer1 announces the same block, but as the common block is still `1`, sync will request
-		// block 2 again.
-		send_block_announce(block4.header().clone(), peer_id1, &mut sync);
-
-		let request2 = get_block_request(&mut sync, FromBlock::Number(2), 1, &peer_id1);
-
-		let response = create_block_response(vec![block4.clone(), block3_fork.clone()]);
-		let res = sync.on_block_data(&peer_id2, Some(request), response).unwrap();
-
-		// We should not yet import the blocks, because there is still an open request for fetching
-		// block `2` which blocks the import.
-		assert!(matches!(res, OnBlockData::Import(_, blocks) if blocks.is_empty()));
-
-		let request3 = get_block_request(&mut sync, FromBlock::Number(2), 1, &peer_id2);
-
-		let response = create_block_response(vec![block2.clone()]);
-		let res = sync.on_block_data(&peer_id1, Some(request2), response).unwrap();
-		assert!(matches!(
-			res,
-			OnBlockData::Import(_, blocks)
-				if blocks.iter().all(|b| [2, 3, 4].contains(b.header.as_ref().unwrap().number()))
-		));
+		let slow = PeerId::random();
+		let fast = PeerId::random();
+		sync.new_peer(slow, Hash::random(), 1000).unwrap();
+		sync.new_peer(fast, Hash::random(), 1000).unwrap();
 
-		let response = create_block_response(vec![block2.clone()]);
-		let res = sync.on_block_data(&peer_id2, Some(request3), response).unwrap();
-		// Nothing to import
-		assert!(matches!(res, OnBlockData::Import(_, blocks) if blocks.is_empty()));
-	}
+		// Small responses don't count, large slow ones make the peer slow.
+		sync.on_block_throughput(&slow, 1, Duration::from_secs(10));
+		assert!(!sync.peers[&slow].is_slow());
+		sync.on_block_throughput(&slow, 64, Duration::from_secs(10));
+		assert!(sync.peers[&slow].is_slow());
+		sync.on_block_throughput(&fast, 64, Duration::from_millis(100));
+		assert!(!sync.peers[&fast].is_slow());
 
-	fn unwrap_from_block_number(from: FromBlock<Hash, u64>) -> u64 {
-		if let FromBlock::Number(from) = from {
-			from
-		} else {
-			panic!("Expected a number!");
-		}
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].0, fast);
+
+		// Slow peers are used when there is no faster one.
+		sync.peer_disconnected(&fast);
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].0, slow);
 	}
 
-	/// A regression test for a behavior we have seen on a live network.
-	///
-	/// The scenario is that the node is doing a full resync and is connected to some node that is
-	/// doing a major sync as well. This other node that is doing a major sync will finish before
-	/// our node and send a block announcement message, but we don't have seen any block
-	/// announcement from this node in its sync process. Meaning our common number didn't change. It
-	/// is now expected that we start an ancestor search to find the common number.
 	#[test]
-	fn do_ancestor_search_when_common_block_to_best_qeued_gap_is_to_big() {
+	fn can_import_response_with_missing_blocks() {
 		sp_tracing::try_init_simple();
-
-		let blocks = {
-			let mut client = Arc::new(TestClientBuilder::new().build());
-			(0..MAX_DOWNLOAD_AHEAD * 2)
-				.map(|_| build_block(&mut client, None, false))
-				.collect::<Vec<_>>()
-		};
-
-		let mut client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
-		let info = client.info();
+		let mut client2 = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..4).map(|_| build_block(&mut client2, None, false)).collect::<Vec<_>>();
+
+		let empty_client = Arc::new(TestClientBuilder::new().build());
 
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
-			client.clone(),
+			empty_client.clone(),
 			ProtocolName::from("test-block-announce-protocol"),
-			5,
+			1,
 			64,
 			None,
 			None,
@@ -2825,149 +5812,166 @@ mod test {
 		.unwrap();
 
 		let peer_id1 = PeerId::random();
-		let peer_id2 = PeerId::random();
-
-		let best_block = blocks.last().unwrap().clone();
-		let max_blocks_to_request = sync.max_blocks_per_request;
-		// Connect the node we will sync from
+		let best_block = blocks[3].clone();
 		sync.new_peer(peer_id1, best_block.hash(), *best_block.header().number())
 			.unwrap();
-		sync.new_peer(peer_id2, info.best_hash, 0).unwrap();
 
-		let mut best_block_num = 0;
-		while best_block_num < MAX_DOWNLOAD_AHEAD {
-			let request = get_block_request(
-				&mut sync,
-				FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
-				max_blocks_to_request as u32,
-				&peer_id1,
-			);
+		sync.peers.get_mut(&peer_id1).unwrap().state = PeerSyncState::Available;
+		sync.peers.get_mut(&peer_id1).unwrap().common_number = 0;
 
-			let from = unwrap_from_block_number(request.from.clone());
+		// Request all missing blocks and respond only with some.
+		let request =
+			get_block_request(&mut sync, FromBlock::Hash(best_block.hash()), 4, &peer_id1);
+		let response =
+			create_block_response(vec![blocks[3].clone(), blocks[2].clone(), blocks[1].clone()]);
+		sync.on_block_data(&peer_id1, Some(request.clone()), response).unwrap();
+		assert_eq!(sync.best_queued_number, 0);
 
-			let mut resp_blocks = blocks[best_block_num as usize..from as usize].to_vec();
-			resp_blocks.reverse();
+		// Request should only contain the missing block.
+		let request = get_block_request(&mut sync, FromBlock::Number(1), 1, &peer_id1);
+		let response = create_block_response(vec![blocks[0].clone()]);
+		sync.on_block_data(&peer_id1, Some(request), response).unwrap();
+		assert_eq!(sync.best_queued_number, 4);
+	}
+	#[test]
+	fn ancestor_search_repeat() {
+		let state = AncestorSearchState::<Block>::BinarySearch(1, 3);
+		assert!(handle_ancestor_search_state(&state, 2, true, 0).is_none());
+	}
 
-			let response = create_block_response(resp_blocks.clone());
+	#[test]
+	fn ancestor_search_is_bounded_by_known_common_block() {
+		let state = AncestorSearchState::<Block>::ExponentialBackoff(8);
 
-			let res = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
-			assert!(matches!(
-				res,
-				OnBlockData::Import(_, blocks) if blocks.len() == max_blocks_to_request as usize
-			),);
+		// Without a known common block, the backoff continues below it.
+		assert_eq!(
+			handle_ancestor_search_state(&state, 100, false, 0),
+			Some((AncestorSearchState::ExponentialBackoff(16), 92)),
+		);
 
-			best_block_num += max_blocks_to_request as u32;
+		// Otherwise the search continues between the known common block and the mismatch.
+		assert_eq!(
+			handle_ancestor_search_state(&state, 100, false, 96),
+			Some((AncestorSearchState::BinarySearch(96, 100), 98)),
+		);
 
-			let _ = sync.on_blocks_processed(
-				max_blocks_to_request as usize,
-				max_blocks_to_request as usize,
-				resp_blocks
-					.iter()
-					.rev()
-					.map(|b| {
-						(
-							Ok(BlockImportStatus::ImportedUnknown(
-								*b.header().number(),
-								Default::default(),
-								Some(peer_id1),
-							)),
-							b.hash(),
-						)
-					})
-					.collect(),
-			);
+		// The known common block is the ancestor if the block above it mismatches.
+		assert!(handle_ancestor_search_state(&state, 97, false, 96).is_none());
+	}
 
-			resp_blocks
-				.into_iter()
-				.rev()
-				.for_each(|b| block_on(client.import_as_final(BlockOrigin::Own, b)).unwrap());
-		}
+	#[test]
+	fn sync_restart_removes_block_but_not_justification_requests() {
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client.clone(),
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-		// "Wait" for the queue to clear
-		sync.queue_blocks.clear();
+		let peers = vec![PeerId::random(), PeerId::random()];
 
-		// Let peer2 announce that it finished syncing
-		send_block_announce(best_block.header().clone(), peer_id2, &mut sync);
+		let mut new_blocks = |n| {
+			for _ in 0..n {
+				let block = client.new_block(Default::default()).unwrap().build().unwrap().block;
+				block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
+			}
 
-		let (peer1_req, peer2_req) =
-			sync.block_requests().into_iter().fold((None, None), |res, req| {
-				if req.0 == peer_id1 {
-					(Some(req.1), res.1)
-				} else if req.0 == peer_id2 {
-					(res.0, Some(req.1))
-				} else {
-					panic!("Unexpected req: {:?}", req)
-				}
-			});
+			let info = client.info();
+			(info.best_hash, info.best_number)
+		};
 
-		// We should now do an ancestor search to find the correct common block.
-		let peer2_req = peer2_req.unwrap();
-		assert_eq!(Some(1), peer2_req.max);
-		assert_eq!(FromBlock::Number(best_block_num as u64), peer2_req.from);
+		let (b1_hash, b1_number) = new_blocks(50);
 
-		let response = create_block_response(vec![blocks[(best_block_num - 1) as usize].clone()]);
-		let res = sync.on_block_data(&peer_id2, Some(peer2_req), response).unwrap();
-		assert!(matches!(
-			res,
-			OnBlockData::Import(_, blocks) if blocks.is_empty()
-		),);
+		// add new peer and request blocks from them
+		sync.new_peer(peers[0], Hash::random(), 42).unwrap();
 
-		let peer1_from = unwrap_from_block_number(peer1_req.unwrap().from);
+		// we don't actually perform any requests, just keep track of peers waiting for a response
+		let mut pending_responses = HashSet::new();
 
-		// As we are on the same chain, we should directly continue with requesting blocks from
-		// peer 2 as well.
-		get_block_request(
-			&mut sync,
-			FromBlock::Number(peer1_from + max_blocks_to_request as u64),
-			max_blocks_to_request as u32,
-			&peer_id2,
-		);
-	}
+		// we wil send block requests to these peers
+		// for these blocks we don't know about
+		for (peer, _request) in sync.block_requests() {
+			// "send" request
+			pending_responses.insert(peer);
+		}
 
-	/// A test that ensures that we can sync a huge fork.
-	///
-	/// The following scenario:
-	/// A peer connects to us and we both have the common block 512. The last finalized is 2048.
-	/// Our best block is 4096. The peer send us a block announcement with 4097 from a fork.
-	///
-	/// We will first do an ancestor search to find the common block. After that we start to sync
-	/// the fork and finish it ;)
-	#[test]
-	fn can_sync_huge_fork() {
-		sp_tracing::try_init_simple();
+		// add a new peer at a known block
+		sync.new_peer(peers[1], b1_hash, b1_number).unwrap();
 
-		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
-		let (_chain_sync_network_provider, chain_sync_network_handle) =
-			NetworkServiceProvider::new();
-		let mut client = Arc::new(TestClientBuilder::new().build());
-		let blocks = (0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 4)
-			.map(|_| build_block(&mut client, None, false))
-			.collect::<Vec<_>>();
+		// we request a justification for a block we have locally
+		sync.request_justification(&b1_hash, b1_number);
 
-		let fork_blocks = {
-			let mut client = Arc::new(TestClientBuilder::new().build());
-			let fork_blocks = blocks[..MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2]
-				.into_iter()
-				.inspect(|b| block_on(client.import(BlockOrigin::Own, (*b).clone())).unwrap())
-				.cloned()
-				.collect::<Vec<_>>();
+		// the justification request should be scheduled to the
+		// new peer which is at the given block
+		let mut requests = sync.justification_requests();
+		assert_eq!(requests.len(), 1);
+		let (peer, _request) = requests.remove(0);
+		// "send" request
+		assert!(pending_responses.insert(peer));
 
-			fork_blocks
-				.into_iter()
-				.chain(
-					(0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 2 + 1)
-						.map(|_| build_block(&mut client, None, true)),
-				)
-				.collect::<Vec<_>>()
-		};
+		assert!(!std::matches!(
+			sync.peers.get(&peers[0]).unwrap().state,
+			PeerSyncState::DownloadingJustification(_),
+		));
+		assert_eq!(
+			sync.peers.get(&peers[1]).unwrap().state,
+			PeerSyncState::DownloadingJustification(b1_hash),
+		);
+		assert_eq!(pending_responses.len(), 2);
 
-		let info = client.info();
+		// restart sync
+		let request_events = sync.restart().collect::<Vec<_>>();
+		for event in request_events.iter() {
+			match event.as_ref().unwrap() {
+				BlockRequestEvent::RemoveStale { peer_id } => {
+					pending_responses.remove(&peer_id);
+				},
+				BlockRequestEvent::SendRequest { peer_id, .. } => {
+					// we drop obsolete response, but don't register a new request, it's checked in
+					// the `assert!` below
+					pending_responses.remove(&peer_id);
+				},
+			}
+		}
+		assert!(request_events.iter().any(|event| {
+			match event.as_ref().unwrap() {
+				BlockRequestEvent::RemoveStale { .. } => false,
+				BlockRequestEvent::SendRequest { peer_id, .. } => peer_id == &peers[0],
+			}
+		}));
+
+		assert_eq!(pending_responses.len(), 1);
+		assert!(pending_responses.contains(&peers[1]));
+		assert_eq!(
+			sync.peers.get(&peers[1]).unwrap().state,
+			PeerSyncState::DownloadingJustification(b1_hash),
+		);
+		sync.peer_disconnected(&peers[1]);
+		pending_responses.remove(&peers[1]);
+		assert_eq!(pending_responses.len(), 0);
+	}
 
+	#[test]
+	fn block_response_must_match_announced_best_block() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
 			client.clone(),
 			ProtocolName::from("test-block-announce-protocol"),
-			5,
+			1,
 			64,
 			None,
 			None,
@@ -2976,135 +5980,92 @@ mod test {
 		)
 		.unwrap();
 
-		let finalized_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2 - 1].clone();
-		let just = (*b"TEST", Vec::new());
-		client.finalize_block(finalized_block.hash(), Some(just)).unwrap();
-		sync.update_chain_info(&info.best_hash, info.best_number);
-
-		let peer_id1 = PeerId::random();
-
-		let common_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize / 2].clone();
-		// Connect the node we will sync from
-		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
-			.unwrap();
-
-		send_block_announce(fork_blocks.last().unwrap().header().clone(), peer_id1, &mut sync);
-
-		let mut request =
-			get_block_request(&mut sync, FromBlock::Number(info.best_number), 1, &peer_id1);
-
-		// Do the ancestor search
-		loop {
-			let block = &fork_blocks[unwrap_from_block_number(request.from.clone()) as usize - 1];
-			let response = create_block_response(vec![block.clone()]);
-
-			let on_block_data = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
-			request = if let OnBlockData::Request(_peer, request) = on_block_data {
-				request
-			} else {
-				// We found the ancenstor
-				break
-			};
-
-			log::trace!(target: LOG_TARGET, "Request: {request:?}");
-		}
-
-		// Now request and import the fork.
-		let mut best_block_num = *finalized_block.header().number() as u32;
-		let max_blocks_to_request = sync.max_blocks_per_request;
-		while best_block_num < *fork_blocks.last().unwrap().header().number() as u32 - 1 {
-			let request = get_block_request(
-				&mut sync,
-				FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
-				max_blocks_to_request as u32,
-				&peer_id1,
-			);
+		let mut announced_client = Arc::new(TestClientBuilder::new().build());
+		let announced = (0..2)
+			.map(|_| build_block(&mut announced_client, None, false))
+			.collect::<Vec<_>>();
+		let mut fork_client = Arc::new(TestClientBuilder::new().build());
+		let fork =
+			(0..2).map(|_| build_block(&mut fork_client, None, true)).collect::<Vec<_>>();
 
-			let from = unwrap_from_block_number(request.from.clone());
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, announced[1].hash(), 2).unwrap();
 
-			let mut resp_blocks = fork_blocks[best_block_num as usize..from as usize].to_vec();
-			resp_blocks.reverse();
+		// Blocks that don't form a chain are rejected.
+		let request = get_block_request(&mut sync, FromBlock::Number(2), 2, &peer_id);
+		let response = create_block_response(vec![announced[1].clone(), fork[0].clone()]);
+		assert_eq!(
+			sync.on_block_data(&peer_id, Some(request), response).err(),
+			Some(BadPeer(peer_id, rep::BAD_BLOCK)),
+		);
 
-			let response = create_block_response(resp_blocks.clone());
+		// Another chain than the announced one is dropped.
+		let request = get_block_request(&mut sync, FromBlock::Number(2), 2, &peer_id);
+		let response = create_block_response(vec![fork[1].clone(), fork[0].clone()]);
+		assert!(matches!(
+			sync.on_block_data(&peer_id, Some(request), response),
+			Ok(OnBlockData::Continue),
+		));
+		assert_eq!(sync.peers.get(&peer_id).unwrap().state, PeerSyncState::Available);
 
-			let res = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
-			assert!(matches!(
-				res,
-				OnBlockData::Import(_, blocks) if blocks.len() == sync.max_blocks_per_request as usize
-			),);
+		// The announced chain is imported.
+		let request = get_block_request(&mut sync, FromBlock::Number(2), 2, &peer_id);
+		let response = create_block_response(vec![announced[1].clone(), announced[0].clone()]);
+		assert!(matches!(
+			sync.on_block_data(&peer_id, Some(request), response),
+			Ok(OnBlockData::Import(_, blocks)) if blocks.len() == 2,
+		));
+	}
 
-			best_block_num += sync.max_blocks_per_request as u32;
+	#[test]
+	fn starved_requests_are_served_first() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-			let _ = sync.on_blocks_processed(
-				max_blocks_to_request as usize,
-				max_blocks_to_request as usize,
-				resp_blocks
-					.iter()
-					.rev()
-					.map(|b| {
-						(
-							Ok(BlockImportStatus::ImportedUnknown(
-								*b.header().number(),
-								Default::default(),
-								Some(peer_id1),
-							)),
-							b.hash(),
-						)
-					})
-					.collect(),
-			);
+		assert_eq!(sync.request_order(), RequestPriority::ALL.to_vec());
 
-			resp_blocks
-				.into_iter()
-				.rev()
-				.for_each(|b| block_on(client.import(BlockOrigin::Own, b)).unwrap());
-		}
+		sync.starved_requests.insert(RequestPriority::Gap, MAX_STARVED_ROUNDS - 1);
+		assert_eq!(sync.request_order(), RequestPriority::ALL.to_vec());
 
-		// Request the tip
-		get_block_request(
-			&mut sync,
-			FromBlock::Hash(fork_blocks.last().unwrap().hash()),
-			1,
-			&peer_id1,
+		sync.starved_requests.insert(RequestPriority::Gap, MAX_STARVED_ROUNDS);
+		sync.starved_requests.insert(RequestPriority::Fork, MAX_STARVED_ROUNDS);
+		assert_eq!(
+			sync.request_order(),
+			vec![
+				RequestPriority::Fork,
+				RequestPriority::Gap,
+				RequestPriority::BestChain,
+				RequestPriority::Justification,
+			],
 		);
 	}
 
 	#[test]
-	fn syncs_fork_without_duplicate_requests() {
-		sp_tracing::try_init_simple();
-
+	fn finality_prunes_fork_targets_and_frees_their_peers() {
+		let client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
-		let mut client = Arc::new(TestClientBuilder::new().build());
-		let blocks = (0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 4)
-			.map(|_| build_block(&mut client, None, false))
-			.collect::<Vec<_>>();
-
-		let fork_blocks = {
-			let mut client = Arc::new(TestClientBuilder::new().build());
-			let fork_blocks = blocks[..MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2]
-				.into_iter()
-				.inspect(|b| block_on(client.import(BlockOrigin::Own, (*b).clone())).unwrap())
-				.cloned()
-				.collect::<Vec<_>>();
-
-			fork_blocks
-				.into_iter()
-				.chain(
-					(0..MAX_BLOCKS_TO_LOOK_BACKWARDS * 2 + 1)
-						.map(|_| build_block(&mut client, None, true)),
-				)
-				.collect::<Vec<_>>()
-		};
-
 		let info = client.info();
-
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
-			client.clone(),
+			client,
 			ProtocolName::from("test-block-announce-protocol"),
-			5,
+			1,
 			64,
 			None,
 			None,
@@ -3113,135 +6074,100 @@ mod test {
 		)
 		.unwrap();
 
-		let finalized_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize * 2 - 1].clone();
-		let just = (*b"TEST", Vec::new());
-		client.finalize_block(finalized_block.hash(), Some(just)).unwrap();
-		sync.update_chain_info(&info.best_hash, info.best_number);
-
-		let peer_id1 = PeerId::random();
-
-		let common_block = blocks[MAX_BLOCKS_TO_LOOK_BACKWARDS as usize / 2].clone();
-		// Connect the node we will sync from
-		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
-			.unwrap();
-
-		send_block_announce(fork_blocks.last().unwrap().header().clone(), peer_id1, &mut sync);
-
-		let mut request =
-			get_block_request(&mut sync, FromBlock::Number(info.best_number), 1, &peer_id1);
-
-		// Do the ancestor search
-		loop {
-			let block = &fork_blocks[unwrap_from_block_number(request.from.clone()) as usize - 1];
-			let response = create_block_response(vec![block.clone()]);
-
-			let on_block_data = sync.on_block_data(&peer_id1, Some(request), response).unwrap();
-			request = if let OnBlockData::Request(_peer, request) = on_block_data {
-				request
-			} else {
-				// We found the ancenstor
-				break
-			};
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, info.best_hash, 0).unwrap();
 
-			log::trace!(target: LOG_TARGET, "Request: {request:?}");
+		let (stale, live) = (Hash::random(), Hash::random());
+		for (hash, number) in [(stale, 3), (live, 10)] {
+			sync.fork_targets.insert(
+				hash,
+				ForkTarget {
+					number,
+					parent_hash: None,
+					header: None,
+					peers: [peer_id].into_iter().collect(),
+				},
+			);
 		}
+		sync.peers.get_mut(&peer_id).unwrap().state = PeerSyncState::DownloadingStale(stale);
 
-		// Now request and import the fork.
-		let mut best_block_num = *finalized_block.header().number() as u32;
-		let max_blocks_to_request = sync.max_blocks_per_request;
-
-		let mut request = get_block_request(
-			&mut sync,
-			FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
-			max_blocks_to_request as u32,
-			&peer_id1,
-		);
-		let last_block_num = *fork_blocks.last().unwrap().header().number() as u32 - 1;
-		while best_block_num < last_block_num {
-			let from = unwrap_from_block_number(request.from.clone());
-
-			let mut resp_blocks = fork_blocks[best_block_num as usize..from as usize].to_vec();
-			resp_blocks.reverse();
-
-			let response = create_block_response(resp_blocks.clone());
-
-			let res = sync.on_block_data(&peer_id1, Some(request.clone()), response).unwrap();
-			assert!(matches!(
-				res,
-				OnBlockData::Import(_, blocks) if blocks.len() == max_blocks_to_request as usize
-			),);
+		sync.on_block_finalized(&Hash::random(), 5);
 
-			best_block_num += max_blocks_to_request as u32;
+		assert!(!sync.fork_targets.contains_key(&stale));
+		assert!(sync.fork_targets.contains_key(&live));
+		assert_eq!(sync.peers.get(&peer_id).unwrap().state, PeerSyncState::Available);
+		assert_eq!(sync.take_cancelled_requests(), vec![peer_id]);
+	}
 
-			if best_block_num < last_block_num {
-				// make sure we're not getting a duplicate request in the time before the blocks are
-				// processed
-				request = get_block_request(
-					&mut sync,
-					FromBlock::Number(max_blocks_to_request as u64 + best_block_num as u64),
-					max_blocks_to_request as u32,
-					&peer_id1,
-				);
-			}
+	#[test]
+	fn justifications_are_only_requested_up_to_peer_finalized_block() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-			let mut notify_imported: Vec<_> = resp_blocks
-				.iter()
-				.rev()
-				.map(|b| {
-					(
-						Ok(BlockImportStatus::ImportedUnknown(
-							*b.header().number(),
-							Default::default(),
-							Some(peer_id1),
-						)),
-						b.hash(),
-					)
-				})
-				.collect();
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 100).unwrap();
+		let attrs = sync.required_block_attributes();
+		let without_justification = attrs & !BlockAttributes::JUSTIFICATION;
+
+		// Without a known finalized block the peer is asked for justifications.
+		let peer = sync.peers.get(&peer_id).unwrap();
+		assert_eq!(range_attributes(peer, &(50..60), attrs), attrs);
+
+		sync.peers.get_mut(&peer_id).unwrap().finalized_number = Some(50);
+		let peer = sync.peers.get(&peer_id).unwrap();
+		assert_eq!(range_attributes(peer, &(40..50), attrs), attrs);
+		assert_eq!(range_attributes(peer, &(50..60), attrs), attrs);
+		assert_eq!(range_attributes(peer, &(51..60), attrs), without_justification);
+	}
 
-			// The import queue may send notifications in batches of varying size. So we simulate
-			// this here by splitting the batch into 2 notifications.
-			let max_blocks_to_request = sync.max_blocks_per_request;
-			let second_batch = notify_imported.split_off(notify_imported.len() / 2);
-			let _ = sync.on_blocks_processed(
-				max_blocks_to_request as usize,
-				max_blocks_to_request as usize,
-				notify_imported,
-			);
+	#[test]
+	fn gap_blocks_must_link_to_their_known_child() {
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let blocks = (0..4).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
+		let peer_id = PeerId::random();
 
-			let _ = sync.on_blocks_processed(
-				max_blocks_to_request as usize,
-				max_blocks_to_request as usize,
-				second_batch,
-			);
+		let mut anchors = HashMap::new();
+		anchors.insert(3, *blocks[3].header().parent_hash());
 
-			resp_blocks
-				.into_iter()
-				.rev()
-				.for_each(|b| block_on(client.import(BlockOrigin::Own, b)).unwrap());
-		}
+		let response = create_block_response(vec![blocks[1].clone(), blocks[2].clone()]);
+		link_gap_blocks(&mut anchors, 2, &response.blocks, &peer_id).unwrap();
+		assert_eq!(anchors, [(1, blocks[0].hash())].into_iter().collect());
 
-		// Request the tip
-		get_block_request(
-			&mut sync,
-			FromBlock::Hash(fork_blocks.last().unwrap().hash()),
-			1,
-			&peer_id1,
+		// A block that doesn't link to its downloaded child is rejected.
+		let response = create_block_response(vec![blocks[1].clone()]);
+		assert_eq!(
+			link_gap_blocks(&mut anchors, 1, &response.blocks, &peer_id).err(),
+			Some(BadPeer(peer_id, rep::BAD_BLOCK)),
 		);
+
+		let response = create_block_response(vec![blocks[0].clone()]);
+		link_gap_blocks(&mut anchors, 1, &response.blocks, &peer_id).unwrap();
+		assert_eq!(anchors, [(0, *blocks[0].header().parent_hash())].into_iter().collect());
 	}
 
 	#[test]
-	fn removes_target_fork_on_disconnect() {
-		sp_tracing::try_init_simple();
+	fn block_requests_pause_until_import_queue_drains() {
+		let client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
-		let mut client = Arc::new(TestClientBuilder::new().build());
-		let blocks = (0..3).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>();
-
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
-			client.clone(),
+			client,
 			ProtocolName::from("test-block-announce-protocol"),
 			1,
 			64,
@@ -3251,37 +6177,37 @@ mod test {
 			import_queue,
 		)
 		.unwrap();
+		sync.set_import_queue_water_marks(2, 1);
 
-		let peer_id1 = PeerId::random();
-		let common_block = blocks[1].clone();
-		// Connect the node we will sync from
-		sync.new_peer(peer_id1, common_block.hash(), *common_block.header().number())
-			.unwrap();
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 10).unwrap();
 
-		// Create a "new" header and announce it
-		let mut header = blocks[0].header().clone();
-		header.number = 4;
-		send_block_announce(header, peer_id1, &mut sync);
-		assert!(sync.fork_targets.len() == 1);
+		let queued = (0..3).map(|_| Hash::random()).collect::<Vec<_>>();
+		sync.queue_blocks.extend(queued.iter().copied());
+		assert!(sync.block_requests().is_empty());
 
-		sync.peer_disconnected(&peer_id1);
-		assert!(sync.fork_targets.len() == 0);
+		// Below the high water mark, but not yet below the low one.
+		sync.queue_blocks.remove(&queued[0]);
+		sync.queue_blocks.remove(&queued[1]);
+		assert!(sync.block_requests().is_empty());
+
+		sync.queue_blocks.remove(&queued[2]);
+		assert_eq!(sync.block_requests().len(), 1);
 	}
 
 	#[test]
-	fn can_import_response_with_missing_blocks() {
-		sp_tracing::try_init_simple();
+	fn banned_blocks_are_neither_requested_nor_accepted() {
+		let client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
-		let mut client2 = Arc::new(TestClientBuilder::new().build());
-		let blocks = (0..4).map(|_| build_block(&mut client2, None, false)).collect::<Vec<_>>();
-
-		let empty_client = Arc::new(TestClientBuilder::new().build());
-
+		let blocks = {
+			let mut client = Arc::new(TestClientBuilder::new().build());
+			(0..2).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>()
+		};
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
-			empty_client.clone(),
+			client.clone(),
 			ProtocolName::from("test-block-announce-protocol"),
 			1,
 			64,
@@ -3292,43 +6218,39 @@ mod test {
 		)
 		.unwrap();
 
-		let peer_id1 = PeerId::random();
-		let best_block = blocks[3].clone();
-		sync.new_peer(peer_id1, best_block.hash(), *best_block.header().number())
-			.unwrap();
-
-		sync.peers.get_mut(&peer_id1).unwrap().state = PeerSyncState::Available;
-		sync.peers.get_mut(&peer_id1).unwrap().common_number = 0;
-
-		// Request all missing blocks and respond only with some.
-		let request =
-			get_block_request(&mut sync, FromBlock::Hash(best_block.hash()), 4, &peer_id1);
-		let response =
-			create_block_response(vec![blocks[3].clone(), blocks[2].clone(), blocks[1].clone()]);
-		sync.on_block_data(&peer_id1, Some(request.clone()), response).unwrap();
-		assert_eq!(sync.best_queued_number, 0);
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, client.info().best_hash, 0).unwrap();
+		sync.set_sync_fork_request(vec![peer_id], &blocks[1].hash(), 2);
+		sync.peers.get_mut(&peer_id).unwrap().state =
+			PeerSyncState::DownloadingStale(blocks[1].hash());
+
+		sync.ban_block(blocks[0].hash());
+		sync.ban_block(blocks[1].hash());
+		assert!(sync.fork_targets.is_empty());
+		assert_eq!(sync.peers.get(&peer_id).unwrap().state, PeerSyncState::Available);
+		assert_eq!(sync.take_cancelled_requests(), vec![peer_id]);
+		assert!(sync.is_banned_block(&Hash::random(), &blocks[0].hash()));
+
+		// Banned forks are not requested again.
+		sync.set_sync_fork_request(vec![peer_id], &blocks[1].hash(), 2);
+		assert!(sync.fork_targets.is_empty());
 
-		// Request should only contain the missing block.
-		let request = get_block_request(&mut sync, FromBlock::Number(1), 1, &peer_id1);
 		let response = create_block_response(vec![blocks[0].clone()]);
-		sync.on_block_data(&peer_id1, Some(request), response).unwrap();
-		assert_eq!(sync.best_queued_number, 4);
-	}
-	#[test]
-	fn ancestor_search_repeat() {
-		let state = AncestorSearchState::<Block>::BinarySearch(1, 3);
-		assert!(handle_ancestor_search_state(&state, 2, true).is_none());
+		assert_eq!(
+			sync.on_block_data(&peer_id, None, response).err(),
+			Some(BadPeer(peer_id, rep::BAD_BLOCK)),
+		);
 	}
 
 	#[test]
-	fn sync_restart_removes_block_but_not_justification_requests() {
-		let mut client = Arc::new(TestClientBuilder::new().build());
+	fn finalized_only_sync_stops_at_known_finalized_block() {
+		let client = Arc::new(TestClientBuilder::new().build());
 		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
 		let (_chain_sync_network_provider, chain_sync_network_handle) =
 			NetworkServiceProvider::new();
 		let mut sync = ChainSync::new(
 			SyncMode::Full,
-			client.clone(),
+			client,
 			ProtocolName::from("test-block-announce-protocol"),
 			1,
 			64,
@@ -3339,86 +6261,197 @@ mod test {
 		)
 		.unwrap();
 
-		let peers = vec![PeerId::random(), PeerId::random()];
+		let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+		for (peer_id, finalized) in peers.iter().zip([10, 20, 1000]) {
+			sync.new_peer(*peer_id, Hash::random(), 1000).unwrap();
+			sync.peers.get_mut(peer_id).unwrap().finalized_number = Some(finalized);
+		}
+		assert_eq!(sync.sync_target_number(), None);
 
-		let mut new_blocks = |n| {
-			for _ in 0..n {
-				let block = client.new_block(Default::default()).unwrap().build().unwrap().block;
-				block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
-			}
+		// A single peer reporting a high finalized block is not trusted.
+		sync.set_finalized_only(true);
+		assert_eq!(sync.sync_target_number(), Some(20));
 
-			let info = client.info();
-			(info.best_hash, info.best_number)
+		sync.set_sync_fork_request(vec![peers[0]], &Hash::random(), 30);
+		assert_eq!(sync.sync_target_number(), Some(30));
+
+		sync.set_sync_target(BlockId::Number(25));
+		assert_eq!(sync.sync_target_number(), Some(25));
+	}
+
+	#[test]
+	fn tail_of_major_sync_is_raced() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let blocks = {
+			let mut client = Arc::new(TestClientBuilder::new().build());
+			(0..10).map(|_| build_block(&mut client, None, false)).collect::<Vec<_>>()
 		};
+		let best = blocks.last().unwrap();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			5,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+		sync.set_tail_race_blocks(64);
 
-		let (b1_hash, b1_number) = new_blocks(50);
+		let (peer_id1, peer_id2) = (PeerId::random(), PeerId::random());
+		sync.new_peer(peer_id1, best.hash(), 10).unwrap();
+		sync.new_peer(peer_id2, best.hash(), 10).unwrap();
 
-		// add new peer and request blocks from them
-		sync.new_peer(peers[0], Hash::random(), 42).unwrap();
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 2);
+		assert_eq!(requests[0].1, requests[1].1);
 
-		// we don't actually perform any requests, just keep track of peers waiting for a response
-		let mut pending_responses = HashSet::new();
+		let (winner, request) = requests[0].clone();
+		let loser = if winner == peer_id1 { peer_id2 } else { peer_id1 };
+		let response = create_block_response(blocks.iter().rev().cloned().collect());
+		sync.on_block_data(&winner, Some(request), response).unwrap();
 
-		// we wil send block requests to these peers
-		// for these blocks we don't know about
-		for (peer, _request) in sync.block_requests() {
-			// "send" request
-			pending_responses.insert(peer);
-		}
+		assert_eq!(sync.peers.get(&loser).unwrap().state, PeerSyncState::Available);
+		assert_eq!(sync.take_cancelled_requests(), vec![loser]);
+	}
 
-		// add a new peer at a known block
-		sync.new_peer(peers[1], b1_hash, b1_number).unwrap();
+	#[test]
+	fn peers_with_stale_best_block_are_kept_off_critical_path() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			5,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
 
-		// we request a justification for a block we have locally
-		sync.request_justification(&b1_hash, b1_number);
+		let best_hash = Hash::random();
+		let (fresh, stale) = (PeerId::random(), PeerId::random());
+		sync.new_peer(fresh, best_hash, 20).unwrap();
+		sync.new_peer(stale, best_hash, 20).unwrap();
+		sync.set_peer_best_stale(&stale, true);
 
-		// the justification request should be scheduled to the
-		// new peer which is at the given block
-		let mut requests = sync.justification_requests();
+		let requests = sync.block_requests();
 		assert_eq!(requests.len(), 1);
-		let (peer, _request) = requests.remove(0);
-		// "send" request
-		assert!(pending_responses.insert(peer));
+		assert_eq!(requests[0].0, fresh);
 
-		assert!(!std::matches!(
-			sync.peers.get(&peers[0]).unwrap().state,
-			PeerSyncState::DownloadingJustification(_),
-		));
+		sync.set_peer_best_stale(&stale, false);
+		let requests = sync.block_requests();
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].0, stale);
+	}
+
+	#[test]
+	fn network_best_estimate_resists_outliers() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+		assert_eq!(sync.network_best_estimate(), None);
+
+		let peers = [10, 11, 12, 1_000_000].map(|number| {
+			let peer_id = PeerId::random();
+			sync.new_peer(peer_id, Hash::random(), number).unwrap();
+			peer_id
+		});
+
+		// The peer reporting a far off best block only moves the upper quartile.
+		let estimate = sync.network_best_estimate().unwrap();
 		assert_eq!(
-			sync.peers.get(&peers[1]).unwrap().state,
-			PeerSyncState::DownloadingJustification(b1_hash),
+			(estimate.lower_quartile, estimate.median, estimate.upper_quartile),
+			(10, 11, 12),
 		);
-		assert_eq!(pending_responses.len(), 2);
+		assert_eq!((estimate.num_peers, estimate.num_fresh_peers), (4, 4));
 
-		// restart sync
-		let request_events = sync.restart().collect::<Vec<_>>();
-		for event in request_events.iter() {
-			match event.as_ref().unwrap() {
-				BlockRequestEvent::RemoveStale { peer_id } => {
-					pending_responses.remove(&peer_id);
-				},
-				BlockRequestEvent::SendRequest { peer_id, .. } => {
-					// we drop obsolete response, but don't register a new request, it's checked in
-					// the `assert!` below
-					pending_responses.remove(&peer_id);
-				},
-			}
-		}
-		assert!(request_events.iter().any(|event| {
-			match event.as_ref().unwrap() {
-				BlockRequestEvent::RemoveStale { .. } => false,
-				BlockRequestEvent::SendRequest { peer_id, .. } => peer_id == &peers[0],
-			}
-		}));
+		// Peers whose best block is stale weigh less.
+		sync.set_peer_best_stale(&peers[0], true);
+		sync.set_peer_best_stale(&peers[1], true);
+		let estimate = sync.network_best_estimate().unwrap();
+		assert_eq!(
+			(estimate.lower_quartile, estimate.median, estimate.upper_quartile),
+			(11, 12, 1_000_000),
+		);
+		assert_eq!((estimate.num_peers, estimate.num_fresh_peers), (4, 2));
+	}
 
-		assert_eq!(pending_responses.len(), 1);
-		assert!(pending_responses.contains(&peers[1]));
+	#[test]
+	fn peers_are_offered_other_requests_after_a_run_of_one_kind() {
+		use RequestPriority::*;
+
+		let run = |kind, count| Some((kind, count));
+		let all = [BestChain, Justification, Fork];
+
+		assert!(!defers_request(None, BestChain, &all));
+		assert!(!defers_request(run(BestChain, MAX_CONSECUTIVE_REQUESTS - 1), BestChain, &all));
+		assert!(defers_request(run(BestChain, MAX_CONSECUTIVE_REQUESTS), BestChain, &all));
+		// Other kinds are not deferred by the run.
+		assert!(!defers_request(run(BestChain, MAX_CONSECUTIVE_REQUESTS), Fork, &all));
+		// Nothing else to do for the peer.
+		assert!(!defers_request(run(BestChain, MAX_CONSECUTIVE_REQUESTS), BestChain, &[BestChain]));
+	}
+
+	#[test]
+	fn justification_is_interleaved_with_block_ranges() {
+		let mut client = Arc::new(TestClientBuilder::new().build());
+		let import_queue = Box::new(sc_consensus::import_queue::mock::MockImportQueueHandle::new());
+		let (_chain_sync_network_provider, chain_sync_network_handle) =
+			NetworkServiceProvider::new();
+		let block = build_block(&mut client, None, false);
+		let mut sync = ChainSync::new(
+			SyncMode::Full,
+			client,
+			ProtocolName::from("test-block-announce-protocol"),
+			1,
+			64,
+			None,
+			None,
+			chain_sync_network_handle,
+			import_queue,
+		)
+		.unwrap();
+
+		// The peer is ahead of us and has been downloading blocks for a while.
+		let peer_id = PeerId::random();
+		sync.new_peer(peer_id, Hash::random(), 10).unwrap();
+		let peer = sync.peers.get_mut(&peer_id).unwrap();
+		peer.state = PeerSyncState::Available;
+		peer.common_number = 1;
+		peer.request_run = Some((RequestPriority::BestChain, MAX_CONSECUTIVE_REQUESTS));
+		sync.request_justification(&block.hash(), 1);
+
+		// The justification goes first although best chain requests have a higher priority.
+		let requests = sync.block_requests();
+		assert_eq!(requests, vec![(peer_id, justification_request::<Block>(block.hash()))]);
 		assert_eq!(
-			sync.peers.get(&peers[1]).unwrap().state,
-			PeerSyncState::DownloadingJustification(b1_hash),
+			sync.peers.get(&peer_id).unwrap().request_run,
+			Some((RequestPriority::Justification, 1)),
 		);
-		sync.peer_disconnected(&peers[1]);
-		pending_responses.remove(&peers[1]);
-		assert_eq!(pending_responses.len(), 0);
 	}
 }
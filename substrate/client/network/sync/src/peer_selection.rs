@@ -0,0 +1,131 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`PeerSelection`] decides which peers `ChainSync` asks for block ranges first.
+//!
+//! `ChainSync` walks the peers in the selected order and assigns each available peer the next
+//! range of blocks it can serve, so the peers coming first get the ranges closest to our best
+//! block. Slow peers are still only used when no faster peer is connected.
+
+use crate::PeerSync;
+use rand::seq::SliceRandom;
+use sp_runtime::traits::Block as BlockT;
+
+/// Strategy ordering the peers `ChainSync` assigns block ranges to.
+///
+/// Custom strategies, e.g. preferring peers in the same datacenter, can be registered with
+/// [`SyncingService::set_peer_selection`](crate::SyncingService::set_peer_selection).
+pub trait PeerSelection<B: BlockT>: Send {
+	/// Order `peers` by preference, most preferred first.
+	fn order(&mut self, peers: &mut [&PeerSync<B>]);
+}
+
+/// Visits peers in a random order. This is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Random;
+
+impl<B: BlockT> PeerSelection<B> for Random {
+	fn order(&mut self, peers: &mut [&PeerSync<B>]) {
+		peers.shuffle(&mut rand::thread_rng());
+	}
+}
+
+/// Starts with a different peer on every round of requests, so ranges are spread evenly.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RoundRobin {
+	next: usize,
+}
+
+impl<B: BlockT> PeerSelection<B> for RoundRobin {
+	fn order(&mut self, peers: &mut [&PeerSync<B>]) {
+		if peers.is_empty() {
+			return
+		}
+		peers.sort_unstable_by_key(|peer| peer.peer_id);
+		peers.rotate_left(self.next % peers.len());
+		self.next = self.next.wrapping_add(1);
+	}
+}
+
+/// Prefers the peers that served block requests fastest. Peers that haven't served one yet come
+/// last.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LowestLatency;
+
+impl<B: BlockT> PeerSelection<B> for LowestLatency {
+	fn order(&mut self, peers: &mut [&PeerSync<B>]) {
+		peers.sort_by_key(|peer| (peer.latency.is_none(), peer.latency));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{state_request_handler::STATE_MODES_LEGACY, PeerSyncState};
+	use libp2p::PeerId;
+	use sp_core::H256;
+	use sp_test_primitives::Block;
+	use std::time::Duration;
+
+	fn peer(latency: Option<u64>) -> PeerSync<Block> {
+		PeerSync {
+			peer_id: PeerId::random(),
+			common_number: 0,
+			best_hash: H256::random(),
+			best_number: 10,
+			state: PeerSyncState::Available,
+			max_blocks_per_request: 64,
+			throughput: None,
+			latency: latency.map(Duration::from_millis),
+			finalized_number: None,
+			max_state_response_size: None,
+			state_modes: STATE_MODES_LEGACY,
+			best_stale: false,
+			request_run: None,
+			justification_hints: Vec::new(),
+		}
+	}
+
+	fn ids(peers: &[&PeerSync<Block>]) -> Vec<PeerId> {
+		peers.iter().map(|peer| peer.peer_id).collect()
+	}
+
+	#[test]
+	fn round_robin_rotates_the_first_peer() {
+		let peers = (0..3).map(|_| peer(None)).collect::<Vec<_>>();
+		let mut selection = RoundRobin::default();
+
+		let mut first = peers.iter().collect::<Vec<_>>();
+		PeerSelection::<Block>::order(&mut selection, &mut first);
+		let mut second = peers.iter().rev().collect::<Vec<_>>();
+		PeerSelection::<Block>::order(&mut selection, &mut second);
+
+		let (first, second) = (ids(&first), ids(&second));
+		assert_ne!(first[0], second[0]);
+		assert_eq!(first[1..], second[..2]);
+	}
+
+	#[test]
+	fn lowest_latency_comes_first() {
+		let peers = [peer(None), peer(Some(300)), peer(Some(100))];
+		let mut ordered = peers.iter().collect::<Vec<_>>();
+		LowestLatency.order(&mut ordered);
+
+		assert_eq!(ids(&ordered), vec![peers[2].peer_id, peers[1].peer_id, peers[0].peer_id]);
+	}
+}
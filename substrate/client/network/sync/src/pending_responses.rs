@@ -30,7 +30,10 @@ use log::error;
 use sc_network::request_responses::RequestFailure;
 use sc_network_common::sync::PeerRequest;
 use sp_runtime::traits::Block as BlockT;
-use std::task::{Context, Poll};
+use std::{
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
 use tokio_stream::StreamMap;
 
 /// Response result.
@@ -44,12 +47,15 @@ pub(crate) struct ResponseEvent<B: BlockT> {
 	pub peer_id: PeerId,
 	pub request: PeerRequest<B>,
 	pub response: ResponseResult,
+	/// Time between sending the request and receiving the response.
+	pub elapsed: Duration,
 }
 
 /// Stream taking care of polling pending responses.
 pub(crate) struct PendingResponses<B: BlockT> {
 	/// Pending responses
-	pending_responses: StreamMap<PeerId, BoxStream<'static, (PeerRequest<B>, ResponseResult)>>,
+	pending_responses:
+		StreamMap<PeerId, BoxStream<'static, (PeerRequest<B>, ResponseResult, Duration)>>,
 }
 
 impl<B: BlockT> PendingResponses<B> {
@@ -64,12 +70,16 @@ impl<B: BlockT> PendingResponses<B> {
 		response_future: ResponseFuture,
 	) {
 		let request_type = request.get_type();
+		let started = Instant::now();
 
 		if self
 			.pending_responses
 			.insert(
 				peer_id,
-				Box::pin(async move { (request, response_future.await) }.into_stream()),
+				Box::pin(
+					async move { (request, response_future.await, started.elapsed()) }
+						.into_stream(),
+				),
 			)
 			.is_some()
 		{
@@ -88,6 +98,10 @@ impl<B: BlockT> PendingResponses<B> {
 	pub fn len(&self) -> usize {
 		self.pending_responses.len()
 	}
+
+	pub fn contains(&self, peer_id: &PeerId) -> bool {
+		self.pending_responses.contains_key(peer_id)
+	}
 }
 
 impl<B: BlockT> Unpin for PendingResponses<B> {}
@@ -100,13 +114,13 @@ impl<B: BlockT> Stream for PendingResponses<B> {
 		cx: &mut Context<'_>,
 	) -> Poll<Option<Self::Item>> {
 		match futures::ready!(self.pending_responses.poll_next_unpin(cx)) {
-			Some((peer_id, (request, response))) => {
+			Some((peer_id, (request, response, elapsed))) => {
 				// We need to manually remove the stream, because `StreamMap` doesn't know yet that
 				// it's going to yield `None`, so may not remove it before the next request is made
 				// to the same peer.
 				self.pending_responses.remove(&peer_id);
 
-				Poll::Ready(Some(ResponseEvent { peer_id, request, response }))
+				Poll::Ready(Some(ResponseEvent { peer_id, request, response, elapsed }))
 			},
 			None => Poll::Ready(None),
 		}
@@ -0,0 +1,142 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`PersistedPeer`] records what we know about useful sync peers, so it can be restored after a
+//! restart instead of rediscovering good peers from scratch.
+
+use codec::{Decode, Encode};
+use libp2p::{Multiaddr, PeerId};
+use sp_runtime::traits::{Block as BlockT, NumberFor};
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/// Name of the file the peers are persisted to, in the network configuration directory.
+const PERSISTED_PEERS_FILE: &str = "sync_peers";
+
+/// Sync knowledge about a peer, persisted across restarts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PersistedPeer<B: BlockT> {
+	/// Id of the peer.
+	pub peer_id: PeerId,
+	/// Addresses the peer was reached at.
+	pub addresses: Vec<Multiaddr>,
+	/// Best block hash last reported by the peer.
+	pub best_hash: B::Hash,
+	/// Best block number last reported by the peer.
+	pub best_number: NumberFor<B>,
+	/// Moving average of the blocks per second served by the peer, if measured.
+	pub throughput: Option<u64>,
+}
+
+/// Encoded form of [`PersistedPeer`], with the peer id and addresses as raw bytes.
+type EncodedPeer<B> = (Vec<u8>, Vec<Vec<u8>>, <B as BlockT>::Hash, NumberFor<B>, Option<u64>);
+
+/// Path of the persisted peers file in the network configuration directory `net_config_path`.
+pub(crate) fn path(net_config_path: &Path) -> PathBuf {
+	net_config_path.join(PERSISTED_PEERS_FILE)
+}
+
+/// Load the peers persisted at `path`. Entries that fail to decode are skipped.
+pub(crate) fn load<B: BlockT>(path: &Path) -> io::Result<Vec<PersistedPeer<B>>> {
+	let encoded = match fs::read(path) {
+		Ok(encoded) => encoded,
+		Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(error) => return Err(error),
+	};
+	let peers = Vec::<EncodedPeer<B>>::decode(&mut &encoded[..])
+		.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+	Ok(peers
+		.into_iter()
+		.filter_map(|(peer_id, addresses, best_hash, best_number, throughput)| {
+			Some(PersistedPeer {
+				peer_id: PeerId::from_bytes(&peer_id).ok()?,
+				addresses: addresses
+					.into_iter()
+					.filter_map(|address| Multiaddr::try_from(address).ok())
+					.collect(),
+				best_hash,
+				best_number,
+				throughput,
+			})
+		})
+		.collect())
+}
+
+/// Persist `peers` at `path`, replacing the previously persisted peers.
+pub(crate) fn save<B: BlockT>(path: &Path, peers: &[PersistedPeer<B>]) -> io::Result<()> {
+	let encoded = peers
+		.iter()
+		.map(|peer| {
+			(
+				peer.peer_id.to_bytes(),
+				peer.addresses.iter().map(|address| address.to_vec()).collect::<Vec<_>>(),
+				peer.best_hash,
+				peer.best_number,
+				peer.throughput,
+			)
+		})
+		.collect::<Vec<EncodedPeer<B>>>()
+		.encode();
+
+	// Write to a temporary file first so a crash doesn't leave a truncated file behind.
+	let tmp_path = path.with_extension("tmp");
+	fs::write(&tmp_path, encoded)?;
+	fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_test_primitives::Block;
+
+	#[test]
+	fn persisted_peers_roundtrip() {
+		let dir = std::env::temp_dir().join(format!("sync-peers-{}", PeerId::random()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = path(&dir);
+
+		assert_eq!(load::<Block>(&path).unwrap(), Vec::new());
+
+		let peers = vec![
+			PersistedPeer::<Block> {
+				peer_id: PeerId::random(),
+				addresses: vec!["/ip4/127.0.0.1/tcp/30333".parse().unwrap()],
+				best_hash: Default::default(),
+				best_number: 42,
+				throughput: Some(100),
+			},
+			PersistedPeer::<Block> {
+				peer_id: PeerId::random(),
+				addresses: Vec::new(),
+				best_hash: Default::default(),
+				best_number: 7,
+				throughput: None,
+			},
+		];
+		save(&path, &peers).unwrap();
+		assert_eq!(load::<Block>(&path).unwrap(), peers);
+
+		fs::write(&path, b"junk").unwrap();
+		assert!(load::<Block>(&path).is_err());
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,91 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`RateLimiter`] limits how often something happens per second, allowing short bursts.
+
+use std::time::Instant;
+
+/// Token bucket refilled at `rate` tokens per second, holding at most `rate` tokens.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+	rate: u32,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl RateLimiter {
+	/// Create a new limiter allowing `rate` events per second, starting with a full bucket.
+	pub fn new(rate: u32, now: Instant) -> Self {
+		Self { rate, tokens: rate as f64, last_refill: now }
+	}
+
+	/// Take a token at `now` if one is available.
+	pub fn try_acquire(&mut self, now: Instant) -> bool {
+		if self.has_token(now) {
+			self.tokens -= 1.0;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Check if a token is available at `now`, without taking it.
+	pub fn has_token(&mut self, now: Instant) -> bool {
+		let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+		self.last_refill = now;
+
+		self.tokens >= 1.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn bursts_are_limited_and_refilled() {
+		let start = Instant::now();
+		let mut limiter = RateLimiter::new(2, start);
+
+		assert!(limiter.try_acquire(start));
+		assert!(limiter.try_acquire(start));
+		assert!(!limiter.try_acquire(start));
+
+		assert!(!limiter.try_acquire(start + Duration::from_millis(250)));
+		assert!(limiter.try_acquire(start + Duration::from_millis(500)));
+
+		// The bucket doesn't grow past `rate` tokens.
+		let later = start + Duration::from_secs(10);
+		assert!(limiter.try_acquire(later));
+		assert!(limiter.try_acquire(later));
+		assert!(!limiter.try_acquire(later));
+	}
+
+	#[test]
+	fn checking_for_a_token_does_not_take_it() {
+		let start = Instant::now();
+		let mut limiter = RateLimiter::new(1, start);
+
+		assert!(limiter.has_token(start));
+		assert!(limiter.has_token(start));
+		assert!(limiter.try_acquire(start));
+		assert!(!limiter.has_token(start));
+	}
+}
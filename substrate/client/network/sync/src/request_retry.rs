@@ -0,0 +1,215 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`RequestRetries`] decides when the peers whose requests failed are sent requests again.
+
+use libp2p::PeerId;
+use sc_network::config::RequestRetryPolicy;
+use sc_network_common::sync::PeerRequestType;
+use std::{collections::HashMap, time::Instant};
+
+/// Why a request failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestFailureKind {
+	/// The peer didn't answer in time.
+	Timeout,
+	/// The peer answered with a response that could not be decoded.
+	MalformedResponse,
+}
+
+/// Consecutive failed requests of one protocol to a peer.
+#[derive(Debug, Default)]
+struct Failures {
+	timeouts: u32,
+	malformed_responses: u32,
+	backoff_until: Option<Instant>,
+}
+
+/// Consecutive failed requests of each peer and protocol, retried according to the protocol's
+/// [`RequestRetryPolicy`].
+#[derive(Debug, Default)]
+pub(crate) struct RequestRetries {
+	block: RequestRetryPolicy,
+	state: RequestRetryPolicy,
+	warp_proof: RequestRetryPolicy,
+	failures: HashMap<(PeerId, PeerRequestType), Failures>,
+}
+
+impl RequestRetries {
+	/// Set the retry policies of the block, state and warp proof requests.
+	pub fn set_policies(
+		&mut self,
+		block: RequestRetryPolicy,
+		state: RequestRetryPolicy,
+		warp_proof: RequestRetryPolicy,
+	) {
+		self.block = block;
+		self.state = state;
+		self.warp_proof = warp_proof;
+	}
+
+	fn policy(&self, request: PeerRequestType) -> &RequestRetryPolicy {
+		match request {
+			PeerRequestType::Block => &self.block,
+			PeerRequestType::State => &self.state,
+			PeerRequestType::WarpProof => &self.warp_proof,
+		}
+	}
+
+	/// Record a failed `request` to `peer_id` at `now`, returning whether the peer failed too
+	/// many times in a row and should be disconnected.
+	///
+	/// Otherwise the peer backs off from requests of the same protocol.
+	pub fn on_failure(
+		&mut self,
+		peer_id: &PeerId,
+		request: PeerRequestType,
+		failure: RequestFailureKind,
+		now: Instant,
+	) -> bool {
+		let policy = *self.policy(request);
+		let failures = self.failures.entry((*peer_id, request)).or_default();
+		let exhausted = match failure {
+			RequestFailureKind::Timeout => {
+				failures.timeouts += 1;
+				failures.timeouts >= policy.max_timeouts
+			},
+			RequestFailureKind::MalformedResponse => {
+				failures.malformed_responses += 1;
+				failures.malformed_responses >= policy.max_malformed_responses
+			},
+		};
+		if exhausted {
+			self.failures.remove(&(*peer_id, request));
+			return true
+		}
+
+		let attempt = failures.timeouts + failures.malformed_responses;
+		let backoff = policy
+			.initial_backoff
+			.saturating_mul(1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX))
+			.min(policy.max_backoff);
+		failures.backoff_until = Some(now + backoff);
+		false
+	}
+
+	/// Forget the failed requests of `peer_id` once it answered a `request`.
+	pub fn on_success(&mut self, peer_id: &PeerId, request: PeerRequestType) {
+		self.failures.remove(&(*peer_id, request));
+	}
+
+	/// Whether `peer_id` still backs off from `request`s at `now`.
+	pub fn is_backing_off(
+		&self,
+		peer_id: &PeerId,
+		request: PeerRequestType,
+		now: Instant,
+	) -> bool {
+		self.failures
+			.get(&(*peer_id, request))
+			.and_then(|failures| failures.backoff_until)
+			.map_or(false, |until| now < until)
+	}
+
+	/// End the backoffs elapsed at `now`, returning whether there were any.
+	pub fn expire_backoffs(&mut self, now: Instant) -> bool {
+		let mut expired = false;
+		for failures in self.failures.values_mut() {
+			if failures.backoff_until.map_or(false, |until| until <= now) {
+				failures.backoff_until = None;
+				expired = true;
+			}
+		}
+		expired
+	}
+
+	/// Forget the failed requests of a disconnected peer.
+	pub fn peer_disconnected(&mut self, peer_id: &PeerId) {
+		self.failures.retain(|(id, _), _| id != peer_id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	const BLOCK: PeerRequestType = PeerRequestType::Block;
+	const TIMEOUT: RequestFailureKind = RequestFailureKind::Timeout;
+	const MALFORMED: RequestFailureKind = RequestFailureKind::MalformedResponse;
+
+	fn retries() -> RequestRetries {
+		let mut retries = RequestRetries::default();
+		retries.set_policies(
+			RequestRetryPolicy {
+				max_timeouts: 3,
+				max_malformed_responses: 1,
+				initial_backoff: Duration::from_secs(1),
+				max_backoff: Duration::from_secs(3),
+			},
+			Default::default(),
+			Default::default(),
+		);
+		retries
+	}
+
+	#[test]
+	fn timeouts_back_off_exponentially_until_exhausted() {
+		let mut retries = retries();
+		let peer_id = PeerId::random();
+		let now = Instant::now();
+		let second = Duration::from_secs(1);
+
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(retries.is_backing_off(&peer_id, BLOCK, now));
+		assert!(!retries.is_backing_off(&peer_id, PeerRequestType::State, now));
+		assert!(!retries.is_backing_off(&peer_id, BLOCK, now + second));
+
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(retries.is_backing_off(&peer_id, BLOCK, now + second));
+		assert!(!retries.expire_backoffs(now + second));
+		assert!(retries.expire_backoffs(now + 2 * second));
+
+		assert!(retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(!retries.is_backing_off(&peer_id, BLOCK, now));
+	}
+
+	#[test]
+	fn malformed_responses_are_counted_separately() {
+		let mut retries = retries();
+		let peer_id = PeerId::random();
+		let now = Instant::now();
+
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(retries.on_failure(&peer_id, BLOCK, MALFORMED, now));
+	}
+
+	#[test]
+	fn success_resets_failures() {
+		let mut retries = retries();
+		let peer_id = PeerId::random();
+		let now = Instant::now();
+
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		retries.on_success(&peer_id, BLOCK);
+		assert!(!retries.is_backing_off(&peer_id, BLOCK, now));
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+		assert!(!retries.on_failure(&peer_id, BLOCK, TIMEOUT, now));
+	}
+}
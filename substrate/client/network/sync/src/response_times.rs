@@ -0,0 +1,83 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`ResponseTimes`] keeps the recent round-trip times of the requests sent to each peer.
+
+use libp2p::PeerId;
+use sc_network_common::sync::PeerRequestType;
+use std::{
+	collections::{HashMap, VecDeque},
+	time::Duration,
+};
+
+/// Number of the most recent round-trip times kept per peer and protocol.
+const MAX_SAMPLES: usize = 32;
+
+/// Recent round-trip times of the requests of each protocol sent to each peer.
+#[derive(Debug, Default)]
+pub(crate) struct ResponseTimes {
+	samples: HashMap<(PeerId, PeerRequestType), VecDeque<Duration>>,
+}
+
+impl ResponseTimes {
+	/// Record that a `request` to `peer_id` took `elapsed` to be answered or to time out.
+	pub fn record(&mut self, peer_id: &PeerId, request: PeerRequestType, elapsed: Duration) {
+		let samples = self.samples.entry((*peer_id, request)).or_default();
+		if samples.len() == MAX_SAMPLES {
+			samples.pop_front();
+		}
+		samples.push_back(elapsed);
+	}
+
+	/// The 95th percentile of the recent round-trip times of `request`s to `peer_id`, `None` if
+	/// none was recorded.
+	pub fn p95(&self, peer_id: &PeerId, request: PeerRequestType) -> Option<Duration> {
+		let mut samples =
+			self.samples.get(&(*peer_id, request))?.iter().copied().collect::<Vec<_>>();
+		samples.sort();
+		let rank = (samples.len() * 95 + 99) / 100;
+		samples.get(rank.saturating_sub(1)).copied()
+	}
+
+	/// Forget the round-trip times of a disconnected peer.
+	pub fn peer_disconnected(&mut self, peer_id: &PeerId) {
+		self.samples.retain(|(id, _), _| id != peer_id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn p95_of_recent_samples() {
+		let mut times = ResponseTimes::default();
+		let peer_id = PeerId::random();
+		assert_eq!(times.p95(&peer_id, PeerRequestType::Block), None);
+
+		for millis in 1..=100 {
+			times.record(&peer_id, PeerRequestType::Block, Duration::from_millis(millis));
+		}
+		// Only the last `MAX_SAMPLES` are kept, 69 to 100 ms.
+		assert_eq!(times.p95(&peer_id, PeerRequestType::Block), Some(Duration::from_millis(99)));
+		assert_eq!(times.p95(&peer_id, PeerRequestType::State), None);
+
+		times.peer_disconnected(&peer_id);
+		assert_eq!(times.p95(&peer_id, PeerRequestType::Block), None);
+	}
+}
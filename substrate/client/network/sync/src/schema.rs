@@ -21,3 +21,7 @@
 pub(crate) mod v1 {
 	include!(concat!(env!("OUT_DIR"), "/api.v1.rs"));
 }
+
+pub(crate) mod v2 {
+	include!(concat!(env!("OUT_DIR"), "/api.v2.rs"));
+}
@@ -16,25 +16,69 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use codec::Decode;
 use futures::{channel::oneshot, Stream};
+use futures_timer::Delay;
 use libp2p::PeerId;
 
+use crate::{
+	block_relay_protocol::BlockResponseError, engine_state::SyncEngineState,
+	fork_choice::ForkChoice, peer_selection::PeerSelection,
+	service::{command_channel::CommandSender, network::PeerAuditEvent},
+	warp::WarpSyncTargetError,
+};
+
 use sc_consensus::{BlockImportError, BlockImportStatus, JustificationSyncLink, Link};
-use sc_network::{NetworkBlock, NetworkSyncForkRequest};
+use sc_network::{request_responses::RequestFailure, NetworkBlock, NetworkSyncForkRequest};
 use sc_network_common::sync::{
-	ExtendedPeerInfo, SyncEvent, SyncEventStream, SyncStatus, SyncStatusProvider,
+	message::BlockData, ExtendedPeerInfo, NetworkBestEstimate, PeerStatistics, SyncEvent,
+	SyncEventStream, SyncStatus, SyncStatusProvider, SyncThroughput,
 };
 use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedSender};
-use sp_runtime::traits::{Block as BlockT, NumberFor};
+use sp_runtime::{
+	generic::{BlockId, SignedBlock},
+	traits::{Block as BlockT, NumberFor},
+};
 
 use std::{
+	collections::HashSet,
+	io::{self, BufRead, BufReader, Read},
+	ops::RangeInclusive,
 	pin::Pin,
 	sync::{
 		atomic::{AtomicBool, AtomicUsize, Ordering},
 		Arc,
 	},
+	time::Duration,
 };
 
+/// Number of blocks read by [`SyncingService::import_blocks_from`] before they are queued.
+const IMPORT_BATCH_SIZE: usize = 128;
+
+/// [`SyncingService::import_blocks_from`] stops reading while more blocks than this are queued
+/// for import.
+const MAX_QUEUED_IMPORT_BLOCKS: u32 = 2048;
+
+/// Error returned by [`SyncingService::request_block_from`].
+#[derive(Debug, thiserror::Error)]
+pub enum RequestBlockError {
+	/// The peer isn't connected over the block announces protocol.
+	#[error("Unknown peer.")]
+	UnknownPeer,
+	/// The request failed.
+	#[error(transparent)]
+	Request(#[from] RequestFailure),
+	/// The request was dropped before a response was received.
+	#[error("Request canceled.")]
+	Canceled,
+	/// The response couldn't be decoded.
+	#[error("Invalid response: {0:?}.")]
+	InvalidResponse(BlockResponseError),
+	/// `SyncingEngine` has terminated.
+	#[error("Syncing engine terminated.")]
+	Terminated,
+}
+
 /// Commands send to `ChainSync`
 pub enum ToServiceCommand<B: BlockT> {
 	SetSyncForkRequest(Vec<PeerId>, B::Hash, NumberFor<B>),
@@ -49,6 +93,10 @@ pub enum ToServiceCommand<B: BlockT> {
 	AnnounceBlock(B::Hash, Option<Vec<u8>>),
 	NewBestBlockImported(B::Hash, NumberFor<B>),
 	EventStream(TracingUnboundedSender<SyncEvent>),
+	SubscribeStatus(TracingUnboundedSender<SyncStatus<B>>),
+	SubscribePeerAudit(TracingUnboundedSender<PeerAuditEvent>),
+	SetPollProfiling(bool),
+	PeerFinalized(PeerId, B::Hash, NumberFor<B>, bool),
 	Status(oneshot::Sender<SyncStatus<B>>),
 	NumActivePeers(oneshot::Sender<usize>),
 	SyncState(oneshot::Sender<SyncStatus<B>>),
@@ -57,8 +105,35 @@ pub enum ToServiceCommand<B: BlockT> {
 	NumQueuedBlocks(oneshot::Sender<u32>),
 	NumDownloadedBlocks(oneshot::Sender<usize>),
 	NumSyncRequests(oneshot::Sender<usize>),
+	BlocksBehind(oneshot::Sender<Option<NumberFor<B>>>),
+	NetworkBestEstimate(oneshot::Sender<Option<NetworkBestEstimate<NumberFor<B>>>>),
 	PeersInfo(oneshot::Sender<Vec<(PeerId, ExtendedPeerInfo<B>)>>),
+	PeerStatistics(oneshot::Sender<Vec<(PeerId, PeerStatistics)>>),
+	Throughput(oneshot::Sender<SyncThroughput>),
 	OnBlockFinalized(B::Hash, B::Header),
+	SetSyncTarget(BlockId<B>),
+	SetWarpSyncTarget(B::Header, oneshot::Sender<Result<(), WarpSyncTargetError>>),
+	StartGapSync(RangeInclusive<NumberFor<B>>),
+	SetMaxGapRequests(Option<usize>),
+	SetPriorityPeers(HashSet<PeerId>),
+	SetReservedPeers(HashSet<PeerId>),
+	SetForkChoice(Box<dyn ForkChoice<B>>),
+	SetPeerSelection(Box<dyn PeerSelection<B>>),
+	ImportBlocks(Vec<SignedBlock<B>>, oneshot::Sender<usize>),
+	SetWaiting(String),
+	SetReady,
+	RequestBlockFrom(
+		PeerId,
+		B::Hash,
+		oneshot::Sender<Result<Option<BlockData<B>>, RequestBlockError>>,
+	),
+	PauseSync,
+	ResumeSync,
+	DisconnectPeer(PeerId, String),
+	BanPeer(PeerId, Duration),
+	BanBlock(B::Hash),
+	DumpState(oneshot::Sender<SyncEngineState>),
+	Stop(oneshot::Sender<()>),
 	// Status {
 	// 	pending_response: oneshot::Sender<SyncStatus<B>>,
 	// },
@@ -67,7 +142,7 @@ pub enum ToServiceCommand<B: BlockT> {
 /// Handle for communicating with `ChainSync` asynchronously
 #[derive(Clone)]
 pub struct SyncingService<B: BlockT> {
-	tx: TracingUnboundedSender<ToServiceCommand<B>>,
+	tx: CommandSender<B>,
 	/// Number of peers we're connected to.
 	num_connected: Arc<AtomicUsize>,
 	/// Are we actively catching up with the chain?
@@ -77,17 +152,22 @@ pub struct SyncingService<B: BlockT> {
 impl<B: BlockT> SyncingService<B> {
 	/// Create new handle
 	pub fn new(
-		tx: TracingUnboundedSender<ToServiceCommand<B>>,
+		tx: CommandSender<B>,
 		num_connected: Arc<AtomicUsize>,
 		is_major_syncing: Arc<AtomicBool>,
 	) -> Self {
 		Self { tx, num_connected, is_major_syncing }
 	}
 
+	/// Whether `SyncingEngine` falls behind processing queries, so that new ones have to wait.
+	pub fn is_overloaded(&self) -> bool {
+		self.tx.is_overloaded()
+	}
+
 	/// Get the number of active peers.
 	pub async fn num_active_peers(&self) -> Result<usize, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::NumActivePeers(tx));
+		self.tx.send_async(ToServiceCommand::NumActivePeers(tx)).await;
 
 		rx.await
 	}
@@ -95,7 +175,7 @@ impl<B: BlockT> SyncingService<B> {
 	/// Get best seen block.
 	pub async fn best_seen_block(&self) -> Result<Option<NumberFor<B>>, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::BestSeenBlock(tx));
+		self.tx.send_async(ToServiceCommand::BestSeenBlock(tx)).await;
 
 		rx.await
 	}
@@ -103,7 +183,7 @@ impl<B: BlockT> SyncingService<B> {
 	/// Get the number of sync peers.
 	pub async fn num_sync_peers(&self) -> Result<u32, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::NumSyncPeers(tx));
+		self.tx.send_async(ToServiceCommand::NumSyncPeers(tx)).await;
 
 		rx.await
 	}
@@ -111,7 +191,33 @@ impl<B: BlockT> SyncingService<B> {
 	/// Get the number of queued blocks.
 	pub async fn num_queued_blocks(&self) -> Result<u32, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::NumQueuedBlocks(tx));
+		self.tx.send_async(ToServiceCommand::NumQueuedBlocks(tx)).await;
+
+		rx.await
+	}
+
+	/// Get the number of blocks our best block is behind the median best block of the peers,
+	/// `None` without peers.
+	///
+	/// Sync is major once this exceeds the configured threshold, consumers needing a stricter or
+	/// looser definition can compare it against their own.
+	pub async fn blocks_behind(&self) -> Result<Option<NumberFor<B>>, oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::BlocksBehind(tx)).await;
+
+		rx.await
+	}
+
+	/// Get an estimate of the best block of the network with its spread, from the best blocks of
+	/// all peers, `None` without peers.
+	///
+	/// Unlike [`SyncingService::best_seen_block`], a few peers reporting far off best blocks
+	/// don't move the median, and the quartiles and number of peers tell how much to trust it.
+	pub async fn network_best_estimate(
+		&self,
+	) -> Result<Option<NetworkBestEstimate<NumberFor<B>>>, oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::NetworkBestEstimate(tx)).await;
 
 		rx.await
 	}
@@ -119,7 +225,7 @@ impl<B: BlockT> SyncingService<B> {
 	/// Get the number of downloaded blocks.
 	pub async fn num_downloaded_blocks(&self) -> Result<usize, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::NumDownloadedBlocks(tx));
+		self.tx.send_async(ToServiceCommand::NumDownloadedBlocks(tx)).await;
 
 		rx.await
 	}
@@ -127,7 +233,7 @@ impl<B: BlockT> SyncingService<B> {
 	/// Get the number of sync requests.
 	pub async fn num_sync_requests(&self) -> Result<usize, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::NumSyncRequests(tx));
+		self.tx.send_async(ToServiceCommand::NumSyncRequests(tx)).await;
 
 		rx.await
 	}
@@ -137,14 +243,225 @@ impl<B: BlockT> SyncingService<B> {
 		&self,
 	) -> Result<Vec<(PeerId, ExtendedPeerInfo<B>)>, oneshot::Canceled> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::PeersInfo(tx));
+		self.tx.send_async(ToServiceCommand::PeersInfo(tx)).await;
+
+		rx.await
+	}
+
+	/// Get sync statistics of the connected peers.
+	pub async fn peer_statistics(
+		&self,
+	) -> Result<Vec<(PeerId, PeerStatistics)>, oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::PeerStatistics(tx)).await;
+
+		rx.await
+	}
+
+	/// Get the recent download throughput and the estimated time to reach the best seen block.
+	pub async fn throughput(&self) -> Result<SyncThroughput, oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::Throughput(tx)).await;
 
 		rx.await
 	}
 
 	/// Notify the `SyncingEngine` that a block has been finalized.
 	pub fn on_block_finalized(&self, hash: B::Hash, header: B::Header) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::OnBlockFinalized(hash, header));
+		self.tx.send(ToServiceCommand::OnBlockFinalized(hash, header));
+	}
+
+	/// Stop downloading blocks past `target`. Syncing is reported as idle once the target is
+	/// reached.
+	pub fn set_sync_target(&self, target: BlockId<B>) {
+		self.tx.send(ToServiceCommand::SetSyncTarget(target));
+	}
+
+	/// Download the blocks in `range` in the background, replacing any gap sync in progress.
+	///
+	/// Gaps reported by the client, e.g. after warp sync, are filled automatically.
+	pub fn start_gap_sync(&self, range: RangeInclusive<NumberFor<B>>) {
+		self.tx.send(ToServiceCommand::StartGapSync(range));
+	}
+
+	/// Limit the number of gap block requests in flight at once. Unlimited if `None`.
+	pub fn set_max_gap_requests(&self, max_gap_requests: Option<usize>) {
+		self.tx.send(ToServiceCommand::SetMaxGapRequests(max_gap_requests));
+	}
+
+	/// Announce blocks to `peers`, e.g. the known validators, before other peers. Peers with the
+	/// authority role are always announced to first.
+	///
+	/// Replaces the previously set peers, so it can be called periodically to refresh them.
+	///
+	/// The peers are also the ones that may take the slots kept with
+	/// [`NetworkConfiguration::authority_peer_slots`](sc_network::config::NetworkConfiguration).
+	pub fn set_priority_peers(&self, peers: HashSet<PeerId>) {
+		self.tx.send(ToServiceCommand::SetPriorityPeers(peers));
+	}
+
+	/// Replace the reserved nodes of the default peer set known to sync with `peers`.
+	///
+	/// Reserved nodes don't occupy peer slots and are the only peers sent requests with
+	/// `sync_from_reserved_peers_only`. Call along with updating the reserved nodes of the block
+	/// announce protocol in the network, e.g. with `NetworkPeers::set_reserved_peers`, so that the
+	/// peerset connects them.
+	pub fn set_reserved_peers(&self, peers: HashSet<PeerId>) {
+		self.tx.send(ToServiceCommand::SetReservedPeers(peers));
+	}
+
+	/// Use `fork_choice` instead of the longest chain rule to decide which announced fork is
+	/// downloaded first.
+	pub fn set_fork_choice(&self, fork_choice: Box<dyn ForkChoice<B>>) {
+		self.tx.send(ToServiceCommand::SetForkChoice(fork_choice));
+	}
+
+	/// Use `peer_selection` to decide which peers are asked for block ranges first, instead of
+	/// visiting them in a random order.
+	pub fn set_peer_selection(&self, peer_selection: Box<dyn PeerSelection<B>>) {
+		self.tx.send(ToServiceCommand::SetPeerSelection(peer_selection));
+	}
+
+	/// Signal that the node is intentionally idle for `reason`, e.g. a parachain collator waiting
+	/// for relay chain blocks, so peers aren't evicted for inactivity until
+	/// [`SyncingService::set_ready`] is called.
+	pub fn set_waiting(&self, reason: String) {
+		self.tx.send(ToServiceCommand::SetWaiting(reason));
+	}
+
+	/// Signal that the node is no longer waiting, see [`SyncingService::set_waiting`].
+	pub fn set_ready(&self) {
+		self.tx.send(ToServiceCommand::SetReady);
+	}
+
+	/// Request the header, body and justifications of block `hash` from `peer_id`, outside of the
+	/// normal sync scheduling. The response isn't imported.
+	///
+	/// Returns `None` if the peer doesn't have the block.
+	pub async fn request_block_from(
+		&self,
+		peer_id: PeerId,
+		hash: B::Hash,
+	) -> Result<Option<BlockData<B>>, RequestBlockError> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send(ToServiceCommand::RequestBlockFrom(peer_id, hash, tx));
+
+		rx.await.map_err(|_| RequestBlockError::Terminated)?
+	}
+
+	/// Set the block whose state warp sync downloads, skipping the download of the warp proofs.
+	///
+	/// The header is trusted, it must be above the finalized block. It replaces the target set
+	/// earlier, unless the state of that one is being downloaded already.
+	pub async fn set_warp_sync_target(&self, header: B::Header) -> Result<(), WarpSyncTargetError> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send(ToServiceCommand::SetWarpSyncTarget(header, tx));
+
+		rx.await.map_err(|_| WarpSyncTargetError::Terminated)?
+	}
+
+	/// Stop sending new block and state requests, and stop importing downloaded blocks, until
+	/// [`SyncingService::resume_sync`] is called. Peers stay connected.
+	pub fn pause_sync(&self) {
+		self.tx.send(ToServiceCommand::PauseSync);
+	}
+
+	/// Resume syncing paused by [`SyncingService::pause_sync`].
+	pub fn resume_sync(&self) {
+		self.tx.send(ToServiceCommand::ResumeSync);
+	}
+
+	/// Disconnect `peer_id` from the block announcement protocol. `reason` is logged.
+	///
+	/// The peer may reconnect right away, use [`SyncingService::ban_peer`] to prevent that.
+	pub fn disconnect_peer(&self, peer_id: PeerId, reason: String) {
+		self.tx.send(ToServiceCommand::DisconnectPeer(peer_id, reason));
+	}
+
+	/// Disconnect `peer_id`, lower its reputation and refuse its sync connections for `duration`.
+	pub fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+		self.tx.send(ToServiceCommand::BanPeer(peer_id, duration));
+	}
+
+	/// Never request, accept or import the block `hash` and its descendants, e.g. to exclude a
+	/// fork in an emergency. Peers announcing or serving them are disconnected.
+	pub fn ban_block(&self, hash: B::Hash) {
+		self.tx.send(ToServiceCommand::BanBlock(hash));
+	}
+
+	/// Enable or disable accounting the time spent in each section of the sync engine poll in
+	/// the `substrate_sync_poll_duration_microseconds` metric.
+	///
+	/// The sections are also traced as spans on the `sync::poll` target whenever it is enabled,
+	/// e.g. with `system_addLogFilter`.
+	pub fn set_poll_profiling(&self, enabled: bool) {
+		self.tx.send(ToServiceCommand::SetPollProfiling(enabled));
+	}
+
+	/// Notify that `peer_id` finalized the block `hash` and whether it holds its justification,
+	/// so that justification requests for the block are sent to the peers holding it.
+	pub fn on_peer_finalized(
+		&self,
+		peer_id: PeerId,
+		hash: B::Hash,
+		number: NumberFor<B>,
+		has_justification: bool,
+	) {
+		self.tx
+			.send(ToServiceCommand::PeerFinalized(peer_id, hash, number, has_justification));
+	}
+
+	/// Import the SCALE-encoded [`SignedBlock`]s read from `reader`, e.g. a block archive or the
+	/// export of another database, without requesting them from the network.
+	///
+	/// Blocks must be in import order. Reading pauses while the import queue is full. Returns the
+	/// number of blocks queued for import.
+	pub async fn import_blocks_from(&self, reader: impl Read) -> io::Result<usize> {
+		let mut reader = BufReader::new(reader);
+		let mut imported = 0;
+
+		loop {
+			let mut blocks = Vec::with_capacity(IMPORT_BATCH_SIZE);
+			while blocks.len() < IMPORT_BATCH_SIZE && !reader.fill_buf()?.is_empty() {
+				let block = SignedBlock::<B>::decode(&mut codec::IoReader(&mut reader))
+					.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+				blocks.push(block);
+			}
+			if blocks.is_empty() {
+				return Ok(imported)
+			}
+
+			let engine_stopped = |_| io::Error::from(io::ErrorKind::BrokenPipe);
+			while self.num_queued_blocks().await.map_err(engine_stopped)? > MAX_QUEUED_IMPORT_BLOCKS
+			{
+				Delay::new(Duration::from_millis(100)).await;
+			}
+
+			let (tx, rx) = oneshot::channel();
+			self.tx.send_async(ToServiceCommand::ImportBlocks(blocks, tx)).await;
+			imported += rx.await.map_err(engine_stopped)?;
+		}
+	}
+
+	/// Get a snapshot of the internal state of `SyncingEngine`, for diagnostic purposes.
+	///
+	/// **Warning**: The format of the snapshot is not stable.
+	pub async fn dump_state(&self) -> Result<SyncEngineState, oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::DumpState(tx)).await;
+
+		rx.await
+	}
+
+	/// Stop the `SyncingEngine`, dropping in-flight requests and saving the sync progress so the
+	/// next start resumes from it.
+	///
+	/// Resolves once the engine has stopped.
+	pub async fn stop(&self) -> Result<(), oneshot::Canceled> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send_async(ToServiceCommand::Stop(tx)).await;
+
+		rx.await
 	}
 
 	/// Get sync status
@@ -152,10 +469,30 @@ impl<B: BlockT> SyncingService<B> {
 	/// Returns an error if `ChainSync` has terminated.
 	pub async fn status(&self) -> Result<SyncStatus<B>, ()> {
 		let (tx, rx) = oneshot::channel();
-		let _ = self.tx.unbounded_send(ToServiceCommand::Status(tx));
+		self.tx.send_async(ToServiceCommand::Status(tx)).await;
 
 		rx.await.map_err(|_| ())
 	}
+
+	/// Subscribe to the sync status.
+	///
+	/// The stream yields the current status right away, then a new one whenever it changes
+	/// materially: on transitions between sync states, strategies and phases, when peers connect
+	/// or disconnect, and every few blocks of progress. Meant for consumers that would otherwise
+	/// poll [`SyncingService::status`] on a timer.
+	pub fn subscribe_status(&self) -> Pin<Box<dyn Stream<Item = SyncStatus<B>> + Send>> {
+		let (tx, rx) = tracing_unbounded("mpsc_sync_status", 100_000);
+		self.tx.send(ToServiceCommand::SubscribeStatus(tx));
+		Box::pin(rx)
+	}
+
+	/// Subscribe to the peers reported or disconnected by syncing, with the protocol, reason and
+	/// reputation change of each action.
+	pub fn subscribe_peer_audit(&self) -> Pin<Box<dyn Stream<Item = PeerAuditEvent> + Send>> {
+		let (tx, rx) = tracing_unbounded("mpsc_sync_peer_audit_stream", 100_000);
+		self.tx.send(ToServiceCommand::SubscribePeerAudit(tx));
+		Box::pin(rx)
+	}
 }
 
 impl<B: BlockT + 'static> NetworkSyncForkRequest<B::Hash, NumberFor<B>> for SyncingService<B> {
@@ -168,9 +505,7 @@ impl<B: BlockT + 'static> NetworkSyncForkRequest<B::Hash, NumberFor<B>> for Sync
 	///
 	/// Passing empty `peers` set effectively removes the sync request.
 	fn set_sync_fork_request(&self, peers: Vec<PeerId>, hash: B::Hash, number: NumberFor<B>) {
-		let _ = self
-			.tx
-			.unbounded_send(ToServiceCommand::SetSyncForkRequest(peers, hash, number));
+		self.tx.send(ToServiceCommand::SetSyncForkRequest(peers, hash, number));
 	}
 }
 
@@ -180,11 +515,11 @@ impl<B: BlockT> JustificationSyncLink<B> for SyncingService<B> {
 	/// On success, the justification will be passed to the import queue that was part at
 	/// initialization as part of the configuration.
 	fn request_justification(&self, hash: &B::Hash, number: NumberFor<B>) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::RequestJustification(*hash, number));
+		self.tx.send(ToServiceCommand::RequestJustification(*hash, number));
 	}
 
 	fn clear_justification_requests(&self) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::ClearJustificationRequests);
+		self.tx.send(ToServiceCommand::ClearJustificationRequests);
 	}
 }
 
@@ -194,7 +529,7 @@ impl<B: BlockT> SyncStatusProvider<B> for SyncingService<B> {
 	async fn status(&self) -> Result<SyncStatus<B>, ()> {
 		let (rtx, rrx) = oneshot::channel();
 
-		let _ = self.tx.unbounded_send(ToServiceCommand::Status(rtx));
+		self.tx.send_async(ToServiceCommand::Status(rtx)).await;
 		rrx.await.map_err(|_| ())
 	}
 }
@@ -206,9 +541,7 @@ impl<B: BlockT> Link<B> for SyncingService<B> {
 		count: usize,
 		results: Vec<(Result<BlockImportStatus<NumberFor<B>>, BlockImportError>, B::Hash)>,
 	) {
-		let _ = self
-			.tx
-			.unbounded_send(ToServiceCommand::BlocksProcessed(imported, count, results));
+		self.tx.send(ToServiceCommand::BlocksProcessed(imported, count, results));
 	}
 
 	fn justification_imported(
@@ -218,13 +551,12 @@ impl<B: BlockT> Link<B> for SyncingService<B> {
 		number: NumberFor<B>,
 		success: bool,
 	) {
-		let _ = self
-			.tx
-			.unbounded_send(ToServiceCommand::JustificationImported(who, *hash, number, success));
+		self.tx
+			.send(ToServiceCommand::JustificationImported(who, *hash, number, success));
 	}
 
 	fn request_justification(&mut self, hash: &B::Hash, number: NumberFor<B>) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::RequestJustification(*hash, number));
+		self.tx.send(ToServiceCommand::RequestJustification(*hash, number));
 	}
 }
 
@@ -232,18 +564,18 @@ impl<B: BlockT> SyncEventStream for SyncingService<B> {
 	/// Get syncing event stream.
 	fn event_stream(&self, name: &'static str) -> Pin<Box<dyn Stream<Item = SyncEvent> + Send>> {
 		let (tx, rx) = tracing_unbounded(name, 100_000);
-		let _ = self.tx.unbounded_send(ToServiceCommand::EventStream(tx));
+		self.tx.send(ToServiceCommand::EventStream(tx));
 		Box::pin(rx)
 	}
 }
 
 impl<B: BlockT> NetworkBlock<B::Hash, NumberFor<B>> for SyncingService<B> {
 	fn announce_block(&self, hash: B::Hash, data: Option<Vec<u8>>) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::AnnounceBlock(hash, data));
+		self.tx.send(ToServiceCommand::AnnounceBlock(hash, data));
 	}
 
 	fn new_best_block_imported(&self, hash: B::Hash, number: NumberFor<B>) {
-		let _ = self.tx.unbounded_send(ToServiceCommand::NewBestBlockImported(hash, number));
+		self.tx.send(ToServiceCommand::NewBestBlockImported(hash, number));
 	}
 }
 
@@ -0,0 +1,129 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Facade over the [`SyncingService`]s of the sync instances running in one process.
+
+use crate::{engine_state::SyncEngineState, service::chain_sync::SyncingService};
+
+use futures::{channel::oneshot, future::BoxFuture, FutureExt};
+use libp2p::PeerId;
+use sp_consensus::SyncOracle;
+use sp_runtime::traits::Block as BlockT;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Operations of a [`SyncingService`] that don't depend on the block type of the instance.
+trait Instance: SyncOracle + Send + Sync {
+	fn pause_sync(&self);
+	fn resume_sync(&self);
+	fn ban_peer(&self, peer_id: PeerId, duration: Duration);
+	fn dump_state(&self) -> BoxFuture<'static, Result<SyncEngineState, oneshot::Canceled>>;
+}
+
+impl<B: BlockT> Instance for SyncingService<B> {
+	fn pause_sync(&self) {
+		SyncingService::pause_sync(self)
+	}
+
+	fn resume_sync(&self) {
+		SyncingService::resume_sync(self)
+	}
+
+	fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+		SyncingService::ban_peer(self, peer_id, duration)
+	}
+
+	fn dump_state(&self) -> BoxFuture<'static, Result<SyncEngineState, oneshot::Canceled>> {
+		let service = self.clone();
+		async move { service.dump_state().await }.boxed()
+	}
+}
+
+/// Controls the sync instances of a process, e.g. a parachain and its embedded relay chain node,
+/// as one.
+///
+/// The instances may sync chains with different block types. Operations needing the block type
+/// are done on the [`SyncingService`] of the instance.
+#[derive(Clone, Default)]
+pub struct CombinedSyncingService {
+	instances: Vec<(String, Arc<dyn Instance>)>,
+}
+
+impl CombinedSyncingService {
+	/// Create a facade without instances.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add the instance `name`, controlled through `service`.
+	pub fn add<B: BlockT>(&mut self, name: impl Into<String>, service: SyncingService<B>) {
+		self.instances.push((name.into(), Arc::new(service)));
+	}
+
+	/// Names of the instances.
+	pub fn instance_names(&self) -> impl Iterator<Item = &str> {
+		self.instances.iter().map(|(name, _)| name.as_str())
+	}
+
+	/// Pause syncing of all instances, see [`SyncingService::pause_sync`].
+	pub fn pause_sync(&self) {
+		self.instances.iter().for_each(|(_, instance)| instance.pause_sync());
+	}
+
+	/// Resume syncing of all instances, see [`SyncingService::resume_sync`].
+	pub fn resume_sync(&self) {
+		self.instances.iter().for_each(|(_, instance)| instance.resume_sync());
+	}
+
+	/// Ban `peer_id` from all instances, see [`SyncingService::ban_peer`].
+	///
+	/// Instances share the peer identity, so a misbehaving peer is usually connected to several.
+	pub fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+		self.instances
+			.iter()
+			.for_each(|(_, instance)| instance.ban_peer(peer_id, duration));
+	}
+
+	/// Get a snapshot of the internal state of the running instances, by name.
+	///
+	/// **Warning**: The format of the snapshots is not stable.
+	pub async fn dump_state(&self) -> HashMap<String, SyncEngineState> {
+		let states = self
+			.instances
+			.iter()
+			.map(|(name, instance)| instance.dump_state().map(move |state| (name, state)));
+
+		futures::future::join_all(states)
+			.await
+			.into_iter()
+			.filter_map(|(name, state)| Some((name.clone(), state.ok()?)))
+			.collect()
+	}
+}
+
+impl SyncOracle for CombinedSyncingService {
+	/// Whether any instance is major syncing.
+	fn is_major_syncing(&self) -> bool {
+		self.instances.iter().any(|(_, instance)| instance.is_major_syncing())
+	}
+
+	/// Whether all instances are offline.
+	fn is_offline(&self) -> bool {
+		self.instances.iter().all(|(_, instance)| instance.is_offline())
+	}
+}
@@ -0,0 +1,267 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Channel carrying [`ToServiceCommand`]s from
+//! [`SyncingService`](super::chain_sync::SyncingService) to `SyncingEngine`.
+//!
+//! Commands are split into [`CommandPriority`] classes, and the engine always processes pending
+//! commands of a higher class first, so block import results and finality notifications aren't
+//! stuck behind a flood of status queries. Commands that depend on each other, like justification
+//! requests and the results of their import, are in the same class so they keep their order.
+//!
+//! Each class goes through a bounded queue: async callers wait for room in it, while commands
+//! sent without waiting are dropped once it is full. [`CommandSender::is_overloaded`] tells when
+//! the engine falls behind on queries.
+
+use crate::service::chain_sync::ToServiceCommand;
+
+use async_channel::{Receiver, Sender, TrySendError};
+use futures::{Stream, StreamExt};
+use log::{error, warn};
+use sp_runtime::traits::Block as BlockT;
+
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+/// Log target for this file.
+const LOG_TARGET: &str = "sync";
+
+/// Maximum number of pending [`CommandPriority::Import`] commands.
+///
+/// They are sent at most once per imported block or batch of blocks, and are processed before
+/// any other command.
+const MAX_PENDING_IMPORTS: usize = 16 * 1024;
+
+/// Maximum number of pending [`CommandPriority::Control`] commands.
+const MAX_PENDING_CONTROLS: usize = 4096;
+
+/// Maximum number of pending [`CommandPriority::Query`] commands.
+const MAX_PENDING_QUERIES: usize = 1024;
+
+/// Class of a [`ToServiceCommand`], in the order `SyncingEngine` processes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommandPriority {
+	/// Block import results, justification requests and finality notifications.
+	Import,
+	/// Commands changing what the engine does.
+	Control,
+	/// Queries of the engine status, answered over a oneshot channel.
+	Query,
+}
+
+impl<B: BlockT> ToServiceCommand<B> {
+	/// Class the command is processed in.
+	pub fn priority(&self) -> CommandPriority {
+		match self {
+			ToServiceCommand::BlocksProcessed(..) |
+			ToServiceCommand::RequestJustification(..) |
+			ToServiceCommand::ClearJustificationRequests |
+			ToServiceCommand::JustificationImported(..) |
+			ToServiceCommand::NewBestBlockImported(..) |
+			ToServiceCommand::OnBlockFinalized(..) => CommandPriority::Import,
+			ToServiceCommand::Status(_) |
+			ToServiceCommand::NumActivePeers(_) |
+			ToServiceCommand::SyncState(_) |
+			ToServiceCommand::BestSeenBlock(_) |
+			ToServiceCommand::NumSyncPeers(_) |
+			ToServiceCommand::NumQueuedBlocks(_) |
+			ToServiceCommand::NumDownloadedBlocks(_) |
+			ToServiceCommand::NumSyncRequests(_) |
+			ToServiceCommand::BlocksBehind(_) |
+			ToServiceCommand::NetworkBestEstimate(_) |
+			ToServiceCommand::PeersInfo(_) |
+			ToServiceCommand::PeerStatistics(_) |
+			ToServiceCommand::Throughput(_) |
+			ToServiceCommand::DumpState(_) => CommandPriority::Query,
+			_ => CommandPriority::Control,
+		}
+	}
+}
+
+/// Create a command channel.
+pub fn command_channel<B: BlockT>() -> (CommandSender<B>, CommandReceiver<B>) {
+	let (import_tx, import_rx) = async_channel::bounded(MAX_PENDING_IMPORTS);
+	let (control_tx, control_rx) = async_channel::bounded(MAX_PENDING_CONTROLS);
+	let (query_tx, query_rx) = async_channel::bounded(MAX_PENDING_QUERIES);
+
+	(
+		CommandSender { import_tx, control_tx, query_tx },
+		CommandReceiver { import_rx, control_rx, query_rx },
+	)
+}
+
+/// Sending half of the command channel.
+pub struct CommandSender<B: BlockT> {
+	import_tx: Sender<ToServiceCommand<B>>,
+	control_tx: Sender<ToServiceCommand<B>>,
+	query_tx: Sender<ToServiceCommand<B>>,
+}
+
+impl<B: BlockT> Clone for CommandSender<B> {
+	fn clone(&self) -> Self {
+		Self {
+			import_tx: self.import_tx.clone(),
+			control_tx: self.control_tx.clone(),
+			query_tx: self.query_tx.clone(),
+		}
+	}
+}
+
+impl<B: BlockT> CommandSender<B> {
+	/// Queue of the class of `command`.
+	fn queue(&self, command: &ToServiceCommand<B>) -> &Sender<ToServiceCommand<B>> {
+		match command.priority() {
+			CommandPriority::Import => &self.import_tx,
+			CommandPriority::Control => &self.control_tx,
+			CommandPriority::Query => &self.query_tx,
+		}
+	}
+
+	/// Send `command` without waiting. It is dropped if too many commands of its class are
+	/// pending.
+	pub fn send(&self, command: ToServiceCommand<B>) {
+		let priority = command.priority();
+		if let Err(TrySendError::Full(_)) = self.queue(&command).try_send(command) {
+			match priority {
+				CommandPriority::Query =>
+					warn!(target: LOG_TARGET, "Syncing engine is overloaded, dropping a query."),
+				_ => error!(
+					target: LOG_TARGET,
+					"Syncing engine is overloaded, dropping a {priority:?} command.",
+				),
+			}
+		}
+	}
+
+	/// Send `command`, waiting for room in the queue of its class if too many commands of it are
+	/// pending.
+	pub async fn send_async(&self, command: ToServiceCommand<B>) {
+		let _ = self.queue(&command).send(command).await;
+	}
+
+	/// Whether the queue of pending queries is full, so that new queries have to wait.
+	pub fn is_overloaded(&self) -> bool {
+		self.query_tx.is_full()
+	}
+}
+
+/// Receiving half of the command channel, yielding the commands of the highest priority first.
+pub struct CommandReceiver<B: BlockT> {
+	import_rx: Receiver<ToServiceCommand<B>>,
+	control_rx: Receiver<ToServiceCommand<B>>,
+	query_rx: Receiver<ToServiceCommand<B>>,
+}
+impl<B: BlockT> Unpin for CommandReceiver<B> {}
+
+impl<B: BlockT> Stream for CommandReceiver<B> {
+	type Item = ToServiceCommand<B>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let import = self.import_rx.poll_next_unpin(cx);
+		if let Poll::Ready(Some(command)) = import {
+			return Poll::Ready(Some(command))
+		}
+		let control = self.control_rx.poll_next_unpin(cx);
+		if let Poll::Ready(Some(command)) = control {
+			return Poll::Ready(Some(command))
+		}
+		let query = self.query_rx.poll_next_unpin(cx);
+		if let Poll::Ready(Some(command)) = query {
+			return Poll::Ready(Some(command))
+		}
+
+		// All senders are dropped together, so the channels terminate together.
+		if import.is_ready() && control.is_ready() && query.is_ready() {
+			Poll::Ready(None)
+		} else {
+			Poll::Pending
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{channel::oneshot, executor::block_on};
+	use substrate_test_runtime_client::runtime::Block;
+
+	#[test]
+	fn import_results_are_received_before_queries() {
+		let (tx, mut rx) = command_channel::<Block>();
+
+		let (status_tx, _status_rx) = oneshot::channel();
+		tx.send(ToServiceCommand::NumSyncPeers(status_tx));
+		tx.send(ToServiceCommand::PauseSync);
+		tx.send(ToServiceCommand::BlocksProcessed(0, 0, Vec::new()));
+
+		block_on(async {
+			assert_eq!(rx.next().await.unwrap().priority(), CommandPriority::Import);
+			assert_eq!(rx.next().await.unwrap().priority(), CommandPriority::Control);
+			assert_eq!(rx.next().await.unwrap().priority(), CommandPriority::Query);
+
+			drop(tx);
+			assert!(rx.next().await.is_none());
+		});
+	}
+
+	#[test]
+	fn queries_are_bounded() {
+		let (tx, _rx) = command_channel::<Block>();
+
+		for _ in 0..MAX_PENDING_QUERIES {
+			tx.send(ToServiceCommand::NumSyncPeers(oneshot::channel().0));
+		}
+		assert!(tx.is_overloaded());
+
+		// Queries over the limit are dropped, their callers see the request canceled.
+		let (status_tx, status_rx) = oneshot::channel();
+		tx.send(ToServiceCommand::NumSyncPeers(status_tx));
+		assert!(block_on(status_rx).is_err());
+	}
+
+	#[test]
+	fn control_commands_are_bounded() {
+		let (tx, _rx) = command_channel::<Block>();
+
+		for _ in 0..MAX_PENDING_CONTROLS {
+			tx.send(ToServiceCommand::PauseSync);
+		}
+
+		let (stop_tx, stop_rx) = oneshot::channel();
+		tx.send(ToServiceCommand::Stop(stop_tx));
+		assert!(block_on(stop_rx).is_err());
+	}
+
+	#[test]
+	fn justification_requests_are_received_in_order_with_their_imports() {
+		let (tx, mut rx) = command_channel::<Block>();
+		let hash = Default::default();
+
+		tx.send(ToServiceCommand::RequestJustification(hash, 1));
+		tx.send(ToServiceCommand::JustificationImported(libp2p::PeerId::random(), hash, 1, true));
+		tx.send(ToServiceCommand::ClearJustificationRequests);
+
+		block_on(async {
+			assert!(matches!(rx.next().await, Some(ToServiceCommand::RequestJustification(..))));
+			assert!(matches!(rx.next().await, Some(ToServiceCommand::JustificationImported(..))));
+			assert!(matches!(rx.next().await, Some(ToServiceCommand::ClearJustificationRequests)));
+		});
+	}
+}
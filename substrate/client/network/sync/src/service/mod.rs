@@ -19,5 +19,7 @@
 //! `ChainSync`-related service code
 
 pub mod chain_sync;
+pub mod combined;
+pub mod command_channel;
 pub mod mock;
 pub mod network;
@@ -17,7 +17,7 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use futures::{channel::oneshot, StreamExt};
-use libp2p::PeerId;
+use libp2p::{Multiaddr, PeerId};
 
 use sc_network::{
 	request_responses::{IfDisconnected, RequestFailure},
@@ -26,7 +26,9 @@ use sc_network::{
 };
 use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedReceiver, TracingUnboundedSender};
 
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
+
+const LOG_TARGET: &str = "sync";
 
 /// Network-related services required by `sc-network-sync`
 pub trait Network: NetworkPeers + NetworkRequest + NetworkNotification {}
@@ -63,6 +65,33 @@ pub enum ToServiceCommand {
 
 	/// Call `NetworkNotification::set_notification_handshake()`
 	SetNotificationHandshake(ProtocolName, Vec<u8>),
+
+	/// Call `NetworkPeers::add_known_address()`
+	AddKnownAddress(PeerId, Multiaddr),
+
+	/// Call `NetworkPeers::add_peers_to_reserved_set()`
+	AddPeersToReservedSet(ProtocolName, HashSet<Multiaddr>),
+
+	/// Call `NetworkPeers::remove_peers_from_reserved_set()`
+	RemovePeersFromReservedSet(ProtocolName, Vec<PeerId>),
+}
+
+/// Peer reported or disconnected through a [`NetworkServiceHandle`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAuditEvent {
+	/// Peer the action was taken against.
+	pub peer_id: PeerId,
+	/// What was done to the peer.
+	pub action: PeerAuditAction,
+}
+
+/// Action taken against a peer, see [`PeerAuditEvent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerAuditAction {
+	/// The reputation of the peer was changed, with the reason of the change.
+	Reported(ReputationChange),
+	/// The peer was disconnected from the protocol.
+	Disconnected(ProtocolName),
 }
 
 /// Handle that is (temporarily) passed to `ChainSync` so it can
@@ -70,24 +99,41 @@ pub enum ToServiceCommand {
 #[derive(Clone)]
 pub struct NetworkServiceHandle {
 	tx: TracingUnboundedSender<ToServiceCommand>,
+	/// Where the peers reported or disconnected are published, if anywhere.
+	peer_audit: Option<TracingUnboundedSender<PeerAuditEvent>>,
 }
 
 impl NetworkServiceHandle {
 	/// Create new service handle
 	pub fn new(tx: TracingUnboundedSender<ToServiceCommand>) -> NetworkServiceHandle {
-		Self { tx }
+		Self { tx, peer_audit: None }
+	}
+
+	/// Publish the peers reported or disconnected through the handle and its clones to
+	/// `peer_audit`.
+	pub fn with_peer_audit(mut self, peer_audit: TracingUnboundedSender<PeerAuditEvent>) -> Self {
+		self.peer_audit = Some(peer_audit);
+		self
 	}
 
 	/// Report peer
 	pub fn report_peer(&self, who: PeerId, cost_benefit: ReputationChange) {
+		self.audit(who, PeerAuditAction::Reported(cost_benefit));
 		let _ = self.tx.unbounded_send(ToServiceCommand::ReportPeer(who, cost_benefit));
 	}
 
 	/// Disconnect peer
 	pub fn disconnect_peer(&self, who: PeerId, protocol: ProtocolName) {
+		self.audit(who, PeerAuditAction::Disconnected(protocol.clone()));
 		let _ = self.tx.unbounded_send(ToServiceCommand::DisconnectPeer(who, protocol));
 	}
 
+	fn audit(&self, peer_id: PeerId, action: PeerAuditAction) {
+		if let Some(peer_audit) = &self.peer_audit {
+			let _ = peer_audit.unbounded_send(PeerAuditEvent { peer_id, action });
+		}
+	}
+
 	/// Send request to peer
 	pub fn start_request(
 		&self,
@@ -115,6 +161,23 @@ impl NetworkServiceHandle {
 			.tx
 			.unbounded_send(ToServiceCommand::SetNotificationHandshake(protocol, handshake));
 	}
+
+	/// Add an address of a peer to the network.
+	pub fn add_known_address(&self, who: PeerId, address: Multiaddr) {
+		let _ = self.tx.unbounded_send(ToServiceCommand::AddKnownAddress(who, address));
+	}
+
+	/// Add peers to the reserved set of the protocol, connecting to them.
+	pub fn add_peers_to_reserved_set(&self, protocol: ProtocolName, peers: HashSet<Multiaddr>) {
+		let _ = self.tx.unbounded_send(ToServiceCommand::AddPeersToReservedSet(protocol, peers));
+	}
+
+	/// Remove peers from the reserved set of the protocol.
+	pub fn remove_peers_from_reserved_set(&self, protocol: ProtocolName, peers: Vec<PeerId>) {
+		let _ = self
+			.tx
+			.unbounded_send(ToServiceCommand::RemovePeersFromReservedSet(protocol, peers));
+	}
 }
 
 impl NetworkServiceProvider {
@@ -139,6 +202,16 @@ impl NetworkServiceProvider {
 					service.write_notification(peer, protocol, message),
 				ToServiceCommand::SetNotificationHandshake(protocol, handshake) =>
 					service.set_notification_handshake(protocol, handshake),
+				ToServiceCommand::AddKnownAddress(peer, address) =>
+					service.add_known_address(peer, address),
+				ToServiceCommand::AddPeersToReservedSet(protocol, peers) =>
+					if let Err(error) = service.add_peers_to_reserved_set(protocol, peers) {
+						log::debug!(target: LOG_TARGET, "Failed to reserve peers: {error}");
+					},
+				ToServiceCommand::RemovePeersFromReservedSet(protocol, peers) =>
+					if let Err(error) = service.remove_peers_from_reserved_set(protocol, peers) {
+						log::debug!(target: LOG_TARGET, "Failed to unreserve peers: {error}");
+					},
 			}
 		}
 	}
@@ -179,4 +252,28 @@ mod tests {
 		handle.disconnect_peer(peer, proto_clone);
 		handle.report_peer(peer, change);
 	}
+
+	#[test]
+	fn reported_and_disconnected_peers_are_audited() {
+		let (_provider, handle) = NetworkServiceProvider::new();
+		let (tx, mut rx) = tracing_unbounded("mpsc_test_peer_audit", 100);
+		let handle = handle.with_peer_audit(tx);
+
+		let peer = PeerId::random();
+		let proto = ProtocolName::from("test-protocol");
+		let change = sc_network::ReputationChange::new(-10, "test-change");
+
+		handle.clone().report_peer(peer, change);
+		handle.disconnect_peer(peer, proto.clone());
+
+		assert_eq!(
+			rx.try_recv().unwrap(),
+			PeerAuditEvent { peer_id: peer, action: PeerAuditAction::Reported(change) },
+		);
+		assert_eq!(
+			rx.try_recv().unwrap(),
+			PeerAuditEvent { peer_id: peer, action: PeerAuditAction::Disconnected(proto) },
+		);
+		assert!(rx.try_recv().is_err());
+	}
 }
@@ -21,8 +21,8 @@
 use crate::schema::v1::{StateEntry, StateRequest, StateResponse};
 use codec::{Decode, Encode};
 use log::debug;
-use sc_client_api::{CompactProof, ProofProvider};
-use sc_consensus::ImportedState;
+use sc_client_api::{CompactProof, KeyValueStates, ProofProvider};
+use sc_consensus::{ImportedState, StateDelta};
 use sc_network_common::sync::StateDownloadProgress;
 use smallvec::SmallVec;
 use sp_core::storage::well_known_keys;
@@ -32,8 +32,15 @@ use sp_runtime::{
 };
 use std::{collections::HashMap, sync::Arc};
 
+/// Storage changes of a trie level, `None` for removed keys.
+type LevelDelta = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
 /// State sync state machine. Accumulates partial state data until it
 /// is ready to be imported.
+///
+/// When created with [`StateSync::new_delta`], only the storage delta between an already
+/// imported base block and the target is downloaded, and applied to the local base state on
+/// import.
 pub struct StateSync<B: BlockT, Client> {
 	target_block: B::Hash,
 	target_header: B::Header,
@@ -42,10 +49,60 @@ pub struct StateSync<B: BlockT, Client> {
 	target_justifications: Option<Justifications>,
 	last_key: SmallVec<[Vec<u8>; 2]>,
 	state: HashMap<Vec<u8>, (Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>)>,
+	/// Base block of a delta download.
+	base: Option<B::Hash>,
+	/// Downloaded storage delta, by prefixed child storage key, empty for the top trie.
+	delta: HashMap<Vec<u8>, LevelDelta>,
 	complete: bool,
 	client: Arc<Client>,
 	imported_bytes: u64,
 	skip_proof: bool,
+	/// Whether the proof of a response is being verified, see [`StateSync::prepare_import`].
+	verifying: bool,
+}
+
+/// Verification of the proof of a state response, which can be run on another thread.
+pub struct StateVerification<B: BlockT, Client> {
+	client: Arc<Client>,
+	root: B::Hash,
+	proof: CompactProof,
+	proof_size: u64,
+	start_keys: SmallVec<[Vec<u8>; 2]>,
+}
+
+impl<B: BlockT, Client: ProofProvider<B>> StateVerification<B, Client> {
+	/// Verify the proof, returning the proven state chunk, or `None` if the proof is invalid.
+	pub fn run(self) -> Option<VerifiedState> {
+		match self
+			.client
+			.verify_range_proof(self.root, self.proof, self.start_keys.as_slice())
+		{
+			Err(e) => {
+				debug!(target: "sync", "StateResponse failed proof verification: {}", e);
+				None
+			},
+			Ok((values, completed)) => {
+				debug!(target: "sync", "Imported with {} keys", values.len());
+				Some(VerifiedState { values, completed, proof_size: self.proof_size })
+			},
+		}
+	}
+}
+
+/// State chunk proven by a [`StateVerification`].
+pub struct VerifiedState {
+	values: KeyValueStates,
+	completed: usize,
+	proof_size: u64,
+}
+
+/// State response prepared for import by [`StateSync::prepare_import`].
+pub enum PreparedImport<B: BlockT, Client> {
+	/// The response is imported.
+	Imported(ImportResult<B>),
+	/// The proof of the response must be verified, the proven chunk is imported by
+	/// [`StateSync::import_verified`].
+	Verify(StateVerification<B, Client>),
 }
 
 /// Import state chunk result.
@@ -80,143 +137,221 @@ where
 			target_justifications,
 			last_key: SmallVec::default(),
 			state: HashMap::default(),
+			base: None,
+			delta: HashMap::default(),
 			complete: false,
 			imported_bytes: 0,
 			skip_proof,
+			verifying: false,
+		}
+	}
+
+	/// Create a new instance downloading the storage delta between `base`, whose state must be
+	/// available locally, and the target.
+	///
+	/// The delta isn't proven, the resulting state is checked against the target state root on
+	/// import.
+	pub fn new_delta(
+		client: Arc<Client>,
+		base: B::Hash,
+		target_header: B::Header,
+		target_body: Option<Vec<B::Extrinsic>>,
+		target_justifications: Option<Justifications>,
+	) -> Self {
+		Self {
+			base: Some(base),
+			..Self::new(client, target_header, target_body, target_justifications, true)
 		}
 	}
 
 	///  Validate and import a state response.
 	pub fn import(&mut self, response: StateResponse) -> ImportResult<B> {
+		match self.prepare_import(response) {
+			PreparedImport::Imported(result) => result,
+			PreparedImport::Verify(verification) => self.import_verified(verification.run()),
+		}
+	}
+
+	/// Validate a state response, importing it right away unless its proof must be verified.
+	///
+	/// No state request is produced until the proof is verified and imported.
+	pub fn prepare_import(&mut self, response: StateResponse) -> PreparedImport<B, Client> {
 		if response.entries.is_empty() && response.proof.is_empty() {
 			debug!(target: "sync", "Bad state response");
-			return ImportResult::BadResponse
+			return PreparedImport::Imported(ImportResult::BadResponse)
 		}
 		if !self.skip_proof && response.proof.is_empty() {
 			debug!(target: "sync", "Missing proof");
-			return ImportResult::BadResponse
+			return PreparedImport::Imported(ImportResult::BadResponse)
+		}
+		if self.base.is_some() {
+			return PreparedImport::Imported(self.import_delta(response))
+		}
+		// Proofs are still requested from the peers not serving raw entries.
+		if self.skip_proof && response.proof.is_empty() {
+			return PreparedImport::Imported(self.import_unproven(response))
 		}
-		let complete = if !self.skip_proof {
-			debug!(target: "sync", "Importing state from {} trie nodes", response.proof.len());
-			let proof_size = response.proof.len() as u64;
-			let proof = match CompactProof::decode(&mut response.proof.as_ref()) {
-				Ok(proof) => proof,
-				Err(e) => {
-					debug!(target: "sync", "Error decoding proof: {:?}", e);
-					return ImportResult::BadResponse
-				},
-			};
-			let (values, completed) = match self.client.verify_range_proof(
-				self.target_root,
-				proof,
-				self.last_key.as_slice(),
-			) {
-				Err(e) => {
-					debug!(
-						target: "sync",
-						"StateResponse failed proof verification: {}",
-						e,
-					);
-					return ImportResult::BadResponse
-				},
-				Ok(values) => values,
-			};
-			debug!(target: "sync", "Imported with {} keys", values.len());
 
-			let complete = completed == 0;
-			if !complete && !values.update_last_key(completed, &mut self.last_key) {
-				debug!(target: "sync", "Error updating key cursor, depth: {}", completed);
+		debug!(target: "sync", "Importing state from {} trie nodes", response.proof.len());
+		let proof_size = response.proof.len() as u64;
+		let proof = match CompactProof::decode(&mut response.proof.as_ref()) {
+			Ok(proof) => proof,
+			Err(e) => {
+				debug!(target: "sync", "Error decoding proof: {:?}", e);
+				return PreparedImport::Imported(ImportResult::BadResponse)
+			},
+		};
+		self.verifying = true;
+		PreparedImport::Verify(StateVerification {
+			client: self.client.clone(),
+			root: self.target_root,
+			proof,
+			proof_size,
+			start_keys: self.last_key.clone(),
+		})
+	}
+
+	/// Import the state chunk proven by the verification prepared by [`Self::prepare_import`],
+	/// `None` if the proof is invalid.
+	pub fn import_verified(&mut self, verified: Option<VerifiedState>) -> ImportResult<B> {
+		self.verifying = false;
+		let Some(VerifiedState { values, completed, proof_size }) = verified else {
+			return ImportResult::BadResponse
+		};
+
+		let complete = completed == 0;
+		if !complete && !values.update_last_key(completed, &mut self.last_key) {
+			debug!(target: "sync", "Error updating key cursor, depth: {}", completed);
+		};
+
+		for values in values.0 {
+			let key_values = if values.state_root.is_empty() {
+				// Read child trie roots.
+				values
+					.key_values
+					.into_iter()
+					.filter(|key_value| {
+						if well_known_keys::is_child_storage_key(key_value.0.as_slice()) {
+							self.state
+								.entry(key_value.1.clone())
+								.or_default()
+								.1
+								.push(key_value.0.clone());
+							false
+						} else {
+							true
+						}
+					})
+					.collect()
+			} else {
+				values.key_values
 			};
+			let entry = self.state.entry(values.state_root).or_default();
+			if entry.0.len() > 0 && entry.1.len() > 1 {
+				// Already imported child_trie with same root.
+				// Warning this will not work with parallel download.
+			} else if entry.0.is_empty() {
+				for (key, _value) in key_values.iter() {
+					self.imported_bytes += key.len() as u64;
+				}
 
-			for values in values.0 {
-				let key_values = if values.state_root.is_empty() {
-					// Read child trie roots.
-					values
-						.key_values
-						.into_iter()
-						.filter(|key_value| {
-							if well_known_keys::is_child_storage_key(key_value.0.as_slice()) {
-								self.state
-									.entry(key_value.1.clone())
-									.or_default()
-									.1
-									.push(key_value.0.clone());
-								false
-							} else {
-								true
-							}
-						})
-						.collect()
-				} else {
-					values.key_values
-				};
-				let entry = self.state.entry(values.state_root).or_default();
-				if entry.0.len() > 0 && entry.1.len() > 1 {
-					// Already imported child_trie with same root.
-					// Warning this will not work with parallel download.
-				} else if entry.0.is_empty() {
-					for (key, _value) in key_values.iter() {
-						self.imported_bytes += key.len() as u64;
-					}
+				entry.0 = key_values;
+			} else {
+				for (key, value) in key_values {
+					self.imported_bytes += key.len() as u64;
+					entry.0.push((key, value))
+				}
+			}
+		}
+		self.imported_bytes += proof_size;
+		self.complete_if(complete)
+	}
 
-					entry.0 = key_values;
-				} else {
-					for (key, value) in key_values {
+	/// Import a state response without proof.
+	fn import_unproven(&mut self, response: StateResponse) -> ImportResult<B> {
+		let mut complete = true;
+		// if the trie is a child trie and one of its parent trie is empty,
+		// the parent cursor stays valid.
+		// Empty parent trie content only happens when all the response content
+		// is part of a single child trie.
+		if self.last_key.len() == 2 && response.entries[0].entries.is_empty() {
+			// Do not remove the parent trie position.
+			self.last_key.pop();
+		} else {
+			self.last_key.clear();
+		}
+		for state in response.entries {
+			debug!(
+				target: "sync",
+				"Importing state from {:?} to {:?}",
+				state.entries.last().map(|e| sp_core::hexdisplay::HexDisplay::from(&e.key)),
+				state.entries.first().map(|e| sp_core::hexdisplay::HexDisplay::from(&e.key)),
+			);
+
+			if !state.complete {
+				if let Some(e) = state.entries.last() {
+					self.last_key.push(e.key.clone());
+				}
+				complete = false;
+			}
+			let is_top = state.state_root.is_empty();
+			let entry = self.state.entry(state.state_root).or_default();
+			if entry.0.len() > 0 && entry.1.len() > 1 {
+				// Already imported child trie with same root.
+			} else {
+				let mut child_roots = Vec::new();
+				for StateEntry { key, value } in state.entries {
+					// Skip all child key root (will be recalculated on import).
+					if is_top && well_known_keys::is_child_storage_key(key.as_slice()) {
+						child_roots.push((value, key));
+					} else {
 						self.imported_bytes += key.len() as u64;
 						entry.0.push((key, value))
 					}
 				}
+				for (root, storage_key) in child_roots {
+					self.state.entry(root).or_default().1.push(storage_key);
+				}
 			}
-			self.imported_bytes += proof_size;
-			complete
+		}
+		self.complete_if(complete)
+	}
+
+	/// Import a state delta response.
+	fn import_delta(&mut self, response: StateResponse) -> ImportResult<B> {
+		let mut complete = true;
+		// Same cursor handling as `import_unproven`, the cursor being the last changed key.
+		let top = &response.entries[0];
+		if self.last_key.len() == 2 && top.entries.is_empty() && top.removed.is_empty() {
+			self.last_key.pop();
 		} else {
-			let mut complete = true;
-			// if the trie is a child trie and one of its parent trie is empty,
-			// the parent cursor stays valid.
-			// Empty parent trie content only happens when all the response content
-			// is part of a single child trie.
-			if self.last_key.len() == 2 && response.entries[0].entries.is_empty() {
-				// Do not remove the parent trie position.
-				self.last_key.pop();
-			} else {
-				self.last_key.clear();
-			}
-			for state in response.entries {
-				debug!(
-					target: "sync",
-					"Importing state from {:?} to {:?}",
-					state.entries.last().map(|e| sp_core::hexdisplay::HexDisplay::from(&e.key)),
-					state.entries.first().map(|e| sp_core::hexdisplay::HexDisplay::from(&e.key)),
-				);
-
-				if !state.complete {
-					if let Some(e) = state.entries.last() {
-						self.last_key.push(e.key.clone());
-					}
-					complete = false;
-				}
-				let is_top = state.state_root.is_empty();
-				let entry = self.state.entry(state.state_root).or_default();
-				if entry.0.len() > 0 && entry.1.len() > 1 {
-					// Already imported child trie with same root.
-				} else {
-					let mut child_roots = Vec::new();
-					for StateEntry { key, value } in state.entries {
-						// Skip all child key root (will be recalculated on import).
-						if is_top && well_known_keys::is_child_storage_key(key.as_slice()) {
-							child_roots.push((value, key));
-						} else {
-							self.imported_bytes += key.len() as u64;
-							entry.0.push((key, value))
-						}
-					}
-					for (root, storage_key) in child_roots {
-						self.state.entry(root).or_default().1.push(storage_key);
-					}
+			self.last_key.clear();
+		}
+		for state in response.entries {
+			let mut changes: LevelDelta = state
+				.entries
+				.into_iter()
+				.map(|StateEntry { key, value }| (key, Some(value)))
+				.chain(state.removed.into_iter().map(|key| (key, None)))
+				.collect();
+			changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+			if !state.complete {
+				if let Some((key, _)) = changes.last() {
+					self.last_key.push(key.clone());
 				}
+				complete = false;
 			}
-			complete
-		};
+			for (key, _) in changes.iter() {
+				self.imported_bytes += key.len() as u64;
+			}
+			self.delta.entry(state.child_storage_key).or_default().extend(changes);
+		}
+		self.complete_if(complete)
+	}
+
+	/// Result of an import, complete if `complete`.
+	fn complete_if(&mut self, complete: bool) -> ImportResult<B> {
 		if complete {
 			self.complete = true;
 			ImportResult::Import(
@@ -225,6 +360,10 @@ where
 				ImportedState {
 					block: self.target_block,
 					state: std::mem::take(&mut self.state).into(),
+					delta: self.base.map(|base| StateDelta {
+						base,
+						changes: std::mem::take(&mut self.delta).into_iter().collect(),
+					}),
 				},
 				self.target_body.clone(),
 				self.target_justifications.clone(),
@@ -240,9 +379,33 @@ where
 			block: self.target_block.encode(),
 			start: self.last_key.clone().into_vec(),
 			no_proof: self.skip_proof,
+			base_block: self.base.map(|base| base.encode()).unwrap_or_default(),
+			// Picked by `ChainSync` for the peer the request is sent to.
+			max_response_size: 0,
 		}
 	}
 
+	/// Download the whole target state instead of the state delta since the base block.
+	pub fn without_base(self, skip_proof: bool) -> Self {
+		Self::new(
+			self.client,
+			self.target_header,
+			self.target_body,
+			self.target_justifications,
+			skip_proof,
+		)
+	}
+
+	/// Returns the base block if only the state delta is downloaded.
+	pub fn base(&self) -> Option<B::Hash> {
+		self.base
+	}
+
+	/// Check if the proof of a response is being verified.
+	pub fn is_verifying(&self) -> bool {
+		self.verifying
+	}
+
 	/// Check if the state is complete.
 	pub fn is_complete(&self) -> bool {
 		self.complete
@@ -17,7 +17,10 @@
 //! Helper for handling (i.e. answering) state requests from a remote peer via the
 //! `crate::request_responses::RequestResponsesBehaviour`.
 
-use crate::schema::v1::{KeyValueStateEntry, StateEntry, StateRequest, StateResponse};
+use crate::schema::{
+	v1::{KeyValueStateEntry, StateEntry, StateRequest, StateResponse},
+	v2,
+};
 
 use codec::{Decode, Encode};
 use futures::{channel::oneshot, stream::StreamExt};
@@ -28,21 +31,38 @@ use schnellru::{ByLength, LruMap};
 
 use sc_client_api::{BlockBackend, ProofProvider};
 use sc_network::{
-	config::ProtocolId,
+	config::{ProtocolId, DEFAULT_MAX_STATE_RESPONSE_SIZE, DEFAULT_STATE_REQUEST_TIMEOUT},
 	request_responses::{IncomingRequest, OutgoingResponse, ProtocolConfig},
+	ReputationChange,
 };
 use sp_runtime::traits::Block as BlockT;
 
 use std::{
 	hash::{Hash, Hasher},
 	sync::Arc,
-	time::Duration,
 };
 
 const LOG_TARGET: &str = "sync";
-const MAX_RESPONSE_BYTES: usize = 2 * 1024 * 1024; // Actual reponse may be bigger.
 const MAX_NUMBER_OF_SAME_REQUESTS_PER_PEER: usize = 2;
 
+/// Maximum size of the proof frames served over the v2 state request protocol.
+pub const MAX_STATE_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Flag of the request modes advertised in state responses, set by responders serving the state
+/// delta between two blocks.
+pub const STATE_MODE_DELTA: u32 = 1;
+
+/// Flag of the request modes advertised in state responses, set by responders serving state
+/// proofs.
+pub const STATE_MODE_PROOF: u32 = 2;
+
+/// Flag of the request modes advertised in state responses, set by responders serving the raw
+/// state entries without proof.
+pub const STATE_MODE_ENTRIES: u32 = 4;
+
+/// Request modes served by the responders that don't advertise any.
+pub const STATE_MODES_LEGACY: u32 = STATE_MODE_PROOF | STATE_MODE_ENTRIES;
+
 mod rep {
 	use sc_network::ReputationChange as Rep;
 
@@ -50,36 +70,80 @@ mod rep {
 	pub const SAME_REQUEST: Rep = Rep::new(i32::MIN, "Same state request multiple times");
 }
 
+/// Version of the state request protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProtocolVersion {
+	/// Responses of up to the requested size, carrying either a proof or the raw entries.
+	V1,
+	/// Proofs served in bounded frames, one per request, each verifiable on its own.
+	V2,
+}
+
+impl ProtocolVersion {
+	/// Last segment of the protocol names, the v1 protocol predating the versioning.
+	fn name(self) -> &'static str {
+		match self {
+			Self::V1 => "state/2",
+			Self::V2 => "state/3",
+		}
+	}
+}
+
 /// Generates a [`ProtocolConfig`] for the state request protocol, refusing incoming requests.
 pub fn generate_protocol_config<Hash: AsRef<[u8]>>(
 	protocol_id: &ProtocolId,
 	genesis_hash: Hash,
 	fork_id: Option<&str>,
+) -> ProtocolConfig {
+	generate_versioned_protocol_config(ProtocolVersion::V1, protocol_id, genesis_hash, fork_id)
+}
+
+/// Generates a [`ProtocolConfig`] for the v2 state request protocol.
+///
+/// The config has no inbound queue, so incoming requests are refused unless a handler is attached
+/// to it, as done by [`StateRequestHandler::new_v2`].
+pub fn generate_v2_protocol_config<Hash: AsRef<[u8]>>(
+	protocol_id: &ProtocolId,
+	genesis_hash: Hash,
+	fork_id: Option<&str>,
+) -> ProtocolConfig {
+	generate_versioned_protocol_config(ProtocolVersion::V2, protocol_id, genesis_hash, fork_id)
+}
+
+fn generate_versioned_protocol_config<Hash: AsRef<[u8]>>(
+	version: ProtocolVersion,
+	protocol_id: &ProtocolId,
+	genesis_hash: Hash,
+	fork_id: Option<&str>,
 ) -> ProtocolConfig {
 	ProtocolConfig {
-		name: generate_protocol_name(genesis_hash, fork_id).into(),
-		fallback_names: std::iter::once(generate_legacy_protocol_name(protocol_id).into())
+		name: generate_protocol_name(version, genesis_hash, fork_id).into(),
+		fallback_names: std::iter::once(generate_legacy_protocol_name(version, protocol_id).into())
 			.collect(),
 		max_request_size: 1024 * 1024,
 		max_response_size: 16 * 1024 * 1024,
-		request_timeout: Duration::from_secs(40),
+		request_timeout: DEFAULT_STATE_REQUEST_TIMEOUT,
 		inbound_queue: None,
 	}
 }
 
 /// Generate the state protocol name from the genesis hash and fork id.
-fn generate_protocol_name<Hash: AsRef<[u8]>>(genesis_hash: Hash, fork_id: Option<&str>) -> String {
+fn generate_protocol_name<Hash: AsRef<[u8]>>(
+	version: ProtocolVersion,
+	genesis_hash: Hash,
+	fork_id: Option<&str>,
+) -> String {
 	let genesis_hash = genesis_hash.as_ref();
 	if let Some(fork_id) = fork_id {
-		format!("/{}/{}/state/2", array_bytes::bytes2hex("", genesis_hash), fork_id)
+		format!("/{}/{}/{}", array_bytes::bytes2hex("", genesis_hash), fork_id, version.name())
 	} else {
-		format!("/{}/state/2", array_bytes::bytes2hex("", genesis_hash))
+		format!("/{}/{}", array_bytes::bytes2hex("", genesis_hash), version.name())
 	}
 }
 
 /// Generate the legacy state protocol name from chain specific protocol identifier.
-fn generate_legacy_protocol_name(protocol_id: &ProtocolId) -> String {
-	format!("/{}/state/2", protocol_id.as_ref())
+fn generate_legacy_protocol_name(version: ProtocolVersion, protocol_id: &ProtocolId) -> String {
+	format!("/{}/{}", protocol_id.as_ref(), version.name())
 }
 
 /// The key of [`BlockRequestHandler::seen_requests`].
@@ -87,6 +151,7 @@ fn generate_legacy_protocol_name(protocol_id: &ProtocolId) -> String {
 struct SeenRequestsKey<B: BlockT> {
 	peer: PeerId,
 	block: B::Hash,
+	base_block: Option<B::Hash>,
 	start: Vec<Vec<u8>>,
 }
 
@@ -95,6 +160,7 @@ impl<B: BlockT> Hash for SeenRequestsKey<B> {
 	fn hash<H: Hasher>(&self, state: &mut H) {
 		self.peer.hash(state);
 		self.block.hash(state);
+		self.base_block.hash(state);
 		self.start.hash(state);
 	}
 }
@@ -115,6 +181,12 @@ pub struct StateRequestHandler<B: BlockT, Client> {
 	///
 	/// This is used to check if a peer is spamming us with the same request.
 	seen_requests: LruMap<SeenRequestsKey<B>, SeenRequestsValue>,
+	/// Maximum size of the served responses, peers may request smaller ones.
+	max_response_size: usize,
+	/// Served request modes, advertised in every response.
+	modes: u32,
+	/// Version of the served protocol.
+	version: ProtocolVersion,
 }
 
 impl<B, Client> StateRequestHandler<B, Client>
@@ -128,13 +200,35 @@ where
 		fork_id: Option<&str>,
 		client: Arc<Client>,
 		num_peer_hint: usize,
+	) -> (Self, ProtocolConfig) {
+		Self::with_version(ProtocolVersion::V1, protocol_id, fork_id, client, num_peer_hint)
+	}
+
+	/// Create a new [`StateRequestHandler`] serving the v2 state request protocol, which serves
+	/// state proofs in frames of up to [`MAX_STATE_FRAME_SIZE`] bytes.
+	pub fn new_v2(
+		protocol_id: &ProtocolId,
+		fork_id: Option<&str>,
+		client: Arc<Client>,
+		num_peer_hint: usize,
+	) -> (Self, ProtocolConfig) {
+		Self::with_version(ProtocolVersion::V2, protocol_id, fork_id, client, num_peer_hint)
+	}
+
+	fn with_version(
+		version: ProtocolVersion,
+		protocol_id: &ProtocolId,
+		fork_id: Option<&str>,
+		client: Arc<Client>,
+		num_peer_hint: usize,
 	) -> (Self, ProtocolConfig) {
 		// Reserve enough request slots for one request per peer when we are at the maximum
 		// number of peers.
 		let capacity = std::cmp::max(num_peer_hint, 1);
 		let (tx, request_receiver) = async_channel::bounded(capacity);
 
-		let mut protocol_config = generate_protocol_config(
+		let mut protocol_config = generate_versioned_protocol_config(
+			version,
 			protocol_id,
 			client
 				.block_hash(0u32.into())
@@ -148,7 +242,40 @@ where
 		let capacity = ByLength::new(num_peer_hint.max(1) as u32 * 2);
 		let seen_requests = LruMap::new(capacity);
 
-		(Self { client, request_receiver, seen_requests }, protocol_config)
+		(
+			Self {
+				client,
+				request_receiver,
+				seen_requests,
+				max_response_size: DEFAULT_MAX_STATE_RESPONSE_SIZE,
+				modes: STATE_MODE_DELTA | STATE_MODE_PROOF | STATE_MODE_ENTRIES,
+				version,
+			},
+			protocol_config,
+		)
+	}
+
+	/// Serve responses of up to `max_response_size` bytes, [`DEFAULT_MAX_STATE_RESPONSE_SIZE`] by
+	/// default. Actual responses may be bigger.
+	///
+	/// Peers may request smaller responses. Lower to serve state to peers on a node with little
+	/// memory, raise to let peers download the state faster. The frames served over the v2
+	/// protocol are further bounded by [`MAX_STATE_FRAME_SIZE`].
+	pub fn set_max_response_size(&mut self, max_response_size: usize) {
+		self.max_response_size = max_response_size;
+	}
+
+	/// Serve state proofs if `serve_proofs`, which is the default.
+	///
+	/// Proofs take more memory and time to build than the raw entries. Requests for proofs are
+	/// answered with an empty response when they aren't served, peers requiring them download
+	/// the state from other peers.
+	pub fn set_serve_proofs(&mut self, serve_proofs: bool) {
+		if serve_proofs {
+			self.modes |= STATE_MODE_PROOF;
+		} else {
+			self.modes &= !STATE_MODE_PROOF;
+		}
 	}
 
 	/// Run [`StateRequestHandler`].
@@ -156,7 +283,11 @@ where
 		while let Some(request) = self.request_receiver.next().await {
 			let IncomingRequest { peer, payload, pending_response } = request;
 
-			match self.handle_request(payload, pending_response, &peer) {
+			let result = match self.version {
+				ProtocolVersion::V1 => self.handle_request(payload, pending_response, &peer),
+				ProtocolVersion::V2 => self.handle_frame_request(payload, pending_response, &peer),
+			};
+			match result {
 				Ok(()) => debug!(target: LOG_TARGET, "Handled block request from {}.", peer),
 				Err(e) => debug!(
 					target: LOG_TARGET,
@@ -174,49 +305,73 @@ where
 	) -> Result<(), HandleRequestError> {
 		let request = StateRequest::decode(&payload[..])?;
 		let block: B::Hash = Decode::decode(&mut request.block.as_ref())?;
+		let base_block: Option<B::Hash> = if request.base_block.is_empty() {
+			None
+		} else {
+			Some(Decode::decode(&mut request.base_block.as_ref())?)
+		};
 
-		let key = SeenRequestsKey { peer: *peer, block, start: request.start.clone() };
-
-		let mut reputation_changes = Vec::new();
-
-		match self.seen_requests.get(&key) {
-			Some(SeenRequestsValue::First) => {},
-			Some(SeenRequestsValue::Fulfilled(ref mut requests)) => {
-				*requests = requests.saturating_add(1);
-
-				if *requests > MAX_NUMBER_OF_SAME_REQUESTS_PER_PEER {
-					reputation_changes.push(rep::SAME_REQUEST);
-				}
-			},
-			None => {
-				self.seen_requests.insert(key.clone(), SeenRequestsValue::First);
-			},
-		}
+		let key =
+			SeenRequestsKey { peer: *peer, block, base_block, start: request.start.clone() };
+		let reputation_changes = self.on_request_seen(&key);
 
 		trace!(
 			target: LOG_TARGET,
-			"Handling state request from {}: Block {:?}, Starting at {:x?}, no_proof={}",
+			"Handling state request from {}: Block {:?}, Base {:?}, Starting at {:x?}, no_proof={}",
 			peer,
 			request.block,
+			base_block,
 			&request.start,
 			request.no_proof,
 		);
 
 		let result = if reputation_changes.is_empty() {
-			let mut response = StateResponse::default();
-
-			if !request.no_proof {
+			let mut response = StateResponse {
+				max_response_size: self.max_response_size.try_into().unwrap_or(u32::MAX),
+				modes: self.modes,
+				..Default::default()
+			};
+			let size_limit = match request.max_response_size as usize {
+				0 => self.max_response_size,
+				requested => requested.min(self.max_response_size),
+			};
+
+			if let Some(base_block) = base_block {
+				let entries = self.client.storage_delta_collection(
+					base_block,
+					block,
+					request.start.as_slice(),
+					size_limit,
+				)?;
+				response.entries = entries
+					.into_iter()
+					.map(|(delta, complete)| KeyValueStateEntry {
+						state_root: Vec::new(),
+						entries: delta
+							.key_values
+							.into_iter()
+							.map(|(key, value)| StateEntry { key, value })
+							.collect(),
+						complete,
+						removed: delta.removed,
+						child_storage_key: delta.storage_key,
+					})
+					.collect();
+			} else if !request.no_proof && self.modes & STATE_MODE_PROOF == 0 {
+				// The empty response advertises the served modes.
+				debug!(target: LOG_TARGET, "Not serving the state proof requested by {}", peer);
+			} else if !request.no_proof {
 				let (proof, _count) = self.client.read_proof_collection(
 					block,
 					request.start.as_slice(),
-					MAX_RESPONSE_BYTES,
+					size_limit,
 				)?;
 				response.proof = proof.encode();
 			} else {
 				let entries = self.client.storage_collection(
 					block,
 					request.start.as_slice(),
-					MAX_RESPONSE_BYTES,
+					size_limit,
 				)?;
 				response.entries = entries
 					.into_iter()
@@ -228,6 +383,8 @@ where
 							.map(|(key, value)| StateEntry { key, value })
 							.collect(),
 						complete,
+						removed: Vec::new(),
+						child_storage_key: Vec::new(),
 					})
 					.collect();
 			}
@@ -246,13 +403,69 @@ where
 					.last()
 					.map(|e| sp_core::hexdisplay::HexDisplay::from(&e.key))),
 			);
-			if let Some(value) = self.seen_requests.get(&key) {
-				// If this is the first time we have processed this request, we need to change
-				// it to `Fulfilled`.
-				if let SeenRequestsValue::First = value {
-					*value = SeenRequestsValue::Fulfilled(1);
-				}
-			}
+			self.on_request_fulfilled(&key);
+
+			let mut data = Vec::with_capacity(response.encoded_len());
+			response.encode(&mut data)?;
+			Ok(data)
+		} else {
+			Err(())
+		};
+
+		pending_response
+			.send(OutgoingResponse { result, reputation_changes, sent_feedback: None })
+			.map_err(|_| HandleRequestError::SendResponse)
+	}
+
+	/// Answer a request of the v2 protocol with the next frame of the state proof.
+	fn handle_frame_request(
+		&mut self,
+		payload: Vec<u8>,
+		pending_response: oneshot::Sender<OutgoingResponse>,
+		peer: &PeerId,
+	) -> Result<(), HandleRequestError> {
+		let request = v2::StateRequest::decode(&payload[..])?;
+		let block: B::Hash = Decode::decode(&mut request.block.as_ref())?;
+
+		let key =
+			SeenRequestsKey { peer: *peer, block, base_block: None, start: request.start.clone() };
+		let reputation_changes = self.on_request_seen(&key);
+
+		trace!(
+			target: LOG_TARGET,
+			"Handling state frame request from {}: Block {:?}, Starting at {:x?}",
+			peer,
+			request.block,
+			&request.start,
+		);
+
+		let result = if reputation_changes.is_empty() {
+			let max_frame_size = self.max_response_size.min(MAX_STATE_FRAME_SIZE);
+			let size_limit = match request.max_frame_size as usize {
+				0 => max_frame_size,
+				requested => requested.min(max_frame_size),
+			};
+			let response = if self.modes & STATE_MODE_PROOF == 0 {
+				// The empty frame advertises the served modes.
+				debug!(target: LOG_TARGET, "Not serving the state frame requested by {}", peer);
+				v2::StateResponse { proof: Vec::new(), modes: self.modes }
+			} else {
+				let (proof, count) = self.client.read_proof_collection(
+					block,
+					request.start.as_slice(),
+					size_limit,
+				)?;
+				let response = v2::StateResponse { proof: proof.encode(), modes: self.modes };
+
+				trace!(
+					target: LOG_TARGET,
+					"State frame proves {} keys with {} bytes",
+					count,
+					response.proof.len(),
+				);
+				response
+			};
+			self.on_request_fulfilled(&key);
 
 			let mut data = Vec::with_capacity(response.encoded_len());
 			response.encode(&mut data)?;
@@ -265,6 +478,39 @@ where
 			.send(OutgoingResponse { result, reputation_changes, sent_feedback: None })
 			.map_err(|_| HandleRequestError::SendResponse)
 	}
+
+	/// Record that the request `key` was received, returning the reputation changes of the peer
+	/// if it sent it too many times.
+	fn on_request_seen(&mut self, key: &SeenRequestsKey<B>) -> Vec<ReputationChange> {
+		let mut reputation_changes = Vec::new();
+
+		match self.seen_requests.get(key) {
+			Some(SeenRequestsValue::First) => {},
+			Some(SeenRequestsValue::Fulfilled(ref mut requests)) => {
+				*requests = requests.saturating_add(1);
+
+				if *requests > MAX_NUMBER_OF_SAME_REQUESTS_PER_PEER {
+					reputation_changes.push(rep::SAME_REQUEST);
+				}
+			},
+			None => {
+				self.seen_requests.insert(key.clone(), SeenRequestsValue::First);
+			},
+		}
+
+		reputation_changes
+	}
+
+	/// Record that the request `key` was answered.
+	fn on_request_fulfilled(&mut self, key: &SeenRequestsKey<B>) {
+		if let Some(value) = self.seen_requests.get(key) {
+			// If this is the first time we have processed this request, we need to change
+			// it to `Fulfilled`.
+			if let SeenRequestsValue::First = value {
+				*value = SeenRequestsValue::Fulfilled(1);
+			}
+		}
+	}
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -0,0 +1,128 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`SyncSnapshot`] is the sync progress saved when `SyncingEngine` is stopped, so the next start
+//! can resume from it.
+
+use codec::{Decode, Encode};
+use sc_network_common::sync::GapSyncProgress;
+use sp_runtime::traits::{Block as BlockT, NumberFor};
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/// Name of the file the snapshot is saved to, in the network configuration directory.
+const SYNC_SNAPSHOT_FILE: &str = "sync_snapshot";
+
+/// Sync progress at the time `SyncingEngine` was stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SyncSnapshot<B: BlockT> {
+	/// Hash of the best block queued for import.
+	pub best_queued_hash: B::Hash,
+	/// Number of the best block queued for import.
+	pub best_queued_number: NumberFor<B>,
+	/// Gap sync in progress, if any.
+	pub gap_sync: Option<GapSyncProgress<NumberFor<B>>>,
+	/// Hash, number and parent hash, if known, of the fork targets being downloaded.
+	pub fork_targets: Vec<(B::Hash, NumberFor<B>, Option<B::Hash>)>,
+}
+
+/// Encoded form of [`SyncSnapshot`].
+type EncodedSnapshot<B> = (
+	<B as BlockT>::Hash,
+	NumberFor<B>,
+	Option<(NumberFor<B>, NumberFor<B>, NumberFor<B>)>,
+	Vec<(<B as BlockT>::Hash, NumberFor<B>, Option<<B as BlockT>::Hash>)>,
+);
+
+/// Path of the snapshot file in the network configuration directory `net_config_path`.
+pub(crate) fn path(net_config_path: &Path) -> PathBuf {
+	net_config_path.join(SYNC_SNAPSHOT_FILE)
+}
+
+/// Load the snapshot saved at `path` and remove it, so it is only resumed from once.
+pub(crate) fn take<B: BlockT>(path: &Path) -> io::Result<Option<SyncSnapshot<B>>> {
+	let encoded = match fs::read(path) {
+		Ok(encoded) => encoded,
+		Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(error) => return Err(error),
+	};
+	fs::remove_file(path)?;
+
+	let (best_queued_hash, best_queued_number, gap_sync, fork_targets) =
+		EncodedSnapshot::<B>::decode(&mut &encoded[..])
+			.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+	Ok(Some(SyncSnapshot {
+		best_queued_hash,
+		best_queued_number,
+		gap_sync: gap_sync.map(|(start, end, best_queued)| GapSyncProgress {
+			start,
+			end,
+			best_queued,
+		}),
+		fork_targets,
+	}))
+}
+
+/// Save `snapshot` at `path`.
+pub(crate) fn save<B: BlockT>(path: &Path, snapshot: &SyncSnapshot<B>) -> io::Result<()> {
+	let encoded: EncodedSnapshot<B> = (
+		snapshot.best_queued_hash,
+		snapshot.best_queued_number,
+		snapshot.gap_sync.as_ref().map(|gap| (gap.start, gap.end, gap.best_queued)),
+		snapshot.fork_targets.clone(),
+	);
+
+	// Write to a temporary file first so a crash doesn't leave a truncated file behind.
+	let tmp_path = path.with_extension("tmp");
+	fs::write(&tmp_path, encoded.encode())?;
+	fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_test_primitives::Block;
+
+	#[test]
+	fn snapshot_is_taken_once() {
+		let dir = std::env::temp_dir().join(format!("sync-snapshot-{}", H256::random()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = path(&dir);
+
+		assert_eq!(take::<Block>(&path).unwrap(), None);
+
+		let snapshot = SyncSnapshot::<Block> {
+			best_queued_hash: H256::random(),
+			best_queued_number: 100,
+			gap_sync: Some(GapSyncProgress { start: 1, end: 50, best_queued: 10 }),
+			fork_targets: vec![
+				(H256::random(), 90, Some(H256::random())),
+				(H256::random(), 95, None),
+			],
+		};
+		save(&path, &snapshot).unwrap();
+		assert_eq!(take::<Block>(&path).unwrap(), Some(snapshot));
+		assert_eq!(take::<Block>(&path).unwrap(), None);
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,126 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`ThroughputTracker`] measures the download throughput of sync over a sliding time window.
+
+use sc_network_common::sync::SyncThroughput;
+use std::{
+	collections::VecDeque,
+	time::{Duration, Instant},
+};
+
+/// Blocks and bytes downloaded at a point in time.
+struct Sample {
+	at: Instant,
+	blocks: u64,
+	bytes: u64,
+}
+
+/// Tracks downloaded blocks and bytes over a sliding time window.
+pub(crate) struct ThroughputTracker {
+	window: Duration,
+	started: Instant,
+	samples: VecDeque<Sample>,
+}
+
+impl ThroughputTracker {
+	/// Create a new tracker measuring throughput over the last `window`.
+	pub fn new(window: Duration, now: Instant) -> Self {
+		Self { window, started: now, samples: VecDeque::new() }
+	}
+
+	/// Record `blocks` and `bytes` downloaded at `now`.
+	pub fn record(&mut self, now: Instant, blocks: u64, bytes: u64) {
+		self.samples.push_back(Sample { at: now, blocks, bytes });
+		self.prune(now);
+	}
+
+	/// Throughput over the window ending at `now`, and the estimated time to download
+	/// `remaining_blocks`.
+	pub fn throughput(&mut self, now: Instant, remaining_blocks: Option<u64>) -> SyncThroughput {
+		self.prune(now);
+
+		// Don't underestimate the throughput before a whole window has passed.
+		let elapsed = now.saturating_duration_since(self.started).min(self.window).as_secs_f64();
+		if elapsed == 0.0 {
+			return SyncThroughput::default()
+		}
+
+		let (blocks, bytes) = self
+			.samples
+			.iter()
+			.fold((0, 0), |(blocks, bytes), sample| (blocks + sample.blocks, bytes + sample.bytes));
+		let blocks_per_second = blocks as f64 / elapsed;
+		let eta = remaining_blocks
+			.filter(|remaining| *remaining > 0 && blocks > 0)
+			.map(|remaining| Duration::from_secs_f64(remaining as f64 / blocks_per_second));
+
+		SyncThroughput { blocks_per_second, bytes_per_second: bytes as f64 / elapsed, eta }
+	}
+
+	fn prune(&mut self, now: Instant) {
+		while self
+			.samples
+			.front()
+			.map_or(false, |sample| now.saturating_duration_since(sample.at) > self.window)
+		{
+			self.samples.pop_front();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn measures_throughput_over_window() {
+		let start = Instant::now();
+		let mut tracker = ThroughputTracker::new(Duration::from_secs(10), start);
+
+		tracker.record(start + Duration::from_secs(1), 10, 1000);
+		tracker.record(start + Duration::from_secs(2), 10, 1000);
+
+		// Only the time since start counts before the window is full.
+		let throughput = tracker.throughput(start + Duration::from_secs(4), Some(20));
+		assert_eq!(throughput.blocks_per_second, 5.0);
+		assert_eq!(throughput.bytes_per_second, 500.0);
+		assert_eq!(throughput.eta, Some(Duration::from_secs(4)));
+
+		// The first sample fell out of the window.
+		let throughput = tracker.throughput(start + Duration::from_secs(12), Some(20));
+		assert_eq!(throughput.blocks_per_second, 1.0);
+		assert_eq!(throughput.eta, Some(Duration::from_secs(20)));
+	}
+
+	#[test]
+	fn no_eta_without_progress_or_remaining_blocks() {
+		let start = Instant::now();
+		let mut tracker = ThroughputTracker::new(Duration::from_secs(10), start);
+
+		assert_eq!(tracker.throughput(start, Some(10)), SyncThroughput::default());
+		assert_eq!(tracker.throughput(start + Duration::from_secs(1), Some(10)).eta, None);
+
+		tracker.record(start + Duration::from_secs(1), 10, 1000);
+		assert_eq!(tracker.throughput(start + Duration::from_secs(2), None).eta, None);
+		assert_eq!(tracker.throughput(start + Duration::from_secs(2), Some(0)).eta, None);
+
+		tracker.record(start + Duration::from_secs(2), 0, 1000);
+		assert_eq!(tracker.throughput(start + Duration::from_secs(20), Some(10)).eta, None);
+	}
+}
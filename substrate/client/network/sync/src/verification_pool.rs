@@ -0,0 +1,148 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`VerificationPool`] runs CPU heavy verification of responses, e.g. of state proofs, on worker
+//! threads, so that it doesn't stall the `SyncingEngine` poll loop.
+
+use futures::StreamExt;
+use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedReceiver};
+use std::{
+	sync::{mpsc, Arc, Mutex},
+	task::{Context, Poll},
+	thread,
+};
+
+/// Verification job run by the pool.
+pub(crate) type Job<T> = Box<dyn FnOnce() -> T + Send>;
+
+/// Bounded pool of worker threads running verification jobs, whose results are polled.
+pub(crate) struct VerificationPool<T> {
+	jobs: mpsc::SyncSender<Job<T>>,
+	results: TracingUnboundedReceiver<T>,
+	/// Number of jobs queued or running.
+	pending: usize,
+}
+
+impl<T: Send + 'static> VerificationPool<T> {
+	/// Spawn `workers` threads named `name`, with room for `queue` jobs waiting for a worker.
+	///
+	/// The threads stop once the pool is dropped.
+	pub fn new(name: &'static str, workers: usize, queue: usize) -> Self {
+		let (jobs, jobs_rx) = mpsc::sync_channel::<Job<T>>(queue);
+		let (results_tx, results) = tracing_unbounded("mpsc_verification_pool", 100);
+		let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+
+		for _ in 0..workers {
+			let jobs_rx = jobs_rx.clone();
+			let results_tx = results_tx.clone();
+			thread::Builder::new()
+				.name(name.into())
+				.spawn(move || loop {
+					let job = match jobs_rx.lock() {
+						Ok(jobs_rx) => jobs_rx.recv(),
+						Err(_) => break,
+					};
+					match job {
+						Ok(job) =>
+							if results_tx.unbounded_send(job()).is_err() {
+								break
+							},
+						Err(_) => break,
+					}
+				})
+				.expect("Spawning a verification thread only fails on OOM; qed");
+		}
+
+		Self { jobs, results, pending: 0 }
+	}
+
+	/// Queue `job`. Returns it back if the queue is full or the workers are gone.
+	pub fn push(&mut self, job: Job<T>) -> Result<(), Job<T>> {
+		match self.jobs.try_send(job) {
+			Ok(()) => {
+				self.pending += 1;
+				Ok(())
+			},
+			Err(mpsc::TrySendError::Full(job) | mpsc::TrySendError::Disconnected(job)) => Err(job),
+		}
+	}
+
+	/// Number of jobs queued or running.
+	pub fn pending(&self) -> usize {
+		self.pending
+	}
+
+	/// Results of the jobs finished since the last call.
+	pub fn poll_results(&mut self, cx: &mut Context) -> Vec<T> {
+		let mut results = Vec::new();
+		while self.pending > 0 {
+			match self.results.poll_next_unpin(cx) {
+				Poll::Ready(Some(result)) => {
+					self.pending -= 1;
+					results.push(result);
+				},
+				Poll::Ready(None) | Poll::Pending => break,
+			}
+		}
+
+		results
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor::block_on, future::poll_fn};
+
+	#[test]
+	fn jobs_run_on_workers_and_queue_is_bounded() {
+		let (started_tx, started_rx) = mpsc::channel();
+		let (unblock_tx, unblock_rx) = mpsc::channel::<()>();
+		let unblock_rx = Arc::new(Mutex::new(unblock_rx));
+		let mut pool = VerificationPool::new("test-verification", 1, 1);
+
+		// The first job occupies the only worker, the second one the queue.
+		for i in 0..2 {
+			let (started_tx, unblock_rx) = (started_tx.clone(), unblock_rx.clone());
+			let job: Job<usize> = Box::new(move || {
+				started_tx.send(i).unwrap();
+				unblock_rx.lock().unwrap().recv().unwrap();
+				i
+			});
+			assert!(pool.push(job).is_ok());
+			if i == 0 {
+				assert_eq!(started_rx.recv().unwrap(), 0);
+			}
+		}
+		assert!(pool.push(Box::new(|| 2)).is_err());
+		assert_eq!(pool.pending(), 2);
+
+		unblock_tx.send(()).unwrap();
+		unblock_tx.send(()).unwrap();
+		let mut results = Vec::new();
+		while results.len() < 2 {
+			results.extend(block_on(poll_fn(|cx| match pool.poll_results(cx) {
+				results if results.is_empty() => Poll::Pending,
+				results => Poll::Ready(results),
+			})));
+		}
+
+		assert_eq!(results, vec![0, 1]);
+		assert_eq!(pool.pending(), 0);
+	}
+}
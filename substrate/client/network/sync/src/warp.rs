@@ -20,44 +20,67 @@
 
 use crate::{
 	schema::v1::{StateRequest, StateResponse},
-	state::{ImportResult, StateSync},
+	state::{ImportResult, PreparedImport, StateSync, VerifiedState},
+	warp_progress::{self, WarpProgress},
+	warp_snapshot,
 };
-use futures::channel::oneshot;
-use log::error;
+use futures::{channel::oneshot, StreamExt};
+use libp2p::PeerId;
 use sc_client_api::ProofProvider;
 use sc_network_common::sync::{
 	message::{BlockAttributes, BlockData, BlockRequest, Direction, FromBlock},
 	warp::{
-		EncodedProof, VerificationResult, WarpProofRequest, WarpSyncPhase, WarpSyncProgress,
-		WarpSyncProvider,
+		EncodedProof, ProofVerification, VerifierState, WarpProofRequest, WarpSyncBackend,
+		WarpSyncPhase, WarpSyncProgress, WarpSyncProvider,
 	},
 };
+use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedReceiver};
 use sp_blockchain::HeaderBackend;
-use sp_consensus_grandpa::{AuthorityList, SetId};
-use sp_runtime::traits::{Block as BlockT, Header, NumberFor, Zero};
-use std::sync::Arc;
+use sp_runtime::{
+	traits::{Block as BlockT, Header, NumberFor, One, Saturating, Zero},
+	SaturatedConversion,
+};
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+	sync::{mpsc, Arc},
+	task::{Context, Poll},
+	thread,
+};
 
 /// Log target for this file.
 const LOG_TARGET: &'static str = "sync";
 
+/// Maximum number of downloaded warp proofs waiting for verification.
+const MAX_UNVERIFIED_PROOFS: usize = 2;
+
 /// The different types of warp syncing, passed to `build_network`.
 pub enum WarpSyncParams<Block: BlockT> {
 	/// Standard warp sync for the chain.
 	WithProvider(Arc<dyn WarpSyncProvider<Block>>),
+	/// Warp sync with the proofs of a finality mechanism other than GRANDPA.
+	WithBackend(Arc<dyn WarpSyncBackend<Block>>),
 	/// Skip downloading proofs and wait for a header of the state that should be downloaded.
 	///
 	/// It is expected that the header provider ensures that the header is trusted.
 	WaitForTarget(oneshot::Receiver<<Block as BlockT>::Header>),
+	/// Warp sync from the [`WarpSnapshot`](crate::warp_snapshot::WarpSnapshot) file at the given
+	/// path, falling back to downloading the proofs if the snapshot is missing or invalid.
+	///
+	/// The proofs are verified with the backend, which also serves them to other peers.
+	FromSnapshot(Arc<dyn WarpSyncBackend<Block>>, PathBuf),
 }
 
 /// Warp sync configuration as accepted by [`WarpSync`].
 pub enum WarpSyncConfig<Block: BlockT> {
-	/// Standard warp sync for the chain.
-	WithProvider(Arc<dyn WarpSyncProvider<Block>>),
+	/// Warp sync with the proofs of the backend.
+	WithBackend(Arc<dyn WarpSyncBackend<Block>>),
 	/// Skip downloading proofs and wait for a header of the state that should be downloaded.
 	///
 	/// It is expected that the header provider ensures that the header is trusted.
 	WaitForTarget,
+	/// Warp sync from the snapshot file at the given path.
+	FromSnapshot(Arc<dyn WarpSyncBackend<Block>>, PathBuf),
 }
 
 impl<Block: BlockT> WarpSyncParams<Block> {
@@ -67,8 +90,101 @@ impl<Block: BlockT> WarpSyncParams<Block> {
 	) -> (WarpSyncConfig<Block>, Option<oneshot::Receiver<<Block as BlockT>::Header>>) {
 		match self {
 			WarpSyncParams::WithProvider(provider) =>
-				(WarpSyncConfig::WithProvider(provider), None),
+				(WarpSyncConfig::WithBackend(Arc::new(provider)), None),
+			WarpSyncParams::WithBackend(backend) => (WarpSyncConfig::WithBackend(backend), None),
 			WarpSyncParams::WaitForTarget(rx) => (WarpSyncConfig::WaitForTarget, Some(rx)),
+			WarpSyncParams::FromSnapshot(provider, path) =>
+				(WarpSyncConfig::FromSnapshot(provider, path), None),
+		}
+	}
+
+	/// Backend serving warp proofs to other peers, `None` if the node doesn't download them.
+	pub fn backend(&self) -> Option<Arc<dyn WarpSyncBackend<Block>>> {
+		match self {
+			WarpSyncParams::WithProvider(provider) => Some(Arc::new(provider.clone())),
+			WarpSyncParams::WithBackend(backend) | WarpSyncParams::FromSnapshot(backend, _) =>
+				Some(backend.clone()),
+			WarpSyncParams::WaitForTarget(_) => None,
+		}
+	}
+}
+
+/// Verifier state and last proven block hash reached while verifying warp proofs.
+type Proven<B> = (VerifierState, <B as BlockT>::Hash);
+
+/// Verify the warp proofs `proofs` in order, starting from `from`.
+///
+/// Returns the verifier state reached and, if the last proof completes the warp sync, the header
+/// of the target block.
+fn verify_proofs<B: BlockT>(
+	backend: &dyn WarpSyncBackend<B>,
+	proofs: &[Vec<u8>],
+	from: Proven<B>,
+) -> Result<(Proven<B>, Option<B::Header>), Box<dyn Error + Send + Sync>> {
+	let mut proven = from;
+	let mut target = None;
+	for proof in proofs {
+		let proof = EncodedProof(proof.clone());
+		match backend.verify(&proof, &proven.0)? {
+			ProofVerification::Partial(state, last_hash) => proven = (state, last_hash),
+			ProofVerification::Complete(state, header) => {
+				proven = (state, header.hash());
+				target = Some(header);
+			},
+		}
+	}
+
+	Ok((proven, target))
+}
+
+/// Warp proof verified on the [`ProofVerifier`] thread.
+struct VerifiedProof<B: BlockT> {
+	peer_id: PeerId,
+	proof: EncodedProof,
+	result: Result<ProofVerification<B>, Box<dyn Error + Send + Sync>>,
+}
+
+/// Verifies warp proofs on a worker thread, in download order, so that the next proof is
+/// downloaded while the previous ones are verified.
+struct ProofVerifier<B: BlockT> {
+	proofs: mpsc::Sender<(PeerId, EncodedProof)>,
+	verified: TracingUnboundedReceiver<VerifiedProof<B>>,
+	/// Number of proofs sent to the thread and not verified yet.
+	pending: usize,
+}
+
+impl<B: BlockT> ProofVerifier<B> {
+	/// Spawn the thread, verifying the first proof against `state`.
+	///
+	/// The thread stops after an invalid proof, or once the verifier is dropped.
+	fn spawn(backend: Arc<dyn WarpSyncBackend<B>>, mut state: VerifierState) -> Self {
+		let (proofs, proofs_rx) = mpsc::channel::<(PeerId, EncodedProof)>();
+		let (verified_tx, verified) = tracing_unbounded("mpsc_warp_proof_verifier", 100);
+		thread::Builder::new()
+			.name("warp-proof-verifier".into())
+			.spawn(move || {
+				for (peer_id, proof) in proofs_rx {
+					let result = backend.verify(&proof, &state);
+					let valid = result.is_ok();
+					if let Ok(ProofVerification::Partial(new_state, _)) = &result {
+						state = new_state.clone();
+					}
+					if verified_tx.unbounded_send(VerifiedProof { peer_id, proof, result }).is_err() ||
+						!valid
+					{
+						break
+					}
+				}
+			})
+			.expect("Spawning the warp proof verifier thread only fails on OOM; qed");
+
+		Self { proofs, verified, pending: 0 }
+	}
+
+	/// Queue `proof`, received from `peer_id`, for verification.
+	fn push(&mut self, peer_id: PeerId, proof: EncodedProof) {
+		if self.proofs.send((peer_id, proof)).is_ok() {
+			self.pending += 1;
 		}
 	}
 }
@@ -76,12 +192,7 @@ impl<Block: BlockT> WarpSyncParams<Block> {
 /// Warp sync phase.
 enum Phase<B: BlockT, Client> {
 	/// Downloading warp proofs.
-	WarpProof {
-		set_id: SetId,
-		authorities: AuthorityList,
-		last_hash: B::Hash,
-		warp_sync_provider: Arc<dyn WarpSyncProvider<B>>,
-	},
+	WarpProof { state: VerifierState, last_hash: B::Hash },
 	/// Waiting for target block to be set externally if we skip warp proofs downloading,
 	/// and start straight from the target block (used by parachains warp sync).
 	PendingTargetBlock,
@@ -91,6 +202,23 @@ enum Phase<B: BlockT, Client> {
 	State(StateSync<B, Client>),
 }
 
+/// Error returned when setting the warp sync target block fails.
+#[derive(Debug, thiserror::Error)]
+pub enum WarpSyncTargetError {
+	/// The node isn't warp syncing.
+	#[error("Not warp syncing.")]
+	NotWarpSyncing,
+	/// The target block isn't above the finalized block.
+	#[error("Target block is not above the finalized block.")]
+	NotAboveFinalized,
+	/// The state of the current target block is being downloaded already.
+	#[error("State download has started already.")]
+	StateDownloadStarted,
+	/// `SyncingEngine` has terminated.
+	#[error("Syncing engine terminated.")]
+	Terminated,
+}
+
 /// Import warp proof result.
 pub enum WarpProofImportResult {
 	/// Import was successful.
@@ -111,7 +239,23 @@ pub enum TargetBlockImportResult {
 pub struct WarpSync<B: BlockT, Client> {
 	phase: Phase<B, Client>,
 	client: Arc<Client>,
+	/// Backend verifying the warp proofs and the target block, `None` if the target block is set
+	/// externally.
+	backend: Option<Arc<dyn WarpSyncBackend<B>>>,
 	total_proof_bytes: u64,
+	/// Where verified warp proofs are saved, `None` if they are not saved.
+	progress_path: Option<PathBuf>,
+	/// Warp proofs verified so far, if they are saved.
+	proofs: Vec<Vec<u8>>,
+	/// Verifier of the downloaded warp proofs, spawned on the first proof.
+	verifier: Option<ProofVerifier<B>>,
+	/// Block the next warp proof starts at, read from the last downloaded proof before it is
+	/// verified. `None` if that proof completes the warp sync or can't be read.
+	next_begin: Option<B::Hash>,
+	/// Number of blocks below the proven target block whose state is downloaded instead.
+	target_offset: u32,
+	/// Whether the target block was set externally, `target_offset` doesn't apply then.
+	external_target: bool,
 }
 
 impl<B, Client> WarpSync<B, Client>
@@ -125,32 +269,185 @@ where
 	pub fn new(client: Arc<Client>, warp_sync_config: WarpSyncConfig<B>) -> Self {
 		let last_hash = client.hash(Zero::zero()).unwrap().expect("Genesis header always exists");
 		match warp_sync_config {
-			WarpSyncConfig::WithProvider(warp_sync_provider) => {
-				let phase = Phase::WarpProof {
-					set_id: 0,
-					authorities: warp_sync_provider.current_authorities(),
-					last_hash,
-					warp_sync_provider: warp_sync_provider.clone(),
-				};
-				Self { client, phase, total_proof_bytes: 0 }
+			WarpSyncConfig::WithBackend(backend) => {
+				let phase = Phase::WarpProof { state: backend.initial_state(), last_hash };
+				Self {
+					client,
+					phase,
+					backend: Some(backend),
+					total_proof_bytes: 0,
+					progress_path: None,
+					proofs: Vec::new(),
+					verifier: None,
+					next_begin: None,
+					target_offset: 0,
+					external_target: false,
+				}
+			},
+			WarpSyncConfig::WaitForTarget => Self {
+				client,
+				phase: Phase::PendingTargetBlock,
+				backend: None,
+				total_proof_bytes: 0,
+				progress_path: None,
+				proofs: Vec::new(),
+				verifier: None,
+				next_begin: None,
+				target_offset: 0,
+				external_target: false,
+			},
+			WarpSyncConfig::FromSnapshot(backend, path) => {
+				let mut warp_sync = Self::new(client, WarpSyncConfig::WithBackend(backend));
+				warp_sync.import_snapshot(&path);
+				warp_sync
 			},
-			WarpSyncConfig::WaitForTarget =>
-				Self { client, phase: Phase::PendingTargetBlock, total_proof_bytes: 0 },
 		}
 	}
 
-	/// Set target block externally in case we skip warp proof downloading.
-	pub fn set_target_block(&mut self, header: B::Header) {
-		let Phase::PendingTargetBlock = self.phase else {
-			error!(
+	/// Skip downloading warp proofs by verifying the ones of the snapshot at `path`.
+	///
+	/// Keeps downloading the proofs from the network if the snapshot can't be used.
+	fn import_snapshot(&mut self, path: &Path) {
+		let (Phase::WarpProof { state, last_hash }, Some(backend)) = (&self.phase, &self.backend)
+		else {
+			return
+		};
+
+		let snapshot = match warp_snapshot::load::<B>(path) {
+			Ok(snapshot) => snapshot,
+			Err(error) => {
+				log::warn!(
+					target: LOG_TARGET,
+					"Failed to load warp sync snapshot {}: {error}",
+					path.display(),
+				);
+				return
+			},
+		};
+		let verified = verify_proofs(&**backend, &snapshot.proofs, (state.clone(), *last_hash));
+		match verified {
+			Ok((_, Some(header))) if header == snapshot.target => {
+				log::info!(
+					target: LOG_TARGET,
+					"Warp syncing to #{} ({}) from snapshot",
+					header.number(),
+					header.hash(),
+				);
+				self.total_proof_bytes =
+					snapshot.proofs.iter().map(|proof| proof.len() as u64).sum();
+				self.phase = Phase::TargetBlock(header);
+			},
+			Ok(_) => log::warn!(
 				target: LOG_TARGET,
-				"Attempt to set warp sync target block in invalid phase.",
-			);
-			debug_assert!(false);
+				"Ignoring warp sync snapshot {}, proofs don't prove its target block",
+				path.display(),
+			),
+			Err(error) => log::warn!(
+				target: LOG_TARGET,
+				"Ignoring warp sync snapshot {}, proof is invalid: {error}",
+				path.display(),
+			),
+		}
+	}
+
+	/// Save verified warp proofs at `path`, and resume from the proofs saved there by an earlier
+	/// run, if any.
+	///
+	/// Saved proofs are verified again before resuming from them, and discarded if they are
+	/// invalid.
+	pub fn resume_from(&mut self, path: PathBuf) {
+		let (Phase::WarpProof { state, last_hash }, Some(backend)) =
+			(&mut self.phase, &self.backend)
+		else {
 			return
 		};
 
+		let progress = match warp_progress::load::<B>(&path) {
+			Ok(progress) => progress,
+			Err(error) => {
+				log::warn!(target: LOG_TARGET, "Failed to load warp sync progress: {error}");
+				None
+			},
+		};
+		self.progress_path = Some(path);
+		let Some(progress) = progress else { return };
+
+		let verified = verify_proofs(&**backend, &progress.proofs, (state.clone(), *last_hash));
+		let (resumed, target) = match verified {
+			Ok(verified) => verified,
+			Err(error) => {
+				log::warn!(
+					target: LOG_TARGET,
+					"Discarding saved warp sync progress, proof is invalid: {error}",
+				);
+				return
+			},
+		};
+		if resumed != (progress.state, progress.last_hash) {
+			log::warn!(
+				target: LOG_TARGET,
+				"Discarding saved warp sync progress, proofs don't match the saved state",
+			);
+			return
+		}
+
+		log::info!(
+			target: LOG_TARGET,
+			"Resuming warp sync from {} saved proofs",
+			progress.proofs.len(),
+		);
+		self.total_proof_bytes = progress.proofs.iter().map(|proof| proof.len() as u64).sum();
+		self.proofs = progress.proofs;
+		match target {
+			Some(header) => self.phase = Phase::TargetBlock(header),
+			None => (*state, *last_hash) = resumed,
+		}
+	}
+
+	/// Record the verified warp proof `proof`, proving blocks up to `last_hash` and leading to the
+	/// verifier state `state`.
+	fn save_proof(&mut self, proof: &EncodedProof, state: VerifierState, last_hash: B::Hash) {
+		let Some(path) = &self.progress_path else { return };
+
+		self.proofs.push(proof.0.clone());
+		let progress = WarpProgress::<B> { proofs: self.proofs.clone(), state, last_hash };
+		if let Err(error) = warp_progress::save(path, &progress) {
+			log::warn!(target: LOG_TARGET, "Failed to save warp sync progress: {error}");
+		}
+	}
+
+	/// Set target block externally in case we skip warp proof downloading, or replace the target
+	/// block whose state download hasn't started.
+	///
+	/// The warp proofs aren't downloaded anymore, the header is trusted.
+	pub fn set_target_block(&mut self, header: B::Header) -> Result<(), WarpSyncTargetError> {
+		if let Phase::State(_) = self.phase {
+			return Err(WarpSyncTargetError::StateDownloadStarted)
+		}
+
+		self.verifier = None;
+		self.next_begin = None;
+		self.external_target = true;
 		self.phase = Phase::TargetBlock(header);
+		Ok(())
+	}
+
+	/// Download the state of the block `offset` blocks below the target block proven by the warp
+	/// proofs, which is less likely to be pruned by the peers while its state is downloaded.
+	///
+	/// Doesn't apply to target blocks set externally.
+	pub fn set_target_offset(&mut self, offset: u32) {
+		self.target_offset = offset;
+	}
+
+	/// Number of blocks below the target block `header` whose state is downloaded.
+	fn target_offset(&self, header: &B::Header) -> NumberFor<B> {
+		if self.external_target {
+			return Zero::zero()
+		}
+		// The state of genesis is known already.
+		let max_offset = header.number().saturating_sub(One::one());
+		max_offset.min(self.target_offset.saturated_into())
 	}
 
 	///  Validate and import a state response.
@@ -164,91 +461,205 @@ where
 		}
 	}
 
-	///  Validate and import a warp proof response.
-	pub fn import_warp_proof(&mut self, response: EncodedProof) -> WarpProofImportResult {
+	/// Validate a state response, see [`StateSync::prepare_import`].
+	pub fn prepare_state_import(&mut self, response: StateResponse) -> PreparedImport<B, Client> {
+		match &mut self.phase {
+			Phase::WarpProof { .. } | Phase::TargetBlock(_) | Phase::PendingTargetBlock { .. } => {
+				log::debug!(target: "sync", "Unexpected state response");
+				PreparedImport::Imported(ImportResult::BadResponse)
+			},
+			Phase::State(sync) => sync.prepare_import(response),
+		}
+	}
+
+	/// Import a verified state chunk, see [`StateSync::import_verified`].
+	///
+	/// Returns `None` if no state proof is being verified.
+	pub fn import_verified_state(
+		&mut self,
+		verified: Option<VerifiedState>,
+	) -> Option<ImportResult<B>> {
+		match &mut self.phase {
+			Phase::State(sync) if sync.is_verifying() => Some(sync.import_verified(verified)),
+			_ => None,
+		}
+	}
+
+	/// Queue the warp proof response of `peer_id` for verification.
+	///
+	/// The result of the verification is returned by [`Self::poll_verified_proofs`].
+	pub fn import_warp_proof(
+		&mut self,
+		peer_id: PeerId,
+		response: EncodedProof,
+	) -> WarpProofImportResult {
 		match &mut self.phase {
 			Phase::State(_) | Phase::TargetBlock(_) | Phase::PendingTargetBlock { .. } => {
 				log::debug!(target: "sync", "Unexpected warp proof response");
 				WarpProofImportResult::BadResponse
 			},
-			Phase::WarpProof { set_id, authorities, last_hash, warp_sync_provider } =>
-				match warp_sync_provider.verify(&response, *set_id, authorities.clone()) {
-					Err(e) => {
-						log::debug!(target: "sync", "Bad warp proof response: {}", e);
-						WarpProofImportResult::BadResponse
-					},
-					Ok(VerificationResult::Partial(new_set_id, new_authorities, new_last_hash)) => {
-						log::debug!(target: "sync", "Verified partial proof, set_id={:?}", new_set_id);
-						*set_id = new_set_id;
-						*authorities = new_authorities;
-						*last_hash = new_last_hash;
-						self.total_proof_bytes += response.0.len() as u64;
-						WarpProofImportResult::Success
-					},
-					Ok(VerificationResult::Complete(new_set_id, _, header)) => {
-						log::debug!(target: "sync", "Verified complete proof, set_id={:?}", new_set_id);
-						self.total_proof_bytes += response.0.len() as u64;
-						self.phase = Phase::TargetBlock(header);
-						WarpProofImportResult::Success
-					},
+			Phase::WarpProof { state, .. } => {
+				let Some(backend) = &self.backend else {
+					log::debug!(target: "sync", "Unexpected warp proof response");
+					return WarpProofImportResult::BadResponse
+				};
+				self.total_proof_bytes += response.0.len() as u64;
+				self.next_begin = backend.next_proof_begin(&response);
+				self.verifier
+					.get_or_insert_with(|| ProofVerifier::spawn(backend.clone(), state.clone()))
+					.push(peer_id, response);
+				WarpProofImportResult::Success
+			},
+		}
+	}
+
+	/// Results of the warp proofs verified since the last call, by the peer that sent the proof.
+	///
+	/// The proofs queued after an invalid proof are discarded.
+	pub fn poll_verified_proofs(
+		&mut self,
+		cx: &mut Context,
+	) -> Vec<(PeerId, WarpProofImportResult)> {
+		let mut results = Vec::new();
+		while let Some(verifier) = &mut self.verifier {
+			match verifier.verified.poll_next_unpin(cx) {
+				Poll::Ready(Some(VerifiedProof { peer_id, proof, result })) => {
+					verifier.pending -= 1;
+					let result = self.on_proof_verified(proof, result);
+					results.push((peer_id, result));
 				},
+				Poll::Ready(None) => {
+					log::warn!(target: LOG_TARGET, "Warp proof verifier stopped unexpectedly");
+					self.verifier = None;
+				},
+				Poll::Pending => break,
+			}
 		}
+
+		results
 	}
 
-	/// Import the target block body.
-	pub fn import_target_block(&mut self, block: BlockData<B>) -> TargetBlockImportResult {
-		match &mut self.phase {
-			Phase::WarpProof { .. } | Phase::State(_) | Phase::PendingTargetBlock { .. } => {
-				log::debug!(target: "sync", "Unexpected target block response");
-				TargetBlockImportResult::BadResponse
+	/// Advance the warp sync with the verified warp proof `proof`.
+	fn on_proof_verified(
+		&mut self,
+		proof: EncodedProof,
+		result: Result<ProofVerification<B>, Box<dyn Error + Send + Sync>>,
+	) -> WarpProofImportResult {
+		let Phase::WarpProof { state, last_hash } = &mut self.phase else {
+			log::debug!(target: "sync", "Unexpected verified warp proof");
+			return WarpProofImportResult::BadResponse
+		};
+
+		match result {
+			Err(e) => {
+				log::debug!(target: "sync", "Bad warp proof response: {}", e);
+				// Proofs downloaded after the invalid one don't follow a verified proof.
+				self.verifier = None;
+				self.next_begin = None;
+				WarpProofImportResult::BadResponse
+			},
+			Ok(ProofVerification::Partial(new_state, new_last_hash)) => {
+				log::debug!(target: "sync", "Verified partial proof up to {new_last_hash:?}");
+				*state = new_state.clone();
+				*last_hash = new_last_hash;
+				self.save_proof(&proof, new_state, new_last_hash);
+				WarpProofImportResult::Success
+			},
+			Ok(ProofVerification::Complete(new_state, header)) => {
+				log::debug!(target: "sync", "Verified complete proof up to {:?}", header.hash());
+				self.save_proof(&proof, new_state, header.hash());
+				self.verifier = None;
+				self.phase = Phase::TargetBlock(header);
+				WarpProofImportResult::Success
 			},
-			Phase::TargetBlock(header) =>
-				if let Some(block_header) = &block.header {
-					if block_header == header {
-						if block.body.is_some() {
-							let state_sync = StateSync::new(
-								self.client.clone(),
-								header.clone(),
-								block.body,
-								block.justifications,
-								false,
-							);
-							self.phase = Phase::State(state_sync);
-							TargetBlockImportResult::Success
-						} else {
-							log::debug!(
-								target: "sync",
-								"Importing target block failed: missing body.",
-							);
-							TargetBlockImportResult::BadResponse
-						}
-					} else {
-						log::debug!(
-							target: "sync",
-							"Importing target block failed: different header.",
-						);
-						TargetBlockImportResult::BadResponse
-					}
-				} else {
-					log::debug!(target: "sync", "Importing target block failed: missing header.");
-					TargetBlockImportResult::BadResponse
-				},
 		}
 	}
 
+	/// Import the target block body.
+	///
+	/// With a target offset, `blocks` are the target block and its ancestors down to the block
+	/// whose state is downloaded, in ascending order. The ancestors are trusted as they are linked
+	/// to the target block by their hashes.
+	pub fn import_target_block(&mut self, mut blocks: Vec<BlockData<B>>) -> TargetBlockImportResult {
+		let Phase::TargetBlock(target) = &self.phase else {
+			log::debug!(target: "sync", "Unexpected target block response");
+			return TargetBlockImportResult::BadResponse
+		};
+		let expected = self.target_offset(target).saturated_into::<usize>() + 1;
+		if blocks.len() != expected {
+			log::debug!(
+				target: "sync",
+				"Importing target block failed: expected {expected} blocks, got {}.",
+				blocks.len(),
+			);
+			return TargetBlockImportResult::BadResponse
+		}
+
+		let target_block = blocks.last().expect("`blocks` len checked above; qed");
+		let Some(block_header) = &target_block.header else {
+			log::debug!(target: "sync", "Importing target block failed: missing header.");
+			return TargetBlockImportResult::BadResponse
+		};
+		if block_header != target {
+			log::debug!(target: "sync", "Importing target block failed: different header.");
+			return TargetBlockImportResult::BadResponse
+		}
+		if let Some(Err(e)) =
+			self.backend.as_ref().map(|backend| backend.verify_target_block(target_block))
+		{
+			log::debug!(target: "sync", "Importing target block failed: {}", e);
+			return TargetBlockImportResult::BadResponse
+		}
+		let linked = blocks.windows(2).all(|pair| match (&pair[0].header, &pair[1].header) {
+			(Some(parent), Some(child)) => *child.parent_hash() == parent.hash(),
+			_ => false,
+		});
+		if !linked {
+			log::debug!(
+				target: "sync",
+				"Importing target block failed: ancestors don't lead to the target block.",
+			);
+			return TargetBlockImportResult::BadResponse
+		}
+
+		let block = blocks.swap_remove(0);
+		let (Some(header), Some(body)) = (block.header, block.body) else {
+			log::debug!(target: "sync", "Importing target block failed: missing body.");
+			return TargetBlockImportResult::BadResponse
+		};
+		if expected > 1 {
+			log::info!(
+				target: LOG_TARGET,
+				"Downloading the state of #{} ({}), {} blocks below the warp sync target",
+				header.number(),
+				header.hash(),
+				expected - 1,
+			);
+		}
+		let state_sync =
+			StateSync::new(self.client.clone(), header, Some(body), block.justifications, false);
+		self.phase = Phase::State(state_sync);
+		TargetBlockImportResult::Success
+	}
+
 	/// Produce next state request.
 	pub fn next_state_request(&self) -> Option<StateRequest> {
 		match &self.phase {
 			Phase::WarpProof { .. } | Phase::TargetBlock(_) | Phase::PendingTargetBlock { .. } =>
 				None,
-			Phase::State(sync) => Some(sync.next_request()),
+			Phase::State(sync) => (!sync.is_verifying()).then(|| sync.next_request()),
 		}
 	}
 
 	/// Produce next warp proof request.
 	pub fn next_warp_proof_request(&self) -> Option<WarpProofRequest<B>> {
 		match &self.phase {
-			Phase::WarpProof { last_hash, .. } => Some(WarpProofRequest { begin: *last_hash }),
+			Phase::WarpProof { last_hash, .. } => match &self.verifier {
+				Some(verifier) if verifier.pending >= MAX_UNVERIFIED_PROOFS => None,
+				Some(verifier) if verifier.pending > 0 =>
+					self.next_begin.map(|begin| WarpProofRequest { begin }),
+				_ => Some(WarpProofRequest { begin: *last_hash }),
+			},
 			Phase::TargetBlock(_) | Phase::State(_) | Phase::PendingTargetBlock { .. } => None,
 		}
 	}
@@ -258,19 +669,36 @@ where
 		match &self.phase {
 			Phase::WarpProof { .. } | Phase::State(_) | Phase::PendingTargetBlock { .. } => None,
 			Phase::TargetBlock(header) => {
+				// The block whose state is downloaded is requested with the blocks leading to the
+				// target block.
+				let offset = self.target_offset(header);
 				let request = BlockRequest::<B> {
 					id: 0,
 					fields: BlockAttributes::HEADER |
-						BlockAttributes::BODY | BlockAttributes::JUSTIFICATION,
+						BlockAttributes::BODY |
+						BlockAttributes::JUSTIFICATION,
 					from: FromBlock::Hash(header.hash()),
-					direction: Direction::Ascending,
-					max: Some(1),
+					direction: if offset.is_zero() {
+						Direction::Ascending
+					} else {
+						Direction::Descending
+					},
+					max: Some(offset.saturated_into::<u32>() + 1),
 				};
 				Some((*header.number(), request))
 			},
 		}
 	}
 
+	/// Remove the saved warp proofs, once the warp sync is complete.
+	pub fn clear_progress(&self) {
+		if let Some(path) = &self.progress_path {
+			if let Err(error) = warp_progress::remove(path) {
+				log::warn!(target: LOG_TARGET, "Failed to remove warp sync progress: {error}");
+			}
+		}
+	}
+
 	/// Return target block hash if it is known.
 	pub fn target_block_hash(&self) -> Option<B::Hash> {
 		match &self.phase {
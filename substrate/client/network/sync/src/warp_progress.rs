@@ -0,0 +1,107 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`WarpProgress`] is the warp sync progress saved after each verified warp proof, so that a node
+//! restarted mid-warp-sync resumes from it instead of downloading the proofs again.
+
+use codec::{Decode, Encode};
+use sc_network_common::sync::warp::VerifierState;
+use sp_runtime::traits::Block as BlockT;
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/// Name of the file the progress is saved to, in the network configuration directory.
+const WARP_PROGRESS_FILE: &str = "warp_sync_progress";
+
+/// Warp proofs verified so far.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct WarpProgress<B: BlockT> {
+	/// Verified warp proof fragments, in download order.
+	pub proofs: Vec<Vec<u8>>,
+	/// Verifier state reached by verifying `proofs`.
+	pub state: VerifierState,
+	/// Hash of the last block proven by `proofs`.
+	pub last_hash: B::Hash,
+}
+
+/// Path of the progress file in the network configuration directory `net_config_path`.
+pub(crate) fn path(net_config_path: &Path) -> PathBuf {
+	net_config_path.join(WARP_PROGRESS_FILE)
+}
+
+/// Load the progress saved at `path`, if any.
+pub(crate) fn load<B: BlockT>(path: &Path) -> io::Result<Option<WarpProgress<B>>> {
+	let encoded = match fs::read(path) {
+		Ok(encoded) => encoded,
+		Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(error) => return Err(error),
+	};
+
+	WarpProgress::decode(&mut &encoded[..])
+		.map(Some)
+		.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Save `progress` at `path`.
+pub(crate) fn save<B: BlockT>(path: &Path, progress: &WarpProgress<B>) -> io::Result<()> {
+	// Write to a temporary file first so a crash doesn't leave a truncated file behind.
+	let tmp_path = path.with_extension("tmp");
+	fs::write(&tmp_path, progress.encode())?;
+	fs::rename(tmp_path, path)
+}
+
+/// Remove the progress saved at `path`, once warp sync is complete.
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+	match fs::remove_file(path) {
+		Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+		_ => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_test_primitives::Block;
+
+	#[test]
+	fn progress_is_kept_until_removed() {
+		let dir = std::env::temp_dir().join(format!("warp-progress-{}", H256::random()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = path(&dir);
+
+		assert_eq!(load::<Block>(&path).unwrap(), None);
+
+		let progress = WarpProgress::<Block> {
+			proofs: vec![vec![1, 2, 3], vec![4, 5]],
+			state: VerifierState(vec![2]),
+			last_hash: H256::random(),
+		};
+		save(&path, &progress).unwrap();
+		assert_eq!(load::<Block>(&path).unwrap(), Some(progress.clone()));
+		assert_eq!(load::<Block>(&path).unwrap(), Some(progress));
+
+		remove(&path).unwrap();
+		assert_eq!(load::<Block>(&path).unwrap(), None);
+		remove(&path).unwrap();
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -21,15 +21,15 @@ use futures::{channel::oneshot, stream::StreamExt};
 use log::debug;
 
 use sc_network::{
-	config::ProtocolId,
+	config::{ProtocolId, DEFAULT_WARP_PROOF_REQUEST_TIMEOUT},
 	request_responses::{
 		IncomingRequest, OutgoingResponse, ProtocolConfig as RequestResponseConfig,
 	},
 };
-use sc_network_common::sync::warp::{EncodedProof, WarpProofRequest, WarpSyncProvider};
+use sc_network_common::sync::warp::{EncodedProof, WarpProofRequest, WarpSyncBackend};
 use sp_runtime::traits::Block as BlockT;
 
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
 const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
 
@@ -49,7 +49,7 @@ pub fn generate_request_response_config<Hash: AsRef<[u8]>>(
 			.collect(),
 		max_request_size: 32,
 		max_response_size: MAX_RESPONSE_SIZE,
-		request_timeout: Duration::from_secs(10),
+		request_timeout: DEFAULT_WARP_PROOF_REQUEST_TIMEOUT,
 		inbound_queue: None,
 	}
 }
@@ -71,7 +71,7 @@ fn generate_legacy_protocol_name(protocol_id: ProtocolId) -> String {
 
 /// Handler for incoming grandpa warp sync requests from a remote peer.
 pub struct RequestHandler<TBlock: BlockT> {
-	backend: Arc<dyn WarpSyncProvider<TBlock>>,
+	backend: Arc<dyn WarpSyncBackend<TBlock>>,
 	request_receiver: async_channel::Receiver<IncomingRequest>,
 }
 
@@ -81,7 +81,7 @@ impl<TBlock: BlockT> RequestHandler<TBlock> {
 		protocol_id: ProtocolId,
 		genesis_hash: Hash,
 		fork_id: Option<&str>,
-		backend: Arc<dyn WarpSyncProvider<TBlock>>,
+		backend: Arc<dyn WarpSyncBackend<TBlock>>,
 	) -> (Self, RequestResponseConfig) {
 		let (tx, request_receiver) = async_channel::bounded(MAX_WARP_REQUEST_QUEUE);
 
@@ -0,0 +1,81 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! [`WarpSnapshot`] packages the warp proofs of a chain up to a target block, so that nodes can
+//! warp sync from a file distributed out of band and only download the state of the target block
+//! from the network.
+//!
+//! The proofs are verified locally from the initial state of the warp sync backend, the file
+//! doesn't need to be trusted.
+
+use codec::{Decode, Encode};
+use sp_runtime::traits::Block as BlockT;
+use std::{fs, io, path::Path};
+
+/// Warp proofs from genesis up to `target`.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct WarpSnapshot<B: BlockT> {
+	/// Encoded warp proofs, in order. The last one completes the warp sync at `target`.
+	pub proofs: Vec<Vec<u8>>,
+	/// Header of the block whose state is downloaded once the proofs are verified.
+	pub target: B::Header,
+}
+
+/// Load the snapshot from the file at `path`.
+pub fn load<B: BlockT>(path: &Path) -> io::Result<WarpSnapshot<B>> {
+	let encoded = fs::read(path)?;
+	WarpSnapshot::decode(&mut &encoded[..])
+		.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Save `snapshot` to the file at `path`.
+pub fn save<B: BlockT>(path: &Path, snapshot: &WarpSnapshot<B>) -> io::Result<()> {
+	fs::write(path, snapshot.encode())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_runtime::{generic::Digest, traits::Header as HeaderT};
+	use sp_test_primitives::{Block, Header};
+
+	#[test]
+	fn snapshot_round_trip() {
+		let path = std::env::temp_dir().join(format!("warp-snapshot-{}", H256::random()));
+		assert!(load::<Block>(&path).is_err());
+
+		let snapshot = WarpSnapshot::<Block> {
+			proofs: vec![vec![1, 2, 3], vec![4, 5]],
+			target: Header::new(
+				42,
+				H256::random(),
+				H256::random(),
+				H256::random(),
+				Digest::default(),
+			),
+		};
+		save(&path, &snapshot).unwrap();
+		assert_eq!(load::<Block>(&path).unwrap(), snapshot);
+
+		fs::write(&path, [1, 2, 3]).unwrap();
+		assert_eq!(load::<Block>(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+		fs::remove_file(path).unwrap();
+	}
+}
@@ -691,6 +691,10 @@ pub struct FullPeerConfig {
 	pub storage_chain: bool,
 	/// Optional target block header to sync to
 	pub target_block: Option<<Block as BlockT>::Header>,
+	/// Download the block history skipped by warp sync.
+	pub download_history: bool,
+	/// Only support the v1 state request protocol, as nodes predating the v2 one.
+	pub legacy_state_requests: bool,
 }
 
 #[async_trait::async_trait]
@@ -777,6 +781,7 @@ pub trait TestNetFactory: Default + Sized + Send {
 		let mut network_config =
 			NetworkConfiguration::new("test-node", "test-client", Default::default(), None);
 		network_config.sync_mode = config.sync_mode;
+		network_config.download_history = config.download_history;
 		network_config.transport = TransportConfig::MemoryOnly;
 		network_config.listen_addresses = vec![listen_addr.clone()];
 		network_config.allow_non_globals_in_dht = true;
@@ -818,6 +823,13 @@ pub trait TestNetFactory: Default + Sized + Send {
 			protocol_config
 		};
 
+		let state_request_v2_protocol_config = (!config.legacy_state_requests).then(|| {
+			let (handler, protocol_config) =
+				StateRequestHandler::new_v2(&protocol_id, None, client.clone(), 50);
+			self.spawn_task(handler.run().boxed());
+			protocol_config
+		});
+
 		let light_client_request_protocol_config = {
 			let (handler, protocol_config) =
 				LightClientRequestHandler::new(&protocol_id, None, client.clone());
@@ -851,7 +863,7 @@ pub trait TestNetFactory: Default + Sized + Send {
 			protocol_config
 		};
 
-		let block_announce_validator = config
+		let block_announce_validator: Box<dyn BlockAnnounceValidator<Block> + Send> = config
 			.block_announce_validator
 			.unwrap_or_else(|| Box::new(DefaultBlockAnnounceValidator));
 
@@ -864,12 +876,14 @@ pub trait TestNetFactory: Default + Sized + Send {
 				&full_net_config,
 				protocol_id.clone(),
 				&fork_id,
-				block_announce_validator,
+				vec![block_announce_validator],
 				Some(warp_sync_params),
 				chain_sync_network_handle,
 				import_queue.service(),
 				block_relay_params.downloader,
+				Vec::new(),
 				state_request_protocol_config.name.clone(),
+				state_request_v2_protocol_config.as_ref().map(|config| config.name.clone()),
 				Some(warp_protocol_config.name.clone()),
 				rx,
 			)
@@ -885,7 +899,10 @@ pub trait TestNetFactory: Default + Sized + Send {
 			state_request_protocol_config,
 			light_client_request_protocol_config,
 			warp_protocol_config,
-		] {
+		]
+		.into_iter()
+		.chain(state_request_v2_protocol_config)
+		{
 			full_net_config.add_request_response_protocol(config);
 		}
 
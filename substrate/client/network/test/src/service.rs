@@ -191,13 +191,15 @@ impl TestNetworkBuilder {
 			&full_net_config,
 			protocol_id.clone(),
 			&None,
-			Box::new(sp_consensus::block_validation::DefaultBlockAnnounceValidator),
+			vec![Box::new(sp_consensus::block_validation::DefaultBlockAnnounceValidator)],
 			None,
 			chain_sync_network_handle,
 			import_queue.service(),
 			block_relay_params.downloader,
+			Vec::new(),
 			state_request_protocol_config.name.clone(),
 			None,
+			None,
 			rx,
 		)
 		.unwrap();
@@ -227,6 +227,136 @@ async fn sync_long_chain_works() {
 	assert!(net.peers()[0].blockchain_canon_equals(peer1));
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn paused_sync_resumes() {
+	sp_tracing::try_init_simple();
+	let mut net = TestNet::new(2);
+	net.peer(0).sync_service().pause_sync();
+	net.run_until_connected().await;
+	net.peer(1).push_blocks(10, false);
+
+	// the paused peer learns about the blocks but doesn't download them
+	for _ in 0..20 {
+		futures::future::poll_fn::<(), _>(|cx| {
+			net.poll(cx);
+			Poll::Ready(())
+		})
+		.await;
+		tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+	}
+	assert_eq!(net.peer(0).client().info().best_number, 0);
+	assert_eq!(net.peer(0).num_peers(), 1);
+
+	net.peer(0).sync_service().resume_sync();
+	net.run_until_sync().await;
+	assert_eq!(net.peer(0).client().info().best_number, 10);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn paused_sync_sends_no_requests() {
+	use sc_consensus::{BlockImportError, Link};
+
+	sp_tracing::try_init_simple();
+	let mut net = TestNet::new(2);
+	net.peer(0).sync_service().pause_sync();
+	net.peer(1).push_blocks(10, false);
+	net.run_until_connected().await;
+
+	async fn poll_for_a_while(net: &mut TestNet) {
+		for _ in 0..20 {
+			futures::future::poll_fn::<(), _>(|cx| {
+				net.poll(cx);
+				Poll::Ready(())
+			})
+			.await;
+			tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+		}
+	}
+	async fn no_request_in_flight(net: &TestNet) -> bool {
+		let state = net.peer(0).sync_service().dump_state().await.unwrap();
+		assert!(state.paused);
+		state.peers.values().all(|peer| !peer.request_in_flight)
+	}
+
+	// the request to the peer on connection isn't sent
+	poll_for_a_while(&mut net).await;
+	assert!(no_request_in_flight(&net).await);
+
+	// neither are those issued when the import queue restarts the sync
+	let mut link = (**net.peer(0).sync_service()).clone();
+	link.blocks_processed(
+		0,
+		1,
+		vec![(Err(BlockImportError::VerificationFailed(None, "test".into())), H256::random())],
+	);
+	poll_for_a_while(&mut net).await;
+	assert!(no_request_in_flight(&net).await);
+	assert_eq!(net.peer(0).client().info().best_number, 0);
+
+	net.peer(0).sync_service().resume_sync();
+	net.run_until_sync().await;
+	assert_eq!(net.peer(0).client().info().best_number, 10);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn disconnected_peer_is_removed_from_sync() {
+	use sc_network_common::sync::{SyncEvent, SyncEventStream};
+	use sc_network_sync::service::network::{PeerAuditAction, PeerAuditEvent};
+
+	sp_tracing::try_init_simple();
+	let mut net = TestNet::new(2);
+	net.run_until_connected().await;
+	let peer_id = net.peer(1).id();
+	let mut events = net.peer(0).sync_service().event_stream("test-sync-events");
+	let mut audit = net.peer(0).sync_service().subscribe_peer_audit();
+
+	net.peer(0).sync_service().disconnect_peer(peer_id, "test".into());
+
+	// the peer is dropped from the sync before the substream is closed
+	assert!(matches!(
+		events.next().await.unwrap(),
+		SyncEvent::PeerDisconnected(disconnected) if disconnected == peer_id
+	));
+	// and only disconnected, its reputation is left as is
+	let PeerAuditEvent { peer_id: disconnected, action } = audit.next().await.unwrap();
+	assert_eq!(disconnected, peer_id);
+	assert!(matches!(action, PeerAuditAction::Disconnected(_)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn banned_peer_is_reported_and_kept_out_of_sync() {
+	use sc_network_sync::service::network::{PeerAuditAction, PeerAuditEvent};
+
+	sp_tracing::try_init_simple();
+	let mut net = TestNet::new(2);
+	net.run_until_connected().await;
+	let peer_id = net.peer(1).id();
+	let mut audit = net.peer(0).sync_service().subscribe_peer_audit();
+
+	net.peer(0).sync_service().ban_peer(peer_id, Duration::from_secs(60));
+
+	let PeerAuditEvent { peer_id: reported, action } = audit.next().await.unwrap();
+	assert_eq!(reported, peer_id);
+	assert!(matches!(action, PeerAuditAction::Reported(change) if change.value < 0));
+	let PeerAuditEvent { peer_id: disconnected, action } = audit.next().await.unwrap();
+	assert_eq!(disconnected, peer_id);
+	assert!(matches!(action, PeerAuditAction::Disconnected(_)));
+
+	// the peer isn't synced from again, even if it reconnects
+	net.peer(1).push_blocks(10, false);
+	for _ in 0..20 {
+		futures::future::poll_fn::<(), _>(|cx| {
+			net.poll(cx);
+			Poll::Ready(())
+		})
+		.await;
+		tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+	}
+	let state = net.peer(0).sync_service().dump_state().await.unwrap();
+	assert!(state.peers.is_empty());
+	assert_eq!(net.peer(0).client().info().best_number, 0);
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn sync_no_common_longer_chain_fails() {
 	sp_tracing::try_init_simple();
@@ -1097,7 +1227,8 @@ async fn syncs_after_missing_announcement() {
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn syncs_state() {
 	sp_tracing::try_init_simple();
-	for skip_proofs in &[false, true] {
+	// Proofs are requested over the v2 state request protocol unless the serving peer predates it.
+	for (skip_proofs, legacy_state_requests) in [(false, false), (false, true), (true, false)] {
 		let mut net = TestNet::new(0);
 		let mut genesis_storage: sp_core::storage::Storage = Default::default();
 		genesis_storage.top.insert(b"additional_key".to_vec(), vec![1]);
@@ -1131,11 +1262,11 @@ async fn syncs_state() {
 			.insert(child3.child_info.storage_key().to_vec(), child3);
 		let mut config_one = FullPeerConfig::default();
 		config_one.extra_storage = Some(genesis_storage.clone());
+		config_one.legacy_state_requests = legacy_state_requests;
 		net.add_full_peer_with_config(config_one);
 		let mut config_two = FullPeerConfig::default();
 		config_two.extra_storage = Some(genesis_storage);
-		config_two.sync_mode =
-			SyncMode::LightState { skip_proofs: *skip_proofs, storage_chain_mode: false };
+		config_two.sync_mode = SyncMode::LightState { skip_proofs, storage_chain_mode: false };
 		net.add_full_peer_with_config(config_two);
 		let hashes = net.peer(0).push_blocks(64, false);
 		// Wait for peer 1 to sync header chain.
@@ -1232,6 +1363,7 @@ async fn warp_sync() {
 	net.add_full_peer_with_config(Default::default());
 	net.add_full_peer_with_config(FullPeerConfig {
 		sync_mode: SyncMode::Warp,
+		download_history: true,
 		..Default::default()
 	});
 	let gap_end = net.peer(0).push_blocks(63, false).pop().unwrap();
@@ -1273,6 +1405,7 @@ async fn warp_sync_to_target_block() {
 
 	net.add_full_peer_with_config(FullPeerConfig {
 		sync_mode: SyncMode::Warp,
+		download_history: true,
 		target_block: Some(target_block),
 		..Default::default()
 	});
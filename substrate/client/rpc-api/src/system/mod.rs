@@ -83,6 +83,14 @@ pub trait SystemApi<Hash, Number> {
 	#[method(name = "system_unstable_networkState")]
 	async fn system_network_state(&self) -> RpcResult<JsonValue>;
 
+	/// Returns the internal state of the syncing engine: peers, in-flight requests, fork
+	/// targets, queued blocks and the sync strategy phase.
+	///
+	/// **Warning**: This API is not stable. Please do not programmatically interpret its output,
+	/// as its format might change at any time.
+	#[method(name = "sync_unstable_dump")]
+	async fn sync_dump(&self) -> RpcResult<JsonValue>;
+
 	/// Adds a reserved peer. Returns the empty string or an error. The string
 	/// parameter should encode a `p2p` multiaddr.
 	///
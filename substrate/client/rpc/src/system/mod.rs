@@ -56,6 +56,8 @@ pub enum Request<B: traits::Block> {
 	Peers(oneshot::Sender<Vec<PeerInfo<B::Hash, <B::Header as HeaderT>::Number>>>),
 	/// Must return the state of the network.
 	NetworkState(oneshot::Sender<serde_json::Value>),
+	/// Must return the internal state of the syncing engine.
+	SyncDump(oneshot::Sender<serde_json::Value>),
 	/// Must return any potential parse error.
 	NetworkAddReservedPeer(String, oneshot::Sender<Result<()>>),
 	/// Must return any potential parse error.
@@ -138,6 +140,13 @@ impl<B: traits::Block> SystemApiServer<B::Hash, <B::Header as HeaderT>::Number>
 		rx.await.map_err(|e| JsonRpseeError::to_call_error(e))
 	}
 
+	async fn sync_dump(&self) -> RpcResult<JsonValue> {
+		self.deny_unsafe.check_if_safe()?;
+		let (tx, rx) = oneshot::channel();
+		let _ = self.send_back.unbounded_send(Request::SyncDump(tx));
+		rx.await.map_err(|e| JsonRpseeError::to_call_error(e))
+	}
+
 	async fn system_add_reserved_peer(&self, peer: String) -> RpcResult<()> {
 		self.deny_unsafe.check_if_safe()?;
 		let (tx, rx) = oneshot::channel();
@@ -98,6 +98,9 @@ fn api<T: Into<Option<Status>>>(sync: T) -> RpcModule<System<Block>> {
 						.unwrap(),
 					);
 				},
+				Request::SyncDump(sender) => {
+					let _ = sender.send(serde_json::json!({ "strategy": "Full" }));
+				},
 				Request::NetworkAddReservedPeer(peer, sender) => {
 					let _ = match sc_network::config::parse_str_addr(&peer) {
 						Ok(_) => sender.send(Ok(())),
@@ -267,6 +270,13 @@ async fn system_peers() {
 	);
 }
 
+#[tokio::test]
+async fn sync_dump() {
+	let dump: serde_json::Value =
+		api(None).call("sync_unstable_dump", EmptyParams::new()).await.unwrap();
+	assert_eq!(dump, serde_json::json!({ "strategy": "Full" }));
+}
+
 #[tokio::test]
 async fn system_network_state() {
 	use sc_network::network_state::NetworkState;
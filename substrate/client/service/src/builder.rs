@@ -51,7 +51,8 @@ use sc_network_common::role::Roles;
 use sc_network_light::light_client_requests::handler::LightClientRequestHandler;
 use sc_network_sync::{
 	block_relay_protocol::BlockRelayParams, block_request_handler::BlockRequestHandler,
-	engine::SyncingEngine, service::network::NetworkServiceProvider,
+	engine::SyncingEngine, finality_notifications::FinalityNotificationsPrototype,
+	instance_protocol_name, service::network::NetworkServiceProvider,
 	state_request_handler::StateRequestHandler, warp::WarpSyncParams,
 	warp_request_handler::RequestHandler as WarpSyncRequestHandler, SyncingService,
 };
@@ -75,7 +76,7 @@ use sp_consensus::block_validation::{
 use sp_core::traits::{CodeExecutor, SpawnNamed};
 use sp_keystore::KeystorePtr;
 use sp_runtime::traits::{Block as BlockT, BlockIdTo, NumberFor, Zero};
-use std::{str::FromStr, sync::Arc, time::SystemTime};
+use std::{iter, str::FromStr, sync::Arc, time::SystemTime};
 
 /// Full client type.
 pub type TFullClient<TBl, TRtApi, TExec> =
@@ -761,9 +762,23 @@ where
 		Box::new(DefaultBlockAnnounceValidator)
 	};
 
+	// The sync protocols of a named sync instance are namespaced with its name.
+	let sync_instance_name = net_config.network_config.sync_instance_name.clone();
+	let namespaced = |name| instance_protocol_name(sync_instance_name.as_deref(), name);
+
 	let (chain_sync_network_provider, chain_sync_network_handle) = NetworkServiceProvider::new();
-	let (mut block_server, block_downloader, block_request_protocol_config) = match block_relay {
-		Some(params) => (params.server, params.downloader, params.request_response_config),
+	let (
+		mut block_server,
+		block_downloader,
+		mut block_request_protocol_config,
+		mut block_request_fallback_configs,
+	) = match block_relay {
+		Some(params) => (
+			params.server,
+			params.downloader,
+			params.request_response_config,
+			params.fallback_protocols,
+		),
 		None => {
 			// Custom protocol was not specified, use the default block handler.
 			// Allow both outgoing and incoming requests.
@@ -775,33 +790,73 @@ where
 				config.network.default_peers_set.in_peers as usize +
 					config.network.default_peers_set.out_peers as usize,
 			);
-			(params.server, params.downloader, params.request_response_config)
+			(
+				params.server,
+				params.downloader,
+				params.request_response_config,
+				params.fallback_protocols,
+			)
 		},
 	};
+	block_request_protocol_config.request_timeout =
+		net_config.network_config.block_request_timeout;
+	for config in iter::once(&mut block_request_protocol_config)
+		.chain(&mut block_request_fallback_configs)
+	{
+		config.name = namespaced(config.name.clone());
+	}
+	// The block request protocols are negotiated with each peer in this order.
+	let block_request_protocol_names = iter::once(&block_request_protocol_config)
+		.chain(&block_request_fallback_configs)
+		.map(|config| config.name.clone())
+		.collect::<Vec<_>>();
 	spawn_handle.spawn("block-request-handler", Some("networking"), async move {
 		block_server.run().await;
 	});
 
+	let num_peer_hint = net_config.network_config.default_peers_set_num_full as usize +
+		net_config.network_config.default_peers_set.reserved_nodes.len();
 	let (state_request_protocol_config, state_request_protocol_name) = {
-		let num_peer_hint = net_config.network_config.default_peers_set_num_full as usize +
-			net_config.network_config.default_peers_set.reserved_nodes.len();
 		// Allow both outgoing and incoming requests.
-		let (handler, protocol_config) = StateRequestHandler::new(
+		let (mut handler, mut protocol_config) = StateRequestHandler::new(
 			&protocol_id,
 			config.chain_spec.fork_id(),
 			client.clone(),
 			num_peer_hint,
 		);
+		handler.set_max_response_size(net_config.network_config.max_state_response_size);
+		handler.set_serve_proofs(net_config.network_config.serve_state_proofs);
+		protocol_config.request_timeout = net_config.network_config.state_request_timeout;
+		protocol_config.name = namespaced(protocol_config.name.clone());
 		let config_name = protocol_config.name.clone();
 
 		spawn_handle.spawn("state-request-handler", Some("networking"), handler.run());
 		(protocol_config, config_name)
 	};
 
-	let (warp_sync_protocol_config, warp_request_protocol_name) = match warp_sync_params.as_ref() {
-		Some(WarpSyncParams::WithProvider(warp_with_provider)) => {
+	let (state_request_v2_protocol_config, state_request_v2_protocol_name) = {
+		// Allow both outgoing and incoming requests.
+		let (mut handler, mut protocol_config) = StateRequestHandler::new_v2(
+			&protocol_id,
+			config.chain_spec.fork_id(),
+			client.clone(),
+			num_peer_hint,
+		);
+		handler.set_max_response_size(net_config.network_config.max_state_response_size);
+		handler.set_serve_proofs(net_config.network_config.serve_state_proofs);
+		protocol_config.request_timeout = net_config.network_config.state_request_timeout;
+		protocol_config.name = namespaced(protocol_config.name.clone());
+		let config_name = protocol_config.name.clone();
+
+		spawn_handle.spawn("state-request-v2-handler", Some("networking"), handler.run());
+		(protocol_config, config_name)
+	};
+
+	let warp_sync_backend = warp_sync_params.as_ref().and_then(WarpSyncParams::backend);
+	let (warp_sync_protocol_config, warp_request_protocol_name) = match warp_sync_backend {
+		Some(warp_sync_backend) => {
 			// Allow both outgoing and incoming requests.
-			let (handler, protocol_config) = WarpSyncRequestHandler::new(
+			let (handler, mut protocol_config) = WarpSyncRequestHandler::new(
 				protocol_id.clone(),
 				client
 					.block_hash(0u32.into())
@@ -809,14 +864,17 @@ where
 					.flatten()
 					.expect("Genesis block exists; qed"),
 				config.chain_spec.fork_id(),
-				warp_with_provider.clone(),
+				warp_sync_backend,
 			);
+			protocol_config.request_timeout =
+				net_config.network_config.warp_proof_request_timeout;
+			protocol_config.name = namespaced(protocol_config.name.clone());
 			let config_name = protocol_config.name.clone();
 
 			spawn_handle.spawn("warp-sync-request-handler", Some("networking"), handler.run());
 			(Some(protocol_config), Some(config_name))
 		},
-		_ => (None, None),
+		None => (None, None),
 	};
 
 	let light_client_request_protocol_config = {
@@ -832,7 +890,12 @@ where
 
 	// install request handlers to `FullNetworkConfiguration`
 	net_config.add_request_response_protocol(block_request_protocol_config);
+	for mut config in block_request_fallback_configs {
+		config.request_timeout = net_config.network_config.block_request_timeout;
+		net_config.add_request_response_protocol(config);
+	}
 	net_config.add_request_response_protocol(state_request_protocol_config);
+	net_config.add_request_response_protocol(state_request_v2_protocol_config);
 	net_config.add_request_response_protocol(light_client_request_protocol_config);
 
 	if let Some(config) = warp_sync_protocol_config {
@@ -858,6 +921,21 @@ where
 	);
 	net_config.add_notification_protocol(transactions_handler_proto.set_config());
 
+	// the finality notification protocol is optional, only registered if enabled
+	let finality_notifications_proto =
+		net_config.network_config.finality_notifications.then(|| {
+			let proto = FinalityNotificationsPrototype::new(
+				client
+					.block_hash(0u32.into())
+					.ok()
+					.flatten()
+					.expect("Genesis block exists; qed"),
+				config.chain_spec.fork_id(),
+			);
+			net_config.add_notification_protocol(proto.set_config());
+			proto
+		});
+
 	// Create `PeerStore` and initialize it with bootnode peer ids.
 	let peer_store = PeerStore::new(
 		net_config
@@ -878,15 +956,25 @@ where
 		&net_config,
 		protocol_id.clone(),
 		&config.chain_spec.fork_id().map(ToOwned::to_owned),
-		block_announce_validator,
+		vec![block_announce_validator],
 		warp_sync_params,
 		chain_sync_network_handle,
 		import_queue.service(),
 		block_downloader,
+		block_request_protocol_names,
 		state_request_protocol_name,
+		Some(state_request_v2_protocol_name),
 		warp_request_protocol_name,
 		rx,
 	)?;
+	// Blocks excluded by the chain spec are never downloaded either.
+	let bad_blocks = get_extension::<BadBlocks<TBl>>(config.chain_spec.extensions())
+		.cloned()
+		.unwrap_or_default()
+		.unwrap_or_default();
+	for hash in bad_blocks {
+		sync_service.ban_block(hash);
+	}
 	let sync_service_import_queue = sync_service.clone();
 	let sync_service = Arc::new(sync_service);
 
@@ -921,6 +1009,15 @@ where
 	)?;
 	spawn_handle.spawn("network-transactions-handler", Some("networking"), tx_handler.run());
 
+	if let Some(proto) = finality_notifications_proto {
+		let handler = proto.build(network.clone(), sync_service.clone(), client.clone());
+		spawn_handle.spawn(
+			"network-finality-notifications-handler",
+			Some("networking"),
+			handler.run(),
+		);
+	}
+
 	spawn_handle.spawn_blocking(
 		"chain-sync-network-service-provider",
 		Some("networking"),
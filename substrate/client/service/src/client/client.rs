@@ -39,10 +39,11 @@ use sc_client_api::{
 	execution_extensions::ExecutionExtensions,
 	notifications::{StorageEventStream, StorageNotifications},
 	CallExecutor, ExecutorProvider, KeysIter, OnFinalityAction, OnImportAction, PairsIter,
-	ProofProvider, UsageProvider,
+	ProofProvider, StorageDeltaLevel, UsageProvider,
 };
 use sc_consensus::{
-	BlockCheckParams, BlockImportParams, ForkChoiceStrategy, ImportResult, StateAction,
+	BlockCheckParams, BlockImportParams, ForkChoiceStrategy, ImportResult, ImportedState,
+	StateAction, StateDelta,
 };
 use sc_executor::RuntimeVersion;
 use sc_telemetry::{telemetry, TelemetryHandle, SUBSTRATE_INFO};
@@ -80,8 +81,9 @@ use sp_state_machine::{
 };
 use sp_trie::{CompactProof, MerkleValue, StorageProof};
 use std::{
-	collections::{HashMap, HashSet},
+	collections::{BTreeSet, HashMap, HashSet},
 	marker::PhantomData,
+	ops::Bound,
 	path::PathBuf,
 	sync::Arc,
 };
@@ -93,6 +95,14 @@ use {
 
 type NotificationSinks<T> = Mutex<Vec<TracingUnboundedSender<T>>>;
 
+/// Maximum number of keys compared by a single call to
+/// [`ProofProvider::storage_delta_collection`], bounding its work when few keys changed.
+const MAX_DELTA_KEYS_VISITED: usize = 64 * 1024;
+
+/// Maximum number of blocks whose recorded changed keys are combined to serve a state delta.
+/// The state delta since older blocks is served by comparing the states.
+const MAX_DELTA_RECORDED_BLOCKS: u32 = 1024;
+
 /// Substrate Client
 pub struct Client<B, E, Block, RA>
 where
@@ -484,6 +494,41 @@ where
 		CallExecutor::runtime_version(&self.executor, hash)
 	}
 
+	/// Storage keys changed between the state of `base` and the state of `hash`, by prefixed
+	/// child storage key, empty for the top trie, combined from the keys the backend recorded for
+	/// each block since `base`.
+	///
+	/// Returns `None` if `base` isn't a recent ancestor of `hash` or if the keys of some blocks
+	/// since `base` aren't recorded.
+	fn changed_storage_keys_since(
+		&self,
+		base: Block::Hash,
+		hash: Block::Hash,
+	) -> sp_blockchain::Result<Option<HashMap<Vec<u8>, BTreeSet<Vec<u8>>>>> {
+		let base_number = self.backend.blockchain().header_metadata(base)?.number;
+		let number = self.backend.blockchain().header_metadata(hash)?.number;
+		if number < base_number || number - base_number > MAX_DELTA_RECORDED_BLOCKS.into() {
+			return Ok(None)
+		}
+
+		let mut changed_keys: HashMap<_, BTreeSet<_>> = HashMap::new();
+		let mut current = hash;
+		while current != base {
+			let header = self.backend.blockchain().header_metadata(current)?;
+			if header.number <= base_number {
+				return Ok(None)
+			}
+			let Some(changes) = self.backend.changed_storage_keys(current)? else {
+				return Ok(None)
+			};
+			for (storage_key, keys) in changes {
+				changed_keys.entry(storage_key).or_default().extend(keys);
+			}
+			current = header.parent;
+		}
+		Ok(Some(changed_keys))
+	}
+
 	/// Apply a checked and validated block to an operation.
 	fn apply_block(
 		&self,
@@ -567,6 +612,68 @@ where
 		result
 	}
 
+	/// Apply the storage changes of a state delta download on top of the state of its base
+	/// block, checking the resulting state root against `header`.
+	fn apply_state_delta(
+		&self,
+		operation: &mut B::BlockImportOperation,
+		delta: StateDelta<Block>,
+		header: &Block::Header,
+	) -> sp_blockchain::Result<()> {
+		let StateDelta { base, changes } = delta;
+		let mut top = Vec::new();
+		let mut children = Vec::new();
+		for (storage_key, changes) in changes {
+			if storage_key.is_empty() {
+				// Child trie roots are recalculated from the child trie changes.
+				top.extend(
+					changes
+						.into_iter()
+						.filter(|(key, _)| !well_known_keys::is_child_storage_key(key)),
+				);
+				continue
+			}
+			match ChildType::from_prefixed_key(PrefixedStorageKey::new_ref(&storage_key)) {
+				Some((ChildType::ParentKeyId, storage_key)) =>
+					children.push((ChildInfo::new_default(storage_key), changes)),
+				None => return Err(Error::Backend("Invalid child storage key.".to_string())),
+			}
+		}
+
+		let state = self.state_at(base)?;
+		// The runtime may have been upgraded since `base`.
+		let state_version = match top.iter().find(|(key, _)| key == well_known_keys::CODE) {
+			Some((key, Some(code))) => {
+				let mut storage = sp_storage::Storage::default();
+				storage.top.insert(key.clone(), code.clone());
+				resolve_state_version_from_wasm(&storage, &self.executor)?
+			},
+			_ => self.runtime_version_at(base)?.state_version(),
+		};
+		let (state_root, transaction) = state.full_storage_root(
+			top.iter().map(|(key, value)| (&key[..], value.as_deref())),
+			children.iter().map(|(child_info, changes)| {
+				(child_info, changes.iter().map(|(key, value)| (&key[..], value.as_deref())))
+			}),
+			state_version,
+		);
+		if state_root != *header.state_root() {
+			warn!("Error importing state delta: State root mismatch.");
+			return Err(Error::InvalidStateRoot)
+		}
+
+		self.backend.begin_state_operation(operation, base)?;
+		operation.update_db_storage(transaction)?;
+		operation.update_storage(
+			top,
+			children
+				.into_iter()
+				.map(|(child_info, changes)| (child_info.storage_key().to_vec(), changes))
+				.collect(),
+		)?;
+		Ok(())
+	}
+
 	fn execute_and_import_block(
 		&self,
 		operation: &mut ClientImportOperation<Block, B>,
@@ -638,6 +745,13 @@ where
 
 						Some((main_sc, child_sc))
 					},
+					sc_consensus::StorageChanges::Import(ImportedState {
+						delta: Some(delta),
+						..
+					}) => {
+						self.apply_state_delta(&mut operation.op, delta, import_headers.post())?;
+						None
+					},
 					sc_consensus::StorageChanges::Import(changes) => {
 						let mut storage = sp_storage::Storage::default();
 						for state in changes.state.0.into_iter() {
@@ -1378,6 +1492,152 @@ where
 		Ok(result)
 	}
 
+	fn storage_delta_collection(
+		&self,
+		base: Block::Hash,
+		hash: Block::Hash,
+		start_key: &[Vec<u8>],
+		size_limit: usize,
+	) -> sp_blockchain::Result<Vec<(StorageDeltaLevel, bool)>> {
+		if start_key.len() > MAX_NESTED_TRIE_DEPTH {
+			return Err(Error::Backend("Invalid start key.".to_string()))
+		}
+		let base_state = self.state_at(base)?;
+		let state = self.state_at(hash)?;
+		// Only the keys recorded as changed since `base` are compared, when they are available.
+		let changed_keys = self.changed_storage_keys_since(base, hash)?;
+		let child_info = |storage_key: &Vec<u8>| -> sp_blockchain::Result<ChildInfo> {
+			let storage_key = PrefixedStorageKey::new_ref(storage_key);
+			match ChildType::from_prefixed_key(storage_key) {
+				Some((ChildType::ParentKeyId, storage_key)) =>
+					Ok(ChildInfo::new_default(storage_key)),
+				None => Err(Error::Backend("Invalid child storage key.".to_string())),
+			}
+		};
+		let value = |state: &B::State,
+		             child: Option<&ChildInfo>,
+		             key: &[u8]|
+		 -> sp_blockchain::Result<Option<Vec<u8>>> {
+			match child {
+				Some(child) => state.child_storage(child, key),
+				None => state.storage(key),
+			}
+			.map_err(|e| sp_blockchain::Error::from_state(Box::new(e)))
+		};
+		// Next key to compare in the given level, in order.
+		let next_key = |child: Option<&(ChildInfo, Vec<u8>)>,
+		                key: &[u8]|
+		 -> sp_blockchain::Result<Option<Vec<u8>>> {
+			if let Some(changed_keys) = changed_keys.as_ref() {
+				let storage_key = child.map_or(&[][..], |(_, storage_key)| &storage_key[..]);
+				return Ok(changed_keys.get(storage_key).and_then(|keys| {
+					keys.range::<[u8], _>((Bound::Excluded(key), Bound::Unbounded)).next().cloned()
+				}))
+			}
+			let next = |state: &B::State| {
+				match child {
+					Some((child, _)) => state.next_child_storage_key(child, key),
+					None => state.next_storage_key(key),
+				}
+				.map_err(|e| sp_blockchain::Error::from_state(Box::new(e)))
+			};
+			// Keys present in either state.
+			Ok(match (next(&base_state)?, next(&state)?) {
+				(Some(base_key), Some(key)) => Some(base_key.min(key)),
+				(Some(key), None) | (None, Some(key)) => Some(key),
+				(None, None) => None,
+			})
+		};
+		let mut current_child = if start_key.len() == 2 {
+			let start_key = start_key.get(0).expect("checked len");
+			// The child trie may have been removed since `base`.
+			if value(&state, None, start_key)?.is_some() ||
+				value(&base_state, None, start_key)?.is_some()
+			{
+				Some((child_info(start_key)?, start_key.clone()))
+			} else {
+				return Err(Error::Backend("Invalid root start key.".to_string()))
+			}
+		} else {
+			None
+		};
+		let mut current_key = start_key.last().map(Clone::clone).unwrap_or_default();
+		let mut total_size = 0;
+		let mut visited = 0;
+		let mut result = vec![(StorageDeltaLevel::default(), false)];
+
+		loop {
+			let mut delta = StorageDeltaLevel::default();
+			let mut complete = true;
+			let mut switch_child_key = None;
+			while let Some(key) = next_key(current_child.as_ref(), &current_key)? {
+				let child = current_child.as_ref().map(|(child, _)| child);
+				visited += 1;
+				let new_value = value(&state, child, &key)?;
+				if value(&base_state, child, &key)? == new_value {
+					if visited < MAX_DELTA_KEYS_VISITED {
+						current_key = key;
+						continue
+					}
+					// Out of keys to visit. The unchanged key is collected if nothing else was,
+					// so that the requester resumes after it.
+					if delta.key_values.is_empty() && delta.removed.is_empty() {
+						match new_value {
+							Some(value) => delta.key_values.push((key, value)),
+							None => delta.removed.push(key),
+						}
+					}
+					complete = false;
+					break
+				}
+				let size = key.len() + new_value.as_ref().map_or(0, |value| value.len());
+				if total_size + size > size_limit &&
+					!(delta.key_values.is_empty() && delta.removed.is_empty())
+				{
+					complete = false;
+					break
+				}
+				total_size += size;
+
+				let switch_child = child.is_none() &&
+					sp_core::storage::well_known_keys::is_child_storage_key(key.as_slice());
+				match new_value {
+					Some(value) => delta.key_values.push((key.clone(), value)),
+					None => delta.removed.push(key.clone()),
+				}
+				if switch_child {
+					// The child trie root changed, collect the child trie delta.
+					switch_child_key = Some(key);
+					break
+				}
+				current_key = key;
+				if visited >= MAX_DELTA_KEYS_VISITED {
+					complete = false;
+					break
+				}
+			}
+			if let Some(storage_key) = switch_child_key.take() {
+				result[0].0.key_values.extend(delta.key_values.into_iter());
+				result[0].0.removed.extend(delta.removed.into_iter());
+				current_child = Some((child_info(&storage_key)?, storage_key));
+				current_key = Vec::new();
+			} else if let Some((_, storage_key)) = current_child.take() {
+				current_key = storage_key.clone();
+				delta.storage_key = storage_key;
+				result.push((delta, complete));
+				if !complete {
+					break
+				}
+			} else {
+				result[0].0.key_values.extend(delta.key_values.into_iter());
+				result[0].0.removed.extend(delta.removed.into_iter());
+				result[0].1 = complete;
+				break
+			}
+		}
+		Ok(result)
+	}
+
 	fn verify_range_proof(
 		&self,
 		root: Block::Hash,
@@ -241,6 +241,8 @@ impl Configuration {
 			state_pruning: self.state_pruning.clone(),
 			source: self.database.clone(),
 			blocks_pruning: self.blocks_pruning,
+			metrics_registry: self.prometheus_registry().cloned(),
+			state_value_cache_size: None,
 		}
 	}
 }
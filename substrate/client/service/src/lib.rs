@@ -286,6 +286,13 @@ pub async fn build_system_rpc_future<
 					break
 				}
 			},
+			sc_rpc::system::Request::SyncDump(sender) => match sync_service.dump_state().await {
+				Ok(state) =>
+					if let Ok(state) = serde_json::to_value(state) {
+						let _ = sender.send(state);
+					},
+				Err(_) => log::error!("`SyncingEngine` shut down"),
+			},
 			sc_rpc::system::Request::NetworkAddReservedPeer(peer_addr, sender) => {
 				let result = match MultiaddrWithPeerId::try_from(peer_addr) {
 					Ok(peer) => network_service.add_reserved_peer(peer),
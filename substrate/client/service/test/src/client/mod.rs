@@ -22,11 +22,12 @@ use parity_scale_codec::{Decode, Encode, Joiner};
 use sc_block_builder::BlockBuilderProvider;
 use sc_client_api::{
 	in_mem, BlockBackend, BlockchainEvents, ExecutorProvider, FinalityNotifications, HeaderBackend,
-	StorageProvider,
+	ProofProvider, StorageProvider,
 };
 use sc_client_db::{Backend, BlocksPruning, DatabaseSettings, DatabaseSource, PruningMode};
 use sc_consensus::{
 	BlockCheckParams, BlockImport, BlockImportParams, ForkChoiceStrategy, ImportResult,
+	ImportedState, StateAction, StateDelta, StorageChanges,
 };
 use sc_service::client::{new_in_mem, Client, LocalCallExecutor};
 use sp_api::ProvideRuntimeApi;
@@ -1265,6 +1266,8 @@ fn doesnt_import_blocks_that_revert_finality() {
 				state_pruning: Some(PruningMode::ArchiveAll),
 				blocks_pruning: BlocksPruning::KeepAll,
 				source: DatabaseSource::RocksDb { path: tmp.path().into(), cache_size: 1024 },
+				metrics_registry: None,
+				state_value_cache_size: None,
 			},
 			u64::MAX,
 		)
@@ -1519,6 +1522,8 @@ fn returns_status_for_pruned_blocks() {
 				state_pruning: Some(PruningMode::blocks_pruning(1)),
 				blocks_pruning: BlocksPruning::KeepFinalized,
 				source: DatabaseSource::RocksDb { path: tmp.path().into(), cache_size: 1024 },
+				metrics_registry: None,
+				state_value_cache_size: None,
 			},
 			u64::MAX,
 		)
@@ -2029,3 +2034,157 @@ fn finalize_after_best_block_updates_best() {
 	assert_eq!(client.chain_info().finalized_hash, a3.hash());
 	assert_eq!(client.chain_info().best_hash, a3.hash());
 }
+
+/// Import a block on top of `parent` applying `changes` to the storage.
+fn import_block_with_storage_changes(
+	client: &mut TestClient,
+	parent: Hash,
+	changes: &[(Vec<u8>, Option<Vec<u8>>)],
+) -> Block {
+	let mut builder = client.new_block_at(parent, Default::default(), false).unwrap();
+	for (key, value) in changes {
+		builder.push_storage_change(key.clone(), value.clone()).unwrap();
+	}
+	let block = builder.build().unwrap().block;
+	block_on(client.import(BlockOrigin::Own, block.clone())).unwrap();
+	block
+}
+
+/// State delta between `base` and `hash` served by `client`, as downloaded by state sync.
+fn state_delta(
+	client: &TestClient,
+	base: Hash,
+	hash: Hash,
+) -> Vec<(Vec<u8>, Vec<(Vec<u8>, Option<Vec<u8>>)>)> {
+	client
+		.storage_delta_collection(base, hash, &[], usize::MAX)
+		.unwrap()
+		.into_iter()
+		.map(|(level, complete)| {
+			assert!(complete);
+			let mut changes = level
+				.key_values
+				.into_iter()
+				.map(|(key, value)| (key, Some(value)))
+				.chain(level.removed.into_iter().map(|key| (key, None)))
+				.collect::<Vec<_>>();
+			changes.sort();
+			(level.storage_key, changes)
+		})
+		.collect()
+}
+
+/// Top trie entries that differ between the states of `base` and `hash`.
+fn compared_state_delta(
+	client: &TestClient,
+	base: Hash,
+	hash: Hash,
+) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+	let pairs = |hash| -> std::collections::BTreeMap<_, _> {
+		client
+			.storage_pairs(hash, None, None)
+			.unwrap()
+			.map(|(key, value)| (key.0, value.0))
+			.collect()
+	};
+	let (base, state) = (pairs(base), pairs(hash));
+	let mut changes = state
+		.iter()
+		.filter(|(key, value)| base.get(*key) != Some(*value))
+		.map(|(key, value)| (key.clone(), Some(value.clone())))
+		.chain(
+			base.keys()
+				.filter(|key| !state.contains_key(*key))
+				.map(|key| (key.clone(), None)),
+		)
+		.collect::<Vec<_>>();
+	changes.sort();
+	changes
+}
+
+#[test]
+fn state_delta_is_served_from_recorded_keys_or_by_comparing_states() {
+	sp_tracing::try_init_simple();
+	let mut client = substrate_test_runtime_client::new();
+	let genesis = client.chain_info().genesis_hash;
+
+	// G -> A1 -> A2 -> A3
+	//   \
+	//    -> B1
+	let a1 = import_block_with_storage_changes(&mut client, genesis, &[(vec![1], Some(vec![1]))]);
+	let a2 = import_block_with_storage_changes(&mut client, a1.hash(), &[(vec![2], Some(vec![2]))]);
+	let a3 = import_block_with_storage_changes(
+		&mut client,
+		a2.hash(),
+		&[(vec![1], None), (vec![3], Some(vec![3]))],
+	);
+	let b1 = import_block_with_storage_changes(&mut client, genesis, &[(vec![4], Some(vec![4]))]);
+
+	// Served from the keys recorded for A2 and A3.
+	let delta = state_delta(&client, a1.hash(), a3.hash());
+	assert_eq!(delta.len(), 1);
+	assert_eq!(delta[0].1, compared_state_delta(&client, a1.hash(), a3.hash()));
+	assert!(delta[0].1.contains(&(vec![1], None)));
+	assert!(delta[0].1.contains(&(vec![2], Some(vec![2]))));
+	assert!(delta[0].1.contains(&(vec![3], Some(vec![3]))));
+
+	// B1 isn't an ancestor of A3, the states are compared.
+	let delta = state_delta(&client, b1.hash(), a3.hash());
+	assert_eq!(delta.len(), 1);
+	assert_eq!(delta[0].1, compared_state_delta(&client, b1.hash(), a3.hash()));
+	assert!(delta[0].1.contains(&(vec![4], None)));
+	assert!(!delta[0].1.iter().any(|(key, _)| key == &vec![1]));
+}
+
+#[test]
+fn state_delta_is_applied_on_top_of_the_base_state() {
+	sp_tracing::try_init_simple();
+	let mut client = substrate_test_runtime_client::new();
+	let genesis = client.chain_info().genesis_hash;
+	let a1 = import_block_with_storage_changes(&mut client, genesis, &[(vec![1], Some(vec![1]))]);
+	let a2 = import_block_with_storage_changes(&mut client, a1.hash(), &[(vec![2], Some(vec![2]))]);
+	let a3 = import_block_with_storage_changes(
+		&mut client,
+		a2.hash(),
+		&[(vec![1], None), (vec![3], Some(vec![3]))],
+	);
+	let delta = state_delta(&client, a1.hash(), a3.hash());
+
+	// A second node with the state of A1, syncing the header of A2 without its state.
+	let mut synced = substrate_test_runtime_client::new();
+	block_on(synced.import(BlockOrigin::Own, a1.clone())).unwrap();
+	let mut import = BlockImportParams::new(BlockOrigin::NetworkInitialSync, a2.header().clone());
+	import.state_action = StateAction::Skip;
+	import.fork_choice = Some(ForkChoiceStrategy::LongestChain);
+	block_on(synced.import_block(import)).unwrap();
+
+	let import_delta = |synced: &mut TestClient, changes| {
+		let mut import =
+			BlockImportParams::new(BlockOrigin::NetworkInitialSync, a3.header().clone());
+		import.body = Some(a3.extrinsics().to_vec());
+		import.state_action = StateAction::ApplyChanges(StorageChanges::Import(ImportedState {
+			block: a3.hash(),
+			state: Default::default(),
+			delta: Some(StateDelta { base: a1.hash(), changes }),
+		}));
+		import.fork_choice = Some(ForkChoiceStrategy::LongestChain);
+		block_on(synced.import_block(import))
+	};
+
+	// Missing the removal of `[1]`, the state root doesn't match.
+	let mut incomplete = delta.clone();
+	incomplete[0].1.retain(|(key, _)| key != &vec![1]);
+	assert!(import_delta(&mut synced, incomplete).is_err());
+	assert!(synced.state_at(a3.hash()).is_err());
+
+	assert!(import_delta(&mut synced, delta).is_ok());
+	let storage =
+		|key: Vec<u8>| synced.storage(a3.hash(), &StorageKey(key)).unwrap().map(|value| value.0);
+	assert_eq!(storage(vec![1]), None);
+	assert_eq!(storage(vec![2]), Some(vec![2]));
+	assert_eq!(storage(vec![3]), Some(vec![3]));
+	assert_eq!(
+		compared_state_delta(&synced, a1.hash(), a3.hash()),
+		compared_state_delta(&client, a1.hash(), a3.hash()),
+	);
+}
@@ -41,19 +41,30 @@
 //! See `RefWindow` for pruning algorithm details. `StateDb` prunes on each canonicalization until
 //! pruning constraints are satisfied.
 
+mod metrics;
+#[cfg(test)]
+mod model;
 mod noncanonical;
 mod pruning;
 #[cfg(test)]
 mod test;
 
-use codec::Codec;
-use log::trace;
-use noncanonical::NonCanonicalOverlay;
-use parking_lot::RwLock;
+use codec::{Codec, Decode, Encode};
+use log::{trace, warn};
+use metrics::Metrics;
+use noncanonical::{NonCanonicalOverlay, ValuesSnapshot};
+use parking_lot::{Mutex, RwLock};
+use prometheus_endpoint::{PrometheusError, Registry};
 use pruning::{HaveBlock, RefWindow};
+use schnellru::{ByLength, LruMap};
 use std::{
-	collections::{hash_map::Entry, HashMap},
+	collections::{hash_map::Entry, HashMap, HashSet},
 	fmt,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	time::Instant,
 };
 
 const LOG_TARGET: &str = "state-db";
@@ -62,11 +73,16 @@ const PRUNING_MODE: &[u8] = b"mode";
 const PRUNING_MODE_ARCHIVE: &[u8] = b"archive";
 const PRUNING_MODE_ARCHIVE_CANON: &[u8] = b"archive_canonical";
 const PRUNING_MODE_CONSTRAINED: &[u8] = b"constrained";
+const RETAINED_CHILD_TRIES: &[u8] = b"retained_child_tries";
 pub(crate) const DEFAULT_MAX_BLOCK_CONSTRAINT: u32 = 256;
+pub(crate) const DEFAULT_DEATH_ROW_CACHE_SIZE: usize = 64 * 1024 * 1024;
 
 /// Database value type.
 pub type DBValue = Vec<u8>;
 
+/// Child trie identifier, e.g. the prefixed storage key of the child trie.
+pub type ChildTrieId = Vec<u8>;
+
 /// Basic set of requirements for the Block hash and node key types.
 pub trait Hash:
 	Send
@@ -145,6 +161,15 @@ pub enum StateDbError {
 	BlockUnavailable,
 	/// Block record is missing from the pruning window
 	BlockMissing,
+	/// Non-canonical journal is missing a level below the recorded journal head.
+	/// See [`StateDb::truncate_journal_gap`] for recovery.
+	JournalGap { missing: u64, head: u64 },
+	/// Trying to canonicalize a block whose journal was not confirmed durable.
+	BlockNotDurable,
+	/// Sparse block numbers were requested along with a pruning window.
+	SparseBlockNumbersWithPruning,
+	/// Inconsistent value refcounts were found in the non-canonical overlay in strict mode.
+	Corruption(String),
 }
 
 impl<E> From<StateDbError> for Error<E> {
@@ -196,6 +221,15 @@ impl fmt::Debug for StateDbError {
 				write!(f, "Trying to get a block record from db while it is not commit to db yet")
 			},
 			Self::BlockMissing => write!(f, "Block record is missing from the pruning window"),
+			Self::JournalGap { missing, head } => write!(
+				f,
+				"Non-canonical journal is missing level #{missing}, journal head is #{head}"
+			),
+			Self::BlockNotDurable =>
+				write!(f, "Trying to canonicalize a block whose journal was not confirmed durable"),
+			Self::SparseBlockNumbersWithPruning =>
+				write!(f, "Sparse block numbers are not supported with a pruning window"),
+			Self::Corruption(e) => write!(f, "Non-canonical overlay is corrupted: {}", e),
 		}
 	}
 }
@@ -207,6 +241,9 @@ pub struct ChangeSet<H: Hash> {
 	pub inserted: Vec<(H, DBValue)>,
 	/// Deleted nodes.
 	pub deleted: Vec<H>,
+	/// Child trie tags for the keys in `inserted` and `deleted`, grouped by child trie.
+	/// Keys that are not tagged belong to the top-level trie.
+	pub child_tries: Vec<(ChildTrieId, Vec<H>)>,
 }
 
 /// A set of changes to the backing database.
@@ -218,12 +255,58 @@ pub struct CommitSet<H: Hash> {
 	pub meta: ChangeSet<Vec<u8>>,
 }
 
+/// Values inserted by a canonicalized block that are not yet copied into its commit set. The
+/// values are shared with the non-canonical overlay, so they can be materialized away from the
+/// thread that canonicalized the block.
+#[derive(Default, Debug, Clone)]
+pub struct DeferredValues<H: Hash> {
+	entries: Vec<(H, Arc<DBValue>)>,
+}
+
+impl<H: Hash> DeferredValues<H> {
+	/// Keys of the inserted values.
+	pub fn keys(&self) -> impl Iterator<Item = &H> {
+		self.entries.iter().map(|(k, _)| k)
+	}
+
+	/// Number of inserted values.
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Returns `true` if the block inserted no values.
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Copy the values into the state node changes of `commit`. This must happen before the
+	/// commit set is written to the database.
+	pub fn materialize(self, commit: &mut CommitSet<H>) {
+		commit.data.inserted.extend(
+			self.entries
+				.into_iter()
+				.map(|(k, v)| (k, Arc::try_unwrap(v).unwrap_or_else(|v| DBValue::clone(&v)))),
+		);
+	}
+}
+
 /// Pruning constraints. If none are specified pruning is
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Constraints {
 	/// Maximum blocks. Defaults to 0 when unspecified, effectively keeping only non-canonical
 	/// states.
 	pub max_blocks: Option<u32>,
+	/// Child tries that are exempt from pruning. Nodes deleted from these child tries are kept
+	/// in the database when the block leaves the pruning window.
+	///
+	/// The set is stored in the database. When reopened without any child trie, the stored set
+	/// is kept.
+	pub retained_child_tries: Vec<ChildTrieId>,
+	/// Memory budget, in bytes, for the pruning window blocks cached in memory. Blocks past the
+	/// budget are read from the database on demand. Without reference counting in the database,
+	/// the re-inserted keys of all blocks are still tracked in memory. Defaults to 64 MiB when
+	/// unspecified.
+	pub death_row_cache_size: Option<usize>,
 }
 
 /// Pruning mode.
@@ -240,7 +323,7 @@ pub enum PruningMode {
 impl PruningMode {
 	/// Create a mode that keeps given number of blocks.
 	pub fn blocks_pruning(n: u32) -> PruningMode {
-		PruningMode::Constrained(Constraints { max_blocks: Some(n) })
+		PruningMode::Constrained(Constraints { max_blocks: Some(n), ..Default::default() })
 	}
 
 	/// Is this an archive (either ArchiveAll or ArchiveCanonical) pruning mode?
@@ -270,6 +353,78 @@ impl PruningMode {
 	}
 }
 
+/// Retention policy applied by [`StateDb`].
+///
+/// [`PruningMode`] implements the built-in policies. Custom policies can be supplied with
+/// [`StateDb::open_with_strategy`].
+pub trait PruningStrategy: fmt::Debug + Send + Sync {
+	/// Pruning mode the strategy is compatible with. This is what gets stored in the database
+	/// and checked against when the database is reopened.
+	fn mode(&self) -> PruningMode;
+
+	/// Whether blocks are canonicalized. If `false`, all changes are written to the database
+	/// immediately and nothing is ever deleted.
+	fn canonicalize(&self) -> bool;
+
+	/// Maximum number of canonical blocks kept in the pruning window. `None` means there is no
+	/// pruning window and all canonical states are kept.
+	fn max_blocks(&self) -> Option<u32>;
+
+	/// Whether the state of canonical block `number`, with the SCALE encoded `hash`, is kept
+	/// when the block leaves the pruning window. The nodes of a retained state are never pruned.
+	fn retain_state(&self, _hash: &[u8], _number: u64) -> bool {
+		false
+	}
+
+	/// Whether nodes deleted from the given child trie are kept when pruning.
+	fn retain_child_trie(&self, _child_trie: &[u8]) -> bool {
+		false
+	}
+
+	/// Memory budget, in bytes, for the pruning window blocks cached in memory.
+	fn death_row_cache_size(&self) -> usize {
+		DEFAULT_DEATH_ROW_CACHE_SIZE
+	}
+
+	/// Whether blocks may be inserted with numbers that skip heights, e.g. when only some blocks
+	/// of the chain are imported. Not supported with a pruning window.
+	fn sparse_block_numbers(&self) -> bool {
+		false
+	}
+}
+
+impl PruningStrategy for PruningMode {
+	fn mode(&self) -> PruningMode {
+		self.clone()
+	}
+
+	fn canonicalize(&self) -> bool {
+		!matches!(self, PruningMode::ArchiveAll)
+	}
+
+	fn max_blocks(&self) -> Option<u32> {
+		match self {
+			PruningMode::Constrained(constraints) => Some(constraints.max_blocks.unwrap_or(0)),
+			PruningMode::ArchiveAll | PruningMode::ArchiveCanonical => None,
+		}
+	}
+
+	fn retain_child_trie(&self, child_trie: &[u8]) -> bool {
+		match self {
+			PruningMode::Constrained(constraints) =>
+				constraints.retained_child_tries.iter().any(|c| c == child_trie),
+			PruningMode::ArchiveAll | PruningMode::ArchiveCanonical => false,
+		}
+	}
+
+	fn death_row_cache_size(&self) -> usize {
+		match self {
+			PruningMode::Constrained(Constraints { death_row_cache_size: Some(size), .. }) => *size,
+			_ => DEFAULT_DEATH_ROW_CACHE_SIZE,
+		}
+	}
+}
+
 impl Default for PruningMode {
 	fn default() -> Self {
 		PruningMode::Constrained(Default::default())
@@ -278,7 +433,11 @@ impl Default for PruningMode {
 
 impl Default for Constraints {
 	fn default() -> Self {
-		Self { max_blocks: Some(DEFAULT_MAX_BLOCK_CONSTRAINT) }
+		Self {
+			max_blocks: Some(DEFAULT_MAX_BLOCK_CONSTRAINT),
+			retained_child_tries: Vec::new(),
+			death_row_cache_size: None,
+		}
 	}
 }
 
@@ -299,30 +458,148 @@ pub enum LastCanonicalized {
 	NotCanonicalizing,
 }
 
+/// Observer of the state nodes deleted by the pruning window, e.g. to stream pruned states to
+/// cold storage.
+pub trait PruneObserver<BlockHash, Key>: Send + Sync {
+	/// Called with the nodes deleted by pruning canonical block `number`, before the commit that
+	/// deletes them is handed out by the state database.
+	fn on_prune(&self, number: u64, hash: &BlockHash, nodes: Vec<(Key, DBValue)>);
+}
+
+struct PruneHook<BlockHash, Key> {
+	observer: Arc<dyn PruneObserver<BlockHash, Key>>,
+	lookup: Box<dyn Fn(&Key) -> Option<DBValue> + Send + Sync>,
+}
+
+impl<BlockHash, Key: Clone> PruneHook<BlockHash, Key> {
+	fn notify(&self, pruned: Vec<PrunedBlock<BlockHash, Key>>) {
+		for (number, hash, keys) in pruned {
+			let nodes = keys
+				.iter()
+				.filter_map(|key| (self.lookup)(key).map(|value| (key.clone(), value)))
+				.collect();
+			self.observer.on_prune(number, &hash, nodes);
+		}
+	}
+}
+
+/// Number, hash and deleted keys of a block pruned while a prune observer is set.
+type PrunedBlock<BlockHash, Key> = (u64, BlockHash, Vec<Key>);
+
+/// Read-only view of the non-canonical overlay, returned by `StateDb::read_handle`. Values read
+/// through the handle are not affected by blocks canonicalized, discarded or reverted after it was
+/// taken. Values of canonical blocks are still read from the backing database, so a block whose
+/// state may be pruned while the handle is in use has to be pinned as well.
+#[derive(Clone)]
+pub struct ReadHandle<Key: Hash> {
+	values: ValuesSnapshot<Key>,
+}
+
+impl<Key: Hash> ReadHandle<Key> {
+	/// Get a value from the overlay snapshot or the backing DB.
+	pub fn get<DB: NodeDb, Q: ?Sized>(
+		&self,
+		key: &Q,
+		db: &DB,
+	) -> Result<Option<DBValue>, Error<DB::Error>>
+	where
+		Q: AsRef<DB::Key>,
+		Key: std::borrow::Borrow<Q>,
+		Q: std::hash::Hash + Eq,
+	{
+		if let Some(value) = self.values.get(key) {
+			return Ok(Some(value))
+		}
+		db.get(key.as_ref()).map_err(Error::Db)
+	}
+}
+
+/// Bounded LRU cache of values read from the backing database.
+struct ValueCache<Key: Hash> {
+	values: Mutex<LruMap<Key, DBValue, ByLength>>,
+	/// Bumped by every invalidation, so that a value read from the database before a commit was
+	/// written isn't cached after it.
+	generation: AtomicU64,
+}
+
+impl<Key: Hash> ValueCache<Key> {
+	fn new(capacity: u32) -> Self {
+		ValueCache {
+			values: Mutex::new(LruMap::new(ByLength::new(capacity))),
+			generation: AtomicU64::new(0),
+		}
+	}
+
+	/// Drop the cached values of the keys written or deleted by `commit`.
+	fn invalidate(&self, commit: &CommitSet<Key>) {
+		self.invalidate_keys(
+			commit.data.deleted.iter().chain(commit.data.inserted.iter().map(|(k, _)| k)),
+		);
+	}
+
+	fn invalidate_keys<'a>(&self, keys: impl Iterator<Item = &'a Key>) {
+		let mut values = self.values.lock();
+		self.generation.fetch_add(1, Ordering::Relaxed);
+		for key in keys {
+			values.remove(key);
+		}
+	}
+}
+
 pub struct StateDbSync<BlockHash: Hash, Key: Hash, D: MetaDb> {
-	mode: PruningMode,
+	strategy: Arc<dyn PruningStrategy>,
 	non_canonical: NonCanonicalOverlay<BlockHash, Key>,
 	pruning: Option<RefWindow<BlockHash, Key, D>>,
 	pinned: HashMap<BlockHash, u32>,
 	ref_counting: bool,
+	metrics: Option<Metrics>,
+	/// Inserted blocks whose journal is not yet confirmed durable. `None` unless two-phase commit
+	/// is enabled.
+	pending_durable: Option<HashSet<BlockHash>>,
+	prune_hook: Option<Arc<PruneHook<BlockHash, Key>>>,
+	/// Blocks pruned since the observer was last notified. The observer reads the deleted nodes
+	/// from the database, which is done after the state database lock is released.
+	pruned: Vec<PrunedBlock<BlockHash, Key>>,
+	value_cache: Option<ValueCache<Key>>,
 }
 
 impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 	fn new(
-		mode: PruningMode,
+		strategy: Arc<dyn PruningStrategy>,
 		ref_counting: bool,
 		db: D,
 	) -> Result<StateDbSync<BlockHash, Key, D>, Error<D::Error>> {
-		trace!(target: LOG_TARGET, "StateDb settings: {:?}. Ref-counting: {}", mode, ref_counting);
-
-		let non_canonical: NonCanonicalOverlay<BlockHash, Key> = NonCanonicalOverlay::new(&db)?;
-		let pruning: Option<RefWindow<BlockHash, Key, D>> = match mode {
-			PruningMode::Constrained(Constraints { max_blocks }) =>
-				Some(RefWindow::new(db, max_blocks.unwrap_or(0), ref_counting)?),
-			PruningMode::ArchiveAll | PruningMode::ArchiveCanonical => None,
-		};
+		trace!(
+			target: LOG_TARGET,
+			"StateDb settings: {:?}. Ref-counting: {}",
+			strategy,
+			ref_counting
+		);
 
-		Ok(StateDbSync { mode, non_canonical, pruning, pinned: Default::default(), ref_counting })
+		if strategy.sparse_block_numbers() && strategy.max_blocks().is_some() {
+			return Err(StateDbError::SparseBlockNumbersWithPruning.into())
+		}
+		let non_canonical: NonCanonicalOverlay<BlockHash, Key> =
+			NonCanonicalOverlay::new(&db, strategy.sparse_block_numbers())?;
+		let pruning: Option<RefWindow<BlockHash, Key, D>> =
+			match (strategy.canonicalize(), strategy.max_blocks()) {
+				(true, Some(max_blocks)) =>
+					Some(RefWindow::new(db, max_blocks, ref_counting, strategy.clone())?),
+				_ => None,
+			};
+
+		Ok(StateDbSync {
+			strategy,
+			non_canonical,
+			pruning,
+			pinned: Default::default(),
+			ref_counting,
+			metrics: None,
+			pending_durable: None,
+			prune_hook: None,
+			pruned: Vec::new(),
+			value_cache: None,
+		})
 	}
 
 	fn insert_block(
@@ -331,43 +608,82 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 		number: u64,
 		parent_hash: &BlockHash,
 		mut changeset: ChangeSet<Key>,
+		origin: Option<Vec<u8>>,
 	) -> Result<CommitSet<Key>, Error<D::Error>> {
-		match self.mode {
-			PruningMode::ArchiveAll => {
-				changeset.deleted.clear();
-				// write changes immediately
-				Ok(CommitSet { data: changeset, meta: Default::default() })
-			},
-			PruningMode::Constrained(_) | PruningMode::ArchiveCanonical => self
+		if self.strategy.canonicalize() {
+			let start = Instant::now();
+			let result = self
 				.non_canonical
-				.insert(hash, number, parent_hash, changeset)
-				.map_err(Into::into),
+				.insert_with_origin(hash, number, parent_hash, changeset, origin)
+				.map_err(Into::into);
+			if let Some(metrics) = &self.metrics {
+				metrics.insert_block_time.observe(start.elapsed().as_secs_f64());
+			}
+			if let (Ok(_), Some(pending)) = (&result, &mut self.pending_durable) {
+				pending.insert(hash.clone());
+			}
+			result
+		} else {
+			changeset.deleted.clear();
+			// write changes immediately
+			Ok(CommitSet { data: changeset, meta: Default::default() })
 		}
 	}
 
 	fn canonicalize_block(&mut self, hash: &BlockHash) -> Result<CommitSet<Key>, Error<D::Error>> {
+		let start = Instant::now();
+		let (mut commit, values) = self.canonicalize_block_deferred(hash)?;
+		values.materialize(&mut commit);
+		if let Some(metrics) = &self.metrics {
+			metrics.canonicalize_block_time.observe(start.elapsed().as_secs_f64());
+		}
+		Ok(commit)
+	}
+
+	fn canonicalize_block_deferred(
+		&mut self,
+		hash: &BlockHash,
+	) -> Result<(CommitSet<Key>, DeferredValues<Key>), Error<D::Error>> {
 		// NOTE: it is important that the change to `LAST_CANONICAL` (emit from
 		// `non_canonical.canonicalize`) and the insert of the new pruning journal (emit from
 		// `pruning.note_canonical`) are collected into the same `CommitSet` and are committed to
 		// the database atomically to keep their consistency when restarting the node
 		let mut commit = CommitSet::default();
-		if self.mode == PruningMode::ArchiveAll {
-			return Ok(commit)
+		if !self.strategy.canonicalize() {
+			return Ok((commit, DeferredValues::default()))
 		}
-		let number = self.non_canonical.canonicalize(hash, &mut commit)?;
-		if self.mode == PruningMode::ArchiveCanonical {
-			commit.data.deleted.clear();
+		// Non-canonical ancestors are canonicalized first, one block at a time, because the
+		// pruning journal takes the deleted keys of each block from the commit set. The whole path
+		// is validated up front, so that no block of it is canonicalized if another one can't be.
+		let path = self.non_canonical.canonicalization_path(hash)?;
+		if let Some(pending) = &self.pending_durable {
+			if path.iter().any(|(hash, _)| pending.contains(hash)) {
+				return Err(StateDbError::BlockNotDurable.into())
+			}
 		}
-		if let Some(ref mut pruning) = self.pruning {
-			pruning.note_canonical(hash, number, &mut commit)?;
+		if let Some(ref pruning) = self.pruning {
+			pruning.check_canonical_numbers(path.iter().map(|(_, number)| *number))?;
 		}
+		let mut values = DeferredValues::default();
+		for (hash, _) in &path {
+			let (number, block_values) =
+				self.non_canonical.canonicalize_validated(hash, &mut commit)?;
+			if self.pruning.is_none() {
+				commit.data.deleted.clear();
+			}
+			if let Some(ref mut pruning) = self.pruning {
+				pruning.note_canonical_deferred(hash, number, &block_values, &mut commit)?;
+			}
+			values.entries.extend(block_values.entries);
+		}
+		self.retain_pending_durable();
 		self.prune(&mut commit)?;
-		Ok(commit)
+		Ok((commit, values))
 	}
 
 	/// Returns the block number of the last canonicalized block.
 	fn last_canonicalized(&self) -> LastCanonicalized {
-		if self.mode == PruningMode::ArchiveAll {
+		if !self.strategy.canonicalize() {
 			LastCanonicalized::NotCanonicalizing
 		} else {
 			self.non_canonical
@@ -378,9 +694,9 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 	}
 
 	fn is_pruned(&self, hash: &BlockHash, number: u64) -> IsPruned {
-		match self.mode {
-			PruningMode::ArchiveAll => IsPruned::NotPruned,
-			PruningMode::ArchiveCanonical | PruningMode::Constrained(_) => {
+		match self.strategy.canonicalize() {
+			false => IsPruned::NotPruned,
+			true => {
 				if self
 					.non_canonical
 					.last_canonicalized_block_number()
@@ -396,6 +712,15 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 					match self.pruning.as_ref() {
 						// We don't know for sure.
 						None => IsPruned::MaybePruned,
+						Some(pruning)
+							if number < pruning.base() &&
+								self.strategy.retain_state(&hash.encode(), number) =>
+							match pruning.retained_state(number) {
+								// Only the canonical block at `number` has its state retained.
+								Ok(Some(retained)) if retained == *hash => IsPruned::NotPruned,
+								Ok(_) => IsPruned::Pruned,
+								Err(_) => IsPruned::MaybePruned,
+							},
 						Some(pruning) => match pruning.have_block(hash, number) {
 							HaveBlock::No => IsPruned::Pruned,
 							HaveBlock::Yes => IsPruned::NotPruned,
@@ -407,12 +732,60 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 		}
 	}
 
+	fn state_availability(&self, hash: &BlockHash, number: u64) -> StateAvailability {
+		match self.is_pruned(hash, number) {
+			IsPruned::NotPruned => StateAvailability::Available,
+			IsPruned::MaybePruned => StateAvailability::Unknown,
+			IsPruned::Pruned => {
+				let range = self.available_block_numbers();
+				let reason = match range {
+					Some((_, high)) if number > high => UnavailableReason::NeverExisted,
+					Some((low, _)) if number >= low => UnavailableReason::Discarded,
+					_ => UnavailableReason::Pruned,
+				};
+				let nearest_available = range.map(|(low, high)| number.clamp(low, high));
+				StateAvailability::Unavailable { reason, nearest_available }
+			},
+		}
+	}
+
+	fn statistics(&self) -> StateDbStatistics {
+		StateDbStatistics {
+			window_base: self.pruning.as_ref().map(|pruning| pruning.base()),
+			window_size: self.pruning.as_ref().map_or(0, |pruning| pruning.window_size()),
+			window_memory: self.pruning.as_ref().map_or(0, |pruning| pruning.memory_size()),
+			non_canonical_levels: self.non_canonical.level_count(),
+			non_canonical_blocks: self.non_canonical.block_count(),
+			journaled_values: self.non_canonical.value_count(),
+			journaled_memory: self.non_canonical.value_bytes(),
+			pinned_blocks: self.pinned.len(),
+			pinned_insertions: self.non_canonical.pinned_insertion_count(),
+			journal_bytes_written: self.non_canonical.journal_bytes_written(),
+			canonicalized_bytes_written: self.non_canonical.canonicalized_bytes_written(),
+		}
+	}
+
+	/// Lowest and highest block numbers that have some state available, either canonical or in
+	/// the non-canonical overlay.
+	fn available_block_numbers(&self) -> Option<(u64, u64)> {
+		let canonical = match &self.pruning {
+			Some(pruning) => (pruning.window_size() > 0)
+				.then(|| (pruning.base(), pruning.base() + pruning.window_size() - 1)),
+			None => self.non_canonical.last_canonicalized_block_number().map(|n| (0, n)),
+		};
+		match (canonical, self.non_canonical.block_number_range()) {
+			(Some((low, _)), Some((_, high))) => Some((low, high)),
+			(canonical, non_canonical) => canonical.or(non_canonical),
+		}
+	}
+
 	fn prune(&mut self, commit: &mut CommitSet<Key>) -> Result<(), Error<D::Error>> {
-		if let (&mut Some(ref mut pruning), PruningMode::Constrained(constraints)) =
-			(&mut self.pruning, &self.mode)
+		if let (&mut Some(ref mut pruning), Some(max_blocks)) =
+			(&mut self.pruning, self.strategy.max_blocks())
 		{
+			let start = Instant::now();
 			loop {
-				if pruning.window_size() <= constraints.max_blocks.unwrap_or(0) as u64 {
+				if pruning.window_size() <= max_blocks as u64 {
 					break
 				}
 
@@ -425,13 +798,92 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 							break
 						},
 				}
-				match pruning.prune_one(commit) {
+				let pruned = self.prune_hook.is_some().then_some(&mut self.pruned);
+				match Self::prune_one(pruning, pruned, commit) {
 					// this branch should not reach as previous `next_hash` don't return error
 					// keeping it for robustness
 					Err(Error::StateDb(StateDbError::BlockUnavailable)) => break,
 					res => res?,
 				}
 			}
+			if let Some(metrics) = &self.metrics {
+				metrics.prune_time.observe(start.elapsed().as_secs_f64());
+				metrics
+					.prune_backlog
+					.set(pruning.window_size().saturating_sub(max_blocks as u64));
+			}
+		}
+		Ok(())
+	}
+
+	/// Prune the next block of the pruning window and record the deleted keys in `pruned`.
+	fn prune_one(
+		pruning: &mut RefWindow<BlockHash, Key, D>,
+		pruned: Option<&mut Vec<PrunedBlock<BlockHash, Key>>>,
+		commit: &mut CommitSet<Key>,
+	) -> Result<(), Error<D::Error>> {
+		let Some(pruned) = pruned else { return pruning.prune_one(commit) };
+		let number = pruning.base();
+		let hash = pruning.next_hash()?;
+		let first_deleted = commit.data.deleted.len();
+		pruning.prune_one(commit)?;
+		if let Some(hash) = hash {
+			pruned.push((number, hash, commit.data.deleted[first_deleted..].to_vec()));
+		}
+		Ok(())
+	}
+
+	/// Take the blocks pruned since the last call, along with the observer to notify of them.
+	fn take_pruned(
+		&mut self,
+	) -> Option<(Arc<PruneHook<BlockHash, Key>>, Vec<PrunedBlock<BlockHash, Key>>)> {
+		let prune_hook = self.prune_hook.clone()?;
+		let pruned = std::mem::take(&mut self.pruned);
+		(!pruned.is_empty()).then_some((prune_hook, pruned))
+	}
+
+	/// Build the commit pruning up to `batch_size` blocks below `number`, stopping early at a
+	/// pinned block. Returns the commit with the number of the last block it prunes and the
+	/// number of pruned blocks, or `None` if there is nothing to prune. The blocks stay in the
+	/// pruning window until `advance_pruning` is called.
+	fn prune_batch(
+		&mut self,
+		number: u64,
+		batch_size: usize,
+	) -> Result<Option<(CommitSet<Key>, u64, u64)>, Error<D::Error>> {
+		let Some(pruning) = &mut self.pruning else { return Ok(None) };
+		let base = pruning.base();
+		let mut commit = CommitSet::default();
+		let mut count = 0;
+		while count < batch_size.max(1) as u64 && base + count < number {
+			let first_deleted = commit.data.deleted.len();
+			let pinned = &self.pinned;
+			let hash = match pruning.prune_commit_at(
+				count,
+				|hash| !pinned.contains_key(hash),
+				&mut commit,
+			) {
+				Ok(Some(hash)) => hash,
+				Ok(None) | Err(Error::StateDb(StateDbError::BlockUnavailable)) => break,
+				Err(e) => return Err(e),
+			};
+			if self.prune_hook.is_some() {
+				self.pruned
+					.push((base + count, hash, commit.data.deleted[first_deleted..].to_vec()));
+			}
+			count += 1;
+		}
+		Ok((count > 0).then_some((commit, base + count - 1, count)))
+	}
+
+	/// Remove `count` blocks from the pruning window once the commit deleting `deleted` to prune
+	/// them is written.
+	fn advance_pruning(&mut self, count: u64, deleted: &[Key]) -> Result<(), Error<D::Error>> {
+		if let Some(pruning) = &mut self.pruning {
+			pruning.advance(count)?;
+		}
+		if let Some(cache) = &self.value_cache {
+			cache.invalidate_keys(deleted.iter());
 		}
 		Ok(())
 	}
@@ -440,18 +892,44 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 	/// Returns a database commit or `None` if not possible.
 	/// For archive an empty commit set is returned.
 	fn revert_one(&mut self) -> Option<CommitSet<Key>> {
-		match self.mode {
-			PruningMode::ArchiveAll => Some(CommitSet::default()),
-			PruningMode::ArchiveCanonical | PruningMode::Constrained(_) =>
-				self.non_canonical.revert_one(),
+		match self.strategy.canonicalize() {
+			false => Some(CommitSet::default()),
+			true => {
+				let commit = self.non_canonical.revert_one();
+				self.retain_pending_durable();
+				commit
+			},
 		}
 	}
 
 	fn remove(&mut self, hash: &BlockHash) -> Option<CommitSet<Key>> {
-		match self.mode {
-			PruningMode::ArchiveAll => Some(CommitSet::default()),
-			PruningMode::ArchiveCanonical | PruningMode::Constrained(_) =>
-				self.non_canonical.remove(hash),
+		match self.strategy.canonicalize() {
+			false => Some(CommitSet::default()),
+			true => {
+				let commit = self.non_canonical.remove(hash);
+				self.retain_pending_durable();
+				commit
+			},
+		}
+	}
+
+	fn set_value_cache_size(&mut self, capacity: u32) {
+		self.value_cache = (capacity > 0).then(|| ValueCache::new(capacity));
+	}
+
+	fn set_two_phase_commit(&mut self, enabled: bool) {
+		self.pending_durable = enabled.then(HashSet::new);
+	}
+
+	fn confirm_durable(&mut self, hash: &BlockHash) -> bool {
+		self.pending_durable.as_mut().map_or(false, |pending| pending.remove(hash))
+	}
+
+	/// Forget pending blocks that have left the non-canonical overlay.
+	fn retain_pending_durable(&mut self) {
+		if let Some(pending) = &mut self.pending_durable {
+			let non_canonical = &self.non_canonical;
+			pending.retain(|hash| non_canonical.have_block(hash));
 		}
 	}
 
@@ -459,9 +937,9 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 	where
 		F: Fn() -> bool,
 	{
-		match self.mode {
-			PruningMode::ArchiveAll => Ok(()),
-			PruningMode::ArchiveCanonical | PruningMode::Constrained(_) => {
+		match self.strategy.canonicalize() {
+			false => Ok(()),
+			true => {
 				let have_block = self.non_canonical.have_block(hash) ||
 					self.pruning.as_ref().map_or_else(
 						|| hint(),
@@ -514,12 +992,29 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 	where
 		Q: AsRef<DB::Key>,
 		Key: std::borrow::Borrow<Q>,
-		Q: std::hash::Hash + Eq,
+		Q: std::hash::Hash + Eq + PartialEq<Key> + ToOwned<Owned = Key>,
 	{
 		if let Some(value) = self.non_canonical.get(key) {
 			return Ok(Some(value))
 		}
-		db.get(key.as_ref()).map_err(Error::Db)
+		let Some(cache) = &self.value_cache else { return db.get(key.as_ref()).map_err(Error::Db) };
+		let generation = cache.generation.load(Ordering::Relaxed);
+		let cached = cache.values.lock().get(key).cloned();
+		if let Some(metrics) = &self.metrics {
+			let result = if cached.is_some() { "hit" } else { "miss" };
+			metrics.value_cache.with_label_values(&[result]).inc();
+		}
+		if cached.is_some() {
+			return Ok(cached)
+		}
+		let value = db.get(key.as_ref()).map_err(Error::Db)?;
+		if let Some(value) = &value {
+			let mut values = cache.values.lock();
+			if cache.generation.load(Ordering::Relaxed) == generation {
+				values.insert(key.to_owned(), value.clone());
+			}
+		}
+		Ok(value)
 	}
 }
 
@@ -527,6 +1022,8 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDbSync<BlockHash, Key, D> {
 /// Can be shared across threads.
 pub struct StateDb<BlockHash: Hash, Key: Hash, D: MetaDb> {
 	db: RwLock<StateDbSync<BlockHash, Key, D>>,
+	/// Held while the pruning window is changed outside of the `db` lock.
+	prune_lock: Mutex<()>,
 }
 
 impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
@@ -536,13 +1033,41 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 		requested_mode: Option<PruningMode>,
 		ref_counting: bool,
 		should_init: bool,
+	) -> Result<(CommitSet<Key>, StateDb<BlockHash, Key, D>), Error<D::Error>> {
+		Self::open_inner(db, requested_mode, None, ref_counting, should_init)
+	}
+
+	/// Create an instance of [`StateDb`] with a custom retention policy.
+	///
+	/// The strategy must be compatible with the pruning mode stored in the database, as reported
+	/// by [`PruningStrategy::mode`].
+	pub fn open_with_strategy(
+		db: D,
+		strategy: Arc<dyn PruningStrategy>,
+		ref_counting: bool,
+		should_init: bool,
+	) -> Result<(CommitSet<Key>, StateDb<BlockHash, Key, D>), Error<D::Error>> {
+		Self::open_inner(db, Some(strategy.mode()), Some(strategy), ref_counting, should_init)
+	}
+
+	/// `custom_strategy` replaces the built-in strategy of the selected pruning mode.
+	fn open_inner(
+		db: D,
+		requested_mode: Option<PruningMode>,
+		custom_strategy: Option<Arc<dyn PruningStrategy>>,
+		ref_counting: bool,
+		should_init: bool,
 	) -> Result<(CommitSet<Key>, StateDb<BlockHash, Key, D>), Error<D::Error>> {
 		let stored_mode = fetch_stored_pruning_mode(&db)?;
+		let stored_child_tries = stored_mode.as_ref().map(retained_child_tries).unwrap_or_default();
+		let strategy = |mode: PruningMode| {
+			custom_strategy.unwrap_or_else(|| Arc::new(mode) as Arc<dyn PruningStrategy>)
+		};
 
-		let selected_mode = match (should_init, stored_mode, requested_mode) {
+		let selected_strategy = match (should_init, stored_mode, requested_mode) {
 			(true, stored_mode, requested_mode) => {
 				assert!(stored_mode.is_none(), "The storage has just been initialized. No meta-data is expected to be found in it.");
-				requested_mode.unwrap_or_default()
+				strategy(requested_mode.unwrap_or_default())
 			},
 
 			(false, None, _) =>
@@ -551,32 +1076,53 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 				)
 				.into()),
 
-			(false, Some(stored), None) => stored,
+			(false, Some(stored), None) => Arc::new(stored),
 
-			(false, Some(stored), Some(requested)) => choose_pruning_mode(stored, requested)?,
+			(false, Some(stored), Some(requested)) =>
+				strategy(choose_pruning_mode(stored, requested)?),
 		};
 
-		let db_init_commit_set = if should_init {
-			let mut cs: CommitSet<Key> = Default::default();
-
+		let mut db_init_commit_set: CommitSet<Key> = Default::default();
+		if should_init {
 			let key = to_meta_key(PRUNING_MODE, &());
-			let value = selected_mode.id().to_owned();
+			let value = selected_strategy.mode().id().to_owned();
 
-			cs.meta.inserted.push((key, value));
+			db_init_commit_set.meta.inserted.push((key, value));
+		}
+		let child_tries = retained_child_tries(&selected_strategy.mode());
+		if child_tries != stored_child_tries {
+			let key = to_meta_key(RETAINED_CHILD_TRIES, &());
+			if child_tries.is_empty() {
+				db_init_commit_set.meta.deleted.push(key);
+			} else {
+				db_init_commit_set.meta.inserted.push((key, child_tries.encode()));
+			}
+		}
 
-			cs
-		} else {
-			Default::default()
+		let state_db = StateDb {
+			db: RwLock::new(StateDbSync::new(selected_strategy, ref_counting, db)?),
+			prune_lock: Mutex::new(()),
 		};
 
-		let state_db =
-			StateDb { db: RwLock::new(StateDbSync::new(selected_mode, ref_counting, db)?) };
-
 		Ok((db_init_commit_set, state_db))
 	}
 
+	/// Build a commit that discards the non-canonical journal records above a gap reported by
+	/// [`StateDbError::JournalGap`]. Once the commit is applied the database can be reopened;
+	/// blocks above the gap are lost and have to be imported again.
+	pub fn truncate_journal_gap(db: &D) -> Result<CommitSet<Key>, Error<D::Error>> {
+		NonCanonicalOverlay::<BlockHash, Key>::truncate_journal(db)
+	}
+
 	pub fn pruning_mode(&self) -> PruningMode {
-		self.db.read().mode.clone()
+		self.db.read().strategy.mode()
+	}
+
+	/// Register commit latency and pruning backlog metrics with the given registry. Passing `None`
+	/// leaves the state database unmetered.
+	pub fn register_metrics(&self, registry: Option<&Registry>) -> Result<(), PrometheusError> {
+		self.db.write().metrics = registry.map(Metrics::register).transpose()?;
+		Ok(())
 	}
 
 	/// Add a new non-canonical block.
@@ -587,12 +1133,61 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 		parent_hash: &BlockHash,
 		changeset: ChangeSet<Key>,
 	) -> Result<CommitSet<Key>, Error<D::Error>> {
-		self.db.write().insert_block(hash, number, parent_hash, changeset)
+		self.db.write().insert_block(hash, number, parent_hash, changeset, None)
 	}
 
-	/// Finalize a previously inserted block.
+	/// Add a new non-canonical block along with a small opaque blob describing its origin, such
+	/// as the block author or import time. The blob is journaled with the block and reported by
+	/// `non_canonical_blocks`, also after a restart. It is ignored when canonicalization is
+	/// disabled.
+	pub fn insert_block_with_origin(
+		&self,
+		hash: &BlockHash,
+		number: u64,
+		parent_hash: &BlockHash,
+		changeset: ChangeSet<Key>,
+		origin: Vec<u8>,
+	) -> Result<CommitSet<Key>, Error<D::Error>> {
+		self.db.write().insert_block(hash, number, parent_hash, changeset, Some(origin))
+	}
+
+	/// Finalize a previously inserted block. Its ancestors that are still in the non-canonical
+	/// overlay are finalized first, and all changes are collected into the same commit set.
 	pub fn canonicalize_block(&self, hash: &BlockHash) -> Result<CommitSet<Key>, Error<D::Error>> {
-		self.db.write().canonicalize_block(hash)
+		let _prune_lock = self.prune_lock.lock();
+		let (result, pruned) = {
+			let mut db = self.db.write();
+			(db.canonicalize_block(hash), db.take_pruned())
+		};
+		Self::notify_pruned(result.is_ok(), pruned);
+		result
+	}
+
+	/// Same as `canonicalize_block`, but the values inserted by the block are not copied into the
+	/// returned commit set. They have to be added with [`DeferredValues::materialize`] before the
+	/// commit set is written, which may be done on another thread.
+	pub fn canonicalize_block_deferred(
+		&self,
+		hash: &BlockHash,
+	) -> Result<(CommitSet<Key>, DeferredValues<Key>), Error<D::Error>> {
+		let _prune_lock = self.prune_lock.lock();
+		let (result, pruned) = {
+			let mut db = self.db.write();
+			(db.canonicalize_block_deferred(hash), db.take_pruned())
+		};
+		Self::notify_pruned(result.is_ok(), pruned);
+		result
+	}
+
+	/// Notify the prune observer of pruned blocks, if the commit pruning them is handed out.
+	/// Must be called without the `db` lock held.
+	fn notify_pruned(
+		handed_out: bool,
+		pruned: Option<(Arc<PruneHook<BlockHash, Key>>, Vec<PrunedBlock<BlockHash, Key>>)>,
+	) {
+		if let (true, Some((prune_hook, pruned))) = (handed_out, pruned) {
+			prune_hook.notify(pruned);
+		}
 	}
 
 	/// Prevents pruning of specified block and its descendants.
@@ -615,7 +1210,54 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 		self.db.write().sync()
 	}
 
-	/// Get a value from non-canonical/pruning overlay or the backing DB.
+	/// Set the observer notified of the state nodes deleted by the pruning window. Values of the
+	/// deleted nodes are read from `db` before the deleting commit is handed out.
+	pub fn set_prune_observer<DB>(&self, observer: Arc<dyn PruneObserver<BlockHash, Key>>, db: DB)
+	where
+		DB: NodeDb + Send + Sync + 'static,
+		Key: std::borrow::Borrow<DB::Key>,
+	{
+		let lookup = move |key: &Key| match db.get(key.borrow()) {
+			Ok(value) => value,
+			Err(e) => {
+				warn!(target: LOG_TARGET, "Failed to read pruned node {:?}: {:?}", key, e);
+				None
+			},
+		};
+		let prune_hook = PruneHook { observer, lookup: Box::new(lookup) };
+		self.db.write().prune_hook = Some(Arc::new(prune_hook));
+	}
+
+	/// Cache up to `capacity` values read through `get` from the backing database, evicting
+	/// the least recently used ones. Cached keys are invalidated by `note_written` and by the
+	/// commits written by `prune_to`. A capacity of zero disables the cache.
+	pub fn set_value_cache_size(&self, capacity: u32) {
+		self.db.write().set_value_cache_size(capacity)
+	}
+
+	/// Enable or disable two-phase commit. When enabled, a block added with `insert_block` can't
+	/// be canonicalized until the embedder has written the returned journal commit durably and
+	/// called `confirm_durable` for it. Has no effect when canonicalization is disabled.
+	pub fn set_two_phase_commit(&self, enabled: bool) {
+		self.db.write().set_two_phase_commit(enabled)
+	}
+
+	/// Enable or disable strict mode. In strict mode, inconsistent value refcounts in the
+	/// non-canonical overlay are reported with [`StateDbError::Corruption`] rather than asserted on
+	/// in debug builds only. After that, inserting and canonicalizing blocks fails and reverting
+	/// them is not possible, so the embedder should recover with `reset` or a restart.
+	pub fn set_strict_mode(&self, enabled: bool) {
+		self.db.write().non_canonical.set_strict(enabled)
+	}
+
+	/// Confirm that the journal commit of a block returned by `insert_block` is durable.
+	/// Returns `false` if the block was not awaiting confirmation.
+	pub fn confirm_durable(&self, hash: &BlockHash) -> bool {
+		self.db.write().confirm_durable(hash)
+	}
+
+	/// Get a value from non-canonical/pruning overlay or the backing DB. Values read from the
+	/// backing DB go through the value cache enabled by `set_value_cache_size`.
 	pub fn get<DB: NodeDb, Q: ?Sized>(
 		&self,
 		key: &Q,
@@ -624,11 +1266,74 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 	where
 		Q: AsRef<DB::Key>,
 		Key: std::borrow::Borrow<Q>,
-		Q: std::hash::Hash + Eq,
+		Q: std::hash::Hash + Eq + PartialEq<Key> + ToOwned<Owned = Key>,
 	{
 		self.db.read().get(key, db)
 	}
 
+	/// Drop the cached values of the keys changed by `commit`. Has to be called once a commit
+	/// returned by `insert_block` or `canonicalize_block`, or a materialized deferred commit, is
+	/// written to the database.
+	pub fn note_written(&self, commit: &CommitSet<Key>) {
+		if let Some(cache) = &self.db.read().value_cache {
+			cache.invalidate(commit);
+		}
+	}
+
+	/// Same as `note_written`, for the keys of the backing DB changed by the written commits.
+	pub fn note_written_keys<'a>(&self, keys: impl Iterator<Item = &'a Key>)
+	where
+		Key: 'a,
+	{
+		if let Some(cache) = &self.db.read().value_cache {
+			cache.invalidate_keys(keys);
+		}
+	}
+
+	/// Take a snapshot of the non-canonical overlay for reads that must not race with
+	/// canonicalization. Values the overlay discards while the handle is alive are kept around
+	/// until it is dropped.
+	pub fn read_handle(&self) -> ReadHandle<Key> {
+		ReadHandle { values: self.db.read().non_canonical.values_snapshot() }
+	}
+
+	/// Prune all canonical states older than block `number`, regardless of the pruning
+	/// constraints. Blocks are pruned in batches of up to `batch_size` blocks; each batch is passed
+	/// to `apply` along with the number of the last block it prunes, and must be committed to the
+	/// database before `apply` returns. Pruning stops early at a pinned block.
+	///
+	/// `apply` is called without the state database locked, but must not canonicalize blocks. A
+	/// batch stays in the pruning window if `apply` fails. Returns the number of pruned blocks,
+	/// which is always zero for archive modes.
+	pub fn prune_to<F>(
+		&self,
+		number: u64,
+		batch_size: usize,
+		mut apply: F,
+	) -> Result<u64, Error<D::Error>>
+	where
+		F: FnMut(CommitSet<Key>, u64) -> Result<(), D::Error>,
+	{
+		// Canonicalization is held off until the pruned blocks are written, so that it can't
+		// prune them again or re-insert the deleted nodes in the meantime.
+		let _prune_lock = self.prune_lock.lock();
+		let mut pruned = 0;
+		loop {
+			let (batch, pruned_blocks) = {
+				let mut db = self.db.write();
+				(db.prune_batch(number, batch_size), db.take_pruned())
+			};
+			let Some((commit, last, count)) = batch? else { break };
+			Self::notify_pruned(true, pruned_blocks);
+			let deleted = commit.data.deleted.clone();
+			apply(commit, last).map_err(Error::Db)?;
+			self.db.write().advance_pruning(count, &deleted)?;
+			pruned += count;
+			trace!(target: LOG_TARGET, "Pruned {} blocks up to #{}", pruned, last);
+		}
+		Ok(pruned)
+	}
+
 	/// Revert all non-canonical blocks with the best block number.
 	/// Returns a database commit or `None` if not possible.
 	/// For archive an empty commit set is returned.
@@ -652,10 +1357,46 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> StateDb<BlockHash, Key, D> {
 		self.db.read().is_pruned(hash, number)
 	}
 
+	/// Check if the state of a block is available. Unlike `is_pruned`, tells why the state is
+	/// unavailable and which block number still has state available.
+	pub fn state_availability(&self, hash: &BlockHash, number: u64) -> StateAvailability {
+		self.db.read().state_availability(hash, number)
+	}
+
+	/// Describe the blocks in the non-canonical overlay, in the order of their numbers.
+	pub fn non_canonical_blocks(&self) -> Vec<NonCanonicalBlock<BlockHash>> {
+		self.db.read().non_canonical.blocks()
+	}
+
+	/// Hashes of the non-canonical blocks that would be discarded if `hash` were canonicalized.
+	pub fn displaced_by(&self, hash: &BlockHash) -> Vec<BlockHash> {
+		self.db.read().non_canonical.displaced_by(hash)
+	}
+
+	/// Summarize the pruning window, the non-canonical overlay and pinned blocks. Walks all
+	/// journaled values to estimate their size.
+	pub fn statistics(&self) -> StateDbStatistics {
+		self.db.read().statistics()
+	}
+
 	/// Reset in-memory changes to the last disk-backed state.
 	pub fn reset(&self, db: D) -> Result<(), Error<D::Error>> {
+		let _prune_lock = self.prune_lock.lock();
 		let mut state_db = self.db.write();
-		*state_db = StateDbSync::new(state_db.mode.clone(), state_db.ref_counting, db)?;
+		let metrics = state_db.metrics.take();
+		let two_phase_commit = state_db.pending_durable.is_some();
+		let prune_hook = state_db.prune_hook.take();
+		let value_cache = state_db.value_cache.take();
+		let strict = state_db.non_canonical.is_strict();
+		if let Some(cache) = &value_cache {
+			cache.values.lock().clear();
+		}
+		*state_db = StateDbSync::new(state_db.strategy.clone(), state_db.ref_counting, db)?;
+		state_db.metrics = metrics;
+		state_db.prune_hook = prune_hook;
+		state_db.value_cache = value_cache;
+		state_db.set_two_phase_commit(two_phase_commit);
+		state_db.non_canonical.set_strict(strict);
 		Ok(())
 	}
 }
@@ -671,10 +1412,95 @@ pub enum IsPruned {
 	MaybePruned,
 }
 
+/// The result return by `StateDb::state_availability`
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateAvailability {
+	/// The state is available
+	Available,
+	/// The state may or may not be available, need further checking
+	Unknown,
+	/// The state is not available
+	Unavailable {
+		/// Why the state is not available
+		reason: UnavailableReason,
+		/// Block number closest to the requested one that still has state available, if any
+		nearest_available: Option<u64>,
+	},
+}
+
+/// Summary of the state database, returned by `StateDb::statistics`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDbStatistics {
+	/// Number of the next block to be pruned, or `None` without a pruning window
+	pub window_base: Option<u64>,
+	/// Number of canonical blocks in the pruning window
+	pub window_size: u64,
+	/// Estimated number of bytes of the pruning window blocks held in memory
+	pub window_memory: usize,
+	/// Number of block numbers in the non-canonical overlay
+	pub non_canonical_levels: usize,
+	/// Number of blocks in the non-canonical overlay
+	pub non_canonical_blocks: usize,
+	/// Number of values journaled in the non-canonical overlay
+	pub journaled_values: usize,
+	/// Estimated number of bytes of the values journaled in the non-canonical overlay
+	pub journaled_memory: usize,
+	/// Number of pinned blocks
+	pub pinned_blocks: usize,
+	/// Number of blocks that left the non-canonical overlay but are kept in memory while pinned
+	pub pinned_insertions: usize,
+	/// Number of bytes written to the journal by the blocks inserted since the database was opened
+	pub journal_bytes_written: u64,
+	/// Number of bytes of the values written again to the database when their blocks were
+	/// canonicalized, since the database was opened
+	pub canonicalized_bytes_written: u64,
+}
+
+/// A block of the non-canonical overlay, returned by `StateDb::non_canonical_blocks`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonCanonicalBlock<BlockHash> {
+	/// Block hash
+	pub hash: BlockHash,
+	/// Parent block hash
+	pub parent_hash: BlockHash,
+	/// Block number
+	pub number: u64,
+	/// Number of state nodes inserted by the block
+	pub inserted: usize,
+	/// Number of state nodes deleted by the block
+	pub deleted: usize,
+	/// Origin metadata given to `StateDb::insert_block_with_origin`
+	pub origin: Option<Vec<u8>>,
+	/// Number of bytes of the journal records of the block
+	pub journal_bytes: usize,
+}
+
+/// Why the state of a block is not available
+#[derive(Debug, PartialEq, Eq)]
+pub enum UnavailableReason {
+	/// The block is older than the pruning window
+	Pruned,
+	/// The block is not canonical, and not in the non-canonical overlay either
+	Discarded,
+	/// The block is newer than any block known to the state database
+	NeverExisted,
+}
+
 fn fetch_stored_pruning_mode<D: MetaDb>(db: &D) -> Result<Option<PruningMode>, Error<D::Error>> {
 	let meta_key_mode = to_meta_key(PRUNING_MODE, &());
 	if let Some(stored_mode) = db.get_meta(&meta_key_mode).map_err(Error::Db)? {
-		if let Some(mode) = PruningMode::from_id(&stored_mode) {
+		if let Some(mut mode) = PruningMode::from_id(&stored_mode) {
+			if let PruningMode::Constrained(constraints) = &mut mode {
+				let meta_key_child_tries = to_meta_key(RETAINED_CHILD_TRIES, &());
+				if let Some(child_tries) = db.get_meta(&meta_key_child_tries).map_err(Error::Db)? {
+					constraints.retained_child_tries = Decode::decode(&mut child_tries.as_slice())
+						.map_err(|e| {
+							StateDbError::Metadata(format!(
+								"Invalid value stored for RETAINED_CHILD_TRIES: {e}"
+							))
+						})?;
+				}
+			}
 			Ok(Some(mode))
 		} else {
 			Err(StateDbError::Metadata(format!(
@@ -696,19 +1522,36 @@ fn choose_pruning_mode(
 		(PruningMode::ArchiveAll, PruningMode::ArchiveAll) => Ok(PruningMode::ArchiveAll),
 		(PruningMode::ArchiveCanonical, PruningMode::ArchiveCanonical) =>
 			Ok(PruningMode::ArchiveCanonical),
-		(PruningMode::Constrained(_), PruningMode::Constrained(requested)) =>
-			Ok(PruningMode::Constrained(requested)),
+		(PruningMode::Constrained(stored), PruningMode::Constrained(mut requested)) => {
+			if requested.retained_child_tries.is_empty() {
+				requested.retained_child_tries = stored.retained_child_tries;
+			}
+			Ok(PruningMode::Constrained(requested))
+		},
 		(stored, requested) => Err(StateDbError::IncompatiblePruningModes { requested, stored }),
 	}
 }
 
+/// Child tries retained by the pruning window of `mode`.
+fn retained_child_tries(mode: &PruningMode) -> Vec<ChildTrieId> {
+	match mode {
+		PruningMode::Constrained(constraints) => constraints.retained_child_tries.clone(),
+		PruningMode::ArchiveAll | PruningMode::ArchiveCanonical => Vec::new(),
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::{
-		test::{make_changeset, make_db, TestDb},
-		Constraints, Error, IsPruned, PruningMode, StateDb, StateDbError,
+		retained_child_tries,
+		test::{make_changeset, make_db, ByteKeys, TestDb},
+		Constraints, DBValue, Error, IsPruned, PruneObserver, PruningMode, PruningStrategy,
+		StateAvailability, StateDb, StateDbError, StateDbStatistics, UnavailableReason,
 	};
+	use parking_lot::Mutex;
+	use prometheus_endpoint::Registry;
 	use sp_core::H256;
+	use std::sync::Arc;
 
 	fn make_test_db(settings: PruningMode) -> (TestDb, StateDb<H256, H256, TestDb>) {
 		let mut db = make_db(&[91, 921, 922, 93, 94]);
@@ -788,8 +1631,10 @@ mod tests {
 
 	#[test]
 	fn block_record_unavailable() {
-		let (mut db, state_db) =
-			make_test_db(PruningMode::Constrained(Constraints { max_blocks: Some(1) }));
+		let (mut db, state_db) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(1),
+			..Default::default()
+		}));
 		// import 2 blocks
 		for i in &[5, 6] {
 			db.commit(
@@ -821,15 +1666,131 @@ mod tests {
 		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(5), 5), IsPruned::Pruned);
 	}
 
+	#[test]
+	fn read_handle_is_isolated_from_canonicalization() {
+		let mut db = make_db(&[]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+			db.clone(),
+			Some(PruningMode::blocks_pruning(0)),
+			false,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		for (hash, key) in [(1, 1), (2, 2)] {
+			db.commit(
+				&state_db
+					.insert_block(
+						&H256::from_low_u64_be(hash),
+						1,
+						&H256::from_low_u64_be(0),
+						make_changeset(&[key], &[]),
+					)
+					.unwrap(),
+			);
+		}
+
+		let handle = state_db.read_handle();
+		db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(1)).unwrap());
+		state_db.sync();
+
+		let key = H256::from_low_u64_be(2);
+		let value = Some(key.as_bytes().to_vec());
+		assert_eq!(state_db.get(&key, &ByteKeys(db.clone())).unwrap(), None);
+		assert_eq!(handle.get(&key, &ByteKeys(db.clone())).unwrap(), value);
+		let key = H256::from_low_u64_be(1);
+		let value = Some(key.as_bytes().to_vec());
+		assert_eq!(handle.get(&key, &ByteKeys(db.clone())).unwrap(), value);
+	}
+
+	#[test]
+	fn statistics() {
+		let (_, sdb) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(1),
+			..Default::default()
+		}));
+		sdb.sync();
+		sdb.pin(&H256::from_low_u64_be(4), 4, || false).unwrap();
+		assert_eq!(
+			sdb.statistics(),
+			StateDbStatistics {
+				window_base: Some(3),
+				window_size: 1,
+				// block 3 was noted while block 21 filled the cache, see `block_record_unavailable`
+				window_memory: 0,
+				non_canonical_levels: 1,
+				non_canonical_blocks: 1,
+				journaled_values: 1,
+				journaled_memory: 32 + 32,
+				pinned_blocks: 1,
+				pinned_insertions: 0,
+				// hashes, one inserted value and one deleted key for each block, 21 deletes two
+				journal_bytes_written: 5 * (32 + 32 + 1 + 32 + 1 + 32 + 1 + 32) + 32,
+				// one value for each of blocks 1, 21 and 3
+				canonicalized_bytes_written: 3 * (32 + 32),
+			}
+		);
+
+		let (_, sdb) = make_test_db(PruningMode::ArchiveCanonical);
+		assert_eq!(sdb.statistics().window_base, None);
+		assert_eq!(sdb.statistics().pinned_insertions, 3);
+	}
+
+	#[test]
+	fn canonicalize_fast_forwards_ancestors() {
+		for mode in [
+			PruningMode::ArchiveCanonical,
+			PruningMode::blocks_pruning(0),
+			PruningMode::blocks_pruning(1),
+			PruningMode::blocks_pruning(4),
+		] {
+			let (expected_db, expected) = make_test_db(mode.clone());
+			let mut db = make_db(&[91, 921, 922, 93, 94]);
+			let (commit, state_db) = StateDb::open(db.clone(), Some(mode), false, true).unwrap();
+			db.commit(&commit);
+			for (hash, number, parent, inserted, deleted) in [
+				(1, 1, 0, 1, &[91][..]),
+				(21, 2, 1, 21, &[921, 1][..]),
+				(22, 2, 1, 22, &[922][..]),
+				(3, 3, 21, 3, &[93][..]),
+				(4, 4, 3, 4, &[94][..]),
+			] {
+				let changeset = make_changeset(&[inserted], deleted);
+				let commit = state_db
+					.insert_block(
+						&H256::from_low_u64_be(hash),
+						number,
+						&H256::from_low_u64_be(parent),
+						changeset,
+					)
+					.unwrap();
+				db.commit(&commit);
+			}
+			db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(3)).unwrap());
+			assert!(db.data_eq(&expected_db));
+			// Blocks noted in one commit stay in the cache of the pruning window.
+			assert_eq!(
+				StateDbStatistics { window_memory: 0, ..state_db.statistics() },
+				StateDbStatistics { window_memory: 0, ..expected.statistics() }
+			);
+		}
+	}
+
 	#[test]
 	fn prune_window_0() {
-		let (db, _) = make_test_db(PruningMode::Constrained(Constraints { max_blocks: Some(0) }));
+		let (db, _) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			..Default::default()
+		}));
 		assert!(db.data_eq(&make_db(&[21, 3, 922, 94])));
 	}
 
 	#[test]
 	fn prune_window_1() {
-		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints { max_blocks: Some(1) }));
+		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(1),
+			..Default::default()
+		}));
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(0), 0), IsPruned::Pruned);
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(1), 1), IsPruned::Pruned);
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(21), 2), IsPruned::Pruned);
@@ -837,9 +1798,246 @@ mod tests {
 		assert!(db.data_eq(&make_db(&[21, 3, 922, 93, 94])));
 	}
 
+	#[test]
+	fn canonicalize_deferred_matches_canonicalize() {
+		let open = || {
+			let mut db = make_db(&[91, 92]);
+			let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+				db.clone(),
+				Some(PruningMode::blocks_pruning(1)),
+				true,
+				true,
+			)
+			.unwrap();
+			db.commit(&state_db_init);
+			(db, state_db)
+		};
+		let (mut db, state_db) = open();
+		let (mut deferred_db, deferred_state_db) = open();
+
+		let blocks = [(1, make_changeset(&[1], &[91])), (2, make_changeset(&[2, 1], &[92]))];
+		for (number, changeset) in blocks {
+			let hash = H256::from_low_u64_be(number);
+			let parent_hash = H256::from_low_u64_be(number - 1);
+			db.commit(
+				&state_db.insert_block(&hash, number, &parent_hash, changeset.clone()).unwrap(),
+			);
+			deferred_db.commit(
+				&deferred_state_db.insert_block(&hash, number, &parent_hash, changeset).unwrap(),
+			);
+
+			db.commit(&state_db.canonicalize_block(&hash).unwrap());
+			let (mut commit, values) =
+				deferred_state_db.canonicalize_block_deferred(&hash).unwrap();
+			assert!(commit.data.inserted.is_empty());
+			assert_eq!(values.len(), number as usize);
+			let commit = std::thread::spawn(move || {
+				values.materialize(&mut commit);
+				commit
+			})
+			.join()
+			.unwrap();
+			deferred_db.commit(&commit);
+			assert!(db.data_eq(&deferred_db));
+		}
+		assert!(db.data_eq(&make_db(&[1, 2, 92])));
+	}
+
+	#[test]
+	fn two_phase_commit_requires_durable_journal() {
+		let mut db = make_db(&[]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+			db.clone(),
+			Some(PruningMode::blocks_pruning(0)),
+			false,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		state_db.set_two_phase_commit(true);
+
+		let (h1, h21, h22) =
+			(H256::from_low_u64_be(1), H256::from_low_u64_be(21), H256::from_low_u64_be(22));
+		let journal = state_db
+			.insert_block(&h1, 1, &H256::from_low_u64_be(0), make_changeset(&[1], &[]))
+			.unwrap();
+		assert!(journal.data.inserted.is_empty());
+		assert_eq!(
+			state_db.canonicalize_block(&h1).unwrap_err(),
+			Error::StateDb(StateDbError::BlockNotDurable)
+		);
+
+		db.commit(&journal);
+		assert!(state_db.confirm_durable(&h1));
+		assert!(!state_db.confirm_durable(&h1));
+		db.commit(&state_db.canonicalize_block(&h1).unwrap());
+		assert!(db.data_eq(&make_db(&[1])));
+
+		// discarded blocks are no longer awaiting confirmation
+		for hash in [h21, h22] {
+			db.commit(&state_db.insert_block(&hash, 2, &h1, make_changeset(&[], &[])).unwrap());
+		}
+		assert!(state_db.confirm_durable(&h21));
+		db.commit(&state_db.canonicalize_block(&h21).unwrap());
+		assert!(!state_db.confirm_durable(&h22));
+	}
+
+	#[test]
+	fn prune_to_in_batches() {
+		let (mut db, sdb) = make_test_db(PruningMode::blocks_pruning(10));
+		assert!(db.data_eq(&make_db(&[1, 21, 3, 91, 921, 922, 93, 94])));
+
+		let mut progress = Vec::new();
+		let pruned = sdb
+			.prune_to(3, 1, |commit, last| {
+				db.commit(&commit);
+				progress.push(last);
+				Ok(())
+			})
+			.unwrap();
+		assert_eq!(pruned, 2);
+		assert_eq!(progress, vec![1, 2]);
+		assert!(db.data_eq(&make_db(&[21, 3, 922, 93, 94])));
+		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(21), 2), IsPruned::Pruned);
+		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(3), 3), IsPruned::NotPruned);
+
+		assert_eq!(sdb.prune_to(3, 1, |_, _| panic!("nothing left to prune")).unwrap(), 0);
+	}
+
+	#[test]
+	fn prune_to_keeps_blocks_if_apply_fails() {
+		let (mut db, sdb) = make_test_db(PruningMode::blocks_pruning(10));
+		assert_eq!(sdb.prune_to(3, 1, |_, _| Err(())), Err(Error::Db(())));
+		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(1), 1), IsPruned::NotPruned);
+
+		let pruned = sdb
+			.prune_to(3, 1, |commit, _| {
+				db.commit(&commit);
+				Ok(())
+			})
+			.unwrap();
+		assert_eq!(pruned, 2);
+		assert!(db.data_eq(&make_db(&[21, 3, 922, 93, 94])));
+	}
+
+	#[derive(Default)]
+	struct RecordPruned(Mutex<Vec<(u64, H256, Vec<(H256, DBValue)>)>>);
+
+	impl PruneObserver<H256, H256> for RecordPruned {
+		fn on_prune(&self, number: u64, hash: &H256, mut nodes: Vec<(H256, DBValue)>) {
+			nodes.sort();
+			self.0.lock().push((number, *hash, nodes));
+		}
+	}
+
+	#[test]
+	fn prune_observer_sees_pruned_nodes() {
+		let (mut db, sdb) = make_test_db(PruningMode::blocks_pruning(10));
+		let observer = Arc::new(RecordPruned::default());
+		sdb.set_prune_observer(observer.clone(), db.clone());
+
+		sdb.prune_to(3, 10, |commit, _| {
+			db.commit(&commit);
+			Ok(())
+		})
+		.unwrap();
+		let node = |n| (H256::from_low_u64_be(n), H256::from_low_u64_be(n).as_bytes().to_vec());
+		assert_eq!(
+			*observer.0.lock(),
+			vec![
+				(1, H256::from_low_u64_be(1), vec![node(91)]),
+				(2, H256::from_low_u64_be(21), vec![node(1), node(921)]),
+			]
+		);
+	}
+
+	/// Reads the pruning window base of the state database it observes.
+	#[derive(Default)]
+	struct ReadWindowBase {
+		state_db: Mutex<Option<Arc<StateDb<H256, H256, TestDb>>>>,
+		bases: Mutex<Vec<Option<u64>>>,
+	}
+
+	impl PruneObserver<H256, H256> for ReadWindowBase {
+		fn on_prune(&self, _number: u64, _hash: &H256, _nodes: Vec<(H256, DBValue)>) {
+			let state_db = self.state_db.lock().clone().unwrap();
+			self.bases.lock().push(state_db.statistics().window_base);
+		}
+	}
+
+	#[test]
+	fn prune_observer_is_notified_without_the_lock() {
+		let (db, sdb) = make_test_db(PruningMode::blocks_pruning(10));
+		let sdb = Arc::new(sdb);
+		let observer = Arc::new(ReadWindowBase::default());
+		*observer.state_db.lock() = Some(sdb.clone());
+		sdb.set_prune_observer(observer.clone(), db.clone());
+
+		sdb.prune_to(2, 10, |_, _| Ok(())).unwrap();
+		observer.state_db.lock().take();
+		assert_eq!(*observer.bases.lock(), vec![Some(1)]);
+	}
+
+	#[test]
+	fn prune_to_stops_at_pinned_block() {
+		let (mut db, sdb) = make_test_db(PruningMode::blocks_pruning(10));
+		sdb.pin(&H256::from_low_u64_be(21), 2, || false).unwrap();
+
+		let pruned = sdb
+			.prune_to(4, 10, |commit, last| {
+				assert_eq!(last, 1);
+				db.commit(&commit);
+				Ok(())
+			})
+			.unwrap();
+		assert_eq!(pruned, 1);
+		assert!(db.data_eq(&make_db(&[1, 21, 3, 921, 922, 93, 94])));
+	}
+
+	#[test]
+	fn state_availability() {
+		let (db, sdb) = make_test_db(PruningMode::blocks_pruning(1));
+		// read the pruning window back from the db, block 3 was noted while block 21 filled the
+		// cache
+		sdb.reset(db).unwrap();
+		let unavailable = |reason, nearest_available| StateAvailability::Unavailable {
+			reason,
+			nearest_available: Some(nearest_available),
+		};
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(21), 2),
+			unavailable(UnavailableReason::Pruned, 3)
+		);
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(93), 3),
+			unavailable(UnavailableReason::Discarded, 3)
+		);
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(3), 3),
+			StateAvailability::Available
+		);
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(4), 4),
+			StateAvailability::Available
+		);
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(5), 5),
+			unavailable(UnavailableReason::NeverExisted, 4)
+		);
+
+		let (_, sdb) = make_test_db(PruningMode::ArchiveCanonical);
+		assert_eq!(
+			sdb.state_availability(&H256::from_low_u64_be(21), 2),
+			StateAvailability::Unknown
+		);
+	}
+
 	#[test]
 	fn prune_window_2() {
-		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints { max_blocks: Some(2) }));
+		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(2),
+			..Default::default()
+		}));
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(0), 0), IsPruned::Pruned);
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(1), 1), IsPruned::Pruned);
 		assert_eq!(sdb.is_pruned(&H256::from_low_u64_be(21), 2), IsPruned::NotPruned);
@@ -847,6 +2045,365 @@ mod tests {
 		assert!(db.data_eq(&make_db(&[1, 21, 3, 921, 922, 93, 94])));
 	}
 
+	#[test]
+	fn retained_child_trie_is_not_pruned() {
+		let child_trie = b"child".to_vec();
+		let mode = PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			retained_child_tries: vec![child_trie.clone()],
+			..Default::default()
+		});
+		let mut db = make_db(&[91, 92]);
+		let (state_db_init, state_db) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), Some(mode.clone()), false, true)
+				.unwrap();
+		db.commit(&state_db_init);
+		db.commit(
+			&state_db
+				.insert_block(
+					&H256::from_low_u64_be(1),
+					1,
+					&H256::from_low_u64_be(0),
+					make_changeset(&[1], &[]),
+				)
+				.unwrap(),
+		);
+		db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(1)).unwrap());
+
+		let mut changeset = make_changeset(&[2], &[91, 92]);
+		changeset.child_tries.push((child_trie, vec![H256::from_low_u64_be(91)]));
+		db.commit(
+			&state_db
+				.insert_block(&H256::from_low_u64_be(2), 2, &H256::from_low_u64_be(1), changeset)
+				.unwrap(),
+		);
+
+		// child trie tags are restored from the journal
+		let (_, state_db) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), Some(mode), false, false).unwrap();
+		db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(2)).unwrap());
+		assert!(db.data_eq(&make_db(&[1, 2, 91])));
+	}
+
+	#[test]
+	fn retained_child_tries_are_kept_on_restart() {
+		let child_trie = b"child".to_vec();
+		let mode = PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			retained_child_tries: vec![child_trie.clone()],
+			..Default::default()
+		});
+		let mut db = make_db(&[91, 92]);
+		let (state_db_init, _) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), Some(mode.clone()), false, true)
+				.unwrap();
+		db.commit(&state_db_init);
+
+		// the stored set is kept when reopened without any retained child trie
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+			db.clone(),
+			Some(PruningMode::blocks_pruning(0)),
+			false,
+			false,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		assert_eq!(state_db.pruning_mode(), mode);
+
+		db.commit(
+			&state_db
+				.insert_block(
+					&H256::from_low_u64_be(1),
+					1,
+					&H256::from_low_u64_be(0),
+					make_changeset(&[1], &[]),
+				)
+				.unwrap(),
+		);
+		db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(1)).unwrap());
+		let mut changeset = make_changeset(&[2], &[91, 92]);
+		changeset
+			.child_tries
+			.push((child_trie.clone(), vec![H256::from_low_u64_be(91)]));
+		db.commit(
+			&state_db
+				.insert_block(&H256::from_low_u64_be(2), 2, &H256::from_low_u64_be(1), changeset)
+				.unwrap(),
+		);
+		db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(2)).unwrap());
+		assert!(db.data_eq(&make_db(&[1, 2, 91])));
+
+		let (_, state_db) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), None, false, false).unwrap();
+		assert_eq!(retained_child_tries(&state_db.pruning_mode()), vec![child_trie]);
+
+		// requesting another set replaces the stored one
+		let other = PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			retained_child_tries: vec![b"other".to_vec()],
+			..Default::default()
+		});
+		let (state_db_init, _) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), Some(other), false, false).unwrap();
+		db.commit(&state_db_init);
+		let (_, state_db) =
+			StateDb::<H256, H256, TestDb>::open(db.clone(), None, false, false).unwrap();
+		assert_eq!(retained_child_tries(&state_db.pruning_mode()), vec![b"other".to_vec()]);
+	}
+
+	#[test]
+	fn reports_prune_backlog() {
+		let mut db = make_db(&[]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+			db.clone(),
+			Some(PruningMode::blocks_pruning(0)),
+			false,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		let registry = Registry::new();
+		state_db.register_metrics(Some(&registry)).unwrap();
+
+		for number in 1..=3 {
+			let hash = H256::from_low_u64_be(number);
+			db.commit(
+				&state_db
+					.insert_block(
+						&hash,
+						number,
+						&H256::from_low_u64_be(number - 1),
+						make_changeset(&[number], &[]),
+					)
+					.unwrap(),
+			);
+			if number == 1 {
+				state_db.pin(&hash, number, || false).unwrap();
+			}
+			db.commit(&state_db.canonicalize_block(&hash).unwrap());
+		}
+
+		let backlog = registry
+			.gather()
+			.into_iter()
+			.find(|family| family.get_name() == "substrate_state_db_prune_backlog")
+			.unwrap();
+		assert_eq!(backlog.get_metric()[0].get_gauge().get_value(), 3.0);
+	}
+
+	#[test]
+	fn value_cache_is_invalidated_by_commits() {
+		let mut db = make_db(&[1, 2]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open(
+			db.clone(),
+			Some(PruningMode::blocks_pruning(0)),
+			false,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		let registry = Registry::new();
+		state_db.register_metrics(Some(&registry)).unwrap();
+		state_db.set_value_cache_size(16);
+
+		let key = H256::from_low_u64_be(1);
+		let value = Some(key.as_bytes().to_vec());
+		assert_eq!(state_db.get(&key, &ByteKeys(db.clone())).unwrap(), value);
+		assert_eq!(state_db.get(&key, &ByteKeys(db.clone())).unwrap(), value);
+
+		db.commit(
+			&state_db
+				.insert_block(
+					&H256::from_low_u64_be(1),
+					1,
+					&H256::from_low_u64_be(0),
+					make_changeset(&[], &[1]),
+				)
+				.unwrap(),
+		);
+		let commit = state_db.canonicalize_block(&H256::from_low_u64_be(1)).unwrap();
+		// Until the commit is written the cached value is still the one in the database.
+		assert_eq!(state_db.get(&key, &ByteKeys(db.clone())).unwrap(), value);
+		db.commit(&commit);
+		state_db.note_written(&commit);
+		assert!(db.data_eq(&make_db(&[2])));
+		assert_eq!(state_db.get(&key, &ByteKeys(db.clone())).unwrap(), None);
+
+		let lookups = registry
+			.gather()
+			.into_iter()
+			.find(|family| family.get_name() == "substrate_state_db_value_cache")
+			.unwrap();
+		let count = |result: &str| {
+			lookups
+				.get_metric()
+				.iter()
+				.find(|metric| metric.get_label()[0].get_value() == result)
+				.map_or(0.0, |metric| metric.get_counter().get_value())
+		};
+		assert_eq!(count("hit"), 2.0);
+		assert_eq!(count("miss"), 2.0);
+	}
+
+	#[derive(Debug)]
+	struct KeepEven;
+
+	impl PruningStrategy for KeepEven {
+		fn mode(&self) -> PruningMode {
+			PruningMode::blocks_pruning(0)
+		}
+
+		fn canonicalize(&self) -> bool {
+			true
+		}
+
+		fn max_blocks(&self) -> Option<u32> {
+			Some(0)
+		}
+
+		fn retain_state(&self, _hash: &[u8], number: u64) -> bool {
+			number % 2 == 0
+		}
+	}
+
+	#[test]
+	fn custom_pruning_strategy() {
+		let mut db = make_db(&[91, 92]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open_with_strategy(
+			db.clone(),
+			Arc::new(KeepEven),
+			false,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		assert_eq!(state_db.pruning_mode(), PruningMode::blocks_pruning(0));
+
+		// Block 3 deletes the nodes of the retained state of block 2, block 4 deletes a node
+		// inserted after it.
+		for (number, inserted, deleted) in [
+			(1, vec![1], vec![91]),
+			(2, vec![2], vec![92]),
+			(3, vec![3], vec![1, 2]),
+			(4, vec![4], vec![3]),
+		] {
+			db.commit(
+				&state_db
+					.insert_block(
+						&H256::from_low_u64_be(number),
+						number,
+						&H256::from_low_u64_be(number - 1),
+						make_changeset(&inserted, &deleted),
+					)
+					.unwrap(),
+			);
+			db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(number)).unwrap());
+		}
+		assert!(db.data_eq(&make_db(&[1, 2, 4])));
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(2), 2), IsPruned::NotPruned);
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(3), 3), IsPruned::Pruned);
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(22), 2), IsPruned::Pruned);
+		assert_eq!(
+			state_db.state_availability(&H256::from_low_u64_be(2), 2),
+			StateAvailability::Available
+		);
+	}
+
+	#[test]
+	fn retained_keys_are_kept_on_restart() {
+		let mut db = make_db(&[91, 92]);
+		let (state_db_init, state_db) = StateDb::<H256, H256, TestDb>::open_with_strategy(
+			db.clone(),
+			Arc::new(KeepEven),
+			true,
+			true,
+		)
+		.unwrap();
+		db.commit(&state_db_init);
+		let commit_blocks = |db: &mut TestDb,
+		                     state_db: &StateDb<H256, H256, TestDb>,
+		                     blocks: Vec<(u64, Vec<u64>, Vec<u64>)>| {
+			for (number, inserted, deleted) in blocks {
+				db.commit(
+					&state_db
+						.insert_block(
+							&H256::from_low_u64_be(number),
+							number,
+							&H256::from_low_u64_be(number - 1),
+							make_changeset(&inserted, &deleted),
+						)
+						.unwrap(),
+				);
+				db.commit(&state_db.canonicalize_block(&H256::from_low_u64_be(number)).unwrap());
+			}
+		};
+		// Block 3 deletes a node of the retained state of block 2.
+		commit_blocks(
+			&mut db,
+			&state_db,
+			vec![(1, vec![1], vec![91]), (2, vec![2], vec![92]), (3, vec![3], vec![2])],
+		);
+
+		let (_, state_db) = StateDb::<H256, H256, TestDb>::open_with_strategy(
+			db.clone(),
+			Arc::new(KeepEven),
+			true,
+			false,
+		)
+		.unwrap();
+		// The node inserted by block 3 is pruned, the node of the retained state is kept even
+		// after being inserted and deleted again.
+		commit_blocks(
+			&mut db,
+			&state_db,
+			vec![(4, vec![4], vec![3]), (5, vec![2], vec![]), (6, vec![], vec![2])],
+		);
+		assert!(db.data_eq(&make_db(&[1, 2, 4])));
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(2), 2), IsPruned::NotPruned);
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(22), 2), IsPruned::Pruned);
+		assert_eq!(state_db.is_pruned(&H256::from_low_u64_be(4), 4), IsPruned::NotPruned);
+	}
+
+	#[derive(Debug)]
+	struct Sparse(PruningMode);
+
+	impl PruningStrategy for Sparse {
+		fn mode(&self) -> PruningMode {
+			self.0.clone()
+		}
+
+		fn canonicalize(&self) -> bool {
+			self.0.canonicalize()
+		}
+
+		fn max_blocks(&self) -> Option<u32> {
+			self.0.max_blocks()
+		}
+
+		fn sparse_block_numbers(&self) -> bool {
+			true
+		}
+	}
+
+	#[test]
+	fn sparse_block_numbers_need_archive_canonical() {
+		let db = make_db(&[]);
+		let open = |mode| {
+			StateDb::<H256, H256, TestDb>::open_with_strategy(
+				db.clone(),
+				Arc::new(Sparse(mode)),
+				false,
+				true,
+			)
+			.map(|_| ())
+		};
+		assert_eq!(
+			open(PruningMode::blocks_pruning(10)),
+			Err(Error::StateDb(StateDbError::SparseBlockNumbersWithPruning))
+		);
+		assert!(open(PruningMode::ArchiveCanonical).is_ok());
+	}
+
 	#[test]
 	fn detects_incompatible_mode() {
 		let mut db = make_db(&[]);
@@ -863,7 +2420,8 @@ mod tests {
 				)
 				.unwrap(),
 		);
-		let new_mode = PruningMode::Constrained(Constraints { max_blocks: Some(2) });
+		let new_mode =
+			PruningMode::Constrained(Constraints { max_blocks: Some(2), ..Default::default() });
 		let state_db_open_result: Result<(_, StateDb<H256, H256, TestDb>), _> =
 			StateDb::open(db.clone(), Some(new_mode), false, false);
 		assert!(state_db_open_result.is_err());
@@ -0,0 +1,79 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! State database Prometheus metrics.
+
+use prometheus_endpoint::{
+	register, CounterVec, Gauge, Histogram, HistogramOpts, Opts, PrometheusError, Registry, U64,
+};
+
+/// State database Prometheus metrics.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+	pub insert_block_time: Histogram,
+	pub canonicalize_block_time: Histogram,
+	pub prune_time: Histogram,
+	pub prune_backlog: Gauge<U64>,
+	pub value_cache: CounterVec<U64>,
+}
+
+impl Metrics {
+	pub(crate) fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+		Ok(Self {
+			insert_block_time: register(
+				Histogram::with_opts(HistogramOpts::new(
+					"substrate_state_db_insert_block_time",
+					"Time taken to insert a block into the non-canonical overlay",
+				))?,
+				registry,
+			)?,
+			canonicalize_block_time: register(
+				Histogram::with_opts(HistogramOpts::new(
+					"substrate_state_db_canonicalize_block_time",
+					"Time taken to canonicalize a block, including pruning",
+				))?,
+				registry,
+			)?,
+			prune_time: register(
+				Histogram::with_opts(HistogramOpts::new(
+					"substrate_state_db_prune_time",
+					"Time taken to prune blocks out of the pruning window",
+				))?,
+				registry,
+			)?,
+			prune_backlog: register(
+				Gauge::new(
+					"substrate_state_db_prune_backlog",
+					"Number of canonicalized blocks above the pruning constraint still awaiting \
+					pruning",
+				)?,
+				registry,
+			)?,
+			value_cache: register(
+				CounterVec::new(
+					Opts::new(
+						"substrate_state_db_value_cache",
+						"Lookups of database values in the state database value cache",
+					),
+					&["result"], // 'hit' or 'miss'
+				)?,
+				registry,
+			)?,
+		})
+	}
+}
@@ -0,0 +1,325 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Model-based tests. Random sequences of operations are applied to a `StateDb` and to a
+//! reference model of the block tree, and the state that can be read through the `StateDb` is
+//! checked against the model after every operation.
+
+use crate::{
+	test::{make_changeset, make_db, ByteKeys, TestDb},
+	Error, IsPruned, PruningMode, StateDb, StateDbError,
+};
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+use sp_core::H256;
+use std::collections::BTreeSet;
+
+/// Number of keys in the database before the first block.
+const GENESIS_KEYS: u64 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+	NonCanonical,
+	Canonical,
+	Discarded,
+	Removed,
+}
+
+struct Block {
+	hash: H256,
+	number: u64,
+	parent: Option<usize>,
+	inserted: Vec<u64>,
+	/// All keys of the block state.
+	state: BTreeSet<u64>,
+	status: Status,
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+	/// Insert a child of a live block, with a few new keys and a few keys of the parent deleted.
+	Insert { parent: u8, inserted: u8, deleted: u8 },
+	/// Canonicalize a non-canonical block, along with its non-canonical ancestors.
+	Canonicalize(u8),
+	/// Remove a non-canonical block.
+	Remove(u8),
+	/// Pin a non-canonical block.
+	Pin(u8),
+	/// Release a pin.
+	Unpin(u8),
+	/// Reopen the state database from the backing database.
+	Restart,
+}
+
+impl Arbitrary for Op {
+	fn arbitrary(g: &mut Gen) -> Self {
+		match u8::arbitrary(g) % 10 {
+			0..=3 => Op::Insert {
+				parent: u8::arbitrary(g),
+				inserted: u8::arbitrary(g) % 3,
+				deleted: u8::arbitrary(g) % 3,
+			},
+			4 | 5 => Op::Canonicalize(u8::arbitrary(g)),
+			6 => Op::Remove(u8::arbitrary(g)),
+			7 => Op::Pin(u8::arbitrary(g)),
+			8 => Op::Unpin(u8::arbitrary(g)),
+			_ => Op::Restart,
+		}
+	}
+}
+
+/// Size of the pruning window, `None` for archiving canonical blocks.
+#[derive(Clone, Debug)]
+struct Window(Option<u32>);
+
+impl Arbitrary for Window {
+	fn arbitrary(g: &mut Gen) -> Self {
+		Window(*g.choose(&[None, Some(0), Some(1), Some(2), Some(4)]).unwrap())
+	}
+}
+
+fn select(candidates: Vec<usize>, selector: u8) -> Option<usize> {
+	(!candidates.is_empty()).then(|| candidates[selector as usize % candidates.len()])
+}
+
+struct Model {
+	window: Option<u32>,
+	db: TestDb,
+	state_db: StateDb<H256, H256, TestDb>,
+	blocks: Vec<Block>,
+	last_canonical: usize,
+	pinned: Vec<usize>,
+	next_key: u64,
+}
+
+impl Model {
+	fn new(window: Option<u32>) -> Self {
+		let genesis_keys: Vec<u64> = (1..=GENESIS_KEYS).collect();
+		let mut db = make_db(&genesis_keys);
+		let (commit, state_db) =
+			StateDb::open(db.clone(), Some(Self::mode(window)), false, true).unwrap();
+		db.commit(&commit);
+		let genesis = Block {
+			hash: H256::from_low_u64_be(u64::MAX),
+			number: 0,
+			parent: None,
+			inserted: Vec::new(),
+			state: genesis_keys.into_iter().collect(),
+			status: Status::Canonical,
+		};
+		Model {
+			window,
+			db,
+			state_db,
+			blocks: vec![genesis],
+			last_canonical: 0,
+			pinned: Vec::new(),
+			next_key: GENESIS_KEYS + 1,
+		}
+	}
+
+	fn mode(window: Option<u32>) -> PruningMode {
+		window.map_or(PruningMode::ArchiveCanonical, PruningMode::blocks_pruning)
+	}
+
+	fn with_status(&self, status: Status) -> Vec<usize> {
+		(0..self.blocks.len())
+			.filter(|&index| self.blocks[index].status == status)
+			.collect()
+	}
+
+	fn descends_from(&self, mut index: usize, ancestor: usize) -> bool {
+		loop {
+			if index == ancestor {
+				return true
+			}
+			match self.blocks[index].parent {
+				Some(parent) => index = parent,
+				None => return false,
+			}
+		}
+	}
+
+	fn apply(&mut self, op: &Op) {
+		match *op {
+			Op::Insert { parent, inserted, deleted } => {
+				let mut candidates = self.with_status(Status::NonCanonical);
+				candidates.push(self.last_canonical);
+				let Some(parent_index) = select(candidates, parent) else { return };
+				let parent_block = &self.blocks[parent_index];
+				let deleted: Vec<u64> = parent_block
+					.state
+					.iter()
+					.cycle()
+					.skip(parent as usize % parent_block.state.len().max(1))
+					.take((deleted as usize).min(parent_block.state.len()))
+					.cloned()
+					.collect();
+				let inserted: Vec<u64> = (self.next_key..self.next_key + inserted as u64).collect();
+				let hash = H256::from_low_u64_be(self.blocks.len() as u64);
+				let number = parent_block.number + 1;
+				match self.state_db.insert_block(
+					&hash,
+					number,
+					&parent_block.hash,
+					make_changeset(&inserted, &deleted),
+				) {
+					Ok(commit) => self.db.commit(&commit),
+					Err(Error::StateDb(StateDbError::TooManySiblingBlocks { .. })) => return,
+					Err(e) => panic!("Failed to insert block #{}: {:?}", number, e),
+				}
+				let mut state = parent_block.state.clone();
+				deleted.iter().for_each(|key| {
+					state.remove(key);
+				});
+				state.extend(inserted.iter().cloned());
+				self.next_key += inserted.len() as u64;
+				self.blocks.push(Block {
+					hash,
+					number,
+					parent: Some(parent_index),
+					inserted,
+					state,
+					status: Status::NonCanonical,
+				});
+			},
+			Op::Canonicalize(selector) => {
+				let Some(index) = select(self.with_status(Status::NonCanonical), selector) else {
+					return
+				};
+				self.db
+					.commit(&self.state_db.canonicalize_block(&self.blocks[index].hash).unwrap());
+				self.state_db.sync();
+				let mut ancestor = index;
+				while ancestor != self.last_canonical {
+					self.blocks[ancestor].status = Status::Canonical;
+					ancestor = self.blocks[ancestor].parent.expect("genesis is canonical; qed");
+				}
+				self.last_canonical = index;
+				for other in self.with_status(Status::NonCanonical) {
+					if !self.descends_from(other, index) {
+						self.blocks[other].status = Status::Discarded;
+					}
+				}
+			},
+			Op::Remove(selector) => {
+				let Some(index) = select(self.with_status(Status::NonCanonical), selector) else {
+					return
+				};
+				let has_children = self
+					.blocks
+					.iter()
+					.any(|b| b.parent == Some(index) && b.status == Status::NonCanonical);
+				let commit = self.state_db.remove(&self.blocks[index].hash);
+				assert_eq!(commit.is_some(), !has_children);
+				if let Some(commit) = commit {
+					self.db.commit(&commit);
+					self.blocks[index].status = Status::Removed;
+				}
+			},
+			Op::Pin(selector) => {
+				let Some(index) = select(self.with_status(Status::NonCanonical), selector) else {
+					return
+				};
+				let block = &self.blocks[index];
+				self.state_db.pin(&block.hash, block.number, || false).unwrap();
+				self.pinned.push(index);
+			},
+			Op::Unpin(selector) => {
+				if self.pinned.is_empty() {
+					return
+				}
+				let index = self.pinned.swap_remove(selector as usize % self.pinned.len());
+				self.state_db.unpin(&self.blocks[index].hash);
+			},
+			Op::Restart => {
+				let (commit, state_db) =
+					StateDb::open(self.db.clone(), Some(Self::mode(self.window)), false, false)
+						.unwrap();
+				self.db.commit(&commit);
+				self.state_db = state_db;
+				self.pinned.clear();
+			},
+		}
+	}
+
+	/// Values of a discarded block are kept while it or any of its descendants is pinned.
+	fn retained(&self, index: usize) -> bool {
+		self.pinned.iter().any(|&pinned| {
+			self.blocks[pinned].status != Status::Removed && self.descends_from(pinned, index)
+		})
+	}
+
+	fn check(&self) {
+		let db = ByteKeys(self.db.clone());
+		let readable =
+			|key: u64| self.state_db.get(&H256::from_low_u64_be(key), &db).unwrap().is_some();
+		for (index, block) in self.blocks.iter().enumerate() {
+			// A pinned block that is later discarded keeps only the values of its own branch, the
+			// state of its canonical ancestors may be pruned regardless.
+			let live = block.status == Status::NonCanonical || index == self.last_canonical;
+			let kept = block.status == Status::Canonical &&
+				self.state_db.is_pruned(&block.hash, block.number) == IsPruned::NotPruned;
+			if live || kept {
+				for key in &block.state {
+					assert!(
+						readable(*key),
+						"Key {} of {:?} block #{} is missing",
+						key,
+						block.status,
+						block.number
+					);
+				}
+			}
+			match block.status {
+				Status::Discarded | Status::Removed if !self.retained(index) =>
+					for key in &block.inserted {
+						assert!(
+							!readable(*key),
+							"Key {} of {:?} block #{} leaked",
+							key,
+							block.status,
+							block.number
+						);
+					},
+				Status::Discarded if self.pinned.contains(&index) =>
+					for key in &block.inserted {
+						assert!(
+							readable(*key),
+							"Key {} of pinned block #{} is missing",
+							key,
+							block.number
+						);
+					},
+				_ => {},
+			}
+		}
+	}
+}
+
+#[test]
+fn state_db_matches_model() {
+	fn prop(window: Window, ops: Vec<Op>) {
+		let mut model = Model::new(window.0);
+		for op in &ops {
+			model.apply(op);
+			model.check();
+		}
+	}
+
+	QuickCheck::new().tests(200).quickcheck(prop as fn(Window, Vec<Op>))
+}
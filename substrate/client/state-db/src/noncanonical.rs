@@ -22,13 +22,24 @@
 
 use crate::{LOG_TARGET, LOG_TARGET_PIN};
 
-use super::{to_meta_key, ChangeSet, CommitSet, DBValue, Error, Hash, MetaDb, StateDbError};
+use super::{
+	to_meta_key, ChangeSet, ChildTrieId, CommitSet, DBValue, DeferredValues, Error, Hash, MetaDb,
+	NonCanonicalBlock, StateDbError,
+};
 use codec::{Decode, Encode};
 use log::trace;
-use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use parking_lot::RwLock;
+use std::{
+	collections::{btree_map, hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
+	sync::Arc,
+};
 
 const NON_CANONICAL_JOURNAL: &[u8] = b"noncanonical_journal";
+const NON_CANONICAL_CHILD_JOURNAL: &[u8] = b"noncanonical_journal_child";
+const NON_CANONICAL_ORIGIN_JOURNAL: &[u8] = b"noncanonical_journal_origin";
 pub(crate) const LAST_CANONICAL: &[u8] = b"last_canonical";
+const NON_CANONICAL_JOURNAL_HEAD: &[u8] = b"noncanonical_journal_head";
+const NON_CANONICAL_JOURNAL_LEVELS: &[u8] = b"noncanonical_journal_levels";
 const MAX_BLOCKS_PER_LEVEL: u64 = 32;
 
 /// See module documentation.
@@ -36,15 +47,22 @@ pub struct NonCanonicalOverlay<BlockHash: Hash, Key: Hash> {
 	last_canonicalized: Option<(BlockHash, u64)>,
 	levels: VecDeque<OverlayLevel<BlockHash, Key>>,
 	parents: HashMap<BlockHash, BlockHash>,
-	values: HashMap<Key, (u32, DBValue)>, // ref counted
+	values: OverlayValues<Key>,
 	// would be deleted but kept around because block is pinned, ref counted.
 	pinned: HashMap<BlockHash, u32>,
 	pinned_insertions: HashMap<BlockHash, (Vec<Key>, u32)>,
 	pinned_canonincalized: Vec<BlockHash>,
+	// levels may skip block numbers, their numbers are journaled separately.
+	sparse: bool,
+	// bytes written to the journal by inserted blocks.
+	journal_bytes_written: u64,
+	// bytes of values written to the database by canonicalized blocks.
+	canonicalized_bytes_written: u64,
 }
 
 #[cfg_attr(test, derive(PartialEq, Debug))]
 struct OverlayLevel<BlockHash: Hash, Key: Hash> {
+	number: u64,
 	blocks: Vec<BlockOverlay<BlockHash, Key>>,
 	used_indicies: u64, // Bitmask of available journal indicies.
 }
@@ -64,8 +82,8 @@ impl<BlockHash: Hash, Key: Hash> OverlayLevel<BlockHash, Key> {
 		self.blocks.remove(index)
 	}
 
-	fn new() -> OverlayLevel<BlockHash, Key> {
-		OverlayLevel { blocks: Vec::new(), used_indicies: 0 }
+	fn new(number: u64) -> OverlayLevel<BlockHash, Key> {
+		OverlayLevel { number, blocks: Vec::new(), used_indicies: 0 }
 	}
 }
 
@@ -81,104 +99,239 @@ fn to_journal_key(block: u64, index: u64) -> Vec<u8> {
 	to_meta_key(NON_CANONICAL_JOURNAL, &(block, index))
 }
 
+/// Child trie tags are journaled in a separate record, which is only written for blocks that
+/// have any. This keeps `JournalRecord` compatible with existing databases.
+fn to_child_journal_key(block: u64, index: u64) -> Vec<u8> {
+	to_meta_key(NON_CANONICAL_CHILD_JOURNAL, &(block, index))
+}
+
+/// Origin metadata is journaled in a separate record as well, only for blocks that have it.
+fn to_origin_journal_key(block: u64, index: u64) -> Vec<u8> {
+	to_meta_key(NON_CANONICAL_ORIGIN_JOURNAL, &(block, index))
+}
+
 #[cfg_attr(test, derive(PartialEq, Debug))]
 struct BlockOverlay<BlockHash: Hash, Key: Hash> {
 	hash: BlockHash,
 	journal_index: u64,
 	journal_key: Vec<u8>,
+	child_journal_key: Option<Vec<u8>>,
+	origin_journal_key: Option<Vec<u8>>,
 	inserted: Vec<Key>,
 	deleted: Vec<Key>,
+	child_tries: Vec<(ChildTrieId, Vec<Key>)>,
+	origin: Option<Vec<u8>>,
+	journal_bytes: usize,
 }
 
-fn insert_values<Key: Hash>(
-	values: &mut HashMap<Key, (u32, DBValue)>,
-	inserted: Vec<(Key, DBValue)>,
-) {
-	for (k, v) in inserted {
-		debug_assert!(values.get(&k).map_or(true, |(_, value)| *value == v));
-		let (ref mut counter, _) = values.entry(k).or_insert_with(|| (0, v));
-		*counter += 1;
+/// Drop the keys that are both inserted and deleted by `changeset`. The value of such a key was
+/// already present before the block, so the pair has no effect on the resulting state.
+fn coalesce_changeset<Key: Hash>(changeset: &mut ChangeSet<Key>) {
+	if changeset.inserted.is_empty() || changeset.deleted.is_empty() {
+		return
+	}
+	let deleted: HashSet<Key> = changeset.deleted.iter().cloned().collect();
+	let coalesced: HashSet<Key> = changeset
+		.inserted
+		.iter()
+		.map(|(k, _)| k)
+		.filter(|k| deleted.contains(k))
+		.cloned()
+		.collect();
+	if coalesced.is_empty() {
+		return
 	}
+	changeset.inserted.retain(|(k, _)| !coalesced.contains(k));
+	changeset.deleted.retain(|k| !coalesced.contains(k));
+	for (_, keys) in &mut changeset.child_tries {
+		keys.retain(|k| !coalesced.contains(k));
+	}
+	changeset.child_tries.retain(|(_, keys)| !keys.is_empty());
 }
 
-fn discard_values<Key: Hash>(values: &mut HashMap<Key, (u32, DBValue)>, inserted: Vec<Key>) {
-	for k in inserted {
-		match values.entry(k) {
-			Entry::Occupied(mut e) => {
-				let (ref mut counter, _) = e.get_mut();
-				*counter -= 1;
-				if *counter == 0 {
-					e.remove_entry();
-				}
-			},
-			Entry::Vacant(_) => {
-				debug_assert!(false, "Trying to discard missing value");
-			},
+/// Ref-counted values inserted by the overlay blocks, shared with the snapshots returned by
+/// `NonCanonicalOverlay::values_snapshot`.
+struct OverlayValues<Key: Hash> {
+	shared: Arc<RwLock<VersionedValues<Key>>>,
+	/// Report inconsistent refcounts as errors rather than asserting on them.
+	strict: bool,
+	/// Set when an inconsistent refcount was found in strict mode.
+	corrupted: bool,
+}
+
+impl<Key: Hash> OverlayValues<Key> {
+	fn new(map: HashMap<Key, (u32, Arc<DBValue>)>) -> OverlayValues<Key> {
+		OverlayValues {
+			shared: Arc::new(RwLock::new(VersionedValues {
+				map,
+				version: 0,
+				snapshots: Default::default(),
+				discarded: Default::default(),
+				discarded_log: Default::default(),
+			})),
+			strict: false,
+			corrupted: false,
+		}
+	}
+
+	fn insert(&mut self, inserted: Vec<(Key, DBValue)>) {
+		insert_values(&mut self.shared.write().map, inserted);
+	}
+
+	fn discard(&mut self, inserted: Vec<Key>) {
+		if !self.shared.write().discard(inserted, self.strict) && self.strict {
+			self.corrupted = true;
 		}
 	}
 }
 
-fn discard_descendants<BlockHash: Hash, Key: Hash>(
-	levels: &mut (&mut [OverlayLevel<BlockHash, Key>], &mut [OverlayLevel<BlockHash, Key>]),
-	values: &mut HashMap<Key, (u32, DBValue)>,
-	parents: &mut HashMap<BlockHash, BlockHash>,
-	pinned: &HashMap<BlockHash, u32>,
-	pinned_insertions: &mut HashMap<BlockHash, (Vec<Key>, u32)>,
-	hash: &BlockHash,
-) -> u32 {
-	let (first, mut remainder) = if let Some((first, rest)) = levels.0.split_first_mut() {
-		(Some(first), (rest, &mut *levels.1))
-	} else if let Some((first, rest)) = levels.1.split_first_mut() {
-		(Some(first), (&mut *levels.0, rest))
-	} else {
-		(None, (&mut *levels.0, &mut *levels.1))
-	};
-	let mut pinned_children = 0;
-	if let Some(level) = first {
-		while let Some(i) = level.blocks.iter().position(|overlay| {
-			parents
-				.get(&overlay.hash)
-				.expect("there is a parent entry for each entry in levels; qed") ==
-				hash
-		}) {
-			let overlay = level.remove(i);
-			let mut num_pinned = discard_descendants(
-				&mut remainder,
-				values,
-				parents,
-				pinned,
-				pinned_insertions,
-				&overlay.hash,
-			);
-			if pinned.contains_key(&overlay.hash) {
-				num_pinned += 1;
+/// Values held by the overlay. Values discarded while a snapshot is alive are kept around, tagged
+/// with the version they were discarded at, until no snapshot taken before that is left.
+struct VersionedValues<Key: Hash> {
+	map: HashMap<Key, (u32, Arc<DBValue>)>,
+	/// Version of the next snapshot.
+	version: u64,
+	/// Number of alive snapshots by version.
+	snapshots: BTreeMap<u64, usize>,
+	/// Discarded values that alive snapshots may still read.
+	discarded: HashMap<Key, (u64, Arc<DBValue>)>,
+	/// Keys of `discarded` in the order they were discarded.
+	discarded_log: VecDeque<(u64, Key)>,
+}
+
+impl<Key: Hash> VersionedValues<Key> {
+	/// Returns `false` if some of the values were missing.
+	fn discard(&mut self, inserted: Vec<Key>, strict: bool) -> bool {
+		let mut consistent = true;
+		for k in inserted {
+			match self.map.entry(k) {
+				Entry::Occupied(mut e) => {
+					let (ref mut counter, _) = e.get_mut();
+					*counter -= 1;
+					if *counter == 0 {
+						let (k, (_, value)) = e.remove_entry();
+						if !self.snapshots.is_empty() {
+							self.discarded_log.push_back((self.version, k.clone()));
+							self.discarded.insert(k, (self.version, value));
+						}
+					}
+				},
+				Entry::Vacant(_) => {
+					debug_assert!(strict, "Trying to discard missing value");
+					consistent = false;
+				},
 			}
-			if num_pinned != 0 {
-				// save to be discarded later.
-				pinned_insertions.insert(overlay.hash.clone(), (overlay.inserted, num_pinned));
-				pinned_children += num_pinned;
-			} else {
-				// discard immediately.
-				parents.remove(&overlay.hash);
-				discard_values(values, overlay.inserted);
+		}
+		consistent
+	}
+
+	fn snapshot(&mut self) -> u64 {
+		let version = self.version;
+		self.version += 1;
+		*self.snapshots.entry(version).or_default() += 1;
+		version
+	}
+
+	fn release(&mut self, version: u64) {
+		if let btree_map::Entry::Occupied(mut e) = self.snapshots.entry(version) {
+			*e.get_mut() -= 1;
+			if *e.get() == 0 {
+				e.remove();
 			}
 		}
+		// A snapshot of `version` needs the values discarded at any later version.
+		match self.snapshots.keys().next() {
+			Some(&oldest) =>
+				while self.discarded_log.front().map_or(false, |(v, _)| *v <= oldest) {
+					let (v, k) = self.discarded_log.pop_front().expect("checked above; qed");
+					if let Entry::Occupied(e) = self.discarded.entry(k) {
+						if e.get().0 == v {
+							e.remove();
+						}
+					}
+				},
+			None => {
+				self.discarded.clear();
+				self.discarded_log.clear();
+			},
+		}
+	}
+}
+
+/// Snapshot of the values of the overlay, returned by `NonCanonicalOverlay::values_snapshot`.
+pub struct ValuesSnapshot<Key: Hash> {
+	values: Arc<RwLock<VersionedValues<Key>>>,
+	version: u64,
+}
+
+impl<Key: Hash> ValuesSnapshot<Key> {
+	/// Get a value the overlay held when the snapshot was taken.
+	pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<DBValue>
+	where
+		Key: std::borrow::Borrow<Q>,
+		Q: std::hash::Hash + Eq,
+	{
+		let values = self.values.read();
+		// Values are addressed by their hash, so values inserted after the snapshot was taken
+		// can be read through it as well.
+		values
+			.map
+			.get(key)
+			.map(|(_, value)| value)
+			.or_else(|| {
+				values
+					.discarded
+					.get(key)
+					.filter(|(version, _)| *version > self.version)
+					.map(|(_, value)| value)
+			})
+			.map(|value| DBValue::clone(value))
+	}
+}
+
+impl<Key: Hash> Clone for ValuesSnapshot<Key> {
+	fn clone(&self) -> Self {
+		*self.values.write().snapshots.entry(self.version).or_default() += 1;
+		ValuesSnapshot { values: self.values.clone(), version: self.version }
+	}
+}
+
+impl<Key: Hash> Drop for ValuesSnapshot<Key> {
+	fn drop(&mut self) {
+		self.values.write().release(self.version);
+	}
+}
+
+fn insert_values<Key: Hash>(
+	values: &mut HashMap<Key, (u32, Arc<DBValue>)>,
+	inserted: Vec<(Key, DBValue)>,
+) {
+	for (k, v) in inserted {
+		debug_assert!(values.get(&k).map_or(true, |(_, value)| **value == v));
+		let (ref mut counter, _) = values.entry(k).or_insert_with(|| (0, Arc::new(v)));
+		*counter += 1;
 	}
-	pinned_children
 }
 
 impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 	/// Creates a new instance. Does not expect any metadata to be present in the DB.
-	pub fn new<D: MetaDb>(db: &D) -> Result<NonCanonicalOverlay<BlockHash, Key>, Error<D::Error>> {
+	///
+	/// With `sparse` set, blocks may be inserted with numbers that skip heights.
+	pub fn new<D: MetaDb>(
+		db: &D,
+		sparse: bool,
+	) -> Result<NonCanonicalOverlay<BlockHash, Key>, Error<D::Error>> {
 		let last_canonicalized =
 			db.get_meta(&to_meta_key(LAST_CANONICAL, &())).map_err(Error::Db)?;
 		let last_canonicalized = last_canonicalized
 			.map(|buffer| <(BlockHash, u64)>::decode(&mut buffer.as_slice()))
 			.transpose()?;
+		let journal_head = read_journal_head(db)?;
+		let journal_levels = if sparse { read_journal_levels(db)? } else { None };
 		let mut levels = VecDeque::new();
 		let mut parents = HashMap::new();
 		let mut values = HashMap::new();
-		if let Some((ref hash, mut block)) = last_canonicalized {
+		if let Some((ref hash, block)) = last_canonicalized {
 			// read the journal
 			trace!(
 				target: LOG_TARGET,
@@ -187,42 +340,38 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 				hash
 			);
 			let mut total: u64 = 0;
-			block += 1;
-			loop {
-				let mut level = OverlayLevel::new();
-				for index in 0..MAX_BLOCKS_PER_LEVEL {
-					let journal_key = to_journal_key(block, index);
-					if let Some(record) = db.get_meta(&journal_key).map_err(Error::Db)? {
-						let record: JournalRecord<BlockHash, Key> =
-							Decode::decode(&mut record.as_slice())?;
-						let inserted = record.inserted.iter().map(|(k, _)| k.clone()).collect();
-						let overlay = BlockOverlay {
-							hash: record.hash.clone(),
-							journal_index: index,
-							journal_key,
-							inserted,
-							deleted: record.deleted,
-						};
-						insert_values(&mut values, record.inserted);
-						trace!(
-							target: LOG_TARGET,
-							"Uncanonicalized journal entry {}.{} ({:?}) ({} inserted, {} deleted)",
-							block,
-							index,
-							record.hash,
-							overlay.inserted.len(),
-							overlay.deleted.len()
-						);
-						level.push(overlay);
-						parents.insert(record.hash, record.parent_hash);
-						total += 1;
+			match journal_levels {
+				Some(numbers) =>
+					for number in numbers {
+						let level = load_level(db, number, &mut parents, &mut values, &mut total)?;
+						if level.blocks.is_empty() {
+							let head = journal_head.unwrap_or(number);
+							return Err(StateDbError::JournalGap { missing: number, head }.into())
+						}
+						levels.push_back(level);
+					},
+				None => {
+					let mut block = block + 1;
+					loop {
+						let level = load_level(db, block, &mut parents, &mut values, &mut total)?;
+						if level.blocks.is_empty() {
+							// A missing level below the recorded head means the journal was only
+							// partially written. Refuse to silently drop the levels above it.
+							if let Some(head) = journal_head.filter(|head| block <= *head) {
+								log::warn!(
+									target: LOG_TARGET,
+									"Non-canonical journal gap at #{}, journal head is #{}",
+									block,
+									head,
+								);
+								return Err(StateDbError::JournalGap { missing: block, head }.into())
+							}
+							break
+						}
+						levels.push_back(level);
+						block += 1;
 					}
-				}
-				if level.blocks.is_empty() {
-					break
-				}
-				levels.push_back(level);
-				block += 1;
+				},
 			}
 			trace!(
 				target: LOG_TARGET,
@@ -236,13 +385,82 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 			parents,
 			pinned: Default::default(),
 			pinned_insertions: Default::default(),
-			values,
+			values: OverlayValues::new(values),
 			pinned_canonincalized: Default::default(),
+			sparse,
+			journal_bytes_written: 0,
+			canonicalized_bytes_written: 0,
 		})
 	}
 
+	/// Build a commit that discards all journal records following the first gap in the journal,
+	/// so that the overlay can be restored from the consistent part of it.
+	///
+	/// Returns an empty commit if there is no gap.
+	pub fn truncate_journal<D: MetaDb>(db: &D) -> Result<CommitSet<Key>, Error<D::Error>> {
+		let mut commit = CommitSet::default();
+		let last_canonicalized =
+			match db.get_meta(&to_meta_key(LAST_CANONICAL, &())).map_err(Error::Db)? {
+				Some(buffer) => <(BlockHash, u64)>::decode(&mut buffer.as_slice())?.1,
+				None => return Ok(commit),
+			};
+		let head = match read_journal_head(db)? {
+			Some(head) => head,
+			None => return Ok(commit),
+		};
+		let journal_levels = read_journal_levels(db)?;
+		let numbers = match &journal_levels {
+			Some(numbers) => numbers.clone(),
+			None => (last_canonicalized + 1..=head).collect(),
+		};
+		let mut gap = None;
+		let mut kept = Vec::new();
+		for block in numbers {
+			let mut journal_keys = Vec::new();
+			for index in 0..MAX_BLOCKS_PER_LEVEL {
+				for journal_key in [
+					to_journal_key(block, index),
+					to_child_journal_key(block, index),
+					to_origin_journal_key(block, index),
+				] {
+					if db.get_meta(&journal_key).map_err(Error::Db)?.is_some() {
+						journal_keys.push(journal_key);
+					}
+				}
+			}
+			if gap.is_some() {
+				commit.meta.deleted.extend(journal_keys);
+			} else if journal_keys.is_empty() {
+				gap = Some(block);
+			} else {
+				kept.push(block);
+			}
+		}
+		if let Some(gap) = gap {
+			trace!(
+				target: LOG_TARGET,
+				"Truncating non-canonical journal at #{}, discarding {} records",
+				gap,
+				commit.meta.deleted.len(),
+			);
+			let head = kept.last().copied().unwrap_or(last_canonicalized);
+			commit
+				.meta
+				.inserted
+				.push((to_meta_key(NON_CANONICAL_JOURNAL_HEAD, &()), head.encode()));
+			if journal_levels.is_some() {
+				commit
+					.meta
+					.inserted
+					.push((to_meta_key(NON_CANONICAL_JOURNAL_LEVELS, &()), kept.encode()));
+			}
+		}
+		Ok(commit)
+	}
+
 	/// Insert a new block into the overlay. If inserted on the second level or lover expects parent
 	/// to be present in the window.
+	#[cfg(test)]
 	pub fn insert(
 		&mut self,
 		hash: &BlockHash,
@@ -250,6 +468,33 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		parent_hash: &BlockHash,
 		changeset: ChangeSet<Key>,
 	) -> Result<CommitSet<Key>, StateDbError> {
+		self.insert_with_origin(hash, number, parent_hash, changeset, None)
+	}
+
+	/// Insert a new block into the overlay, along with an opaque blob describing where the block
+	/// came from. The blob is journaled with the block.
+	pub fn insert_with_origin(
+		&mut self,
+		hash: &BlockHash,
+		number: u64,
+		parent_hash: &BlockHash,
+		mut changeset: ChangeSet<Key>,
+		origin: Option<Vec<u8>>,
+	) -> Result<CommitSet<Key>, StateDbError> {
+		self.check_consistency()?;
+		if self.values.strict {
+			let values = self.values.shared.read();
+			let mismatch = changeset
+				.inserted
+				.iter()
+				.find(|(k, v)| values.map.get(k).map_or(false, |(_, value)| **value != *v));
+			if let Some((k, _)) = mismatch {
+				return Err(StateDbError::Corruption(format!(
+					"Block {:?} inserts a different value for key {:?}",
+					hash, k
+				)))
+			}
+		}
 		let mut commit = CommitSet::default();
 		let front_block_number = self.front_block_number();
 		if self.levels.is_empty() && self.last_canonicalized.is_none() && number > 0 {
@@ -261,8 +506,9 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 				.push((to_meta_key(LAST_CANONICAL, &()), last_canonicalized.encode()));
 			self.last_canonicalized = Some(last_canonicalized);
 		} else if self.last_canonicalized.is_some() {
-			if number < front_block_number || number > front_block_number + self.levels.len() as u64
-			{
+			let max_block_number =
+				if self.sparse { u64::MAX } else { front_block_number + self.levels.len() as u64 };
+			if number < front_block_number || number > max_block_number {
 				trace!(
 					target: LOG_TARGET,
 					"Failed to insert block {}, current is {} .. {})",
@@ -273,7 +519,17 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 				return Err(StateDbError::InvalidBlockNumber)
 			}
 			// check for valid parent if inserting on second level or higher
-			if number == front_block_number {
+			if self.sparse {
+				// the parent is either canonical or on a lower level
+				if !self.last_canonicalized.as_ref().map_or(false, |(h, _)| h == parent_hash) &&
+					self.levels
+						.iter()
+						.find(|level| level.blocks.iter().any(|b| b.hash == *parent_hash))
+						.map_or(true, |level| level.number >= number)
+				{
+					return Err(StateDbError::InvalidParent)
+				}
+			} else if number == front_block_number {
 				if !self
 					.last_canonicalized
 					.as_ref()
@@ -285,15 +541,15 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 				return Err(StateDbError::InvalidParent)
 			}
 		}
-		let level = if self.levels.is_empty() ||
-			number == front_block_number + self.levels.len() as u64
-		{
-			self.levels.push_back(OverlayLevel::new());
-			self.levels.back_mut().expect("can't be empty after insertion; qed")
-		} else {
-			self.levels.get_mut((number - front_block_number) as usize)
-				.expect("number is [front_block_number .. front_block_number + levels.len()) is asserted in precondition; qed")
+		let level_index = match self.levels.binary_search_by_key(&number, |level| level.number) {
+			Ok(index) => index,
+			Err(index) => {
+				self.levels.insert(index, OverlayLevel::new(number));
+				self.note_journal_head(&mut commit);
+				index
+			},
 		};
+		let level = &mut self.levels[level_index];
 
 		if level.blocks.len() >= MAX_BLOCKS_PER_LEVEL as usize {
 			trace!(
@@ -310,23 +566,48 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		let index = level.available_index();
 		let journal_key = to_journal_key(number, index);
 
+		coalesce_changeset(&mut changeset);
 		let inserted = changeset.inserted.iter().map(|(k, _)| k.clone()).collect();
-		let overlay = BlockOverlay {
-			hash: hash.clone(),
-			journal_index: index,
-			journal_key: journal_key.clone(),
-			inserted,
-			deleted: changeset.deleted.clone(),
+		let journal_start = commit.meta.inserted.len();
+		let child_journal_key = if changeset.child_tries.is_empty() {
+			None
+		} else {
+			let child_journal_key = to_child_journal_key(number, index);
+			commit
+				.meta
+				.inserted
+				.push((child_journal_key.clone(), changeset.child_tries.encode()));
+			Some(child_journal_key)
 		};
-		level.push(overlay);
-		self.parents.insert(hash.clone(), parent_hash.clone());
+		let origin_journal_key = origin.as_ref().map(|origin| {
+			let origin_journal_key = to_origin_journal_key(number, index);
+			commit.meta.inserted.push((origin_journal_key.clone(), origin.clone()));
+			origin_journal_key
+		});
 		let journal_record = JournalRecord {
 			hash: hash.clone(),
 			parent_hash: parent_hash.clone(),
 			inserted: changeset.inserted,
 			deleted: changeset.deleted,
 		};
-		commit.meta.inserted.push((journal_key, journal_record.encode()));
+		commit.meta.inserted.push((journal_key.clone(), journal_record.encode()));
+		let journal_bytes =
+			commit.meta.inserted[journal_start..].iter().map(|(_, v)| v.len()).sum();
+		self.journal_bytes_written += journal_bytes as u64;
+		let overlay = BlockOverlay {
+			hash: hash.clone(),
+			journal_index: index,
+			journal_key,
+			child_journal_key,
+			origin_journal_key,
+			inserted,
+			deleted: journal_record.deleted.clone(),
+			child_tries: changeset.child_tries,
+			origin,
+			journal_bytes,
+		};
+		level.push(overlay);
+		self.parents.insert(hash.clone(), parent_hash.clone());
 		trace!(
 			target: LOG_TARGET,
 			"Inserted uncanonicalized changeset {}.{} {:?} ({} inserted, {} deleted)",
@@ -336,7 +617,7 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 			journal_record.inserted.len(),
 			journal_record.deleted.len()
 		);
-		insert_values(&mut self.values, journal_record.inserted);
+		self.values.insert(journal_record.inserted);
 		Ok(commit)
 	}
 
@@ -346,7 +627,9 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		discarded_journals: &mut Vec<Vec<u8>>,
 		hash: &BlockHash,
 	) {
-		if let Some(level) = self.levels.get(level_index) {
+		// Children are on the next level, unless block numbers are sparse.
+		let levels = if self.sparse { self.levels.len() } else { 1 };
+		for (index, level) in self.levels.iter().enumerate().skip(level_index).take(levels) {
 			level.blocks.iter().for_each(|overlay| {
 				let parent = self
 					.parents
@@ -355,12 +638,31 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 					.clone();
 				if parent == *hash {
 					discarded_journals.push(overlay.journal_key.clone());
-					self.discard_journals(level_index + 1, discarded_journals, &overlay.hash);
+					discarded_journals.extend(overlay.child_journal_key.clone());
+					discarded_journals.extend(overlay.origin_journal_key.clone());
+					self.discard_journals(index + 1, discarded_journals, &overlay.hash);
 				}
 			});
 		}
 	}
 
+	/// Record the number of the topmost level in the journal, or clear it if there are no
+	/// levels left. Sparse overlays also record the numbers of all levels.
+	fn note_journal_head(&self, commit: &mut CommitSet<Key>) {
+		let key = to_meta_key(NON_CANONICAL_JOURNAL_HEAD, &());
+		match self.levels.back() {
+			Some(level) => commit.meta.inserted.push((key, level.number.encode())),
+			None => commit.meta.deleted.push(key),
+		}
+		if self.sparse {
+			let numbers: Vec<u64> = self.levels.iter().map(|level| level.number).collect();
+			commit
+				.meta
+				.inserted
+				.push((to_meta_key(NON_CANONICAL_JOURNAL_LEVELS, &()), numbers.encode()));
+		}
+	}
+
 	fn front_block_number(&self) -> u64 {
 		self.last_canonicalized.as_ref().map(|&(_, n)| n + 1).unwrap_or(0)
 	}
@@ -369,6 +671,141 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		self.last_canonicalized.as_ref().map(|&(_, n)| n)
 	}
 
+	/// Numbers of the lowest and the highest level in the overlay.
+	pub fn block_number_range(&self) -> Option<(u64, u64)> {
+		Some((self.levels.front()?.number, self.levels.back()?.number))
+	}
+
+	pub fn level_count(&self) -> usize {
+		self.levels.len()
+	}
+
+	pub fn block_count(&self) -> usize {
+		self.levels.iter().map(|level| level.blocks.len()).sum()
+	}
+
+	/// The chain of blocks of the overlay that ends with `hash`, starting with its oldest
+	/// ancestor in the overlay, along with their numbers. Canonicalizing the chain front to back
+	/// with `canonicalize_validated` canonicalizes `hash`.
+	pub fn canonicalization_path(
+		&self,
+		hash: &BlockHash,
+	) -> Result<Vec<(BlockHash, u64)>, StateDbError> {
+		self.check_consistency()?;
+		self.path_to(hash).ok_or(StateDbError::InvalidBlock)
+	}
+
+	fn path_to(&self, hash: &BlockHash) -> Option<Vec<(BlockHash, u64)>> {
+		let mut level_index = self
+			.levels
+			.iter()
+			.position(|level| level.blocks.iter().any(|overlay| overlay.hash == *hash))?;
+		let mut path = vec![(hash.clone(), self.levels[level_index].number)];
+		loop {
+			let (last, _) = path.last().expect("path is never empty; qed");
+			let parent = self
+				.parents
+				.get(last)
+				.expect("there is a parent entry for each entry in levels; qed");
+			// The parent is on the previous level, unless block numbers are sparse.
+			let lowest = if self.sparse { 0 } else { level_index.saturating_sub(1) };
+			match self
+				.levels
+				.range(lowest..level_index)
+				.rposition(|level| level.blocks.iter().any(|overlay| overlay.hash == *parent))
+			{
+				Some(index) => {
+					level_index = lowest + index;
+					path.push((parent.clone(), self.levels[level_index].number));
+				},
+				None => break,
+			}
+		}
+		// Without sparse block numbers only blocks of the front level have no parent in the
+		// overlay.
+		if !self.sparse && level_index != 0 {
+			return None
+		}
+		path.reverse();
+		Some(path)
+	}
+
+	/// Hashes of the blocks that would be discarded if `hash` were canonicalized along with its
+	/// non-canonical ancestors: all blocks that are neither ancestors nor descendants of `hash`, in
+	/// the order of their numbers. Returns an empty list if `hash` is not in the overlay.
+	pub fn displaced_by(&self, hash: &BlockHash) -> Vec<BlockHash> {
+		let path = match self.path_to(hash) {
+			Some(path) => path,
+			None => return Vec::new(),
+		};
+		let ancestors: HashSet<&BlockHash> = path.iter().map(|(hash, _)| hash).collect();
+		let mut descendants = HashSet::from([hash]);
+		let mut displaced = Vec::new();
+		for level in &self.levels {
+			for overlay in &level.blocks {
+				let parent = self
+					.parents
+					.get(&overlay.hash)
+					.expect("there is a parent entry for each entry in levels; qed");
+				if descendants.contains(parent) {
+					descendants.insert(&overlay.hash);
+				} else if !ancestors.contains(&overlay.hash) {
+					displaced.push(overlay.hash.clone());
+				}
+			}
+		}
+		displaced
+	}
+
+	/// Describe the blocks in the overlay, in the order of their numbers.
+	pub fn blocks(&self) -> Vec<NonCanonicalBlock<BlockHash>> {
+		self.levels
+			.iter()
+			.flat_map(|level| {
+				level.blocks.iter().map(move |overlay| NonCanonicalBlock {
+					hash: overlay.hash.clone(),
+					parent_hash: self
+						.parents
+						.get(&overlay.hash)
+						.expect("there is a parent entry for each entry in levels; qed")
+						.clone(),
+					number: level.number,
+					inserted: overlay.inserted.len(),
+					deleted: overlay.deleted.len(),
+					origin: overlay.origin.clone(),
+					journal_bytes: overlay.journal_bytes,
+				})
+			})
+			.collect()
+	}
+
+	/// Number of values held by the overlay, including values kept for pinned blocks.
+	pub fn value_count(&self) -> usize {
+		self.values.shared.read().map.len()
+	}
+
+	/// Estimated number of bytes of the values held by the overlay.
+	pub fn value_bytes(&self) -> usize {
+		self.values.shared.read().map.iter().map(|(k, (_, v))| k.encoded_size() + v.len()).sum()
+	}
+
+	/// Number of bytes written to the journal by the blocks inserted since the overlay was
+	/// loaded.
+	pub fn journal_bytes_written(&self) -> u64 {
+		self.journal_bytes_written
+	}
+
+	/// Number of bytes of the values written to the database by the blocks canonicalized since
+	/// the overlay was loaded. These values were already written once to the journal.
+	pub fn canonicalized_bytes_written(&self) -> u64 {
+		self.canonicalized_bytes_written
+	}
+
+	/// Number of canonicalized or discarded blocks whose values are kept because they are pinned.
+	pub fn pinned_insertion_count(&self) -> usize {
+		self.pinned_insertions.len()
+	}
+
 	/// Confirm that all changes made to commit sets are on disk. Allows for temporarily pinned
 	/// blocks to be released.
 	pub fn sync(&mut self) {
@@ -384,12 +821,90 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 	/// Select a top-level root and canonicalized it. Discards all sibling subtrees and the root.
 	/// Add a set of changes of the canonicalized block to `CommitSet`
 	/// Return the block number of the canonicalized block
+	#[cfg(test)]
 	pub fn canonicalize(
 		&mut self,
 		hash: &BlockHash,
 		commit: &mut CommitSet<Key>,
 	) -> Result<u64, StateDbError> {
+		let (number, values) = self.canonicalize_deferred(hash, commit)?;
+		values.materialize(commit);
+		Ok(number)
+	}
+
+	/// Select a top-level root and canonicalize it. Discards all sibling subtrees and the root.
+	/// Add the deleted keys and journal changes of the canonicalized block to `CommitSet`.
+	/// Return the block number of the canonicalized block and the values it inserted.
+	#[cfg(test)]
+	pub fn canonicalize_deferred(
+		&mut self,
+		hash: &BlockHash,
+		commit: &mut CommitSet<Key>,
+	) -> Result<(u64, DeferredValues<Key>), StateDbError> {
+		self.check_consistency()?;
+		self.canonicalize_validated(hash, commit)
+	}
+
+	/// Same as `canonicalize_deferred`, for the blocks of a path returned by
+	/// `canonicalization_path`. Corruption found while canonicalizing the earlier blocks of the
+	/// path doesn't stop it, it is reported by the next change to the overlay.
+	pub fn canonicalize_validated(
+		&mut self,
+		hash: &BlockHash,
+		commit: &mut CommitSet<Key>,
+	) -> Result<(u64, DeferredValues<Key>), StateDbError> {
 		trace!(target: LOG_TARGET, "Canonicalizing {:?}", hash);
+		// With sparse block numbers the root may be above the front level. All blocks below it
+		// are on other branches.
+		let discarded_levels = if self.sparse {
+			let level_index = self
+				.levels
+				.iter()
+				.position(|level| level.blocks.iter().any(|overlay| overlay.hash == *hash))
+				.ok_or(StateDbError::InvalidBlock)?;
+			let parent = self
+				.parents
+				.get(hash)
+				.expect("there is a parent entry for each entry in levels; qed");
+			if self
+				.levels
+				.iter()
+				.take(level_index)
+				.any(|level| level.blocks.iter().any(|overlay| overlay.hash == *parent))
+			{
+				return Err(StateDbError::InvalidBlock)
+			}
+			level_index
+		} else {
+			0
+		};
+		// The values of the block are collected before the overlay is changed.
+		let overlay = self
+			.levels
+			.get(discarded_levels)
+			.and_then(|level| level.blocks.iter().find(|overlay| overlay.hash == *hash))
+			.ok_or(StateDbError::InvalidBlock)?;
+		let mut values = DeferredValues::default();
+		{
+			let shared = self.values.shared.read();
+			for k in &overlay.inserted {
+				let Some((_, value)) = shared.map.get(k) else {
+					return Err(StateDbError::Corruption(format!(
+						"Block {:?} inserts key {:?} without a value",
+						hash, k
+					)))
+				};
+				values.entries.push((k.clone(), value.clone()));
+			}
+		}
+		let mut discarded_journals = Vec::new();
+		for _ in 0..discarded_levels {
+			let level = self.levels.pop_front().expect("level_index is within levels; qed");
+			for overlay in level.blocks {
+				self.discard_front_overlay(overlay, &mut discarded_journals);
+			}
+		}
+
 		let level = match self.levels.pop_front() {
 			Some(level) => level,
 			None => return Err(StateDbError::InvalidBlock),
@@ -406,58 +921,108 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		self.pin(hash);
 		self.pinned_canonincalized.push(hash.clone());
 
-		let mut discarded_journals = Vec::new();
+		let number = level.number;
 		for (i, overlay) in level.blocks.into_iter().enumerate() {
-			let mut pinned_children = 0;
 			// That's the one we need to canonicalize
 			if i == index {
-				commit.data.inserted.extend(overlay.inserted.iter().map(|k| {
-					(
-						k.clone(),
-						self.values
-							.get(k)
-							.expect("For each key in overlays there's a value in values")
-							.1
-							.clone(),
-					)
-				}));
+				self.canonicalized_bytes_written += values
+					.entries
+					.iter()
+					.map(|(k, v)| (k.encoded_size() + v.len()) as u64)
+					.sum::<u64>();
 				commit.data.deleted.extend(overlay.deleted.clone());
+				commit.data.child_tries.extend(overlay.child_tries.clone());
+				self.release_front_overlay(overlay, 0, &mut discarded_journals);
 			} else {
 				// Discard this overlay
-				self.discard_journals(0, &mut discarded_journals, &overlay.hash);
-				pinned_children = discard_descendants(
-					&mut self.levels.as_mut_slices(),
-					&mut self.values,
-					&mut self.parents,
-					&self.pinned,
-					&mut self.pinned_insertions,
-					&overlay.hash,
-				);
-			}
-			if self.pinned.contains_key(&overlay.hash) {
-				pinned_children += 1;
-			}
-			if pinned_children != 0 {
-				self.pinned_insertions
-					.insert(overlay.hash.clone(), (overlay.inserted, pinned_children));
-			} else {
-				self.parents.remove(&overlay.hash);
-				discard_values(&mut self.values, overlay.inserted);
+				self.discard_front_overlay(overlay, &mut discarded_journals);
 			}
-			discarded_journals.push(overlay.journal_key.clone());
 		}
 		commit.meta.deleted.append(&mut discarded_journals);
+		// Discarded branches may leave levels without blocks, which the journal head must not
+		// point at.
+		let level_count = self.levels.len();
+		self.levels.retain(|level| !level.blocks.is_empty());
 
-		let canonicalized = (hash.clone(), self.front_block_number());
+		let canonicalized = (hash.clone(), number);
 		commit
 			.meta
 			.inserted
 			.push((to_meta_key(LAST_CANONICAL, &()), canonicalized.encode()));
 		trace!(target: LOG_TARGET, "Discarding {} records", commit.meta.deleted.len());
 
-		let num = canonicalized.1;
 		self.last_canonicalized = Some(canonicalized);
-		Ok(num)
+		if self.sparse || self.levels.len() != level_count {
+			self.note_journal_head(commit);
+		}
+		Ok((number, values))
+	}
+
+	/// Discard a block taken off the front level along with its descendants.
+	fn discard_front_overlay(
+		&mut self,
+		overlay: BlockOverlay<BlockHash, Key>,
+		discarded_journals: &mut Vec<Vec<u8>>,
+	) {
+		self.discard_journals(0, discarded_journals, &overlay.hash);
+		let pinned_children = self.discard_descendants(0, &overlay.hash);
+		self.release_front_overlay(overlay, pinned_children, discarded_journals);
+	}
+
+	/// Discard the descendants of `hash` from the levels starting at `from`. Returns the number
+	/// of pinned blocks among them, whose values are kept until they are unpinned.
+	fn discard_descendants(&mut self, from: usize, hash: &BlockHash) -> u32 {
+		let mut pinned_children = 0;
+		// Children are on the next level, unless block numbers are sparse.
+		let end = if self.sparse { self.levels.len() } else { self.levels.len().min(from + 1) };
+		for level_index in from..end {
+			while let Some(i) = self.levels[level_index].blocks.iter().position(|overlay| {
+				self.parents
+					.get(&overlay.hash)
+					.expect("there is a parent entry for each entry in levels; qed") ==
+					hash
+			}) {
+				let overlay = self.levels[level_index].remove(i);
+				let mut num_pinned = self.discard_descendants(level_index + 1, &overlay.hash);
+				if self.pinned.contains_key(&overlay.hash) {
+					num_pinned += 1;
+				}
+				if num_pinned != 0 {
+					// save to be discarded later.
+					self.pinned_insertions
+						.insert(overlay.hash.clone(), (overlay.inserted, num_pinned));
+					pinned_children += num_pinned;
+				} else {
+					// discard immediately.
+					self.parents.remove(&overlay.hash);
+					self.values.discard(overlay.inserted);
+				}
+			}
+		}
+		pinned_children
+	}
+
+	/// Release a block taken off the front level, keeping its values around while it or any of
+	/// its discarded descendants is pinned.
+	fn release_front_overlay(
+		&mut self,
+		overlay: BlockOverlay<BlockHash, Key>,
+		mut pinned_children: u32,
+		discarded_journals: &mut Vec<Vec<u8>>,
+	) {
+		if self.pinned.contains_key(&overlay.hash) {
+			pinned_children += 1;
+		}
+		if pinned_children != 0 {
+			self.pinned_insertions
+				.insert(overlay.hash.clone(), (overlay.inserted, pinned_children));
+		} else {
+			self.parents.remove(&overlay.hash);
+			self.values.discard(overlay.inserted);
+		}
+		discarded_journals.push(overlay.journal_key);
+		discarded_journals.extend(overlay.child_journal_key);
+		discarded_journals.extend(overlay.origin_journal_key);
 	}
 
 	/// Get a value from the node overlay. This searches in every existing changeset.
@@ -466,7 +1031,34 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		Key: std::borrow::Borrow<Q>,
 		Q: std::hash::Hash + Eq,
 	{
-		self.values.get(key).map(|v| v.1.clone())
+		self.values.shared.read().map.get(key).map(|v| DBValue::clone(&v.1))
+	}
+
+	/// Report inconsistent value refcounts with `StateDbError::Corruption` instead of asserting on
+	/// them in debug builds. Once corruption is found, the overlay refuses further changes.
+	pub fn set_strict(&mut self, strict: bool) {
+		self.values.strict = strict;
+		if !strict {
+			self.values.corrupted = false;
+		}
+	}
+
+	pub fn is_strict(&self) -> bool {
+		self.values.strict
+	}
+
+	fn check_consistency(&self) -> Result<(), StateDbError> {
+		if self.values.strict && self.values.corrupted {
+			return Err(StateDbError::Corruption("Discarded a value that is missing".into()))
+		}
+		Ok(())
+	}
+
+	/// Snapshot of the values held by the overlay. Values discarded by later changes to the overlay
+	/// can still be read through it.
+	pub fn values_snapshot(&self) -> ValuesSnapshot<Key> {
+		let version = self.values.shared.write().snapshot();
+		ValuesSnapshot { values: self.values.shared.clone(), version }
 	}
 
 	/// Check if the block is in the canonicalization queue.
@@ -477,13 +1069,17 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 	/// Revert a single level. Returns commit set that deletes the journal or `None` if not
 	/// possible.
 	pub fn revert_one(&mut self) -> Option<CommitSet<Key>> {
+		self.check_consistency().ok()?;
 		self.levels.pop_back().map(|level| {
 			let mut commit = CommitSet::default();
 			for overlay in level.blocks.into_iter() {
 				commit.meta.deleted.push(overlay.journal_key);
+				commit.meta.deleted.extend(overlay.child_journal_key);
+				commit.meta.deleted.extend(overlay.origin_journal_key);
 				self.parents.remove(&overlay.hash);
-				discard_values(&mut self.values, overlay.inserted);
+				self.values.discard(overlay.inserted);
 			}
+			self.note_journal_head(&mut commit);
 			commit
 		})
 	}
@@ -491,6 +1087,7 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 	/// Revert a single block. Returns commit set that deletes the journal or `None` if not
 	/// possible.
 	pub fn remove(&mut self, hash: &BlockHash) -> Option<CommitSet<Key>> {
+		self.check_consistency().ok()?;
 		let mut commit = CommitSet::default();
 		let level_count = self.levels.len();
 		for (level_index, level) in self.levels.iter_mut().enumerate().rev() {
@@ -505,13 +1102,17 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 			}
 			let overlay = level.remove(index);
 			commit.meta.deleted.push(overlay.journal_key);
+			commit.meta.deleted.extend(overlay.child_journal_key);
+			commit.meta.deleted.extend(overlay.origin_journal_key);
 			self.parents.remove(&overlay.hash);
-			discard_values(&mut self.values, overlay.inserted);
+			self.values.discard(overlay.inserted);
+			// Only the last level may be left empty, unless block numbers are sparse.
+			if level.blocks.is_empty() {
+				self.levels.remove(level_index);
+				self.note_journal_head(&mut commit);
+			}
 			break
 		}
-		if self.levels.back().map_or(false, |l| l.blocks.is_empty()) {
-			self.levels.pop_back();
-		}
 		if !commit.meta.deleted.is_empty() {
 			Some(commit)
 		} else {
@@ -557,7 +1158,7 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 								"Discarding unpinned non-canon block: {:?}",
 								hash
 							);
-							discard_values(&mut self.values, inserted);
+							self.values.discard(inserted);
 							self.parents.remove(&hash);
 						}
 					},
@@ -568,12 +1169,85 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 	}
 }
 
+/// Read all journal records of the level at block `number`.
+fn load_level<BlockHash: Hash, Key: Hash, D: MetaDb>(
+	db: &D,
+	number: u64,
+	parents: &mut HashMap<BlockHash, BlockHash>,
+	values: &mut HashMap<Key, (u32, Arc<DBValue>)>,
+	total: &mut u64,
+) -> Result<OverlayLevel<BlockHash, Key>, Error<D::Error>> {
+	let mut level = OverlayLevel::new(number);
+	for index in 0..MAX_BLOCKS_PER_LEVEL {
+		let journal_key = to_journal_key(number, index);
+		if let Some(record) = db.get_meta(&journal_key).map_err(Error::Db)? {
+			let mut journal_bytes = record.len();
+			let record: JournalRecord<BlockHash, Key> = Decode::decode(&mut record.as_slice())?;
+			let inserted = record.inserted.iter().map(|(k, _)| k.clone()).collect();
+			let child_journal_key = to_child_journal_key(number, index);
+			let child_tries: Vec<(ChildTrieId, Vec<Key>)> =
+				match db.get_meta(&child_journal_key).map_err(Error::Db)? {
+					Some(record) => {
+						journal_bytes += record.len();
+						Decode::decode(&mut record.as_slice())?
+					},
+					None => Vec::new(),
+				};
+			let origin_journal_key = to_origin_journal_key(number, index);
+			let origin = db.get_meta(&origin_journal_key).map_err(Error::Db)?;
+			journal_bytes += origin.as_ref().map_or(0, |origin| origin.len());
+			let overlay = BlockOverlay {
+				hash: record.hash.clone(),
+				journal_index: index,
+				journal_key,
+				child_journal_key: (!child_tries.is_empty()).then_some(child_journal_key),
+				origin_journal_key: origin.is_some().then_some(origin_journal_key),
+				inserted,
+				deleted: record.deleted,
+				child_tries,
+				origin,
+				journal_bytes,
+			};
+			insert_values(values, record.inserted);
+			trace!(
+				target: LOG_TARGET,
+				"Uncanonicalized journal entry {}.{} ({:?}) ({} inserted, {} deleted)",
+				number,
+				index,
+				record.hash,
+				overlay.inserted.len(),
+				overlay.deleted.len()
+			);
+			level.push(overlay);
+			parents.insert(record.hash, record.parent_hash);
+			*total += 1;
+		}
+	}
+	Ok(level)
+}
+
+fn read_journal_levels<D: MetaDb>(db: &D) -> Result<Option<Vec<u64>>, Error<D::Error>> {
+	db.get_meta(&to_meta_key(NON_CANONICAL_JOURNAL_LEVELS, &()))
+		.map_err(Error::Db)?
+		.map(|buffer| Vec::<u64>::decode(&mut buffer.as_slice()))
+		.transpose()
+		.map_err(Into::into)
+}
+
+fn read_journal_head<D: MetaDb>(db: &D) -> Result<Option<u64>, Error<D::Error>> {
+	db.get_meta(&to_meta_key(NON_CANONICAL_JOURNAL_HEAD, &()))
+		.map_err(Error::Db)?
+		.map(|buffer| u64::decode(&mut buffer.as_slice()))
+		.transpose()
+		.map_err(Into::into)
+}
+
 #[cfg(test)]
 mod tests {
-	use super::{to_journal_key, NonCanonicalOverlay};
+	use super::{to_journal_key, to_origin_journal_key, NonCanonicalOverlay};
 	use crate::{
 		test::{make_changeset, make_db},
-		ChangeSet, CommitSet, MetaDb, StateDbError,
+		ChangeSet, CommitSet, Error, MetaDb, StateDbError,
 	};
 	use sp_core::H256;
 
@@ -585,7 +1259,8 @@ mod tests {
 	#[test]
 	fn created_from_empty_db() {
 		let db = make_db(&[]);
-		let overlay: NonCanonicalOverlay<H256, H256> = NonCanonicalOverlay::new(&db).unwrap();
+		let overlay: NonCanonicalOverlay<H256, H256> =
+			NonCanonicalOverlay::new(&db, false).unwrap();
 		assert_eq!(overlay.last_canonicalized, None);
 		assert!(overlay.levels.is_empty());
 		assert!(overlay.parents.is_empty());
@@ -595,7 +1270,7 @@ mod tests {
 	#[should_panic]
 	fn canonicalize_empty_panics() {
 		let db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		let mut commit = CommitSet::default();
 		overlay.canonicalize(&H256::default(), &mut commit).unwrap();
 	}
@@ -606,7 +1281,7 @@ mod tests {
 		let db = make_db(&[]);
 		let h1 = H256::random();
 		let h2 = H256::random();
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		overlay.insert(&h1, 2, &H256::default(), ChangeSet::default()).unwrap();
 		overlay.insert(&h2, 1, &h1, ChangeSet::default()).unwrap();
 	}
@@ -617,7 +1292,7 @@ mod tests {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		overlay.insert(&h1, 1, &H256::default(), ChangeSet::default()).unwrap();
 		overlay.insert(&h2, 3, &h1, ChangeSet::default()).unwrap();
 	}
@@ -628,7 +1303,7 @@ mod tests {
 		let db = make_db(&[]);
 		let h1 = H256::random();
 		let h2 = H256::random();
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		overlay.insert(&h1, 1, &H256::default(), ChangeSet::default()).unwrap();
 		overlay.insert(&h2, 2, &H256::default(), ChangeSet::default()).unwrap();
 	}
@@ -637,7 +1312,7 @@ mod tests {
 	fn insert_existing_fails() {
 		let db = make_db(&[]);
 		let h1 = H256::random();
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		overlay.insert(&h1, 2, &H256::default(), ChangeSet::default()).unwrap();
 		assert!(matches!(
 			overlay.insert(&h1, 2, &H256::default(), ChangeSet::default()),
@@ -651,22 +1326,104 @@ mod tests {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		overlay.insert(&h1, 1, &H256::default(), ChangeSet::default()).unwrap();
 		let mut commit = CommitSet::default();
 		overlay.canonicalize(&h2, &mut commit).unwrap();
 	}
 
+	#[test]
+	fn strict_mode_reports_value_mismatch() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.set_strict(true);
+		overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap();
+		let mut changeset = make_changeset(&[], &[]);
+		changeset.inserted.push((H256::from_low_u64_be(1), vec![0]));
+		assert!(matches!(
+			overlay.insert(&h2, 1, &H256::default(), changeset),
+			Err(StateDbError::Corruption(_))
+		));
+		assert!(!overlay.have_block(&h2));
+		// the overlay is still usable
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h1, &mut commit).unwrap();
+	}
+
+	#[test]
+	fn strict_mode_fails_after_missing_value() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.set_strict(true);
+		overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap();
+		overlay.insert(&h2, 2, &h1, make_changeset(&[2], &[])).unwrap();
+		overlay.values.shared.write().map.remove(&H256::from_low_u64_be(2));
+		assert!(overlay.revert_one().is_some());
+		assert!(overlay.revert_one().is_none());
+		assert!(overlay.remove(&h1).is_none());
+		assert!(matches!(
+			overlay.insert(&h2, 2, &h1, make_changeset(&[2], &[])),
+			Err(StateDbError::Corruption(_))
+		));
+		let mut commit = CommitSet::default();
+		assert!(matches!(overlay.canonicalize(&h1, &mut commit), Err(StateDbError::Corruption(_))));
+	}
+
+	#[test]
+	fn canonicalize_reports_missing_value() {
+		let h1 = H256::random();
+		let db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap();
+		overlay.values.shared.write().map.remove(&H256::from_low_u64_be(1));
+		let mut commit = CommitSet::default();
+		assert!(matches!(overlay.canonicalize(&h1, &mut commit), Err(StateDbError::Corruption(_))));
+		// the overlay is left unchanged
+		assert!(overlay.have_block(&h1));
+		assert_eq!(overlay.last_canonicalized_block_number(), Some(0));
+		assert!(commit.meta.inserted.is_empty());
+	}
+
+	#[test]
+	fn strict_mode_reports_corruption_before_changes() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.set_strict(true);
+		overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap();
+		overlay.insert(&h2, 1, &H256::default(), make_changeset(&[2], &[])).unwrap();
+		overlay.values.shared.write().map.remove(&H256::from_low_u64_be(2));
+		// the canonicalization that finds the missing value is applied as usual
+		let mut commit = CommitSet::default();
+		assert_eq!(overlay.canonicalize(&h1, &mut commit).unwrap(), 1);
+		assert!(!overlay.have_block(&h2));
+		let h3 = H256::random();
+		assert!(matches!(
+			overlay.insert(&h3, 2, &h1, make_changeset(&[3], &[])),
+			Err(StateDbError::Corruption(_))
+		));
+		// leaving strict mode clears the error
+		overlay.set_strict(false);
+		overlay.insert(&h3, 2, &h1, make_changeset(&[3], &[])).unwrap();
+		overlay.set_strict(true);
+		overlay.canonicalize(&h3, &mut commit).unwrap();
+	}
+
 	#[test]
 	fn insert_canonicalize_one() {
 		let h1 = H256::random();
 		let mut db = make_db(&[1, 2]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		let changeset = make_changeset(&[3, 4], &[2]);
 		let insertion = overlay.insert(&h1, 1, &H256::default(), changeset.clone()).unwrap();
 		assert_eq!(insertion.data.inserted.len(), 0);
 		assert_eq!(insertion.data.deleted.len(), 0);
-		assert_eq!(insertion.meta.inserted.len(), 2);
+		assert_eq!(insertion.meta.inserted.len(), 3);
 		assert_eq!(insertion.meta.deleted.len(), 0);
 		db.commit(&insertion);
 		let mut finalization = CommitSet::default();
@@ -679,21 +1436,112 @@ mod tests {
 		assert!(db.data_eq(&make_db(&[1, 3, 4])));
 	}
 
+	#[test]
+	fn insert_coalesces_rewritten_keys() {
+		let h1 = H256::random();
+		let mut db = make_db(&[1, 2]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		let mut changeset = make_changeset(&[2, 3], &[1, 2]);
+		changeset.child_tries = vec![
+			(vec![1], vec![H256::from_low_u64_be(2)]),
+			(vec![2], vec![H256::from_low_u64_be(1)]),
+		];
+		db.commit(&overlay.insert(&h1, 1, &H256::default(), changeset).unwrap());
+		assert!(contains(&overlay, 3));
+		assert!(!contains(&overlay, 2));
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		assert_eq!(overlay.levels, overlay2.levels);
+		assert_eq!(overlay.levels[0].blocks[0].inserted, vec![H256::from_low_u64_be(3)]);
+		assert_eq!(overlay.levels[0].blocks[0].deleted, vec![H256::from_low_u64_be(1)]);
+		assert_eq!(
+			overlay.levels[0].blocks[0].child_tries,
+			vec![(vec![2], vec![H256::from_low_u64_be(1)])]
+		);
+
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h1, &mut commit).unwrap();
+		assert_eq!(commit.data.inserted.len(), 1);
+		assert_eq!(commit.data.deleted, vec![H256::from_low_u64_be(1)]);
+		db.commit(&commit);
+		assert!(db.data_eq(&make_db(&[2, 3])));
+	}
+
+	#[test]
+	fn restore_after_discarding_top_level() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let h3 = H256::random();
+		let mut db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		db.commit(&overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap());
+		db.commit(&overlay.insert(&h2, 1, &H256::default(), make_changeset(&[2], &[])).unwrap());
+		db.commit(&overlay.insert(&h3, 2, &h2, make_changeset(&[3], &[])).unwrap());
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h1, &mut commit).unwrap();
+		db.commit(&commit);
+		assert!(overlay.levels.is_empty());
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		assert!(overlay2.levels.is_empty());
+		assert_eq!(overlay2.last_canonicalized, overlay.last_canonicalized);
+	}
+
+	#[test]
+	fn origin_is_journaled() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let mut db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		let origin = |block: &H256| Some(block.as_bytes()[..4].to_vec());
+		let changeset = make_changeset(&[1], &[]);
+		db.commit(
+			&overlay
+				.insert_with_origin(&h1, 1, &H256::default(), changeset, origin(&h1))
+				.unwrap(),
+		);
+		let changeset = make_changeset(&[2], &[]);
+		db.commit(
+			&overlay
+				.insert_with_origin(&h2, 1, &H256::default(), changeset, origin(&h2))
+				.unwrap(),
+		);
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		assert_eq!(overlay.levels, overlay2.levels);
+		let blocks = overlay2.blocks();
+		assert_eq!(blocks.len(), 2);
+		assert_eq!(blocks[0].hash, h1);
+		assert_eq!(blocks[0].parent_hash, H256::default());
+		assert_eq!((blocks[0].number, blocks[0].inserted, blocks[0].deleted), (1, 1, 0));
+		assert_eq!(blocks[0].origin, origin(&h1));
+		assert_eq!(blocks[1].origin, origin(&h2));
+		// hashes, one inserted value, no deleted keys and the origin
+		assert_eq!(blocks[0].journal_bytes, 32 + 32 + 1 + 32 + 1 + 32 + 1 + 4);
+		assert_eq!(overlay.journal_bytes_written(), 2 * blocks[0].journal_bytes as u64);
+		assert_eq!(overlay2.journal_bytes_written(), 0);
+
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h1, &mut commit).unwrap();
+		db.commit(&commit);
+		assert!(db.get_meta(&to_origin_journal_key(1, 0)).unwrap().is_none());
+		assert!(db.get_meta(&to_origin_journal_key(1, 1)).unwrap().is_none());
+	}
+
 	#[test]
 	fn restore_from_journal() {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let mut db = make_db(&[1, 2]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(
 			&overlay
 				.insert(&h1, 10, &H256::default(), make_changeset(&[3, 4], &[2]))
 				.unwrap(),
 		);
 		db.commit(&overlay.insert(&h2, 11, &h1, make_changeset(&[5], &[3])).unwrap());
-		assert_eq!(db.meta_len(), 3);
+		assert_eq!(db.meta_len(), 4);
 
-		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert_eq!(overlay.levels, overlay2.levels);
 		assert_eq!(overlay.parents, overlay2.parents);
 		assert_eq!(overlay.last_canonicalized, overlay2.last_canonicalized);
@@ -704,7 +1552,7 @@ mod tests {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let mut db = make_db(&[1, 2]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(
 			&overlay
 				.insert(&h1, 10, &H256::default(), make_changeset(&[3, 4], &[2]))
@@ -717,18 +1565,69 @@ mod tests {
 		db.commit(&commit);
 		assert_eq!(overlay.levels.len(), 1);
 
-		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert_eq!(overlay.levels, overlay2.levels);
 		assert_eq!(overlay.parents, overlay2.parents);
 		assert_eq!(overlay.last_canonicalized, overlay2.last_canonicalized);
 	}
 
+	#[test]
+	fn restore_detects_journal_gap() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let h3 = H256::random();
+		let mut db = make_db(&[1, 2]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		db.commit(&overlay.insert(&h1, 10, &H256::default(), make_changeset(&[3], &[])).unwrap());
+		db.commit(&overlay.insert(&h2, 11, &h1, make_changeset(&[4], &[])).unwrap());
+		db.commit(&overlay.insert(&h3, 12, &h2, make_changeset(&[5], &[])).unwrap());
+
+		// lose the journal record of the middle level
+		let mut commit = CommitSet::default();
+		commit.meta.deleted.push(to_journal_key(11, 0));
+		db.commit(&commit);
+
+		assert!(matches!(
+			NonCanonicalOverlay::<H256, H256>::new(&db, false),
+			Err(Error::StateDb(StateDbError::JournalGap { missing: 11, head: 12 }))
+		));
+
+		let commit = NonCanonicalOverlay::<H256, H256>::truncate_journal(&db).unwrap();
+		assert_eq!(commit.meta.deleted, vec![to_journal_key(12, 0)]);
+		db.commit(&commit);
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		assert_eq!(overlay2.levels.len(), 1);
+		assert!(overlay2.have_block(&h1));
+		assert!(!overlay2.have_block(&h3));
+		assert!(NonCanonicalOverlay::<H256, H256>::truncate_journal(&db)
+			.unwrap()
+			.meta
+			.inserted
+			.is_empty());
+	}
+
+	#[test]
+	fn restore_after_revert_has_no_gap() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let mut db = make_db(&[1, 2]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		db.commit(&overlay.insert(&h1, 10, &H256::default(), make_changeset(&[3], &[])).unwrap());
+		db.commit(&overlay.insert(&h2, 11, &h1, make_changeset(&[4], &[])).unwrap());
+		db.commit(&overlay.revert_one().unwrap());
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		assert_eq!(overlay.levels, overlay2.levels);
+		assert_eq!(overlay.parents, overlay2.parents);
+	}
+
 	#[test]
 	fn insert_canonicalize_two() {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let mut db = make_db(&[1, 2, 3, 4]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		let changeset1 = make_changeset(&[5, 6], &[2]);
 		let changeset2 = make_changeset(&[7, 8], &[5, 3]);
 		db.commit(&overlay.insert(&h1, 1, &H256::default(), changeset1).unwrap());
@@ -761,7 +1660,7 @@ mod tests {
 		let (h_1, c_1) = (H256::random(), make_changeset(&[1], &[]));
 		let (h_2, c_2) = (H256::random(), make_changeset(&[1], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_1, 1, &H256::default(), c_1).unwrap());
 		db.commit(&overlay.insert(&h_2, 1, &H256::default(), c_2).unwrap());
 		assert!(contains(&overlay, 1));
@@ -778,7 +1677,7 @@ mod tests {
 		let h2 = H256::random();
 		let h3 = H256::random();
 		let mut db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		let changeset = make_changeset(&[], &[]);
 		db.commit(&overlay.insert(&h1, 1, &H256::default(), changeset.clone()).unwrap());
 		db.commit(&overlay.insert(&h2, 2, &h1, changeset.clone()).unwrap());
@@ -819,7 +1718,7 @@ mod tests {
 		let (h_1_2_3, c_1_2_3) = (H256::random(), make_changeset(&[123], &[]));
 		let (h_2_1_1, c_2_1_1) = (H256::random(), make_changeset(&[211], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_1, 1, &H256::default(), c_1).unwrap());
 
 		db.commit(&overlay.insert(&h_1_1, 2, &h_1, c_1_1).unwrap());
@@ -847,7 +1746,7 @@ mod tests {
 		assert_eq!(overlay.last_canonicalized, Some((H256::default(), 0)));
 
 		// check if restoration from journal results in the same tree
-		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert_eq!(overlay.levels, overlay2.levels);
 		assert_eq!(overlay.parents, overlay2.parents);
 		assert_eq!(overlay.last_canonicalized, overlay2.last_canonicalized);
@@ -906,7 +1805,7 @@ mod tests {
 		let h1 = H256::random();
 		let h2 = H256::random();
 		let mut db = make_db(&[1, 2, 3, 4]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert!(overlay.revert_one().is_none());
 		let changeset1 = make_changeset(&[5, 6], &[2]);
 		let changeset2 = make_changeset(&[7, 8], &[5, 3]);
@@ -934,7 +1833,7 @@ mod tests {
 		let (h_1, c_1) = (H256::random(), make_changeset(&[1], &[]));
 		let (h_2, c_2) = (H256::random(), make_changeset(&[2], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_1, 1, &H256::default(), c_1).unwrap());
 		db.commit(&overlay.insert(&h_2, 1, &H256::default(), c_2).unwrap());
 
@@ -962,7 +1861,7 @@ mod tests {
 		let (h_2, c_2) = (H256::random(), make_changeset(&[1], &[]));
 		let (h_3, c_3) = (H256::random(), make_changeset(&[], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_1, 1, &H256::default(), c_1).unwrap());
 		db.commit(&overlay.insert(&h_2, 1, &H256::default(), c_2).unwrap());
 		db.commit(&overlay.insert(&h_3, 1, &H256::default(), c_3).unwrap());
@@ -985,7 +1884,7 @@ mod tests {
 		let (h_1, c_1) = (H256::random(), make_changeset(&[1], &[]));
 		let (h_2, c_2) = (H256::random(), make_changeset(&[2], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_1, 1, &H256::default(), c_1).unwrap());
 		db.commit(&overlay.insert(&h_2, 2, &h_1, c_2).unwrap());
 
@@ -1012,7 +1911,7 @@ mod tests {
 		let (h_12, c_12) = (H256::random(), make_changeset(&[], &[]));
 		let (h_21, c_21) = (H256::random(), make_changeset(&[], &[]));
 
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&h_11, 1, &H256::default(), c_11).unwrap());
 		db.commit(&overlay.insert(&h_12, 1, &H256::default(), c_12).unwrap());
 		db.commit(&overlay.insert(&h_21, 2, &h_11, c_21).unwrap());
@@ -1040,7 +1939,7 @@ mod tests {
 		let h11 = H256::random();
 		let h21 = H256::random();
 		let mut db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&root, 10, &H256::default(), make_changeset(&[], &[])).unwrap());
 		db.commit(&overlay.insert(&h1, 11, &root, make_changeset(&[1], &[])).unwrap());
 		db.commit(&overlay.insert(&h2, 11, &root, make_changeset(&[2], &[])).unwrap());
@@ -1056,7 +1955,7 @@ mod tests {
 		assert!(db.get_meta(&to_journal_key(12, 1)).unwrap().is_some());
 
 		// Restore into a new overlay and check that journaled value exists.
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert!(contains(&overlay, 21));
 
 		let mut commit = CommitSet::default();
@@ -1076,7 +1975,7 @@ mod tests {
 		let h11 = H256::random();
 		let h21 = H256::random();
 		let mut db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&root, 10, &H256::default(), make_changeset(&[], &[])).unwrap());
 		db.commit(&overlay.insert(&h1, 11, &root, make_changeset(&[1], &[])).unwrap());
 		db.commit(&overlay.insert(&h2, 11, &root, make_changeset(&[2], &[])).unwrap());
@@ -1095,7 +1994,7 @@ mod tests {
 		assert_eq!(overlay.levels[0].blocks[1].journal_index, 0);
 
 		// Restore into a new overlay and check that journaled value exists.
-		let overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		assert_eq!(overlay.parents.len(), 2);
 		assert!(contains(&overlay, 21));
 		assert!(contains(&overlay, 22));
@@ -1109,7 +2008,7 @@ mod tests {
 		let h11 = H256::random();
 		let h21 = H256::random();
 		let mut db = make_db(&[]);
-		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
 		db.commit(&overlay.insert(&root, 10, &H256::default(), make_changeset(&[], &[])).unwrap());
 		db.commit(&overlay.insert(&h1, 11, &root, make_changeset(&[1], &[])).unwrap());
 		db.commit(&overlay.insert(&h2, 11, &root, make_changeset(&[2], &[])).unwrap());
@@ -1128,4 +2027,112 @@ mod tests {
 		db.commit(&overlay.remove(&h2).unwrap());
 		assert!(!contains(&overlay, 2));
 	}
+	#[test]
+	fn snapshot_keeps_discarded_values() {
+		let h1 = H256::random();
+		let h2 = H256::random();
+		let db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.insert(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap();
+		overlay.insert(&h2, 1, &H256::default(), make_changeset(&[2], &[])).unwrap();
+		let snapshot = overlay.values_snapshot();
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h1, &mut commit).unwrap();
+		overlay.unpin(&h1);
+		let key = H256::from_low_u64_be(2);
+		assert!(!contains(&overlay, 2));
+		assert_eq!(snapshot.get(&key), Some(key.as_bytes().to_vec()));
+		// values inserted after the snapshot was taken are visible too.
+		overlay.insert(&H256::random(), 2, &h1, make_changeset(&[3], &[])).unwrap();
+		assert!(snapshot.get(&H256::from_low_u64_be(3)).is_some());
+		// a later snapshot doesn't see the discarded value.
+		let later = overlay.values_snapshot();
+		assert_eq!(later.get(&key), None);
+		assert_eq!(overlay.values.shared.read().discarded.len(), 2);
+		drop(snapshot);
+		assert!(overlay.values.shared.read().discarded.is_empty());
+	}
+
+	#[test]
+	fn displaced_by_lists_other_branches() {
+		let db = make_db(&[]);
+		let (h_1, h_2, h_1_1, h_1_2, h_2_1, h_1_1_1) = (
+			H256::random(),
+			H256::random(),
+			H256::random(),
+			H256::random(),
+			H256::random(),
+			H256::random(),
+		);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, false).unwrap();
+		overlay.insert(&h_1, 1, &H256::default(), ChangeSet::default()).unwrap();
+		overlay.insert(&h_2, 1, &H256::default(), ChangeSet::default()).unwrap();
+		overlay.insert(&h_1_1, 2, &h_1, ChangeSet::default()).unwrap();
+		overlay.insert(&h_1_2, 2, &h_1, ChangeSet::default()).unwrap();
+		overlay.insert(&h_2_1, 2, &h_2, ChangeSet::default()).unwrap();
+		overlay.insert(&h_1_1_1, 3, &h_1_1, ChangeSet::default()).unwrap();
+
+		assert_eq!(overlay.displaced_by(&h_1), vec![h_2, h_2_1]);
+		assert_eq!(overlay.displaced_by(&h_2), vec![h_1, h_1_1, h_1_2, h_1_1_1]);
+		assert_eq!(overlay.displaced_by(&h_1_1), vec![h_2, h_1_2, h_2_1]);
+		assert_eq!(overlay.displaced_by(&h_1_1_1), vec![h_2, h_1_2, h_2_1]);
+		assert!(overlay.displaced_by(&H256::random()).is_empty());
+
+		let mut commit = CommitSet::default();
+		overlay.canonicalize(&h_1, &mut commit).unwrap();
+		assert_eq!(overlay.displaced_by(&h_1_1), vec![h_1_2]);
+		assert_eq!(overlay.displaced_by(&h_1_2), vec![h_1_1, h_1_1_1]);
+	}
+
+	#[test]
+	fn sparse_block_numbers() {
+		let mut db = make_db(&[]);
+		let (h0, h1, h3, h5, h9) =
+			(H256::random(), H256::random(), H256::random(), H256::random(), H256::random());
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, true).unwrap();
+		db.commit(&overlay.insert(&h1, 1, &h0, make_changeset(&[1], &[])).unwrap());
+		db.commit(&overlay.insert(&h5, 5, &h1, make_changeset(&[5], &[])).unwrap());
+		db.commit(&overlay.insert(&h3, 3, &h1, make_changeset(&[3], &[])).unwrap());
+		db.commit(&overlay.insert(&h9, 9, &h5, make_changeset(&[9], &[])).unwrap());
+		assert!(matches!(
+			overlay.insert(&H256::random(), 7, &h9, ChangeSet::default()),
+			Err(StateDbError::InvalidParent)
+		));
+		assert_eq!(overlay.levels.iter().map(|l| l.number).collect::<Vec<_>>(), vec![1, 3, 5, 9]);
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, true).unwrap();
+		assert_eq!(overlay.levels, overlay2.levels);
+
+		let mut commit = CommitSet::default();
+		assert_eq!(overlay.canonicalize(&h1, &mut commit).unwrap(), 1);
+		db.commit(&commit);
+		assert_eq!(overlay.displaced_by(&h3), vec![h5, h9]);
+		assert_eq!(overlay.displaced_by(&h5), vec![h3]);
+		assert_eq!(overlay.displaced_by(&h9), vec![h3]);
+		// block 3 is on another branch and is discarded along the way
+		let mut commit = CommitSet::default();
+		assert_eq!(overlay.canonicalize(&h5, &mut commit).unwrap(), 5);
+		db.commit(&commit);
+		overlay.sync();
+		assert!(db.data_eq(&make_db(&[1, 5])));
+		assert!(!contains(&overlay, 3));
+		assert!(contains(&overlay, 9));
+		assert_eq!(overlay.last_canonicalized_block_number(), Some(5));
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db, true).unwrap();
+		assert_eq!(overlay.levels, overlay2.levels);
+		assert_eq!(overlay2.levels.iter().map(|l| l.number).collect::<Vec<_>>(), vec![9]);
+	}
+
+	#[test]
+	fn sparse_canonicalize_requires_root() {
+		let mut db = make_db(&[]);
+		let (h0, h1, h3) = (H256::random(), H256::random(), H256::random());
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db, true).unwrap();
+		db.commit(&overlay.insert(&h1, 1, &h0, ChangeSet::default()).unwrap());
+		db.commit(&overlay.insert(&h3, 3, &h1, ChangeSet::default()).unwrap());
+		let mut commit = CommitSet::default();
+		assert_eq!(overlay.canonicalize(&h3, &mut commit), Err(StateDbError::InvalidBlock));
+		assert_eq!(overlay.levels.len(), 2);
+		assert!(commit.meta.deleted.is_empty());
+	}
 }
@@ -25,58 +25,180 @@
 //! The changes are journaled in the DB.
 
 use crate::{
-	noncanonical::LAST_CANONICAL, to_meta_key, CommitSet, Error, Hash, MetaDb, StateDbError,
-	DEFAULT_MAX_BLOCK_CONSTRAINT, LOG_TARGET,
+	noncanonical::LAST_CANONICAL, to_meta_key, ChildTrieId, CommitSet, DeferredValues, Error, Hash,
+	MetaDb, PruningStrategy, StateDbError, DEFAULT_MAX_BLOCK_CONSTRAINT, LOG_TARGET,
 };
 use codec::{Decode, Encode};
 use log::trace;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{
+	collections::{BTreeSet, HashMap, HashSet, VecDeque},
+	sync::Arc,
+};
 
 pub(crate) const LAST_PRUNED: &[u8] = b"last_pruned";
+const LAST_RETAINED: &[u8] = b"last_retained";
+const RETAINED_STATE: &[u8] = b"retained_state";
+const RETAINED_JOURNAL: &[u8] = b"retained_journal";
+const RETAINED_KEYS: &[u8] = b"retained_keys";
 const PRUNING_JOURNAL: &[u8] = b"pruning_journal";
+const PRUNING_CHILD_JOURNAL: &[u8] = b"pruning_journal_child";
+/// Size of the filter of the keys kept for retained states, in bytes.
+const RETAINED_KEYS_SIZE: usize = 1024 * 1024;
+/// Size of the pages the filter of the keys kept for retained states is stored in, in bytes.
+const RETAINED_KEYS_PAGE_SIZE: usize = 4096;
+/// Number of bits set in the filter of the keys kept for retained states for each key.
+const RETAINED_KEYS_HASHES: u64 = 4;
 
 /// See module documentation.
 pub struct RefWindow<BlockHash: Hash, Key: Hash, D: MetaDb> {
+	/// The backend database.
+	db: D,
 	/// A queue of blocks keep tracking keys that should be deleted for each block in the
 	/// pruning window.
-	queue: DeathRowQueue<BlockHash, Key, D>,
+	queue: DeathRowQueue<BlockHash, Key>,
 	/// Block number that is next to be pruned.
 	base: u64,
+	/// Retention policy consulted for each canonicalized block.
+	strategy: Arc<dyn PruningStrategy>,
+	/// Number of the last canonical block whose state is retained, so that the nodes it
+	/// references must not be pruned.
+	last_retained: Option<u64>,
+	/// Number of references to each key inserted since the last retained state. Only deletions
+	/// of these references are pruned while retaining. The changes are journaled for each block,
+	/// and replayed on restart.
+	fresh: HashMap<Key, u32>,
+	/// Keys kept for retained states. Without reference counting in the database, re-inserting
+	/// one of them must not make it prunable again. `None` with reference counting.
+	retained_keys: Option<RetainedKeys>,
+}
+
+/// Changes to [`RefWindow::fresh`] made by a block canonicalized while retaining.
+#[derive(Encode, Decode)]
+struct RetainedJournalRecord<Key: Hash> {
+	/// Keys inserted by the block.
+	inserted: Vec<Key>,
+	/// Keys deleted by the block, and pruned.
+	pruned: Vec<Key>,
+}
+
+/// Bloom filter of the keys kept for retained states, stored in pages in the meta column.
+///
+/// The filter has a fixed size. As it fills up, more keys are wrongly taken for retained ones,
+/// which keeps their nodes in the database instead of pruning them.
+#[derive(Default)]
+struct RetainedKeys {
+	/// The bits of the filter, allocated on the first insertion.
+	bits: Vec<u8>,
+	/// Indices of the pages changed since the filter was last written.
+	dirty: BTreeSet<usize>,
+}
+
+impl RetainedKeys {
+	fn load<D: MetaDb>(db: &D) -> Result<RetainedKeys, Error<D::Error>> {
+		let mut bits = vec![0; RETAINED_KEYS_SIZE];
+		for (index, page) in bits.chunks_mut(RETAINED_KEYS_PAGE_SIZE).enumerate() {
+			if let Some(stored) = db.get_meta(&to_retained_keys_key(index)).map_err(Error::Db)? {
+				if stored.len() != page.len() {
+					return Err(StateDbError::Metadata(format!(
+						"Invalid size of retained keys page {index}: {}",
+						stored.len()
+					))
+					.into())
+				}
+				page.copy_from_slice(&stored);
+			}
+		}
+		Ok(RetainedKeys { bits, dirty: Default::default() })
+	}
+
+	/// Bits of the filter set for `key`.
+	fn positions<Key: Hash>(key: &Key) -> impl Iterator<Item = usize> {
+		// 64-bit FNV-1a of the encoded key, which must not change as the filter is stored.
+		let hash = key.using_encoded(|data| {
+			data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+				(hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+			})
+		});
+		let (first, step) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+		let bits = (RETAINED_KEYS_SIZE * 8) as u64;
+		(0..RETAINED_KEYS_HASHES).map(move |i| (first.wrapping_add(i * step) % bits) as usize)
+	}
+
+	fn contains<Key: Hash>(&self, key: &Key) -> bool {
+		!self.bits.is_empty() &&
+			Self::positions(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+	}
+
+	fn insert<Key: Hash>(&mut self, key: &Key) {
+		if self.bits.is_empty() {
+			self.bits = vec![0; RETAINED_KEYS_SIZE];
+		}
+		for bit in Self::positions(key) {
+			self.bits[bit / 8] |= 1 << (bit % 8);
+			self.dirty.insert(bit / 8 / RETAINED_KEYS_PAGE_SIZE);
+		}
+	}
+
+	/// Add the pages changed since the last write to `commit`.
+	fn write<Key: Hash>(&mut self, commit: &mut CommitSet<Key>) {
+		for index in std::mem::take(&mut self.dirty) {
+			let page = &self.bits[index * RETAINED_KEYS_PAGE_SIZE..][..RETAINED_KEYS_PAGE_SIZE];
+			commit.meta.inserted.push((to_retained_keys_key(index), page.to_vec()));
+		}
+	}
 }
 
 /// `DeathRowQueue` used to keep track of blocks in the pruning window, there are two flavors:
-/// - `Mem`, used when the backend database do not supports reference counting, keep all
-/// 	blocks in memory, and keep track of re-inserted keys to not delete them when pruning
+/// - `Mem`, used when the backend database do not supports reference counting, keep the blocks
+/// 	fitting in the cache budget in memory, and track re-inserted keys to not delete them
 /// - `DbBacked`, used when the backend database supports reference counting, only keep
-/// 	a few number of blocks in memory and load more blocks on demand
-enum DeathRowQueue<BlockHash: Hash, Key: Hash, D: MetaDb> {
+/// 	as many blocks in memory as fit in the cache budget and load more blocks on demand
+enum DeathRowQueue<BlockHash: Hash, Key: Hash> {
 	Mem {
-		/// A queue of keys that should be deleted for each block in the pruning window.
+		/// A queue of keys that should be deleted for each block in the pruning window. Only
+		/// the first `cached` blocks keep their keys, the keys of the other blocks are read
+		/// from the database when they are pruned.
 		death_rows: VecDeque<DeathRow<BlockHash, Key>>,
 		/// An index that maps each key from `death_rows` to block number.
 		death_index: HashMap<Key, u64>,
+		/// Number of blocks at the front of `death_rows` that keep their keys
+		cached: usize,
+		/// A soft limit of the size of the cached blocks, in bytes
+		cache_capacity: usize,
+		/// Accounted size of the cached blocks, in bytes
+		cache_size: usize,
 	},
 	DbBacked {
-		// The backend database
-		db: D,
 		/// A queue of keys that should be deleted for each block in the pruning window.
 		/// Only caching the first few blocks of the pruning window, blocks inside are
 		/// successive and ordered by block number
 		cache: VecDeque<DeathRow<BlockHash, Key>>,
-		/// A soft limit of the cache's size
+		/// A limit of the number of blocks in `cache`
+		cache_blocks: usize,
+		/// A soft limit of the cache's size, in bytes
 		cache_capacity: usize,
+		/// Accounted size of the blocks in `cache`, in bytes
+		cache_size: usize,
 		/// Last block number added to the window
 		last: Option<u64>,
 	},
 }
 
-impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
-	/// Return a `DeathRowQueue` that all blocks are keep in memory
-	fn new_mem(db: &D, base: u64) -> Result<DeathRowQueue<BlockHash, Key, D>, Error<D::Error>> {
+impl<BlockHash: Hash, Key: Hash> DeathRowQueue<BlockHash, Key> {
+	/// Return a `DeathRowQueue` that keep the re-inserted keys of all blocks in memory, and
+	/// only keep `cache_capacity` bytes worth of blocks in memory
+	fn new_mem<D: MetaDb>(
+		db: &D,
+		base: u64,
+		cache_capacity: usize,
+	) -> Result<DeathRowQueue<BlockHash, Key>, Error<D::Error>> {
 		let mut block = base;
-		let mut queue = DeathRowQueue::<BlockHash, Key, D>::Mem {
+		let mut queue = DeathRowQueue::<BlockHash, Key>::Mem {
 			death_rows: VecDeque::new(),
 			death_index: HashMap::new(),
+			cached: 0,
+			cache_capacity,
+			cache_size: 0,
 		};
 		// read the journal
 		trace!(
@@ -90,6 +212,7 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 				Some(record) => {
 					let record: JournalRecord<BlockHash, Key> =
 						Decode::decode(&mut record.as_slice())?;
+					let child_tries = load_child_tries_from_db::<Key, D>(db, block)?;
 					trace!(
 						target: LOG_TARGET,
 						"Pruning journal entry {} ({} inserted, {} deleted)",
@@ -97,7 +220,7 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 						record.inserted.len(),
 						record.deleted.len(),
 					);
-					queue.import(base, block, record);
+					queue.import(base, block, record, child_tries, false);
 				},
 				None => break,
 			}
@@ -106,45 +229,81 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 		Ok(queue)
 	}
 
-	/// Return a `DeathRowQueue` that backed by an database, and only keep a few number
-	/// of blocks in memory
-	fn new_db_backed(
-		db: D,
+	/// Return a `DeathRowQueue` that backed by an database, and only keep `cache_capacity`
+	/// bytes worth of blocks in memory, and at most `window_size` blocks
+	fn new_db_backed<D: MetaDb>(
+		db: &D,
 		base: u64,
 		last: Option<u64>,
 		window_size: u32,
-	) -> Result<DeathRowQueue<BlockHash, Key, D>, Error<D::Error>> {
-		// limit the cache capacity from 1 to `DEFAULT_MAX_BLOCK_CONSTRAINT`
-		let cache_capacity = window_size.clamp(1, DEFAULT_MAX_BLOCK_CONSTRAINT) as usize;
-		let mut cache = VecDeque::with_capacity(cache_capacity);
+		cache_capacity: usize,
+	) -> Result<DeathRowQueue<BlockHash, Key>, Error<D::Error>> {
+		// limit the number of cached blocks from 1 to `DEFAULT_MAX_BLOCK_CONSTRAINT`
+		let cache_blocks = window_size.clamp(1, DEFAULT_MAX_BLOCK_CONSTRAINT) as usize;
+		let mut cache = VecDeque::new();
+		let mut cache_size = 0;
 		trace!(
 			target: LOG_TARGET,
 			"Reading pruning journal for the database-backed queue. Pending #{}",
 			base
 		);
-		DeathRowQueue::load_batch_from_db(&db, &mut cache, base, cache_capacity)?;
-		Ok(DeathRowQueue::DbBacked { db, cache, cache_capacity, last })
+		DeathRowQueue::load_batch_from_db(
+			db,
+			&mut cache,
+			&mut cache_size,
+			base,
+			cache_blocks,
+			cache_capacity,
+		)?;
+		Ok(DeathRowQueue::DbBacked { cache, cache_blocks, cache_capacity, cache_size, last })
 	}
 
-	/// import a new block to the back of the queue
-	fn import(&mut self, base: u64, num: u64, journal_record: JournalRecord<BlockHash, Key>) {
+	/// import a new block to the back of the queue. A `chained` block is noted in the same
+	/// commit as the previous one, and is kept in memory regardless of the cache limits if the
+	/// previous one is, as it can't be loaded from the database until the commit is written.
+	fn import(
+		&mut self,
+		base: u64,
+		num: u64,
+		journal_record: JournalRecord<BlockHash, Key>,
+		child_tries: Vec<(ChildTrieId, Vec<Key>)>,
+		chained: bool,
+	) {
 		let JournalRecord { hash, inserted, deleted } = journal_record;
 		trace!(target: LOG_TARGET, "Importing {}, base={}", num, base);
 		match self {
-			DeathRowQueue::DbBacked { cache, cache_capacity, last, .. } => {
+			DeathRowQueue::DbBacked { cache, cache_blocks, cache_capacity, cache_size, last } => {
 				// If the new block continues cached range and there is space, load it directly into
 				// cache.
-				if num == base + cache.len() as u64 && cache.len() < *cache_capacity {
-					trace!(target: LOG_TARGET, "Adding to DB backed cache {:?} (#{})", hash, num);
-					cache.push_back(DeathRow { hash, deleted: deleted.into_iter().collect() });
+				if num == base + cache.len() as u64 {
+					let row =
+						DeathRow { hash, deleted: deleted.into_iter().collect(), child_tries };
+					let row_size = row.size();
+					if cache.is_empty() ||
+						chained ||
+						(cache.len() < *cache_blocks &&
+							*cache_size + row_size <= *cache_capacity)
+					{
+						trace!(
+							target: LOG_TARGET,
+							"Adding to DB backed cache {:?} (#{})",
+							row.hash,
+							num
+						);
+						*cache_size += row_size;
+						cache.push_back(row);
+					}
 				}
 				*last = Some(num);
 			},
-			DeathRowQueue::Mem { death_rows, death_index } => {
+			DeathRowQueue::Mem { death_rows, death_index, cached, cache_capacity, cache_size } => {
 				// remove all re-inserted keys from death rows
 				for k in inserted {
 					if let Some(block) = death_index.remove(&k) {
-						death_rows[(block - base) as usize].deleted.remove(&k);
+						let index = (block - base) as usize;
+						if index < *cached && death_rows[index].deleted.remove(&k) {
+							*cache_size -= k.encoded_size();
+						}
 					}
 				}
 				// add new keys
@@ -152,53 +311,141 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 				for k in deleted.iter() {
 					death_index.insert(k.clone(), imported_block);
 				}
-				death_rows.push_back(DeathRow { hash, deleted: deleted.into_iter().collect() });
+				let row = DeathRow { hash, deleted: deleted.into_iter().collect(), child_tries };
+				let row_size = row.size();
+				// Only keep the keys if the new block continues the cached blocks and there is
+				// space.
+				if *cached == death_rows.len() &&
+					(*cached == 0 || chained || *cache_size + row_size <= *cache_capacity)
+				{
+					*cached += 1;
+					*cache_size += row_size;
+					death_rows.push_back(row);
+				} else {
+					death_rows.push_back(DeathRow {
+						hash: row.hash,
+						deleted: Default::default(),
+						child_tries: Default::default(),
+					});
+				}
 			},
 		}
 	}
 
 	/// Pop out one block from the front of the queue, `base` is the block number
 	/// of the first block of the queue
-	fn pop_front(
+	fn pop_front<D: MetaDb>(
 		&mut self,
+		db: &D,
 		base: u64,
 	) -> Result<Option<DeathRow<BlockHash, Key>>, Error<D::Error>> {
 		match self {
-			DeathRowQueue::DbBacked { db, cache, cache_capacity, .. } => {
+			DeathRowQueue::DbBacked { cache, cache_blocks, cache_capacity, cache_size, .. } => {
 				if cache.is_empty() {
-					DeathRowQueue::load_batch_from_db(db, cache, base, *cache_capacity)?;
+					DeathRowQueue::load_batch_from_db(
+						db,
+						cache,
+						cache_size,
+						base,
+						*cache_blocks,
+						*cache_capacity,
+					)?;
 				}
-				Ok(cache.pop_front())
+				let row = cache.pop_front();
+				if let Some(row) = &row {
+					*cache_size -= row.size();
+				}
+				Ok(row)
 			},
-			DeathRowQueue::Mem { death_rows, death_index } => match death_rows.pop_front() {
-				Some(row) => {
-					for k in row.deleted.iter() {
-						death_index.remove(k);
+			DeathRowQueue::Mem { death_rows, death_index, cached, cache_capacity, cache_size } => {
+				if *cached == 0 {
+					DeathRowQueue::load_mem_batch_from_db(
+						db,
+						death_rows,
+						death_index,
+						cached,
+						cache_size,
+						base,
+						*cache_capacity,
+					)?;
+					if *cached == 0 {
+						// The journal of the block is not written yet.
+						return Ok(None)
 					}
-					Ok(Some(row))
-				},
-				None => Ok(None),
+				}
+				match death_rows.pop_front() {
+					Some(row) => {
+						*cached -= 1;
+						*cache_size -= row.size();
+						for k in row.deleted.iter() {
+							death_index.remove(k);
+						}
+						Ok(Some(row))
+					},
+					None => Ok(None),
+				}
 			},
 		}
 	}
 
-	/// Load a batch of blocks from the backend database into `cache`, starting from `base` and up
-	/// to `base + cache_capacity`
-	fn load_batch_from_db(
+	/// Load a batch of blocks from the backend database into `cache`, starting from `base` and
+	/// until `cache_size` reaches `cache_capacity` bytes or `cache` holds `cache_blocks` blocks.
+	/// At least one block is loaded into an empty cache, regardless of its size.
+	fn load_batch_from_db<D: MetaDb>(
 		db: &D,
 		cache: &mut VecDeque<DeathRow<BlockHash, Key>>,
+		cache_size: &mut usize,
 		base: u64,
+		cache_blocks: usize,
 		cache_capacity: usize,
 	) -> Result<(), Error<D::Error>> {
-		let start = base + cache.len() as u64;
-		let batch_size = cache_capacity;
-		for i in 0..batch_size as u64 {
-			match load_death_row_from_db::<BlockHash, Key, D>(db, start + i)? {
+		let mut block = base + cache.len() as u64;
+		while cache.is_empty() || (*cache_size < cache_capacity && cache.len() < cache_blocks) {
+			match load_death_row_from_db::<BlockHash, Key, D>(db, block)? {
 				Some(row) => {
+					let row_size = row.size();
+					if !cache.is_empty() && *cache_size + row_size > cache_capacity {
+						break
+					}
+					*cache_size += row_size;
 					cache.push_back(row);
 				},
 				None => break,
 			}
+			block += 1;
+		}
+		Ok(())
+	}
+
+	/// Load the keys of the blocks of the in-memory queue that are not cached, starting from
+	/// `base` and until `cache_size` reaches `cache_capacity` bytes. At least one block is loaded
+	/// if there is none cached, regardless of its size.
+	fn load_mem_batch_from_db<D: MetaDb>(
+		db: &D,
+		death_rows: &mut VecDeque<DeathRow<BlockHash, Key>>,
+		death_index: &HashMap<Key, u64>,
+		cached: &mut usize,
+		cache_size: &mut usize,
+		base: u64,
+		cache_capacity: usize,
+	) -> Result<(), Error<D::Error>> {
+		while *cached < death_rows.len() {
+			let block = base + *cached as u64;
+			match load_death_row_from_db::<BlockHash, Key, D>(db, block)? {
+				Some(mut row) => {
+					// Re-inserted keys, and keys deleted again by later blocks, are not in the
+					// index for this block.
+					row.deleted.retain(|k| death_index.get(k) == Some(&block));
+					let row_size = row.size();
+					if *cached != 0 && *cache_size + row_size > cache_capacity {
+						break
+					}
+					*cache_size += row_size;
+					death_rows[*cached] = row;
+					*cached += 1;
+				},
+				None => break,
+			}
 		}
 		Ok(())
 	}
@@ -219,6 +466,14 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 		}
 	}
 
+	/// Estimated number of bytes of the blocks held in memory
+	fn memory_size(&self) -> usize {
+		match self {
+			DeathRowQueue::DbBacked { cache_size, .. } => *cache_size,
+			DeathRowQueue::Mem { cache_size, .. } => *cache_size,
+		}
+	}
+
 	/// Return the number of block in the pruning window
 	fn len(&self, base: u64) -> u64 {
 		match self {
@@ -233,7 +488,7 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> DeathRowQueue<BlockHash, Key, D> {
 	) -> Option<(&VecDeque<DeathRow<BlockHash, Key>>, &HashMap<Key, u64>)> {
 		match self {
 			DeathRowQueue::DbBacked { .. } => None,
-			DeathRowQueue::Mem { death_rows, death_index } => Some((death_rows, death_index)),
+			DeathRowQueue::Mem { death_rows, death_index, .. } => Some((death_rows, death_index)),
 		}
 	}
 
@@ -256,16 +511,38 @@ fn load_death_row_from_db<BlockHash: Hash, Key: Hash, D: MetaDb>(
 	match db.get_meta(&journal_key).map_err(Error::Db)? {
 		Some(record) => {
 			let JournalRecord { hash, deleted, .. } = Decode::decode(&mut record.as_slice())?;
-			Ok(Some(DeathRow { hash, deleted: deleted.into_iter().collect() }))
+			let child_tries = load_child_tries_from_db::<Key, D>(db, block)?;
+			Ok(Some(DeathRow { hash, deleted: deleted.into_iter().collect(), child_tries }))
 		},
 		None => Ok(None),
 	}
 }
 
+fn load_child_tries_from_db<Key: Hash, D: MetaDb>(
+	db: &D,
+	block: u64,
+) -> Result<Vec<(ChildTrieId, Vec<Key>)>, Error<D::Error>> {
+	match db.get_meta(&to_child_journal_key(block)).map_err(Error::Db)? {
+		Some(record) => Ok(Decode::decode(&mut record.as_slice())?),
+		None => Ok(Vec::new()),
+	}
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct DeathRow<BlockHash: Hash, Key: Hash> {
 	hash: BlockHash,
 	deleted: HashSet<Key>,
+	/// Child trie tags of the keys in `deleted`.
+	child_tries: Vec<(ChildTrieId, Vec<Key>)>,
+}
+
+impl<BlockHash: Hash, Key: Hash> DeathRow<BlockHash, Key> {
+	/// Number of bytes accounted for this row in the database-backed cache.
+	fn size(&self) -> usize {
+		self.hash.encoded_size() +
+			self.deleted.iter().map(Encode::encoded_size).sum::<usize>() +
+			self.child_tries.encoded_size()
+	}
 }
 
 #[derive(Encode, Decode, Default)]
@@ -279,6 +556,33 @@ fn to_journal_key(block: u64) -> Vec<u8> {
 	to_meta_key(PRUNING_JOURNAL, &block)
 }
 
+fn to_retained_journal_key(block: u64) -> Vec<u8> {
+	to_meta_key(RETAINED_JOURNAL, &block)
+}
+
+fn to_retained_keys_key(page: usize) -> Vec<u8> {
+	to_meta_key(RETAINED_KEYS, &(page as u32))
+}
+
+/// Drop a reference to `key` inserted after the last retained state. Returns `false` if there
+/// is none, i.e. the key may belong to a retained state.
+fn release_fresh<Key: Hash>(fresh: &mut HashMap<Key, u32>, key: &Key) -> bool {
+	match fresh.get_mut(key) {
+		Some(count) => {
+			*count -= 1;
+			if *count == 0 {
+				fresh.remove(key);
+			}
+			true
+		},
+		None => false,
+	}
+}
+
+fn to_child_journal_key(block: u64) -> Vec<u8> {
+	to_meta_key(PRUNING_CHILD_JOURNAL, &block)
+}
+
 /// The result return by `RefWindow::have_block`
 #[derive(Debug, PartialEq, Eq)]
 pub enum HaveBlock {
@@ -305,6 +609,7 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 		db: D,
 		window_size: u32,
 		count_insertions: bool,
+		strategy: Arc<dyn PruningStrategy>,
 	) -> Result<RefWindow<BlockHash, Key, D>, Error<D::Error>> {
 		// the block number of the first block in the queue or the next block number if the queue is
 		// empty
@@ -319,6 +624,36 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 				None => None,
 			};
 
+		let last_retained =
+			match db.get_meta(&to_meta_key(LAST_RETAINED, &())).map_err(Error::Db)? {
+				Some(buffer) => Some(u64::decode(&mut buffer.as_slice())?),
+				None => None,
+			};
+		// Replay the journal of the blocks canonicalized since the last retained state.
+		let mut fresh = HashMap::new();
+		if let (Some(last_retained), Some(last)) = (last_retained, last_canonicalized_number) {
+			for number in last_retained + 1..=last {
+				let Some(record) =
+					db.get_meta(&to_retained_journal_key(number)).map_err(Error::Db)?
+				else {
+					continue
+				};
+				let RetainedJournalRecord::<Key> { inserted, pruned } =
+					Decode::decode(&mut record.as_slice())?;
+				for key in inserted {
+					*fresh.entry(key).or_default() += 1;
+				}
+				for key in pruned {
+					release_fresh(&mut fresh, &key);
+				}
+			}
+		}
+		let retained_keys = match (count_insertions, last_retained) {
+			(false, _) => None,
+			(true, None) => Some(RetainedKeys::default()),
+			(true, Some(_)) => Some(RetainedKeys::load(&db)?),
+		};
+
 		let queue = if count_insertions {
 			// Highly scientific crafted number for deciding when to print the warning!
 			//
@@ -332,7 +667,7 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 				);
 			}
 
-			DeathRowQueue::new_mem(&db, base)?
+			DeathRowQueue::new_mem(&db, base, strategy.death_row_cache_size())?
 		} else {
 			let last = match last_canonicalized_number {
 				Some(last_canonicalized_number) => {
@@ -344,22 +679,45 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 				// ever been committed to the db, thus set `unload` to zero
 				None => None,
 			};
-			DeathRowQueue::new_db_backed(db, base, last, window_size)?
+			DeathRowQueue::new_db_backed(
+				&db,
+				base,
+				last,
+				window_size,
+				strategy.death_row_cache_size(),
+			)?
 		};
 
-		Ok(RefWindow { queue, base })
+		Ok(RefWindow { db, queue, base, strategy, last_retained, fresh, retained_keys })
 	}
 
 	pub fn window_size(&self) -> u64 {
 		self.queue.len(self.base) as u64
 	}
 
+	/// Estimated number of bytes of the pruning window blocks held in memory
+	pub fn memory_size(&self) -> usize {
+		self.queue.memory_size()
+	}
+
+	/// Get the number of the next pruning block
+	pub fn base(&self) -> u64 {
+		self.base
+	}
+
 	/// Get the hash of the next pruning block
 	pub fn next_hash(&mut self) -> Result<Option<BlockHash>, Error<D::Error>> {
 		let res = match &mut self.queue {
-			DeathRowQueue::DbBacked { db, cache, cache_capacity, .. } => {
+			DeathRowQueue::DbBacked { cache, cache_blocks, cache_capacity, cache_size, .. } => {
 				if cache.is_empty() {
-					DeathRowQueue::load_batch_from_db(db, cache, self.base, *cache_capacity)?;
+					DeathRowQueue::load_batch_from_db(
+						&self.db,
+						cache,
+						cache_size,
+						self.base,
+						*cache_blocks,
+						*cache_capacity,
+					)?;
 				}
 				cache.front().map(|r| r.hash.clone())
 			},
@@ -384,12 +742,8 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 
 	/// Prune next block. Expects at least one block in the window. Adds changes to `commit`.
 	pub fn prune_one(&mut self, commit: &mut CommitSet<Key>) -> Result<(), Error<D::Error>> {
-		if let Some(pruned) = self.queue.pop_front(self.base)? {
-			trace!(target: "state-db", "Pruning {:?} ({} deleted)", pruned.hash, pruned.deleted.len());
-			let index = self.base;
-			commit.data.deleted.extend(pruned.deleted.into_iter());
-			commit.meta.inserted.push((to_meta_key(LAST_PRUNED, &()), index.encode()));
-			commit.meta.deleted.push(to_journal_key(self.base));
+		if let Some(pruned) = self.queue.pop_front(&self.db, self.base)? {
+			self.add_prune_changes(self.base, pruned, commit);
 			self.base += 1;
 			Ok(())
 		} else {
@@ -398,12 +752,213 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 		}
 	}
 
+	/// Add the changes pruning block `base + offset` to `commit`, without removing the block
+	/// from the window. Returns the hash of the block, or `None` if the window doesn't have it or
+	/// `can_prune` rejects it. Once the commit is written the block has to be removed with
+	/// `advance`.
+	pub fn prune_commit_at(
+		&mut self,
+		offset: u64,
+		can_prune: impl FnOnce(&BlockHash) -> bool,
+		commit: &mut CommitSet<Key>,
+	) -> Result<Option<BlockHash>, Error<D::Error>> {
+		if offset >= self.window_size() {
+			return Ok(None)
+		}
+		let row = match &mut self.queue {
+			DeathRowQueue::DbBacked { cache, .. } => match cache.get(offset as usize) {
+				Some(row) => Some(row.clone()),
+				None => load_death_row_from_db::<BlockHash, Key, D>(&self.db, self.base + offset)?,
+			},
+			DeathRowQueue::Mem { death_rows, death_index, cached, .. } =>
+				if (offset as usize) < *cached {
+					death_rows.get(offset as usize).cloned()
+				} else {
+					let block = self.base + offset;
+					load_death_row_from_db::<BlockHash, Key, D>(&self.db, block)?.map(|mut row| {
+						row.deleted.retain(|k| death_index.get(k) == Some(&block));
+						row
+					})
+				},
+		};
+		match row {
+			Some(row) if can_prune(&row.hash) => {
+				let hash = row.hash.clone();
+				self.add_prune_changes(self.base + offset, row, commit);
+				Ok(Some(hash))
+			},
+			_ => Ok(None),
+		}
+	}
+
+	/// Remove the next `count` blocks from the window, after the commit built for them by
+	/// `prune_commit_at` is written.
+	pub fn advance(&mut self, count: u64) -> Result<(), Error<D::Error>> {
+		for _ in 0..count {
+			if self.queue.pop_front(&self.db, self.base)?.is_none() {
+				return Err(Error::StateDb(StateDbError::BlockUnavailable))
+			}
+			self.base += 1;
+		}
+		Ok(())
+	}
+
+	fn add_prune_changes(
+		&self,
+		index: u64,
+		pruned: DeathRow<BlockHash, Key>,
+		commit: &mut CommitSet<Key>,
+	) {
+		trace!(target: "state-db", "Pruning {:?} ({} deleted)", pruned.hash, pruned.deleted.len());
+		let DeathRow { mut deleted, child_tries, .. } = pruned;
+		if !child_tries.is_empty() {
+			for (child_trie, keys) in child_tries {
+				if self.strategy.retain_child_trie(&child_trie) {
+					for key in keys {
+						deleted.remove(&key);
+					}
+				}
+			}
+			commit.meta.deleted.push(to_child_journal_key(index));
+		}
+		commit.data.deleted.extend(deleted.into_iter());
+		commit.meta.inserted.push((to_meta_key(LAST_PRUNED, &()), index.encode()));
+		commit.meta.deleted.push(to_journal_key(index));
+	}
+
 	/// Add a change set to the window. Creates a journal record and pushes it to `commit`
+	#[cfg(test)]
 	pub fn note_canonical(
 		&mut self,
 		hash: &BlockHash,
 		number: u64,
 		commit: &mut CommitSet<Key>,
+	) -> Result<(), Error<D::Error>> {
+		let keys: Vec<Key> = commit.data.inserted.iter().map(|(k, _)| k.clone()).collect();
+		self.retain(hash, number, keys.iter(), commit);
+		let inserted = self.journal_inserted(keys.iter());
+		self.note_canonical_record(hash, number, inserted, commit)
+	}
+
+	/// Same as `note_canonical`, for a block whose inserted values are not yet in `commit`.
+	pub fn note_canonical_deferred(
+		&mut self,
+		hash: &BlockHash,
+		number: u64,
+		values: &DeferredValues<Key>,
+		commit: &mut CommitSet<Key>,
+	) -> Result<(), Error<D::Error>> {
+		self.retain(hash, number, values.keys(), commit);
+		let inserted = self.journal_inserted(values.keys());
+		self.note_canonical_record(hash, number, inserted, commit)
+	}
+
+	/// Keep the nodes of retained states out of the deleted keys of the block being
+	/// canonicalized. A deletion is only pruned if it drops a reference inserted after the last
+	/// retained state.
+	fn retain<'a>(
+		&mut self,
+		hash: &BlockHash,
+		number: u64,
+		inserted: impl Iterator<Item = &'a Key>,
+		commit: &mut CommitSet<Key>,
+	) {
+		let retaining = self.last_retained.is_some();
+		let mut pruned = Vec::new();
+		if retaining {
+			let fresh = &mut self.fresh;
+			let retained_keys = &mut self.retained_keys;
+			let spared = commit.data.deleted.len();
+			commit.data.deleted.retain(|key| {
+				if release_fresh(fresh, key) {
+					pruned.push(key.clone());
+					true
+				} else {
+					if let Some(retained_keys) = retained_keys.as_mut() {
+						retained_keys.insert(key);
+					}
+					false
+				}
+			});
+			let spared = spared - commit.data.deleted.len();
+			if spared > 0 {
+				trace!(
+					target: "state-db",
+					"Keeping {} nodes of retained states deleted by #{}",
+					spared,
+					number,
+				);
+			}
+			if let Some(retained_keys) = self.retained_keys.as_mut() {
+				retained_keys.write(commit);
+			}
+		}
+		if self.strategy.retain_state(&hash.encode(), number) {
+			trace!(target: "state-db", "Retaining the state of #{}", number);
+			if let Some(last_retained) = self.last_retained {
+				commit
+					.meta
+					.deleted
+					.extend((last_retained + 1..number).map(to_retained_journal_key));
+			}
+			self.last_retained = Some(number);
+			self.fresh.clear();
+			commit.meta.inserted.push((to_meta_key(LAST_RETAINED, &()), number.encode()));
+			commit.meta.inserted.push((to_meta_key(RETAINED_STATE, &number), hash.encode()));
+		} else if retaining {
+			let retained_keys = &self.retained_keys;
+			let inserted: Vec<Key> = inserted
+				.filter(|key| !retained_keys.as_ref().is_some_and(|keys| keys.contains(*key)))
+				.cloned()
+				.collect();
+			for key in &inserted {
+				*self.fresh.entry(key.clone()).or_default() += 1;
+			}
+			if !inserted.is_empty() || !pruned.is_empty() {
+				let record = RetainedJournalRecord { inserted, pruned };
+				commit.meta.inserted.push((to_retained_journal_key(number), record.encode()));
+			}
+		}
+	}
+
+	/// The hash of the block whose state was retained at `number`, if any.
+	pub fn retained_state(&self, number: u64) -> Result<Option<BlockHash>, Error<D::Error>> {
+		match self.db.get_meta(&to_meta_key(RETAINED_STATE, &number)).map_err(Error::Db)? {
+			Some(buffer) => Ok(Some(BlockHash::decode(&mut buffer.as_slice())?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Inserted keys are only tracked by the in-memory queue, for reference counting.
+	fn journal_inserted<'a>(&self, keys: impl Iterator<Item = &'a Key>) -> Vec<Key> {
+		if matches!(self.queue, DeathRowQueue::Mem { .. }) {
+			keys.cloned().collect()
+		} else {
+			Default::default()
+		}
+	}
+
+	/// Check that blocks with `numbers` can be noted as canonical one after another.
+	pub fn check_canonical_numbers(
+		&self,
+		numbers: impl Iterator<Item = u64>,
+	) -> Result<(), Error<D::Error>> {
+		let mut next = (self.base != 0 || !self.is_empty()).then(|| self.base + self.window_size());
+		for number in numbers {
+			if next.map_or(false, |next| next != number) {
+				return Err(Error::StateDb(StateDbError::InvalidBlockNumber))
+			}
+			next = Some(number + 1);
+		}
+		Ok(())
+	}
+
+	fn note_canonical_record(
+		&mut self,
+		hash: &BlockHash,
+		number: u64,
+		inserted: Vec<Key>,
+		commit: &mut CommitSet<Key>,
 	) -> Result<(), Error<D::Error>> {
 		if self.base == 0 && self.is_empty() && number > 0 {
 			// assume that parent was canonicalized
@@ -411,35 +966,66 @@ impl<BlockHash: Hash, Key: Hash, D: MetaDb> RefWindow<BlockHash, Key, D> {
 		} else if (self.base + self.window_size()) != number {
 			return Err(Error::StateDb(StateDbError::InvalidBlockNumber))
 		}
-		trace!(target: "state-db", "Adding to pruning window: {:?} ({} inserted, {} deleted)", hash, commit.data.inserted.len(), commit.data.deleted.len());
-		let inserted = if matches!(self.queue, DeathRowQueue::Mem { .. }) {
-			commit.data.inserted.iter().map(|(k, _)| k.clone()).collect()
-		} else {
-			Default::default()
-		};
+		trace!(target: "state-db", "Adding to pruning window: {:?} ({} inserted, {} deleted)", hash, inserted.len(), commit.data.deleted.len());
 		let deleted = std::mem::take(&mut commit.data.deleted);
+		// Only the tags of deleted keys are relevant for pruning.
+		let mut child_tries = std::mem::take(&mut commit.data.child_tries);
+		if !child_tries.is_empty() {
+			let deleted_keys: HashSet<&Key> = deleted.iter().collect();
+			child_tries
+				.iter_mut()
+				.for_each(|(_, keys)| keys.retain(|k| deleted_keys.contains(k)));
+			child_tries.retain(|(_, keys)| !keys.is_empty());
+		}
+		if !child_tries.is_empty() {
+			commit.meta.inserted.push((to_child_journal_key(number), child_tries.encode()));
+		}
+		// The previous block can't be read back before `commit` is written if it was noted in
+		// the same commit.
+		let previous_key = number.checked_sub(1).map(to_journal_key);
+		let chained =
+			commit.meta.inserted.iter().any(|(key, _)| Some(key) == previous_key.as_ref());
 		let journal_record = JournalRecord { hash: hash.clone(), inserted, deleted };
 		commit.meta.inserted.push((to_journal_key(number), journal_record.encode()));
-		self.queue.import(self.base, number, journal_record);
+		self.queue.import(self.base, number, journal_record, child_tries, chained);
 		Ok(())
 	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{to_journal_key, DeathRowQueue, HaveBlock, JournalRecord, RefWindow, LAST_PRUNED};
+	use super::{
+		to_journal_key, DeathRow, DeathRowQueue, HaveBlock, JournalRecord, RefWindow, LAST_PRUNED,
+	};
 	use crate::{
 		noncanonical::LAST_CANONICAL,
 		test::{make_commit, make_db, TestDb},
-		to_meta_key, CommitSet, Error, Hash, StateDbError, DEFAULT_MAX_BLOCK_CONSTRAINT,
+		to_meta_key, CommitSet, Constraints, Error, Hash, PruningMode, StateDbError,
+		DEFAULT_MAX_BLOCK_CONSTRAINT,
 	};
 	use codec::Encode;
 	use sp_core::H256;
+	use std::sync::Arc;
+
+	/// Strategy whose death row cache fits `blocks` empty blocks.
+	fn cache_strategy(blocks: usize) -> Arc<PruningMode> {
+		let row =
+			DeathRow::<u64, H256> { hash: 0, deleted: Default::default(), child_tries: vec![] };
+		Arc::new(PruningMode::Constrained(Constraints {
+			death_row_cache_size: Some(blocks * row.size()),
+			..Default::default()
+		}))
+	}
 
 	fn check_journal(pruning: &RefWindow<H256, H256, TestDb>, db: &TestDb) {
 		let count_insertions = matches!(pruning.queue, DeathRowQueue::Mem { .. });
-		let restored: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, count_insertions).unwrap();
+		let restored: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			count_insertions,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		assert_eq!(pruning.base, restored.base);
 		assert_eq!(pruning.queue.get_mem_queue_state(), restored.queue.get_mem_queue_state());
 	}
@@ -447,8 +1033,13 @@ mod tests {
 	#[test]
 	fn created_from_empty_db() {
 		let db = make_db(&[]);
-		let pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db, DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db,
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		assert_eq!(pruning.base, 0);
 		let (death_rows, death_index) = pruning.queue.get_mem_queue_state().unwrap();
 		assert!(death_rows.is_empty());
@@ -458,8 +1049,13 @@ mod tests {
 	#[test]
 	fn prune_empty() {
 		let db = make_db(&[]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db, DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db,
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = CommitSet::default();
 		assert_eq!(
 			Err(Error::StateDb(StateDbError::BlockUnavailable)),
@@ -474,8 +1070,13 @@ mod tests {
 	#[test]
 	fn prune_one() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[4, 5], &[1, 3]);
 		let hash = H256::random();
 		pruning.note_canonical(&hash, 0, &mut commit).unwrap();
@@ -501,11 +1102,40 @@ mod tests {
 		assert_eq!(pruning.base, 1);
 	}
 
+	#[test]
+	fn canonical_numbers_are_checked_up_front() {
+		let db = make_db(&[]);
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db,
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
+		assert!(pruning.check_canonical_numbers([3, 4, 5].into_iter()).is_ok());
+		let mut commit = make_commit(&[1], &[]);
+		pruning.note_canonical(&H256::random(), 3, &mut commit).unwrap();
+		assert!(pruning.check_canonical_numbers([4, 5].into_iter()).is_ok());
+		assert_eq!(
+			pruning.check_canonical_numbers([4, 6].into_iter()),
+			Err(Error::StateDb(StateDbError::InvalidBlockNumber))
+		);
+		assert_eq!(
+			pruning.check_canonical_numbers([5].into_iter()),
+			Err(Error::StateDb(StateDbError::InvalidBlockNumber))
+		);
+	}
+
 	#[test]
 	fn prune_two() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[4], &[1]);
 		pruning.note_canonical(&H256::random(), 0, &mut commit).unwrap();
 		db.commit(&commit);
@@ -530,8 +1160,13 @@ mod tests {
 	#[test]
 	fn prune_two_pending() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[4], &[1]);
 		pruning.note_canonical(&H256::random(), 0, &mut commit).unwrap();
 		db.commit(&commit);
@@ -553,8 +1188,13 @@ mod tests {
 	#[test]
 	fn reinserted_survives() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[], &[2]);
 		pruning.note_canonical(&H256::random(), 0, &mut commit).unwrap();
 		db.commit(&commit);
@@ -585,8 +1225,13 @@ mod tests {
 	#[test]
 	fn reinserted_survive_pending() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			true,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[], &[2]);
 		pruning.note_canonical(&H256::random(), 0, &mut commit).unwrap();
 		db.commit(&commit);
@@ -615,8 +1260,13 @@ mod tests {
 	#[test]
 	fn reinserted_ignores() {
 		let mut db = make_db(&[1, 2, 3]);
-		let mut pruning: RefWindow<H256, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		let mut pruning: RefWindow<H256, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			Arc::new(PruningMode::default()),
+		)
+		.unwrap();
 		let mut commit = make_commit(&[], &[2]);
 		pruning.note_canonical(&H256::random(), 0, &mut commit).unwrap();
 		db.commit(&commit);
@@ -653,8 +1303,13 @@ mod tests {
 		let mut commit = CommitSet::default();
 
 		fn load_pruning_from_db(db: TestDb) -> (usize, u64) {
-			let pruning: RefWindow<u64, H256, TestDb> =
-				RefWindow::new(db, DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+			let pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+				db,
+				DEFAULT_MAX_BLOCK_CONSTRAINT,
+				false,
+				Arc::new(PruningMode::default()),
+			)
+			.unwrap();
 			let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
 			(cache.len(), pruning.base)
 		}
@@ -707,8 +1362,13 @@ mod tests {
 	#[test]
 	fn db_backed_queue() {
 		let mut db = make_db(&[]);
-		let mut pruning: RefWindow<u64, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		let mut pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		let cache_capacity = DEFAULT_MAX_BLOCK_CONSTRAINT as usize;
 
 		// start as an empty queue
@@ -752,7 +1412,13 @@ mod tests {
 
 		// revert the last add that no apply yet
 		// NOTE: do not commit the previous `CommitSet` to db
-		pruning = RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		pruning = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		let cache_capacity = DEFAULT_MAX_BLOCK_CONSTRAINT as usize;
 		assert_eq!(pruning.window_size(), cache_capacity as u64 + 10);
 		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
@@ -772,8 +1438,13 @@ mod tests {
 
 		// load a new queue from db
 		// `cache` is full again but the content of the queue should be the same
-		let pruning: RefWindow<u64, H256, TestDb> =
-			RefWindow::new(db, DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		let pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+			db,
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		assert_eq!(pruning.window_size(), cache_capacity as u64 + 9);
 		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
 		assert_eq!(cache.len(), cache_capacity);
@@ -783,10 +1454,102 @@ mod tests {
 	}
 
 	#[test]
-	fn load_block_from_db() {
+	fn db_backed_cache_respects_memory_budget() {
 		let mut db = make_db(&[]);
+		let row = DeathRow::<u64, H256> {
+			hash: 0,
+			deleted: (0..3).map(H256::from_low_u64_be).collect(),
+			child_tries: vec![],
+		};
+		let strategy = Arc::new(PruningMode::Constrained(Constraints {
+			death_row_cache_size: Some(row.size() * 2),
+			..Default::default()
+		}));
+		let mut pruning: RefWindow<u64, H256, TestDb> =
+			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false, strategy.clone())
+				.unwrap();
+
+		// only two blocks fit in the cache
+		for i in 0..4 {
+			let mut commit = make_commit(&[], &[i * 3 + 1, i * 3 + 2, i * 3 + 3]);
+			pruning.note_canonical(&i, i, &mut commit).unwrap();
+			push_last_canonicalized(i, &mut commit);
+			db.commit(&commit);
+		}
+		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
+		assert_eq!(cache.len(), 2);
+
 		let mut pruning: RefWindow<u64, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false, strategy).unwrap();
+		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
+		assert_eq!(cache.len(), 2);
+
+		// blocks past the budget are read from the db
+		let mut commit = CommitSet::default();
+		for i in 0..4 {
+			assert_eq!(pruning.next_hash().unwrap(), Some(i));
+			pruning.prune_one(&mut commit).unwrap();
+		}
+		assert_eq!(commit.data.deleted.len(), 12);
+		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn mem_queue_respects_memory_budget() {
+		let mut db = make_db(&[]);
+		let row = DeathRow::<u64, H256> {
+			hash: 0,
+			deleted: (0..3).map(H256::from_low_u64_be).collect(),
+			child_tries: vec![],
+		};
+		let strategy = Arc::new(PruningMode::Constrained(Constraints {
+			death_row_cache_size: Some(row.size() * 2),
+			..Default::default()
+		}));
+		let mut pruning: RefWindow<u64, H256, TestDb> =
+			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true, strategy.clone())
+				.unwrap();
+
+		// only the keys of two blocks fit in the cache
+		for i in 0..4 {
+			let mut commit = make_commit(&[], &[i * 3 + 1, i * 3 + 2, i * 3 + 3]);
+			pruning.note_canonical(&i, i, &mut commit).unwrap();
+			push_last_canonicalized(i, &mut commit);
+			db.commit(&commit);
+		}
+		// re-insert a key of a block past the budget
+		let mut commit = make_commit(&[8], &[]);
+		pruning.note_canonical(&4, 4, &mut commit).unwrap();
+		push_last_canonicalized(4, &mut commit);
+		db.commit(&commit);
+		assert_eq!(pruning.memory_size(), row.size() * 2);
+
+		let mut pruning: RefWindow<u64, H256, TestDb> =
+			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, true, strategy).unwrap();
+		assert_eq!(pruning.memory_size(), row.size() * 2);
+
+		// the keys of blocks past the budget are read from the db
+		let mut commit = CommitSet::default();
+		for i in 0..5 {
+			assert_eq!(pruning.next_hash().unwrap(), Some(i));
+			pruning.prune_one(&mut commit).unwrap();
+		}
+		assert_eq!(commit.data.deleted.len(), 11);
+		assert!(!commit.data.deleted.contains(&H256::from_low_u64_be(8)));
+		assert_eq!(pruning.memory_size(), 0);
+	}
+
+	#[test]
+	fn load_block_from_db() {
+		let mut db = make_db(&[]);
+		let mut pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		let cache_capacity = DEFAULT_MAX_BLOCK_CONSTRAINT as usize;
 
 		// import blocks
@@ -822,8 +1585,13 @@ mod tests {
 
 		// load a new queue from db
 		// `cache` should be the same
-		let pruning: RefWindow<u64, H256, TestDb> =
-			RefWindow::new(db, DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		let pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+			db,
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		assert_eq!(pruning.window_size(), 10);
 		let (cache, _) = pruning.queue.get_db_backed_queue_state().unwrap();
 		assert_eq!(cache.len(), 10);
@@ -835,8 +1603,13 @@ mod tests {
 	#[test]
 	fn get_block_from_queue() {
 		let mut db = make_db(&[]);
-		let mut pruning: RefWindow<u64, H256, TestDb> =
-			RefWindow::new(db.clone(), DEFAULT_MAX_BLOCK_CONSTRAINT, false).unwrap();
+		let mut pruning: RefWindow<u64, H256, TestDb> = RefWindow::new(
+			db.clone(),
+			DEFAULT_MAX_BLOCK_CONSTRAINT,
+			false,
+			cache_strategy(DEFAULT_MAX_BLOCK_CONSTRAINT as usize),
+		)
+		.unwrap();
 		let cache_capacity = DEFAULT_MAX_BLOCK_CONSTRAINT as u64;
 
 		// import blocks and commit to db
@@ -51,6 +51,18 @@ impl NodeDb for TestDb {
 	}
 }
 
+/// Node database keyed by byte slices, as the client database is.
+pub struct ByteKeys(pub TestDb);
+
+impl NodeDb for ByteKeys {
+	type Error = ();
+	type Key = [u8];
+
+	fn get(&self, key: &[u8]) -> Result<Option<DBValue>, ()> {
+		self.0.get(&H256::from_slice(key))
+	}
+}
+
 impl TestDb {
 	pub fn commit(&mut self, commit: &CommitSet<H256>) {
 		self.0.write().unwrap().data.extend(commit.data.inserted.iter().cloned());
@@ -80,6 +92,7 @@ pub fn make_changeset(inserted: &[u64], deleted: &[u64]) -> ChangeSet<H256> {
 			.map(|v| (H256::from_low_u64_be(*v), H256::from_low_u64_be(*v).as_bytes().to_vec()))
 			.collect(),
 		deleted: deleted.iter().map(|v| H256::from_low_u64_be(*v)).collect(),
+		child_tries: Vec::new(),
 	}
 }
 
@@ -19,7 +19,10 @@
 
 use crate::BlockStatus;
 use futures::FutureExt as _;
-use sp_runtime::traits::Block;
+use sp_runtime::{
+	codec::{DecodeAll, Encode},
+	traits::Block,
+};
 use std::{error::Error, future::Future, pin::Pin, sync::Arc};
 
 /// A type which provides access to chain information.
@@ -96,3 +99,146 @@ impl<B: Block> BlockAnnounceValidator<B> for DefaultBlockAnnounceValidator {
 		.boxed()
 	}
 }
+
+/// Validator running a list of [`BlockAnnounceValidator`]s in order.
+///
+/// The announcement data is expected to be the SCALE encoded list of the data blobs of each
+/// validator, see [`ChainedBlockAnnounceValidator::encode_data`]. Empty data passes empty blobs to
+/// all validators. The first failure wins, and the announced block is the new best block if any
+/// of the validators says so.
+pub struct ChainedBlockAnnounceValidator<B: Block> {
+	validators: Vec<Box<dyn BlockAnnounceValidator<B> + Send>>,
+}
+
+impl<B: Block> ChainedBlockAnnounceValidator<B> {
+	/// Create a new instance running `validators` in order.
+	pub fn new(validators: Vec<Box<dyn BlockAnnounceValidator<B> + Send>>) -> Self {
+		Self { validators }
+	}
+
+	/// Combine the announcement data blobs of each validator, in the order of the validators.
+	pub fn encode_data(data: Vec<Vec<u8>>) -> Vec<u8> {
+		data.encode()
+	}
+}
+
+impl<B: Block> BlockAnnounceValidator<B> for ChainedBlockAnnounceValidator<B> {
+	fn validate(
+		&mut self,
+		header: &B::Header,
+		data: &[u8],
+	) -> Pin<Box<dyn Future<Output = Result<Validation, Box<dyn Error + Send>>> + Send>> {
+		let data = if data.is_empty() {
+			vec![Vec::new(); self.validators.len()]
+		} else {
+			match Vec::<Vec<u8>>::decode_all(&mut &data[..]) {
+				Ok(data) if data.len() == self.validators.len() => data,
+				_ => {
+					log::debug!(
+						target: "sync",
+						"Received block announcement data not matching the {} chained validators.",
+						self.validators.len(),
+					);
+					return async { Ok(Validation::Failure { disconnect: true }) }.boxed()
+				},
+			}
+		};
+
+		let validations = self
+			.validators
+			.iter_mut()
+			.zip(data)
+			.map(|(validator, data)| validator.validate(header, &data))
+			.collect::<Vec<_>>();
+
+		async move {
+			let mut is_new_best = false;
+			for validation in validations {
+				match validation.await? {
+					Validation::Success { is_new_best: new_best } => is_new_best |= new_best,
+					failure @ Validation::Failure { .. } => return Ok(failure),
+				}
+			}
+			Ok(Validation::Success { is_new_best })
+		}
+		.boxed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_runtime::traits::Header as _;
+	use sp_test_primitives::{Block, Header};
+
+	/// Validator checking that its data equals `expected`.
+	struct ExpectData {
+		expected: Vec<u8>,
+		is_new_best: bool,
+	}
+
+	impl BlockAnnounceValidator<Block> for ExpectData {
+		fn validate(
+			&mut self,
+			_: &Header,
+			data: &[u8],
+		) -> Pin<Box<dyn Future<Output = Result<Validation, Box<dyn Error + Send>>> + Send>> {
+			let validation = if data == &self.expected[..] {
+				Validation::Success { is_new_best: self.is_new_best }
+			} else {
+				Validation::Failure { disconnect: false }
+			};
+			async move { Ok(validation) }.boxed()
+		}
+	}
+
+	fn chained(validators: Vec<(&[u8], bool)>) -> ChainedBlockAnnounceValidator<Block> {
+		ChainedBlockAnnounceValidator::new(
+			validators
+				.into_iter()
+				.map(|(expected, is_new_best)| {
+					Box::new(ExpectData { expected: expected.to_vec(), is_new_best })
+						as Box<dyn BlockAnnounceValidator<Block> + Send>
+				})
+				.collect(),
+		)
+	}
+
+	fn validate(validator: &mut ChainedBlockAnnounceValidator<Block>, data: &[u8]) -> Validation {
+		let header = Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		futures::executor::block_on(validator.validate(&header, data)).unwrap()
+	}
+
+	#[test]
+	fn passes_each_validator_its_data() {
+		let mut validator = chained(vec![(b"first", false), (b"second", true)]);
+		let data = ChainedBlockAnnounceValidator::<Block>::encode_data(vec![
+			b"first".to_vec(),
+			b"second".to_vec(),
+		]);
+
+		assert_eq!(validate(&mut validator, &data), Validation::Success { is_new_best: true });
+	}
+
+	#[test]
+	fn first_failure_wins() {
+		let mut validator = chained(vec![(b"", false), (b"other", false), (b"", false)]);
+
+		assert_eq!(validate(&mut validator, &[]), Validation::Failure { disconnect: false });
+	}
+
+	#[test]
+	fn data_of_wrong_number_of_validators_fails() {
+		let mut validator = chained(vec![(b"", false), (b"", false)]);
+		let data = ChainedBlockAnnounceValidator::<Block>::encode_data(vec![Vec::new()]);
+
+		assert_eq!(validate(&mut validator, &data), Validation::Failure { disconnect: true });
+		assert_eq!(validate(&mut validator, b"junk"), Validation::Failure { disconnect: true });
+	}
+}